@@ -0,0 +1,113 @@
+use goose::metrics::{GooseMetrics, GooseRequestMetricAggregate};
+use std::fmt;
+
+/// The latency a request may take for 95% of calls and how many calls may fail
+#[derive(Debug, Clone, PartialEq)]
+pub struct Budget {
+	pub name: &'static str,
+	pub p95_ms: usize,
+	pub max_fail_ratio: f64,
+}
+
+impl Budget {
+	/// `LOAD_BUDGET_<NAME>_P95`: overrides the p95 in milliseconds, e.g. `LOAD_BUDGET_LOGIN_P95=800`
+	pub fn from_env(name: &'static str, p95_ms: usize) -> Self {
+		let variable = format!("LOAD_BUDGET_{}_P95", name.to_uppercase());
+		let p95_ms = std::env::var(&variable)
+			.map(|p95| p95.parse().unwrap_or_else(|_| panic!("Invalid value for {variable}")))
+			.unwrap_or(p95_ms);
+
+		Self {
+			name,
+			p95_ms,
+			max_fail_ratio: 0.0,
+		}
+	}
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Breach {
+	Missing {
+		name: &'static str,
+	},
+	Latency {
+		name: &'static str,
+		p95_ms: usize,
+		budget_ms: usize,
+	},
+	Failures {
+		name: &'static str,
+		fail_ratio: f64,
+		budget: f64,
+	},
+}
+
+impl fmt::Display for Breach {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Breach::Missing { name } => write!(f, "{name}: no requests were made"),
+			Breach::Latency {
+				name,
+				p95_ms,
+				budget_ms,
+			} => write!(f, "{name}: p95 of {p95_ms}ms is over {budget_ms}ms"),
+			Breach::Failures {
+				name,
+				fail_ratio,
+				budget,
+			} => {
+				write!(f, "{name}: {:.1}% failed, {:.1}% allowed", fail_ratio * 100.0, budget * 100.0)
+			},
+		}
+	}
+}
+
+// Goose keeps response times as a histogram of milliseconds to number of requests
+fn percentile(request: &GooseRequestMetricAggregate, percentile: f64) -> usize {
+	let total = request.raw_data.times.values().sum::<usize>();
+	let target = (total as f64 * percentile).ceil() as usize;
+	let mut seen = 0;
+	for (time, count) in &request.raw_data.times {
+		seen += count;
+		if seen >= target {
+			return *time;
+		}
+	}
+
+	request.raw_data.maximum_time
+}
+
+pub fn check(metrics: &GooseMetrics, budgets: &[Budget]) -> Vec<Breach> {
+	budgets
+		.iter()
+		.flat_map(|budget| {
+			let Some(request) = metrics.requests.values().find(|request| request.path == budget.name) else {
+				return vec![Breach::Missing { name: budget.name }];
+			};
+
+			let mut breaches = Vec::new();
+			let p95_ms = percentile(request, 0.95);
+			if p95_ms > budget.p95_ms {
+				breaches.push(Breach::Latency {
+					name: budget.name,
+					p95_ms,
+					budget_ms: budget.p95_ms,
+				});
+			}
+			let total = request.success_count + request.fail_count;
+			let fail_ratio = if total == 0 {
+				0.0
+			} else {
+				request.fail_count as f64 / total as f64
+			};
+			if fail_ratio > budget.max_fail_ratio {
+				breaches.push(Breach::Failures {
+					name: budget.name,
+					fail_ratio,
+					budget: budget.max_fail_ratio,
+				});
+			}
+			breaches
+		})
+		.collect()
+}
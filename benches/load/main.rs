@@ -0,0 +1,121 @@
+//! Load test of the auth and todo endpoints against a running server, fails when a latency budget is breached
+//!
+//! The REST API and the server functions the app calls are both measured, the server functions are named with a
+//! `fn_` in front. Every run adds todos to the list of the user it logs in as.
+//!
+//! Start the server with the test data of `dev/psql-compose/init.sql` and limits the load stays under, like
+//! `SERVER_FN_RATE_LIMITS=*=100000/60 LOGIN_MAX_ATTEMPTS=100000`, then run
+//! `cargo bench --bench load -- --host http://127.0.0.1:3000`, every goose flag works after the `--`
+//! - `LOAD_USERNAME`, `LOAD_PASSWORD`: the user to log in as, defaults to the test user `dom`
+//! - `LOAD_BUDGET_<NAME>_P95`: the budget of a request in milliseconds, like `LOAD_BUDGET_FN_ADD_TODO_P95`
+
+mod budget;
+
+use budget::Budget;
+use goose::prelude::*;
+use leptos::server_fn::ServerFn;
+use serde_json::json;
+use session_auth_axum::{
+	auth::Login,
+	todo::{AddTodo, GetTodos},
+};
+
+fn credentials() -> serde_json::Value {
+	json!({
+		"username": std::env::var("LOAD_USERNAME").unwrap_or(String::from("dom")),
+		"password": std::env::var("LOAD_PASSWORD").unwrap_or(String::from("test")),
+	})
+}
+
+// The session cookie is kept by the user's client for the requests that follow
+async fn login(user: &mut GooseUser) -> TransactionResult {
+	let request_builder = user.get_request_builder(&GooseMethod::Post, "/api/v1/auth/login")?.json(&credentials());
+	let request = GooseRequest::builder().set_request_builder(request_builder).name("login").build();
+	user.request(request).await?;
+
+	Ok(())
+}
+
+async fn get_todos(user: &mut GooseUser) -> TransactionResult {
+	user.get_named("/api/v1/todos", "get_todos").await?;
+
+	Ok(())
+}
+
+// Server functions answer with JSON instead of redirecting when they are asked for it, like the app does
+async fn call_server_fn(
+	user: &mut GooseUser,
+	method: GooseMethod,
+	path: &str,
+	name: &str,
+	form: &[(&str, String)],
+) -> TransactionResult {
+	let request_builder = user.get_request_builder(&method, path)?.header("Accept", "application/json").form(form);
+	let request = GooseRequest::builder().set_request_builder(request_builder).name(name).build();
+	user.request(request).await?;
+
+	Ok(())
+}
+
+async fn fn_login(user: &mut GooseUser) -> TransactionResult {
+	let credentials = credentials();
+	let form = [
+		("username", credentials["username"].as_str().unwrap_or_default().to_string()),
+		("password", credentials["password"].as_str().unwrap_or_default().to_string()),
+	];
+	call_server_fn(user, GooseMethod::Post, Login::PATH, "fn_login", &form).await
+}
+
+async fn fn_get_todos(user: &mut GooseUser) -> TransactionResult {
+	user.get_named(GetTodos::PATH, "fn_get_todos").await?;
+
+	Ok(())
+}
+
+async fn fn_add_todo(user: &mut GooseUser) -> TransactionResult {
+	let form = [("title", String::from("Load test"))];
+	call_server_fn(user, GooseMethod::Post, AddTodo::PATH, "fn_add_todo", &form).await
+}
+
+#[tokio::main]
+async fn main() -> Result<(), GooseError> {
+	let metrics = GooseAttack::initialize()?
+		.register_scenario(
+			scenario!("Todos")
+				.register_transaction(transaction!(login).set_on_start())
+				.register_transaction(transaction!(get_todos).set_weight(9)?)
+				.register_transaction(transaction!(login)),
+		)
+		.register_scenario(
+			scenario!("Server functions")
+				.register_transaction(transaction!(fn_login).set_on_start())
+				.register_transaction(transaction!(fn_get_todos).set_weight(9)?)
+				.register_transaction(transaction!(fn_add_todo).set_weight(3)?)
+				.register_transaction(transaction!(fn_login)),
+		)
+		.set_default(GooseDefault::Host, "http://127.0.0.1:3000")?
+		.set_default(GooseDefault::Users, 20)?
+		.set_default(GooseDefault::HatchRate, "5")?
+		.set_default(GooseDefault::RunTime, 30)?
+		.execute()
+		.await?;
+
+	// Argon2 makes logins slow on purpose
+	let budgets = [
+		Budget::from_env("login", 500),
+		Budget::from_env("get_todos", 100),
+		Budget::from_env("fn_login", 500),
+		Budget::from_env("fn_get_todos", 100),
+		Budget::from_env("fn_add_todo", 250),
+	];
+	let breaches = budget::check(&metrics, &budgets);
+	if !breaches.is_empty() {
+		for breach in &breaches {
+			eprintln!("Performance budget breached: {breach}");
+		}
+		std::process::exit(1);
+	}
+
+	println!("All performance budgets met");
+	Ok(())
+}
@@ -1,25 +1,389 @@
 -- init.sql
+CREATE TABLE tenants (
+  id         INTEGER PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
+  slug       TEXT NOT NULL UNIQUE,
+  name       TEXT NOT NULL,
+  created_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP
+);
+INSERT INTO tenants (slug, name) VALUES ('default', 'Default'), ('acme', 'Acme Corp');
+
+CREATE TABLE tenant_settings (
+  tenant                       INT PRIMARY KEY REFERENCES tenants(id) ON DELETE CASCADE,
+  password_min_length          INT NOT NULL DEFAULT 8,
+  password_require_number      BOOLEAN NOT NULL DEFAULT false,
+  password_require_symbol      BOOLEAN NOT NULL DEFAULT false,
+  session_lifetime_hours       INT NOT NULL DEFAULT 720,
+  open_signup                  BOOLEAN NOT NULL DEFAULT true,
+  -- The permissions users who sign up start with, `[self]` stands for the id of the new user
+  default_permission_equipment TEXT NOT NULL DEFAULT 'READ(person[self])|WRITE(person[self])|CREATE(false)',
+  default_permission_user      TEXT NOT NULL DEFAULT 'READ(*)|WRITE(person[self])|CREATE(false)',
+  default_permission_todo      TEXT NOT NULL DEFAULT 'READ(person[self])|WRITE(person[self])|CREATE(true)'
+);
+INSERT INTO tenant_settings
+  (tenant, password_min_length, password_require_number, password_require_symbol, session_lifetime_hours, open_signup)
+  VALUES
+  (2, 12, true, true, 8, false);
+
 CREATE TABLE users (
   id                   INTEGER PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
-  username             TEXT NOT NULL UNIQUE,
+  tenant               INT NOT NULL REFERENCES tenants(id) ON DELETE CASCADE,
+  username             TEXT NOT NULL,
   password             TEXT NOT NULL,
+  avatar               TEXT,
   created_at           TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP,
   permission_equipment TEXT NOT NULL,
   permission_user      TEXT NOT NULL,
-  permission_todo      TEXT NOT NULL
+  permission_todo      TEXT NOT NULL,
+  -- Bumped by every login in single session mode, sessions of an older generation are refused
+  session_generation   INT NOT NULL DEFAULT 0,
+  concurrent_sessions  BOOLEAN NOT NULL DEFAULT false,
+  -- Deactivated users keep their todos but can't log in
+  active               BOOLEAN NOT NULL DEFAULT true,
+  -- Service accounts are for automation, they can't log in and only authenticate with API tokens
+  service_account      BOOLEAN NOT NULL DEFAULT false,
+  UNIQUE (tenant, username)
 );
 INSERT INTO users
-  (username, password, permission_equipment, permission_user, permission_todo)
+  (tenant, username, password, permission_equipment, permission_user, permission_todo)
+  VALUES
+  (1, 'dom', '$argon2id$v=19$m=19456,t=2,p=1$T9GO2wvNWMGcMQ/uPdH8lQ$EjVtyckTRnjly15GvDW3RAo2GvZPT/Dv7prpRDv6YcI', 'READ(*)|WRITE(*)|CREATE(true)', 'READ(*)|WRITE(*)|CREATE(true)', 'READ(equipment[2])|WRITE(equipment[2])|CREATE(true)'),
+  (1, 'thewizzy', '$argon2id$v=19$m=19456,t=2,p=1$jGpHvsSseOmqYpSjYmHsDw$C/tnsXIf8dEdGojiKDcYis3e7gniaT40jvqIyFzri4c', 'READ(*)|WRITE(*)|CREATE(true)', 'READ(*)|WRITE(*)|CREATE(true)', 'READ(*)|WRITE(*)|CREATE(true)'),
+  (2, 'wile', '$argon2id$v=19$m=19456,t=2,p=1$T9GO2wvNWMGcMQ/uPdH8lQ$EjVtyckTRnjly15GvDW3RAo2GvZPT/Dv7prpRDv6YcI', 'READ(*)|WRITE(*)|CREATE(true)', 'READ(*)|WRITE(*)|CREATE(true)', 'READ(*)|WRITE(*)|CREATE(true)');
+-- password for all: test
+
+CREATE TABLE saml_permission_presets (
+  id                   INTEGER PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
+  tenant               INT NOT NULL REFERENCES tenants(id) ON DELETE CASCADE,
+  idp_group            TEXT NOT NULL,
+  priority             INT NOT NULL DEFAULT 0,
+  permission_equipment TEXT NOT NULL,
+  permission_user      TEXT NOT NULL,
+  permission_todo      TEXT NOT NULL,
+  UNIQUE (tenant, idp_group)
+);
+INSERT INTO saml_permission_presets
+  (tenant, idp_group, priority, permission_equipment, permission_user, permission_todo)
   VALUES
-  ('dom', '$argon2id$v=19$m=19456,t=2,p=1$T9GO2wvNWMGcMQ/uPdH8lQ$EjVtyckTRnjly15GvDW3RAo2GvZPT/Dv7prpRDv6YcI', 'READ(*)|WRITE(*)|CREATE(true)', 'READ(*)|WRITE(*)|CREATE(true)', 'READ(equipment[2])|WRITE(equipment[2])|CREATE(true)'),
-  ('thewizzy', '$argon2id$v=19$m=19456,t=2,p=1$jGpHvsSseOmqYpSjYmHsDw$C/tnsXIf8dEdGojiKDcYis3e7gniaT40jvqIyFzri4c', 'READ(*)|WRITE(*)|CREATE(true)', 'READ(*)|WRITE(*)|CREATE(true)', 'READ(*)|WRITE(*)|CREATE(true)');
--- password for both: test
+  (1, 'admins', 0, 'READ(*)|WRITE(*)|CREATE(true)', 'READ(*)|WRITE(*)|CREATE(true)', 'READ(*)|WRITE(*)|CREATE(true)'),
+  (1, 'staff', 10, 'READ(*)|WRITE(equipment[1])|CREATE(false)', 'READ(*)|WRITE(person[1])|CREATE(false)', 'READ(*)|WRITE(equipment[1])|CREATE(true)');
+
+-- Authentication requests waiting for the IdP to answer, found by the hash of the RelayState it posts back
+CREATE TABLE saml_requests (
+  token_hash TEXT PRIMARY KEY,
+  request_id TEXT NOT NULL,
+  tenant     INT NOT NULL REFERENCES tenants(id) ON DELETE CASCADE,
+  -- Who links SAML to their account, NULL to log in
+  person     INT REFERENCES users(id) ON DELETE CASCADE,
+  expires_at TIMESTAMPTZ NOT NULL
+);
+
+-- The ways a user can log in, the subject is the username, email or SAML name the provider knows them by
+CREATE TABLE identities (
+  id         INTEGER PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
+  tenant     INT NOT NULL REFERENCES tenants(id) ON DELETE CASCADE,
+  person     INT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  provider   TEXT NOT NULL,
+  subject    TEXT NOT NULL,
+  created_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP,
+  UNIQUE (person, provider),
+  UNIQUE (tenant, provider, subject)
+);
+INSERT INTO identities (tenant, person, provider, subject) SELECT tenant, id, 'password', username FROM users;
+
+CREATE TABLE refresh_tokens (
+  id         INTEGER PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
+  person     INT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  family     TEXT NOT NULL,
+  token_hash TEXT NOT NULL UNIQUE,
+  expires_at TIMESTAMPTZ NOT NULL,
+  used_at    TIMESTAMPTZ,
+  revoked    BOOLEAN NOT NULL DEFAULT false,
+  created_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP
+);
+CREATE INDEX refresh_tokens_family ON refresh_tokens (family);
+
+-- Service accounts authenticate with these instead of a login, only the hash of the token is kept
+CREATE TABLE api_tokens (
+  id                   INTEGER PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
+  tenant               INT NOT NULL REFERENCES tenants(id) ON DELETE CASCADE,
+  person               INT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  name                 TEXT NOT NULL,
+  token_hash           TEXT NOT NULL UNIQUE,
+  -- Narrow the token down to less than the account may do, NULL leaves the permission of the account
+  permission_equipment TEXT,
+  permission_user      TEXT,
+  permission_todo      TEXT,
+  created_at           TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  last_used_at         TIMESTAMPTZ
+);
+CREATE INDEX api_tokens_person ON api_tokens (person);
+
+-- Todos are grouped into projects, permissions can be scoped to them with `project[id]`
+CREATE TABLE projects (
+  id         INTEGER PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
+  tenant     INT NOT NULL REFERENCES tenants(id) ON DELETE CASCADE,
+  name       TEXT NOT NULL,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  UNIQUE (tenant, name)
+);
 
 CREATE TABLE todos (
+  id          INTEGER PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
+  tenant      INT NOT NULL REFERENCES tenants(id) ON DELETE CASCADE,
+  person      INT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  title       TEXT NOT NULL,
+  -- Markdown, turned into HTML by the server when the todo is read
+  description TEXT,
+  completed   BOOLEAN,
+  created_at  TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP,
+  updated_at  TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  -- Bumped by every edit so stale edits can be refused
+  version     INT NOT NULL DEFAULT 1,
+  recurrence  TEXT,
+  due_at      TIMESTAMPTZ,
+  priority    SMALLINT NOT NULL DEFAULT 0,
+  position    INT NOT NULL DEFAULT 0,
+  -- Set once the next occurrence of a completed recurring todo has been created
+  recurred    BOOLEAN NOT NULL DEFAULT false,
+  -- Archived todos are kept out of the list until they are deleted by the retention
+  archived_at TIMESTAMPTZ,
+  project     INT REFERENCES projects(id) ON DELETE SET NULL,
+  -- Deleted todos are gone for everyone but can be brought back until the undo window is over
+  deleted_at  TIMESTAMPTZ
+);
+CREATE INDEX todos_active ON todos (tenant, position) WHERE archived_at IS NULL;
+INSERT INTO todos (tenant, person, title, completed) VALUES (1, 1, 'Something to do!', false), (1, 2, 'So much todo', false), (1, 1, 'Last thing!', false), (2, 3, 'Acme only', false);
+
+-- Who a todo is assigned to, besides the person it belongs to
+CREATE TABLE todo_assignees (
+  todo   INT NOT NULL REFERENCES todos(id) ON DELETE CASCADE,
+  person INT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  PRIMARY KEY (todo, person)
+);
+CREATE INDEX todo_assignees_person ON todo_assignees (person);
+
+CREATE TABLE attachments (
+  id           INTEGER PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
+  todo         INT NOT NULL REFERENCES todos(id) ON DELETE CASCADE,
+  person       INT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  filename     TEXT NOT NULL,
+  content_type TEXT NOT NULL,
+  size         BIGINT NOT NULL,
+  storage_key  TEXT NOT NULL UNIQUE,
+  created_at   TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE comments (
   id         INTEGER PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
+  todo       INT NOT NULL REFERENCES todos(id) ON DELETE CASCADE,
   person     INT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
-  title      TEXT NOT NULL,
-  completed  BOOLEAN,
+  body       TEXT NOT NULL,
   created_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP
 );
-INSERT INTO todos (person, title, completed) VALUES (1, 'Something to do!', false), (2, 'So much todo', false), (1, 'Last thing!', false);
+CREATE INDEX comments_todo ON comments (todo);
+
+CREATE TABLE todo_events (
+  id         INTEGER PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
+  todo       INT NOT NULL REFERENCES todos(id) ON DELETE CASCADE,
+  person     INT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  kind       TEXT NOT NULL,
+  old_value  TEXT,
+  new_value  TEXT,
+  created_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP
+);
+CREATE INDEX todo_events_todo ON todo_events (todo);
+
+CREATE TABLE notifications (
+  id         INTEGER PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
+  tenant     INT NOT NULL REFERENCES tenants(id) ON DELETE CASCADE,
+  person     INT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  todo       INT REFERENCES todos(id) ON DELETE SET NULL,
+  message    TEXT NOT NULL,
+  read_at    TIMESTAMPTZ,
+  created_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP
+);
+CREATE INDEX notifications_unread ON notifications (person) WHERE read_at IS NULL;
+
+CREATE TABLE notification_preferences (
+  person       INT PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+  todo_created BOOLEAN NOT NULL DEFAULT true,
+  todo_updated BOOLEAN NOT NULL DEFAULT true
+);
+
+-- Chat rooms that events about todos are posted to
+CREATE TABLE integrations (
+  id           INTEGER PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
+  tenant       INT NOT NULL REFERENCES tenants(id) ON DELETE CASCADE,
+  -- Who connected the room, only todos they can read are posted
+  person       INT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  -- Posts about every todo of the tenant instead of those of the person, only admins connect these
+  tenant_wide  BOOLEAN NOT NULL DEFAULT false,
+  kind         TEXT NOT NULL CHECK (kind IN ('slack', 'matrix')),
+  -- The webhook of Slack, the homeserver of Matrix
+  url          TEXT NOT NULL,
+  room         TEXT,
+  access_token TEXT,
+  events       TEXT[] NOT NULL,
+  last_error   TEXT,
+  created_at   TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP
+);
+CREATE INDEX integrations_tenant ON integrations (tenant);
+
+-- The due dates todos were found overdue at, so each one is only reported once
+CREATE TABLE overdue_todos (
+  todo   INT NOT NULL REFERENCES todos(id) ON DELETE CASCADE,
+  due_at TIMESTAMPTZ NOT NULL,
+  PRIMARY KEY (todo, due_at)
+);
+
+CREATE TABLE digest_subscriptions (
+  person            INT PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+  email             TEXT NOT NULL,
+  frequency         TEXT NOT NULL CHECK (frequency IN ('daily', 'weekly')),
+  unsubscribe_token TEXT NOT NULL UNIQUE,
+  last_sent_at      TIMESTAMPTZ,
+  created_at        TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE feature_flags (
+  tenant      INT NOT NULL REFERENCES tenants(id) ON DELETE CASCADE,
+  name        TEXT NOT NULL,
+  description TEXT NOT NULL DEFAULT '',
+  enabled     BOOLEAN NOT NULL DEFAULT false,
+  rollout     SMALLINT NOT NULL DEFAULT 100 CHECK (rollout BETWEEN 0 AND 100),
+  PRIMARY KEY (tenant, name)
+);
+
+CREATE TABLE magic_links (
+  token_hash TEXT PRIMARY KEY,
+  person     INT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  expires_at TIMESTAMPTZ NOT NULL,
+  used_at    TIMESTAMPTZ,
+  created_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP
+);
+
+-- New login emails waiting for the link mailed to them, one per user, confirming moves it to identities
+CREATE TABLE email_changes (
+  id         INTEGER PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
+  tenant     INT NOT NULL REFERENCES tenants(id) ON DELETE CASCADE,
+  person     INT NOT NULL UNIQUE REFERENCES users(id) ON DELETE CASCADE,
+  email      TEXT NOT NULL,
+  token_hash TEXT NOT NULL UNIQUE,
+  expires_at TIMESTAMPTZ NOT NULL,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+-- Calendar apps read the feed of a user with the token of its address, only the hash of the token is kept
+CREATE TABLE calendar_feeds (
+  person     INT PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+  token_hash TEXT NOT NULL UNIQUE,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+-- Read only links to a single todo for people without an account, revoking one deletes it
+CREATE TABLE share_links (
+  id         INTEGER PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
+  todo       INT NOT NULL REFERENCES todos(id) ON DELETE CASCADE,
+  person     INT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  token_hash TEXT NOT NULL UNIQUE,
+  expires_at TIMESTAMPTZ NOT NULL,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+-- Deleting or completing a todo can be taken back for a little while by whoever did it
+CREATE TABLE undo_tokens (
+  id         INTEGER PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
+  tenant     INT NOT NULL REFERENCES tenants(id) ON DELETE CASCADE,
+  person     INT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  todo       INT NOT NULL REFERENCES todos(id) ON DELETE CASCADE,
+  action     TEXT NOT NULL,
+  token_hash TEXT NOT NULL UNIQUE,
+  expires_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE TABLE outbox (
+  id           BIGINT PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
+  event        JSONB NOT NULL,
+  attempts     INT NOT NULL DEFAULT 0,
+  created_at   TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP,
+  delivered_at TIMESTAMPTZ
+);
+CREATE INDEX outbox_pending ON outbox (id) WHERE delivered_at IS NULL;
+
+-- Calls of WEBHOOK_URLS with outbox entries, claimed by pushing next_attempt_at out and retried until delivered
+CREATE TABLE webhook_calls (
+  id              BIGINT PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
+  outbox          BIGINT NOT NULL REFERENCES outbox(id) ON DELETE CASCADE,
+  url             TEXT NOT NULL,
+  attempts        INT NOT NULL DEFAULT 0,
+  next_attempt_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  delivered_at    TIMESTAMPTZ,
+  UNIQUE (outbox, url)
+);
+CREATE INDEX webhook_calls_pending ON webhook_calls (next_attempt_at) WHERE delivered_at IS NULL;
+
+-- Posts of outbox entries to integrations, claimed by pushing next_attempt_at out and retried until delivered
+CREATE TABLE integration_posts (
+  id              BIGINT PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
+  integration     INT NOT NULL REFERENCES integrations(id) ON DELETE CASCADE,
+  outbox          BIGINT NOT NULL REFERENCES outbox(id) ON DELETE CASCADE,
+  attempts        INT NOT NULL DEFAULT 0,
+  next_attempt_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  delivered_at    TIMESTAMPTZ,
+  UNIQUE (integration, outbox)
+);
+CREATE INDEX integration_posts_pending ON integration_posts (next_attempt_at) WHERE delivered_at IS NULL;
+
+CREATE TABLE rate_limits (
+  key        TEXT PRIMARY KEY,
+  hits       INT NOT NULL,
+  expires_at TIMESTAMPTZ NOT NULL
+);
+
+-- Which todos each user can read, only kept up to date with MATERIALIZED_VISIBILITY on
+CREATE TABLE user_visible_todos (
+  person INT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  todo   INT NOT NULL REFERENCES todos(id) ON DELETE CASCADE,
+  PRIMARY KEY (person, todo)
+);
+CREATE INDEX user_visible_todos_todo ON user_visible_todos (todo);
+
+-- Access to todos users lend each other, empty lists stand for any id like in a scope
+CREATE TABLE delegations (
+  id         INTEGER PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
+  tenant     INT NOT NULL REFERENCES tenants(id) ON DELETE CASCADE,
+  grantor    INT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  grantee    INT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  equipment  INT[] NOT NULL DEFAULT '{}',
+  persons    INT[] NOT NULL DEFAULT '{}',
+  write      BOOLEAN NOT NULL DEFAULT false,
+  until      TIMESTAMPTZ NOT NULL,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+CREATE INDEX delegations_grantee ON delegations (grantee, until);
+CREATE INDEX delegations_grantor ON delegations (grantor);
+
+-- Permissions admins set to take effect later, rows are deleted once applied
+CREATE TABLE scheduled_permission_changes (
+  id                   INTEGER PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
+  tenant               INT NOT NULL REFERENCES tenants(id) ON DELETE CASCADE,
+  person               INT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  permission_equipment TEXT NOT NULL,
+  permission_user      TEXT NOT NULL,
+  permission_todo      TEXT NOT NULL,
+  apply_at             TIMESTAMPTZ NOT NULL,
+  scheduled_by         INT REFERENCES users(id) ON DELETE SET NULL,
+  created_at           TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+CREATE INDEX scheduled_permission_changes_due ON scheduled_permission_changes (apply_at);
+
+-- Usernames users gave up, reserved for them for a while so nobody else can pose as them
+CREATE TABLE username_history (
+  id         INTEGER PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
+  tenant     INT NOT NULL REFERENCES tenants(id) ON DELETE CASCADE,
+  person     INT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  username   TEXT NOT NULL,
+  changed_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+CREATE INDEX username_history_username ON username_history (tenant, username);
+CREATE INDEX username_history_person ON username_history (person, changed_at);
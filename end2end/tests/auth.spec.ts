@@ -0,0 +1,56 @@
+import { expect, test } from "@playwright/test";
+import { expectHydrated, login, signup, uniqueUsername } from "./helpers";
+
+test("hydrates the server rendered page", async ({ page }) => {
+  await page.goto("/");
+  await expect(page.getByRole("heading", { name: "My Tasks" })).toBeVisible();
+  await expectHydrated(page);
+});
+
+test("hides user only links when logged out", async ({ page }) => {
+  await page.goto("/");
+  await expect(page.getByText("Logged out.")).toBeVisible();
+  await expect(page.getByRole("link", { name: "Settings" })).toHaveCount(0);
+  await expect(page.getByRole("link", { name: "Feature flags" })).toHaveCount(0);
+});
+
+test("sends the admin page to the login when logged out", async ({ page }) => {
+  await page.goto("/admin/flags");
+  await expect(page).toHaveURL(/\/login\?next=\/admin\/flags$/);
+  await expect(page.getByRole("button", { name: "Log In" })).toBeVisible();
+});
+
+test("signs up, logs out and logs back in", async ({ page }) => {
+  const username = uniqueUsername("auth");
+  await signup(page, username);
+  await expect(page.getByRole("link", { name: "Settings" })).toBeVisible();
+
+  await page.goto("/settings");
+  await page.getByRole("button", { name: "Log Out" }).click();
+  await expect(page.getByText("Logged out.")).toBeVisible();
+
+  await login(page, username);
+});
+
+test("rejects a wrong password", async ({ page }) => {
+  const username = uniqueUsername("wrong");
+  await signup(page, username);
+  await page.goto("/settings");
+  await page.getByRole("button", { name: "Log Out" }).click();
+
+  await page.goto("/login");
+  await page.getByPlaceholder("User Name").fill(username);
+  await page.getByPlaceholder("Password").fill("not-the-password");
+  await page.getByRole("button", { name: "Log In" }).click();
+  await expect(page.getByText("Logged out.")).toBeVisible();
+  await expect(page.getByText(`Logged in as: ${username}`)).toHaveCount(0);
+});
+
+test("rejects mismatching passwords on signup", async ({ page }) => {
+  await page.goto("/signup");
+  await page.getByPlaceholder("User Name").fill(uniqueUsername("mismatch"));
+  await page.getByPlaceholder("Password", { exact: true }).fill("correct-horse-battery-staple-42");
+  await page.getByPlaceholder("Password again").fill("correct-horse-battery-staple-43");
+  await page.getByRole("button", { name: "Sign Up" }).click();
+  await expect(page.getByText("Logged out.")).toBeVisible();
+});
@@ -0,0 +1,33 @@
+import { expect, Page } from "@playwright/test";
+
+// Every test signs up its own user so tests can run in parallel against the same database
+export function uniqueUsername(prefix: string): string {
+  return `${prefix}_${Date.now().toString(36)}${Math.random().toString(36).slice(2, 6)}`;
+}
+
+export const PASSWORD = "correct-horse-battery-staple-42";
+
+export async function signup(page: Page, username: string, password = PASSWORD) {
+  await page.goto("/signup");
+  await page.getByPlaceholder("User Name").fill(username);
+  await page.getByPlaceholder("Password", { exact: true }).fill(password);
+  await page.getByPlaceholder("Password again").fill(password);
+  await page.getByRole("button", { name: "Sign Up" }).click();
+  await expect(page.getByText(`Logged in as: ${username}`)).toBeVisible();
+}
+
+export async function login(page: Page, username: string, password = PASSWORD) {
+  await page.goto("/login");
+  await page.getByPlaceholder("User Name").fill(username);
+  await page.getByPlaceholder("Password").fill(password);
+  await page.getByRole("button", { name: "Log In" }).click();
+  await expect(page.getByText(`Logged in as: ${username}`)).toBeVisible();
+}
+
+// Client side navigation only works once the app hydrated, a full page load would drop the marker
+export async function expectHydrated(page: Page) {
+  await page.evaluate(() => ((window as any).__e2eMarker = true));
+  await page.getByRole("link", { name: "Login" }).click();
+  await expect(page).toHaveURL(/\/login$/);
+  expect(await page.evaluate(() => (window as any).__e2eMarker)).toBe(true);
+}
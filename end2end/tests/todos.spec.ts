@@ -0,0 +1,43 @@
+import { expect, test } from "@playwright/test";
+import { signup, uniqueUsername } from "./helpers";
+
+test("loads the list of a new user", async ({ page }) => {
+  // The default permissions of new users have to parse or every server function of the list answers with a 500
+  const failed: string[] = [];
+  page.on("response", (response) => {
+    if (response.status() >= 500) failed.push(`${response.status()} ${response.url()}`);
+  });
+
+  await signup(page, uniqueUsername("defaults"));
+  await page.goto("/");
+  await expect(page.getByRole("heading", { name: "My Tasks" })).toBeVisible();
+  await expect(page.getByLabel("Add a Todo")).toBeVisible();
+  await page.reload();
+  await expect(page.getByLabel("Add a Todo")).toBeVisible();
+  expect(failed).toEqual([]);
+});
+
+test("adds and deletes a todo", async ({ page }) => {
+  await signup(page, uniqueUsername("todos"));
+  const title = uniqueUsername("todo");
+
+  await page.getByLabel("Add a Todo").fill(title);
+  await page.getByRole("button", { name: "Add" }).click();
+  const todo = page.locator("li", { hasText: title });
+  await expect(todo).toBeVisible();
+
+  await todo.getByRole("button", { name: "X" }).first().click();
+  await expect(todo).toHaveCount(0);
+});
+
+test("keeps a todo across reloads", async ({ page }) => {
+  await signup(page, uniqueUsername("reload"));
+  const title = uniqueUsername("todo");
+
+  await page.getByLabel("Add a Todo").fill(title);
+  await page.getByRole("button", { name: "Add" }).click();
+  await expect(page.locator("li", { hasText: title })).toBeVisible();
+
+  await page.reload();
+  await expect(page.locator("li", { hasText: title })).toBeVisible();
+});
@@ -0,0 +1,202 @@
+//! API tokens, how service accounts authenticate instead of logging in
+//!
+//! A token is sent as `Authorization: Bearer` like a JWT, the prefix tells them apart before any JWT keys are needed.
+//! Only the hash of a token is stored, it's shown once when it's created or rotated. Tokens don't expire, they work
+//! until they are rotated or revoked or the account is deactivated.
+//!
+//! A token can be narrowed down to less than its account may do, with permission strings in the same grammar. Those
+//! are intersected with the permissions of the account on every request, so a token never does more than either.
+//! Access others delegated to the account doesn't reach a narrowed token.
+
+use crate::{
+	auth::User,
+	jwt::ssr::{generate_token, hash_token, Claims},
+	permission::{LazyPermissions, Permission, Permissions},
+	tenant::Tenant,
+};
+use axum::http::StatusCode;
+use chrono::prelude::*;
+use sqlx::{PgExecutor, PgPool};
+
+/// What every API token starts with
+pub const API_TOKEN_PREFIX: &str = "sat_";
+
+const TOKEN_LENGTH: usize = 48;
+
+pub fn is_api_token(token: &str) -> bool {
+	token.starts_with(API_TOKEN_PREFIX)
+}
+
+/// What a token is narrowed down to, `None` leaves the permission of the account as it is
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TokenScopes {
+	pub permission_equipment: Option<Permissions>,
+	pub permission_user: Option<Permissions>,
+	pub permission_todo: Option<Permissions>,
+}
+
+impl TokenScopes {
+	/// The scopes of the strings as stored, `None` when the token isn't narrowed down at all
+	pub fn parse(
+		permission_equipment: Option<String>,
+		permission_user: Option<String>,
+		permission_todo: Option<String>,
+	) -> Result<Option<Self>, &'static str> {
+		if permission_equipment.is_none() && permission_user.is_none() && permission_todo.is_none() {
+			return Ok(None);
+		}
+
+		Ok(Some(Self {
+			permission_equipment: permission_equipment.map(Permission::parse).transpose()?,
+			permission_user: permission_user.map(Permission::parse).transpose()?,
+			permission_todo: permission_todo.map(Permission::parse).transpose()?,
+		}))
+	}
+
+	/// The user with the permissions the token has scopes for intersected with them
+	pub fn narrow(&self, user: User) -> User {
+		let narrow = |permissions: &LazyPermissions, scopes: &Option<Permissions>| match scopes {
+			Some(scopes) => LazyPermissions::from(permissions.get().intersect(scopes)),
+			None => permissions.clone(),
+		};
+		let permission_equipment = narrow(&user.permission_equipment, &self.permission_equipment);
+		let permission_user = narrow(&user.permission_user, &self.permission_user);
+		let permission_todo = narrow(&user.permission_todo, &self.permission_todo);
+
+		User {
+			permission_equipment,
+			permission_user,
+			permission_todo,
+			token_scoped: true,
+			..user
+		}
+	}
+}
+
+fn new_token() -> String {
+	format!("{API_TOKEN_PREFIX}{}", generate_token(TOKEN_LENGTH))
+}
+
+/// Stands in for the refresh token family of a JWT, API tokens have none so logging out with one revokes nothing
+fn session_id(id: i32) -> String {
+	format!("api_token:{id}")
+}
+
+/// Issue a token for `person` and return it, the only time it's seen in full
+///
+/// The permission strings narrow the token down, validate them first.
+pub async fn issue(
+	executor: impl PgExecutor<'_>,
+	tenant: i32,
+	person: i32,
+	name: &str,
+	permission_equipment: Option<&str>,
+	permission_user: Option<&str>,
+	permission_todo: Option<&str>,
+) -> Result<String, sqlx::Error> {
+	let token = new_token();
+	sqlx::query(
+		"INSERT INTO api_tokens
+		(tenant, person, name, token_hash, permission_equipment, permission_user, permission_todo)
+		VALUES ($1, $2, $3, $4, $5, $6, $7)",
+	)
+	.bind(tenant)
+	.bind(person)
+	.bind(name)
+	.bind(hash_token(&token))
+	.bind(permission_equipment)
+	.bind(permission_user)
+	.bind(permission_todo)
+	.execute(executor)
+	.await?;
+
+	Ok(token)
+}
+
+/// Replace the secret of a token of a service account of the tenant, the old one stops working right away
+///
+/// `None` when there is no such token
+pub async fn rotate(id: i32, tenant: i32, pool: &PgPool) -> Result<Option<String>, sqlx::Error> {
+	let token = new_token();
+	let rotated = sqlx::query(
+		"UPDATE api_tokens SET token_hash = $1, created_at = now(), last_used_at = NULL
+		WHERE id = $2 AND tenant = $3",
+	)
+	.bind(hash_token(&token))
+	.bind(id)
+	.bind(tenant)
+	.execute(pool)
+	.await?
+	.rows_affected();
+
+	Ok((rotated > 0).then_some(token))
+}
+
+/// The user an API token authenticates as, with claims that stand in for those of a JWT
+pub async fn authenticate(token: &str, tenant: &Tenant, pool: &PgPool) -> Result<(Claims, User), (StatusCode, String)> {
+	let invalid_token = (StatusCode::UNAUTHORIZED, String::from("Invalid token"));
+
+	let (id, person, created_at, permission_equipment, permission_user, permission_todo) =
+		sqlx::query_as::<_, (i32, i32, DateTime<Utc>, Option<String>, Option<String>, Option<String>)>(
+			"UPDATE api_tokens SET last_used_at = now() WHERE token_hash = $1 AND tenant = $2
+		RETURNING id, person, created_at, permission_equipment, permission_user, permission_todo",
+		)
+		.bind(hash_token(token))
+		.bind(tenant.id)
+		.fetch_optional(pool)
+		.await
+		.map_err(|error| (StatusCode::INTERNAL_SERVER_ERROR, error.to_string()))?
+		.ok_or(invalid_token.clone())?;
+	// Scopes that don't parse would otherwise leave the token with everything the account may do
+	let scopes =
+		TokenScopes::parse(permission_equipment, permission_user, permission_todo).map_err(|_| invalid_token.clone())?;
+	let user = User::get_from_id(person, pool)
+		.await
+		.filter(|user| user.active && user.tenant == tenant.id)
+		.ok_or(invalid_token)?;
+
+	let claims = Claims {
+		sub: user.id,
+		tenant: tenant.id,
+		sid: session_id(id),
+		iat: created_at.timestamp(),
+		exp: i64::MAX,
+		scopes,
+	};
+
+	Ok((claims, user))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn tokens_are_told_apart_from_jwts() {
+		let token = new_token();
+		assert!(is_api_token(&token));
+		assert_eq!(token.len(), API_TOKEN_PREFIX.len() + TOKEN_LENGTH);
+		assert!(!is_api_token("eyJhbGciOiJIUzI1NiJ9.e30.signature"));
+	}
+
+	#[test]
+	fn scopes_narrow_the_account() {
+		let parse = |permission: &str| Permission::parse(permission.to_string()).unwrap();
+		let user = User {
+			id: 3,
+			permission_todo: parse("READ(*)|WRITE(*)|CREATE(true)").into(),
+			permission_user: parse("READ(*)|WRITE(*)|CREATE(true)").into(),
+			..User::default()
+		};
+		assert_eq!(TokenScopes::parse(None, None, None), Ok(None));
+
+		let scopes = TokenScopes::parse(None, None, Some(String::from("READ(project[2])|WRITE(project[2])|CREATE(false)")))
+			.unwrap()
+			.unwrap();
+		let narrowed = scopes.narrow(user.clone());
+		assert!(narrowed.token_scoped);
+		assert_eq!(narrowed.permission_todo(), &parse("READ(project[2])|WRITE(project[2])|CREATE(false)"));
+		// Resources without a scope keep what the account has
+		assert_eq!(narrowed.permission_user, user.permission_user);
+	}
+}
@@ -0,0 +1,162 @@
+use crate::{
+	auth::ConfirmPassword,
+	errors::AppServerError,
+	toast::{use_toasts, Toasted},
+};
+use leptos::*;
+
+/// Longest todos can wait to be archived or deleted, in days, far longer ones would overflow the timestamps of Postgres
+pub const MAX_ARCHIVE_DAYS: i32 = 100 * 365;
+
+/// The days as the `int4` that `make_interval` takes, `None` above [`MAX_ARCHIVE_DAYS`]
+pub fn archive_days(days: u32) -> Option<i32> {
+	i32::try_from(days).ok().filter(|days| *days <= MAX_ARCHIVE_DAYS)
+}
+
+#[cfg(feature = "ssr")]
+pub mod ssr {
+	use super::{archive_days, MAX_ARCHIVE_DAYS};
+	use crate::{cache::response_cache, config::Config, jobs};
+	use sqlx::PgPool;
+	use std::time::Duration;
+
+	/// Cached lists of every tenant that lost or changed todos are stale
+	fn invalidate(mut tenants: Vec<i32>) -> u64 {
+		let changed = tenants.len() as u64;
+		tenants.sort_unstable();
+		tenants.dedup();
+		for tenant in tenants {
+			response_cache().invalidate_tenant(tenant);
+		}
+
+		changed
+	}
+
+	/// Archive completed todos that didn't change for `days`, of a single tenant or of all of them, returns how many
+	///
+	/// Nobody made this change, so it doesn't show up in the history of the todos or notify anyone
+	pub async fn archive_completed_older_than(days: i32, tenant: Option<i32>, pool: &PgPool) -> Result<u64, sqlx::Error> {
+		let tenants = sqlx::query_scalar::<_, i32>(
+			"UPDATE todos SET archived_at = now(), updated_at = now(), version = version + 1
+			WHERE completed AND archived_at IS NULL AND updated_at < now() - make_interval(days => $1)
+			AND ($2::int4 IS NULL OR tenant = $2)
+			RETURNING tenant",
+		)
+		.bind(days)
+		.bind(tenant)
+		.fetch_all(pool)
+		.await?;
+
+		Ok(invalidate(tenants))
+	}
+
+	/// Delete todos that were archived more than `days` ago, returns how many
+	pub async fn delete_archived_older_than(days: i32, pool: &PgPool) -> Result<u64, sqlx::Error> {
+		let tenants = sqlx::query_scalar::<_, i32>(
+			"DELETE FROM todos WHERE archived_at < now() - make_interval(days => $1) RETURNING tenant",
+		)
+		.bind(days)
+		.fetch_all(pool)
+		.await?;
+
+		Ok(invalidate(tenants))
+	}
+
+	/// Apply the retention of the config every `ARCHIVE_INTERVAL` seconds
+	///
+	/// Doesn't start at all when neither `ARCHIVE_COMPLETED_AFTER` nor `ARCHIVE_RETENTION` is set
+	pub fn spawn_archiver(pool: PgPool, config: &Config) {
+		if config.archive_completed_after == 0 && config.archive_retention == 0 {
+			return;
+		}
+		let (Some(archive_after), Some(retention)) =
+			(archive_days(config.archive_completed_after), archive_days(config.archive_retention))
+		else {
+			log::error!(
+				"ARCHIVE_COMPLETED_AFTER and ARCHIVE_RETENTION can't be more than {MAX_ARCHIVE_DAYS} days, not archiving"
+			);
+			return;
+		};
+
+		jobs::every("Archive", Duration::from_secs(config.archive_interval), move || {
+			let pool = pool.clone();
+			async move {
+				let archived = match archive_after {
+					0 => 0,
+					days => archive_completed_older_than(days, None, &pool).await?,
+				};
+				let deleted = match retention {
+					0 => 0,
+					days => delete_archived_older_than(days, &pool).await?,
+				};
+				log::info!("Archived {archived} completed todos and deleted {deleted} archived todos");
+				Ok::<_, sqlx::Error>(())
+			}
+		});
+	}
+}
+
+/// Archive the completed todos of the tenant that didn't change for `days`, admins only
+#[server]
+pub async fn archive_completed(days: u32) -> Result<Toasted<u64>, ServerFnError<AppServerError>> {
+	use self::ssr::archive_completed_older_than;
+	use crate::{
+		auth::{
+			get_user,
+			ssr::{require_recent_auth, STEP_UP_MAX_AGE},
+		},
+		denial::{ssr::record_denial, DeniedAction},
+		tenant::Tenant,
+	};
+	use sqlx::PgPool;
+
+	let pool = use_context::<PgPool>().expect("Database not initialized");
+	let tenant = use_context::<Tenant>().expect("No tenant found");
+	let user = get_user().await?.ok_or_else(AppServerError::unauthenticated)?;
+	if !user.is_admin() {
+		record_denial("archive_completed", DeniedAction::Admin, &user);
+		return Err(AppServerError::forbidden("Missing permission to archive todos").into());
+	}
+	require_recent_auth(STEP_UP_MAX_AGE)?;
+	let days = archive_days(days)
+		.ok_or_else(|| AppServerError::invalid(format!("Todos can be archived after up to {MAX_ARCHIVE_DAYS} days")))?;
+
+	let archived = archive_completed_older_than(days, Some(tenant.id), &pool).await.map_err(AppServerError::from)?;
+
+	Ok(Toasted::success(archived, format!("Archived {archived} completed todos")))
+}
+
+/// Lets admins archive old completed todos right away instead of waiting for the retention
+#[component]
+pub fn ArchiveCompletedForm() -> impl IntoView {
+	let archive = create_server_action::<ArchiveCompleted>();
+	use_toasts().follow(archive);
+	let error = Signal::derive(move || archive.value().get().and_then(Result::err));
+
+	view! {
+		<ActionForm action=archive>
+			<label>
+				"Archive todos completed more than "
+				<input type="number" name="days" min=0 max=MAX_ARCHIVE_DAYS value=30 required=true />
+				" days ago"
+			</label>
+			<input type="submit" value="Archive" />
+		</ActionForm>
+		<ConfirmPassword error=error />
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn archive_days_are_capped() {
+		assert_eq!(archive_days(0), Some(0));
+		assert_eq!(archive_days(30), Some(30));
+		assert_eq!(archive_days(36_500), Some(MAX_ARCHIVE_DAYS));
+		assert_eq!(archive_days(36_501), None);
+		assert_eq!(archive_days(2_000_000_000), None);
+		assert_eq!(archive_days(u32::MAX), None);
+	}
+}
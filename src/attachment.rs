@@ -0,0 +1,220 @@
+use crate::errors::AppServerError;
+use chrono::prelude::*;
+use leptos::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "ssr", derive(sqlx::FromRow, utoipa::ToSchema))]
+pub struct Attachment {
+	pub id: i32,
+	pub todo: i32,
+	pub filename: String,
+	pub content_type: String,
+	pub size: i64,
+	pub created_at: DateTime<Utc>,
+}
+
+#[cfg(feature = "ssr")]
+pub mod ssr {
+	use crate::{
+		auth::ssr::AuthSession,
+		authorize::Action,
+		denial::{ssr::record_denial, DeniedAction},
+		fallback::unauthenticated,
+		routes::Route,
+		state::AppState,
+		storage::generate_key,
+		tenant::Tenant,
+		todo::ssr::get_todo_with_permission,
+	};
+	use axum::{
+		extract::{Multipart, Path, State},
+		http::{header, StatusCode},
+		response::{IntoResponse, Redirect, Response},
+	};
+
+	#[derive(sqlx::FromRow)]
+	struct SqlAttachmentFile {
+		todo: i32,
+		filename: String,
+		content_type: String,
+		storage_key: String,
+	}
+
+	fn internal_error(error: impl std::fmt::Display) -> (StatusCode, String) {
+		(StatusCode::INTERNAL_SERVER_ERROR, format!("Something went wrong: {error}"))
+	}
+
+	// File names end up in a header so we only keep what's safe to put in there
+	fn sanitize_filename(filename: &str) -> String {
+		filename
+			.chars()
+			.map(|c| {
+				if c.is_ascii_graphic() && c != '"' && c != '\\' {
+					c
+				} else {
+					'_'
+				}
+			})
+			.collect()
+	}
+
+	pub async fn upload_attachment(
+		State(app_state): State<AppState>,
+		auth_session: AuthSession,
+		tenant: Tenant,
+		Path(todo_id): Path<i32>,
+		mut multipart: Multipart,
+	) -> Result<Redirect, (StatusCode, String)> {
+		let user = auth_session.current_user.filter(|user| user.tenant == tenant.id).ok_or_else(unauthenticated)?;
+
+		if get_todo_with_permission(todo_id, tenant.id, &user, Action::Write, &app_state.pool).await.is_none() {
+			record_denial("upload_attachment", DeniedAction::Write, &user);
+			return Err((StatusCode::FORBIDDEN, String::from("Missing write permission for this todo")));
+		}
+
+		while let Some(field) =
+			multipart.next_field().await.map_err(|error| (StatusCode::BAD_REQUEST, error.to_string()))?
+		{
+			if field.name() != Some("file") {
+				continue;
+			}
+
+			let filename = field.file_name().unwrap_or("attachment").to_string();
+			let content_type = field.content_type().unwrap_or("application/octet-stream").to_string();
+			let data = field.bytes().await.map_err(|error| (StatusCode::BAD_REQUEST, error.to_string()))?;
+
+			if data.is_empty() {
+				continue;
+			}
+
+			let key = generate_key();
+			let size = data.len() as i64;
+			app_state.storage.put(&key, data).await.map_err(internal_error)?;
+
+			if let Err(error) = sqlx::query(
+				"INSERT INTO attachments (todo, person, filename, content_type, size, storage_key) VALUES ($1, $2, $3, $4, $5, $6)",
+			)
+			.bind(todo_id)
+			.bind(user.id)
+			.bind(filename)
+			.bind(content_type)
+			.bind(size)
+			.bind(&key)
+			.execute(&app_state.pool)
+			.await
+			{
+				// Don't leave orphaned files behind
+				let _ = app_state.storage.delete(&key).await;
+				return Err(internal_error(error));
+			}
+		}
+
+		Ok(Redirect::to(&Route::Home.path()))
+	}
+
+	pub async fn download_attachment(
+		State(app_state): State<AppState>,
+		auth_session: AuthSession,
+		tenant: Tenant,
+		Path(id): Path<i32>,
+	) -> Result<Response, (StatusCode, String)> {
+		let user = auth_session.current_user.filter(|user| user.tenant == tenant.id).ok_or_else(unauthenticated)?;
+
+		let not_found = (StatusCode::NOT_FOUND, String::from("Not Found"));
+		let file = sqlx::query_as::<_, SqlAttachmentFile>(
+			"SELECT todo, filename, content_type, storage_key FROM attachments WHERE id = $1",
+		)
+		.bind(id)
+		.fetch_optional(&app_state.pool)
+		.await
+		.map_err(internal_error)?
+		.ok_or(not_found.clone())?;
+
+		// We answer with a 404 so users can't probe for attachments on todos they can't read
+		get_todo_with_permission(file.todo, tenant.id, &user, Action::Read, &app_state.pool).await.ok_or(not_found)?;
+
+		let data = app_state.storage.get(&file.storage_key).await.map_err(internal_error)?;
+
+		Ok(
+			(
+				[
+					(header::CONTENT_TYPE, file.content_type),
+					(header::CONTENT_DISPOSITION, format!("attachment; filename=\"{}\"", sanitize_filename(&file.filename))),
+					(header::X_CONTENT_TYPE_OPTIONS, String::from("nosniff")),
+				],
+				data,
+			)
+				.into_response(),
+		)
+	}
+}
+
+#[server(input = GetUrl)]
+pub async fn get_attachments(todo_id: i32) -> Result<Vec<Attachment>, ServerFnError<AppServerError>> {
+	use crate::{
+		auth::get_user, authorize::Action, cache::cache_privately, tenant::Tenant, todo::ssr::get_todo_with_permission,
+	};
+	use sqlx::PgPool;
+
+	cache_privately::<GetAttachments>(0);
+
+	let pool = use_context::<PgPool>().expect("Database not initialized");
+	let tenant = use_context::<Tenant>().expect("No tenant found");
+	let user = get_user().await?.ok_or_else(AppServerError::unauthenticated)?;
+
+	if get_todo_with_permission(todo_id, tenant.id, &user, Action::Read, &pool).await.is_none() {
+		return Err(AppServerError::not_found("Todo not found").into());
+	}
+
+	Ok(
+		sqlx::query_as::<_, Attachment>(
+			"SELECT id, todo, filename, content_type, size, created_at FROM attachments WHERE todo = $1 ORDER BY id",
+		)
+		.bind(todo_id)
+		.fetch_all(&pool)
+		.await
+		.map_err(AppServerError::from)?,
+	)
+}
+
+#[component]
+pub fn Attachments(todo_id: i32) -> impl IntoView {
+	let attachments = create_resource(move || (), move |_| get_attachments(todo_id));
+
+	view! {
+		<div class="attachments">
+			<Transition fallback=move || ()>
+				{move || {
+					attachments
+						.get()
+						.map(|attachments| match attachments {
+							Err(e) => view! { <span class="error">{e.to_string()}</span> }.into_view(),
+							Ok(attachments) => {
+								attachments
+									.into_iter()
+									.map(|attachment| {
+										view! {
+											<a href=format!("/attachments/{}", attachment.id) rel="external">
+												{attachment.filename}
+											</a>
+											" "
+										}
+									})
+									.collect_view()
+							}
+						})
+				}}
+
+			</Transition>
+			<form
+				method="post"
+				enctype="multipart/form-data"
+				action=format!("/attachments/upload/{todo_id}")
+			>
+				<input type="file" name="file" />
+				<input type="submit" value="Attach" />
+			</form>
+		</div>
+	}
+}
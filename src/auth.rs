@@ -1,30 +1,86 @@
 use leptos::*;
+use leptos_router::ActionForm;
 use serde::{Deserialize, Serialize};
 
-use crate::permission::{Permission, Permissions, Scope};
+use crate::{
+	errors::{AppError, AppServerError, ErrorCode},
+	permission::{LazyPermissions, Permission, Permissions},
+	routes::Route,
+};
+
+/// The message of server functions refused by [`ssr::require_recent_auth`], [`ConfirmPassword`] asks for the password
+/// when it sees their [`ErrorCode::ReauthenticationRequired`]
+pub const REAUTHENTICATION_REQUIRED: &str = "Please confirm your password to continue.";
+
+/// How long before the session runs out [`SessionExpiryWarning`] warns about it
+const SESSION_WARNING: std::time::Duration = std::time::Duration::from_secs(60);
+/// How often [`SessionExpiryWarning`] looks at the clock
+const SESSION_CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(5);
 
 // Explicitly not Serialize/Deserialize
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct UserPasshash(String);
 
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "ssr", derive(utoipa::ToSchema))]
 pub struct User {
+	pub id: i32,
+	pub tenant: i32,
+	pub username: String,
+	pub avatar: Option<String>,
+	#[cfg_attr(feature = "ssr", schema(value_type = Permissions))]
+	pub permission_equipment: LazyPermissions,
+	#[cfg_attr(feature = "ssr", schema(value_type = Permissions))]
+	pub permission_user: LazyPermissions,
+	#[cfg_attr(feature = "ssr", schema(value_type = Permissions))]
+	pub permission_todo: LazyPermissions,
+	/// Deactivated users can't log in and their sessions end with their next request
+	pub active: bool,
+	/// Service accounts can't log in, they only authenticate with API tokens
+	pub service_account: bool,
+	/// The permissions were narrowed down to the scopes of the API token the request came with, so they can be less
+	/// than the stored ones
+	pub token_scoped: bool,
+}
+
+/// What responses show of users other than the logged in one, their permissions stay on the server
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "ssr", derive(sqlx::FromRow, utoipa::ToSchema))]
+pub struct PublicUser {
 	pub id: i32,
 	pub username: String,
-	pub permission_equipment: Permissions,
-	pub permission_user: Permissions,
-	pub permission_todo: Permissions,
+	pub avatar: Option<String>,
+}
+
+impl From<User> for PublicUser {
+	fn from(user: User) -> Self {
+		Self {
+			id: user.id,
+			username: user.username,
+			avatar: user.avatar,
+		}
+	}
+}
+
+impl Default for PublicUser {
+	fn default() -> Self {
+		User::default().into()
+	}
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[cfg_attr(feature = "ssr", derive(sqlx::FromRow))]
 pub struct UserSQL {
 	pub id: i32,
+	pub tenant: i32,
 	pub username: String,
 	pub password: String,
+	pub avatar: Option<String>,
 	pub permission_equipment: String,
 	pub permission_user: String,
 	pub permission_todo: String,
+	pub active: bool,
+	pub service_account: bool,
 }
 
 #[cfg(feature = "ssr")]
@@ -32,10 +88,15 @@ impl From<UserSQL> for User {
 	fn from(val: UserSQL) -> Self {
 		User {
 			id: val.id,
+			tenant: val.tenant,
 			username: val.username,
-			permission_equipment: Permission::parse(val.permission_equipment).expect("Invalid permission string"),
-			permission_user: Permission::parse(val.permission_user).expect("Invalid permission string"),
-			permission_todo: Permission::parse(val.permission_todo).expect("Invalid permission string"),
+			avatar: val.avatar,
+			permission_equipment: LazyPermissions::new(val.permission_equipment),
+			permission_user: LazyPermissions::new(val.permission_user),
+			permission_todo: LazyPermissions::new(val.permission_todo),
+			active: val.active,
+			service_account: val.service_account,
+			token_scoped: false,
 		}
 	}
 }
@@ -48,51 +109,353 @@ impl UserSQL {
 	}
 }
 
+/// The guest, allowed nothing so a default user that ends up in a permission check is always refused
 impl Default for User {
 	fn default() -> Self {
 		Self {
 			id: -1,
+			tenant: -1,
 			username: "Guest".into(),
+			avatar: None,
 			permission_equipment: Permissions::ReadWrite {
-				read: Permission::Read(vec![Scope::Equipment(-1)]),
-				write: Permission::Write(vec![Scope::Equipment(-1)]),
+				read: Permission::Read(Vec::new()),
+				write: Permission::Write(Vec::new()),
 				create: Permission::Create(false),
-			},
+			}
+			.into(),
 			permission_user: Permissions::ReadWrite {
-				read: Permission::Read(vec![Scope::Equipment(-1)]),
-				write: Permission::Write(vec![Scope::Equipment(-1)]),
+				read: Permission::Read(Vec::new()),
+				write: Permission::Write(Vec::new()),
 				create: Permission::Create(false),
-			},
+			}
+			.into(),
 			permission_todo: Permissions::ReadWrite {
-				read: Permission::Read(vec![Scope::Equipment(-1)]),
-				write: Permission::Write(vec![Scope::Equipment(-1)]),
+				read: Permission::Read(Vec::new()),
+				write: Permission::Write(Vec::new()),
 				create: Permission::Create(false),
-			},
+			}
+			.into(),
+			active: true,
+			service_account: false,
+			token_scoped: false,
 		}
 	}
 }
 
+impl User {
+	/// A user allowed everything, for tests that aren't about permissions
+	pub fn privileged_default() -> Self {
+		let everything = || {
+			Permissions::ReadWrite {
+				read: Permission::ReadAny,
+				write: Permission::WriteAny,
+				create: Permission::Create(true),
+			}
+			.into()
+		};
+
+		Self {
+			permission_equipment: everything(),
+			permission_user: everything(),
+			permission_todo: everything(),
+			..Self::default()
+		}
+	}
+
+	pub fn permission_equipment(&self) -> &Permissions {
+		self.permission_equipment.get()
+	}
+
+	pub fn permission_user(&self) -> &Permissions {
+		self.permission_user.get()
+	}
+
+	pub fn permission_todo(&self) -> &Permissions {
+		self.permission_todo.get()
+	}
+
+	/// Admins can change any user of their tenant, which also lets them manage the tenant itself
+	pub fn is_admin(&self) -> bool {
+		let Permissions::ReadWrite { write, .. } = self.permission_user();
+		*write == Permission::WriteAny
+	}
+}
+
 #[cfg(feature = "ssr")]
 pub mod ssr {
-	pub use super::{User, UserPasshash, UserSQL};
+	pub use super::{PublicUser, User, UserPasshash, UserSQL};
+	pub use crate::auth_backend::AuthBackend;
+	use crate::{
+		breaker::ssr::breaker,
+		config::Config,
+		errors::{AppServerError, ErrorCode},
+		jwt::ssr::Claims,
+		retry::retry_read,
+		tenant::{Tenant, TenantSettings},
+	};
 	pub use argon2::{
 		self,
 		password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
 		Argon2,
 	};
 	pub use async_trait::async_trait;
+	pub use axum_session::SessionAnyPool;
+	use axum_session::{SessionConfig, SessionError, SessionLayer, SessionStore};
+	use axum_session_auth::{AuthConfig, AuthSessionLayer};
 	pub use axum_session_auth::{Authentication, HasPermission};
-	pub use axum_session_sqlx::SessionPgPool;
 	pub use rand::rngs::OsRng;
 	pub use sqlx::PgPool;
-	pub use std::collections::HashSet;
+	use std::sync::{Mutex, OnceLock};
+	pub use std::{collections::HashSet, sync::Arc};
+
+	pub type AuthSession = axum_session_auth::AuthSession<User, i32, SessionAnyPool, Arc<dyn AuthBackend>>;
+	/// The layer that hands out an [`AuthSession`] to every request, underneath the session layer
+	pub type AuthLayer = AuthSessionLayer<User, i32, SessionAnyPool, Arc<dyn AuthBackend>>;
+
+	/// The session layer and the auth layer on top of it, built together so they share the session store and the types
+	/// of [`AuthSession`]
+	pub struct AuthStack {
+		pub session: SessionLayer<SessionAnyPool>,
+		pub auth: AuthLayer,
+	}
+
+	pub struct AuthStackBuilder {
+		pool: SessionAnyPool,
+		table_name: &'static str,
+		backend: Option<Arc<dyn AuthBackend>>,
+	}
+
+	impl AuthStack {
+		/// Sessions kept in `pool`, in the `axum_sessions` table the cleanup purges unless another one is named
+		pub fn builder(pool: SessionAnyPool) -> AuthStackBuilder {
+			AuthStackBuilder {
+				pool,
+				table_name: "axum_sessions",
+				backend: None,
+			}
+		}
+	}
+
+	impl AuthStackBuilder {
+		pub fn table_name(mut self, table_name: &'static str) -> Self {
+			self.table_name = table_name;
+			self
+		}
+
+		/// Where users are loaded from, without one every request is logged out
+		pub fn backend(mut self, backend: Arc<dyn AuthBackend>) -> Self {
+			self.backend = Some(backend);
+			self
+		}
+
+		/// Fails when the session table can't be created
+		pub async fn build(self) -> Result<AuthStack, SessionError> {
+			let config = SessionConfig::default().with_table_name(self.table_name);
+			let store = SessionStore::<SessionAnyPool>::new(Some(self.pool), config).await?;
 
-	pub type AuthSession = axum_session_auth::AuthSession<User, i32, SessionPgPool, PgPool>;
+			Ok(AuthStack {
+				session: SessionLayer::new(store),
+				auth: AuthLayer::new(self.backend).with_config(AuthConfig::<i32>::default()),
+			})
+		}
+	}
+
+	/// Session key holding the unix timestamp of the last login, used to enforce the tenant's session lifetime
+	pub const LOGGED_IN_AT: &str = "logged_in_at";
+	/// Session key holding the unix timestamp the session was last extended at, its lifetime starts over from there
+	pub const EXTENDED_AT: &str = "extended_at";
+	/// However often it's extended, a session ends this long after its login or after its lifetime if that is longer
+	pub const SESSION_MAX_AGE: i64 = 90 * 24 * 60 * 60;
+	/// Session key holding the session generation of the user at login, only set in single session mode
+	pub const SESSION_GENERATION: &str = "session_generation";
+	/// Session key holding the unix timestamp the user last entered their password, see [`require_recent_auth`]
+	pub const AUTHENTICATED_AT: &str = "authenticated_at";
+
+	/// The user of the current request, resolved once by the first [`get_user`](super::get_user) and shared by every
+	/// server function the request runs, so the session checks and permission parsing aren't repeated
+	///
+	/// Provide a new one for every request, a login or logout only shows in the requests after it
+	#[derive(Clone, Debug, Default)]
+	pub struct RequestUser(Arc<tokio::sync::OnceCell<Option<User>>>);
+
+	static STALE_USERS: OnceLock<Mutex<HashSet<i32>>> = OnceLock::new();
+
+	/// Have the sessions of a user load it again on their next request, for changes made outside of any request like
+	/// by background jobs, requests call [`AuthSession::cache_clear_user`] themselves
+	pub fn reload_user(user: i32) {
+		STALE_USERS.get_or_init(Default::default).lock().expect("Stale users poisoned").insert(user);
+	}
+
+	/// When the session was logged in, sessions from before this was kept start their lifetime on their next request
+	pub(crate) fn logged_in_at(auth: &AuthSession) -> i64 {
+		auth.session.get::<i64>(LOGGED_IN_AT).unwrap_or_else(|| {
+			let now = chrono::Utc::now().timestamp();
+			auth.session.set(LOGGED_IN_AT, now);
+			now
+		})
+	}
+
+	/// The unix timestamp the session runs out at
+	pub(crate) fn session_expires_at(logged_in_at: i64, extended_at: Option<i64>, lifetime: i64) -> i64 {
+		let expires_at = extended_at.unwrap_or(logged_in_at).max(logged_in_at) + lifetime;
+
+		expires_at.min(logged_in_at + lifetime.max(SESSION_MAX_AGE))
+	}
+
+	fn take_stale(user: i32) -> bool {
+		STALE_USERS.get().is_some_and(|stale| stale.lock().expect("Stale users poisoned").remove(&user))
+	}
+
+	pub async fn start_session(
+		auth: &AuthSession,
+		user_id: i32,
+		remember: bool,
+		config: &Config,
+		pool: &PgPool,
+	) -> Result<(), sqlx::Error> {
+		let generation = end_other_sessions(user_id, config, pool).await?;
+
+		auth.login_user(user_id);
+		auth.remember_user(remember);
+		auth.session.set(LOGGED_IN_AT, chrono::Utc::now().timestamp());
+		auth.session.remove(EXTENDED_AT);
+		auth.session.set(AUTHENTICATED_AT, chrono::Utc::now().timestamp());
+		if let Some(generation) = generation {
+			auth.session.set(SESSION_GENERATION, generation);
+		}
+
+		Ok(())
+	}
+
+	/// How long ago the password may have been entered for actions that need it confirmed
+	pub const STEP_UP_MAX_AGE: std::time::Duration = std::time::Duration::from_secs(10 * 60);
+
+	/// Refuse sensitive server functions unless the user entered their password within `max_age`
+	///
+	/// Requests authenticated with a JWT are always refused, a token doesn't prove anyone entered a password recently
+	pub fn require_recent_auth(max_age: std::time::Duration) -> Result<(), AppServerError> {
+		use leptos::use_context;
+
+		let auth = use_context::<AuthSession>().expect("No session found");
+		if use_context::<Claims>().is_some() || !recently_authenticated(&auth, max_age) {
+			return Err(AppServerError::new(ErrorCode::ReauthenticationRequired, super::REAUTHENTICATION_REQUIRED));
+		}
+
+		Ok(())
+	}
+
+	/// Whether the user of the session entered their password within `max_age`, for handlers outside server functions
+	pub fn recently_authenticated(auth: &AuthSession, max_age: std::time::Duration) -> bool {
+		let authenticated_at = auth.session.get::<i64>(AUTHENTICATED_AT).unwrap_or_default();
+
+		chrono::Utc::now().timestamp() - authenticated_at <= max_age.as_secs() as i64
+	}
+
+	/// In single session mode end every session of the user, returns the generation the next session belongs to
+	///
+	/// Sessions can live in Redis or Postgres so they aren't deleted, the user's generation is bumped instead and
+	/// sessions of an older one are refused. Refresh tokens are revoked so API clients have to log in again too.
+	/// Users allowed concurrent sessions are left alone.
+	pub async fn end_other_sessions(user_id: i32, config: &Config, pool: &PgPool) -> Result<Option<i32>, sqlx::Error> {
+		if !config.single_session {
+			return Ok(None);
+		}
+
+		let generation = sqlx::query_scalar::<_, i32>(
+			"UPDATE users SET session_generation = session_generation + 1
+			WHERE id = $1 AND NOT concurrent_sessions
+			RETURNING session_generation",
+		)
+		.bind(user_id)
+		.fetch_optional(pool)
+		.await?;
+		if generation.is_some() {
+			sqlx::query("UPDATE refresh_tokens SET revoked = true WHERE person = $1 AND NOT revoked")
+				.bind(user_id)
+				.execute(pool)
+				.await?;
+		}
+
+		Ok(generation)
+	}
+
+	async fn is_current_session(auth: &AuthSession, user_id: i32, pool: &PgPool) -> bool {
+		let Ok((generation, concurrent_sessions)) = retry_read("is_current_session", || {
+			sqlx::query_as::<_, (i32, bool)>("SELECT session_generation, concurrent_sessions FROM users WHERE id = $1")
+				.bind(user_id)
+				.fetch_one(pool)
+		})
+		.await
+		else {
+			// Logging everyone out wouldn't bring the database back, while it's down the session stands as it is
+			return breaker().is_tripped();
+		};
+
+		// Sessions from before single session mode was turned on have no generation and end as well
+		concurrent_sessions || auth.session.get::<i32>(SESSION_GENERATION) == Some(generation)
+	}
+
+	/// The user a request is authenticated as
+	///
+	/// A session is only valid for the tenant it was created in, for the tenant's session lifetime and in single
+	/// session mode until the user logs in somewhere else
+	pub async fn authenticated_user(
+		auth: &AuthSession,
+		tenant: &Tenant,
+		claims: Option<&Claims>,
+		config: &Config,
+		pool: &PgPool,
+	) -> Option<User> {
+		let mut user = auth.current_user.clone().filter(|user| user.tenant == tenant.id)?;
+		// While the database is down the user is served as the session has it, stale or not
+		if !breaker().is_tripped() && take_stale(user.id) {
+			auth.cache_clear_user(user.id);
+			user = User::get_from_id(user.id, pool).await?;
+		}
+		if !user.is_active() {
+			auth.logout_user();
+			return None;
+		}
+
+		// Requests authenticated with a JWT don't have a session, the token carries its own expiry
+		if claims.is_none() {
+			// Service accounts never log in, a session of one didn't come from a login
+			if user.service_account {
+				auth.logout_user();
+				return None;
+			}
+			let settings = TenantSettings::get_for_tenant(tenant.id, pool).await;
+			let expires_at = session_expires_at(
+				logged_in_at(auth),
+				auth.session.get::<i64>(EXTENDED_AT),
+				settings.session_lifetime_hours as i64 * 60 * 60,
+			);
+			if chrono::Utc::now().timestamp() > expires_at {
+				auth.logout_user();
+				return None;
+			}
+			if config.single_session && !is_current_session(auth, user.id, pool).await {
+				auth.logout_user();
+				return None;
+			}
+		}
+
+		// A token with scopes does no more than they allow, however much more the user may do
+		if let Some(scopes) = claims.and_then(|claims| claims.scopes.as_ref()) {
+			user = scopes.narrow(user);
+		}
+
+		Some(user)
+	}
 
 	impl User {
 		pub async fn get_from_id_with_passhash(id: i32, pool: &PgPool) -> Option<(Self, UserPasshash)> {
-			let sqluser =
-				sqlx::query_as::<_, UserSQL>("SELECT * FROM users WHERE id = $1").bind(id).fetch_one(pool).await.ok()?;
+			// Every request loads its user through here, a failed read would log the user out
+			let sqluser = retry_read("get_user", || {
+				sqlx::query_as::<_, UserSQL>("SELECT * FROM users WHERE id = $1").bind(id).fetch_one(pool)
+			})
+			.await
+			.ok()?;
 
 			Some(sqluser.into_user())
 		}
@@ -101,9 +464,14 @@ pub mod ssr {
 			User::get_from_id_with_passhash(id, pool).await.map(|(user, _)| user)
 		}
 
-		pub async fn get_from_username_with_passhash(name: String, pool: &PgPool) -> Option<(Self, UserPasshash)> {
-			let sqluser = sqlx::query_as::<_, UserSQL>("SELECT * FROM users WHERE username = $1")
+		pub async fn get_from_username_with_passhash(
+			name: String,
+			tenant: i32,
+			pool: &PgPool,
+		) -> Option<(Self, UserPasshash)> {
+			let sqluser = sqlx::query_as::<_, UserSQL>("SELECT * FROM users WHERE username = $1 AND tenant = $2")
 				.bind(name)
+				.bind(tenant)
 				.fetch_one(pool)
 				.await
 				.ok()?;
@@ -111,16 +479,27 @@ pub mod ssr {
 			Some(sqluser.into_user())
 		}
 
-		pub async fn get_from_username(name: String, pool: &PgPool) -> Option<Self> {
-			User::get_from_username_with_passhash(name, pool).await.map(|(user, _)| user)
+		pub async fn get_from_username(name: String, tenant: i32, pool: &PgPool) -> Option<Self> {
+			User::get_from_username_with_passhash(name, tenant, pool).await.map(|(user, _)| user)
+		}
+	}
+
+	impl PublicUser {
+		/// Only loads the columns that are shown, nothing near the password hash
+		pub async fn get_from_id(id: i32, pool: &PgPool) -> Option<Self> {
+			sqlx::query_as::<_, PublicUser>("SELECT id, username, avatar FROM users WHERE id = $1")
+				.bind(id)
+				.fetch_optional(pool)
+				.await
+				.ok()?
 		}
 	}
 
 	#[async_trait]
-	impl Authentication<User, i32, PgPool> for User {
-		async fn load_user(userid: i32, pool: Option<&PgPool>) -> Result<User, anyhow::Error> {
-			let pool = pool.unwrap();
-			User::get_from_id(userid, pool).await.ok_or_else(|| anyhow::anyhow!("Cannot get user"))
+	impl Authentication<User, i32, Arc<dyn AuthBackend>> for User {
+		async fn load_user(userid: i32, backend: Option<&Arc<dyn AuthBackend>>) -> Result<User, anyhow::Error> {
+			let backend = backend.unwrap();
+			Ok(backend.load_user(userid).await?)
 		}
 
 		fn is_authenticated(&self) -> bool {
@@ -128,7 +507,7 @@ pub mod ssr {
 		}
 
 		fn is_active(&self) -> bool {
-			true
+			self.active
 		}
 
 		fn is_anonymous(&self) -> bool {
@@ -137,39 +516,94 @@ pub mod ssr {
 	}
 }
 
-#[server]
-pub async fn get_user() -> Result<Option<User>, ServerFnError> {
-	use crate::auth::ssr::AuthSession;
+#[server(input = GetUrl)]
+pub async fn get_user() -> Result<Option<User>, ServerFnError<AppServerError>> {
+	use crate::{
+		auth::ssr::{authenticated_user, AuthSession, RequestUser},
+		cache::cache_privately,
+		config::Config,
+		jwt::ssr::Claims,
+		tenant::Tenant,
+	};
+	use sqlx::PgPool;
+
+	cache_privately::<GetUser>(0);
 
+	let pool = use_context::<PgPool>().expect("Database not initialized");
 	let auth = use_context::<AuthSession>().expect("No session found");
+	let tenant = use_context::<Tenant>().expect("No tenant found");
+	let config = use_context::<Config>().expect("No config found");
+
+	let claims = use_context::<Claims>();
+	let resolve = || authenticated_user(&auth, &tenant, claims.as_ref(), &config, &pool);
 
-	Ok(auth.current_user)
+	Ok(match use_context::<RequestUser>() {
+		Some(request_user) => request_user.0.get_or_init(resolve).await.clone(),
+		None => resolve().await,
+	})
 }
 
 #[server]
-pub async fn login(username: String, password: String, remember: Option<String>) -> Result<(), ServerFnError> {
+pub async fn login(
+	username: String,
+	password: String,
+	remember: Option<String>,
+	next: Option<String>,
+) -> Result<(), ServerFnError<AppServerError>> {
 	use self::ssr::*;
-	use server_fn::error::NoCustomError;
+	use crate::{
+		config::Config,
+		events::{record, DomainEvent},
+		fallback::next_path,
+		rate_limit::{check_login, login_succeeded, request_client, RateLimiter},
+		tenant::Tenant,
+	};
 
 	let pool = use_context::<PgPool>().expect("Database not initialized");
+	let backend = use_context::<Arc<dyn AuthBackend>>().expect("No auth backend found");
+	let limiter = use_context::<Arc<dyn RateLimiter>>().expect("No rate limiter found");
+	let config = use_context::<Config>().expect("No config found");
 	let auth = use_context::<AuthSession>().expect("No session found");
+	let tenant = use_context::<Tenant>().expect("No tenant found");
+	let client = request_client(&config);
 
-	let (user, UserPasshash(expected_passhash)) = User::get_from_username_with_passhash(username, &pool)
+	check_login(limiter.as_ref(), &config, tenant.id, &username, client)
 		.await
-		.ok_or_else(|| ServerFnError::new("Username or Password does not match."))?;
+		.map_err(|error| AppServerError::new(error.code(), error))?;
+
+	let user = match backend.login(&username, &password, tenant.id).await {
+		Ok(user) => user,
+		Err(error) => {
+			record(
+				&pool,
+				&DomainEvent::LoginFailed {
+					tenant: tenant.id,
+					username,
+				},
+			)
+			.await
+			.map_err(AppServerError::from)?;
+			return Err(AppServerError::new(error.code(), error).into());
+		},
+	};
+	login_succeeded(limiter.as_ref(), tenant.id, &username, client)
+		.await
+		.map_err(|error| AppServerError::new(error.code(), error))?;
+	record(
+		&pool,
+		&DomainEvent::UserLoggedIn {
+			tenant: tenant.id,
+			user: user.id,
+		},
+	)
+	.await
+	.map_err(AppServerError::from)?;
 
-	let parsed_hash = PasswordHash::new(&expected_passhash)
-		.map_err(|error| ServerFnError::<NoCustomError>::ServerError(format!("Hash parsing error: {}", error)))?;
+	start_session(&auth, user.id, remember.is_some(), &config, &pool).await.map_err(AppServerError::from)?;
+	// Pages that needed a session sent the browser here, it goes back to them
+	leptos_axum::redirect(next_path(next.as_deref()));
 
-	match Argon2::default().verify_password(password.as_bytes(), &parsed_hash) {
-		Ok(_) => {
-			auth.login_user(user.id);
-			auth.remember_user(remember.is_some());
-			leptos_axum::redirect("/");
-			Ok(())
-		},
-		Err(_) => Err(ServerFnError::ServerError("Username or Password does not match.".to_string())),
-	}
+	Ok(())
 }
 
 #[server]
@@ -178,55 +612,323 @@ pub async fn signup(
 	password: String,
 	password_confirmation: String,
 	remember: Option<String>,
-) -> Result<(), ServerFnError> {
+) -> Result<(), ServerFnError<AppServerError>> {
 	use self::ssr::*;
-	use server_fn::error::NoCustomError;
+	use crate::{
+		breach::PasswordBreachCheck,
+		config::Config,
+		events::{record, DomainEvent},
+		tenant::{Tenant, TenantSettings},
+		validation::USERNAME,
+	};
 
 	let pool = use_context::<PgPool>().expect("Database not initialized");
+	let backend = use_context::<Arc<dyn AuthBackend>>().expect("No auth backend found");
 	let auth = use_context::<AuthSession>().expect("No session found");
+	let tenant = use_context::<Tenant>().expect("No tenant found");
+	let config = use_context::<Config>().expect("No config found");
+	let settings = TenantSettings::get_for_tenant(tenant.id, &pool).await;
 
-	if password != password_confirmation {
-		return Err(ServerFnError::ServerError("Passwords did not match.".to_string()));
+	if !settings.open_signup {
+		return Err(AppServerError::forbidden("Signup is disabled for this organization.").into());
 	}
 
-	let salt = SaltString::generate(&mut OsRng);
+	let username = USERNAME.clean(&username).map_err(AppServerError::invalid)?;
+	if password != password_confirmation {
+		return Err(AppServerError::invalid("Passwords did not match.").into());
+	}
 
-	let password_hashed = Argon2::default()
-		.hash_password(password.as_bytes(), &salt)
-		.map_err(|error| ServerFnError::<NoCustomError>::ServerError(format!("Hashing error: {}", error)))?
-		.to_string();
+	settings.validate_password(&password).map_err(AppServerError::invalid)?;
+	use_context::<PasswordBreachCheck>()
+		.expect("No password breach check found")
+		.check(&password)
+		.await
+		.map_err(AppServerError::invalid)?;
 
-	sqlx::query(
-		"INSERT INTO users
-		(username, password, permission_equipment, permission_user, permission_todo)
-		VALUES
-		($1, $2, 'READ(*)|WRITE(*)', 'READ(*)|WRITE(*)', 'READ(*)|WRITE(*)')",
+	let user = backend
+		.signup(&username, &password, tenant.id, &settings)
+		.await
+		.map_err(|error| AppServerError::new(error.code(), error))?;
+	record(
+		&pool,
+		&DomainEvent::UserSignedUp {
+			tenant: tenant.id,
+			user: user.id,
+		},
 	)
-	.bind(username.clone())
-	.bind(password_hashed)
-	.execute(&pool)
-	.await?;
+	.await
+	.map_err(AppServerError::from)?;
 
-	let user = User::get_from_username(username, &pool)
-		.await
-		.ok_or_else(|| ServerFnError::new("Signup failed: User does not exist."))?;
+	start_session(&auth, user.id, remember.is_some(), &config, &pool).await.map_err(AppServerError::from)?;
+
+	leptos_axum::redirect(&Route::Home.path());
+
+	Ok(())
+}
+
+/// Confirm the password of the logged in user so [`ssr::require_recent_auth`] lets sensitive server functions through
+#[server]
+pub async fn reauthenticate(password: String) -> Result<(), ServerFnError<AppServerError>> {
+	use self::ssr::*;
+	use crate::{
+		config::Config,
+		rate_limit::{check_login, login_succeeded, request_client, RateLimiter},
+		tenant::Tenant,
+	};
 
-	auth.login_user(user.id);
-	auth.remember_user(remember.is_some());
+	let backend = use_context::<Arc<dyn AuthBackend>>().expect("No auth backend found");
+	let limiter = use_context::<Arc<dyn RateLimiter>>().expect("No rate limiter found");
+	let config = use_context::<Config>().expect("No config found");
+	let auth = use_context::<AuthSession>().expect("No session found");
+	let tenant = use_context::<Tenant>().expect("No tenant found");
+	let user = get_user().await?.ok_or_else(AppServerError::unauthenticated)?;
+
+	// Confirmations count like logins so a stolen session can't be used to guess the password
+	let client = request_client(&config);
+	check_login(limiter.as_ref(), &config, tenant.id, &user.username, client)
+		.await
+		.map_err(|error| AppServerError::new(error.code(), error))?;
+	if let Err(error) = backend.login(&user.username, &password, tenant.id).await {
+		return Err(AppServerError::new(error.code(), error).into());
+	}
+	login_succeeded(limiter.as_ref(), tenant.id, &user.username, client)
+		.await
+		.map_err(|error| AppServerError::new(error.code(), error))?;
 
-	leptos_axum::redirect("/");
+	auth.session.set(AUTHENTICATED_AT, chrono::Utc::now().timestamp());
 
 	Ok(())
 }
 
 #[server]
-pub async fn logout() -> Result<(), ServerFnError> {
+pub async fn logout() -> Result<(), ServerFnError<AppServerError>> {
 	use self::ssr::*;
+	use crate::{
+		cache::response_cache,
+		events::{record, DomainEvent},
+		jwt::ssr::{revoke_family, Claims},
+	};
 
+	let pool = use_context::<PgPool>().expect("Database not initialized");
 	let auth = use_context::<AuthSession>().expect("No session found");
 
+	// API clients log out by revoking the refresh tokens their JWT was issued with
+	if let Some(claims) = use_context::<Claims>() {
+		revoke_family(&claims.sid, &pool).await.map_err(AppServerError::from)?;
+	}
+
+	if let Some(user) = &auth.current_user {
+		record(
+			&pool,
+			&DomainEvent::UserLoggedOut {
+				tenant: user.tenant,
+				user: user.id,
+			},
+		)
+		.await
+		.map_err(AppServerError::from)?;
+		auth.cache_clear_user(user.id);
+		response_cache().invalidate_user(user.id);
+	}
+
+	// Destroying the session also drops the long lived cookie of remembered devices
+	auth.remember_user(false);
 	auth.logout_user();
-	leptos_axum::redirect("/");
+	auth.session.destroy();
+	leptos_axum::redirect(&Route::Home.path());
 
 	Ok(())
 }
+
+/// Seconds until the session of the logged in user runs out of its lifetime, `None` without a session, `extend`
+/// starts the lifetime over up to [`SESSION_MAX_AGE`](ssr::SESSION_MAX_AGE) after the login
+///
+/// Requests authenticated with a JWT have no session to run out, their token expires on its own.
+#[server]
+pub async fn ping_session(#[server(default)] extend: bool) -> Result<Option<i64>, ServerFnError<AppServerError>> {
+	use self::ssr::*;
+	use crate::{
+		jwt::ssr::Claims,
+		tenant::{Tenant, TenantSettings},
+	};
+
+	let pool = use_context::<PgPool>().expect("Database not initialized");
+	let auth = use_context::<AuthSession>().expect("No session found");
+	let tenant = use_context::<Tenant>().expect("No tenant found");
+	if use_context::<Claims>().is_some() || get_user().await?.is_none() {
+		return Ok(None);
+	}
+
+	let now = chrono::Utc::now().timestamp();
+	// Only the button extends it, pages left open don't keep a session alive on their own
+	// The login time stays so extending can't keep a session alive forever
+	if extend {
+		auth.session.set(EXTENDED_AT, now);
+	}
+	let settings = TenantSettings::get_for_tenant(tenant.id, &pool).await;
+	let expires_at = session_expires_at(
+		logged_in_at(&auth),
+		auth.session.get::<i64>(EXTENDED_AT),
+		settings.session_lifetime_hours as i64 * 60 * 60,
+	);
+
+	Ok(Some(expires_at - now))
+}
+
+/// The logged in user and the actions that change who it is, shared with every component through the context
+#[derive(Clone, Copy)]
+pub struct UserContext {
+	pub login: Action<Login, Result<(), AppError>>,
+	pub signup: Action<Signup, Result<(), AppError>>,
+	pub logout: Action<Logout, Result<(), AppError>>,
+	/// Refetched whenever one of the actions completes
+	pub user: Resource<(usize, usize, usize), Result<Option<User>, AppError>>,
+	/// Notified once a logout went through, components holding data of the user wipe it
+	pub logged_out: Trigger,
+}
+
+/// Load the logged in user once for every component below, nested apps reuse the context of their parent
+pub fn provide_user_context() -> UserContext {
+	if let Some(context) = use_context::<UserContext>() {
+		return context;
+	}
+
+	let login = create_server_action::<Login>();
+	let signup = create_server_action::<Signup>();
+	let logout = create_server_action::<Logout>();
+	let user = create_resource(
+		move || (login.version().get(), signup.version().get(), logout.version().get()),
+		move |_| get_user(),
+	);
+	let logged_out = create_trigger();
+	create_effect(move |_| {
+		if let Some(Ok(())) = logout.value().get() {
+			logged_out.notify();
+		}
+	});
+
+	let context = UserContext {
+		login,
+		signup,
+		logout,
+		user,
+		logged_out,
+	};
+	provide_context(context);
+	context
+}
+
+pub fn use_user_context() -> UserContext {
+	use_context::<UserContext>().expect("No user context found, call provide_user_context first")
+}
+
+/// The logged in user, `None` while loading and when logged out
+pub fn use_current_user() -> Signal<Option<User>> {
+	let user = use_user_context().user;
+
+	Signal::derive(move || user.get().and_then(Result::ok).flatten())
+}
+
+/// Asks for the password once a server function refused with [`ErrorCode::ReauthenticationRequired`], the refused
+/// action has to be submitted again after
+#[component]
+pub fn ConfirmPassword(#[prop(into)] error: Signal<Option<AppError>>) -> impl IntoView {
+	let reauthenticate = create_server_action::<Reauthenticate>();
+	let refused = move || {
+		error.get().is_some_and(|error| AppServerError::code_of(&error) == Some(ErrorCode::ReauthenticationRequired))
+	};
+
+	// Every new refusal asks again
+	create_effect(move |_| {
+		error.track();
+		reauthenticate.value().set(None);
+	});
+
+	view! {
+		<Show when=refused>
+			{move || match reauthenticate.value().get() {
+				Some(Ok(())) => view! { <span>"Password confirmed, please try again."</span> }.into_view(),
+				result => {
+					view! {
+						<ActionForm action=reauthenticate>
+							<label>
+								"Confirm your password:"
+								<input type="password" name="password" required=true class="auth-input" />
+							</label>
+							<button type="submit" class="button">
+								"Confirm"
+							</button>
+						</ActionForm>
+						{result.and_then(Result::err).map(|e| view! { <span class="error">{e.to_string()}</span> })}
+					}
+						.into_view()
+				}
+			}}
+
+		</Show>
+	}
+}
+
+/// Warns a minute before the session runs out so nothing typed in the meantime is lost, staying logged in starts the
+/// session lifetime over
+#[component]
+pub fn SessionExpiryWarning() -> impl IntoView {
+	let stay = create_server_action::<PingSession>();
+	let UserContext { user, .. } = use_user_context();
+	// The server counts the seconds left so a wrong clock on the device doesn't matter
+	let ends_at = create_resource(
+		move || (user.get().and_then(Result::ok).flatten().map(|user| user.id), stay.version().get()),
+		|(user, _)| async move {
+			match user {
+				Some(_) => {
+					ping_session(false).await.ok().flatten().map(|left| chrono::Utc::now() + chrono::Duration::seconds(left))
+				},
+				None => None,
+			}
+		},
+	);
+	let now = create_rw_signal(chrono::Utc::now());
+	set_interval(move || now.set(chrono::Utc::now()), SESSION_CHECK_INTERVAL);
+	let left = move || ends_at.get().flatten().map(|ends_at| (ends_at - now.get()).num_seconds());
+
+	view! {
+		<Transition fallback=move || ()>
+			{move || match left() {
+				Some(left) if left <= 0 => {
+					view! {
+						<p class="session-warning">
+							"Your session ended, log in again before saving anything. "
+							<a href=Route::Login.path()>"Log in"</a>
+						</p>
+					}
+						.into_view()
+				}
+				Some(left) if left <= SESSION_WARNING.as_secs() as i64 => {
+					view! {
+						<p class="session-warning">
+							{format!("Your session ends in {left} seconds. ")}
+							<button on:click=move |_| stay.dispatch(PingSession { extend: true })>"Stay logged in"</button>
+						</p>
+					}
+						.into_view()
+				}
+				_ => ().into_view(),
+			}}
+		</Transition>
+	}
+}
+
+#[cfg(all(test, feature = "ssr"))]
+mod tests {
+	use super::ssr::{session_expires_at, SESSION_MAX_AGE};
+
+	#[test]
+	fn extending_sessions_is_capped() {
+		let day = 24 * 60 * 60;
+		assert_eq!(session_expires_at(0, None, day), day);
+		assert_eq!(session_expires_at(0, Some(10 * day), day), 11 * day);
+		assert_eq!(session_expires_at(0, Some(SESSION_MAX_AGE), day), SESSION_MAX_AGE);
+		// A lifetime longer than the cap still gets its full length once
+		assert_eq!(session_expires_at(0, Some(day), 100 * day), 100 * day);
+	}
+}
@@ -0,0 +1,251 @@
+use crate::{
+	auth::{
+		ssr::{Argon2, OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+		User, UserPasshash, UserSQL,
+	},
+	errors::ErrorCode,
+	identity::{
+		ssr::{has_identity, link_identity},
+		Provider,
+	},
+	tenant::TenantSettings,
+	username::ssr::is_reserved,
+};
+use argon2::{Algorithm, Params, Version};
+use async_trait::async_trait;
+use sqlx::PgPool;
+use std::fmt::Debug;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum AuthError {
+	#[error("Username or Password does not match.")]
+	InvalidCredentials,
+	#[error("Username is already taken.")]
+	UsernameTaken,
+	#[error("User not found")]
+	NotFound,
+	#[error("Authentication error: {0}")]
+	Backend(String),
+}
+
+impl AuthError {
+	pub fn code(&self) -> ErrorCode {
+		match self {
+			AuthError::InvalidCredentials => ErrorCode::InvalidCredentials,
+			AuthError::UsernameTaken => ErrorCode::Conflict,
+			AuthError::NotFound => ErrorCode::NotFound,
+			AuthError::Backend(_) => ErrorCode::Internal,
+		}
+	}
+}
+
+/// Where users are stored and how their credentials are checked
+///
+/// The backend in use lives in `AppState` and is handed to the session layer, swap it to use a different table layout
+/// or an external identity service
+#[async_trait]
+pub trait AuthBackend: Debug + Send + Sync {
+	/// Check the credentials of a user of the given tenant
+	async fn login(&self, username: &str, password: &str, tenant: i32) -> Result<User, AuthError>;
+	/// Create a user in the given tenant with the tenant's default permissions, the password has already been checked
+	/// against the tenant's policy
+	///
+	/// Callers record the `UserSignedUp` event, which is what gives the user their materialized visible todos.
+	async fn signup(
+		&self,
+		username: &str,
+		password: &str,
+		tenant: i32,
+		settings: &TenantSettings,
+	) -> Result<User, AuthError>;
+	/// Give a user a new password, or a first one to users who only logged in without one so far
+	async fn set_password(&self, user: &User, password: &str) -> Result<(), AuthError>;
+	/// Load the user a session belongs to
+	async fn load_user(&self, id: i32) -> Result<User, AuthError>;
+}
+
+/// The default backend, users live in the `users` table and passwords are hashed with argon2
+#[derive(Clone)]
+pub struct PgAuthBackend {
+	pool: PgPool,
+	/// Mixed into every hash so a leaked database alone isn't enough to crack passwords
+	pepper: Option<String>,
+}
+
+impl PgAuthBackend {
+	pub fn new(pool: PgPool, pepper: Option<String>) -> Self {
+		Self { pool, pepper }
+	}
+
+	fn argon2(&self) -> Result<Argon2<'_>, AuthError> {
+		match &self.pepper {
+			Some(pepper) => {
+				Argon2::new_with_secret(pepper.as_bytes(), Algorithm::default(), Version::default(), Params::default())
+					.map_err(|error| AuthError::Backend(format!("Invalid password pepper: {}", error)))
+			},
+			None => Ok(Argon2::default()),
+		}
+	}
+
+	fn hash_password(&self, password: &str) -> Result<String, AuthError> {
+		let salt = SaltString::generate(&mut OsRng);
+
+		Ok(
+			self
+				.argon2()?
+				.hash_password(password.as_bytes(), &salt)
+				.map_err(|error| AuthError::Backend(format!("Hashing error: {}", error)))?
+				.to_string(),
+		)
+	}
+
+	/// Replace the unpeppered hash of a password that just verified, failing leaves it for the next login to try again
+	async fn rehash(&self, user: &User, password: &str, old_hash: &str) {
+		let hashed = match self.hash_password(password) {
+			Ok(hashed) => hashed,
+			Err(error) => {
+				log::error!("Hashing the password of user {} with the pepper failed: {error}", user.id);
+				return;
+			},
+		};
+		// A password changed in the meantime is left alone
+		if let Err(error) = sqlx::query("UPDATE users SET password = $1 WHERE id = $2 AND password = $3")
+			.bind(hashed)
+			.bind(user.id)
+			.bind(old_hash)
+			.execute(&self.pool)
+			.await
+		{
+			log::error!("Storing the peppered password hash of user {} failed: {error}", user.id);
+		}
+	}
+}
+
+// Keep the pepper out of any logs
+impl Debug for PgAuthBackend {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.debug_struct("PgAuthBackend").field("pool", &self.pool).finish_non_exhaustive()
+	}
+}
+
+#[async_trait]
+impl AuthBackend for PgAuthBackend {
+	async fn login(&self, username: &str, password: &str, tenant: i32) -> Result<User, AuthError> {
+		let (user, UserPasshash(expected_passhash)) =
+			User::get_from_username_with_passhash(username.to_string(), tenant, &self.pool)
+				.await
+				.ok_or(AuthError::InvalidCredentials)?;
+		// Service accounts authenticate with API tokens only
+		if user.service_account {
+			return Err(AuthError::InvalidCredentials);
+		}
+
+		let parsed_hash = PasswordHash::new(&expected_passhash)
+			.map_err(|error| AuthError::Backend(format!("Hash parsing error: {}", error)))?;
+
+		let peppered = self.argon2()?.verify_password(password.as_bytes(), &parsed_hash).is_ok();
+		// Hashes from before the pepper was set don't have it, they are hashed again with it after the login
+		if !peppered
+			&& (self.pepper.is_none() || Argon2::default().verify_password(password.as_bytes(), &parsed_hash).is_err())
+		{
+			return Err(AuthError::InvalidCredentials);
+		}
+
+		// Deactivated users get the same answer as a wrong password, the login form doesn't tell them apart
+		if !user.active {
+			return Err(AuthError::InvalidCredentials);
+		}
+
+		// Users who unlinked their password keep the hash but can't use it anymore
+		if !has_identity(user.id, Provider::Password, &self.pool)
+			.await
+			.map_err(|error| AuthError::Backend(error.to_string()))?
+		{
+			return Err(AuthError::InvalidCredentials);
+		}
+		if !peppered {
+			self.rehash(&user, password, &expected_passhash).await;
+		}
+
+		Ok(user)
+	}
+
+	async fn signup(
+		&self,
+		username: &str,
+		password: &str,
+		tenant: i32,
+		settings: &TenantSettings,
+	) -> Result<User, AuthError> {
+		let password_hashed = self.hash_password(password)?;
+		let backend_error = |error: sqlx::Error| AuthError::Backend(error.to_string());
+		if is_reserved(&self.pool, tenant, username, None).await.map_err(backend_error)? {
+			return Err(AuthError::UsernameTaken);
+		}
+
+		// The permission strings can refer to the new user, so its id is taken before the row is inserted
+		let id = sqlx::query_scalar::<_, i64>("SELECT nextval(pg_get_serial_sequence('users', 'id'))")
+			.fetch_one(&self.pool)
+			.await
+			.map_err(backend_error)?;
+		let id = i32::try_from(id).map_err(|error| AuthError::Backend(error.to_string()))?;
+		// Stored strings that don't parse would make every permission check of the new user panic
+		let [(permission_equipment, _), (permission_user, _), (permission_todo, _)] = settings
+			.default_permissions(id)
+			.map_err(|error| AuthError::Backend(format!("Invalid default permissions: {error}")))?;
+
+		let user = sqlx::query_as::<_, UserSQL>(
+			"WITH new_user AS (
+				INSERT INTO users
+				(id, tenant, username, password, permission_equipment, permission_user, permission_todo)
+				OVERRIDING SYSTEM VALUE
+				VALUES
+				($7, $1, $2, $3, $4, $5, $6)
+				RETURNING *
+			), password_identity AS (
+				INSERT INTO identities (tenant, person, provider, subject)
+				SELECT tenant, id, 'password', username FROM new_user
+			)
+			SELECT * FROM new_user",
+		)
+		.bind(tenant)
+		.bind(username)
+		.bind(password_hashed)
+		.bind(permission_equipment)
+		.bind(permission_user)
+		.bind(permission_todo)
+		.bind(id)
+		.fetch_one(&self.pool)
+		.await
+		.map_err(|error| match error {
+			sqlx::Error::Database(error) if error.is_unique_violation() => AuthError::UsernameTaken,
+			error => AuthError::Backend(error.to_string()),
+		})?;
+
+		Ok(user.into())
+	}
+
+	async fn set_password(&self, user: &User, password: &str) -> Result<(), AuthError> {
+		let password_hashed = self.hash_password(password)?;
+		let backend_error = |error: sqlx::Error| AuthError::Backend(error.to_string());
+
+		let mut transaction = self.pool.begin().await.map_err(backend_error)?;
+		sqlx::query("UPDATE users SET password = $1 WHERE id = $2")
+			.bind(password_hashed)
+			.bind(user.id)
+			.execute(&mut *transaction)
+			.await
+			.map_err(backend_error)?;
+		link_identity(&mut *transaction, user.tenant, user.id, Provider::Password, &user.username)
+			.await
+			.map_err(backend_error)?;
+		transaction.commit().await.map_err(backend_error)?;
+
+		Ok(())
+	}
+
+	async fn load_user(&self, id: i32) -> Result<User, AuthError> {
+		User::get_from_id(id, &self.pool).await.filter(|user| user.active).ok_or(AuthError::NotFound)
+	}
+}
@@ -0,0 +1,125 @@
+//! Whether users may read, write or create todos, decided by their permission strings or by the policies of the
+//! deployment
+//!
+//! `AUTHORIZATION` picks how: `scopes`, the default, uses the todo permission of every user, `policy` evaluates the
+//! rules in `POLICY_FILE` (`policies.txt` by default) instead, see [`crate::policy`]. Either way callers get the same
+//! answers, a clause to filter todos with and checks for single todos, so nothing else has to know which is in use.
+//! Access other users delegated is added on top of either, see [`crate::delegation`].
+//!
+//! Requests with an API token that has scopes are held to the todo permission of the account narrowed down by the
+//! token, with policies on top of what they allow, see [`crate::api_token`].
+
+pub use crate::policy::Action;
+use crate::{
+	auth::User,
+	delegation::ssr::widen,
+	permission::{IdColumn, Permission, Permissions, ScopeFilter},
+	policy::Policies,
+};
+use std::sync::OnceLock;
+
+enum Engine {
+	Scopes,
+	Policy(Policies),
+}
+
+static ENGINE: OnceLock<Engine> = OnceLock::new();
+
+fn engine() -> &'static Engine {
+	ENGINE.get_or_init(|| match std::env::var("AUTHORIZATION").as_deref() {
+		Err(_) | Ok("scopes") => Engine::Scopes,
+		Ok("policy") => {
+			let path = std::env::var("POLICY_FILE").unwrap_or_else(|_| String::from("policies.txt"));
+			let source = std::fs::read_to_string(&path).unwrap_or_else(|error| panic!("Can't read {path}: {error}"));
+			Engine::Policy(Policies::parse(&source).unwrap_or_else(|error| panic!("Invalid policy in {path}: {error}")))
+		},
+		Ok(other) => panic!("Invalid value for AUTHORIZATION: {other}, expected scopes or policy"),
+	})
+}
+
+/// The policies in use, none when the permission strings decide, call it on start to fail early on invalid ones
+pub fn policies() -> Option<&'static Policies> {
+	match engine() {
+		Engine::Scopes => None,
+		Engine::Policy(policies) => Some(policies),
+	}
+}
+
+/// The todo permission of the user for the action, which is what the scope watch reports on either way
+pub fn scope(user: &User, action: Action) -> &Permission {
+	let Permissions::ReadWrite { read, write, create } = user.permission_todo();
+	match action {
+		Action::Read => read,
+		Action::Write => write,
+		Action::Create => create,
+	}
+}
+
+/// The clause that narrows todos down to the ones the user may act on, in queries on the `todos` table
+pub fn todo_filter(user: &User, action: Action, first_param: usize) -> ScopeFilter {
+	widen(own_filter(user, action, first_param), user, action)
+}
+
+/// The clause of [`todo_filter`] without the access others delegated to the user
+pub fn own_filter(user: &User, action: Action, first_param: usize) -> ScopeFilter {
+	match engine() {
+		Engine::Scopes => scope(user, action).query_filter(IdColumn::Id, first_param),
+		Engine::Policy(policies) if user.token_scoped => {
+			let policy = policies.query_filter(user, action, first_param);
+			let token = scope(user, action).query_filter(IdColumn::Id, first_param + policy.arrays.len());
+			ScopeFilter {
+				sql: format!("{}{}", policy.sql, token.sql),
+				arrays: [policy.arrays, token.arrays].concat(),
+			}
+		},
+		Engine::Policy(policies) => policies.query_filter(user, action, first_param),
+	}
+}
+
+/// Whether the token the request came with allows what the policies do, requests without scopes aren't narrowed down
+fn token_allows(user: &User, action: Action, check: impl FnOnce(&Permission) -> bool) -> bool {
+	!user.token_scoped || check(scope(user, action))
+}
+
+/// Whether the user may act on a todo with `id` that belongs to `person` and is in `project` by their own access, for
+/// todos that weren't loaded through [`own_filter`]
+///
+/// Policies don't know about projects, they decide by the id and person alone.
+pub fn allows(user: &User, action: Action, id: i32, person: i32, project: Option<i32>) -> bool {
+	match engine() {
+		Engine::Scopes => scope(user, action).covers(id, person, project),
+		Engine::Policy(policies) => {
+			policies.covers(user, action, id, person) && token_allows(user, action, |scope| scope.covers(id, person, project))
+		},
+	}
+}
+
+/// Whether the user may act on any todos of a project, which policies decide per todo so they reach into every project
+pub fn reaches_project(user: &User, action: Action, project: i32) -> bool {
+	match engine() {
+		Engine::Scopes => scope(user, action).covers_project(project),
+		Engine::Policy(_) => token_allows(user, action, |scope| scope.covers_project(project)),
+	}
+}
+
+/// Whether the user may act on every todo with an id out of `equipment` and a person out of `persons`, empty lists
+/// standing for any id, and so may delegate that
+pub fn includes(user: &User, action: Action, equipment: &[i32], persons: &[i32]) -> bool {
+	match engine() {
+		Engine::Scopes => scope(user, action).includes(equipment, persons),
+		Engine::Policy(policies) => {
+			policies.includes(user, action, equipment, persons)
+				&& token_allows(user, action, |scope| scope.includes(equipment, persons))
+		},
+	}
+}
+
+pub fn can_create_todo(user: &User) -> bool {
+	match engine() {
+		Engine::Scopes => *scope(user, Action::Create) == Permission::Create(true),
+		Engine::Policy(policies) => {
+			policies.permits(user, Action::Create)
+				&& token_allows(user, Action::Create, |scope| *scope == Permission::Create(true))
+		},
+	}
+}
@@ -0,0 +1,193 @@
+use leptos::*;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AvatarSize {
+	Small,
+	Medium,
+	Large,
+}
+
+impl AvatarSize {
+	pub const ALL: [AvatarSize; 3] = [AvatarSize::Small, AvatarSize::Medium, AvatarSize::Large];
+
+	pub fn pixels(&self) -> u32 {
+		match self {
+			AvatarSize::Small => 32,
+			AvatarSize::Medium => 64,
+			AvatarSize::Large => 256,
+		}
+	}
+
+	pub fn from_pixels(pixels: u32) -> Option<Self> {
+		AvatarSize::ALL.into_iter().find(|size| size.pixels() == pixels)
+	}
+}
+
+pub fn avatar_url(key: &str, size: AvatarSize) -> String {
+	format!("/avatars/{key}/{}", size.pixels())
+}
+
+#[cfg(feature = "ssr")]
+pub mod ssr {
+	use super::AvatarSize;
+	use crate::{
+		auth::ssr::AuthSession, fallback::unauthenticated, routes::Route, state::AppState, storage::generate_key,
+		tenant::Tenant,
+	};
+	use axum::{
+		body::Bytes,
+		extract::{Multipart, Path, State},
+		http::{header, StatusCode},
+		response::{IntoResponse, Redirect, Response},
+	};
+	use image::{imageops::FilterType, ImageError, ImageFormat, ImageReader, Limits};
+	use std::io::Cursor;
+
+	/// Pixels an upload may be wide and high, a small file can declare a canvas that takes gigabytes once decoded
+	const MAX_DIMENSION: u32 = 4096;
+
+	fn storage_key(key: &str, size: AvatarSize) -> String {
+		format!("{key}_{}", size.pixels())
+	}
+
+	pub(super) fn resize(data: Bytes) -> Result<Vec<(AvatarSize, Vec<u8>)>, ImageError> {
+		let mut limits = Limits::default();
+		limits.max_image_width = Some(MAX_DIMENSION);
+		limits.max_image_height = Some(MAX_DIMENSION);
+		let mut reader = ImageReader::new(Cursor::new(&data)).with_guessed_format()?;
+		reader.limits(limits);
+		let img = reader.decode()?;
+
+		AvatarSize::ALL
+			.into_iter()
+			.map(|size| {
+				let mut buffer = Cursor::new(Vec::new());
+				img
+					.resize_to_fill(size.pixels(), size.pixels(), FilterType::Lanczos3)
+					.write_to(&mut buffer, ImageFormat::Png)?;
+				Ok((size, buffer.into_inner()))
+			})
+			.collect()
+	}
+
+	pub async fn upload_avatar(
+		State(app_state): State<AppState>,
+		auth_session: AuthSession,
+		tenant: Tenant,
+		mut multipart: Multipart,
+	) -> Result<Redirect, (StatusCode, String)> {
+		let user = auth_session.current_user.filter(|user| user.tenant == tenant.id).ok_or_else(unauthenticated)?;
+
+		let mut data = None;
+		while let Some(field) =
+			multipart.next_field().await.map_err(|error| (StatusCode::BAD_REQUEST, error.to_string()))?
+		{
+			if field.name() == Some("avatar") {
+				data = Some(field.bytes().await.map_err(|error| (StatusCode::BAD_REQUEST, error.to_string()))?);
+			}
+		}
+		let data =
+			data.filter(|data| !data.is_empty()).ok_or((StatusCode::BAD_REQUEST, String::from("No image uploaded")))?;
+
+		// Decoding and resizing is CPU bound so we keep it off the async workers
+		let images = tokio::task::spawn_blocking(move || resize(data))
+			.await
+			.map_err(|error| (StatusCode::INTERNAL_SERVER_ERROR, error.to_string()))?
+			.map_err(|error| match error {
+				ImageError::Limits(_) => {
+					(StatusCode::PAYLOAD_TOO_LARGE, format!("The image can be {MAX_DIMENSION} pixels wide and high at most"))
+				},
+				error => (StatusCode::BAD_REQUEST, format!("Invalid image: {error}")),
+			})?;
+
+		let key = generate_key();
+		for (size, image) in images {
+			app_state
+				.storage
+				.put(&storage_key(&key, size), image.into())
+				.await
+				.map_err(|error| (StatusCode::INTERNAL_SERVER_ERROR, error.to_string()))?;
+		}
+
+		sqlx::query("UPDATE users SET avatar = $1 WHERE id = $2")
+			.bind(&key)
+			.bind(user.id)
+			.execute(&app_state.pool)
+			.await
+			.map_err(|error| (StatusCode::INTERNAL_SERVER_ERROR, error.to_string()))?;
+
+		// Every upload gets a new key so the old files can go
+		if let Some(old_key) = user.avatar {
+			for size in AvatarSize::ALL {
+				let _ = app_state.storage.delete(&storage_key(&old_key, size)).await;
+			}
+		}
+
+		Ok(Redirect::to(&Route::Settings.path()))
+	}
+
+	pub async fn serve_avatar(
+		State(app_state): State<AppState>,
+		Path((key, pixels)): Path<(String, u32)>,
+	) -> Result<Response, StatusCode> {
+		let size = AvatarSize::from_pixels(pixels).ok_or(StatusCode::NOT_FOUND)?;
+		let data = app_state.storage.get(&storage_key(&key, size)).await.map_err(|_| StatusCode::NOT_FOUND)?;
+
+		// A new upload always gets a new key so whatever lives under a key never changes
+		Ok(
+			(
+				[
+					(header::CONTENT_TYPE, "image/png"),
+					(header::CACHE_CONTROL, "public, max-age=31536000, immutable"),
+				],
+				data,
+			)
+				.into_response(),
+		)
+	}
+}
+
+#[component]
+pub fn Avatar(#[prop(into)] avatar: Option<String>, size: AvatarSize) -> impl IntoView {
+	avatar.map(|key| {
+		view! {
+			<img
+				class="avatar"
+				src=avatar_url(&key, size)
+				width=size.pixels()
+				height=size.pixels()
+				alt=""
+			/>
+		}
+	})
+}
+
+#[component]
+pub fn AvatarUpload() -> impl IntoView {
+	view! {
+		<form method="post" enctype="multipart/form-data" action="/avatars/upload">
+			<label>"Avatar: " <input type="file" name="avatar" accept="image/*" /></label>
+			<input type="submit" value="Upload" />
+		</form>
+	}
+}
+
+#[cfg(all(test, feature = "ssr"))]
+mod tests {
+	use super::ssr::resize;
+	use image::{ImageError, ImageFormat, RgbImage};
+	use std::io::Cursor;
+
+	fn png(width: u32, height: u32) -> axum::body::Bytes {
+		let mut buffer = Cursor::new(Vec::new());
+		RgbImage::new(width, height).write_to(&mut buffer, ImageFormat::Png).unwrap();
+		buffer.into_inner().into()
+	}
+
+	#[test]
+	fn huge_images_are_refused() {
+		assert_eq!(resize(png(40, 30)).unwrap().len(), 3);
+		assert!(matches!(resize(png(4097, 1)), Err(ImageError::Limits(_))));
+		assert!(matches!(resize(png(1, 4097)), Err(ImageError::Limits(_))));
+	}
+}
@@ -0,0 +1,88 @@
+use crate::config::Config;
+use sha1::{Digest, Sha1};
+use std::time::Duration;
+
+/// Checks passwords against the HaveIBeenPwned range API
+///
+/// Only the first five characters of the SHA-1 hash leave the server, and an unreachable API lets the password
+/// through so signups keep working offline
+#[derive(Debug, Clone)]
+pub struct PasswordBreachCheck {
+	client: reqwest::Client,
+	api_url: String,
+	enabled: bool,
+}
+
+impl PasswordBreachCheck {
+	pub fn from_config(config: &Config) -> Self {
+		Self {
+			client: reqwest::Client::builder().timeout(Duration::from_secs(3)).build().expect("Unable to build HTTP client"),
+			api_url: config.password_breach_api.clone(),
+			enabled: config.password_breach_check,
+		}
+	}
+
+	pub async fn is_breached(&self, password: &str) -> Result<bool, reqwest::Error> {
+		let (prefix, suffix) = split_hash(password);
+		let body = self
+			.client
+			.get(format!("{}/range/{prefix}", self.api_url))
+			.header("Add-Padding", "true")
+			.send()
+			.await?
+			.error_for_status()?
+			.text()
+			.await?;
+
+		Ok(is_listed(&body, &suffix))
+	}
+
+	/// Refuse breached passwords with a message for the user, does nothing when the check is off
+	pub async fn check(&self, password: &str) -> Result<(), String> {
+		if !self.enabled {
+			return Ok(());
+		}
+
+		match self.is_breached(password).await {
+			Ok(true) => {
+				Err(String::from("This password has appeared in a data breach and can't be used, please choose another one."))
+			},
+			Ok(false) => Ok(()),
+			Err(error) => {
+				log::warn!("Password breach check unavailable, letting the password through: {error}");
+				Ok(())
+			},
+		}
+	}
+}
+
+fn split_hash(password: &str) -> (String, String) {
+	let hash = Sha1::digest(password.as_bytes()).iter().map(|byte| format!("{byte:02X}")).collect::<String>();
+	let (prefix, suffix) = hash.split_at(5);
+
+	(prefix.to_string(), suffix.to_string())
+}
+
+// Padding entries have a count of 0 and never match
+fn is_listed(body: &str, suffix: &str) -> bool {
+	body.lines().filter_map(|line| line.trim().split_once(':')).any(|(candidate, count)| {
+		candidate.eq_ignore_ascii_case(suffix) && count.trim().parse::<u64>().is_ok_and(|count| count > 0)
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn range_test() {
+		let (prefix, suffix) = split_hash("password");
+		assert_eq!(prefix, "5BAA6");
+		assert_eq!(suffix, "1E4C9B93F3F0682250B6CF8331B7EE68FD8");
+
+		let body = "0018A45C4D1DEF81644B54AB7F969B88D65:1\r\n1E4C9B93F3F0682250B6CF8331B7EE68FD8:9659365\r\n";
+		assert!(is_listed(body, &suffix));
+		assert!(!is_listed(body, "1E4C9B93F3F0682250B6CF8331B7EE68FD9"));
+		assert!(!is_listed("1E4C9B93F3F0682250B6CF8331B7EE68FD8:0", &suffix));
+	}
+}
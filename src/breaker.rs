@@ -0,0 +1,194 @@
+//! Stops sending queries to a database that keeps failing, so requests fail right away during an outage instead of
+//! each waiting for the pool to time out
+//!
+//! Transient failures of [`crate::retry::retry_read`] count towards tripping the breaker and any successful read
+//! resets it. While it's tripped reads fail without touching the database, the logged in user is taken from the
+//! session as it is, every request that writes is refused with [`crate::errors::ErrorCode::Unavailable`] and
+//! [`DegradedBanner`] tells users their changes can't be saved. Once the cooldown is over the next reads go through to
+//! find out whether the database is back.
+
+use crate::errors::AppServerError;
+use leptos::*;
+use std::time::Duration;
+
+/// How often the banner asks whether the database is back
+const DEGRADED_POLL_INTERVAL: Duration = Duration::from_secs(15);
+
+#[cfg(feature = "ssr")]
+pub mod ssr {
+	use crate::{
+		config::Config,
+		errors::{AppServerError, ErrorCode},
+	};
+	use axum::{
+		body::Body,
+		http::{Request, StatusCode},
+		middleware::Next,
+		response::{IntoResponse, Json, Response},
+	};
+	use leptos::server_fn::error::{ServerFnError, ServerFnErrorSerde};
+	use std::{
+		sync::{
+			atomic::{AtomicI64, AtomicU32, Ordering},
+			OnceLock,
+		},
+		time::Duration,
+	};
+
+	pub struct Breaker {
+		threshold: u32,
+		cooldown: Duration,
+		failures: AtomicU32,
+		/// Unix timestamp in milliseconds until which queries are held back
+		open_until: AtomicI64,
+	}
+
+	impl Breaker {
+		pub fn new(threshold: u32, cooldown: Duration) -> Self {
+			Self {
+				threshold,
+				cooldown,
+				failures: AtomicU32::new(0),
+				open_until: AtomicI64::new(0),
+			}
+		}
+
+		/// Whether the database failed often enough in a row to count as down, until a query works again
+		pub fn is_tripped(&self) -> bool {
+			self.failures.load(Ordering::Relaxed) >= self.threshold
+		}
+
+		/// Whether a query may be sent, which it may again once the cooldown is over to find out if the database is back
+		pub fn allows(&self) -> bool {
+			!self.is_tripped() || now() >= self.open_until.load(Ordering::Relaxed)
+		}
+
+		pub fn succeeded(&self) {
+			if self.failures.swap(0, Ordering::Relaxed) >= self.threshold {
+				log::info!("The database is reachable again");
+			}
+		}
+
+		pub fn failed(&self) {
+			let failures = self.failures.fetch_add(1, Ordering::Relaxed) + 1;
+			if failures >= self.threshold {
+				self.open_until.store(now() + self.cooldown.as_millis() as i64, Ordering::Relaxed);
+				if failures == self.threshold {
+					log::error!("The database failed {failures} times in a row, queries are held back");
+				}
+			}
+		}
+	}
+
+	fn now() -> i64 {
+		chrono::Utc::now().timestamp_millis()
+	}
+
+	static BREAKER: OnceLock<Breaker> = OnceLock::new();
+
+	fn breaker_from(config: &Config) -> Breaker {
+		Breaker::new(config.database_breaker_threshold, Duration::from_secs(config.database_breaker_cooldown))
+	}
+
+	/// Take `DATABASE_BREAKER_THRESHOLD` and `DATABASE_BREAKER_COOLDOWN` from the config, call it on start before the
+	/// database is queried
+	pub fn init_breaker(config: &Config) {
+		if BREAKER.set(breaker_from(config)).is_err() {
+			log::warn!("The database breaker was set up twice, keeping the first setting");
+		}
+	}
+
+	/// The breaker of the app pool
+	pub fn breaker() -> &'static Breaker {
+		BREAKER.get_or_init(|| breaker_from(&Config::default()))
+	}
+
+	/// Refuses every request that isn't a read while the breaker is tripped since it couldn't be saved anyway, server
+	/// functions that write are sent as POST just like the REST API, GraphQL, uploads and login links
+	///
+	/// Server function calls get the error their clients decode, everything else gets it as JSON.
+	pub async fn refuse_writes(request: Request<Body>, next: Next) -> Response {
+		if request.method().is_safe() || !breaker().is_tripped() {
+			return next.run(request).await;
+		}
+
+		let error =
+			AppServerError::new(ErrorCode::Unavailable, "The database is unavailable, changes can't be saved right now");
+		let path = request.uri().path();
+		if path.starts_with("/api/") && !path.starts_with("/api/v1/") {
+			let error = ServerFnError::WrappedServerError(error);
+			return (StatusCode::SERVICE_UNAVAILABLE, error.ser().unwrap_or_default()).into_response();
+		}
+
+		(StatusCode::SERVICE_UNAVAILABLE, Json(error)).into_response()
+	}
+}
+
+/// Whether the database counts as down, asking tries it again once the cooldown is over
+#[server(input = GetUrl)]
+pub async fn get_database_degraded() -> Result<bool, ServerFnError<AppServerError>> {
+	use self::ssr::breaker;
+	use crate::retry::retry_read;
+	use sqlx::PgPool;
+
+	let pool = use_context::<PgPool>().expect("Database not initialized");
+
+	if breaker().is_tripped() && breaker().allows() {
+		// Whether the probe works or not, the breaker knows afterwards
+		_ = retry_read("database_probe", || sqlx::query("SELECT 1").execute(&pool)).await;
+	}
+
+	Ok(breaker().is_tripped())
+}
+
+/// A banner while the database is down, everything shown is read-only until it's back
+#[component]
+pub fn DegradedBanner() -> impl IntoView {
+	let degraded = create_resource(|| (), |_| get_database_degraded());
+	set_interval(move || degraded.refetch(), DEGRADED_POLL_INTERVAL);
+
+	view! {
+		<Transition fallback=move || ()>
+			{move || {
+				degraded
+					.get()
+					.and_then(Result::ok)
+					.filter(|degraded| *degraded)
+					.map(|_| {
+						view! {
+							<p class="degraded-banner">
+								"We can't reach the database right now. You can keep reading, but changes can't be saved until it's back."
+							</p>
+						}
+					})
+			}}
+		</Transition>
+	}
+}
+
+#[cfg(all(test, feature = "ssr"))]
+mod tests {
+	use super::ssr::Breaker;
+	use std::time::Duration;
+
+	#[test]
+	fn breaker_trips_and_resets() {
+		let breaker = Breaker::new(2, Duration::from_secs(60));
+		breaker.failed();
+		assert!(!breaker.is_tripped());
+		assert!(breaker.allows());
+
+		breaker.failed();
+		assert!(breaker.is_tripped());
+		assert!(!breaker.allows());
+
+		breaker.succeeded();
+		assert!(!breaker.is_tripped());
+		assert!(breaker.allows());
+
+		let breaker = Breaker::new(1, Duration::ZERO);
+		breaker.failed();
+		assert!(breaker.is_tripped());
+		assert!(breaker.allows());
+	}
+}
@@ -0,0 +1,260 @@
+use crate::{
+	auth::User,
+	config::Config,
+	events::{next_event, EventBus},
+};
+use leptos::server_fn::ServerFn;
+use std::{
+	any::Any,
+	collections::{hash_map::DefaultHasher, HashMap},
+	future::Future,
+	hash::{Hash, Hasher},
+	sync::{Arc, Mutex, OnceLock},
+	time::{Duration, Instant},
+};
+
+// Entries are dropped once the cache is full rather than evicting the least used, invalidations empty it often enough
+const MAX_ENTRIES: usize = 10_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct CacheKey {
+	function: &'static str,
+	tenant: i32,
+	user: i32,
+	/// Changed permissions make a new key so nobody is served what they could read before
+	permissions: u64,
+}
+
+struct Entry {
+	generation: u64,
+	stored_at: Instant,
+	value: Arc<dyn Any + Send + Sync>,
+}
+
+#[derive(Default)]
+struct Inner {
+	entries: HashMap<CacheKey, Entry>,
+	/// Bumped on every invalidation of a tenant, results loaded before the bump are never stored
+	generations: HashMap<i32, u64>,
+}
+
+/// Results of read server functions per user, invalidated per tenant whenever its todos change
+///
+/// Writes invalidate right after they commit so the writer reads its own changes, domain events invalidate the
+/// caches of the other replicas
+pub struct ResponseCache {
+	inner: Mutex<Inner>,
+	ttl: Duration,
+}
+
+static CACHE: OnceLock<ResponseCache> = OnceLock::new();
+
+/// Take `RESPONSE_CACHE_TTL` from the config, call it on start before any request is served
+pub fn init_response_cache(config: &Config) {
+	if CACHE.set(ResponseCache::new(Duration::from_secs(config.response_cache_ttl))).is_err() {
+		log::warn!("The response cache was set up twice, keeping the first setting");
+	}
+}
+
+/// The cache of this process, entries live for `RESPONSE_CACHE_TTL` seconds at most
+pub fn response_cache<'a>() -> &'a ResponseCache {
+	CACHE.get_or_init(|| ResponseCache::new(Duration::from_secs(Config::default().response_cache_ttl)))
+}
+
+pub(crate) fn permission_hash(user: &User) -> u64 {
+	let mut hasher = DefaultHasher::new();
+	format!("{:?}", user.permission_todo()).hash(&mut hasher);
+	hasher.finish()
+}
+
+impl ResponseCache {
+	pub fn new(ttl: Duration) -> Self {
+		Self {
+			inner: Mutex::new(Inner::default()),
+			ttl,
+		}
+	}
+
+	/// Serve a cached result of `function` for the user or load and store it, failed loads aren't stored
+	pub async fn get_or_load<T, E, F, Fut>(
+		&self,
+		function: &'static str,
+		tenant: i32,
+		user: &User,
+		load: F,
+	) -> Result<T, E>
+	where
+		T: Clone + Send + Sync + 'static,
+		F: FnOnce() -> Fut,
+		Fut: Future<Output = Result<T, E>>,
+	{
+		let key = CacheKey {
+			function,
+			tenant,
+			user: user.id,
+			permissions: permission_hash(user),
+		};
+
+		let generation = {
+			let inner = self.inner.lock().expect("Response cache poisoned");
+			let generation = inner.generations.get(&tenant).copied().unwrap_or_default();
+			let cached = inner
+				.entries
+				.get(&key)
+				.filter(|entry| entry.generation == generation && entry.stored_at.elapsed() < self.ttl)
+				.and_then(|entry| entry.value.downcast_ref::<T>().cloned());
+			if let Some(value) = cached {
+				return Ok(value);
+			}
+			generation
+		};
+
+		let value = load().await?;
+
+		let mut inner = self.inner.lock().expect("Response cache poisoned");
+		if inner.generations.get(&tenant).copied().unwrap_or_default() == generation {
+			if inner.entries.len() >= MAX_ENTRIES {
+				let ttl = self.ttl;
+				inner.entries.retain(|_, entry| entry.stored_at.elapsed() < ttl);
+			}
+			if inner.entries.len() < MAX_ENTRIES {
+				inner.entries.insert(
+					key,
+					Entry {
+						generation,
+						stored_at: Instant::now(),
+						value: Arc::new(value.clone()),
+					},
+				);
+			}
+		}
+
+		Ok(value)
+	}
+
+	/// Drop everything cached for a user, e.g. once they logged out
+	pub fn invalidate_user(&self, user: i32) {
+		let mut inner = self.inner.lock().expect("Response cache poisoned");
+		inner.entries.retain(|key, _| key.user != user);
+	}
+
+	pub fn invalidate_tenant(&self, tenant: i32) {
+		let mut inner = self.inner.lock().expect("Response cache poisoned");
+		*inner.generations.entry(tenant).or_default() += 1;
+		inner.entries.retain(|key, _| key.tenant != tenant);
+	}
+}
+
+/// Let browsers keep the response of the GET server function `T` for `max_age` seconds, `0` makes them ask again
+/// every time
+///
+/// Responses are per user so shared caches never store them, and nothing is set when the function is called while
+/// rendering a page
+pub fn cache_privately<T: ServerFn>(max_age: u64) {
+	use axum::http::{
+		header::{CACHE_CONTROL, VARY},
+		request::Parts,
+		HeaderValue,
+	};
+	use leptos::use_context;
+	use leptos_axum::ResponseOptions;
+
+	let called = use_context::<Parts>().is_some_and(|parts| parts.uri.path() == T::PATH);
+	let Some(response) = use_context::<ResponseOptions>().filter(|_| called) else {
+		return;
+	};
+
+	let cache_control = if max_age == 0 {
+		String::from("private, no-cache")
+	} else {
+		format!("private, max-age={max_age}")
+	};
+	response.insert_header(CACHE_CONTROL, HeaderValue::from_str(&cache_control).expect("Invalid cache control"));
+	response.insert_header(VARY, HeaderValue::from_static("Cookie, Authorization"));
+}
+
+/// Invalidate the tenants of todo events, events of other replicas only reach us through the outbox
+pub fn spawn_invalidator(events: &EventBus) {
+	use crate::events::DomainEvent;
+
+	let mut receiver = events.subscribe();
+
+	tokio::spawn(async move {
+		while let Some(event) = next_event(&mut receiver, "Response cache").await {
+			if matches!(
+				event,
+				DomainEvent::TodoCreated { .. } | DomainEvent::TodoUpdated { .. } | DomainEvent::TodoDeleted { .. }
+			) {
+				response_cache().invalidate_tenant(event.tenant());
+			}
+		}
+	});
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::permission::{Permission, Permissions};
+
+	#[tokio::test]
+	async fn invalidation_test() {
+		let cache = ResponseCache::new(Duration::from_secs(60));
+		let user = User {
+			id: 1,
+			tenant: 1,
+			..User::default()
+		};
+		let load = |value: i32| move || async move { Ok::<_, ()>(value) };
+
+		assert_eq!(cache.get_or_load("count", 1, &user, load(1)).await, Ok(1));
+		assert_eq!(cache.get_or_load("count", 1, &user, load(2)).await, Ok(1));
+
+		cache.invalidate_tenant(2);
+		assert_eq!(cache.get_or_load("count", 1, &user, load(2)).await, Ok(1));
+
+		cache.invalidate_tenant(1);
+		assert_eq!(cache.get_or_load("count", 1, &user, load(2)).await, Ok(2));
+
+		// Another user or changed permissions never share an entry
+		let other = User {
+			id: 2,
+			tenant: 1,
+			..User::default()
+		};
+		assert_eq!(cache.get_or_load("count", 1, &other, load(3)).await, Ok(3));
+		let promoted = User {
+			permission_todo: Permissions::ReadWrite {
+				read: Permission::ReadAny,
+				write: Permission::WriteAny,
+				create: Permission::Create(true),
+			}
+			.into(),
+			..user.clone()
+		};
+		assert_eq!(cache.get_or_load("count", 1, &promoted, load(4)).await, Ok(4));
+
+		cache.invalidate_user(2);
+		assert_eq!(cache.get_or_load("count", 1, &other, load(5)).await, Ok(5));
+		assert_eq!(cache.get_or_load("count", 1, &user, load(5)).await, Ok(2));
+	}
+
+	#[tokio::test]
+	async fn stale_load_test() {
+		let cache = ResponseCache::new(Duration::from_secs(60));
+		let user = User {
+			id: 1,
+			tenant: 1,
+			..User::default()
+		};
+
+		// A load that started before an invalidation may have read old data so it isn't stored
+		let value = cache
+			.get_or_load("count", 1, &user, || async {
+				cache.invalidate_tenant(1);
+				Ok::<_, ()>(1)
+			})
+			.await;
+		assert_eq!(value, Ok(1));
+		assert_eq!(cache.get_or_load("count", 1, &user, || async { Ok::<_, ()>(2) }).await, Ok(2));
+	}
+}
@@ -0,0 +1,328 @@
+//! The open todos with a due date as a calendar feed, for calendar apps to subscribe to
+//!
+//! Calendar apps can't log in, so the feed is read with a token in its address instead. The token shows up once when
+//! the feed is created and only its hash is stored, creating the feed again replaces the token and removing it stops
+//! the old address from working. The feed lists what the user could read in the app, with the permissions they have
+//! when the calendar app asks for it.
+
+use crate::{
+	errors::AppServerError,
+	toast::{use_toasts, Toasted},
+};
+use chrono::prelude::*;
+use leptos::*;
+use serde::{Deserialize, Serialize};
+
+/// Where the feed of a token is read from
+pub const FEED_PATH: &str = "/calendar/:token";
+
+/// The calendar feed of the logged in user, the token is only known to whoever created it
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "ssr", derive(utoipa::ToSchema))]
+pub struct CalendarFeed {
+	pub created_at: DateTime<Utc>,
+}
+
+#[cfg(feature = "ssr")]
+pub mod ssr {
+	use crate::{
+		auth::User,
+		jwt::ssr::hash_token,
+		routes::Route,
+		state::AppState,
+		tenant::Tenant,
+		todo::{ssr::list_todos, Todo},
+	};
+	use axum::{
+		extract::{Path, State},
+		http::{header, StatusCode},
+		response::{IntoResponse, Response},
+	};
+	use chrono::prelude::*;
+
+	/// Lines of a calendar can be 75 bytes long, longer ones continue on the next line after a space
+	const MAX_LINE_LENGTH: usize = 75;
+
+	/// Backslashes, commas, semicolons and line breaks have a meaning in calendar text
+	fn escape(text: &str) -> String {
+		let mut escaped = String::with_capacity(text.len());
+		for character in text.chars() {
+			match character {
+				'\\' | ';' | ',' => {
+					escaped.push('\\');
+					escaped.push(character);
+				},
+				'\n' => escaped.push_str("\\n"),
+				'\r' => {},
+				character => escaped.push(character),
+			}
+		}
+
+		escaped
+	}
+
+	fn push_line(calendar: &mut String, line: &str) {
+		let mut length = 0;
+		for character in line.chars() {
+			if length + character.len_utf8() > MAX_LINE_LENGTH {
+				calendar.push_str("\r\n ");
+				// The space the continued line starts with counts too
+				length = 1;
+			}
+			calendar.push(character);
+			length += character.len_utf8();
+		}
+		calendar.push_str("\r\n");
+	}
+
+	fn timestamp(time: DateTime<Utc>) -> String {
+		time.format("%Y%m%dT%H%M%SZ").to_string()
+	}
+
+	/// The todos that have a due date as events at the time they are due
+	pub fn calendar(name: &str, todos: &[Todo], public_url: &str) -> String {
+		let mut calendar = String::new();
+		for line in [
+			"BEGIN:VCALENDAR",
+			"VERSION:2.0",
+			"PRODID:-//session_auth_axum//Todos//EN",
+			"CALSCALE:GREGORIAN",
+		] {
+			push_line(&mut calendar, line);
+		}
+		push_line(&mut calendar, &format!("X-WR-CALNAME:{}", escape(name)));
+
+		for todo in todos {
+			let Some(due_at) = todo.due_at else {
+				continue;
+			};
+			let url = format!("{public_url}{}", Route::TodoDetail(todo.id));
+			push_line(&mut calendar, "BEGIN:VEVENT");
+			push_line(
+				&mut calendar,
+				&format!("UID:todo-{}@{}", todo.id, public_url.rsplit("://").next().unwrap_or(public_url)),
+			);
+			push_line(&mut calendar, &format!("DTSTAMP:{}", timestamp(todo.updated_at)));
+			push_line(&mut calendar, &format!("DTSTART:{}", timestamp(due_at)));
+			push_line(&mut calendar, "DURATION:PT30M");
+			push_line(&mut calendar, &format!("SUMMARY:{}", escape(&todo.title)));
+			push_line(&mut calendar, &format!("URL:{url}"));
+			push_line(&mut calendar, &format!("SEQUENCE:{}", todo.version));
+			push_line(&mut calendar, "END:VEVENT");
+		}
+		push_line(&mut calendar, "END:VCALENDAR");
+
+		calendar
+	}
+
+	/// The feed of the token in the address, calendar apps want it to end in `.ics`
+	pub async fn calendar_feed(
+		State(app_state): State<AppState>,
+		tenant: Tenant,
+		Path(token): Path<String>,
+	) -> Result<Response, (StatusCode, String)> {
+		let not_found = (StatusCode::NOT_FOUND, String::from("Unknown calendar feed"));
+		// Calendar apps show whatever comes back, what went wrong only goes to the log
+		let internal_error = |error: sqlx::Error| {
+			log::error!("Serving a calendar feed failed: {error}");
+			(StatusCode::INTERNAL_SERVER_ERROR, String::from("Something went wrong"))
+		};
+
+		let token = token.strip_suffix(".ics").unwrap_or(&token);
+		let person = sqlx::query_scalar::<_, i32>("SELECT person FROM calendar_feeds WHERE token_hash = $1")
+			.bind(hash_token(token))
+			.fetch_optional(&app_state.pool)
+			.await
+			.map_err(internal_error)?
+			.ok_or(not_found.clone())?;
+		// Users that were deactivated or moved to another tenant don't get their todos out this way either
+		let user = User::get_from_id(person, &app_state.pool)
+			.await
+			.filter(|user| user.active && user.tenant == tenant.id)
+			.ok_or(not_found)?;
+
+		let todos = list_todos(&user, tenant.id, &app_state.pool)
+			.await
+			.map_err(internal_error)?
+			.into_iter()
+			.filter(|todo| !todo.completed)
+			.collect::<Vec<_>>();
+
+		Ok(
+			(
+				[
+					(header::CONTENT_TYPE, "text/calendar; charset=utf-8"),
+					(header::CACHE_CONTROL, "private, no-store"),
+				],
+				calendar(&format!("Todos of {}", tenant.name), &todos, &app_state.config.public_url),
+			)
+				.into_response(),
+		)
+	}
+}
+
+/// The calendar feed of the logged in user, `None` when there is none
+#[server(input = GetUrl)]
+pub async fn get_calendar_feed() -> Result<Option<CalendarFeed>, ServerFnError<AppServerError>> {
+	use crate::{auth::get_user, cache::cache_privately};
+	use sqlx::PgPool;
+
+	cache_privately::<GetCalendarFeed>(0);
+
+	let pool = use_context::<PgPool>().expect("Database not initialized");
+	let user = get_user().await?.ok_or_else(AppServerError::unauthenticated)?;
+
+	Ok(
+		sqlx::query_as::<_, (DateTime<Utc>,)>("SELECT created_at FROM calendar_feeds WHERE person = $1")
+			.bind(user.id)
+			.fetch_optional(&pool)
+			.await
+			.map_err(AppServerError::from)?
+			.map(|(created_at,)| CalendarFeed { created_at }),
+	)
+}
+
+/// Create the calendar feed of the logged in user and return its address, an existing feed gets a new address
+#[server]
+pub async fn create_calendar_feed() -> Result<String, ServerFnError<AppServerError>> {
+	use crate::{
+		auth::get_user,
+		config::Config,
+		jwt::ssr::{generate_token, hash_token},
+	};
+	use sqlx::PgPool;
+
+	let pool = use_context::<PgPool>().expect("Database not initialized");
+	let config = use_context::<Config>().expect("No config found");
+	let user = get_user().await?.ok_or_else(AppServerError::unauthenticated)?;
+
+	let token = generate_token(48);
+	sqlx::query(
+		"INSERT INTO calendar_feeds (person, token_hash) VALUES ($1, $2)
+		ON CONFLICT (person) DO UPDATE SET token_hash = EXCLUDED.token_hash, created_at = now()",
+	)
+	.bind(user.id)
+	.bind(hash_token(&token))
+	.execute(&pool)
+	.await
+	.map_err(AppServerError::from)?;
+
+	Ok(format!("{}{}", config.public_url, FEED_PATH.replace(":token", &format!("{token}.ics"))))
+}
+
+/// Stop the calendar feed of the logged in user from working
+#[server]
+pub async fn delete_calendar_feed() -> Result<Toasted<()>, ServerFnError<AppServerError>> {
+	use crate::auth::get_user;
+	use sqlx::PgPool;
+
+	let pool = use_context::<PgPool>().expect("Database not initialized");
+	let user = get_user().await?.ok_or_else(AppServerError::unauthenticated)?;
+
+	sqlx::query("DELETE FROM calendar_feeds WHERE person = $1")
+		.bind(user.id)
+		.execute(&pool)
+		.await
+		.map_err(AppServerError::from)?;
+
+	Ok(Toasted::success((), "Calendar feed removed"))
+}
+
+#[component]
+pub fn CalendarFeedSettings() -> impl IntoView {
+	let create = create_server_action::<CreateCalendarFeed>();
+	let delete = create_server_action::<DeleteCalendarFeed>();
+	use_toasts().follow(delete);
+	let feed = create_resource(move || (create.version().get(), delete.version().get()), move |_| get_calendar_feed());
+
+	view! {
+		<h2>"Calendar"</h2>
+		<Transition fallback=move || view! { <p>"Loading..."</p> }>
+			{move || {
+				feed.get()
+					.map(|feed| match feed {
+						Err(e) => view! { <span class="error">{e.to_string()}</span> }.into_view(),
+						Ok(None) => {
+							view! {
+								<p>"Subscribe to your open todos with a due date from your calendar app."</p>
+								<ActionForm action=create>
+									<input type="submit" value="Create calendar feed" />
+								</ActionForm>
+							}
+								.into_view()
+						}
+						Ok(Some(feed)) => {
+							view! {
+								<p>{format!("Your calendar feed was created at {}.", feed.created_at)}</p>
+								<ActionForm action=create>
+									<input type="submit" value="New address" />
+								</ActionForm>
+								<ActionForm action=delete>
+									<input type="submit" value="Remove" />
+								</ActionForm>
+							}
+								.into_view()
+						}
+					})
+			}}
+
+		</Transition>
+		{move || {
+			create
+				.value()
+				.get()
+				.map(|result| match result {
+					Ok(url) => {
+						view! {
+							<p>
+								"Add this address to your calendar app, it's only shown once and anyone with it can see your todos: "
+								<code>{url}</code>
+							</p>
+						}
+							.into_view()
+					}
+					Err(e) => view! { <span class="error">{e.to_string()}</span> }.into_view(),
+				})
+		}}
+	}
+}
+
+#[cfg(all(test, feature = "ssr"))]
+mod tests {
+	use super::ssr::calendar;
+	use crate::todo::{Priority, Todo};
+	use chrono::prelude::*;
+
+	#[test]
+	fn calendars_list_due_todos() {
+		let due_at = Utc.with_ymd_and_hms(2024, 3, 1, 9, 30, 0).unwrap();
+		let todo = |id: i32, title: &str, due_at: Option<DateTime<Utc>>| Todo {
+			id,
+			user: None,
+			title: String::from(title),
+			description: None,
+			description_html: None,
+			created_at: due_at.unwrap_or_default(),
+			completed: false,
+			recurrence: None,
+			due_at,
+			priority: Priority::Normal,
+			position: id,
+			updated_at: due_at.unwrap_or_default(),
+			version: 1,
+			archived_at: None,
+			assignees: Vec::new(),
+			project: None,
+		};
+		let long_title = "Check the brakes, lights; and tyres ".repeat(4);
+		let calendar =
+			calendar("Todos", &[todo(1, &long_title, Some(due_at)), todo(2, "Someday", None)], "https://todos.example.com");
+
+		assert!(calendar.starts_with("BEGIN:VCALENDAR\r\n") && calendar.ends_with("END:VCALENDAR\r\n"));
+		assert!(calendar.contains("UID:todo-1@todos.example.com\r\n"));
+		assert!(calendar.contains("DTSTART:20240301T093000Z\r\n"));
+		assert!(calendar.contains("SUMMARY:Check the brakes\\, lights\\; and tyres"));
+		assert!(!calendar.contains("Someday"), "todos without a due date aren't events");
+		assert!(calendar.split("\r\n").all(|line| line.len() <= 75));
+	}
+}
@@ -0,0 +1,161 @@
+use crate::{
+	config::{Config, StoreBackend},
+	jobs,
+	username::USERNAME_RETENTION_DAYS,
+};
+use serde::Serialize;
+use sqlx::PgPool;
+use std::{
+	sync::atomic::{AtomicU64, Ordering},
+	time::Duration,
+};
+use utoipa::ToSchema;
+
+/// Rows removed by the cleanup of this process since it started
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, ToSchema)]
+pub struct CleanupMetrics {
+	pub runs: u64,
+	pub sessions: u64,
+	pub refresh_tokens: u64,
+	pub rate_limits: u64,
+	pub magic_links: u64,
+	pub share_links: u64,
+	pub delegations: u64,
+	pub username_history: u64,
+	pub email_changes: u64,
+	pub undo_tokens: u64,
+	pub deleted_todos: u64,
+	pub saml_requests: u64,
+}
+
+static RUNS: AtomicU64 = AtomicU64::new(0);
+static SESSIONS: AtomicU64 = AtomicU64::new(0);
+static REFRESH_TOKENS: AtomicU64 = AtomicU64::new(0);
+static RATE_LIMITS: AtomicU64 = AtomicU64::new(0);
+static MAGIC_LINKS: AtomicU64 = AtomicU64::new(0);
+static SHARE_LINKS: AtomicU64 = AtomicU64::new(0);
+static DELEGATIONS: AtomicU64 = AtomicU64::new(0);
+static USERNAME_HISTORY: AtomicU64 = AtomicU64::new(0);
+static EMAIL_CHANGES: AtomicU64 = AtomicU64::new(0);
+static UNDO_TOKENS: AtomicU64 = AtomicU64::new(0);
+static DELETED_TODOS: AtomicU64 = AtomicU64::new(0);
+static SAML_REQUESTS: AtomicU64 = AtomicU64::new(0);
+
+pub fn metrics() -> CleanupMetrics {
+	CleanupMetrics {
+		runs: RUNS.load(Ordering::Relaxed),
+		sessions: SESSIONS.load(Ordering::Relaxed),
+		refresh_tokens: REFRESH_TOKENS.load(Ordering::Relaxed),
+		rate_limits: RATE_LIMITS.load(Ordering::Relaxed),
+		magic_links: MAGIC_LINKS.load(Ordering::Relaxed),
+		share_links: SHARE_LINKS.load(Ordering::Relaxed),
+		delegations: DELEGATIONS.load(Ordering::Relaxed),
+		username_history: USERNAME_HISTORY.load(Ordering::Relaxed),
+		email_changes: EMAIL_CHANGES.load(Ordering::Relaxed),
+		undo_tokens: UNDO_TOKENS.load(Ordering::Relaxed),
+		deleted_todos: DELETED_TODOS.load(Ordering::Relaxed),
+		saml_requests: SAML_REQUESTS.load(Ordering::Relaxed),
+	}
+}
+
+/// Delete everything that expired, returns the rows removed by this run
+pub async fn purge_expired(
+	pool: &PgPool,
+	session_pool: &PgPool,
+	config: &Config,
+) -> Result<CleanupMetrics, sqlx::Error> {
+	// Redis expires its sessions on its own
+	let sessions = if config.session_backend == StoreBackend::Postgres {
+		sqlx::query("DELETE FROM axum_sessions WHERE expires < EXTRACT(EPOCH FROM now())")
+			.execute(session_pool)
+			.await?
+			.rows_affected()
+	} else {
+		0
+	};
+	// Expired refresh tokens are refused anyway, so reuse detection doesn't need them anymore
+	let refresh_tokens =
+		sqlx::query("DELETE FROM refresh_tokens WHERE expires_at < now()").execute(pool).await?.rows_affected();
+	let rate_limits =
+		sqlx::query("DELETE FROM rate_limits WHERE expires_at < now()").execute(pool).await?.rows_affected();
+	// Used links are kept until they expire to tell them apart from unknown ones
+	let magic_links =
+		sqlx::query("DELETE FROM magic_links WHERE expires_at < now()").execute(pool).await?.rows_affected();
+	let share_links =
+		sqlx::query("DELETE FROM share_links WHERE expires_at < now()").execute(pool).await?.rows_affected();
+	let delegations = sqlx::query("DELETE FROM delegations WHERE until < now()").execute(pool).await?.rows_affected();
+	// Past the retention old usernames are free to take and the cooldown is long over
+	let username_history =
+		sqlx::query("DELETE FROM username_history WHERE changed_at < now() - make_interval(days => $1)")
+			.bind(USERNAME_RETENTION_DAYS as i32)
+			.execute(pool)
+			.await?
+			.rows_affected();
+	let email_changes =
+		sqlx::query("DELETE FROM email_changes WHERE expires_at < now()").execute(pool).await?.rows_affected();
+	let undo_tokens =
+		sqlx::query("DELETE FROM undo_tokens WHERE expires_at < now()").execute(pool).await?.rows_affected();
+	// Once they can't be brought back anymore deleted todos are removed for good
+	let deleted_todos = sqlx::query("DELETE FROM todos WHERE deleted_at < now() - make_interval(secs => $1)")
+		.bind(config.undo_window as f64)
+		.execute(pool)
+		.await?
+		.rows_affected();
+	// The IdP never answered these
+	let saml_requests =
+		sqlx::query("DELETE FROM saml_requests WHERE expires_at < now()").execute(pool).await?.rows_affected();
+
+	RUNS.fetch_add(1, Ordering::Relaxed);
+	SESSIONS.fetch_add(sessions, Ordering::Relaxed);
+	REFRESH_TOKENS.fetch_add(refresh_tokens, Ordering::Relaxed);
+	RATE_LIMITS.fetch_add(rate_limits, Ordering::Relaxed);
+	MAGIC_LINKS.fetch_add(magic_links, Ordering::Relaxed);
+	SHARE_LINKS.fetch_add(share_links, Ordering::Relaxed);
+	DELEGATIONS.fetch_add(delegations, Ordering::Relaxed);
+	USERNAME_HISTORY.fetch_add(username_history, Ordering::Relaxed);
+	EMAIL_CHANGES.fetch_add(email_changes, Ordering::Relaxed);
+	UNDO_TOKENS.fetch_add(undo_tokens, Ordering::Relaxed);
+	DELETED_TODOS.fetch_add(deleted_todos, Ordering::Relaxed);
+	SAML_REQUESTS.fetch_add(saml_requests, Ordering::Relaxed);
+
+	Ok(CleanupMetrics {
+		runs: 1,
+		sessions,
+		refresh_tokens,
+		rate_limits,
+		magic_links,
+		share_links,
+		delegations,
+		username_history,
+		email_changes,
+		undo_tokens,
+		deleted_todos,
+		saml_requests,
+	})
+}
+
+/// Purge expired rows every `CLEANUP_INTERVAL` seconds
+pub fn spawn_cleanup(pool: PgPool, session_pool: PgPool, config: Config) {
+	jobs::every("Cleanup", Duration::from_secs(config.cleanup_interval), move || {
+		let (pool, session_pool, config) = (pool.clone(), session_pool.clone(), config.clone());
+		async move {
+			let removed = purge_expired(&pool, &session_pool, &config).await?;
+			log::info!(
+				"Cleanup removed {} sessions, {} refresh tokens, {} rate limits, {} magic links, {} share links, {} \
+				 delegations, {} old usernames, {} email changes, {} undo tokens, {} deleted todos and {} SAML requests",
+				removed.sessions,
+				removed.refresh_tokens,
+				removed.rate_limits,
+				removed.magic_links,
+				removed.share_links,
+				removed.delegations,
+				removed.username_history,
+				removed.email_changes,
+				removed.undo_tokens,
+				removed.deleted_todos,
+				removed.saml_requests
+			);
+			Ok::<_, sqlx::Error>(())
+		}
+	});
+}
@@ -0,0 +1,192 @@
+use leptos::*;
+
+/// Something the command palette can run
+#[derive(Clone)]
+pub struct Command {
+	/// Registering a command with an id that's already taken replaces the older one
+	pub id: String,
+	pub label: String,
+	pub run: Callback<()>,
+}
+
+/// The commands of everything that's mounted right now, features add theirs with [`CommandRegistry::register`]
+#[derive(Clone, Copy)]
+pub struct CommandRegistry {
+	commands: RwSignal<Vec<Command>>,
+}
+
+impl CommandRegistry {
+	/// Offer a command for as long as the calling component or effect lives, it's removed again on cleanup
+	pub fn register(&self, id: impl Into<String>, label: impl Into<String>, run: impl Fn() + 'static) {
+		let id = id.into();
+		let command = Command {
+			id: id.clone(),
+			label: label.into(),
+			run: Callback::new(move |()| run()),
+		};
+		let commands = self.commands;
+
+		commands.update(|commands| {
+			commands.retain(|command| command.id != id);
+			commands.push(command);
+		});
+		on_cleanup(move || commands.update(|commands| commands.retain(|command| command.id != id)));
+	}
+
+	/// The commands whose label contains every word of the query, ignoring case
+	pub fn matching(&self, query: &str) -> Vec<Command> {
+		let words = query.to_lowercase().split_whitespace().map(String::from).collect::<Vec<_>>();
+
+		self.commands.with(|commands| {
+			commands
+				.iter()
+				.filter(|command| {
+					let label = command.label.to_lowercase();
+					words.iter().all(|word| label.contains(word))
+				})
+				.cloned()
+				.collect()
+		})
+	}
+}
+
+/// Start an empty registry for every component below, call it once above the router
+pub fn provide_command_registry() -> CommandRegistry {
+	let registry = CommandRegistry {
+		commands: create_rw_signal(Vec::new()),
+	};
+	provide_context(registry);
+	registry
+}
+
+pub fn use_command_registry() -> CommandRegistry {
+	use_context::<CommandRegistry>().expect("No command registry found, call provide_command_registry first")
+}
+
+/// Opens with Ctrl+K (Cmd+K on macOS), type to filter, arrows to pick, Enter to run and Escape to close
+#[component]
+pub fn CommandPalette() -> impl IntoView {
+	let registry = use_command_registry();
+	let (open, set_open) = create_signal(false);
+	let (query, set_query) = create_signal(String::new());
+	let (selected, set_selected) = create_signal(0usize);
+	let input = create_node_ref::<html::Input>();
+	let matching = Signal::derive(move || registry.matching(&query.get()));
+
+	let close = move || {
+		set_open.set(false);
+		set_query.set(String::new());
+		set_selected.set(0);
+	};
+	let run = move |command: Command| {
+		close();
+		command.run.call(());
+	};
+
+	let toggle = window_event_listener(ev::keydown, move |ev| {
+		if (ev.ctrl_key() || ev.meta_key()) && ev.key().eq_ignore_ascii_case("k") {
+			ev.prevent_default();
+			if open.get_untracked() {
+				close();
+			} else {
+				set_open.set(true);
+			}
+		}
+	});
+	on_cleanup(move || toggle.remove());
+
+	// The input only exists once the palette is open
+	create_effect(move |_| {
+		if let Some(input) = input.get() {
+			_ = input.focus();
+		}
+	});
+
+	let keydown = move |ev: ev::KeyboardEvent| match ev.key().as_str() {
+		"Escape" => close(),
+		"ArrowDown" => {
+			ev.prevent_default();
+			set_selected.update(|selected| *selected = (*selected + 1).min(matching.get_untracked().len().saturating_sub(1)));
+		},
+		"ArrowUp" => {
+			ev.prevent_default();
+			set_selected.update(|selected| *selected = selected.saturating_sub(1));
+		},
+		"Enter" => {
+			ev.prevent_default();
+			if let Some(command) = matching.get_untracked().into_iter().nth(selected.get_untracked()) {
+				run(command);
+			}
+		},
+		_ => {},
+	};
+
+	view! {
+		<Show when=move || open.get()>
+			<div class="command-palette" on:click=move |_| close()>
+				<div class="command-palette-dialog" on:click=|ev| ev.stop_propagation()>
+					<input
+						type="text"
+						placeholder="Type a command"
+						node_ref=input
+						prop:value=move || query.get()
+						on:input=move |ev| {
+							set_query.set(event_target_value(&ev));
+							set_selected.set(0);
+						}
+						on:keydown=keydown
+					/>
+					<ul>
+						{move || {
+							matching
+								.get()
+								.into_iter()
+								.enumerate()
+								.map(|(index, command)| {
+									let label = command.label.clone();
+									view! {
+										<li
+											class:selected=move || selected.get() == index
+											on:mouseenter=move |_| set_selected.set(index)
+											on:click=move |_| run(command.clone())
+										>
+											{label}
+										</li>
+									}
+								})
+								.collect_view()
+						}}
+
+					</ul>
+				</div>
+			</div>
+		</Show>
+	}
+}
+
+/// The commands that are always there, render it inside the router so they can navigate
+#[component]
+pub fn DefaultCommands() -> impl IntoView {
+	use crate::{
+		auth::{use_current_user, use_user_context, Logout},
+		routes::Route,
+	};
+	use leptos_router::use_navigate;
+
+	let registry = use_command_registry();
+	let logout = use_user_context().logout;
+	let user = use_current_user();
+	let go = |route: Route| {
+		let navigate = use_navigate();
+		move || navigate(&route.path(), Default::default())
+	};
+
+	registry.register("go_todos", "Go to todos", go(Route::Home));
+	registry.register("go_settings", "Go to settings", go(Route::Settings));
+	// Only logged in users get to log out, the command comes and goes with the user
+	create_effect(move |_| {
+		if user.get().is_some() {
+			registry.register("logout", "Log out", move || logout.dispatch(Logout {}));
+		}
+	});
+}
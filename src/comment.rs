@@ -0,0 +1,232 @@
+use crate::{
+	auth::PublicUser,
+	avatar::{Avatar, AvatarSize},
+	errors::AppServerError,
+	validation::COMMENT_BODY,
+};
+use chrono::prelude::*;
+use leptos::*;
+use leptos_router::*;
+use serde::{Deserialize, Serialize};
+
+/// Longest comment we accept, in characters
+pub const MAX_COMMENT_LENGTH: usize = COMMENT_BODY.max_length;
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "ssr", derive(utoipa::ToSchema))]
+pub struct Comment {
+	pub id: i32,
+	pub todo: i32,
+	pub user: Option<PublicUser>,
+	pub body: String,
+	pub created_at: DateTime<Utc>,
+}
+
+#[cfg(feature = "ssr")]
+pub mod ssr {
+	use super::Comment;
+	use crate::auth::PublicUser;
+	use chrono::prelude::*;
+	use sqlx::PgPool;
+
+	#[derive(sqlx::FromRow, Clone)]
+	pub struct SqlComment {
+		pub id: i32,
+		pub todo: i32,
+		pub person: i32,
+		pub body: String,
+		pub created_at: DateTime<Utc>,
+	}
+
+	impl SqlComment {
+		pub async fn into_comment(self, pool: &PgPool) -> Comment {
+			Comment {
+				id: self.id,
+				todo: self.todo,
+				user: PublicUser::get_from_id(self.person, pool).await,
+				body: self.body,
+				created_at: self.created_at,
+			}
+		}
+	}
+}
+
+/// Comments on a todo, anyone who can read the todo can read its comments
+#[server(input = GetUrl)]
+pub async fn get_comments(todo_id: i32) -> Result<Vec<Comment>, ServerFnError<AppServerError>> {
+	use self::ssr::SqlComment;
+	use crate::{
+		auth::get_user, authorize::Action, cache::cache_privately, tenant::Tenant, todo::ssr::get_todo_with_permission,
+	};
+	use futures::future::join_all;
+	use sqlx::PgPool;
+
+	cache_privately::<GetComments>(0);
+
+	let pool = use_context::<PgPool>().expect("Database not initialized");
+	let tenant = use_context::<Tenant>().expect("No tenant found");
+	let user = get_user().await?.ok_or_else(AppServerError::unauthenticated)?;
+
+	if get_todo_with_permission(todo_id, tenant.id, &user, Action::Read, &pool).await.is_none() {
+		return Err(AppServerError::not_found("Todo not found").into());
+	}
+
+	let comments = sqlx::query_as::<_, SqlComment>("SELECT * FROM comments WHERE todo = $1 ORDER BY id")
+		.bind(todo_id)
+		.fetch_all(&pool)
+		.await
+		.map_err(AppServerError::from)?;
+
+	Ok(join_all(comments.into_iter().map(|comment| comment.into_comment(&pool))).await)
+}
+
+/// Comment on a todo, being able to read a todo is enough to join the discussion
+#[server]
+pub async fn add_comment(todo_id: i32, body: String) -> Result<(), ServerFnError<AppServerError>> {
+	use crate::{auth::get_user, authorize::Action, tenant::Tenant, todo::ssr::get_todo_with_permission};
+	use sqlx::PgPool;
+
+	let pool = use_context::<PgPool>().expect("Database not initialized");
+	let tenant = use_context::<Tenant>().expect("No tenant found");
+	let user = get_user().await?.ok_or_else(AppServerError::unauthenticated)?;
+
+	let body = COMMENT_BODY.clean(&body).map_err(AppServerError::invalid)?;
+
+	if get_todo_with_permission(todo_id, tenant.id, &user, Action::Read, &pool).await.is_none() {
+		return Err(AppServerError::not_found("Todo not found").into());
+	}
+
+	sqlx::query("INSERT INTO comments (todo, person, body) VALUES ($1, $2, $3)")
+		.bind(todo_id)
+		.bind(user.id)
+		.bind(body)
+		.execute(&pool)
+		.await
+		.map_err(AppServerError::from)?;
+
+	Ok(())
+}
+
+/// Delete a comment, authors can delete their own and write access to the todo allows deleting anyone's
+#[server]
+pub async fn delete_comment(id: i32) -> Result<(), ServerFnError<AppServerError>> {
+	use self::ssr::SqlComment;
+	use crate::{
+		auth::get_user,
+		authorize::Action,
+		denial::{ssr::record_denial, DeniedAction},
+		tenant::Tenant,
+		todo::ssr::get_todo_with_permission,
+	};
+	use sqlx::PgPool;
+
+	let pool = use_context::<PgPool>().expect("Database not initialized");
+	let tenant = use_context::<Tenant>().expect("No tenant found");
+	let user = get_user().await?.ok_or_else(AppServerError::unauthenticated)?;
+
+	let not_found = || AppServerError::not_found("Comment not found");
+	let comment = sqlx::query_as::<_, SqlComment>("SELECT * FROM comments WHERE id = $1")
+		.bind(id)
+		.fetch_optional(&pool)
+		.await
+		.map_err(AppServerError::from)?
+		.ok_or_else(not_found)?;
+
+	// Comments on todos the user can't read are reported as missing so their ids can't be probed
+	if get_todo_with_permission(comment.todo, tenant.id, &user, Action::Read, &pool).await.is_none() {
+		return Err(not_found().into());
+	}
+	if comment.person != user.id
+		&& get_todo_with_permission(comment.todo, tenant.id, &user, Action::Write, &pool).await.is_none()
+	{
+		record_denial("delete_comment", DeniedAction::Write, &user);
+		return Err(AppServerError::forbidden("Missing permission to delete this comment").into());
+	}
+
+	sqlx::query("DELETE FROM comments WHERE id = $1").bind(id).execute(&pool).await.map_err(AppServerError::from)?;
+
+	Ok(())
+}
+
+/// A collapsed comment thread, the comments are only loaded once it's opened
+#[component]
+pub fn Comments(todo_id: i32) -> impl IntoView {
+	let (open, set_open) = create_signal(false);
+
+	view! {
+		<div class="comments">
+			<button type="button" on:click=move |_| set_open.update(|open| *open = !*open)>
+				{move || if open.get() { "Hide comments" } else { "Comments" }}
+			</button>
+			<Show when=move || open.get()>
+				<CommentThread todo_id=todo_id />
+			</Show>
+		</div>
+	}
+}
+
+#[component]
+fn CommentThread(todo_id: i32) -> impl IntoView {
+	let add_comment = create_server_action::<AddComment>();
+	let delete_comment = create_server_action::<DeleteComment>();
+	let comments = create_resource(
+		move || (add_comment.version().get(), delete_comment.version().get()),
+		move |_| get_comments(todo_id),
+	);
+
+	view! {
+		<Transition fallback=move || view! { <p>"Loading..."</p> }>
+			{move || {
+				comments
+					.get()
+					.map(|comments| match comments {
+						Err(e) => view! { <span class="error">{e.to_string()}</span> }.into_view(),
+						Ok(comments) if comments.is_empty() => view! { <p>"No comments yet."</p> }.into_view(),
+						Ok(comments) => {
+							view! {
+								<ul>
+									{comments
+										.into_iter()
+										.map(|comment| {
+											let user = comment.user.unwrap_or_default();
+											view! {
+												<li>
+													<Avatar avatar=user.avatar size=AvatarSize::Small />
+													<strong>{user.username}</strong>
+													": "
+													{comment.body}
+													" "
+													<small>{comment.created_at.to_string()}</small>
+													<ActionForm action=delete_comment>
+														<input type="hidden" name="id" value=comment.id />
+														<input type="submit" value="X" />
+													</ActionForm>
+												</li>
+											}
+										})
+										.collect_view()}
+								</ul>
+							}
+								.into_view()
+						}
+					})
+			}}
+
+		</Transition>
+		{move || {
+			delete_comment
+				.value()
+				.get()
+				.and_then(Result::err)
+				.map(|e| view! { <span class="error">{e.to_string()}</span> })
+		}}
+		<ActionForm action=add_comment>
+			<input type="hidden" name="todo_id" value=todo_id />
+			<textarea name="body" maxlength=MAX_COMMENT_LENGTH required=true></textarea>
+			<input type="submit" value="Comment" />
+		</ActionForm>
+		{move || {
+			add_comment.value().get().and_then(Result::err).map(|e| view! { <span class="error">{e.to_string()}</span> })
+		}}
+	}
+}
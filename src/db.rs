@@ -1,22 +1,78 @@
 #[cfg(feature = "ssr")]
 pub mod ssr {
 	use dotenvy::dotenv;
-	use sqlx::{postgres::PgPoolOptions, PgPool, Pool, Postgres};
+	use log::LevelFilter;
+	use sqlx::{
+		postgres::{PgConnectOptions, PgPoolOptions},
+		ConnectOptions, PgPool, Pool, Postgres,
+	};
+	use std::{str::FromStr, time::Duration};
 
 	static DB: std::sync::OnceLock<PgPool> = std::sync::OnceLock::new();
+	static SESSION_DB: std::sync::OnceLock<PgPool> = std::sync::OnceLock::new();
 
-	async fn create_pool() -> PgPool {
-		dotenv().ok();
+	async fn create_pool(database_url: &str, schema: Option<&str>) -> PgPool {
+		let mut options = PgConnectOptions::from_str(database_url).expect("Invalid database url");
+		if let Some(schema) = schema {
+			options = options.options([("search_path", schema)]);
+		}
+		// Statements are logged as events of the span they ran in, which carries the server function and the user
+		let slow_query_ms = std::env::var("DATABASE_SLOW_QUERY_MS").ok().and_then(|ms| ms.parse().ok()).unwrap_or(200);
+		options = options
+			.log_statements(LevelFilter::Debug)
+			.log_slow_statements(LevelFilter::Warn, Duration::from_millis(slow_query_ms));
 
-		let database_url = std::env::var("DATABASE_URL").expect("No database url found in environment");
-		PgPoolOptions::new().max_connections(5).connect(database_url.as_str()).await.expect("Unable to connect to database")
+		PgPoolOptions::new().max_connections(5).connect_with(options).await.expect("Unable to connect to database")
 	}
 
-	pub async fn init_db() -> Result<(), Pool<Postgres>> {
-		DB.set(create_pool().await)
+	fn schema_from_env(name: &str) -> Option<String> {
+		let schema = std::env::var(name).ok().filter(|schema| !schema.is_empty())?;
+		// The schema ends up in a statement unquoted, so only plain names are allowed
+		if !schema.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+			panic!("Invalid value for {name}: only letters, digits and _ are allowed");
+		}
+
+		Some(schema)
+	}
+
+	/// The pool of the app tables, connected to the `DATABASE_URL` secret
+	/// - `DATABASE_SCHEMA`: the schema the app tables are in, defaults to the search path of the database user
+	/// - `DATABASE_SLOW_QUERY_MS`: statements taking longer are logged as warnings with their duration, defaults to 200,
+	///   the rest only shows up at the debug level of `sqlx::query`
+	pub async fn init_db(database_url: &str) -> Result<(), Pool<Postgres>> {
+		dotenv().ok();
+
+		DB.set(create_pool(database_url, schema_from_env("DATABASE_SCHEMA").as_deref()).await)
 	}
 
 	pub fn get_db<'a>() -> &'a PgPool {
 		DB.get().expect("Database has not been initialized")
 	}
+
+	/// The pool of the session store, which can live in its own schema or database so volatile session data can get
+	/// its own retention and backups, shares the app pool when neither is set
+	/// - `session_database_url`: the `SESSION_DATABASE_URL` secret, falls back to `database_url`
+	/// - `SESSION_DATABASE_SCHEMA`: the schema sessions are kept in, created when it doesn't exist
+	pub async fn init_session_db(session_database_url: Option<&str>, database_url: &str) -> Result<(), Pool<Postgres>> {
+		dotenv().ok();
+
+		let schema = schema_from_env("SESSION_DATABASE_SCHEMA");
+		if session_database_url.is_none() && schema.is_none() {
+			return SESSION_DB.set(get_db().clone());
+		}
+
+		let pool = create_pool(session_database_url.unwrap_or(database_url), schema.as_deref()).await;
+		if let Some(schema) = &schema {
+			sqlx::query(&format!("CREATE SCHEMA IF NOT EXISTS {schema}"))
+				.execute(&pool)
+				.await
+				.expect("Unable to create the session schema");
+		}
+
+		SESSION_DB.set(pool)
+	}
+
+	pub fn get_session_db<'a>() -> &'a PgPool {
+		SESSION_DB.get().expect("Session database has not been initialized")
+	}
 }
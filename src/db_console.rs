@@ -0,0 +1,545 @@
+//! A read-only look into the database for support engineers, so debugging a permission issue doesn't take psql
+//!
+//! There is no SQL to type. A query picks one of the allowlisted tables, filters its columns and is always limited to
+//! the tenant and to [`MAX_ROWS`] rows. Values are bound as parameters, table and column names only ever come from
+//! the allowlist, which leaves out secrets like password and token hashes. Queries run in a read-only transaction with
+//! a statement timeout.
+//!
+//! The console is off unless `DB_CONSOLE` is set. Only admins who confirmed their password recently can use it and
+//! every query is recorded as an event, so it ends up in the audit log with the filters as they were typed.
+
+use crate::{auth::ConfirmPassword, errors::AppServerError};
+use leptos::*;
+use serde::{Deserialize, Serialize};
+use std::{fmt, str::FromStr};
+
+/// How many rows a query returns unless it asks for fewer
+pub const DEFAULT_ROWS: i64 = 50;
+/// The most rows a query returns, however many it asks for
+pub const MAX_ROWS: i64 = 200;
+
+/// A table the console can look into with the columns it shows and filters on
+#[derive(Debug, PartialEq, Eq)]
+pub struct ConsoleTable {
+	pub name: &'static str,
+	/// In the order they are shown, rows are ordered by the first one unless the query asks otherwise
+	pub columns: &'static [&'static str],
+	/// What keeps the rows to the tenant bound as `$1`, for tables without a tenant column of their own
+	tenant_filter: &'static str,
+}
+
+const OWN_TENANT: &str = "tenant = $1";
+const TENANT_OF_PERSON: &str = "person IN (SELECT id FROM users WHERE tenant = $1)";
+
+pub const TABLES: &[ConsoleTable] = &[
+	ConsoleTable {
+		name: "users",
+		columns: &[
+			"id",
+			"username",
+			"permission_equipment",
+			"permission_user",
+			"permission_todo",
+			"active",
+			"service_account",
+			"concurrent_sessions",
+			"session_generation",
+			"created_at",
+		],
+		tenant_filter: OWN_TENANT,
+	},
+	ConsoleTable {
+		name: "identities",
+		columns: &["id", "person", "provider", "subject", "created_at"],
+		tenant_filter: OWN_TENANT,
+	},
+	ConsoleTable {
+		name: "api_tokens",
+		columns: &[
+			"id",
+			"person",
+			"name",
+			"permission_equipment",
+			"permission_user",
+			"permission_todo",
+			"created_at",
+			"last_used_at",
+		],
+		tenant_filter: OWN_TENANT,
+	},
+	ConsoleTable {
+		name: "projects",
+		columns: &["id", "name", "created_at"],
+		tenant_filter: OWN_TENANT,
+	},
+	ConsoleTable {
+		name: "todos",
+		columns: &[
+			"id",
+			"person",
+			"project",
+			"title",
+			"completed",
+			"priority",
+			"due_at",
+			"version",
+			"created_at",
+			"updated_at",
+			"archived_at",
+			"deleted_at",
+		],
+		tenant_filter: OWN_TENANT,
+	},
+	ConsoleTable {
+		name: "todo_assignees",
+		columns: &["todo", "person"],
+		tenant_filter: TENANT_OF_PERSON,
+	},
+	ConsoleTable {
+		name: "user_visible_todos",
+		columns: &["todo", "person"],
+		tenant_filter: TENANT_OF_PERSON,
+	},
+	ConsoleTable {
+		name: "delegations",
+		columns: &[
+			"id",
+			"grantor",
+			"grantee",
+			"equipment",
+			"persons",
+			"write",
+			"until",
+			"created_at",
+		],
+		tenant_filter: OWN_TENANT,
+	},
+	ConsoleTable {
+		name: "scheduled_permission_changes",
+		columns: &[
+			"id",
+			"person",
+			"permission_equipment",
+			"permission_user",
+			"permission_todo",
+			"apply_at",
+			"scheduled_by",
+			"created_at",
+		],
+		tenant_filter: OWN_TENANT,
+	},
+	ConsoleTable {
+		name: "saml_permission_presets",
+		columns: &[
+			"id",
+			"idp_group",
+			"priority",
+			"permission_equipment",
+			"permission_user",
+			"permission_todo",
+		],
+		tenant_filter: OWN_TENANT,
+	},
+	ConsoleTable {
+		name: "feature_flags",
+		columns: &["name", "description", "enabled", "rollout"],
+		tenant_filter: OWN_TENANT,
+	},
+	ConsoleTable {
+		name: "notifications",
+		columns: &["id", "person", "todo", "message", "read_at", "created_at"],
+		tenant_filter: OWN_TENANT,
+	},
+	ConsoleTable {
+		name: "integrations",
+		columns: &[
+			"id",
+			"person",
+			"tenant_wide",
+			"kind",
+			"room",
+			"events",
+			"last_error",
+			"created_at",
+		],
+		tenant_filter: OWN_TENANT,
+	},
+	ConsoleTable {
+		name: "username_history",
+		columns: &["id", "person", "username", "changed_at"],
+		tenant_filter: OWN_TENANT,
+	},
+];
+
+impl ConsoleTable {
+	pub fn get(name: &str) -> Option<&'static ConsoleTable> {
+		TABLES.iter().find(|table| table.name == name)
+	}
+
+	fn column(&self, name: &str) -> Result<&'static str, String> {
+		self
+			.columns
+			.iter()
+			.copied()
+			.find(|column| *column == name)
+			.ok_or_else(|| format!("{} has no column {name} the console can show", self.name))
+	}
+}
+
+/// What a filter wants of the column, values are compared with the column as text
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Condition {
+	Equals(String),
+	NotEquals(String),
+	/// The column contains the value, ignoring case
+	Contains(String),
+	IsNull,
+	IsNotNull,
+}
+
+/// A filter as typed, like `person = 3`, `username ~ dom` or `archived_at is null`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Filter {
+	pub column: String,
+	pub condition: Condition,
+}
+
+impl FromStr for Filter {
+	type Err = String;
+
+	fn from_str(filter: &str) -> Result<Self, Self::Err> {
+		let invalid = || format!("{filter} isn't a filter like `column = value`, `!=`, `~`, `is null` or `is not null`");
+		let (column, rest) = filter.trim().split_once(char::is_whitespace).ok_or_else(invalid)?;
+		let rest = rest.trim();
+
+		let condition = match rest.to_lowercase().as_str() {
+			"is null" => Condition::IsNull,
+			"is not null" => Condition::IsNotNull,
+			_ => {
+				let (operator, value) = rest.split_once(char::is_whitespace).ok_or_else(invalid)?;
+				let value = value.trim().to_string();
+				match operator {
+					"=" => Condition::Equals(value),
+					"!=" => Condition::NotEquals(value),
+					"~" => Condition::Contains(value),
+					_ => return Err(invalid()),
+				}
+			},
+		};
+
+		Ok(Self {
+			column: column.to_string(),
+			condition,
+		})
+	}
+}
+
+impl fmt::Display for Filter {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match &self.condition {
+			Condition::Equals(value) => write!(f, "{} = {value}", self.column),
+			Condition::NotEquals(value) => write!(f, "{} != {value}", self.column),
+			Condition::Contains(value) => write!(f, "{} ~ {value}", self.column),
+			Condition::IsNull => write!(f, "{} is null", self.column),
+			Condition::IsNotNull => write!(f, "{} is not null", self.column),
+		}
+	}
+}
+
+/// A query with every name checked against the allowlist
+#[derive(Debug, PartialEq, Eq)]
+pub struct ConsoleQuery {
+	pub table: &'static ConsoleTable,
+	pub filters: Vec<Filter>,
+	pub order: &'static str,
+	pub descending: bool,
+	pub limit: i64,
+}
+
+impl ConsoleQuery {
+	/// A query from the form, filters one per line and the order like `created_at desc`, empty for the first column
+	/// descending
+	pub fn parse(table: &str, filters: &str, order: Option<&str>, limit: Option<i64>) -> Result<Self, String> {
+		let table = ConsoleTable::get(table).ok_or_else(|| format!("The console can't look into {table}"))?;
+		let filters = filters
+			.lines()
+			.filter(|line| !line.trim().is_empty())
+			.map(|line| {
+				let filter = line.parse::<Filter>()?;
+				table.column(&filter.column)?;
+				Ok(filter)
+			})
+			.collect::<Result<Vec<_>, String>>()?;
+
+		let (order, descending) = match order.map(str::trim).filter(|order| !order.is_empty()) {
+			None => (table.columns[0], true),
+			Some(order) => match order.split_once(char::is_whitespace) {
+				None => (table.column(order)?, false),
+				Some((column, direction)) => match direction.trim().to_lowercase().as_str() {
+					"asc" => (table.column(column)?, false),
+					"desc" => (table.column(column)?, true),
+					_ => return Err(format!("{order} isn't an order like `column` or `column desc`")),
+				},
+			},
+		};
+
+		Ok(Self {
+			table,
+			filters,
+			order,
+			descending,
+			limit: limit.unwrap_or(DEFAULT_ROWS).clamp(1, MAX_ROWS),
+		})
+	}
+
+	/// The SQL with the tenant as `$1` and the values to bind after it, one row more than the limit is asked for to
+	/// tell whether there were more
+	///
+	/// Every column is turned into text so rows of any table come back the same way, as a JSON array.
+	#[cfg(feature = "ssr")]
+	pub fn sql(&self) -> (String, Vec<String>) {
+		let columns = self.table.columns.iter().map(|column| format!("{column}::text")).collect::<Vec<_>>().join(", ");
+		let mut conditions = vec![format!("({})", self.table.tenant_filter)];
+		let mut values = Vec::new();
+		for filter in &self.filters {
+			// The column was checked against the allowlist when the query was parsed
+			let column = &filter.column;
+			let mut bind = |value: &String| {
+				values.push(value.clone());
+				format!("${}", values.len() + 1)
+			};
+			conditions.push(match &filter.condition {
+				Condition::Equals(value) => format!("{column}::text = {}", bind(value)),
+				Condition::NotEquals(value) => format!("{column}::text IS DISTINCT FROM {}", bind(value)),
+				Condition::Contains(value) => format!("strpos(lower({column}::text), lower({})) > 0", bind(value)),
+				Condition::IsNull => format!("{column} IS NULL"),
+				Condition::IsNotNull => format!("{column} IS NOT NULL"),
+			});
+		}
+
+		(
+			format!(
+				"SELECT json_build_array({columns}) FROM {} WHERE {} ORDER BY {} {} LIMIT {}",
+				self.table.name,
+				conditions.join(" AND "),
+				self.order,
+				if self.descending { "DESC" } else { "ASC" },
+				self.limit + 1,
+			),
+			values,
+		)
+	}
+}
+
+/// The rows a query found with every value as text, `None` for `NULL`
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "ssr", derive(utoipa::ToSchema))]
+pub struct QueryResult {
+	pub columns: Vec<String>,
+	pub rows: Vec<Vec<Option<String>>>,
+	/// There were more rows than the limit
+	pub truncated: bool,
+}
+
+#[cfg(feature = "ssr")]
+pub mod ssr {
+	use super::{ConsoleQuery, QueryResult};
+	use sqlx::{types::Json, PgPool};
+
+	/// Run the query for the tenant in a read-only transaction
+	pub async fn run(query: &ConsoleQuery, tenant: i32, pool: &PgPool) -> Result<QueryResult, sqlx::Error> {
+		let (sql, values) = query.sql();
+
+		let mut transaction = pool.begin().await?;
+		sqlx::query("SET TRANSACTION READ ONLY").execute(&mut *transaction).await?;
+		// Postgres cancels queries that would hold up the database for the app
+		sqlx::query("SET LOCAL statement_timeout = '5s'").execute(&mut *transaction).await?;
+		let mut rows = values
+			.iter()
+			.fold(sqlx::query_scalar::<_, Json<Vec<Option<String>>>>(&sql).bind(tenant), |rows, value| rows.bind(value))
+			.fetch_all(&mut *transaction)
+			.await?
+			.into_iter()
+			.map(|Json(row)| row)
+			.collect::<Vec<_>>();
+		// Nothing was written, there is nothing to commit either
+		transaction.rollback().await?;
+
+		let truncated = rows.len() as i64 > query.limit;
+		rows.truncate(query.limit as usize);
+
+		Ok(QueryResult {
+			columns: query.table.columns.iter().map(|column| column.to_string()).collect(),
+			rows,
+			truncated,
+		})
+	}
+}
+
+/// Look into an allowlisted table of the tenant, admins only when the console is turned on
+///
+/// `filters` are one per line, like `person = 3`, `username ~ dom` or `archived_at is null`, and `order` is a column
+/// with an optional `desc`.
+#[server]
+pub async fn query_database(
+	table: String,
+	filters: String,
+	order: Option<String>,
+	limit: Option<i64>,
+) -> Result<QueryResult, ServerFnError<AppServerError>> {
+	use crate::{
+		auth::{
+			get_user,
+			ssr::{require_recent_auth, STEP_UP_MAX_AGE},
+		},
+		config::Config,
+		denial::{ssr::record_denial, DeniedAction},
+		events::{self, DomainEvent},
+		tenant::Tenant,
+	};
+	use sqlx::PgPool;
+
+	let pool = use_context::<PgPool>().expect("Database not initialized");
+	let config = use_context::<Config>().expect("No config found");
+	let tenant = use_context::<Tenant>().expect("No tenant found");
+	let user = get_user().await?.ok_or_else(AppServerError::unauthenticated)?;
+	if !user.is_admin() {
+		record_denial("query_database", DeniedAction::Admin, &user);
+		return Err(AppServerError::forbidden("Missing permission to use the database console").into());
+	}
+	if !config.db_console {
+		return Err(AppServerError::forbidden("The database console is turned off").into());
+	}
+	require_recent_auth(STEP_UP_MAX_AGE)?;
+
+	let query = ConsoleQuery::parse(&table, &filters, order.as_deref(), limit).map_err(AppServerError::invalid)?;
+
+	// Recorded before the query runs so queries that fail or time out are in the audit log too
+	events::record(
+		&pool,
+		&DomainEvent::DatabaseQueried {
+			tenant: tenant.id,
+			user: user.id,
+			table: query.table.name.to_string(),
+			filters: query.filters.iter().map(ToString::to_string).collect(),
+			limit: query.limit,
+		},
+	)
+	.await
+	.map_err(AppServerError::from)?;
+
+	Ok(ssr::run(&query, tenant.id, &pool).await.map_err(AppServerError::from)?)
+}
+
+#[component]
+pub fn DatabaseConsole() -> impl IntoView {
+	let query = create_server_action::<QueryDatabase>();
+	let table = create_rw_signal(String::from(TABLES[0].name));
+	let error = Signal::derive(move || query.value().get().and_then(Result::err));
+
+	view! {
+		<h1>"Database"</h1>
+		<p>
+			"Look into the tables of this tenant, read only. Every query is in the audit log. Filter with one line per column, like "
+			<code>"person = 3"</code> ", " <code>"username ~ dom"</code> " or " <code>"archived_at is null"</code> "."
+		</p>
+		<ActionForm action=query>
+			<label>
+				"Table "
+				<select name="table" on:change=move |event| table.set(event_target_value(&event))>
+					{TABLES.iter().map(|table| view! { <option value=table.name>{table.name}</option> }).collect_view()}
+				</select>
+			</label>
+			<p>
+				"Columns: "
+				{move || ConsoleTable::get(&table.get()).map(|table| table.columns.join(", "))}
+			</p>
+			<label>"Filters " <textarea name="filters"></textarea></label>
+			<label>"Order " <input type="text" name="order" placeholder="created_at desc" /></label>
+			<label>
+				"Rows " <input type="number" name="limit" min=1 max=MAX_ROWS placeholder=DEFAULT_ROWS.to_string() />
+			</label>
+			<input type="submit" value="Query" />
+		</ActionForm>
+		{move || error.get().map(|e| view! { <span class="error">{e.to_string()}</span> })}
+		<ConfirmPassword error=error />
+		{move || {
+			query
+				.value()
+				.get()
+				.and_then(Result::ok)
+				.map(|result| {
+					view! {
+						<table class="db-console">
+							<tr>{result.columns.into_iter().map(|column| view! { <th>{column}</th> }).collect_view()}</tr>
+							{result
+								.rows
+								.into_iter()
+								.map(|row| {
+									view! {
+										<tr>
+											{row
+												.into_iter()
+												.map(|value| {
+													view! { <td class:null=value.is_none()>{value.unwrap_or_else(|| String::from("NULL"))}</td> }
+												})
+												.collect_view()}
+										</tr>
+									}
+								})
+								.collect_view()}
+						</table>
+						{result.truncated.then(|| view! { <p>"There are more rows, narrow the query down."</p> })}
+					}
+				})
+		}}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn filters_are_parsed() {
+		assert_eq!(
+			"person = 3".parse::<Filter>(),
+			Ok(Filter {
+				column: String::from("person"),
+				condition: Condition::Equals(String::from("3")),
+			})
+		);
+		assert_eq!("title ~ buy milk".parse::<Filter>().unwrap().condition, Condition::Contains(String::from("buy milk")));
+		assert_eq!("archived_at IS NOT NULL".parse::<Filter>().unwrap().condition, Condition::IsNotNull);
+		assert_eq!("username != dom".parse::<Filter>().unwrap().to_string(), "username != dom");
+		assert!("person".parse::<Filter>().is_err());
+		assert!("person > 3".parse::<Filter>().is_err());
+	}
+
+	#[test]
+	fn queries_only_name_the_allowlist() {
+		let query = ConsoleQuery::parse("users", "active = false\n\n", Some("created_at DESC"), Some(1000)).unwrap();
+		assert_eq!(query.order, "created_at");
+		assert!(query.descending);
+		assert_eq!(query.limit, MAX_ROWS);
+
+		assert!(ConsoleQuery::parse("refresh_tokens", "", None, None).is_err());
+		assert!(ConsoleQuery::parse("users", "password = x", None, None).is_err());
+		assert!(ConsoleQuery::parse("users", "", Some("password"), None).is_err());
+		assert!(ConsoleQuery::parse("users", "", Some("id; DROP TABLE users"), None).is_err());
+	}
+
+	#[cfg(feature = "ssr")]
+	#[test]
+	fn values_are_bound() {
+		let query = ConsoleQuery::parse("todo_assignees", "person = 1' OR '1' = '1\ntodo is null", None, Some(10)).unwrap();
+		let (sql, values) = query.sql();
+		assert_eq!(
+			sql,
+			"SELECT json_build_array(todo::text, person::text) FROM todo_assignees \
+			WHERE (person IN (SELECT id FROM users WHERE tenant = $1)) AND person::text = $2 AND todo IS NULL \
+			ORDER BY todo DESC LIMIT 11"
+		);
+		assert_eq!(values, vec![String::from("1' OR '1' = '1")]);
+	}
+}
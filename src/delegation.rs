@@ -0,0 +1,438 @@
+use crate::{
+	auth::PublicUser,
+	errors::AppServerError,
+	toast::{use_toasts, Toasted},
+};
+use chrono::prelude::*;
+use leptos::*;
+use leptos_router::*;
+use serde::{Deserialize, Serialize};
+
+/// Longest a delegation can last, in days
+pub const MAX_DELEGATION_DAYS: i64 = 90;
+
+/// Access to todos a user lent another user until it runs out
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "ssr", derive(utoipa::ToSchema))]
+pub struct Delegation {
+	pub id: i32,
+	/// Who lent the access
+	pub grantor: Option<PublicUser>,
+	/// Who got it
+	pub grantee: Option<PublicUser>,
+	/// The todos with one of these ids, any id when empty
+	pub equipment: Vec<i32>,
+	/// The todos of one of these people, anyone's when empty
+	pub persons: Vec<i32>,
+	/// Whether the todos can be changed too and not just read
+	pub write: bool,
+	pub until: DateTime<Utc>,
+	pub created_at: DateTime<Utc>,
+}
+
+#[cfg(feature = "ssr")]
+pub mod ssr {
+	use super::Delegation;
+	use crate::{
+		auth::{PublicUser, User},
+		authorize::{self, Action},
+		cache::response_cache,
+		permission::ScopeFilter,
+	};
+	use chrono::prelude::*;
+	use sqlx::PgPool;
+
+	#[derive(sqlx::FromRow, Clone)]
+	pub struct SqlDelegation {
+		pub id: i32,
+		pub grantor: i32,
+		pub grantee: i32,
+		pub equipment: Vec<i32>,
+		pub persons: Vec<i32>,
+		pub write: bool,
+		pub until: DateTime<Utc>,
+		pub created_at: DateTime<Utc>,
+	}
+
+	impl SqlDelegation {
+		pub async fn into_delegation(self, pool: &PgPool) -> Delegation {
+			Delegation {
+				id: self.id,
+				grantor: PublicUser::get_from_id(self.grantor, pool).await,
+				grantee: PublicUser::get_from_id(self.grantee, pool).await,
+				equipment: self.equipment,
+				persons: self.persons,
+				write: self.write,
+				until: self.until,
+				created_at: self.created_at,
+			}
+		}
+	}
+
+	/// Widen a clause of the todos the user may act on by every delegation to them that hasn't run out yet
+	///
+	/// The delegation is looked up by the query itself, so lists filtered with it stay a single statement. The clause
+	/// refers to the todo as `todos`, the table every todo query reads from. What a deactivated user lent stops
+	/// counting until they are reactivated.
+	pub fn widen(filter: ScopeFilter, user: &User, action: Action) -> ScopeFilter {
+		// Nothing to add to access to everything, nobody can delegate creating todos and API tokens with scopes only
+		// reach what they name
+		if filter.sql.is_empty() || action == Action::Create || user.token_scoped {
+			return filter;
+		}
+
+		let write = if action == Action::Write {
+			" AND delegations.write"
+		} else {
+			""
+		};
+		// The id is an integer so there is nothing to escape
+		ScopeFilter {
+			sql: format!(
+				" AND ((true{}) OR EXISTS (SELECT 1 FROM delegations JOIN users grantors ON grantors.id = delegations.grantor
+				WHERE delegations.grantee = {} AND delegations.tenant = todos.tenant AND grantors.active
+				AND delegations.until > now(){write}
+				AND (cardinality(delegations.equipment) = 0 OR todos.id = ANY(delegations.equipment))
+				AND (cardinality(delegations.persons) = 0 OR todos.person = ANY(delegations.persons))))",
+				filter.sql, user.id
+			),
+			arrays: filter.arrays,
+		}
+	}
+
+	/// The delegations to the user that haven't run out yet and allow `action`, the ones [`widen`] looks up in queries
+	pub async fn delegated(
+		user: &User,
+		tenant: i32,
+		action: Action,
+		pool: &PgPool,
+	) -> Result<Vec<SqlDelegation>, sqlx::Error> {
+		if action == Action::Create || user.token_scoped {
+			return Ok(Vec::new());
+		}
+
+		sqlx::query_as::<_, SqlDelegation>(
+			"SELECT delegations.* FROM delegations JOIN users grantors ON grantors.id = delegations.grantor
+			WHERE delegations.grantee = $1 AND delegations.tenant = $2 AND grantors.active AND delegations.until > now()
+			AND (delegations.write OR NOT $3)",
+		)
+		.bind(user.id)
+		.bind(tenant)
+		.bind(action == Action::Write)
+		.fetch_all(pool)
+		.await
+	}
+
+	/// [`authorize::allows`] for checks that follow a lookup through [`widen`], so what opened the todo by delegation
+	/// goes on to allow the rest, `delegations` come from [`delegated`] for the same action
+	pub fn allows_delegated(
+		user: &User,
+		action: Action,
+		id: i32,
+		person: i32,
+		project: Option<i32>,
+		delegations: &[SqlDelegation],
+	) -> bool {
+		authorize::allows(user, action, id, person, project)
+			|| (!user.token_scoped
+				&& delegations.iter().any(|delegation| {
+					(delegation.equipment.is_empty() || delegation.equipment.contains(&id))
+						&& (delegation.persons.is_empty() || delegation.persons.contains(&person))
+				}))
+	}
+
+	/// The ids of a list of scopes like `EQUIPMENT[1], PERSON[2]`, as the equipment and the persons they name
+	pub fn parse_scopes(scopes: &str) -> Result<(Vec<i32>, Vec<i32>), String> {
+		let mut equipment = Vec::new();
+		let mut persons = Vec::new();
+
+		for scope in scopes.split(',').map(str::trim).filter(|scope| !scope.is_empty()) {
+			let invalid = || format!("Invalid scope {scope}, expected EQUIPMENT[id] or PERSON[id]");
+			let (kind, id) = scope.strip_suffix(']').and_then(|scope| scope.split_once('[')).ok_or_else(invalid)?;
+			let id = id.trim().parse::<i32>().map_err(|_| invalid())?;
+			match kind.trim().to_ascii_uppercase().as_str() {
+				"EQUIPMENT" => equipment.push(id),
+				"PERSON" => persons.push(id),
+				_ => return Err(invalid()),
+			}
+		}
+
+		if equipment.is_empty() && persons.is_empty() {
+			return Err(String::from("Name at least one scope to delegate"));
+		}
+		Ok((equipment, persons))
+	}
+
+	/// A time entered in a form, either with an offset or from a `datetime-local` input and taken as UTC
+	pub fn parse_time(time: &str) -> Option<DateTime<Utc>> {
+		DateTime::parse_from_rfc3339(time)
+			.map(|time| time.with_timezone(&Utc))
+			.or_else(|_| NaiveDateTime::parse_from_str(time, "%Y-%m-%dT%H:%M").map(|time| time.and_utc()))
+			.ok()
+	}
+
+	/// Revoke what a user delegated but can't do anymore themselves, call it whenever the permissions of a user changed
+	pub async fn revoke_uncovered(grantor: i32, pool: &PgPool) -> Result<(), sqlx::Error> {
+		let Some(user) = User::get_from_id(grantor, pool).await else {
+			return Ok(());
+		};
+
+		let uncovered = sqlx::query_as::<_, SqlDelegation>("SELECT * FROM delegations WHERE grantor = $1")
+			.bind(user.id)
+			.fetch_all(pool)
+			.await?
+			.into_iter()
+			.filter(|delegation| {
+				!authorize::includes(&user, Action::Read, &delegation.equipment, &delegation.persons)
+					|| (delegation.write
+						&& !authorize::includes(&user, Action::Write, &delegation.equipment, &delegation.persons))
+			})
+			.map(|delegation| delegation.id)
+			.collect::<Vec<_>>();
+		if uncovered.is_empty() {
+			return Ok(());
+		}
+
+		sqlx::query("DELETE FROM delegations WHERE id = ANY($1)").bind(&uncovered).execute(pool).await?;
+		response_cache().invalidate_tenant(user.tenant);
+
+		Ok(())
+	}
+}
+
+/// The delegations that haven't run out yet, the ones the user gave and the ones they got
+#[server(input = GetUrl)]
+pub async fn get_delegations() -> Result<Vec<Delegation>, ServerFnError<AppServerError>> {
+	use self::ssr::SqlDelegation;
+	use crate::{auth::get_user, cache::cache_privately};
+	use futures::future::join_all;
+	use sqlx::PgPool;
+
+	cache_privately::<GetDelegations>(0);
+
+	let pool = use_context::<PgPool>().expect("Database not initialized");
+	let user = get_user().await?.ok_or_else(AppServerError::unauthenticated)?;
+
+	let delegations = sqlx::query_as::<_, SqlDelegation>(
+		"SELECT * FROM delegations WHERE (grantor = $1 OR grantee = $1) AND until > now() ORDER BY until",
+	)
+	.bind(user.id)
+	.fetch_all(&pool)
+	.await
+	.map_err(AppServerError::from)?;
+
+	Ok(join_all(delegations.into_iter().map(|delegation| delegation.into_delegation(&pool))).await)
+}
+
+/// Lend another user of the tenant access to some of the todos the user can access themselves, until a given time
+///
+/// `scopes` lists what to lend like `EQUIPMENT[1], PERSON[2]`, the same way permissions name them, and the user has
+/// to have all of it themselves. Writing is only lent along with `write`.
+#[server]
+pub async fn grant_access(
+	to_user: String,
+	scopes: String,
+	until: String,
+	write: Option<String>,
+) -> Result<Toasted<()>, ServerFnError<AppServerError>> {
+	use self::ssr::{parse_scopes, parse_time};
+	use crate::{
+		auth::{get_user, User},
+		authorize::{self, Action},
+		cache::response_cache,
+		denial::{ssr::record_denial, DeniedAction},
+		tenant::Tenant,
+	};
+	use sqlx::PgPool;
+
+	let pool = use_context::<PgPool>().expect("Database not initialized");
+	let tenant = use_context::<Tenant>().expect("No tenant found");
+	let user = get_user().await?.ok_or_else(AppServerError::unauthenticated)?;
+
+	let (equipment, persons) = parse_scopes(&scopes).map_err(AppServerError::invalid)?;
+	let until = parse_time(&until).ok_or_else(|| AppServerError::invalid("Invalid time to delegate until"))?;
+	let now = Utc::now();
+	if until <= now || until > now + chrono::Duration::days(MAX_DELEGATION_DAYS) {
+		return Err(
+			AppServerError::invalid(format!("Access can be delegated for up to {MAX_DELEGATION_DAYS} days")).into(),
+		);
+	}
+
+	let grantee = User::get_from_username(to_user.trim().to_string(), tenant.id, &pool)
+		.await
+		.filter(|grantee| grantee.active)
+		.ok_or_else(|| AppServerError::not_found("User not found"))?;
+	if grantee.id == user.id {
+		return Err(AppServerError::invalid("Access can't be delegated to yourself").into());
+	}
+
+	let write = write.is_some();
+	if !authorize::includes(&user, Action::Read, &equipment, &persons) {
+		record_denial("grant_access", DeniedAction::Read, &user);
+		return Err(AppServerError::forbidden("Only access you have yourself can be delegated").into());
+	}
+	if write && !authorize::includes(&user, Action::Write, &equipment, &persons) {
+		record_denial("grant_access", DeniedAction::Write, &user);
+		return Err(AppServerError::forbidden("Only access you have yourself can be delegated").into());
+	}
+
+	sqlx::query(
+		"INSERT INTO delegations (tenant, grantor, grantee, equipment, persons, write, until)
+		VALUES ($1, $2, $3, $4, $5, $6, $7)",
+	)
+	.bind(tenant.id)
+	.bind(user.id)
+	.bind(grantee.id)
+	.bind(&equipment)
+	.bind(&persons)
+	.bind(write)
+	.bind(until)
+	.execute(&pool)
+	.await
+	.map_err(AppServerError::from)?;
+	response_cache().invalidate_tenant(tenant.id);
+
+	Ok(Toasted::success((), format!("Delegated access to {} until {until}", grantee.username)))
+}
+
+/// End a delegation early, both the user who gave it and the one who got it can
+#[server]
+pub async fn revoke_delegation(id: i32) -> Result<Toasted<()>, ServerFnError<AppServerError>> {
+	use crate::{auth::get_user, cache::response_cache, tenant::Tenant};
+	use sqlx::PgPool;
+
+	let pool = use_context::<PgPool>().expect("Database not initialized");
+	let tenant = use_context::<Tenant>().expect("No tenant found");
+	let user = get_user().await?.ok_or_else(AppServerError::unauthenticated)?;
+
+	let revoked = sqlx::query("DELETE FROM delegations WHERE id = $1 AND tenant = $2 AND (grantor = $3 OR grantee = $3)")
+		.bind(id)
+		.bind(tenant.id)
+		.bind(user.id)
+		.execute(&pool)
+		.await
+		.map_err(AppServerError::from)?
+		.rows_affected();
+	if revoked == 0 {
+		return Err(AppServerError::not_found("Delegation not found").into());
+	}
+	response_cache().invalidate_tenant(tenant.id);
+
+	Ok(Toasted::success((), "Delegation revoked"))
+}
+
+fn describe(delegation: &Delegation) -> String {
+	let list = |kind: &str, ids: &[i32]| ids.iter().map(|id| format!("{kind}[{id}]")).collect::<Vec<_>>();
+	let scopes = [
+		list("EQUIPMENT", &delegation.equipment),
+		list("PERSON", &delegation.persons),
+	]
+	.concat()
+	.join(", ");
+	let access = if delegation.write { "read and write" } else { "read" };
+
+	format!("{access} {scopes} until {}", delegation.until)
+}
+
+/// The delegations of the user and a form to lend access to someone else
+#[component]
+pub fn Delegations() -> impl IntoView {
+	let grant = create_server_action::<GrantAccess>();
+	let revoke = create_server_action::<RevokeDelegation>();
+	use_toasts().follow(grant);
+	use_toasts().follow(revoke);
+	let delegations =
+		create_resource(move || (grant.version().get(), revoke.version().get()), move |_| get_delegations());
+
+	view! {
+		<h2>"Delegated access"</h2>
+		<Transition fallback=move || view! { <p>"Loading..."</p> }>
+			{move || {
+				delegations
+					.get()
+					.map(|delegations| match delegations {
+						Err(e) => view! { <span class="error">{e.to_string()}</span> }.into_view(),
+						Ok(delegations) if delegations.is_empty() => {
+							view! { <p>"Nothing delegated."</p> }.into_view()
+						}
+						Ok(delegations) => {
+							view! {
+								<ul>
+									{delegations
+										.into_iter()
+										.map(|delegation| {
+											let grantor = delegation.grantor.clone().unwrap_or_default();
+											let grantee = delegation.grantee.clone().unwrap_or_default();
+											view! {
+												<li>
+													{format!(
+														"{} lent {} {}",
+														grantor.username,
+														grantee.username,
+														describe(&delegation),
+													)}
+													<ActionForm action=revoke>
+														<input type="hidden" name="id" value=delegation.id />
+														<input type="submit" value="Revoke" />
+													</ActionForm>
+												</li>
+											}
+										})
+										.collect_view()}
+								</ul>
+							}
+								.into_view()
+						}
+					})
+			}}
+
+		</Transition>
+		<ActionForm action=grant>
+			<label>"To " <input type="text" name="to_user" placeholder="Username" required=true /></label>
+			<label>
+				"Scopes " <input type="text" name="scopes" placeholder="EQUIPMENT[1], PERSON[2]" required=true />
+			</label>
+			<label>"Until (UTC) " <input type="datetime-local" name="until" required=true /></label>
+			<label><input type="checkbox" name="write" /> " Writing too"</label>
+			<input type="submit" value="Delegate" />
+		</ActionForm>
+	}
+}
+
+#[cfg(all(test, feature = "ssr"))]
+mod tests {
+	use super::ssr::*;
+	use crate::{auth::User, authorize::Action, permission::ScopeFilter};
+
+	#[test]
+	fn scopes_parse() {
+		assert_eq!(parse_scopes("EQUIPMENT[1], person[2],PERSON[3]"), Ok((vec![1], vec![2, 3])));
+		assert!(parse_scopes("").is_err());
+		assert!(parse_scopes("EQUIPMENT[x]").is_err());
+		assert!(parse_scopes("TEAM[1]").is_err());
+		assert!(parse_time("2026-10-14T10:30").is_some());
+		assert!(parse_time("2026-10-14T10:30:00+02:00").is_some());
+		assert!(parse_time("tomorrow").is_none());
+	}
+
+	#[test]
+	fn delegations_widen_filters() {
+		let user = User {
+			id: 4,
+			..User::default()
+		};
+		let filter = |sql: &str| ScopeFilter {
+			sql: sql.to_string(),
+			arrays: Vec::new(),
+		};
+
+		assert_eq!(widen(filter(""), &user, Action::Read), filter(""));
+		assert_eq!(widen(filter(" AND false"), &user, Action::Create), filter(" AND false"));
+
+		let widened = widen(filter(" AND id IN (1)"), &user, Action::Write).sql;
+		assert!(widened.starts_with(" AND ((true AND id IN (1)) OR EXISTS (SELECT 1 FROM delegations JOIN users grantors"));
+		assert!(widened.contains("WHERE delegations.grantee = 4 "));
+		assert!(widened.contains(" AND grantors.active"));
+		assert!(widened.contains(" AND delegations.write\n"));
+	}
+}
@@ -0,0 +1,141 @@
+use crate::errors::AppServerError;
+use leptos::*;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// What a refused user tried to do
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[cfg_attr(feature = "ssr", derive(utoipa::ToSchema))]
+pub enum DeniedAction {
+	Read,
+	Write,
+	Create,
+	Admin,
+}
+
+impl fmt::Display for DeniedAction {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			DeniedAction::Read => write!(f, "read"),
+			DeniedAction::Write => write!(f, "write"),
+			DeniedAction::Create => write!(f, "create"),
+			DeniedAction::Admin => write!(f, "admin"),
+		}
+	}
+}
+
+/// Denials of one action on one operation since the process started
+///
+/// Many users refused the same thing usually means a misconfigured permission, one user refused over and over looks
+/// more like probing
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "ssr", derive(utoipa::ToSchema))]
+pub struct DenialCount {
+	pub operation: String,
+	pub action: DeniedAction,
+	pub count: u64,
+	/// How many different users were refused
+	pub users: u64,
+}
+
+#[cfg(feature = "ssr")]
+pub mod ssr {
+	use super::{DenialCount, DeniedAction};
+	use crate::auth::User;
+	use std::{
+		collections::{BTreeMap, BTreeSet},
+		sync::Mutex,
+	};
+
+	#[derive(Default)]
+	struct Denials {
+		count: u64,
+		users: BTreeSet<i32>,
+	}
+
+	static DENIALS: Mutex<BTreeMap<(&'static str, DeniedAction), Denials>> = Mutex::new(BTreeMap::new());
+
+	/// Count a refused request, call it wherever a permission check fails
+	pub fn record_denial(operation: &'static str, action: DeniedAction, user: &User) {
+		log::warn!("Denied {action} on {operation} to user {} of tenant {}", user.id, user.tenant);
+
+		let mut denials = DENIALS.lock().expect("Denial counters poisoned");
+		let denial = denials.entry((operation, action)).or_default();
+		denial.count += 1;
+		denial.users.insert(user.id);
+	}
+
+	/// The denials of this process, most frequent first
+	pub fn denials() -> Vec<DenialCount> {
+		let denials = DENIALS.lock().expect("Denial counters poisoned");
+		let mut counts = denials
+			.iter()
+			.map(|((operation, action), denial)| DenialCount {
+				operation: operation.to_string(),
+				action: *action,
+				count: denial.count,
+				users: denial.users.len() as u64,
+			})
+			.collect::<Vec<_>>();
+		counts.sort_by(|a, b| b.count.cmp(&a.count));
+
+		counts
+	}
+}
+
+/// Permission denials counted by this replica since it started, admins only
+#[server(input = GetUrl)]
+pub async fn get_permission_denials() -> Result<Vec<DenialCount>, ServerFnError<AppServerError>> {
+	use self::ssr::{denials, record_denial};
+	use crate::{auth::get_user, cache::cache_privately};
+
+	cache_privately::<GetPermissionDenials>(0);
+
+	let user = get_user().await?.ok_or_else(AppServerError::unauthenticated)?;
+	if !user.is_admin() {
+		record_denial("get_permission_denials", DeniedAction::Admin, &user);
+		return Err(AppServerError::forbidden("Missing permission to see permission denials").into());
+	}
+
+	Ok(denials())
+}
+
+#[component]
+pub fn PermissionDenials() -> impl IntoView {
+	let denials = create_resource(|| (), |_| get_permission_denials());
+
+	view! {
+		<h1>"Permission denials"</h1>
+		<Transition fallback=move || view! { <p>"Loading..."</p> }>
+			{move || {
+				denials
+					.get()
+					.map(|denials| match denials {
+						Err(e) => view! { <span class="error">{e.to_string()}</span> }.into_view(),
+						Ok(denials) if denials.is_empty() => view! { <p>"Nothing was denied yet."</p> }.into_view(),
+						Ok(denials) => {
+							let most = denials.iter().map(|denial| denial.count).max().unwrap_or(1);
+							denials
+								.into_iter()
+								.map(|denial| {
+									view! {
+										<div class="denial">
+											<span>{format!("{} {}", denial.action, denial.operation)}</span>
+											<div
+												class="denial-bar"
+												style=format!("width: {}%", denial.count * 100 / most)
+											></div>
+											<span>
+												{format!("{} by {} users", denial.count, denial.users)}
+											</span>
+										</div>
+									}
+								})
+								.collect_view()
+						}
+					})
+			}}
+
+		</Transition>
+	}
+}
@@ -0,0 +1,35 @@
+use testcontainers_modules::{
+	postgres::Postgres,
+	testcontainers::{runners::AsyncRunner, ContainerAsync, ImageExt, TestcontainersError},
+};
+
+/// A disposable Postgres for development, removed when this is dropped
+pub struct DevDatabase {
+	_container: ContainerAsync<Postgres>,
+}
+
+/// Start a Postgres container with the dev schema and point `DATABASE_URL` at it when no database is configured,
+/// keep the returned guard alive for as long as the server runs
+pub async fn provision() -> Result<Option<DevDatabase>, TestcontainersError> {
+	dotenvy::dotenv().ok();
+	if std::env::var_os("DATABASE_URL").is_some() || std::env::var_os("DATABASE_URL_FILE").is_some() {
+		return Ok(None);
+	}
+
+	log::info!("No DATABASE_URL found, starting a disposable Postgres container");
+	let container = Postgres::default()
+		.with_db_name("leptossession")
+		.with_user("admin")
+		.with_password("admin")
+		.with_init_sql(include_str!("../dev/psql-compose/init.sql").to_string().into_bytes())
+		.with_tag("latest")
+		.start()
+		.await?;
+	let host = container.get_host().await?;
+	let port = container.get_host_port_ipv4(5432).await?;
+
+	// Nothing else runs yet, so the environment is safe to change
+	std::env::set_var("DATABASE_URL", format!("postgres://admin:admin@{host}:{port}/leptossession"));
+
+	Ok(Some(DevDatabase { _container: container }))
+}
@@ -0,0 +1,338 @@
+use crate::errors::AppServerError;
+use leptos::*;
+use leptos_router::*;
+use serde::{Deserialize, Serialize};
+use std::{fmt, str::FromStr};
+
+/// How often a digest of open todos is mailed
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "ssr", derive(utoipa::ToSchema))]
+pub enum DigestFrequency {
+	Daily,
+	Weekly,
+}
+
+impl fmt::Display for DigestFrequency {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			DigestFrequency::Daily => write!(f, "daily"),
+			DigestFrequency::Weekly => write!(f, "weekly"),
+		}
+	}
+}
+
+impl FromStr for DigestFrequency {
+	type Err = String;
+
+	fn from_str(frequency: &str) -> Result<Self, Self::Err> {
+		match frequency.trim().to_ascii_lowercase().as_str() {
+			"daily" => Ok(DigestFrequency::Daily),
+			"weekly" => Ok(DigestFrequency::Weekly),
+			_ => Err(format!("Unknown digest frequency \"{frequency}\"")),
+		}
+	}
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "ssr", derive(utoipa::ToSchema))]
+pub struct DigestSubscription {
+	pub email: String,
+	pub frequency: DigestFrequency,
+}
+
+#[cfg(feature = "ssr")]
+pub mod ssr {
+	use super::DigestFrequency;
+	use crate::{
+		auth::User,
+		config::Config,
+		identity::Provider,
+		jobs,
+		mailer::{Mail, Mailer},
+		state::AppState,
+		todo::{ssr::list_todos, Todo},
+	};
+	use axum::{
+		extract::{Path, State},
+		http::StatusCode,
+		response::Html,
+	};
+	use chrono::prelude::*;
+	use sqlx::PgPool;
+	use std::{str::FromStr, sync::Arc, time::Duration};
+
+	#[derive(sqlx::FromRow)]
+	struct SqlDueDigest {
+		person: i32,
+		email: String,
+		frequency: String,
+		unsubscribe_token: String,
+	}
+
+	fn format_todo(todo: &Todo) -> String {
+		match todo.due_at {
+			Some(due_at) => format!("- {} (due {})", todo.title, due_at.format("%Y-%m-%d %H:%M UTC")),
+			None => format!("- {}", todo.title),
+		}
+	}
+
+	fn digest_body(open: &[Todo], now: DateTime<Utc>, unsubscribe_url: &str) -> String {
+		let (overdue, upcoming): (Vec<_>, Vec<_>) =
+			open.iter().partition(|todo| todo.due_at.is_some_and(|due_at| due_at < now));
+
+		let mut body = String::new();
+		if !overdue.is_empty() {
+			body.push_str(&format!("Overdue ({}):\n", overdue.len()));
+			overdue.iter().for_each(|todo| body.push_str(&format!("{}\n", format_todo(todo))));
+			body.push('\n');
+		}
+		if !upcoming.is_empty() {
+			body.push_str(&format!("Open ({}):\n", upcoming.len()));
+			upcoming.iter().for_each(|todo| body.push_str(&format!("{}\n", format_todo(todo))));
+			body.push('\n');
+		}
+		body.push_str(&format!("Stop getting these emails: {unsubscribe_url}\n"));
+
+		body
+	}
+
+	/// Mail every subscriber whose digest is due
+	///
+	/// The todos are listed with the subscriber's own permissions so a digest never shows more than the app would.
+	/// Digests only go to active users at the login email they confirmed, and are counted as sent before they are
+	/// mailed so no transaction waits on the mail server, a digest that fails to send is skipped until the next period.
+	pub async fn send_digests(pool: &PgPool, mailer: &dyn Mailer, config: &Config) -> Result<usize, sqlx::Error> {
+		let mut tx = pool.begin().await?;
+
+		// Locked rows are skipped so several replicas can run this without mailing anyone twice
+		let due = sqlx::query_as::<_, SqlDueDigest>(
+			"SELECT digest_subscriptions.person, digest_subscriptions.email, digest_subscriptions.frequency,
+				digest_subscriptions.unsubscribe_token
+			FROM digest_subscriptions
+			JOIN users ON users.id = digest_subscriptions.person AND users.active
+			JOIN identities ON identities.person = digest_subscriptions.person AND identities.provider = $1
+				AND lower(identities.subject) = lower(digest_subscriptions.email)
+			WHERE digest_subscriptions.last_sent_at IS NULL
+				OR digest_subscriptions.last_sent_at
+					<= now() - CASE digest_subscriptions.frequency WHEN 'weekly' THEN interval '7 days' ELSE interval '1 day' END
+			ORDER BY digest_subscriptions.person FOR UPDATE OF digest_subscriptions SKIP LOCKED",
+		)
+		.bind(Provider::MagicLink.to_string())
+		.fetch_all(&mut *tx)
+		.await?;
+
+		let mut mails = Vec::new();
+		for digest in due {
+			let Some(user) = User::get_from_id(digest.person, pool).await else {
+				continue;
+			};
+			let open =
+				list_todos(&user, user.tenant, pool).await?.into_iter().filter(|todo| !todo.completed).collect::<Vec<_>>();
+
+			// Nothing to report isn't worth an email, the digest is still counted as sent so it waits for the next period
+			if !open.is_empty() {
+				let frequency = DigestFrequency::from_str(&digest.frequency).unwrap_or(DigestFrequency::Daily);
+				let mail = Mail {
+					to: digest.email,
+					subject: format!("Your {frequency} todo digest: {} open", open.len()),
+					body: digest_body(
+						&open,
+						Utc::now(),
+						&format!("{}/digest/unsubscribe/{}", config.public_url, digest.unsubscribe_token),
+					),
+				};
+				mails.push((digest.person, mail));
+			}
+
+			sqlx::query("UPDATE digest_subscriptions SET last_sent_at = now() WHERE person = $1")
+				.bind(digest.person)
+				.execute(&mut *tx)
+				.await?;
+		}
+
+		tx.commit().await?;
+
+		let mut sent = 0;
+		for (person, mail) in mails {
+			match mailer.send(mail).await {
+				Ok(()) => sent += 1,
+				Err(error) => log::error!("Sending the digest to user {person} failed: {error}"),
+			}
+		}
+
+		Ok(sent)
+	}
+
+	/// Check for due digests every `DIGEST_INTERVAL` seconds
+	pub fn spawn_digests(pool: PgPool, mailer: Arc<dyn Mailer>, config: Config) {
+		jobs::every("Digests", Duration::from_secs(config.digest_interval), move || {
+			let (pool, mailer, config) = (pool.clone(), mailer.clone(), config.clone());
+			async move { send_digests(&pool, mailer.as_ref(), &config).await.map(|_| ()) }
+		});
+	}
+
+	fn page(message: &str) -> Html<String> {
+		Html(format!("<!DOCTYPE html><html><head><title>Digest</title></head><body>{message}</body></html>"))
+	}
+
+	/// Ask before unsubscribing, mail scanners follow links and would unsubscribe everyone on their own
+	pub async fn unsubscribe_page(Path(token): Path<String>) -> Html<String> {
+		page(&format!(
+			"<form method=\"post\" action=\"/digest/unsubscribe/{}\"><p>Stop getting todo digests?</p>\
+			<input type=\"submit\" value=\"Unsubscribe\" /></form>",
+			token.chars().filter(char::is_ascii_alphanumeric).collect::<String>()
+		))
+	}
+
+	/// Unsubscribe with the token from a digest, works without being logged in
+	pub async fn unsubscribe(
+		State(app_state): State<AppState>,
+		Path(token): Path<String>,
+	) -> Result<Html<String>, (StatusCode, String)> {
+		let deleted = sqlx::query("DELETE FROM digest_subscriptions WHERE unsubscribe_token = $1")
+			.bind(token)
+			.execute(&app_state.pool)
+			.await
+			.map_err(|error| (StatusCode::INTERNAL_SERVER_ERROR, error.to_string()))?
+			.rows_affected();
+
+		if deleted == 0 {
+			return Err((StatusCode::NOT_FOUND, String::from("Unknown or already used unsubscribe link")));
+		}
+
+		Ok(page("<p>You won't get any more todo digests.</p>"))
+	}
+}
+
+/// The digest the logged in user is subscribed to, `None` when there is none
+#[server(input = GetUrl)]
+pub async fn get_digest_subscription() -> Result<Option<DigestSubscription>, ServerFnError<AppServerError>> {
+	use crate::{auth::get_user, cache::cache_privately};
+	use sqlx::PgPool;
+
+	cache_privately::<GetDigestSubscription>(0);
+
+	let pool = use_context::<PgPool>().expect("Database not initialized");
+	let user = get_user().await?.ok_or_else(AppServerError::unauthenticated)?;
+
+	let subscription =
+		sqlx::query_as::<_, (String, String)>("SELECT email, frequency FROM digest_subscriptions WHERE person = $1")
+			.bind(user.id)
+			.fetch_optional(&pool)
+			.await
+			.map_err(AppServerError::from)?;
+
+	Ok(subscription.and_then(|(email, frequency)| {
+		Some(DigestSubscription {
+			email,
+			frequency: frequency.parse().ok()?,
+		})
+	}))
+}
+
+/// Subscribe to a digest of open todos, an empty frequency unsubscribes
+#[server]
+pub async fn set_digest_subscription(email: String, frequency: String) -> Result<(), ServerFnError<AppServerError>> {
+	use crate::{auth::get_user, identity::Provider, jwt::ssr::generate_token, validation::EMAIL};
+	use sqlx::PgPool;
+
+	let pool = use_context::<PgPool>().expect("Database not initialized");
+	let user = get_user().await?.ok_or_else(AppServerError::unauthenticated)?;
+
+	if frequency.trim().is_empty() {
+		sqlx::query("DELETE FROM digest_subscriptions WHERE person = $1")
+			.bind(user.id)
+			.execute(&pool)
+			.await
+			.map_err(AppServerError::from)?;
+		return Ok(());
+	}
+
+	let frequency = frequency.parse::<DigestFrequency>().map_err(AppServerError::invalid)?;
+	let email = EMAIL.clean(&email).map_err(AppServerError::invalid)?;
+	if !email.contains('@') {
+		return Err(AppServerError::invalid("Invalid email address").into());
+	}
+	// Digests are only mailed to addresses the user proved are theirs
+	let confirmed = sqlx::query_scalar::<_, bool>(
+		"SELECT EXISTS (SELECT 1 FROM identities WHERE person = $1 AND provider = $2 AND lower(subject) = lower($3))",
+	)
+	.bind(user.id)
+	.bind(Provider::MagicLink.to_string())
+	.bind(&email)
+	.fetch_one(&pool)
+	.await
+	.map_err(AppServerError::from)?;
+	if !confirmed {
+		return Err(AppServerError::invalid("Digests are only sent to your confirmed login email").into());
+	}
+
+	// The token stays the same on changes so links in digests already sent keep working
+	sqlx::query(
+		"INSERT INTO digest_subscriptions (person, email, frequency, unsubscribe_token) VALUES ($1, $2, $3, $4)
+		ON CONFLICT (person) DO UPDATE SET email = EXCLUDED.email, frequency = EXCLUDED.frequency",
+	)
+	.bind(user.id)
+	.bind(email)
+	.bind(frequency.to_string())
+	.bind(generate_token(48))
+	.execute(&pool)
+	.await
+	.map_err(AppServerError::from)?;
+
+	Ok(())
+}
+
+#[component]
+pub fn DigestSettings() -> impl IntoView {
+	let save = create_server_action::<SetDigestSubscription>();
+	let subscription = create_resource(move || save.version().get(), move |_| get_digest_subscription());
+
+	view! {
+		<h2>"Email digest"</h2>
+		<Transition fallback=move || view! { <p>"Loading..."</p> }>
+			{move || {
+				subscription
+					.get()
+					.map(|subscription| match subscription {
+						Err(e) => view! { <span class="error">{e.to_string()}</span> }.into_view(),
+						Ok(subscription) => {
+							let frequency = subscription.as_ref().map(|subscription| subscription.frequency);
+							let email = subscription.map(|subscription| subscription.email).unwrap_or_default();
+							view! {
+								<ActionForm action=save>
+									<label>
+										"Email " <input type="email" name="email" value=email />
+									</label>
+									<label>
+										"Send "
+										<select name="frequency">
+											<option value="" selected=frequency.is_none()>
+												"never"
+											</option>
+											<option
+												value="daily"
+												selected={frequency == Some(DigestFrequency::Daily)}
+											>
+												"daily"
+											</option>
+											<option
+												value="weekly"
+												selected={frequency == Some(DigestFrequency::Weekly)}
+											>
+												"weekly"
+											</option>
+										</select>
+									</label>
+									<input type="submit" value="Save" />
+								</ActionForm>
+							}
+								.into_view()
+						}
+					})
+			}}
+
+		</Transition>
+		{move || save.value().get().and_then(Result::err).map(|e| view! { <span class="error">{e.to_string()}</span> })}
+	}
+}
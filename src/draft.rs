@@ -0,0 +1,93 @@
+//! What was typed into forms, kept in the local storage of the browser for each user so a reload or a dropped
+//! connection doesn't lose it
+//!
+//! Only the browser has a local storage, drafts are restored once the app runs there and never while rendering on the
+//! server.
+
+use crate::auth::use_current_user;
+use leptos::*;
+use std::collections::BTreeMap;
+use wasm_bindgen::{prelude::wasm_bindgen, JsValue};
+
+// Private browsing and full storages throw, which only costs the draft
+#[wasm_bindgen]
+extern "C" {
+	#[wasm_bindgen(catch, js_namespace = localStorage, js_name = getItem)]
+	fn get_item(key: &str) -> Result<Option<String>, JsValue>;
+	#[wasm_bindgen(catch, js_namespace = localStorage, js_name = setItem)]
+	fn set_item(key: &str, value: &str) -> Result<(), JsValue>;
+	#[wasm_bindgen(catch, js_namespace = localStorage, js_name = removeItem)]
+	fn remove_item(key: &str) -> Result<(), JsValue>;
+}
+
+fn storage_key(form: &str, user: i32) -> String {
+	format!("draft:{user}:{form}")
+}
+
+/// The fields of a form as they were last typed by the logged in user, by input name
+#[derive(Clone, Copy)]
+pub struct FormDraft {
+	form: &'static str,
+	user: Signal<Option<i32>>,
+	fields: RwSignal<BTreeMap<String, String>>,
+}
+
+/// The draft of `form`, restored once it's known who is logged in so nobody sees the draft of someone else
+pub fn use_form_draft(form: &'static str) -> FormDraft {
+	let user = use_current_user();
+	let user = Signal::derive(move || user.get().map(|user| user.id));
+	let fields = create_rw_signal(BTreeMap::new());
+
+	create_effect(move |_| {
+		let restored = user
+			.get()
+			.and_then(|user| get_item(&storage_key(form, user)).ok().flatten())
+			.and_then(|draft| serde_json::from_str(&draft).ok())
+			.unwrap_or_default();
+		fields.set(restored);
+	});
+
+	FormDraft { form, user, fields }
+}
+
+impl FormDraft {
+	pub fn value(&self, name: &str) -> String {
+		self.fields.with(|fields| fields.get(name).cloned().unwrap_or_default())
+	}
+
+	pub fn value_untracked(&self, name: &str) -> String {
+		self.fields.with_untracked(|fields| fields.get(name).cloned().unwrap_or_default())
+	}
+
+	pub fn set(&self, name: &str, value: String) {
+		self.fields.update(|fields| {
+			if value.is_empty() {
+				fields.remove(name);
+			} else {
+				fields.insert(name.to_string(), value);
+			}
+		});
+		self.save();
+	}
+
+	/// Forget the draft, once what was typed is saved
+	pub fn clear(&self) {
+		self.fields.set(BTreeMap::new());
+		self.save();
+	}
+
+	fn save(&self) {
+		let Some(user) = self.user.get_untracked() else {
+			return;
+		};
+		let key = storage_key(self.form, user);
+
+		self.fields.with_untracked(|fields| {
+			_ = if fields.is_empty() {
+				remove_item(&key)
+			} else {
+				set_item(&key, &serde_json::to_string(fields).unwrap_or_default())
+			};
+		});
+	}
+}
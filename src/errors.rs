@@ -1,4 +1,7 @@
 use http::status::StatusCode;
+use leptos::ServerFnError;
+use serde::{Deserialize, Serialize};
+use std::{fmt, str::FromStr};
 use thiserror::Error;
 
 #[derive(Debug, Clone, Error)]
@@ -7,6 +10,8 @@ pub enum TodoAppError {
 	NotFound,
 	#[error("Internal Server Error")]
 	InternalServerError,
+	#[error("The server is too busy right now, please try again in a moment")]
+	Overloaded,
 }
 
 impl TodoAppError {
@@ -14,6 +19,207 @@ impl TodoAppError {
 		match self {
 			TodoAppError::NotFound => StatusCode::NOT_FOUND,
 			TodoAppError::InternalServerError => StatusCode::INTERNAL_SERVER_ERROR,
+			TodoAppError::Overloaded => StatusCode::SERVICE_UNAVAILABLE,
 		}
 	}
 }
+
+/// What went wrong in a server function, as a stable code clients can branch on without matching messages
+///
+/// The codes are part of the API, new ones can be added but existing ones are never renamed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[cfg_attr(feature = "ssr", derive(utoipa::ToSchema))]
+pub enum ErrorCode {
+	/// Nobody is logged in
+	#[serde(rename = "AUTH001")]
+	Unauthenticated,
+	/// The password has to be confirmed again first, see [`crate::auth::ConfirmPassword`]
+	#[serde(rename = "AUTH002")]
+	ReauthenticationRequired,
+	/// The username, password or token didn't match
+	#[serde(rename = "AUTH003")]
+	InvalidCredentials,
+	/// Logged in but not allowed to do this
+	#[serde(rename = "PERM403")]
+	Forbidden,
+	#[serde(rename = "NF404")]
+	NotFound,
+	/// Someone else got there first, like a taken username or a todo that changed in the meantime
+	#[serde(rename = "CONF409")]
+	Conflict,
+	/// The input was refused, the message says why
+	#[serde(rename = "VAL422")]
+	Invalid,
+	#[serde(rename = "RATE429")]
+	RateLimited,
+	#[serde(rename = "INT500")]
+	Internal,
+	/// The database is down and writes are refused until it's back, see [`crate::breaker`]
+	#[serde(rename = "DB503")]
+	Unavailable,
+}
+
+impl ErrorCode {
+	const ALL: [ErrorCode; 10] = [
+		ErrorCode::Unauthenticated,
+		ErrorCode::ReauthenticationRequired,
+		ErrorCode::InvalidCredentials,
+		ErrorCode::Forbidden,
+		ErrorCode::NotFound,
+		ErrorCode::Conflict,
+		ErrorCode::Invalid,
+		ErrorCode::RateLimited,
+		ErrorCode::Internal,
+		ErrorCode::Unavailable,
+	];
+
+	pub fn as_str(&self) -> &'static str {
+		match self {
+			ErrorCode::Unauthenticated => "AUTH001",
+			ErrorCode::ReauthenticationRequired => "AUTH002",
+			ErrorCode::InvalidCredentials => "AUTH003",
+			ErrorCode::Forbidden => "PERM403",
+			ErrorCode::NotFound => "NF404",
+			ErrorCode::Conflict => "CONF409",
+			ErrorCode::Invalid => "VAL422",
+			ErrorCode::RateLimited => "RATE429",
+			ErrorCode::Internal => "INT500",
+			ErrorCode::Unavailable => "DB503",
+		}
+	}
+
+	pub fn status_code(&self) -> StatusCode {
+		match self {
+			ErrorCode::Unauthenticated | ErrorCode::ReauthenticationRequired | ErrorCode::InvalidCredentials => {
+				StatusCode::UNAUTHORIZED
+			},
+			ErrorCode::Forbidden => StatusCode::FORBIDDEN,
+			ErrorCode::NotFound => StatusCode::NOT_FOUND,
+			ErrorCode::Conflict => StatusCode::CONFLICT,
+			ErrorCode::Invalid => StatusCode::UNPROCESSABLE_ENTITY,
+			ErrorCode::RateLimited => StatusCode::TOO_MANY_REQUESTS,
+			ErrorCode::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+			ErrorCode::Unavailable => StatusCode::SERVICE_UNAVAILABLE,
+		}
+	}
+}
+
+impl fmt::Display for ErrorCode {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str(self.as_str())
+	}
+}
+
+impl FromStr for ErrorCode {
+	type Err = String;
+
+	fn from_str(code: &str) -> Result<Self, Self::Err> {
+		ErrorCode::ALL
+			.into_iter()
+			.find(|known| known.as_str() == code)
+			.ok_or_else(|| format!("Unknown error code \"{code}\""))
+	}
+}
+
+/// The custom error of every server function, `ServerFnError<AppServerError>`
+///
+/// It travels to the client as `CODE: message` so it survives the string based encoding of server function errors.
+/// `?` only turns errors into a `ServerFnError` when they are the custom error already, so other errors go through
+/// `.map_err(AppServerError::from)` first, which files them under [`ErrorCode::Internal`].
+///
+/// It deliberately doesn't implement `std::error::Error`, that would rule out the blanket conversion.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "ssr", derive(utoipa::ToSchema))]
+pub struct AppServerError {
+	pub code: ErrorCode,
+	pub message: String,
+}
+
+/// What server functions fail with, for the places outside their signatures where the type is spelled out
+pub type AppError = ServerFnError<AppServerError>;
+
+impl AppServerError {
+	pub fn new(code: ErrorCode, message: impl ToString) -> Self {
+		Self {
+			code,
+			message: message.to_string(),
+		}
+	}
+
+	pub fn unauthenticated() -> Self {
+		Self::new(ErrorCode::Unauthenticated, "User not authenticated")
+	}
+
+	pub fn forbidden(message: impl ToString) -> Self {
+		Self::new(ErrorCode::Forbidden, message)
+	}
+
+	pub fn not_found(message: impl ToString) -> Self {
+		Self::new(ErrorCode::NotFound, message)
+	}
+
+	pub fn conflict(message: impl ToString) -> Self {
+		Self::new(ErrorCode::Conflict, message)
+	}
+
+	pub fn invalid(message: impl ToString) -> Self {
+		Self::new(ErrorCode::Invalid, message)
+	}
+
+	pub fn internal(message: impl ToString) -> Self {
+		Self::new(ErrorCode::Internal, message)
+	}
+
+	/// The code of a server function error, `None` when it never reached the server function, like network errors
+	pub fn code_of(error: &AppError) -> Option<ErrorCode> {
+		match error {
+			ServerFnError::WrappedServerError(error) => Some(error.code),
+			_ => None,
+		}
+	}
+}
+
+impl fmt::Display for AppServerError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{}: {}", self.code, self.message)
+	}
+}
+
+impl FromStr for AppServerError {
+	type Err = String;
+
+	fn from_str(error: &str) -> Result<Self, Self::Err> {
+		let (code, message) = error.split_once(": ").ok_or_else(|| format!("Not an error with a code: {error}"))?;
+
+		Ok(Self::new(code.parse()?, message))
+	}
+}
+
+impl<E: std::error::Error> From<E> for AppServerError {
+	fn from(error: E) -> Self {
+		Self::internal(error)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn errors_round_trip() {
+		for code in ErrorCode::ALL {
+			let error = AppServerError::new(code, "Todo not found: 3");
+			assert_eq!(error.to_string().parse::<AppServerError>(), Ok(error));
+			assert_eq!(serde_json::to_string(&code).unwrap(), format!("\"{code}\""));
+		}
+
+		assert_eq!(AppServerError::invalid("Invalid email address").to_string(), "VAL422: Invalid email address");
+		assert!("Invalid email address".parse::<AppServerError>().is_err());
+		assert!("NOPE1: Invalid email address".parse::<AppServerError>().is_err());
+		assert_eq!(
+			AppServerError::code_of(&AppServerError::forbidden("Missing permission").into()),
+			Some(ErrorCode::Forbidden)
+		);
+		assert_eq!(AppServerError::code_of(&ServerFnError::Request(String::from("offline"))), None);
+	}
+}
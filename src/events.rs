@@ -0,0 +1,481 @@
+use crate::{
+	auth::{
+		ssr::{authenticated_user, AuthSession},
+		User,
+	},
+	authorize::Action,
+	config::Config,
+	fallback::unauthenticated,
+	integration,
+	state::AppState,
+	tenant::Tenant,
+	todo::ssr::get_todo_with_permission,
+	visibility,
+};
+use axum::{
+	extract::{
+		ws::{Message, WebSocket, WebSocketUpgrade},
+		State,
+	},
+	http::{header, HeaderMap, StatusCode},
+	response::{
+		sse::{Event, KeepAlive, Sse},
+		Response,
+	},
+};
+use futures::{stream, Stream};
+use serde::{Deserialize, Serialize};
+use sqlx::{postgres::PgListener, types::Json, PgExecutor, PgPool};
+use std::{convert::Infallible, time::Duration};
+use tokio::sync::broadcast::{self, error::RecvError};
+
+/// Something that happened in the app, events only carry ids so subscribers look up what they are allowed to see
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum DomainEvent {
+	UserSignedUp {
+		tenant: i32,
+		user: i32,
+	},
+	UserLoggedIn {
+		tenant: i32,
+		user: i32,
+	},
+	UserLoggedOut {
+		tenant: i32,
+		user: i32,
+	},
+	LoginFailed {
+		tenant: i32,
+		username: String,
+	},
+	TodoCreated {
+		tenant: i32,
+		user: i32,
+		todo: i32,
+	},
+	TodoUpdated {
+		tenant: i32,
+		user: i32,
+		todo: i32,
+	},
+	TodoDeleted {
+		tenant: i32,
+		user: i32,
+		todo: i32,
+	},
+	/// `user` added `person` to the assignees of the todo
+	TodoAssigned {
+		tenant: i32,
+		user: i32,
+		todo: i32,
+		person: i32,
+	},
+	/// The todo is still open after it was due, recorded once per due date
+	TodoOverdue {
+		tenant: i32,
+		todo: i32,
+	},
+	/// An admin scheduled new permissions for `person`
+	PermissionChangeScheduled {
+		tenant: i32,
+		user: i32,
+		person: i32,
+		change: i32,
+	},
+	PermissionChangeCancelled {
+		tenant: i32,
+		user: i32,
+		person: i32,
+		change: i32,
+	},
+	/// The scheduler gave `person` the permissions `user` scheduled, `None` when that admin is gone by now
+	PermissionChangeApplied {
+		tenant: i32,
+		user: Option<i32>,
+		person: i32,
+		change: i32,
+	},
+	/// The stored permissions of `person` were replaced, `user` is `None` when the identity provider set them on login
+	PermissionsChanged {
+		tenant: i32,
+		user: Option<i32>,
+		person: i32,
+	},
+	/// An admin deactivated `person`, who can't log in until reactivated
+	UserDeactivated {
+		tenant: i32,
+		user: i32,
+		person: i32,
+	},
+	UserReactivated {
+		tenant: i32,
+		user: i32,
+		person: i32,
+	},
+	UsernameChanged {
+		tenant: i32,
+		user: i32,
+		from: String,
+		to: String,
+	},
+	/// The user confirmed a new address for login links
+	LoginEmailChanged {
+		tenant: i32,
+		user: i32,
+	},
+	/// An admin looked into a table with the database console, with the filters as they were typed
+	DatabaseQueried {
+		tenant: i32,
+		user: i32,
+		table: String,
+		filters: Vec<String>,
+		limit: i64,
+	},
+}
+
+impl DomainEvent {
+	pub fn tenant(&self) -> i32 {
+		match self {
+			DomainEvent::UserSignedUp { tenant, .. }
+			| DomainEvent::UserLoggedIn { tenant, .. }
+			| DomainEvent::UserLoggedOut { tenant, .. }
+			| DomainEvent::LoginFailed { tenant, .. }
+			| DomainEvent::TodoCreated { tenant, .. }
+			| DomainEvent::TodoUpdated { tenant, .. }
+			| DomainEvent::TodoDeleted { tenant, .. }
+			| DomainEvent::TodoAssigned { tenant, .. }
+			| DomainEvent::TodoOverdue { tenant, .. }
+			| DomainEvent::PermissionChangeScheduled { tenant, .. }
+			| DomainEvent::PermissionChangeCancelled { tenant, .. }
+			| DomainEvent::PermissionChangeApplied { tenant, .. }
+			| DomainEvent::PermissionsChanged { tenant, .. }
+			| DomainEvent::UserDeactivated { tenant, .. }
+			| DomainEvent::UserReactivated { tenant, .. }
+			| DomainEvent::UsernameChanged { tenant, .. }
+			| DomainEvent::LoginEmailChanged { tenant, .. }
+			| DomainEvent::DatabaseQueried { tenant, .. } => *tenant,
+		}
+	}
+}
+
+/// In-process pub/sub for domain events that made it through the outbox, used for live updates
+#[derive(Debug, Clone)]
+pub struct EventBus {
+	sender: broadcast::Sender<DomainEvent>,
+}
+
+impl Default for EventBus {
+	fn default() -> Self {
+		Self::new(1024)
+	}
+}
+
+impl EventBus {
+	pub fn new(capacity: usize) -> Self {
+		Self {
+			sender: broadcast::channel(capacity).0,
+		}
+	}
+
+	pub fn publish(&self, event: DomainEvent) {
+		// Sending only fails when nobody is listening which is fine
+		let _ = self.sender.send(event);
+	}
+
+	pub fn subscribe(&self) -> broadcast::Receiver<DomainEvent> {
+		self.sender.subscribe()
+	}
+}
+
+// A slow subscriber misses events rather than holding up everyone else
+pub(crate) async fn next_event(
+	receiver: &mut broadcast::Receiver<DomainEvent>,
+	subscriber: &str,
+) -> Option<DomainEvent> {
+	loop {
+		match receiver.recv().await {
+			Ok(event) => return Some(event),
+			Err(RecvError::Lagged(missed)) => log::warn!("{subscriber} missed {missed} events"),
+			Err(RecvError::Closed) => return None,
+		}
+	}
+}
+
+/// Write an event to the outbox, pass the transaction of the data change so the event is stored if and only if the
+/// change is
+pub async fn record<'c>(executor: impl PgExecutor<'c>, event: &DomainEvent) -> Result<(), sqlx::Error> {
+	// The notification is only sent once the transaction commits and wakes up the pollers right away
+	sqlx::query(
+		"WITH inserted AS (INSERT INTO outbox (event) VALUES ($1) RETURNING id)
+		SELECT pg_notify('outbox', id::text) FROM inserted",
+	)
+	.bind(Json(event))
+	.execute(executor)
+	.await?;
+
+	Ok(())
+}
+
+/// Outbox entries and webhook calls are given up on after this many failed attempts and stay in their table for
+/// inspection
+const MAX_ATTEMPTS: i32 = 10;
+const BATCH_SIZE: usize = 100;
+
+/// Delivers outbox entries to the audit log, queues their webhook calls and posts to chat rooms and applies them to the
+/// materialized visibility, then publishes them on the bus for live listeners
+///
+/// Delivery is at least once. Webhooks are called after the entries are let go of, each url on its own so one that is
+/// down doesn't get the others called again, and failed calls are retried with the time between them doubling.
+#[derive(Debug, Clone)]
+pub struct OutboxPoller {
+	pool: PgPool,
+	events: EventBus,
+	client: reqwest::Client,
+	webhook_urls: Vec<String>,
+	interval: Duration,
+}
+
+impl OutboxPoller {
+	/// Calls the `WEBHOOK_URLS` and polls every `OUTBOX_POLL_INTERVAL` seconds of the config
+	pub fn from_config(pool: PgPool, events: EventBus, config: &Config) -> Self {
+		Self {
+			pool,
+			events,
+			client: reqwest::Client::builder().timeout(Duration::from_secs(10)).build().expect("Unable to build HTTP client"),
+			webhook_urls: config.webhook_urls.clone(),
+			interval: Duration::from_secs(config.outbox_poll_interval),
+		}
+	}
+
+	pub fn spawn(self) {
+		tokio::spawn(async move {
+			let mut listener = match PgListener::connect_with(&self.pool).await {
+				Ok(mut listener) => match listener.listen("outbox").await {
+					Ok(_) => Some(listener),
+					Err(error) => {
+						log::error!("Outbox can't listen for notifications, falling back to polling: {error}");
+						None
+					},
+				},
+				Err(error) => {
+					log::error!("Outbox can't listen for notifications, falling back to polling: {error}");
+					None
+				},
+			};
+
+			loop {
+				let delivered = self.deliver_batch().await.unwrap_or_else(|error| {
+					log::error!("Outbox delivery failed: {error}");
+					0
+				});
+				let called = self.call_webhooks().await.unwrap_or_else(|error| {
+					log::error!("Calling webhooks failed: {error}");
+					0
+				});
+				// A full batch means there is probably more waiting
+				if delivered == BATCH_SIZE || called == BATCH_SIZE {
+					continue;
+				}
+
+				match &mut listener {
+					Some(listener) => {
+						let _ = tokio::time::timeout(self.interval, listener.recv()).await;
+					},
+					None => tokio::time::sleep(self.interval).await,
+				}
+			}
+		});
+	}
+
+	async fn deliver_batch(&self) -> Result<usize, sqlx::Error> {
+		let mut tx = self.pool.begin().await?;
+
+		// Locked rows are skipped so several replicas can poll the same outbox without delivering twice
+		let entries = sqlx::query_as::<_, (i64, Json<DomainEvent>)>(
+			"SELECT id, event FROM outbox WHERE delivered_at IS NULL AND attempts < $1
+			ORDER BY id LIMIT $2 FOR UPDATE SKIP LOCKED",
+		)
+		.bind(MAX_ATTEMPTS)
+		.bind(BATCH_SIZE as i64)
+		.fetch_all(&mut *tx)
+		.await?;
+		let count = entries.len();
+
+		let mut delivered = Vec::new();
+		for (id, Json(event)) in entries {
+			log::info!(target: "audit", "{}", serde_json::to_string(&event).unwrap_or_default());
+			// Retried entries find their calls queued already
+			sqlx::query(
+				"INSERT INTO webhook_calls (outbox, url) SELECT $1, url FROM unnest($2::text[]) AS url ON CONFLICT DO NOTHING",
+			)
+			.bind(id)
+			.bind(&self.webhook_urls)
+			.execute(&mut *tx)
+			.await?;
+			integration::ssr::queue_posts(&mut *tx, id, &event).await?;
+			visibility::apply(&mut tx, &event).await?;
+			sqlx::query("UPDATE outbox SET delivered_at = now(), attempts = attempts + 1 WHERE id = $1")
+				.bind(id)
+				.execute(&mut *tx)
+				.await?;
+			delivered.push(event);
+		}
+
+		tx.commit().await?;
+
+		for event in delivered {
+			self.events.publish(event);
+		}
+
+		Ok(count)
+	}
+
+	/// Calls are claimed by pushing their next attempt out before anything is sent, so no transaction is held while
+	/// waiting on a webhook and a replica that dies halfway only delays them
+	async fn call_webhooks(&self) -> Result<usize, sqlx::Error> {
+		let calls = sqlx::query_as::<_, (i64, String, Json<DomainEvent>)>(
+			"WITH claimed AS (
+				UPDATE webhook_calls
+				SET attempts = attempts + 1, next_attempt_at = now() + interval '30 seconds' * power(2, attempts)
+				WHERE id IN (
+					SELECT id FROM webhook_calls
+					WHERE delivered_at IS NULL AND attempts < $1 AND next_attempt_at <= now()
+					ORDER BY id LIMIT $2 FOR UPDATE SKIP LOCKED
+				)
+				RETURNING id, outbox, url
+			)
+			SELECT claimed.id, claimed.url, outbox.event FROM claimed JOIN outbox ON outbox.id = claimed.outbox
+			ORDER BY claimed.id",
+		)
+		.bind(MAX_ATTEMPTS)
+		.bind(BATCH_SIZE as i64)
+		.fetch_all(&self.pool)
+		.await?;
+		let count = calls.len();
+
+		for (id, url, Json(event)) in calls {
+			let result = self.client.post(&url).json(&event).send().await.and_then(|response| response.error_for_status());
+			match result {
+				Ok(_) => {
+					sqlx::query("UPDATE webhook_calls SET delivered_at = now() WHERE id = $1")
+						.bind(id)
+						.execute(&self.pool)
+						.await?;
+				},
+				Err(error) => log::error!("Webhook to {url} failed: {error}"),
+			}
+		}
+
+		Ok(count)
+	}
+}
+
+/// Whether a user may see an event live, todo events are filtered by the todo read permission
+async fn can_see(user: &User, event: &DomainEvent, pool: &PgPool) -> bool {
+	if event.tenant() != user.tenant {
+		return false;
+	}
+
+	match event {
+		DomainEvent::TodoCreated { todo, .. } | DomainEvent::TodoUpdated { todo, .. } => {
+			get_todo_with_permission(*todo, user.tenant, user, Action::Read, pool).await.is_some()
+		},
+		// The todo is gone so there is nothing left to check, the id alone doesn't give anything away
+		DomainEvent::TodoDeleted { .. } => true,
+		// Other tabs and devices of the user wipe what they show
+		DomainEvent::UserLoggedOut { user: id, .. } => *id == user.id,
+		_ => false,
+	}
+}
+
+/// Stream the events a logged in user is allowed to see over a WebSocket
+pub async fn events_ws(
+	ws: WebSocketUpgrade,
+	State(app_state): State<AppState>,
+	auth_session: AuthSession,
+	tenant: Tenant,
+	headers: HeaderMap,
+) -> Result<Response, (StatusCode, String)> {
+	// Browsers send the session cookie along with cross site WebSockets so we have to check where they come from
+	let host = headers.get(header::HOST).and_then(|host| host.to_str().ok());
+	let origin_host = headers
+		.get(header::ORIGIN)
+		.and_then(|origin| origin.to_str().ok())
+		.map(|origin| origin.split_once("://").map(|(_scheme, host)| host).unwrap_or(origin));
+	if origin_host.is_some() && origin_host != host {
+		return Err((StatusCode::FORBIDDEN, String::from("Cross origin WebSocket")));
+	}
+
+	let user = authenticated_user(&auth_session, &tenant, None, &app_state.config, &app_state.pool)
+		.await
+		.ok_or_else(unauthenticated)?;
+
+	Ok(ws.on_upgrade(move |socket| forward_events(socket, user, app_state)))
+}
+
+/// Stream the same events as [`events_ws`] as server-sent events, for networks that don't let WebSockets through
+///
+/// Unlike WebSockets, an `EventSource` of another site can't read the stream without CORS headers allowing it, which
+/// are never sent, so the origin doesn't need checking here.
+pub async fn events_sse(
+	State(app_state): State<AppState>,
+	auth_session: AuthSession,
+	tenant: Tenant,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, (StatusCode, String)> {
+	let user = authenticated_user(&auth_session, &tenant, None, &app_state.config, &app_state.pool)
+		.await
+		.ok_or_else(unauthenticated)?;
+	let receiver = app_state.events.subscribe();
+
+	let events = stream::unfold(Some((receiver, user, app_state)), |state| async move {
+		let (mut receiver, user, app_state) = state?;
+		loop {
+			let event = next_event(&mut receiver, "Server-sent events").await?;
+			if !can_see(&user, &event, &app_state.pool).await {
+				continue;
+			}
+
+			let Ok(data) = serde_json::to_string(&event) else {
+				continue;
+			};
+			// Like the WebSocket the stream ends with the session, clients that are still logged in reconnect
+			let next = (!matches!(event, DomainEvent::UserLoggedOut { .. })).then_some((receiver, user, app_state));
+			return Some((Ok(Event::default().data(data)), next));
+		}
+	});
+
+	// Proxies drop connections that are quiet for too long
+	Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+}
+
+async fn forward_events(mut socket: WebSocket, user: User, app_state: AppState) {
+	let mut receiver = app_state.events.subscribe();
+
+	loop {
+		tokio::select! {
+			event = next_event(&mut receiver, "WebSocket") => {
+				let Some(event) = event else { break };
+				if !can_see(&user, &event, &app_state.pool).await {
+					continue;
+				}
+
+				let Ok(message) = serde_json::to_string(&event) else { continue };
+				if socket.send(Message::Text(message)).await.is_err() {
+					break;
+				}
+				// The socket may belong to the session that just ended, clients that are still logged in reconnect
+				if matches!(event, DomainEvent::UserLoggedOut { .. }) {
+					break;
+				}
+			},
+			message = socket.recv() => {
+				// We don't expect anything from the client, we just need to notice when it goes away
+				match message {
+					Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+					Some(Ok(_)) => {},
+				}
+			},
+		}
+	}
+}
@@ -1,11 +1,20 @@
-use crate::{error_template::ErrorTemplate, errors::TodoAppError};
+use crate::{
+	error_template::ErrorTemplate,
+	errors::{AppServerError, ErrorCode, TodoAppError},
+	routes::Route,
+};
 use axum::{
 	body::Body,
 	extract::State,
-	http::{Request, Response, StatusCode, Uri},
-	response::{IntoResponse, Response as AxumResponse},
+	http::{header, HeaderMap, Method, Request, Response, StatusCode, Uri},
+	middleware::Next,
+	response::{Html, IntoResponse, Json, Response as AxumResponse},
+	BoxError,
+};
+use leptos::{
+	server_fn::error::{ServerFnError, ServerFnErrorSerde},
+	view, Errors, LeptosOptions,
 };
-use leptos::{view, Errors, LeptosOptions};
 use tower::ServiceExt;
 use tower_http::services::ServeDir;
 
@@ -30,6 +39,145 @@ pub async fn file_and_error_handler(
 	}
 }
 
+/// The answer to requests shed because too many are in flight, pages get the error template and API calls an error
+/// with [`ErrorCode::Unavailable`] their clients understand
+///
+/// It's rendered without the app around it, the whole point is to stay cheap while the server is busy.
+pub async fn overloaded(uri: Uri, error: BoxError) -> AxumResponse {
+	let status = TodoAppError::Overloaded.status_code();
+	if !error.is::<tower::load_shed::error::Overloaded>() {
+		log::error!("Unhandled error in the request middleware: {error}");
+		return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+	}
+
+	if uri.path().starts_with("/api/") {
+		let error = ServerFnError::WrappedServerError(AppServerError::new(
+			ErrorCode::Unavailable,
+			TodoAppError::Overloaded.to_string(),
+		));
+		return (status, error.ser().unwrap_or_default()).into_response();
+	}
+
+	let mut errors = Errors::default();
+	errors.insert_with_default_key(TodoAppError::Overloaded);
+	let page = leptos::ssr::render_to_string(move || view! { <ErrorTemplate outside_errors=errors.clone() /> });
+
+	(status, Html(page.to_string())).into_response()
+}
+
+/// Pages that only make sense with a session, rendering them without one sends the browser to the login instead
+///
+/// The first segment of each route counts, so every page under `settings` or `admin` is covered.
+const LOGIN_REQUIRED: [&str; 4] = [Route::SETTINGS, Route::ADMIN_USERS, Route::TODO_DETAIL, Route::PRINT];
+
+pub fn requires_login(path: &str) -> bool {
+	let Some(section) = path.strip_prefix('/').and_then(|path| path.split('/').next()) else {
+		return false;
+	};
+
+	LOGIN_REQUIRED.iter().any(|route| route.split('/').next() == Some(section))
+}
+
+/// The login page, coming back to `next` once logged in
+pub fn login_path(next: &str) -> String {
+	let mut encoded = String::new();
+	for byte in next.bytes() {
+		match byte {
+			b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => encoded.push(byte as char),
+			_ => encoded.push_str(&format!("%{byte:02X}")),
+		}
+	}
+
+	format!("{}?next={encoded}", Route::Login)
+}
+
+/// Where to go after logging in, only paths of this app so the login can't be used to send people elsewhere
+///
+/// Browsers drop tabs and newlines from urls and read `\` like `/`, so `/\t/evil.example` would leave the app too.
+pub fn next_path(next: Option<&str>) -> &str {
+	match next {
+		Some(next)
+			if next.starts_with('/')
+				&& !next.starts_with("//")
+				&& !next.chars().any(|c| c == '\\' || c.is_control() || c.is_whitespace())
+				&& next.parse::<Uri>().is_ok_and(|uri| uri.scheme().is_none() && uri.authority().is_none()) =>
+		{
+			next
+		},
+		_ => "/",
+	}
+}
+
+/// What handlers answer requests without a session with, [`unauthorized_responses`] turns it into what the caller
+/// understands
+pub fn unauthenticated() -> (StatusCode, String) {
+	(StatusCode::UNAUTHORIZED, AppServerError::unauthenticated().to_string())
+}
+
+fn is_navigation(method: &Method, headers: &HeaderMap) -> bool {
+	let value = |name: &str| headers.get(name).and_then(|value| value.to_str().ok());
+
+	method == Method::GET
+		&& match value("sec-fetch-mode") {
+			Some(mode) => mode == "navigate",
+			// Browsers without fetch metadata still ask for HTML when they navigate
+			None => value(header::ACCEPT.as_str()).is_some_and(|accept| accept.contains("text/html")),
+		}
+}
+
+/// The one way a missing session is answered: browsers navigating to a page are sent to the login and come back
+/// afterwards, server function calls keep the error their clients decode, everything else gets it as a JSON
+/// [`AppServerError`]
+///
+/// Every 401 passes through here, so handlers only have to answer with [`unauthenticated`] or say what was wrong.
+pub async fn unauthorized_responses(request: Request<Body>, next: Next) -> AxumResponse {
+	let method = request.method().clone();
+	let headers = request.headers().clone();
+	let uri = request.uri().clone();
+
+	let response = next.run(request).await;
+	if response.status() != StatusCode::UNAUTHORIZED {
+		return response;
+	}
+
+	let path = uri.path();
+	if is_navigation(&method, &headers) && !path.starts_with("/api/") {
+		let next = uri.path_and_query().map(|path| path.as_str()).unwrap_or(path);
+		return (StatusCode::FOUND, [(header::LOCATION, login_path(next))]).into_response();
+	}
+	if path.starts_with("/api/") && !path.starts_with("/api/v1/") {
+		return response;
+	}
+
+	// Handlers say why in a string, a coded error is what clients can branch on
+	let message = match axum::body::to_bytes(response.into_body(), usize::MAX).await {
+		Ok(body) => String::from_utf8_lossy(&body).into_owned(),
+		Err(_) => String::new(),
+	};
+	let error =
+		message.parse::<AppServerError>().unwrap_or_else(|_| AppServerError::new(ErrorCode::Unauthenticated, message));
+
+	(StatusCode::UNAUTHORIZED, Json(error)).into_response()
+}
+
+/// Server function errors go out as a 500 whatever went wrong, this gives them the status of their [`ErrorCode`]
+pub async fn with_error_status(response: AxumResponse) -> AxumResponse {
+	if response.status() != StatusCode::INTERNAL_SERVER_ERROR {
+		return response;
+	}
+
+	let (mut parts, body) = response.into_parts();
+	let Ok(body) = axum::body::to_bytes(body, usize::MAX).await else {
+		return AxumResponse::from_parts(parts, Body::empty());
+	};
+	if let ServerFnError::WrappedServerError(error) = ServerFnError::<AppServerError>::de(&String::from_utf8_lossy(&body))
+	{
+		parts.status = error.code.status_code();
+	}
+
+	AxumResponse::from_parts(parts, Body::from(body))
+}
+
 async fn get_static_file(uri: Uri, root: &str) -> Result<Response<Body>, (StatusCode, String)> {
 	let req = Request::builder().uri(uri.clone()).body(Body::empty()).unwrap();
 	// `ServeDir` implements `tower::Service` so we can call it with `tower::ServiceExt::oneshot`
@@ -39,3 +187,31 @@ async fn get_static_file(uri: Uri, root: &str) -> Result<Response<Body>, (Status
 		Err(err) => Err((StatusCode::INTERNAL_SERVER_ERROR, format!("Something went wrong: {err}"))),
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn login_redirects_test() {
+		assert!(requires_login("/settings"));
+		assert!(requires_login("/settings/permissions"));
+		assert!(requires_login("/todo/3"));
+		assert!(!requires_login("/"));
+		assert!(!requires_login("/login"));
+		assert!(!requires_login("/settingsx"));
+		assert!(requires_login("/admin/flags"));
+		assert!(requires_login("/print"));
+		assert!(!requires_login("/share/abc"));
+
+		assert_eq!(login_path("/todo/3?tab=history&x=1"), "/login?next=/todo/3%3Ftab%3Dhistory%26x%3D1");
+		assert_eq!(next_path(Some("/todo/3?tab=history")), "/todo/3?tab=history");
+		assert_eq!(next_path(Some("//evil.example")), "/");
+		assert_eq!(next_path(Some("https://evil.example")), "/");
+		assert_eq!(next_path(Some("/\t/evil.example")), "/");
+		assert_eq!(next_path(Some("/\n/evil.example")), "/");
+		assert_eq!(next_path(Some("/ /evil.example")), "/");
+		assert_eq!(next_path(Some("/\\evil.example")), "/");
+		assert_eq!(next_path(None), "/");
+	}
+}
@@ -0,0 +1,294 @@
+use crate::{
+	auth::{ConfirmPassword, User},
+	errors::{AppError, AppServerError},
+};
+use leptos::*;
+use leptos_router::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "ssr", derive(sqlx::FromRow, utoipa::ToSchema))]
+pub struct FeatureFlag {
+	pub name: String,
+	pub description: String,
+	pub enabled: bool,
+	/// Percentage of users the flag is on for, from 0 to 100
+	pub rollout: i16,
+}
+
+impl FeatureFlag {
+	/// Logged out users only see flags rolled out to everyone, logged in users keep their bucket across requests
+	pub fn is_enabled_for(&self, user: Option<&User>) -> bool {
+		if !self.enabled {
+			return false;
+		}
+		if self.rollout >= 100 {
+			return true;
+		}
+
+		user.is_some_and(|user| bucket(&self.name, user.id) < self.rollout.max(0) as u32)
+	}
+}
+
+// FNV-1a so the server and the browser put a user into the same bucket without sharing a hash implementation,
+// the flag name is part of the hash so the same users aren't always the first to get every rollout
+fn bucket(flag: &str, user: i32) -> u32 {
+	let hash = flag
+		.bytes()
+		.chain(user.to_le_bytes())
+		.fold(0x811c9dc5_u32, |hash, byte| (hash ^ byte as u32).wrapping_mul(0x01000193));
+
+	hash % 100
+}
+
+/// The feature flags of a tenant, on the server they are in the context of every request and in the browser they
+/// come with the initial state
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "ssr", derive(utoipa::ToSchema))]
+pub struct FeatureFlags(pub Vec<FeatureFlag>);
+
+impl FeatureFlags {
+	/// Unknown flags are off
+	pub fn is_enabled(&self, flag: &str, user: Option<&User>) -> bool {
+		self.0.iter().find(|feature| feature.name == flag).is_some_and(|feature| feature.is_enabled_for(user))
+	}
+}
+
+#[cfg(feature = "ssr")]
+pub mod ssr {
+	use super::{FeatureFlag, FeatureFlags};
+	use crate::auth::User;
+	use leptos::use_context;
+	use sqlx::PgPool;
+
+	impl FeatureFlags {
+		pub async fn get_for_tenant(tenant: i32, pool: &PgPool) -> Result<Self, sqlx::Error> {
+			Ok(FeatureFlags(
+				sqlx::query_as::<_, FeatureFlag>(
+					"SELECT name, description, enabled, rollout FROM feature_flags WHERE tenant = $1 ORDER BY name",
+				)
+				.bind(tenant)
+				.fetch_all(pool)
+				.await?,
+			))
+		}
+	}
+
+	/// Check a flag from inside a server function
+	pub fn is_enabled(flag: &str, user: Option<&User>) -> bool {
+		use_context::<FeatureFlags>().is_some_and(|flags| flags.is_enabled(flag, user))
+	}
+}
+
+/// The flags of the current tenant, evaluate them with [`FeatureFlags::is_enabled`]
+#[server(input = GetUrl)]
+pub async fn get_feature_flags() -> Result<FeatureFlags, ServerFnError<AppServerError>> {
+	crate::cache::cache_privately::<GetFeatureFlags>(0);
+
+	Ok(use_context::<FeatureFlags>().unwrap_or_default())
+}
+
+/// Create or change a flag, the fields come from a form so any `enabled` value means on
+#[server]
+pub async fn set_feature_flag(
+	name: String,
+	description: String,
+	enabled: Option<String>,
+	rollout: i16,
+) -> Result<(), ServerFnError<AppServerError>> {
+	use crate::{
+		auth::{
+			get_user,
+			ssr::{require_recent_auth, STEP_UP_MAX_AGE},
+		},
+		denial::{ssr::record_denial, DeniedAction},
+		tenant::Tenant,
+	};
+	use sqlx::PgPool;
+
+	let pool = use_context::<PgPool>().expect("Database not initialized");
+	let tenant = use_context::<Tenant>().expect("No tenant found");
+	let user = get_user().await?.ok_or_else(AppServerError::unauthenticated)?;
+
+	if !user.is_admin() {
+		record_denial("set_feature_flag", DeniedAction::Admin, &user);
+		return Err(AppServerError::forbidden("Missing permission to manage feature flags").into());
+	}
+	require_recent_auth(STEP_UP_MAX_AGE)?;
+	let name = name.trim();
+	if name.is_empty() {
+		return Err(AppServerError::invalid("A feature flag needs a name").into());
+	}
+	if !(0..=100).contains(&rollout) {
+		return Err(AppServerError::invalid("The rollout has to be between 0 and 100 percent").into());
+	}
+
+	sqlx::query(
+		"INSERT INTO feature_flags (tenant, name, description, enabled, rollout) VALUES ($1, $2, $3, $4, $5)
+		ON CONFLICT (tenant, name) DO UPDATE SET
+			description = EXCLUDED.description, enabled = EXCLUDED.enabled, rollout = EXCLUDED.rollout",
+	)
+	.bind(tenant.id)
+	.bind(name)
+	.bind(description.trim())
+	.bind(enabled.is_some())
+	.bind(rollout)
+	.execute(&pool)
+	.await
+	.map_err(AppServerError::from)?;
+
+	Ok(())
+}
+
+#[server]
+pub async fn delete_feature_flag(name: String) -> Result<(), ServerFnError<AppServerError>> {
+	use crate::{
+		auth::{
+			get_user,
+			ssr::{require_recent_auth, STEP_UP_MAX_AGE},
+		},
+		denial::{ssr::record_denial, DeniedAction},
+		tenant::Tenant,
+	};
+	use sqlx::PgPool;
+
+	let pool = use_context::<PgPool>().expect("Database not initialized");
+	let tenant = use_context::<Tenant>().expect("No tenant found");
+	let user = get_user().await?.ok_or_else(AppServerError::unauthenticated)?;
+
+	if !user.is_admin() {
+		record_denial("delete_feature_flag", DeniedAction::Admin, &user);
+		return Err(AppServerError::forbidden("Missing permission to manage feature flags").into());
+	}
+	require_recent_auth(STEP_UP_MAX_AGE)?;
+
+	sqlx::query("DELETE FROM feature_flags WHERE tenant = $1 AND name = $2")
+		.bind(tenant.id)
+		.bind(name)
+		.execute(&pool)
+		.await
+		.map_err(AppServerError::from)?;
+
+	Ok(())
+}
+
+/// Load the flags with the initial state and share them with every component, call once at the root of the app
+pub fn provide_feature_flags() {
+	let flags = create_blocking_resource(|| (), |_| get_feature_flags());
+	provide_context(flags);
+}
+
+/// The flags of the current tenant, empty until they are loaded so every flag starts off
+pub fn use_feature_flags() -> Signal<FeatureFlags> {
+	let flags = use_context::<Resource<(), Result<FeatureFlags, AppError>>>();
+
+	Signal::derive(move || flags.and_then(|flags| flags.get()).and_then(Result::ok).unwrap_or_default())
+}
+
+#[component]
+pub fn FeatureFlagAdmin() -> impl IntoView {
+	let save = create_server_action::<SetFeatureFlag>();
+	let delete = create_server_action::<DeleteFeatureFlag>();
+	let flags = create_resource(move || (save.version().get(), delete.version().get()), move |_| get_feature_flags());
+	let error = Signal::derive(move || save.value().get().or_else(|| delete.value().get()).and_then(Result::err));
+
+	let flag_form = move |flag: FeatureFlag, new: bool| {
+		view! {
+			<ActionForm action=save>
+				<input type="text" name="name" value=flag.name.clone() readonly=!new required=true placeholder="name" />
+				<input type="text" name="description" value=flag.description placeholder="description" />
+				<label>
+					<input type="checkbox" name="enabled" checked=flag.enabled />
+					"On"
+				</label>
+				<label>
+					<input type="number" name="rollout" min=0 max=100 value=flag.rollout />
+					"%"
+				</label>
+				<input type="submit" value=if new { "Add" } else { "Save" } />
+			</ActionForm>
+			<Show when=move || !new>
+				<ActionForm action=delete>
+					<input type="hidden" name="name" value=flag.name.clone() />
+					<input type="submit" value="X" />
+				</ActionForm>
+			</Show>
+		}
+	};
+
+	view! {
+		<h1>"Feature flags"</h1>
+		<Transition fallback=move || view! { <p>"Loading..."</p> }>
+			{move || {
+				flags
+					.get()
+					.map(|flags| match flags {
+						Err(e) => view! { <span class="error">{e.to_string()}</span> }.into_view(),
+						Ok(flags) => {
+							flags
+								.0
+								.into_iter()
+								.map(|flag| view! { <div class="flag">{flag_form(flag, false)}</div> })
+								.collect_view()
+						}
+					})
+			}}
+
+		</Transition>
+		<div class="flag">
+			{flag_form(
+				FeatureFlag {
+					name: String::new(),
+					description: String::new(),
+					enabled: false,
+					rollout: 100,
+				},
+				true,
+			)}
+
+		</div>
+		{move || error.get().map(|e| view! { <span class="error">{e.to_string()}</span> })}
+		<ConfirmPassword error=error />
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn flag(enabled: bool, rollout: i16) -> FeatureFlag {
+		FeatureFlag {
+			name: String::from("new_dashboard"),
+			description: String::new(),
+			enabled,
+			rollout,
+		}
+	}
+
+	#[test]
+	fn rollout_test() {
+		let users = (1..=1000).map(|id| User { id, ..User::default() }).collect::<Vec<_>>();
+		let enabled = |flag: &FeatureFlag| users.iter().filter(|user| flag.is_enabled_for(Some(user))).count();
+
+		assert_eq!(enabled(&flag(true, 100)), 1000);
+		assert_eq!(enabled(&flag(true, 0)), 0);
+		assert_eq!(enabled(&flag(false, 100)), 0);
+		assert!((400..600).contains(&enabled(&flag(true, 50))));
+
+		// Raising the rollout only ever adds users
+		assert!(users
+			.iter()
+			.all(|user| !flag(true, 20).is_enabled_for(Some(user)) || flag(true, 30).is_enabled_for(Some(user))));
+
+		assert!(flag(true, 100).is_enabled_for(None));
+		assert!(!flag(true, 99).is_enabled_for(None));
+	}
+
+	#[test]
+	fn unknown_flag_test() {
+		let flags = FeatureFlags(vec![flag(true, 100)]);
+
+		assert!(flags.is_enabled("new_dashboard", None));
+		assert!(!flags.is_enabled("old_dashboard", None));
+	}
+}
@@ -0,0 +1,291 @@
+use crate::{
+	auth::{PublicUser, User, UserSQL},
+	errors::AppServerError,
+	permission::{Permission, Permissions, Scope},
+	tenant::Tenant,
+	todo::{ssr::list_todos, Todo},
+};
+use async_graphql::{Context, EmptyMutation, EmptySubscription, ErrorExtensions, Object, Schema, SimpleObject};
+use chrono::prelude::*;
+use sqlx::PgPool;
+use std::sync::OnceLock;
+
+pub type ApiSchema = Schema<Query, EmptyMutation, EmptySubscription>;
+
+static SCHEMA: OnceLock<ApiSchema> = OnceLock::new();
+
+pub fn schema() -> &'static ApiSchema {
+	// Keep clients from sending queries that are expensive to resolve
+	SCHEMA.get_or_init(|| {
+		Schema::build(Query, EmptyMutation, EmptySubscription).limit_depth(8).limit_complexity(256).finish()
+	})
+}
+
+/// What every resolver gets to see of the request
+pub struct RequestContext {
+	pub user: Option<User>,
+	pub tenant: Tenant,
+	pub pool: PgPool,
+}
+
+impl RequestContext {
+	fn user(&self) -> async_graphql::Result<&User> {
+		self.user.as_ref().ok_or_else(|| {
+			let error = AppServerError::unauthenticated();
+			// The same code server functions fail with, GraphQL answers with 200 so clients look for it in the error
+			async_graphql::Error::new(error.message).extend_with(|_, extensions| extensions.set("code", error.code.as_str()))
+		})
+	}
+}
+
+#[derive(SimpleObject)]
+pub struct PermissionSet {
+	read: Vec<String>,
+	write: Vec<String>,
+	create: bool,
+}
+
+fn scopes(permission: &Permission) -> Vec<String> {
+	match permission {
+		Permission::ReadAny | Permission::WriteAny => vec![String::from("*")],
+		Permission::Read(scopes) | Permission::Write(scopes) => scopes
+			.iter()
+			.map(|scope| match scope {
+				Scope::Equipment(id) => format!("equipment[{id}]"),
+				Scope::Person(id) => format!("person[{id}]"),
+				Scope::Project(id) => format!("project[{id}]"),
+				Scope::Any => String::from("*"),
+			})
+			.collect(),
+		Permission::Create(_) => Vec::new(),
+	}
+}
+
+impl From<&Permissions> for PermissionSet {
+	fn from(permissions: &Permissions) -> Self {
+		let Permissions::ReadWrite { read, write, create } = permissions;
+
+		Self {
+			read: scopes(read),
+			write: scopes(write),
+			create: matches!(create, Permission::Create(true)),
+		}
+	}
+}
+
+#[derive(SimpleObject)]
+pub struct UserPermissions {
+	equipment: PermissionSet,
+	user: PermissionSet,
+	todo: PermissionSet,
+}
+
+#[derive(SimpleObject)]
+#[graphql(name = "User")]
+pub struct UserObject {
+	id: i32,
+	username: String,
+	avatar: Option<String>,
+	permissions: UserPermissions,
+}
+
+impl From<User> for UserObject {
+	fn from(user: User) -> Self {
+		Self {
+			permissions: UserPermissions {
+				equipment: user.permission_equipment().into(),
+				user: user.permission_user().into(),
+				todo: user.permission_todo().into(),
+			},
+			id: user.id,
+			username: user.username,
+			avatar: user.avatar,
+		}
+	}
+}
+
+impl TryFrom<UserSQL> for UserObject {
+	type Error = &'static str;
+
+	/// Parses the permissions up front so a user stored with invalid ones can be left out instead of panicking
+	fn try_from(user: UserSQL) -> Result<Self, Self::Error> {
+		Ok(Self {
+			permissions: UserPermissions {
+				equipment: (&Permission::parse(user.permission_equipment)?).into(),
+				user: (&Permission::parse(user.permission_user)?).into(),
+				todo: (&Permission::parse(user.permission_todo)?).into(),
+			},
+			id: user.id,
+			username: user.username,
+			avatar: user.avatar,
+		})
+	}
+}
+
+/// Other users, without their permissions
+#[derive(SimpleObject)]
+#[graphql(name = "PublicUser")]
+pub struct PublicUserObject {
+	id: i32,
+	username: String,
+	avatar: Option<String>,
+}
+
+impl From<PublicUser> for PublicUserObject {
+	fn from(user: PublicUser) -> Self {
+		Self {
+			id: user.id,
+			username: user.username,
+			avatar: user.avatar,
+		}
+	}
+}
+
+#[derive(SimpleObject)]
+#[graphql(name = "Todo")]
+pub struct TodoObject {
+	id: i32,
+	title: String,
+	completed: bool,
+	created_at: DateTime<Utc>,
+	owner: Option<PublicUserObject>,
+}
+
+impl From<Todo> for TodoObject {
+	fn from(todo: Todo) -> Self {
+		Self {
+			id: todo.id,
+			title: todo.title,
+			completed: todo.completed,
+			created_at: todo.created_at,
+			owner: todo.user.map(PublicUserObject::from),
+		}
+	}
+}
+
+pub struct Query;
+
+#[Object]
+impl Query {
+	/// The logged in user
+	async fn me(&self, ctx: &Context<'_>) -> Option<UserObject> {
+		ctx.data_unchecked::<RequestContext>().user.clone().map(UserObject::from)
+	}
+
+	/// Todos the logged in user is allowed to read
+	async fn todos(&self, ctx: &Context<'_>) -> async_graphql::Result<Vec<TodoObject>> {
+		let request = ctx.data_unchecked::<RequestContext>();
+		let todos = list_todos(request.user()?, request.tenant.id, &request.pool).await?;
+
+		Ok(todos.into_iter().map(TodoObject::from).collect())
+	}
+
+	/// Users of the organization the logged in user is allowed to read, that always includes themselves
+	async fn users(&self, ctx: &Context<'_>) -> async_graphql::Result<Vec<UserObject>> {
+		let request = ctx.data_unchecked::<RequestContext>();
+		let viewer = request.user()?;
+		let Permissions::ReadWrite { read, .. } = viewer.permission_user();
+
+		// Service accounts are left out, only their own token lists them
+		let users = sqlx::query_as::<_, UserSQL>(
+			"SELECT * FROM users WHERE tenant = $1 AND (NOT service_account OR id = $2) ORDER BY id",
+		)
+		.bind(request.tenant.id)
+		.bind(viewer.id)
+		.fetch_all(&request.pool)
+		.await?
+		.into_iter()
+		.filter(|user| match read {
+			Permission::ReadAny | Permission::WriteAny => true,
+			Permission::Read(scopes) | Permission::Write(scopes) => {
+				user.id == viewer.id || scopes.contains(&Scope::Person(user.id)) || scopes.contains(&Scope::Any)
+			},
+			Permission::Create(_) => user.id == viewer.id,
+		})
+		.filter_map(|user| {
+			let id = user.id;
+			match UserObject::try_from(user) {
+				Ok(user) => Some(user),
+				Err(error) => {
+					log::warn!("Leaving user {id} out of the users query: {error}");
+					None
+				},
+			}
+		})
+		.collect();
+
+		Ok(users)
+	}
+}
+
+pub mod ssr {
+	use super::{schema, RequestContext};
+	use crate::{
+		auth::ssr::{authenticated_user, AuthSession},
+		jwt::ssr::authenticate_bearer,
+		state::AppState,
+		tenant::Tenant,
+	};
+	use async_graphql::http::GraphiQLSource;
+	use async_graphql_axum::{GraphQLRequest, GraphQLResponse};
+	use axum::{
+		extract::State,
+		http::{HeaderMap, StatusCode},
+		response::Html,
+	};
+
+	pub async fn graphql_handler(
+		State(app_state): State<AppState>,
+		mut auth_session: AuthSession,
+		tenant: Tenant,
+		headers: HeaderMap,
+		request: GraphQLRequest,
+	) -> Result<GraphQLResponse, (StatusCode, String)> {
+		// Same as the server functions, API clients can send a JWT instead of the session cookie
+		let claims = match authenticate_bearer(&headers, &tenant, &app_state).await? {
+			Some((claims, user)) => {
+				auth_session.current_user = Some(user);
+				Some(claims)
+			},
+			None => None,
+		};
+
+		let context = RequestContext {
+			user: authenticated_user(&auth_session, &tenant, claims.as_ref(), &app_state.config, &app_state.pool).await,
+			tenant,
+			pool: app_state.pool.clone(),
+		};
+
+		Ok(schema().execute(request.into_inner().data(context)).await.into())
+	}
+
+	pub async fn graphiql() -> Html<String> {
+		Html(GraphiQLSource::build().endpoint("/graphql").finish())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn user_sql(permission_todo: &str) -> UserSQL {
+		UserSQL {
+			id: 2,
+			tenant: 1,
+			username: String::from("someone"),
+			password: String::new(),
+			avatar: None,
+			permission_equipment: String::from("READ(*)|WRITE(*)|CREATE(true)"),
+			permission_user: String::from("READ(*)|WRITE(*)|CREATE(true)"),
+			permission_todo: String::from(permission_todo),
+			active: true,
+			service_account: false,
+		}
+	}
+
+	#[test]
+	fn users_with_invalid_permissions_are_refused() {
+		assert!(UserObject::try_from(user_sql("READ(*)|WRITE(*)|CREATE(false)")).is_ok());
+		assert!(UserObject::try_from(user_sql("READ(person[x])|WRITE(*)|CREATE(false)")).is_err());
+	}
+}
@@ -0,0 +1,288 @@
+use crate::{
+	auth::PublicUser,
+	avatar::{Avatar, AvatarSize},
+	errors::AppServerError,
+	todo::Priority,
+};
+use chrono::prelude::*;
+use leptos::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "ssr", derive(utoipa::ToSchema))]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum TodoChange {
+	Created {
+		title: String,
+	},
+	Renamed {
+		from: String,
+		to: String,
+	},
+	Completed,
+	Reopened,
+	/// Descriptions can be long, the history only keeps that it changed
+	Described,
+	Reassigned {
+		from: Option<PublicUser>,
+		to: Option<PublicUser>,
+	},
+	Prioritized {
+		from: Priority,
+		to: Priority,
+	},
+	Archived,
+	Restored,
+	/// Assignees that were deleted since are left out
+	Assigned {
+		from: Vec<PublicUser>,
+		to: Vec<PublicUser>,
+	},
+	/// The names of the projects, `None` outside of any project or for a project deleted since
+	Moved {
+		from: Option<String>,
+		to: Option<String>,
+	},
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "ssr", derive(utoipa::ToSchema))]
+pub struct TodoHistoryEntry {
+	pub id: i32,
+	/// Who made the change
+	pub user: Option<PublicUser>,
+	pub change: TodoChange,
+	pub created_at: DateTime<Utc>,
+}
+
+#[cfg(feature = "ssr")]
+pub mod ssr {
+	use super::{TodoChange, TodoHistoryEntry};
+	use crate::{auth::PublicUser, todo::Priority};
+	use chrono::prelude::*;
+	use futures::future::join_all;
+	use sqlx::{PgConnection, PgPool};
+
+	pub const CREATED: &str = "created";
+	pub const TITLE: &str = "title";
+	pub const DESCRIPTION: &str = "description";
+	pub const COMPLETED: &str = "completed";
+	pub const PERSON: &str = "person";
+	pub const PRIORITY: &str = "priority";
+	pub const ARCHIVED: &str = "archived";
+	/// The values are the ids of the assignees, separated by commas
+	pub const ASSIGNEES: &str = "assignees";
+	pub const PROJECT: &str = "project";
+
+	/// Store a change of a todo, run it in the transaction of the change so the history can't miss any
+	pub async fn record_change(
+		connection: &mut PgConnection,
+		todo: i32,
+		user: i32,
+		kind: &str,
+		old_value: Option<String>,
+		new_value: Option<String>,
+	) -> Result<(), sqlx::Error> {
+		sqlx::query("INSERT INTO todo_events (todo, person, kind, old_value, new_value) VALUES ($1, $2, $3, $4, $5)")
+			.bind(todo)
+			.bind(user)
+			.bind(kind)
+			.bind(old_value)
+			.bind(new_value)
+			.execute(connection)
+			.await?;
+
+		Ok(())
+	}
+
+	#[derive(sqlx::FromRow, Clone)]
+	pub struct SqlTodoEvent {
+		id: i32,
+		person: i32,
+		kind: String,
+		old_value: Option<String>,
+		new_value: Option<String>,
+		created_at: DateTime<Utc>,
+	}
+
+	async fn user_from_value(value: Option<String>, pool: &PgPool) -> Option<PublicUser> {
+		PublicUser::get_from_id(value?.parse().ok()?, pool).await
+	}
+
+	async fn users_from_value(value: Option<String>, pool: &PgPool) -> Vec<PublicUser> {
+		let value = value.unwrap_or_default();
+		let ids = value.split(',').filter_map(|id| id.parse().ok());
+
+		join_all(ids.map(|id| PublicUser::get_from_id(id, pool))).await.into_iter().flatten().collect()
+	}
+
+	async fn project_from_value(value: Option<String>, pool: &PgPool) -> Option<String> {
+		sqlx::query_scalar::<_, String>("SELECT name FROM projects WHERE id = $1")
+			.bind(value?.parse::<i32>().ok()?)
+			.fetch_optional(pool)
+			.await
+			.ok()?
+	}
+
+	impl SqlTodoEvent {
+		pub async fn into_entry(self, pool: &PgPool) -> Option<TodoHistoryEntry> {
+			let change = match self.kind.as_str() {
+				CREATED => TodoChange::Created {
+					title: self.new_value.unwrap_or_default(),
+				},
+				TITLE => TodoChange::Renamed {
+					from: self.old_value.unwrap_or_default(),
+					to: self.new_value.unwrap_or_default(),
+				},
+				COMPLETED if self.new_value.as_deref() == Some("true") => TodoChange::Completed,
+				COMPLETED => TodoChange::Reopened,
+				DESCRIPTION => TodoChange::Described,
+				PERSON => TodoChange::Reassigned {
+					from: user_from_value(self.old_value, pool).await,
+					to: user_from_value(self.new_value, pool).await,
+				},
+				PRIORITY => {
+					let priority = |value: Option<String>| Some(Priority::from(value?.parse::<i16>().ok()?));
+					TodoChange::Prioritized {
+						from: priority(self.old_value).unwrap_or_default(),
+						to: priority(self.new_value).unwrap_or_default(),
+					}
+				},
+				ARCHIVED if self.new_value.as_deref() == Some("true") => TodoChange::Archived,
+				ARCHIVED => TodoChange::Restored,
+				ASSIGNEES => TodoChange::Assigned {
+					from: users_from_value(self.old_value, pool).await,
+					to: users_from_value(self.new_value, pool).await,
+				},
+				PROJECT => TodoChange::Moved {
+					from: project_from_value(self.old_value, pool).await,
+					to: project_from_value(self.new_value, pool).await,
+				},
+				_ => return None,
+			};
+
+			Some(TodoHistoryEntry {
+				id: self.id,
+				user: PublicUser::get_from_id(self.person, pool).await,
+				change,
+				created_at: self.created_at,
+			})
+		}
+	}
+}
+
+/// Everything that happened to a todo, oldest first, for users who can read the todo
+#[server(input = GetUrl)]
+pub async fn get_todo_history(id: i32) -> Result<Vec<TodoHistoryEntry>, ServerFnError<AppServerError>> {
+	use self::ssr::SqlTodoEvent;
+	use crate::{
+		auth::get_user, authorize::Action, cache::cache_privately, tenant::Tenant, todo::ssr::get_todo_with_permission,
+	};
+	use futures::future::join_all;
+	use sqlx::PgPool;
+
+	cache_privately::<GetTodoHistory>(0);
+
+	let pool = use_context::<PgPool>().expect("Database not initialized");
+	let tenant = use_context::<Tenant>().expect("No tenant found");
+	let user = get_user().await?.ok_or_else(AppServerError::unauthenticated)?;
+
+	if get_todo_with_permission(id, tenant.id, &user, Action::Read, &pool).await.is_none() {
+		return Err(AppServerError::not_found("Todo not found").into());
+	}
+
+	let events = sqlx::query_as::<_, SqlTodoEvent>("SELECT * FROM todo_events WHERE todo = $1 ORDER BY id")
+		.bind(id)
+		.fetch_all(&pool)
+		.await
+		.map_err(AppServerError::from)?;
+
+	Ok(join_all(events.into_iter().map(|event| event.into_entry(&pool))).await.into_iter().flatten().collect())
+}
+
+fn describe(change: TodoChange) -> String {
+	let username =
+		|user: Option<PublicUser>| user.map(|user| user.username).unwrap_or_else(|| String::from("a deleted user"));
+
+	match change {
+		TodoChange::Created { title } => format!("created \"{title}\""),
+		TodoChange::Renamed { from, to } => format!("renamed \"{from}\" to \"{to}\""),
+		TodoChange::Completed => String::from("marked it as done"),
+		TodoChange::Reopened => String::from("reopened it"),
+		TodoChange::Described => String::from("changed the description"),
+		TodoChange::Reassigned { from, to } => format!("reassigned it from {} to {}", username(from), username(to)),
+		TodoChange::Prioritized { from, to } => {
+			format!("changed the priority from {} to {}", from.as_str(), to.as_str())
+		},
+		TodoChange::Archived => String::from("archived it"),
+		TodoChange::Restored => String::from("restored it from the archive"),
+		TodoChange::Assigned { to, .. } if to.is_empty() => String::from("took everyone off it"),
+		TodoChange::Assigned { to, .. } => {
+			format!("assigned it to {}", to.into_iter().map(|user| user.username).collect::<Vec<_>>().join(", "))
+		},
+		TodoChange::Moved { from, to } => {
+			let project = |project: Option<String>| project.unwrap_or_else(|| String::from("no project"));
+			format!("moved it from {} to {}", project(from), project(to))
+		},
+	}
+}
+
+/// A closed drawer with the history of a todo, the history is only loaded once it's opened
+#[component]
+pub fn History(todo_id: i32) -> impl IntoView {
+	let (open, set_open) = create_signal(false);
+
+	view! {
+		<div class="history">
+			<button type="button" on:click=move |_| set_open.update(|open| *open = !*open)>
+				{move || if open.get() { "Hide history" } else { "History" }}
+			</button>
+			<Show when=move || open.get()>
+				<HistoryDrawer todo_id=todo_id />
+			</Show>
+		</div>
+	}
+}
+
+#[component]
+fn HistoryDrawer(todo_id: i32) -> impl IntoView {
+	let history = create_resource(move || (), move |_| get_todo_history(todo_id));
+
+	view! {
+		<aside class="history-drawer">
+			<Transition fallback=move || view! { <p>"Loading..."</p> }>
+				{move || {
+					history
+						.get()
+						.map(|history| match history {
+							Err(e) => view! { <span class="error">{e.to_string()}</span> }.into_view(),
+							Ok(history) => {
+								view! {
+									<ol>
+										{history
+											.into_iter()
+											.map(|entry| {
+												let user = entry.user.unwrap_or_default();
+												view! {
+													<li>
+														<small>{entry.created_at.to_string()}</small>
+														" "
+														<Avatar avatar=user.avatar size=AvatarSize::Small />
+														{user.username}
+														" "
+														{describe(entry.change)}
+													</li>
+												}
+											})
+											.collect_view()}
+									</ol>
+								}
+									.into_view()
+							}
+						})
+				}}
+
+			</Transition>
+		</aside>
+	}
+}
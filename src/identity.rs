@@ -0,0 +1,341 @@
+use crate::{auth::ConfirmPassword, errors::AppServerError};
+use leptos::*;
+use leptos_router::*;
+use serde::{Deserialize, Serialize};
+use std::{fmt, str::FromStr};
+
+/// A way to log in, a user can have one credential of every provider
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "ssr", derive(utoipa::ToSchema))]
+pub enum Provider {
+	Password,
+	MagicLink,
+	Saml,
+}
+
+impl Provider {
+	pub fn label(&self) -> &'static str {
+		match self {
+			Provider::Password => "Password",
+			Provider::MagicLink => "Email login links",
+			Provider::Saml => "Single sign-on",
+		}
+	}
+}
+
+impl fmt::Display for Provider {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Provider::Password => write!(f, "password"),
+			Provider::MagicLink => write!(f, "magic_link"),
+			Provider::Saml => write!(f, "saml"),
+		}
+	}
+}
+
+impl FromStr for Provider {
+	type Err = String;
+
+	fn from_str(provider: &str) -> Result<Self, Self::Err> {
+		match provider.trim().to_ascii_lowercase().as_str() {
+			"password" => Ok(Provider::Password),
+			"magic_link" => Ok(Provider::MagicLink),
+			"saml" => Ok(Provider::Saml),
+			_ => Err(format!("Unknown login provider \"{provider}\"")),
+		}
+	}
+}
+
+/// A credential linked to a user, the subject is what the provider knows the user by
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "ssr", derive(utoipa::ToSchema))]
+pub struct Identity {
+	pub provider: Provider,
+	pub subject: String,
+}
+
+#[cfg(feature = "ssr")]
+pub mod ssr {
+	use super::{Identity, Provider};
+	use sqlx::{PgExecutor, PgPool};
+
+	/// The user of the tenant a credential belongs to
+	pub async fn find_identity(
+		tenant: i32,
+		provider: Provider,
+		subject: &str,
+		pool: &PgPool,
+	) -> Result<Option<i32>, sqlx::Error> {
+		sqlx::query_scalar::<_, i32>("SELECT person FROM identities WHERE tenant = $1 AND provider = $2 AND subject = $3")
+			.bind(tenant)
+			.bind(provider.to_string())
+			.bind(subject)
+			.fetch_optional(pool)
+			.await
+	}
+
+	pub async fn has_identity(person: i32, provider: Provider, pool: &PgPool) -> Result<bool, sqlx::Error> {
+		sqlx::query_scalar::<_, bool>("SELECT EXISTS(SELECT 1 FROM identities WHERE person = $1 AND provider = $2)")
+			.bind(person)
+			.bind(provider.to_string())
+			.fetch_one(pool)
+			.await
+	}
+
+	/// Link a credential to a user, linking a provider the user already has replaces its subject
+	///
+	/// Fails with a unique violation when the credential belongs to another user of the tenant
+	pub async fn link_identity<'c>(
+		executor: impl PgExecutor<'c>,
+		tenant: i32,
+		person: i32,
+		provider: Provider,
+		subject: &str,
+	) -> Result<(), sqlx::Error> {
+		sqlx::query(
+			"INSERT INTO identities (tenant, person, provider, subject) VALUES ($1, $2, $3, $4)
+			ON CONFLICT (person, provider) DO UPDATE SET subject = EXCLUDED.subject",
+		)
+		.bind(tenant)
+		.bind(person)
+		.bind(provider.to_string())
+		.bind(subject)
+		.execute(executor)
+		.await?;
+
+		Ok(())
+	}
+
+	pub async fn list_identities(person: i32, pool: &PgPool) -> Result<Vec<Identity>, sqlx::Error> {
+		let identities = sqlx::query_as::<_, (String, String)>(
+			"SELECT provider, subject FROM identities WHERE person = $1 ORDER BY created_at",
+		)
+		.bind(person)
+		.fetch_all(pool)
+		.await?
+		.into_iter()
+		// A provider this build doesn't know is skipped instead of hiding every other one
+		.filter_map(|(provider, subject)| {
+			Some(Identity {
+				provider: provider.parse().ok()?,
+				subject,
+			})
+		})
+		.collect();
+
+		Ok(identities)
+	}
+}
+
+/// The credentials the logged in user can log in with
+#[server(input = GetUrl)]
+pub async fn get_identities() -> Result<Vec<Identity>, ServerFnError<AppServerError>> {
+	use self::ssr::list_identities;
+	use crate::{auth::get_user, cache::cache_privately};
+	use sqlx::PgPool;
+
+	cache_privately::<GetIdentities>(0);
+
+	let pool = use_context::<PgPool>().expect("Database not initialized");
+	let user = get_user().await?.ok_or_else(AppServerError::unauthenticated)?;
+
+	Ok(list_identities(user.id, &pool).await.map_err(AppServerError::from)?)
+}
+
+/// Log in with a password from now on, replaces the password when the user already has one
+#[server]
+pub async fn link_password(
+	password: String,
+	password_confirmation: String,
+) -> Result<(), ServerFnError<AppServerError>> {
+	use crate::{
+		auth::{
+			get_user,
+			ssr::{require_recent_auth, AuthBackend, STEP_UP_MAX_AGE},
+		},
+		breach::PasswordBreachCheck,
+		tenant::{Tenant, TenantSettings},
+	};
+	use sqlx::PgPool;
+	use std::sync::Arc;
+
+	let pool = use_context::<PgPool>().expect("Database not initialized");
+	let backend = use_context::<Arc<dyn AuthBackend>>().expect("No auth backend found");
+	let tenant = use_context::<Tenant>().expect("No tenant found");
+	let user = get_user().await?.ok_or_else(AppServerError::unauthenticated)?;
+	require_recent_auth(STEP_UP_MAX_AGE)?;
+
+	if password != password_confirmation {
+		return Err(AppServerError::invalid("Passwords did not match.").into());
+	}
+
+	TenantSettings::get_for_tenant(tenant.id, &pool)
+		.await
+		.validate_password(&password)
+		.map_err(AppServerError::invalid)?;
+	use_context::<PasswordBreachCheck>()
+		.expect("No password breach check found")
+		.check(&password)
+		.await
+		.map_err(AppServerError::invalid)?;
+
+	backend.set_password(&user, &password).await.map_err(|error| AppServerError::new(error.code(), error))?;
+
+	Ok(())
+}
+
+/// Stop logging in with a provider, the last credential of a user can't be removed
+#[server]
+pub async fn unlink_identity(provider: String) -> Result<(), ServerFnError<AppServerError>> {
+	use crate::auth::{
+		get_user,
+		ssr::{require_recent_auth, STEP_UP_MAX_AGE},
+	};
+	use sqlx::PgPool;
+
+	let pool = use_context::<PgPool>().expect("Database not initialized");
+	let user = get_user().await?.ok_or_else(AppServerError::unauthenticated)?;
+	require_recent_auth(STEP_UP_MAX_AGE)?;
+	let provider = provider.parse::<Provider>().map_err(AppServerError::invalid)?;
+
+	// Locking the user keeps two unlinks running at once from each leaving the other as the last credential
+	let mut transaction = pool.begin().await.map_err(AppServerError::from)?;
+	sqlx::query("SELECT id FROM users WHERE id = $1 FOR UPDATE")
+		.bind(user.id)
+		.execute(&mut *transaction)
+		.await
+		.map_err(AppServerError::from)?;
+	let remaining = sqlx::query_scalar::<_, i64>("SELECT count(*) FROM identities WHERE person = $1 AND provider <> $2")
+		.bind(user.id)
+		.bind(provider.to_string())
+		.fetch_one(&mut *transaction)
+		.await
+		.map_err(AppServerError::from)?;
+	if remaining == 0 {
+		return Err(AppServerError::invalid("This is your last way to log in, link another one before removing it").into());
+	}
+
+	sqlx::query("DELETE FROM identities WHERE person = $1 AND provider = $2")
+		.bind(user.id)
+		.bind(provider.to_string())
+		.execute(&mut *transaction)
+		.await
+		.map_err(AppServerError::from)?;
+	// Links already mailed would still log in otherwise
+	if provider == Provider::MagicLink {
+		sqlx::query("DELETE FROM magic_links WHERE person = $1")
+			.bind(user.id)
+			.execute(&mut *transaction)
+			.await
+			.map_err(AppServerError::from)?;
+	}
+	transaction.commit().await.map_err(AppServerError::from)?;
+
+	Ok(())
+}
+
+#[component]
+pub fn LinkedIdentities() -> impl IntoView {
+	let link_password = create_server_action::<LinkPassword>();
+	let link_email = create_server_action::<crate::magic_link::SetLoginEmail>();
+	let cancel_email = create_server_action::<crate::magic_link::CancelLoginEmailChange>();
+	let unlink = create_server_action::<UnlinkIdentity>();
+	let identities = create_resource(
+		move || (link_password.version().get(), link_email.version().get(), unlink.version().get()),
+		move |_| get_identities(),
+	);
+	let pending_email = create_resource(
+		move || (link_email.version().get(), cancel_email.version().get()),
+		move |_| crate::magic_link::get_pending_login_email(),
+	);
+	let error = Signal::derive(move || {
+		[
+			link_password.value().get(),
+			link_email.value().get(),
+			cancel_email.value().get(),
+			unlink.value().get(),
+		]
+		.into_iter()
+		.flatten()
+		.find_map(Result::err)
+	});
+
+	view! {
+		<h2>"Ways to log in"</h2>
+		<Transition fallback=move || view! { <p>"Loading..."</p> }>
+			{move || {
+				identities
+					.get()
+					.map(|identities| match identities {
+						Err(e) => view! { <span class="error">{e.to_string()}</span> }.into_view(),
+						Ok(identities) => {
+							let has_password = identities.iter().any(|identity| identity.provider == Provider::Password);
+							let email = identities
+								.iter()
+								.find(|identity| identity.provider == Provider::MagicLink)
+								.map(|identity| identity.subject.clone())
+								.unwrap_or_default();
+							let linked = identities
+								.into_iter()
+								.map(|identity| {
+									view! {
+										<li>
+											{identity.provider.label()} " (" {identity.subject} ")"
+											<ActionForm action=unlink>
+												<input
+													type="hidden"
+													name="provider"
+													value=identity.provider.to_string()
+												/>
+												<input type="submit" value="Remove" />
+											</ActionForm>
+										</li>
+									}
+								})
+								.collect_view();
+							view! {
+								<ul>{linked}</ul>
+								<ActionForm action=link_password>
+									<label>
+										{if has_password { "New password " } else { "Password " }}
+										<input type="password" name="password" />
+									</label>
+									<label>
+										"Confirm " <input type="password" name="password_confirmation" />
+									</label>
+									<input
+										type="submit"
+										value=if has_password { "Change password" } else { "Add password" }
+									/>
+								</ActionForm>
+								<ActionForm action=link_email>
+									<label>
+										"Email for login links " <input type="email" name="email" value=email />
+									</label>
+									<input type="submit" value="Save" />
+								</ActionForm>
+								{pending_email
+									.get()
+									.and_then(Result::ok)
+									.flatten()
+									.map(|pending| {
+										view! {
+											<ActionForm action=cancel_email>
+												{format!(
+													"Waiting for you to confirm {pending}, check that inbox for the link. "
+												)}
+												<input type="submit" value="Cancel" />
+											</ActionForm>
+										}
+									})}
+							}
+								.into_view()
+						}
+					})
+			}}
+
+		</Transition>
+		{move || error.get().map(|e| view! { <span class="error">{e.to_string()}</span> })}
+		<ConfirmPassword error=error />
+	}
+}
@@ -0,0 +1,968 @@
+//! Messages about todos in chat rooms, posted to a Slack webhook or a Matrix room
+//!
+//! Users connect rooms for the todos that concern them, the ones they own or are assigned to, admins connect rooms for
+//! every todo of the tenant. Either way a message is only posted when whoever connected the room can read the todo
+//! right then, and each integration picks the events it wants.
+
+use crate::{
+	auth::{use_user_context, UserContext},
+	errors::AppServerError,
+	toast::{use_toasts, Toasted},
+};
+use chrono::prelude::*;
+use leptos::*;
+use leptos_router::*;
+use serde::{Deserialize, Serialize};
+use std::{fmt, str::FromStr};
+
+/// Slack webhooks all live on this host, Matrix homeservers can be anywhere as long as they're public
+const SLACK_WEBHOOK_PREFIX: &str = "https://hooks.slack.com/";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "ssr", derive(utoipa::ToSchema))]
+pub enum IntegrationKind {
+	Slack,
+	Matrix,
+}
+
+impl fmt::Display for IntegrationKind {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			IntegrationKind::Slack => write!(f, "slack"),
+			IntegrationKind::Matrix => write!(f, "matrix"),
+		}
+	}
+}
+
+impl FromStr for IntegrationKind {
+	type Err = String;
+
+	fn from_str(kind: &str) -> Result<Self, Self::Err> {
+		match kind.trim().to_ascii_lowercase().as_str() {
+			"slack" => Ok(IntegrationKind::Slack),
+			"matrix" => Ok(IntegrationKind::Matrix),
+			_ => Err(format!("Unknown integration \"{kind}\"")),
+		}
+	}
+}
+
+/// The events an integration can post about
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "ssr", derive(utoipa::ToSchema))]
+pub enum IntegrationEvent {
+	TodoAssigned,
+	TodoOverdue,
+}
+
+impl IntegrationEvent {
+	pub const ALL: [IntegrationEvent; 2] = [IntegrationEvent::TodoAssigned, IntegrationEvent::TodoOverdue];
+
+	pub fn as_str(&self) -> &'static str {
+		match self {
+			IntegrationEvent::TodoAssigned => "todo_assigned",
+			IntegrationEvent::TodoOverdue => "todo_overdue",
+		}
+	}
+
+	fn label(&self) -> &'static str {
+		match self {
+			IntegrationEvent::TodoAssigned => "A todo is assigned",
+			IntegrationEvent::TodoOverdue => "A todo is overdue",
+		}
+	}
+}
+
+impl FromStr for IntegrationEvent {
+	type Err = String;
+
+	fn from_str(event: &str) -> Result<Self, Self::Err> {
+		IntegrationEvent::ALL
+			.into_iter()
+			.find(|known| known.as_str() == event)
+			.ok_or_else(|| format!("Unknown integration event \"{event}\""))
+	}
+}
+
+/// A connected room, what's needed to post to it stays on the server
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "ssr", derive(utoipa::ToSchema))]
+pub struct Integration {
+	pub id: i32,
+	pub kind: IntegrationKind,
+	/// The Matrix room, Slack webhooks are secrets themselves so they aren't shown
+	pub target: String,
+	/// Posts about every todo of the tenant instead of the ones of the user who connected it
+	pub tenant_wide: bool,
+	pub events: Vec<IntegrationEvent>,
+	/// Why the last post failed, `None` once one went through again
+	pub last_error: Option<String>,
+	pub created_at: DateTime<Utc>,
+}
+
+#[cfg(feature = "ssr")]
+pub mod ssr {
+	use super::{Integration, IntegrationEvent, IntegrationKind};
+	use crate::{
+		auth::User,
+		authorize::Action,
+		config::Config,
+		events::{record, DomainEvent},
+		jobs,
+		jwt::ssr::generate_token,
+		routes::Route,
+		todo::ssr::get_todo_with_permission,
+		validation::TextField,
+	};
+	use chrono::prelude::*;
+	use reqwest::{
+		dns::{Addrs, Name, Resolve, Resolving},
+		redirect, Url,
+	};
+	use sqlx::{types::Json, PgExecutor, PgPool};
+	use std::{
+		io,
+		net::{IpAddr, SocketAddr},
+		str::FromStr,
+		sync::Arc,
+		time::Duration,
+	};
+
+	pub(super) const INTEGRATION_URL: TextField = TextField {
+		name: "address",
+		max_length: 2048,
+		multiline: false,
+	};
+
+	pub(super) const MATRIX_ROOM: TextField = TextField {
+		name: "room",
+		max_length: 255,
+		multiline: false,
+	};
+
+	pub(super) const MATRIX_ACCESS_TOKEN: TextField = TextField {
+		name: "access token",
+		max_length: 1024,
+		multiline: false,
+	};
+
+	/// Whether an address is out on the internet, everything else is somebody's internal network or this server
+	pub(super) fn is_public(ip: IpAddr) -> bool {
+		match ip {
+			IpAddr::V4(ip) => {
+				let [first, second, ..] = ip.octets();
+				!(ip.is_unspecified()
+					|| ip.is_loopback()
+					|| ip.is_private()
+					|| ip.is_link_local()
+					|| ip.is_broadcast()
+					|| ip.is_documentation()
+					|| ip.is_multicast()
+					// Carrier grade NAT, 100.64.0.0/10
+					|| (first == 100 && second & 0b1100_0000 == 64)
+					|| first == 0)
+			},
+			IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+				Some(ip) => is_public(IpAddr::V4(ip)),
+				None => {
+					let first = ip.segments()[0];
+					!(ip.is_unspecified()
+						|| ip.is_loopback()
+						|| ip.is_multicast()
+						// Unique local fc00::/7 and link local fe80::/10
+						|| first & 0xfe00 == 0xfc00
+						|| first & 0xffc0 == 0xfe80)
+				},
+			},
+		}
+	}
+
+	/// Resolves hosts to their public addresses only, so a name pointing inside is refused when it's posted to too
+	struct PublicResolver;
+
+	impl Resolve for PublicResolver {
+		fn resolve(&self, name: Name) -> Resolving {
+			Box::pin(async move {
+				let public = tokio::net::lookup_host((name.as_str(), 0))
+					.await?
+					.filter(|address| is_public(address.ip()))
+					.collect::<Vec<SocketAddr>>();
+				if public.is_empty() {
+					return Err(io::Error::new(io::ErrorKind::PermissionDenied, "Only public addresses are posted to").into());
+				}
+				let addrs: Addrs = Box::new(public.into_iter());
+
+				Ok(addrs)
+			})
+		}
+	}
+
+	/// Posts to integrations, redirects aren't followed as they could lead anywhere
+	fn client() -> reqwest::Client {
+		reqwest::Client::builder()
+			.timeout(Duration::from_secs(10))
+			.redirect(redirect::Policy::none())
+			.dns_resolver(Arc::new(PublicResolver))
+			.build()
+			.expect("Unable to build HTTP client")
+	}
+
+	/// The homeserver as an https address without credentials whose host only resolves to public addresses
+	pub(super) async fn homeserver(url: &str) -> Result<Url, String> {
+		let invalid = || String::from("The homeserver has to be a public https:// address");
+		let url = Url::parse(url).map_err(|_| invalid())?;
+		if url.scheme() != "https" || !url.username().is_empty() || url.password().is_some() {
+			return Err(invalid());
+		}
+		let host = url.host_str().ok_or_else(invalid)?.trim_start_matches('[').trim_end_matches(']');
+		let addresses = match host.parse::<IpAddr>() {
+			Ok(ip) => vec![ip],
+			Err(_) => tokio::net::lookup_host((host, 443))
+				.await
+				.map_err(|_| String::from("The homeserver couldn't be found"))?
+				.map(|address| address.ip())
+				.collect(),
+		};
+		if addresses.is_empty() || !addresses.into_iter().all(is_public) {
+			return Err(invalid());
+		}
+
+		Ok(url)
+	}
+
+	/// The events picked with checkboxes, any value means yes
+	pub(super) fn picked_events(todo_assigned: Option<String>, todo_overdue: Option<String>) -> Vec<&'static str> {
+		[
+			(IntegrationEvent::TodoAssigned, todo_assigned),
+			(IntegrationEvent::TodoOverdue, todo_overdue),
+		]
+		.into_iter()
+		.filter(|(_, picked)| picked.is_some())
+		.map(|(event, _)| event.as_str())
+		.collect()
+	}
+
+	#[derive(sqlx::FromRow)]
+	pub(super) struct SqlIntegration {
+		id: i32,
+		person: i32,
+		tenant_wide: bool,
+		kind: String,
+		url: String,
+		room: Option<String>,
+		access_token: Option<String>,
+		events: Vec<String>,
+		last_error: Option<String>,
+		created_at: DateTime<Utc>,
+	}
+
+	impl SqlIntegration {
+		pub(super) fn into_integration(self) -> Option<Integration> {
+			let kind = IntegrationKind::from_str(&self.kind).ok()?;
+			Some(Integration {
+				id: self.id,
+				kind,
+				target: match kind {
+					IntegrationKind::Slack => String::from("Slack webhook"),
+					IntegrationKind::Matrix => self.room.unwrap_or_default(),
+				},
+				tenant_wide: self.tenant_wide,
+				events: self.events.iter().filter_map(|event| event.parse().ok()).collect(),
+				last_error: self.last_error,
+				created_at: self.created_at,
+			})
+		}
+	}
+
+	/// A post to a room, as plain text and as HTML for Matrix clients that show formatting
+	#[derive(Debug, Clone, PartialEq, Eq)]
+	pub struct Message {
+		pub slack: String,
+		pub text: String,
+		pub html: String,
+	}
+
+	fn escape_slack(text: &str) -> String {
+		text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+	}
+
+	fn escape_html(text: &str) -> String {
+		escape_slack(text).replace('"', "&quot;")
+	}
+
+	/// What is said about the todo, `what` comes after the linked title
+	pub fn message(title: &str, url: &str, what: &str) -> Message {
+		Message {
+			slack: format!("<{url}|{}> {}", escape_slack(title), escape_slack(what)),
+			text: format!("\"{title}\" {what}: {url}"),
+			html: format!("<a href=\"{}\">{}</a> {}", escape_html(url), escape_html(title), escape_html(what)),
+		}
+	}
+
+	/// Posting failed, with what the user who connected the room is told about it
+	#[derive(Debug)]
+	struct PostError {
+		shown: String,
+		logged: String,
+	}
+
+	impl PostError {
+		fn new(shown: impl Into<String>, logged: impl ToString) -> Self {
+			Self {
+				shown: shown.into(),
+				logged: logged.to_string(),
+			}
+		}
+	}
+
+	async fn post_message(
+		client: &reqwest::Client,
+		integration: &SqlIntegration,
+		message: &Message,
+	) -> Result<(), PostError> {
+		let request = match IntegrationKind::from_str(&integration.kind)
+			.map_err(|error| PostError::new("The integration is broken, connect the room again", error))?
+		{
+			IntegrationKind::Slack => client.post(&integration.url).json(&serde_json::json!({ "text": message.slack })),
+			IntegrationKind::Matrix => {
+				// Whether the homeserver is public is checked again as names can point somewhere else by now
+				let mut url = homeserver(&integration.url)
+					.await
+					.map_err(|error| PostError::new(format!("{error}, connect the room again"), &error))?;
+				// Every message needs its own transaction id, Matrix drops repeated ones
+				let transaction = generate_token(24);
+				url
+					.path_segments_mut()
+					.map_err(|_| PostError::new("The homeserver address can't take a path", "Address can't be a base"))?
+					.pop_if_empty()
+					.extend(["_matrix", "client", "v3", "rooms"])
+					.push(integration.room.as_deref().unwrap_or_default())
+					.extend(["send", "m.room.message", transaction.as_str()]);
+				client.put(url).bearer_auth(integration.access_token.as_deref().unwrap_or_default()).json(&serde_json::json!({
+					"msgtype": "m.text",
+					"body": message.text,
+					"format": "org.matrix.custom.html",
+					"formatted_body": message.html,
+				}))
+			},
+		};
+
+		let response =
+			request.send().await.map_err(|error| PostError::new("The room couldn't be reached", error.without_url()))?;
+		let status = response.status();
+		if status.is_redirection() {
+			return Err(PostError::new(
+				format!("The room answered with a redirect ({status}), which isn't followed"),
+				status,
+			));
+		}
+		if !status.is_success() {
+			return Err(PostError::new(format!("The room refused the message ({status})"), status));
+		}
+
+		Ok(())
+	}
+
+	/// Posts are given up on after this many failed attempts, the time between them doubles from half a minute
+	const MAX_POST_ATTEMPTS: i32 = 8;
+	const POST_BATCH_SIZE: i64 = 50;
+
+	/// The integration event an outbox event is, `None` for the ones that are never posted
+	fn integration_event(event: &DomainEvent) -> Option<(i32, i32, IntegrationEvent)> {
+		match event {
+			DomainEvent::TodoAssigned { tenant, todo, .. } => Some((*tenant, *todo, IntegrationEvent::TodoAssigned)),
+			DomainEvent::TodoOverdue { tenant, todo } => Some((*tenant, *todo, IntegrationEvent::TodoOverdue)),
+			_ => None,
+		}
+	}
+
+	/// Queue a post of the outbox entry to every integration that wants it, pass the transaction of the outbox poller
+	/// so the posts are queued if and only if the entry is delivered
+	pub async fn queue_posts<'c>(
+		executor: impl PgExecutor<'c>,
+		outbox: i64,
+		event: &DomainEvent,
+	) -> Result<(), sqlx::Error> {
+		let Some((tenant, _, wanted)) = integration_event(event) else {
+			return Ok(());
+		};
+
+		// Retried outbox entries find their posts queued already
+		sqlx::query(
+			"INSERT INTO integration_posts (integration, outbox)
+			SELECT id, $3 FROM integrations WHERE tenant = $1 AND $2 = ANY(events)
+			ON CONFLICT DO NOTHING",
+		)
+		.bind(tenant)
+		.bind(wanted.as_str())
+		.bind(outbox)
+		.execute(executor)
+		.await?;
+
+		Ok(())
+	}
+
+	/// What is posted about the event to the integration, `None` when whoever connected it isn't told about it
+	async fn compose(
+		event: &DomainEvent,
+		integration: &SqlIntegration,
+		public_url: &str,
+		pool: &PgPool,
+	) -> Result<Option<Message>, sqlx::Error> {
+		let Some((tenant, todo, _)) = integration_event(event) else {
+			return Ok(None);
+		};
+		let actor = match event {
+			DomainEvent::TodoAssigned { user, .. } => Some(*user),
+			_ => None,
+		};
+		// Nobody needs to be told what they just did themselves
+		if Some(integration.person) == actor && !integration.tenant_wide {
+			return Ok(None);
+		}
+
+		let Some((owner, title)) =
+			sqlx::query_as::<_, (i32, String)>("SELECT person, title FROM todos WHERE id = $1 AND deleted_at IS NULL")
+				.bind(todo)
+				.fetch_optional(pool)
+				.await?
+		else {
+			return Ok(None);
+		};
+		if !integration.tenant_wide && integration.person != owner {
+			let assigned =
+				sqlx::query_scalar::<_, bool>("SELECT EXISTS (SELECT 1 FROM todo_assignees WHERE todo = $1 AND person = $2)")
+					.bind(todo)
+					.bind(integration.person)
+					.fetch_one(pool)
+					.await?;
+			if !assigned {
+				return Ok(None);
+			}
+		}
+		let can_read = match User::get_from_id(integration.person, pool).await {
+			Some(user) if user.active && user.tenant == tenant => {
+				get_todo_with_permission(todo, tenant, &user, Action::Read, pool).await.is_some()
+			},
+			_ => false,
+		};
+		if !can_read {
+			return Ok(None);
+		}
+
+		let username =
+			|id: i32| async move { User::get_from_id(id, pool).await.map(|user| user.username).unwrap_or_default() };
+		let what = match event {
+			DomainEvent::TodoAssigned { user, person, .. } => {
+				format!("was assigned to {} by {}", username(*person).await, username(*user).await)
+			},
+			_ => String::from("is overdue"),
+		};
+
+		Ok(Some(message(&title, &format!("{public_url}{}", Route::TodoDetail(todo)), &what)))
+	}
+
+	#[derive(sqlx::FromRow)]
+	struct QueuedPost {
+		post: i64,
+		event: Json<DomainEvent>,
+		#[sqlx(flatten)]
+		integration: SqlIntegration,
+	}
+
+	/// Post what is queued and due, failed posts are tried again later
+	///
+	/// Posts are claimed by pushing their next attempt out before anything is sent, so no transaction is held while
+	/// waiting on a room and a replica that dies halfway only delays them
+	pub async fn post_queued(client: &reqwest::Client, public_url: &str, pool: &PgPool) -> Result<usize, sqlx::Error> {
+		let queued = sqlx::query_as::<_, QueuedPost>(
+			"WITH claimed AS (
+				UPDATE integration_posts
+				SET attempts = attempts + 1, next_attempt_at = now() + interval '30 seconds' * power(2, attempts)
+				WHERE id IN (
+					SELECT id FROM integration_posts
+					WHERE delivered_at IS NULL AND attempts < $1 AND next_attempt_at <= now()
+					ORDER BY id LIMIT $2 FOR UPDATE SKIP LOCKED
+				)
+				RETURNING id, integration, outbox
+			)
+			SELECT claimed.id AS post, outbox.event, integrations.id, integrations.person, integrations.tenant_wide,
+				integrations.kind, integrations.url, integrations.room, integrations.access_token, integrations.events,
+				integrations.last_error, integrations.created_at
+			FROM claimed
+			JOIN integrations ON integrations.id = claimed.integration
+			JOIN outbox ON outbox.id = claimed.outbox
+			ORDER BY claimed.id",
+		)
+		.bind(MAX_POST_ATTEMPTS)
+		.bind(POST_BATCH_SIZE)
+		.fetch_all(pool)
+		.await?;
+		let count = queued.len();
+
+		for QueuedPost {
+			post,
+			event: Json(event),
+			integration,
+		} in queued
+		{
+			let Some(message) = compose(&event, &integration, public_url, pool).await? else {
+				sqlx::query("UPDATE integration_posts SET delivered_at = now() WHERE id = $1").bind(post).execute(pool).await?;
+				continue;
+			};
+
+			match post_message(client, &integration, &message).await {
+				Ok(()) => {
+					let mut tx = pool.begin().await?;
+					sqlx::query("UPDATE integration_posts SET delivered_at = now() WHERE id = $1")
+						.bind(post)
+						.execute(&mut *tx)
+						.await?;
+					sqlx::query("UPDATE integrations SET last_error = NULL WHERE id = $1")
+						.bind(integration.id)
+						.execute(&mut *tx)
+						.await?;
+					tx.commit().await?;
+				},
+				Err(error) => {
+					log::error!("Posting to integration {} failed: {}", integration.id, error.logged);
+					sqlx::query("UPDATE integrations SET last_error = $2 WHERE id = $1")
+						.bind(integration.id)
+						.bind(error.shown)
+						.execute(pool)
+						.await?;
+				},
+			}
+		}
+
+		Ok(count)
+	}
+
+	/// Record an event for every open todo that passed its due date since the last run
+	///
+	/// Each due date is only reported once, moving the due date makes the todo due again. Only todos that became
+	/// overdue within the last day are looked at so long forgotten todos don't all show up at once.
+	pub async fn record_overdue(pool: &PgPool) -> Result<usize, sqlx::Error> {
+		let mut tx = pool.begin().await?;
+
+		// The primary key makes replicas that run this at the same time wait for each other and skip what's recorded
+		let overdue = sqlx::query_as::<_, (i32, i32)>(
+			"WITH found AS (
+				INSERT INTO overdue_todos (todo, due_at)
+				SELECT id, due_at FROM todos
+				WHERE completed IS NOT TRUE AND deleted_at IS NULL AND archived_at IS NULL
+					AND due_at < now() AND due_at > now() - interval '1 day'
+				ON CONFLICT DO NOTHING RETURNING todo
+			)
+			SELECT todos.tenant, todos.id FROM todos JOIN found ON found.todo = todos.id",
+		)
+		.fetch_all(&mut *tx)
+		.await?;
+		for (tenant, todo) in &overdue {
+			record(
+				&mut *tx,
+				&DomainEvent::TodoOverdue {
+					tenant: *tenant,
+					todo: *todo,
+				},
+			)
+			.await?;
+		}
+		// Due dates older than what is looked at can't come up again
+		sqlx::query("DELETE FROM overdue_todos WHERE due_at < now() - interval '2 days'").execute(&mut *tx).await?;
+
+		tx.commit().await?;
+
+		Ok(overdue.len())
+	}
+
+	/// Look for overdue todos every `OVERDUE_INTERVAL` seconds and post what the outbox queued every
+	/// `INTEGRATION_POST_INTERVAL` seconds
+	pub fn spawn_integrations(pool: PgPool, config: &Config) {
+		{
+			let pool = pool.clone();
+			jobs::every("Overdue todos", Duration::from_secs(config.overdue_interval), move || {
+				let pool = pool.clone();
+				async move { record_overdue(&pool).await.map(|_| ()) }
+			});
+		}
+
+		let client = client();
+		let public_url = config.public_url.clone();
+		jobs::every("Integration posts", Duration::from_secs(config.integration_post_interval), move || {
+			let (client, public_url, pool) = (client.clone(), public_url.clone(), pool.clone());
+			async move {
+				// A full batch means there is probably more waiting
+				while post_queued(&client, &public_url, &pool).await? == POST_BATCH_SIZE as usize {}
+				Ok::<_, sqlx::Error>(())
+			}
+		});
+	}
+}
+
+/// The integrations the logged in user connected, and for admins those of the whole tenant
+#[server(input = GetUrl)]
+pub async fn get_integrations() -> Result<Vec<Integration>, ServerFnError<AppServerError>> {
+	use self::ssr::SqlIntegration;
+	use crate::{auth::get_user, cache::cache_privately, tenant::Tenant};
+	use sqlx::PgPool;
+
+	cache_privately::<GetIntegrations>(0);
+
+	let pool = use_context::<PgPool>().expect("Database not initialized");
+	let tenant = use_context::<Tenant>().expect("No tenant found");
+	let user = get_user().await?.ok_or_else(AppServerError::unauthenticated)?;
+
+	Ok(
+		sqlx::query_as::<_, SqlIntegration>(
+			"SELECT id, person, tenant_wide, kind, url, room, access_token, events, last_error, created_at
+			FROM integrations WHERE tenant = $1 AND ((person = $2 AND NOT tenant_wide) OR (tenant_wide AND $3))
+			ORDER BY tenant_wide, id",
+		)
+		.bind(tenant.id)
+		.bind(user.id)
+		.bind(user.is_admin())
+		.fetch_all(&pool)
+		.await
+		.map_err(AppServerError::from)?
+		.into_iter()
+		.filter_map(SqlIntegration::into_integration)
+		.collect(),
+	)
+}
+
+/// Connect a Slack webhook, or a Matrix room with the homeserver as `url`, only admins connect rooms for everyone
+#[server]
+pub async fn add_integration(
+	kind: String,
+	url: String,
+	room: Option<String>,
+	access_token: Option<String>,
+	tenant_wide: Option<String>,
+	todo_assigned: Option<String>,
+	todo_overdue: Option<String>,
+) -> Result<Toasted<()>, ServerFnError<AppServerError>> {
+	use self::ssr::{homeserver, picked_events, INTEGRATION_URL, MATRIX_ACCESS_TOKEN, MATRIX_ROOM};
+	use crate::{
+		auth::{
+			get_user,
+			ssr::{require_recent_auth, STEP_UP_MAX_AGE},
+		},
+		denial::{ssr::record_denial, DeniedAction},
+		tenant::Tenant,
+	};
+	use sqlx::PgPool;
+
+	let pool = use_context::<PgPool>().expect("Database not initialized");
+	let tenant = use_context::<Tenant>().expect("No tenant found");
+	let user = get_user().await?.ok_or_else(AppServerError::unauthenticated)?;
+
+	let tenant_wide = tenant_wide.is_some();
+	if tenant_wide {
+		if !user.is_admin() {
+			record_denial("add_integration", DeniedAction::Admin, &user);
+			return Err(AppServerError::forbidden("Missing permission to connect rooms for everyone").into());
+		}
+		require_recent_auth(STEP_UP_MAX_AGE)?;
+	}
+
+	let kind = kind.parse::<IntegrationKind>().map_err(AppServerError::invalid)?;
+	let url = INTEGRATION_URL.clean(&url).map_err(AppServerError::invalid)?;
+	let (room, access_token) = match kind {
+		IntegrationKind::Slack => {
+			let webhook = reqwest::Url::parse(&url)
+				.ok()
+				.filter(|webhook| webhook.host_str() == Some("hooks.slack.com") && webhook.port().is_none());
+			if !url.starts_with(SLACK_WEBHOOK_PREFIX) || webhook.is_none() {
+				return Err(AppServerError::invalid(format!("Slack webhooks start with {SLACK_WEBHOOK_PREFIX}")).into());
+			}
+			(None, None)
+		},
+		IntegrationKind::Matrix => {
+			homeserver(&url).await.map_err(AppServerError::invalid)?;
+			let room = MATRIX_ROOM.clean(&room.unwrap_or_default()).map_err(AppServerError::invalid)?;
+			if !room.starts_with('!') {
+				return Err(AppServerError::invalid("Matrix room ids start with !").into());
+			}
+			let access_token =
+				MATRIX_ACCESS_TOKEN.clean(&access_token.unwrap_or_default()).map_err(AppServerError::invalid)?;
+			(Some(room), Some(access_token))
+		},
+	};
+	let events = picked_events(todo_assigned, todo_overdue);
+	if events.is_empty() {
+		return Err(AppServerError::invalid("Pick at least one event to post about").into());
+	}
+
+	sqlx::query(
+		"INSERT INTO integrations (tenant, person, tenant_wide, kind, url, room, access_token, events)
+		VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+	)
+	.bind(tenant.id)
+	.bind(user.id)
+	.bind(tenant_wide)
+	.bind(kind.to_string())
+	.bind(url)
+	.bind(room)
+	.bind(access_token)
+	.bind(events)
+	.execute(&pool)
+	.await
+	.map_err(AppServerError::from)?;
+
+	Ok(Toasted::success((), "Room connected"))
+}
+
+/// Change which events an integration posts about
+#[server]
+pub async fn set_integration_events(
+	id: i32,
+	todo_assigned: Option<String>,
+	todo_overdue: Option<String>,
+) -> Result<Toasted<()>, ServerFnError<AppServerError>> {
+	use self::ssr::picked_events;
+	use crate::{auth::get_user, tenant::Tenant};
+	use sqlx::PgPool;
+
+	let pool = use_context::<PgPool>().expect("Database not initialized");
+	let tenant = use_context::<Tenant>().expect("No tenant found");
+	let user = get_user().await?.ok_or_else(AppServerError::unauthenticated)?;
+
+	let events = picked_events(todo_assigned, todo_overdue);
+	if events.is_empty() {
+		return Err(AppServerError::invalid("Pick at least one event to post about, or remove the room").into());
+	}
+
+	let updated = sqlx::query(
+		"UPDATE integrations SET events = $4
+		WHERE id = $1 AND tenant = $2 AND ((person = $3 AND NOT tenant_wide) OR (tenant_wide AND $5))",
+	)
+	.bind(id)
+	.bind(tenant.id)
+	.bind(user.id)
+	.bind(events)
+	.bind(user.is_admin())
+	.execute(&pool)
+	.await
+	.map_err(AppServerError::from)?
+	.rows_affected();
+	if updated == 0 {
+		return Err(AppServerError::not_found("Unknown integration").into());
+	}
+
+	Ok(Toasted::success((), "Events saved"))
+}
+
+#[server]
+pub async fn delete_integration(id: i32) -> Result<Toasted<()>, ServerFnError<AppServerError>> {
+	use crate::{auth::get_user, tenant::Tenant};
+	use sqlx::PgPool;
+
+	let pool = use_context::<PgPool>().expect("Database not initialized");
+	let tenant = use_context::<Tenant>().expect("No tenant found");
+	let user = get_user().await?.ok_or_else(AppServerError::unauthenticated)?;
+
+	let deleted = sqlx::query(
+		"DELETE FROM integrations
+		WHERE id = $1 AND tenant = $2 AND ((person = $3 AND NOT tenant_wide) OR (tenant_wide AND $4))",
+	)
+	.bind(id)
+	.bind(tenant.id)
+	.bind(user.id)
+	.bind(user.is_admin())
+	.execute(&pool)
+	.await
+	.map_err(AppServerError::from)?
+	.rows_affected();
+	if deleted == 0 {
+		return Err(AppServerError::not_found("Unknown integration").into());
+	}
+
+	Ok(Toasted::success((), "Room disconnected"))
+}
+
+#[component]
+pub fn IntegrationSettings() -> impl IntoView {
+	let add = create_server_action::<AddIntegration>();
+	let set_events = create_server_action::<SetIntegrationEvents>();
+	let delete = create_server_action::<DeleteIntegration>();
+	let toasts = use_toasts();
+	toasts.follow(add);
+	toasts.follow(set_events);
+	toasts.follow(delete);
+	let UserContext { user, .. } = use_user_context();
+	let is_admin = move || user.get().and_then(Result::ok).flatten().is_some_and(|user| user.is_admin());
+	let (kind, set_kind) = create_signal(IntegrationKind::Slack);
+	let integrations = create_resource(
+		move || (add.version().get(), set_events.version().get(), delete.version().get()),
+		move |_| get_integrations(),
+	);
+
+	view! {
+		<h2>"Chat rooms"</h2>
+		<Transition fallback=move || view! { <p>"Loading..."</p> }>
+			{move || {
+				integrations
+					.get()
+					.map(|integrations| match integrations {
+						Err(e) => view! { <span class="error">{e.to_string()}</span> }.into_view(),
+						Ok(integrations) if integrations.is_empty() => {
+							view! { <p>"No rooms are connected."</p> }.into_view()
+						}
+						Ok(integrations) => {
+							view! {
+								<ul class="integrations">
+									{integrations
+										.into_iter()
+										.map(|integration| {
+											let events = integration.events.clone();
+											view! {
+												<li>
+													<strong>{integration.kind.to_string()}</strong>
+													" "
+													{integration.target}
+													{integration.tenant_wide.then_some(" (everyone)")}
+													{integration
+														.last_error
+														.map(|error| {
+															view! { <span class="error">" Last post failed: " {error}</span> }
+														})}
+													<ActionForm action=set_events>
+														<input type="hidden" name="id" value=integration.id />
+														{IntegrationEvent::ALL
+															.into_iter()
+															.map(|event| {
+																view! {
+																	<label>
+																		<input
+																			type="checkbox"
+																			name=event.as_str()
+																			checked=events.contains(&event)
+																		/>
+																		{event.label()}
+																	</label>
+																}
+															})
+															.collect_view()}
+														<input type="submit" value="Save" />
+													</ActionForm>
+													<ActionForm action=delete>
+														<input type="hidden" name="id" value=integration.id />
+														<input type="submit" value="Remove" />
+													</ActionForm>
+												</li>
+											}
+										})
+										.collect_view()}
+								</ul>
+							}
+								.into_view()
+						}
+					})
+			}}
+
+		</Transition>
+		<ActionForm action=add>
+			<label>
+				"Post to "
+				<select
+					name="kind"
+					on:change=move |ev| {
+						if let Ok(kind) = event_target_value(&ev).parse() {
+							set_kind.set(kind);
+						}
+					}
+				>
+					<option value="slack">"Slack"</option>
+					<option value="matrix">"Matrix"</option>
+				</select>
+			</label>
+			<Show
+				when=move || kind.get() == IntegrationKind::Matrix
+				fallback=|| {
+					view! {
+						<label>
+							"Webhook " <input type="url" name="url" required=true placeholder=SLACK_WEBHOOK_PREFIX />
+						</label>
+					}
+				}
+			>
+				<label>
+					"Homeserver " <input type="url" name="url" required=true placeholder="https://matrix.org" />
+				</label>
+				<label>
+					"Room id " <input type="text" name="room" required=true placeholder="!room:matrix.org" />
+				</label>
+				<label>
+					"Access token " <input type="password" name="access_token" required=true autocomplete="off" />
+				</label>
+			</Show>
+			{IntegrationEvent::ALL
+				.into_iter()
+				.map(|event| {
+					view! {
+						<label>
+							<input type="checkbox" name=event.as_str() checked=true />
+							{event.label()}
+						</label>
+					}
+				})
+				.collect_view()}
+			<Show when=is_admin>
+				<label>
+					<input type="checkbox" name="tenant_wide" />
+					"For every todo of the organization"
+				</label>
+			</Show>
+			<input type="submit" value="Connect" />
+		</ActionForm>
+	}
+}
+
+#[cfg(all(test, feature = "ssr"))]
+mod tests {
+	use super::ssr::message;
+	use super::*;
+
+	#[test]
+	fn events_round_trip() {
+		for event in IntegrationEvent::ALL {
+			assert_eq!(event.as_str().parse::<IntegrationEvent>(), Ok(event));
+		}
+		assert!("todo_deleted".parse::<IntegrationEvent>().is_err());
+	}
+
+	#[test]
+	fn only_public_addresses_are_posted_to() {
+		use super::ssr::is_public;
+
+		for internal in [
+			"127.0.0.1",
+			"10.1.2.3",
+			"172.16.0.1",
+			"192.168.1.1",
+			"169.254.169.254",
+			"100.64.0.1",
+			"0.0.0.0",
+			"::1",
+			"fd00::1",
+			"fe80::1",
+			"::ffff:127.0.0.1",
+		] {
+			assert!(!is_public(internal.parse().unwrap()), "{internal}");
+		}
+		for public in ["93.184.216.34", "2606:2800:220:1::"] {
+			assert!(is_public(public.parse().unwrap()), "{public}");
+		}
+	}
+
+	#[test]
+	fn messages_are_escaped() {
+		let message = message("Fix <b> & \"co\"", "https://todos.example.com/todo/3", "is overdue");
+		assert_eq!(message.slack, "<https://todos.example.com/todo/3|Fix &lt;b&gt; &amp; \"co\"> is overdue");
+		assert_eq!(message.text, "\"Fix <b> & \"co\"\" is overdue: https://todos.example.com/todo/3");
+		assert_eq!(
+			message.html,
+			"<a href=\"https://todos.example.com/todo/3\">Fix &lt;b&gt; &amp; &quot;co&quot;</a> is overdue"
+		);
+	}
+}
@@ -0,0 +1,21 @@
+use std::{fmt::Display, future::Future, time::Duration};
+
+/// Run a job in the background every `interval`, failures are logged and the job runs again on the next tick
+///
+/// Jobs run on every replica, so they have to lock whatever they work on (`FOR UPDATE SKIP LOCKED`) to not do the
+/// same work twice
+pub fn every<F, Fut, E>(name: &'static str, interval: Duration, job: F)
+where
+	F: Fn() -> Fut + Send + 'static,
+	Fut: Future<Output = Result<(), E>> + Send,
+	E: Display,
+{
+	tokio::spawn(async move {
+		loop {
+			if let Err(error) = job().await {
+				log::error!("Job {name} failed: {error}");
+			}
+			tokio::time::sleep(interval).await;
+		}
+	});
+}
@@ -0,0 +1,288 @@
+use crate::errors::AppServerError;
+use leptos::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "ssr", derive(utoipa::ToSchema))]
+pub struct JwtTokens {
+	pub access_token: String,
+	pub refresh_token: String,
+	/// Seconds until the access token expires
+	pub expires_in: i64,
+}
+
+#[cfg(feature = "ssr")]
+pub mod ssr {
+	use super::JwtTokens;
+	use crate::{
+		api_token::{self, TokenScopes},
+		auth::User,
+		config::Config,
+		secrets::Secrets,
+		state::AppState,
+		tenant::Tenant,
+	};
+	use axum::{
+		extract::State,
+		http::{header, HeaderMap, StatusCode},
+		Json,
+	};
+	use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation};
+	use serde::{Deserialize, Serialize};
+	use sha2::{Digest, Sha256};
+	use sqlx::PgPool;
+
+	#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+	pub struct Claims {
+		/// The user id
+		pub sub: i32,
+		pub tenant: i32,
+		/// The refresh token family the token was issued for, logging out revokes it
+		pub sid: String,
+		pub iat: i64,
+		pub exp: i64,
+		/// What the API token the request came with is narrowed down to, JWTs are never narrowed
+		#[serde(skip)]
+		pub scopes: Option<TokenScopes>,
+	}
+
+	#[derive(Clone)]
+	pub struct JwtKeys {
+		algorithm: Algorithm,
+		encoding: EncodingKey,
+		decoding: DecodingKey,
+		expiry: i64,
+		refresh_expiry: i64,
+	}
+
+	// Keep the keys out of any logs
+	impl std::fmt::Debug for JwtKeys {
+		fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+			f.debug_struct("JwtKeys")
+				.field("algorithm", &self.algorithm)
+				.field("expiry", &self.expiry)
+				.field("refresh_expiry", &self.refresh_expiry)
+				.finish_non_exhaustive()
+		}
+	}
+
+	impl JwtKeys {
+		/// Take the signing keys from the secrets, an RSA key pair wins over `JWT_SECRET`, JWT auth is disabled when
+		/// neither is set, tokens expire after `JWT_EXPIRY` and `JWT_REFRESH_EXPIRY` of the config
+		pub fn from_config(config: &Config, secrets: &Secrets) -> Option<Self> {
+			let expiry = config.jwt_expiry;
+			let refresh_expiry = config.jwt_refresh_expiry;
+
+			if let (Some(private_key), Some(public_key)) = (&secrets.jwt_private_key, &secrets.jwt_public_key) {
+				return Some(Self {
+					algorithm: Algorithm::RS256,
+					encoding: EncodingKey::from_rsa_pem(private_key.as_bytes()).expect("Invalid JWT private key"),
+					decoding: DecodingKey::from_rsa_pem(public_key.as_bytes()).expect("Invalid JWT public key"),
+					expiry,
+					refresh_expiry,
+				});
+			}
+
+			let secret = secrets.jwt_secret.as_ref()?;
+			Some(Self {
+				algorithm: Algorithm::HS256,
+				encoding: EncodingKey::from_secret(secret.as_bytes()),
+				decoding: DecodingKey::from_secret(secret.as_bytes()),
+				expiry,
+				refresh_expiry,
+			})
+		}
+
+		pub fn issue(&self, user: &User, family: &str) -> Result<String, jsonwebtoken::errors::Error> {
+			let now = chrono::Utc::now().timestamp();
+			let claims = Claims {
+				sub: user.id,
+				tenant: user.tenant,
+				sid: family.to_string(),
+				iat: now,
+				exp: now + self.expiry,
+				scopes: None,
+			};
+
+			jsonwebtoken::encode(&Header::new(self.algorithm), &claims, &self.encoding)
+		}
+
+		pub fn verify(&self, token: &str) -> Result<Claims, jsonwebtoken::errors::Error> {
+			// Only accept the algorithm we sign with so a token can't pick a weaker one
+			let validation = Validation::new(self.algorithm);
+			jsonwebtoken::decode::<Claims>(token, &self.decoding, &validation).map(|data| data.claims)
+		}
+	}
+
+	// Refresh and login tokens are random and long so a fast unsalted hash keeps them useless if their table leaks
+	pub(crate) fn hash_token(token: &str) -> String {
+		Sha256::digest(token.as_bytes()).iter().map(|byte| format!("{byte:02x}")).collect()
+	}
+
+	pub(crate) fn generate_token(length: usize) -> String {
+		use rand::{distributions::Alphanumeric, Rng};
+
+		rand::thread_rng().sample_iter(&Alphanumeric).take(length).map(char::from).collect()
+	}
+
+	/// Issue an access token and a refresh token, a new login starts a new refresh token family while a refresh
+	/// continues the family of the token it used up
+	pub async fn issue_tokens(
+		keys: &JwtKeys,
+		user: &User,
+		family: Option<String>,
+		pool: &PgPool,
+	) -> Result<JwtTokens, String> {
+		let family = family.unwrap_or_else(|| generate_token(32));
+		let refresh_token = generate_token(64);
+
+		sqlx::query(
+			"INSERT INTO refresh_tokens (person, family, token_hash, expires_at)
+			VALUES ($1, $2, $3, now() + make_interval(secs => $4))",
+		)
+		.bind(user.id)
+		.bind(&family)
+		.bind(hash_token(&refresh_token))
+		.bind(keys.refresh_expiry as f64)
+		.execute(pool)
+		.await
+		.map_err(|error| error.to_string())?;
+
+		Ok(JwtTokens {
+			access_token: keys.issue(user, &family).map_err(|error| error.to_string())?,
+			refresh_token,
+			expires_in: keys.expiry,
+		})
+	}
+
+	pub async fn revoke_family(family: &str, pool: &PgPool) -> Result<(), sqlx::Error> {
+		sqlx::query("UPDATE refresh_tokens SET revoked = true WHERE family = $1").bind(family).execute(pool).await?;
+
+		Ok(())
+	}
+
+	#[derive(Debug, Deserialize)]
+	pub struct RefreshRequest {
+		refresh_token: String,
+	}
+
+	/// Trade a refresh token for a new token pair, every refresh token can only be used once
+	pub async fn refresh(
+		State(app_state): State<AppState>,
+		tenant: Tenant,
+		Json(request): Json<RefreshRequest>,
+	) -> Result<Json<JwtTokens>, (StatusCode, String)> {
+		app_state.schema.check()?;
+		let invalid_token = (StatusCode::UNAUTHORIZED, String::from("Invalid refresh token"));
+		let internal_error = |error: sqlx::Error| (StatusCode::INTERNAL_SERVER_ERROR, error.to_string());
+		let keys = app_state.jwt.as_ref().ok_or(invalid_token.clone())?;
+		let token_hash = hash_token(&request.refresh_token);
+
+		// Using up the token in the same statement we check it in means two concurrent refreshes can't both win
+		let used = sqlx::query_as::<_, (i32, String)>(
+			"UPDATE refresh_tokens SET used_at = now()
+			WHERE token_hash = $1 AND used_at IS NULL AND NOT revoked AND expires_at > now()
+			RETURNING person, family",
+		)
+		.bind(&token_hash)
+		.fetch_optional(&app_state.pool)
+		.await
+		.map_err(internal_error)?;
+
+		let Some((user_id, family)) = used else {
+			// A token that was already used has leaked (or was stolen from us), so nobody gets to keep the family
+			sqlx::query(
+				"UPDATE refresh_tokens SET revoked = true
+				WHERE family = (SELECT family FROM refresh_tokens WHERE token_hash = $1 AND used_at IS NOT NULL)",
+			)
+			.bind(&token_hash)
+			.execute(&app_state.pool)
+			.await
+			.map_err(internal_error)?;
+
+			return Err(invalid_token);
+		};
+
+		let user = app_state.auth_backend.load_user(user_id).await.map_err(|_| invalid_token.clone())?;
+		if user.tenant != tenant.id {
+			return Err(invalid_token);
+		}
+
+		issue_tokens(keys, &user, Some(family), &app_state.pool)
+			.await
+			.map(Json)
+			.map_err(|error| (StatusCode::INTERNAL_SERVER_ERROR, error))
+	}
+
+	pub fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+		headers.get(header::AUTHORIZATION)?.to_str().ok()?.strip_prefix("Bearer ")
+	}
+
+	/// Authenticate a request by its `Authorization: Bearer` header, with a JWT or the API token of a service account
+	///
+	/// Requests without one return `Ok(None)` so they fall back to the session cookie, a token that is present but
+	/// doesn't check out is rejected instead of silently ignored
+	pub async fn authenticate_bearer(
+		headers: &HeaderMap,
+		tenant: &Tenant,
+		app_state: &AppState,
+	) -> Result<Option<(Claims, User)>, (StatusCode, String)> {
+		let Some(token) = bearer_token(headers) else {
+			return Ok(None);
+		};
+
+		// API tokens work without JWT keys configured
+		if api_token::is_api_token(token) {
+			return api_token::authenticate(token, tenant, &app_state.pool).await.map(Some);
+		}
+
+		let invalid_token = (StatusCode::UNAUTHORIZED, String::from("Invalid token"));
+		let keys = app_state.jwt.as_ref().ok_or(invalid_token.clone())?;
+		let claims = keys.verify(token).map_err(|_| invalid_token.clone())?;
+
+		// Tokens are only valid for the tenant they were issued in
+		if claims.tenant != tenant.id {
+			return Err(invalid_token);
+		}
+
+		// Logging out or reusing a refresh token revokes the family, which takes its access tokens down with it
+		let revoked =
+			sqlx::query_scalar::<_, bool>("SELECT EXISTS (SELECT 1 FROM refresh_tokens WHERE family = $1 AND revoked)")
+				.bind(&claims.sid)
+				.fetch_one(&app_state.pool)
+				.await
+				.map_err(|error| (StatusCode::INTERNAL_SERVER_ERROR, error.to_string()))?;
+		if revoked {
+			return Err(invalid_token);
+		}
+
+		let user = app_state.auth_backend.load_user(claims.sub).await.map_err(|_| invalid_token)?;
+
+		Ok(Some((claims, user)))
+	}
+}
+
+/// Issue a JWT and refresh token for the logged in user so API clients can call server functions with a Bearer token
+#[server]
+pub async fn issue_jwt() -> Result<JwtTokens, ServerFnError<AppServerError>> {
+	use self::ssr::{issue_tokens, Claims, JwtKeys};
+	use crate::auth::{
+		get_user,
+		ssr::{require_recent_auth, STEP_UP_MAX_AGE},
+	};
+	use sqlx::PgPool;
+
+	let pool = use_context::<PgPool>().expect("Database not initialized");
+	let keys = use_context::<JwtKeys>().ok_or_else(|| AppServerError::not_found("JWT authentication is not enabled"))?;
+
+	// A token must not be able to mint itself a successor
+	if use_context::<Claims>().is_some() {
+		return Err(AppServerError::forbidden("Tokens can only be issued for a session login").into());
+	}
+
+	let user = get_user().await?.ok_or_else(AppServerError::unauthenticated)?;
+	// Tokens outlive the session, so whoever asks for them has to know the password
+	require_recent_auth(STEP_UP_MAX_AGE)?;
+
+	issue_tokens(&keys, &user, None, &pool).await.map_err(|error| AppServerError::internal(error).into())
+}
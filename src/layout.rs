@@ -0,0 +1,126 @@
+//! The frame pages are put into, laid out for small screens first
+//!
+//! On narrow screens the navigation folds away behind a menu button and sidebars move above the content, wider screens
+//! show both side by side. Rows can be swiped on touch screens with [`use_swipe`].
+
+use crate::routes::Route;
+use leptos::{ev::PointerEvent, *};
+use leptos_router::{use_location, A};
+
+/// How far a finger has to move sideways for a swipe, in CSS pixels
+const SWIPE_DISTANCE: i32 = 80;
+
+/// The header of every page, the title leads home and the navigation folds away on small screens
+#[component]
+pub fn AppHeader(#[prop(into)] title: String, children: Children) -> impl IntoView {
+	let (open, set_open) = create_signal(false);
+	// Following a link of the folded out navigation folds it away again
+	let location = use_location();
+	create_effect(move |_| {
+		location.pathname.track();
+		set_open.set(false);
+	});
+
+	view! {
+		<header class="app-header">
+			<div class="app-header-bar">
+				<A href=Route::Home class="brand">
+					<h1>{title}</h1>
+				</A>
+				<button
+					type="button"
+					class="nav-toggle"
+					aria-controls="app-nav"
+					aria-expanded=move || open.get().to_string()
+					on:click=move |_| set_open.update(|open| *open = !*open)
+				>
+					{move || if open.get() { "Close" } else { "Menu" }}
+				</button>
+			</div>
+			<nav id="app-nav" class="app-nav" class:open=move || open.get()>
+				{children()}
+			</nav>
+		</header>
+	}
+}
+
+/// What goes next to the content of a [`WithSidebar`]
+#[slot]
+pub struct Sidebar {
+	children: Children,
+}
+
+/// Content with a sidebar next to it, the sidebar goes above the content on small screens
+#[component]
+pub fn WithSidebar(sidebar: Sidebar, children: Children) -> impl IntoView {
+	view! {
+		<div class="with-sidebar">
+			<aside class="sidebar">{(sidebar.children)()}</aside>
+			<div class="content">{children()}</div>
+		</div>
+	}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SwipeDirection {
+	Left,
+	Right,
+}
+
+/// Which way a touch went, if it went far enough sideways and more sideways than up or down to not be scrolling
+pub fn swipe_direction(dx: i32, dy: i32) -> Option<SwipeDirection> {
+	if dx.abs() < SWIPE_DISTANCE || dy.abs() * 2 > dx.abs() {
+		return None;
+	}
+
+	Some(if dx > 0 {
+		SwipeDirection::Right
+	} else {
+		SwipeDirection::Left
+	})
+}
+
+/// Follows a finger across an element, mice and pens are left to click and drag
+#[derive(Clone, Copy)]
+pub struct Swipe {
+	start: StoredValue<Option<(i32, i32)>>,
+}
+
+/// Feed the pointer events of an element to the swipe and it tells which way the finger went once it's lifted,
+/// elements need `touch-action: pan-y` for browsers to hand sideways moves to them instead of scrolling
+pub fn use_swipe() -> Swipe {
+	Swipe {
+		start: store_value(None),
+	}
+}
+
+impl Swipe {
+	pub fn down(&self, ev: &PointerEvent) {
+		self.start.set_value((ev.pointer_type() == "touch").then(|| (ev.client_x(), ev.client_y())));
+	}
+
+	pub fn up(&self, ev: &PointerEvent) -> Option<SwipeDirection> {
+		let (x, y) = self.start.get_value()?;
+		self.cancel();
+
+		swipe_direction(ev.client_x() - x, ev.client_y() - y)
+	}
+
+	/// The browser took the touch over, to scroll for example
+	pub fn cancel(&self) {
+		self.start.set_value(None);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn swipes_are_sideways() {
+		assert_eq!(swipe_direction(120, 10), Some(SwipeDirection::Right));
+		assert_eq!(swipe_direction(-90, -20), Some(SwipeDirection::Left));
+		assert_eq!(swipe_direction(40, 0), None, "too short");
+		assert_eq!(swipe_direction(100, 80), None, "rather scrolling");
+	}
+}
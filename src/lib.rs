@@ -1,13 +1,102 @@
+#[cfg(feature = "ssr")]
+pub mod api_token;
+pub mod archive;
+pub mod attachment;
 pub mod auth;
+#[cfg(feature = "ssr")]
+pub mod auth_backend;
+#[cfg(feature = "ssr")]
+pub mod authorize;
+pub mod avatar;
+#[cfg(feature = "ssr")]
+pub mod breach;
+pub mod breaker;
+#[cfg(feature = "ssr")]
+pub mod cache;
+pub mod calendar;
+#[cfg(feature = "ssr")]
+pub mod cleanup;
+pub mod command;
+pub mod comment;
+#[cfg(feature = "ssr")]
+pub mod config;
 pub mod db;
+pub mod db_console;
+pub mod delegation;
+pub mod denial;
+#[cfg(feature = "docker")]
+pub mod dev_db;
+pub mod digest;
+pub mod draft;
 pub mod error_template;
 pub mod errors;
 #[cfg(feature = "ssr")]
+pub mod events;
+#[cfg(feature = "ssr")]
 pub mod fallback;
+pub mod flags;
+#[cfg(feature = "graphql")]
+pub mod graphql;
+pub mod history;
+pub mod identity;
+pub mod integration;
+#[cfg(feature = "ssr")]
+pub mod jobs;
+pub mod jwt;
+pub mod layout;
+pub mod live;
+pub mod magic_link;
+#[cfg(feature = "ssr")]
+pub mod mailer;
+#[cfg(feature = "ssr")]
+pub mod markdown;
+#[cfg(feature = "ssr")]
+pub mod migrations;
+pub mod notification;
+pub mod offline;
+#[cfg(feature = "ssr")]
+pub mod openapi;
+#[cfg(feature = "ssr")]
+pub mod pdf;
 pub mod permission;
+pub mod permission_schedule;
+pub mod permission_transfer;
+#[cfg(feature = "ssr")]
+pub mod policy;
+pub mod print;
+pub mod project;
+pub mod pwa;
+#[cfg(feature = "ssr")]
+pub mod rate_limit;
+pub mod recurrence;
+#[cfg(feature = "ssr")]
+pub mod rest;
+#[cfg(feature = "ssr")]
+pub mod retry;
+pub mod routes;
+#[cfg(feature = "saml")]
+pub mod saml;
+pub mod scope_badge;
+#[cfg(feature = "ssr")]
+pub mod secrets;
+pub mod service_account;
+pub mod share;
+pub mod startup;
 #[cfg(feature = "ssr")]
 pub mod state;
+#[cfg(feature = "ssr")]
+pub mod storage;
+pub mod tenant;
+pub mod toast;
 pub mod todo;
+pub mod todo_menu;
+pub mod undo;
+pub mod user_admin;
+pub mod user_import;
+pub mod username;
+pub mod validation;
+#[cfg(feature = "ssr")]
+pub mod visibility;
 
 #[cfg(feature = "hydrate")]
 #[wasm_bindgen::prelude::wasm_bindgen]
@@ -0,0 +1,210 @@
+//! Live updates from the server, over a WebSocket or server-sent events where WebSockets are blocked
+//!
+//! Both carry the same events filtered by the same permissions. The browser tries the WebSocket first and switches to
+//! server-sent events for the rest of the page once a WebSocket fails before it ever opened, which is what proxies
+//! that don't let WebSockets through look like.
+
+use crate::{auth::UserContext, routes::Route};
+use leptos::*;
+use serde::Deserialize;
+use std::{cell::Cell, rc::Rc, time::Duration};
+use wasm_bindgen::{closure::Closure, prelude::wasm_bindgen, JsCast, JsValue};
+
+pub const WEBSOCKET_PATH: &str = "/ws/events";
+pub const SSE_PATH: &str = "/events";
+
+/// How long to wait before connecting again after an open WebSocket closed
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Transport {
+	WebSocket,
+	ServerSentEvents,
+}
+
+thread_local! {
+	static TRANSPORT: Cell<Transport> = const { Cell::new(Transport::WebSocket) };
+}
+
+/// An event as the browser sees it, the ids are all components need to refetch what they show
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+pub struct LiveEvent {
+	/// The name of the event in snake case, like `todo_updated`
+	#[serde(rename = "type")]
+	pub kind: String,
+	#[serde(default)]
+	pub todo: Option<i32>,
+}
+
+impl LiveEvent {
+	/// Whether a todo was created, changed or deleted
+	pub fn changes_todos(&self) -> bool {
+		matches!(self.kind.as_str(), "todo_created" | "todo_updated" | "todo_deleted")
+	}
+
+	/// Whether the user logged out in another tab or on another device, the server only sends it to that user
+	pub fn ends_session(&self) -> bool {
+		self.kind == "user_logged_out"
+	}
+}
+
+#[wasm_bindgen]
+extern "C" {
+	type WebSocket;
+	#[wasm_bindgen(catch, constructor)]
+	fn new(url: &str) -> Result<WebSocket, JsValue>;
+	#[wasm_bindgen(method, setter)]
+	fn set_onopen(this: &WebSocket, handler: &JsValue);
+	#[wasm_bindgen(method, setter)]
+	fn set_onmessage(this: &WebSocket, handler: &JsValue);
+	#[wasm_bindgen(method, setter)]
+	fn set_onclose(this: &WebSocket, handler: &JsValue);
+	#[wasm_bindgen(method)]
+	fn close(this: &WebSocket);
+
+	type EventSource;
+	#[wasm_bindgen(catch, constructor)]
+	fn new(url: &str) -> Result<EventSource, JsValue>;
+	#[wasm_bindgen(method, setter)]
+	fn set_onmessage(this: &EventSource, handler: &JsValue);
+	#[wasm_bindgen(method)]
+	fn close(this: &EventSource);
+
+	type MessageEvent;
+	#[wasm_bindgen(method, getter)]
+	fn data(this: &MessageEvent) -> JsValue;
+}
+
+enum Connection {
+	WebSocket(WebSocket),
+	EventSource(EventSource),
+}
+
+impl Connection {
+	fn close(&self) {
+		match self {
+			// The close handler would connect again right away
+			Connection::WebSocket(socket) => {
+				socket.set_onclose(&JsValue::NULL);
+				socket.close();
+			},
+			Connection::EventSource(source) => source.close(),
+		}
+	}
+}
+
+type Handler = Rc<dyn Fn(LiveEvent)>;
+
+fn message_handler(on_event: Handler) -> JsValue {
+	Closure::<dyn Fn(JsValue)>::new(move |message: JsValue| {
+		let Some(data) = message.unchecked_into::<MessageEvent>().data().as_string() else {
+			return;
+		};
+		if let Ok(event) = serde_json::from_str::<LiveEvent>(&data) {
+			on_event(event);
+		}
+	})
+	.into_js_value()
+}
+
+fn websocket_url() -> String {
+	let location = window().location();
+	let scheme = if location.protocol().is_ok_and(|protocol| protocol == "https:") {
+		"wss"
+	} else {
+		"ws"
+	};
+
+	format!("{scheme}://{}{WEBSOCKET_PATH}", location.host().unwrap_or_default())
+}
+
+/// Keep the connection, or close it right away when the component went away in the meantime
+fn keep(connection: StoredValue<Option<Connection>>, new: Connection) {
+	if let Some(Some(new)) = connection.try_set_value(Some(new)) {
+		new.close();
+	}
+}
+
+fn connect(on_event: Handler, connection: StoredValue<Option<Connection>>) {
+	if TRANSPORT.with(Cell::get) == Transport::ServerSentEvents {
+		// The browser reconnects an EventSource on its own and gives up once the server refuses it
+		if let Ok(source) = EventSource::new(SSE_PATH) {
+			source.set_onmessage(&message_handler(on_event));
+			keep(connection, Connection::EventSource(source));
+		}
+		return;
+	}
+
+	let Ok(socket) = WebSocket::new(&websocket_url()) else {
+		TRANSPORT.with(|transport| transport.set(Transport::ServerSentEvents));
+		return connect(on_event, connection);
+	};
+	let opened = Rc::new(Cell::new(false));
+	socket.set_onopen(
+		&Closure::<dyn Fn()>::new({
+			let opened = opened.clone();
+			move || opened.set(true)
+		})
+		.into_js_value(),
+	);
+	socket.set_onmessage(&message_handler(on_event.clone()));
+	socket.set_onclose(
+		&Closure::<dyn Fn()>::new(move || {
+			if opened.get() {
+				let on_event = on_event.clone();
+				set_timeout(move || connect(on_event, connection), RECONNECT_DELAY);
+			} else {
+				TRANSPORT.with(|transport| transport.set(Transport::ServerSentEvents));
+				connect(on_event.clone(), connection);
+			}
+		})
+		.into_js_value(),
+	);
+	keep(connection, Connection::WebSocket(socket));
+}
+
+/// Call `on_event` with every event the logged in user may see for as long as the calling component lives
+///
+/// A logout elsewhere wipes what components hold through [`UserContext::logged_out`] and sends the page to the login.
+pub fn use_live_events(on_event: impl Fn(LiveEvent) + 'static) {
+	let context = use_context::<UserContext>();
+	let on_event: Handler = Rc::new(move |event: LiveEvent| {
+		if event.ends_session() {
+			if let Some(UserContext { logged_out, user, .. }) = context {
+				logged_out.notify();
+				user.refetch();
+			}
+			// Replaced so going back doesn't show the page of the user again
+			_ = window().location().replace(&Route::Login.path());
+		}
+		on_event(event);
+	});
+	let connection = store_value(None::<Connection>);
+
+	// Effects only run in the browser
+	create_effect(move |_| connect(on_event.clone(), connection));
+	on_cleanup(move || {
+		connection.try_update_value(|connection| {
+			if let Some(connection) = connection.take() {
+				connection.close();
+			}
+		});
+	});
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn events_are_read_from_both_transports() {
+		let event = serde_json::from_str::<LiveEvent>(r#"{"type":"todo_updated","tenant":1,"user":2,"todo":3}"#).unwrap();
+		assert_eq!(event.todo, Some(3));
+		assert!(event.changes_todos());
+
+		let event = serde_json::from_str::<LiveEvent>(r#"{"type":"user_logged_out","tenant":1,"user":2}"#).unwrap();
+		assert_eq!(event.todo, None);
+		assert!(!event.changes_todos());
+		assert!(event.ends_session());
+	}
+}
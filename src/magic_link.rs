@@ -0,0 +1,416 @@
+use crate::errors::AppServerError;
+use leptos::*;
+use leptos_router::*;
+
+/// How long the link that confirms a new email address works
+pub const EMAIL_CONFIRMATION_HOURS: i32 = 24;
+
+#[cfg(feature = "ssr")]
+pub mod ssr {
+	use crate::{
+		auth::ssr::{start_session, AuthSession},
+		events::{record, DomainEvent},
+		identity::{ssr::link_identity, Provider},
+		jwt::ssr::hash_token,
+		routes::Route,
+		state::AppState,
+		tenant::Tenant,
+	};
+	use axum::{
+		extract::{Path, State},
+		http::{header, HeaderMap, StatusCode},
+		response::{Html, Redirect},
+	};
+
+	/// Whether a form was posted from a page of the app itself, so another site can't log the browser in to an account
+	/// of its choosing
+	///
+	/// Browsers that send `Sec-Fetch-Site` are taken by it, older ones by the `Origin` or `Referer` they send.
+	pub(super) fn same_origin(headers: &HeaderMap) -> bool {
+		if let Some(site) = headers.get("sec-fetch-site") {
+			return site == "same-origin";
+		}
+		let Some(host) = headers.get(header::HOST).and_then(|host| host.to_str().ok()) else {
+			return false;
+		};
+
+		headers
+			.get(header::ORIGIN)
+			.or_else(|| headers.get(header::REFERER))
+			.and_then(|origin| origin.to_str().ok())
+			.and_then(|origin| origin.split_once("://"))
+			.is_some_and(|(_, rest)| rest.split(['/', '?', '#']).next() == Some(host))
+	}
+
+	fn cross_site() -> (StatusCode, String) {
+		(StatusCode::FORBIDDEN, String::from("Open the link again and confirm on the page it shows"))
+	}
+
+	/// Ask before logging in, mail scanners follow links and would use every one of them up
+	pub async fn magic_link_page(Path(token): Path<String>) -> Html<String> {
+		Html(format!(
+			"<!DOCTYPE html><html><head><title>Log in</title></head><body>\
+			<form method=\"post\" action=\"/auth/magic/{}\"><p>Log in to your todos?</p>\
+			<input type=\"submit\" value=\"Log in\" /></form></body></html>",
+			token.chars().filter(char::is_ascii_alphanumeric).collect::<String>()
+		))
+	}
+
+	/// Log in with the token from a magic link, a link works once and only on the tenant it was sent from
+	pub async fn magic_login(
+		State(app_state): State<AppState>,
+		auth_session: AuthSession,
+		tenant: Tenant,
+		headers: HeaderMap,
+		Path(token): Path<String>,
+	) -> Result<Redirect, (StatusCode, String)> {
+		app_state.schema.check()?;
+		if !same_origin(&headers) {
+			return Err(cross_site());
+		}
+		let internal_error = |error: sqlx::Error| (StatusCode::INTERNAL_SERVER_ERROR, error.to_string());
+
+		// Using the link up in the same statement that finds it keeps two clicks from both logging in
+		let user_id = sqlx::query_scalar::<_, i32>(
+			"UPDATE magic_links SET used_at = now()
+			WHERE token_hash = $1 AND used_at IS NULL AND expires_at > now()
+				AND person IN (SELECT id FROM users WHERE tenant = $2 AND active)
+			RETURNING person",
+		)
+		.bind(hash_token(&token))
+		.bind(tenant.id)
+		.fetch_optional(&app_state.pool)
+		.await
+		.map_err(internal_error)?
+		.ok_or((StatusCode::NOT_FOUND, String::from("Unknown, expired or already used login link")))?;
+
+		record(
+			&app_state.pool,
+			&DomainEvent::UserLoggedIn {
+				tenant: tenant.id,
+				user: user_id,
+			},
+		)
+		.await
+		.map_err(internal_error)?;
+		start_session(&auth_session, user_id, false, &app_state.config, &app_state.pool).await.map_err(internal_error)?;
+
+		Ok(Redirect::to(&Route::Home.path()))
+	}
+
+	/// Ask before changing the address, for the same reason as [`magic_link_page`]
+	pub async fn email_confirmation_page(Path(token): Path<String>) -> Html<String> {
+		Html(format!(
+			"<!DOCTYPE html><html><head><title>Confirm email</title></head><body>\
+			<form method=\"post\" action=\"/auth/email/{}\"><p>Log in with this email address from now on?</p>\
+			<input type=\"submit\" value=\"Confirm\" /></form></body></html>",
+			token.chars().filter(char::is_ascii_alphanumeric).collect::<String>()
+		))
+	}
+
+	/// Switch the login email over to the address the token was mailed to, works without being logged in
+	pub async fn confirm_email(
+		State(app_state): State<AppState>,
+		tenant: Tenant,
+		headers: HeaderMap,
+		Path(token): Path<String>,
+	) -> Result<Redirect, (StatusCode, String)> {
+		app_state.schema.check()?;
+		if !same_origin(&headers) {
+			return Err(cross_site());
+		}
+		let internal_error = |error: sqlx::Error| (StatusCode::INTERNAL_SERVER_ERROR, error.to_string());
+
+		let mut transaction = app_state.pool.begin().await.map_err(internal_error)?;
+		let (person, email) = sqlx::query_as::<_, (i32, String)>(
+			"DELETE FROM email_changes WHERE token_hash = $1 AND tenant = $2 AND expires_at > now() RETURNING person, email",
+		)
+		.bind(hash_token(&token))
+		.bind(tenant.id)
+		.fetch_optional(&mut *transaction)
+		.await
+		.map_err(internal_error)?
+		.ok_or((StatusCode::NOT_FOUND, String::from("Unknown, expired or already used confirmation link")))?;
+		link_identity(&mut *transaction, tenant.id, person, Provider::MagicLink, &email).await.map_err(
+			|error| match error {
+				sqlx::Error::Database(error) if error.is_unique_violation() => {
+					(StatusCode::CONFLICT, String::from("This email address is already used by another account"))
+				},
+				error => internal_error(error),
+			},
+		)?;
+		// Links mailed to the old address would still log in otherwise
+		sqlx::query("DELETE FROM magic_links WHERE person = $1")
+			.bind(person)
+			.execute(&mut *transaction)
+			.await
+			.map_err(internal_error)?;
+		record(
+			&mut *transaction,
+			&DomainEvent::LoginEmailChanged {
+				tenant: tenant.id,
+				user: person,
+			},
+		)
+		.await
+		.map_err(internal_error)?;
+		transaction.commit().await.map_err(internal_error)?;
+
+		Ok(Redirect::to(&Route::Settings.path()))
+	}
+}
+
+/// Email a login link to the user with this address, succeeds for unknown addresses too so nobody can find out who
+/// has an account
+#[server]
+pub async fn request_magic_link(email: String) -> Result<(), ServerFnError<AppServerError>> {
+	use crate::{
+		config::Config,
+		identity::{ssr::find_identity, Provider},
+		jwt::ssr::{generate_token, hash_token},
+		mailer::{Mail, Mailer},
+		rate_limit::{check_login, request_client, RateLimiter},
+		tenant::Tenant,
+		validation::EMAIL,
+	};
+	use sqlx::PgPool;
+	use std::sync::Arc;
+
+	let pool = use_context::<PgPool>().expect("Database not initialized");
+	let mailer = use_context::<Arc<dyn Mailer>>().expect("No mailer found");
+	let limiter = use_context::<Arc<dyn RateLimiter>>().expect("No rate limiter found");
+	let config = use_context::<Config>().expect("No config found");
+	let tenant = use_context::<Tenant>().expect("No tenant found");
+	let email = EMAIL.clean(&email).map_err(AppServerError::invalid)?.to_lowercase();
+
+	// Every link counts like a login that failed so nobody can flood an inbox
+	check_login(limiter.as_ref(), &config, tenant.id, &email, request_client(&config))
+		.await
+		.map_err(|error| AppServerError::new(error.code(), error))?;
+
+	let Some(person) =
+		find_identity(tenant.id, Provider::MagicLink, &email, &pool).await.map_err(AppServerError::from)?
+	else {
+		return Ok(());
+	};
+
+	let token = generate_token(48);
+	sqlx::query(
+		"INSERT INTO magic_links (token_hash, person, expires_at) VALUES ($1, $2, now() + make_interval(secs => $3))",
+	)
+	.bind(hash_token(&token))
+	.bind(person)
+	.bind(config.magic_link_lifetime as f64)
+	.execute(&pool)
+	.await
+	.map_err(AppServerError::from)?;
+
+	mailer
+		.send(Mail {
+			to: email,
+			subject: String::from("Your login link"),
+			body: format!(
+				"Log in to your todos with this link:\n{}/auth/magic/{token}\n\nIt works once within the next {} minutes. \
+				If you didn't ask for it you can ignore this mail.",
+				config.public_url,
+				config.magic_link_lifetime / 60
+			),
+		})
+		.await
+		.map_err(AppServerError::internal)?;
+
+	Ok(())
+}
+
+/// Link the address login links are sent to, or change it when there already is one
+///
+/// Nothing changes until the link mailed to the new address is followed, the old address is told about the change so
+/// a hijacked session can't quietly take the account over
+#[server]
+pub async fn set_login_email(email: String) -> Result<(), ServerFnError<AppServerError>> {
+	use crate::{
+		auth::{
+			get_user,
+			ssr::{require_recent_auth, STEP_UP_MAX_AGE},
+		},
+		config::Config,
+		identity::{
+			ssr::{find_identity, list_identities},
+			Provider,
+		},
+		jwt::ssr::{generate_token, hash_token},
+		mailer::{Mail, Mailer},
+		validation::EMAIL,
+	};
+	use sqlx::PgPool;
+	use std::sync::Arc;
+
+	let pool = use_context::<PgPool>().expect("Database not initialized");
+	let mailer = use_context::<Arc<dyn Mailer>>().expect("No mailer found");
+	let config = use_context::<Config>().expect("No config found");
+	let user = get_user().await?.ok_or_else(AppServerError::unauthenticated)?;
+	// Whoever controls the address can log in, so changing it needs the password
+	require_recent_auth(STEP_UP_MAX_AGE)?;
+
+	let email = EMAIL.clean(&email).map_err(AppServerError::invalid)?.to_lowercase();
+	if !email.contains('@') {
+		return Err(AppServerError::invalid("Invalid email address").into());
+	}
+
+	let current = list_identities(user.id, &pool)
+		.await
+		.map_err(AppServerError::from)?
+		.into_iter()
+		.find(|identity| identity.provider == Provider::MagicLink)
+		.map(|identity| identity.subject);
+	if current.as_deref() == Some(email.as_str()) {
+		return Err(AppServerError::invalid("This is your email address already").into());
+	}
+	if find_identity(user.tenant, Provider::MagicLink, &email, &pool).await.map_err(AppServerError::from)?.is_some() {
+		return Err(AppServerError::conflict("This email address is already used by another account").into());
+	}
+
+	// Asking again replaces the change waiting so far, its link stops working
+	let token = generate_token(48);
+	sqlx::query(
+		"INSERT INTO email_changes (tenant, person, email, token_hash, expires_at)
+		VALUES ($1, $2, $3, $4, now() + make_interval(hours => $5))
+		ON CONFLICT (person) DO UPDATE SET email = EXCLUDED.email, token_hash = EXCLUDED.token_hash,
+			expires_at = EXCLUDED.expires_at, created_at = now()",
+	)
+	.bind(user.tenant)
+	.bind(user.id)
+	.bind(&email)
+	.bind(hash_token(&token))
+	.bind(EMAIL_CONFIRMATION_HOURS)
+	.execute(&pool)
+	.await
+	.map_err(AppServerError::from)?;
+
+	mailer
+		.send(Mail {
+			to: email.clone(),
+			subject: String::from("Confirm your email address"),
+			body: format!(
+				"Confirm that login links for {} should go to this address:\n{}/auth/email/{token}\n\nThe link works \
+				 within the next {EMAIL_CONFIRMATION_HOURS} hours. If you didn't ask for it you can ignore this mail.",
+				user.username, config.public_url,
+			),
+		})
+		.await
+		.map_err(AppServerError::internal)?;
+	if let Some(current) = current {
+		mailer
+			.send(Mail {
+				to: current,
+				subject: String::from("Your email address is about to change"),
+				body: format!(
+					"Someone asked to send the login links for {} to {email} from now on. Nothing changes until the new \
+					 address is confirmed. If that wasn't you, change your password and remove the change from your \
+					 settings.",
+					user.username
+				),
+			})
+			.await
+			.map_err(AppServerError::internal)?;
+	}
+
+	Ok(())
+}
+
+/// The address the logged in user asked to change their login email to, until it is confirmed or runs out
+#[server(input = GetUrl)]
+pub async fn get_pending_login_email() -> Result<Option<String>, ServerFnError<AppServerError>> {
+	use crate::{auth::get_user, cache::cache_privately};
+	use sqlx::PgPool;
+
+	cache_privately::<GetPendingLoginEmail>(0);
+
+	let pool = use_context::<PgPool>().expect("Database not initialized");
+	let user = get_user().await?.ok_or_else(AppServerError::unauthenticated)?;
+
+	Ok(
+		sqlx::query_scalar::<_, String>("SELECT email FROM email_changes WHERE person = $1 AND expires_at > now()")
+			.bind(user.id)
+			.fetch_optional(&pool)
+			.await
+			.map_err(AppServerError::from)?,
+	)
+}
+
+/// Drop the email change waiting for confirmation, its link stops working
+#[server]
+pub async fn cancel_login_email_change() -> Result<(), ServerFnError<AppServerError>> {
+	use crate::auth::get_user;
+	use sqlx::PgPool;
+
+	let pool = use_context::<PgPool>().expect("Database not initialized");
+	let user = get_user().await?.ok_or_else(AppServerError::unauthenticated)?;
+
+	sqlx::query("DELETE FROM email_changes WHERE person = $1")
+		.bind(user.id)
+		.execute(&pool)
+		.await
+		.map_err(AppServerError::from)?;
+
+	Ok(())
+}
+
+#[component]
+pub fn MagicLinkLogin() -> impl IntoView {
+	let request = create_server_action::<RequestMagicLink>();
+
+	view! {
+		<ActionForm action=request>
+			<h2>"Log in without a password"</h2>
+			<label>
+				"Email:" <input type="email" name="email" required=true class="auth-input" />
+			</label>
+			<button type="submit" class="button">
+				"Email me a login link"
+			</button>
+		</ActionForm>
+		{move || {
+			request
+				.value()
+				.get()
+				.map(|result| match result {
+					Ok(()) => view! { <span>"If the address belongs to an account a login link is on its way."</span> }
+						.into_view(),
+					Err(e) => view! { <span class="error">{e.to_string()}</span> }.into_view(),
+				})
+		}}
+	}
+}
+
+#[cfg(all(test, feature = "ssr"))]
+mod tests {
+	use super::ssr::same_origin;
+	use axum::http::HeaderMap;
+
+	#[test]
+	fn only_forms_of_the_app_log_in() {
+		let headers = |pairs: &[(&'static str, &'static str)]| {
+			let mut headers = HeaderMap::new();
+			for (name, value) in pairs {
+				headers.insert(*name, value.parse().unwrap());
+			}
+			headers
+		};
+
+		assert!(same_origin(&headers(&[("sec-fetch-site", "same-origin")])));
+		assert!(!same_origin(&headers(&[("sec-fetch-site", "cross-site"), ("host", "todos.example.com")])));
+		assert!(same_origin(&headers(&[("host", "todos.example.com"), ("origin", "https://todos.example.com")])));
+		assert!(same_origin(&headers(&[
+			("host", "todos.example.com"),
+			("referer", "https://todos.example.com/auth/magic/a")
+		])));
+		assert!(!same_origin(&headers(&[("host", "todos.example.com"), ("origin", "https://evil.example")])));
+		assert!(!same_origin(&headers(&[
+			("host", "todos.example.com"),
+			("origin", "https://todos.example.com.evil.example")
+		])));
+		assert!(!same_origin(&headers(&[("host", "todos.example.com")])));
+		assert!(!same_origin(&headers(&[("origin", "https://todos.example.com")])));
+	}
+}
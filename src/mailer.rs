@@ -0,0 +1,91 @@
+use crate::secrets::Secrets;
+use async_trait::async_trait;
+use std::{fmt::Debug, sync::Arc};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum MailError {
+	#[error("Invalid email address: {0}")]
+	InvalidAddress(String),
+	#[error("Mail error: {0}")]
+	Backend(String),
+}
+
+/// A plain text email
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mail {
+	pub to: String,
+	pub subject: String,
+	pub body: String,
+}
+
+#[async_trait]
+pub trait Mailer: Debug + Send + Sync {
+	async fn send(&self, mail: Mail) -> Result<(), MailError>;
+}
+
+/// Pick the mailer from the environment, mails are only logged when the `SMTP_URL` secret isn't set
+/// - `MAIL_FROM`: the sender address, defaults to `todos@localhost`
+pub fn from_env(secrets: &Secrets) -> Arc<dyn Mailer> {
+	let from = std::env::var("MAIL_FROM").unwrap_or(String::from("todos@localhost"));
+
+	match &secrets.smtp_url {
+		Some(url) => Arc::new(SmtpMailer::new(url, &from).expect("Unable to configure SMTP")),
+		None => Arc::new(LogMailer),
+	}
+}
+
+/// Logs mails instead of sending them, for development
+#[derive(Debug, Clone)]
+pub struct LogMailer;
+
+#[async_trait]
+impl Mailer for LogMailer {
+	async fn send(&self, mail: Mail) -> Result<(), MailError> {
+		log::info!("Mail to {} \"{}\":\n{}", mail.to, mail.subject, mail.body);
+		Ok(())
+	}
+}
+
+#[derive(Clone)]
+pub struct SmtpMailer {
+	transport: lettre::AsyncSmtpTransport<lettre::Tokio1Executor>,
+	from: lettre::message::Mailbox,
+}
+
+// The transport holds the SMTP credentials
+impl Debug for SmtpMailer {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.debug_struct("SmtpMailer").field("from", &self.from).finish_non_exhaustive()
+	}
+}
+
+impl SmtpMailer {
+	pub fn new(url: &str, from: &str) -> Result<Self, MailError> {
+		Ok(Self {
+			transport: lettre::AsyncSmtpTransport::<lettre::Tokio1Executor>::from_url(url)
+				.map_err(|error| MailError::Backend(error.to_string()))?
+				.build(),
+			from: from.parse().map_err(|_| MailError::InvalidAddress(from.to_string()))?,
+		})
+	}
+}
+
+#[async_trait]
+impl Mailer for SmtpMailer {
+	async fn send(&self, mail: Mail) -> Result<(), MailError> {
+		use lettre::{message::header::ContentType, AsyncTransport, Message};
+
+		let message = Message::builder()
+			.from(self.from.clone())
+			.to(mail.to.parse().map_err(|_| MailError::InvalidAddress(mail.to.clone()))?)
+			.subject(mail.subject)
+			.header(ContentType::TEXT_PLAIN)
+			.body(mail.body)
+			.map_err(|error| MailError::Backend(error.to_string()))?;
+
+		self.transport.send(message).await.map_err(|error| MailError::Backend(error.to_string()))?;
+
+		Ok(())
+	}
+}
@@ -2,76 +2,234 @@ pub mod db;
 
 use axum::{
 	body::Body as AxumBody,
-	extract::{Path, State},
+	error_handling::HandleErrorLayer,
+	extract::{DefaultBodyLimit, Path, State},
 	http::Request,
+	middleware,
 	response::{IntoResponse, Response},
-	routing::get,
+	routing::{get, post},
 	Router,
 };
-use axum_session::{SessionConfig, SessionLayer, SessionStore};
-use axum_session_auth::{AuthConfig, AuthSessionLayer};
+use axum_session::SessionAnyPool;
 pub use axum_session_sqlx::SessionPgPool;
 use leptos::{get_configuration, logging::log, provide_context};
 use leptos_axum::{generate_route_list, handle_server_fns_with_context, LeptosRoutes};
 use session_auth_axum::{
-	auth::{ssr::AuthSession, User},
-	fallback::file_and_error_handler,
+	archive,
+	attachment::ssr::{download_attachment, upload_attachment},
+	auth::ssr::{AuthSession, AuthStack, RequestUser},
+	auth_backend::{AuthBackend, PgAuthBackend},
+	authorize,
+	avatar::ssr::{serve_avatar, upload_avatar},
+	breach::PasswordBreachCheck,
+	breaker, cache, calendar, cleanup,
+	config::{Config, StoreBackend},
+	digest::{
+		self,
+		ssr::{unsubscribe, unsubscribe_page},
+	},
+	events::{self, EventBus, OutboxPoller},
+	fallback::{
+		file_and_error_handler, overloaded, requires_login, unauthenticated, unauthorized_responses, with_error_status,
+	},
+	flags::FeatureFlags,
+	integration,
+	jwt::ssr::{authenticate_bearer, refresh, JwtKeys},
+	live,
+	magic_link::ssr::{confirm_email, email_confirmation_page, magic_link_page, magic_login},
+	mailer,
+	migrations::{self, SchemaGuard},
+	notification, openapi, permission, permission_schedule, print,
+	pwa::ssr::{manifest, serve_icon, service_worker, Pwa},
+	rate_limit::{self, RateLimitError},
+	recurrence, rest,
+	secrets::Secrets,
+	startup::StartupReport,
 	state::AppState,
+	storage,
+	tenant::Tenant,
 	todo::*,
+	visibility,
 };
-use sqlx::PgPool;
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+use tower::{limit::GlobalConcurrencyLimitLayer, ServiceBuilder};
+use tower_http::{
+	compression::CompressionLayer, limit::RequestBodyLimitLayer, timeout::TimeoutLayer, trace::TraceLayer,
+};
+use tracing::Instrument;
+use tracing_subscriber::EnvFilter;
+
+// Flags failing to load shouldn't take the app down with them, everything behind a flag just stays off
+async fn feature_flags(tenant: &Tenant, app_state: &AppState) -> FeatureFlags {
+	FeatureFlags::get_for_tenant(tenant.id, &app_state.pool).await.unwrap_or_else(|error| {
+		log::error!("Loading the feature flags of tenant {} failed: {error}", tenant.id);
+		FeatureFlags::default()
+	})
+}
 
 async fn server_fn_handler(
 	State(app_state): State<AppState>,
-	auth_session: AuthSession,
+	mut auth_session: AuthSession,
+	tenant: Tenant,
 	path: Path<String>,
 	request: Request<AxumBody>,
-) -> impl IntoResponse {
+) -> Response {
 	log!("{:?}", path);
 
-	handle_server_fns_with_context(
+	// API clients can send a JWT instead of the session cookie
+	let claims = match authenticate_bearer(request.headers(), &tenant, &app_state).await {
+		Ok(Some((claims, user))) => {
+			auth_session.current_user = Some(user);
+			Some(claims)
+		},
+		Ok(None) => None,
+		Err(error) => return error.into_response(),
+	};
+	if let Some(refused) = migrations::refuse_server_fn(request.uri().path(), &app_state.schema) {
+		return refused;
+	}
+	if let Some(user) = &auth_session.current_user {
+		let name = rate_limit::server_fn_name(request.uri().path());
+		match rate_limit::check_server_fn(app_state.rate_limiter.as_ref(), &app_state.config, user.id, name).await {
+			Ok(()) => {},
+			Err(RateLimitError::Limited { retry_after }) => return rate_limit::refuse_server_fn(retry_after),
+			// Nobody should be locked out of the app because the counters can't be reached
+			Err(error) => log::error!("Rate limiting {name} failed: {error}"),
+		}
+	}
+	let flags = feature_flags(&tenant, &app_state).await;
+	// Every statement the server function runs is logged inside this span
+	let span = tracing::info_span!(
+		"server_fn",
+		name = %path.as_str(),
+		tenant = tenant.id,
+		user = auth_session.current_user.as_ref().map(|user| user.id),
+	);
+
+	let response = handle_server_fns_with_context(
 		move || {
 			provide_context(auth_session.clone());
+			provide_context(RequestUser::default());
+			provide_context(tenant.clone());
 			provide_context(app_state.pool.clone());
+			provide_context(app_state.auth_backend.clone());
+			provide_context(app_state.rate_limiter.clone());
+			provide_context(app_state.config.clone());
+			provide_context(app_state.breach_check.clone());
+			provide_context(app_state.mailer.clone());
+			provide_context(app_state.startup.clone());
+			provide_context(flags.clone());
+			if let Some(keys) = app_state.jwt.clone() {
+				provide_context(keys);
+			}
+			if let Some(claims) = claims.clone() {
+				provide_context(claims);
+			}
 		},
 		request,
 	)
-	.await
+	.instrument(span)
+	.await;
+
+	with_error_status(response.into_response()).await
 }
 
 async fn leptos_routes_handler(
 	auth_session: AuthSession,
+	tenant: Tenant,
 	State(app_state): State<AppState>,
 	req: Request<AxumBody>,
 ) -> Response {
+	if requires_login(req.uri().path())
+		&& !auth_session.current_user.as_ref().is_some_and(|user| user.tenant == tenant.id)
+	{
+		return unauthenticated().into_response();
+	}
+
+	let flags = feature_flags(&tenant, &app_state).await;
 	let handler = leptos_axum::render_route_with_context(
 		app_state.leptos_options.clone(),
 		app_state.routes.clone(),
 		move || {
 			provide_context(auth_session.clone());
+			provide_context(RequestUser::default());
+			provide_context(tenant.clone());
 			provide_context(app_state.pool.clone());
+			provide_context(app_state.auth_backend.clone());
+			provide_context(app_state.config.clone());
+			provide_context(app_state.startup.clone());
+			provide_context(flags.clone());
 		},
 		TodoApp,
 	);
 	handler(req).await.into_response()
 }
 
+/// The store sessions are kept in, Redis takes the load of reading the session on every request off Postgres
+fn session_pool(config: &Config) -> SessionAnyPool {
+	use crate::db::ssr::get_session_db;
+
+	match config.session_backend {
+		StoreBackend::Postgres => SessionAnyPool::new(SessionPgPool::from(get_session_db().clone())),
+		#[cfg(feature = "redis")]
+		StoreBackend::Redis => {
+			use axum_session_redispool::SessionRedisPool;
+			use redis_pool::RedisPool;
+
+			let client = redis::Client::open(config.redis_url.as_str()).expect("Invalid REDIS_URL");
+			SessionAnyPool::new(SessionRedisPool::from(RedisPool::from(client)))
+		},
+		#[cfg(not(feature = "redis"))]
+		StoreBackend::Redis => panic!("The redis session backend needs the redis feature enabled"),
+	}
+}
+
+const SESSION_TABLE: &str = "axum_sessions";
+
 #[tokio::main]
 async fn main() {
-	use crate::db::ssr::{get_db, init_db};
+	use crate::db::ssr::{get_db, get_session_db, init_db, init_session_db};
 
-	simple_logger::init_with_level(log::Level::Info).expect("couldn't initialize logging");
+	// `RUST_LOG` picks what's logged, defaults to info, `sqlx::query=debug` adds every statement with its duration
+	tracing_subscriber::fmt()
+		.with_env_filter(EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")))
+		.init();
 
-	init_db().await.expect("Initialization of database failed");
+	#[cfg(feature = "docker")]
+	let _dev_database = session_auth_axum::dev_db::provision().await.expect("Unable to start the disposable dev database");
+	let secrets = Secrets::from_env().unwrap_or_else(|error| {
+		log::error!("Missing secrets: {error}");
+		std::process::exit(1);
+	});
+	let config = Config::from_env();
+	visibility::init(&config);
+	cache::init_response_cache(&config);
+	permission::ssr::init_scope_watch(&config);
+	breaker::ssr::init_breaker(&config);
+	init_db(&secrets.database_url).await.expect("Initialization of database failed");
+	init_session_db(secrets.session_database_url.as_deref(), &secrets.database_url)
+		.await
+		.expect("Initialization of session database failed");
+	#[cfg(feature = "saml")]
+	session_auth_axum::saml::init_saml().expect("Initialization of SAML failed");
 
 	// Auth section
-	let session_config = SessionConfig::default().with_table_name("axum_sessions");
-	let auth_config = AuthConfig::<i32>::default();
-	let session_store =
-		SessionStore::<SessionPgPool>::new(Some(SessionPgPool::from(get_db().clone())), session_config).await.unwrap();
+	// Swap this out to authenticate against a different user store
+	let auth_backend: Arc<dyn AuthBackend> =
+		Arc::new(PgAuthBackend::new(get_db().clone(), secrets.password_pepper.clone()));
+	let auth_stack = AuthStack::builder(session_pool(&config))
+		.table_name(SESSION_TABLE)
+		.backend(auth_backend.clone())
+		.build()
+		.await
+		.unwrap();
+
+	let schema = SchemaGuard::default();
+	migrations::run(config.migrations, get_db(), &schema).await;
 
-	if let Err(e) = sqlx::migrate!().run(&get_db().clone()).await {
-		eprintln!("{e:?}");
+	// Invalid policies stop the server right away instead of on the first request that needs them
+	if let Some(policies) = authorize::policies() {
+		log!("authorizing with {} policies", policies.len());
 	}
 
 	// Setting this to None means we'll be using cargo-leptos and its env vars
@@ -80,24 +238,121 @@ async fn main() {
 	let addr = leptos_options.site_addr;
 	let routes = generate_route_list(TodoApp);
 
+	let events = EventBus::default();
+	let mailer = mailer::from_env(&secrets);
+	OutboxPoller::from_config(get_db().clone(), events.clone(), &config).spawn();
+	recurrence::ssr::spawn_scheduler(get_db().clone(), &config);
+	permission_schedule::ssr::spawn_scheduler(get_db().clone(), &config);
+	notification::ssr::spawn_notifier(get_db().clone(), &events);
+	integration::ssr::spawn_integrations(get_db().clone(), &config);
+	cache::spawn_invalidator(&events);
+	visibility::spawn_rebuild(get_db().clone());
+	cleanup::spawn_cleanup(get_db().clone(), get_session_db().clone(), config.clone());
+	archive::ssr::spawn_archiver(get_db().clone(), &config);
+	digest::ssr::spawn_digests(get_db().clone(), mailer.clone(), config.clone());
+
+	let pwa = Pwa::build(&leptos_options);
+	let startup =
+		Arc::new(StartupReport::build(&config, &secrets, addr, SESSION_TABLE, get_db(), get_session_db()).await);
+
 	let app_state = AppState {
 		leptos_options,
 		routes: routes.clone(),
 		pool: get_db().clone(),
+		session_pool: get_session_db().clone(),
+		storage: storage::from_env(),
+		rate_limiter: rate_limit::from_config(&config, get_db().clone()),
+		auth_backend,
+		config: config.clone(),
+		jwt: JwtKeys::from_config(&config, &secrets),
+		events,
+		mailer,
+		schema,
+		breach_check: PasswordBreachCheck::from_config(&config),
+		pwa,
+		startup: startup.clone(),
 	};
 
+	// Keep slow or giant requests from tying up workers
+	// The timeout sits inside the body limit so it can answer with its own empty 408 body
+	let server_fn_limits = ServiceBuilder::new()
+		.layer(RequestBodyLimitLayer::new(config.body_limit))
+		.layer(TimeoutLayer::new(Duration::from_secs(config.request_timeout)));
+	let upload_limits = ServiceBuilder::new()
+		.layer(RequestBodyLimitLayer::new(config.upload_body_limit))
+		.layer(DefaultBodyLimit::max(config.upload_body_limit))
+		.layer(TimeoutLayer::new(Duration::from_secs(config.upload_timeout)));
+
 	// build our application with a route
 	let app = Router::new()
-		.route("/api/*fn_name", get(server_fn_handler).post(server_fn_handler))
+		// Reads are registered for GET and everything else for POST, the other method finds no server function
+		.route("/api/*fn_name", get(server_fn_handler).post(server_fn_handler).layer(server_fn_limits.clone()))
+		.route("/api/openapi.json", get(openapi::server_fns_json))
+		.route("/api/docs", get(openapi::swagger_ui))
+		.nest("/api/v1", rest::router().layer(server_fn_limits.clone()))
+		.route("/auth/refresh", post(refresh).layer(server_fn_limits.clone()))
+		.route("/attachments/upload/:todo_id", post(upload_attachment).layer(upload_limits.clone()))
+		.route("/attachments/:id", get(download_attachment))
+		.route(print::PDF_PATH, get(print::ssr::export_pdf))
+		.route(calendar::FEED_PATH, get(calendar::ssr::calendar_feed))
+		.route("/avatars/upload", post(upload_avatar).layer(upload_limits))
+		.route("/avatars/:key/:size", get(serve_avatar))
+		.route("/manifest.webmanifest", get(manifest))
+		.route("/icons/:size", get(serve_icon))
+		.route("/sw.js", get(service_worker))
+		.route(live::WEBSOCKET_PATH, get(events::events_ws))
+		.route(live::SSE_PATH, get(events::events_sse))
+		.route("/digest/unsubscribe/:token", get(unsubscribe_page).post(unsubscribe))
+		.route("/auth/magic/:token", get(magic_link_page).post(magic_login).layer(server_fn_limits.clone()))
+		.route("/auth/email/:token", get(email_confirmation_page).post(confirm_email).layer(server_fn_limits.clone()));
+
+	#[cfg(feature = "saml")]
+	let app = {
+		use session_auth_axum::saml::ssr::{saml_acs, saml_login, saml_metadata};
+
+		app
+			.route("/saml/metadata", get(saml_metadata))
+			.route("/saml/login", get(saml_login))
+			.route("/saml/acs", post(saml_acs).layer(server_fn_limits.clone()))
+	};
+
+	#[cfg(feature = "graphql")]
+	let app = {
+		use session_auth_axum::graphql::ssr::{graphiql, graphql_handler};
+
+		app.route("/graphql", get(graphiql).post(graphql_handler).layer(server_fn_limits.clone()))
+	};
+
+	let app = app
 		.leptos_routes_with_handler(routes, get(leptos_routes_handler))
 		.fallback(file_and_error_handler)
-		.layer(AuthSessionLayer::<User, i32, SessionPgPool, PgPool>::new(Some(get_db().clone())).with_config(auth_config))
-		.layer(SessionLayer::new(session_store))
+		.layer(middleware::from_fn(unauthorized_responses))
+		.layer(middleware::from_fn(breaker::ssr::refuse_writes))
+		.layer(auth_stack.auth)
+		.layer(auth_stack.session)
+		// Requests over the limit are answered right away, outside the session layer so they don't touch the database.
+		// The limit is shared, every route gets its own copy of the layers
+		.layer(
+			ServiceBuilder::new()
+				.layer(HandleErrorLayer::new(overloaded))
+				.load_shed()
+				.layer(GlobalConcurrencyLimitLayer::new(config.max_concurrent_requests)),
+		)
+		.layer(TraceLayer::new_for_http())
 		.with_state(app_state);
 
+	// The encoder flushes whenever the SSR stream is waiting on a resource so streamed chunks still reach the browser
+	// as they are rendered
+	let app = if config.compression {
+		app.layer(CompressionLayer::new().quality(config.compression_level.into()))
+	} else {
+		app
+	};
+
 	// run our app with hyper
 	// `axum::Server` is a re-export of `hyper::Server`
-	log!("listening on http://{}", &addr);
+	startup.log();
 	let listener = tokio::net::TcpListener::bind(&addr).await.unwrap();
-	axum::serve(listener, app.into_make_service()).await.unwrap();
+	// The address of the connection is what failed logins are counted by without a proxy in front
+	axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>()).await.unwrap();
 }
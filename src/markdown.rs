@@ -0,0 +1,139 @@
+//! Descriptions of todos are written in markdown and turned into HTML on the server
+//!
+//! pulldown-cmark renders the markdown and ammonia cleans what comes out of it, so the only tags in the output are
+//! paragraphs, headings, lists, quotes, code, emphasis and links to `http`, `https` and `mailto` addresses. HTML written
+//! into the markdown is shown as the text it was typed as.
+
+use ammonia::{Builder, UrlRelative};
+use pulldown_cmark::{html, Event, HeadingLevel, Options, Parser, Tag, TagEnd};
+use std::{collections::HashSet, sync::OnceLock};
+
+/// Where a link may point to, everything else like `javascript:` loses its address
+const LINK_SCHEMES: [&str; 3] = ["https", "http", "mailto"];
+
+const TAGS: [&str; 17] = [
+	"p",
+	"h2",
+	"h3",
+	"h4",
+	"h5",
+	"h6",
+	"ul",
+	"ol",
+	"li",
+	"blockquote",
+	"pre",
+	"code",
+	"em",
+	"strong",
+	"a",
+	"br",
+	"hr",
+];
+
+fn cleaner() -> &'static Builder<'static> {
+	static CLEANER: OnceLock<Builder<'static>> = OnceLock::new();
+
+	CLEANER.get_or_init(|| {
+		let mut builder = Builder::empty();
+		builder
+			.add_tags(TAGS)
+			.add_tag_attributes("a", ["href"])
+			.url_schemes(HashSet::from(LINK_SCHEMES))
+			.url_relative(UrlRelative::Deny)
+			.link_rel(Some("nofollow noopener noreferrer"));
+		builder
+	})
+}
+
+/// One level down so `#` is an `<h2>`, the title of the todo is the `<h1>` of its page
+fn demote(level: HeadingLevel) -> HeadingLevel {
+	match level {
+		HeadingLevel::H1 => HeadingLevel::H2,
+		HeadingLevel::H2 => HeadingLevel::H3,
+		HeadingLevel::H3 => HeadingLevel::H4,
+		HeadingLevel::H4 => HeadingLevel::H5,
+		HeadingLevel::H5 | HeadingLevel::H6 => HeadingLevel::H6,
+	}
+}
+
+/// The HTML of the description, safe to put into the page as it is
+pub fn render(markdown: &str) -> String {
+	let events = Parser::new_ext(markdown, Options::ENABLE_STRIKETHROUGH).map(|event| match event {
+		Event::Start(Tag::Heading {
+			level,
+			id,
+			classes,
+			attrs,
+		}) => Event::Start(Tag::Heading {
+			level: demote(level),
+			id,
+			classes,
+			attrs,
+		}),
+		Event::End(TagEnd::Heading(level)) => Event::End(TagEnd::Heading(demote(level))),
+		Event::Html(raw) | Event::InlineHtml(raw) => Event::Text(raw),
+		event => event,
+	});
+	let mut rendered = String::with_capacity(markdown.len() * 3 / 2);
+	html::push_html(&mut rendered, events);
+
+	cleaner().clean(&rendered).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn markdown_renders() {
+		let html = render("# Plan\n\nBuy **oat** milk\nand *bread*\n\n- `one`\n- [two](https://example.com/?a=1&b=2)");
+		assert!(html.starts_with("<h2>Plan</h2>"), "{html}");
+		assert!(html.contains("<p>Buy <strong>oat</strong> milk\nand <em>bread</em></p>"), "{html}");
+		assert!(html.contains("<li><code>one</code></li>"), "{html}");
+		assert!(
+			html.contains("<a href=\"https://example.com/?a=1&amp;b=2\" rel=\"nofollow noopener noreferrer\">two</a>"),
+			"{html}"
+		);
+		assert!(render("```\n<b>bold</b>\n```").starts_with("<pre><code>&lt;b&gt;bold&lt;/b&gt;"));
+		assert!(render("###### Deep").starts_with("<h6>Deep</h6>"));
+	}
+
+	#[test]
+	fn scripts_never_reach_the_html() {
+		for payload in [
+			"<script>alert(1)</script>",
+			"<img src=x onerror=alert(1)>",
+			"**<svg onload=alert(1)>**",
+			"`</code><script>alert(1)</script>`",
+			"[click](javascript:alert(1))",
+			"[click](JaVaScRiPt:alert(1))",
+			"[click](https://example.com\" onmouseover=\"alert(1))",
+			"[<script>alert(1)</script>](https://example.com)",
+			"# <iframe src=https://example.com>",
+			"<a href=\"javascript:alert(1)\">click</a>",
+			"[click](/relative)",
+		] {
+			let html = render(payload);
+			for tag in [
+				"<script",
+				"<img",
+				"<svg",
+				"<iframe",
+				"href=\"javascript",
+				"href=\"JaVaScRiPt",
+				"href=\"/relative",
+				"\" onmouseover",
+			] {
+				assert!(!html.contains(tag), "{payload} rendered as {html}");
+			}
+		}
+	}
+
+	#[test]
+	fn long_descriptions_render() {
+		// Unclosed emphasis all the way through used to be scanned again for every character
+		let html = render(&"*a `b [c](d ".repeat(2000));
+		assert!(html.starts_with("<p>"));
+	}
+}
@@ -0,0 +1,129 @@
+use crate::config::MigrationMode;
+use axum::{
+	http::StatusCode,
+	response::{IntoResponse, Response},
+};
+use serde::Serialize;
+use sqlx::{migrate::Migrator, PgPool};
+use std::sync::{
+	atomic::{AtomicBool, Ordering},
+	Arc,
+};
+use utoipa::ToSchema;
+
+pub static MIGRATOR: Migrator = sqlx::migrate!();
+
+/// Where the database schema stands compared to the migrations this build ships with
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct MigrationStatus {
+	/// The latest migration applied to the database, `None` when there is none
+	pub current: Option<i64>,
+	/// The latest migration this build ships with
+	pub latest: Option<i64>,
+	/// Migrations this build ships with that aren't applied yet
+	pub pending: Vec<i64>,
+	/// Migrations that were started but failed, the schema is in an unknown state until they are fixed by hand
+	pub failed: Vec<i64>,
+}
+
+impl MigrationStatus {
+	pub fn is_current(&self) -> bool {
+		self.pending.is_empty() && self.failed.is_empty()
+	}
+}
+
+pub async fn status(pool: &PgPool) -> Result<MigrationStatus, sqlx::Error> {
+	// The table only exists once the first migration ran
+	let table_exists =
+		sqlx::query_scalar::<_, bool>("SELECT to_regclass('_sqlx_migrations') IS NOT NULL").fetch_one(pool).await?;
+	let applied = if table_exists {
+		sqlx::query_as::<_, (i64, bool)>("SELECT version, success FROM _sqlx_migrations ORDER BY version")
+			.fetch_all(pool)
+			.await?
+	} else {
+		Vec::new()
+	};
+
+	let known = MIGRATOR.iter().filter(|migration| !migration.migration_type.is_down_migration());
+
+	Ok(MigrationStatus {
+		current: applied.iter().filter(|(_, success)| *success).map(|(version, _)| *version).max(),
+		latest: MIGRATOR.iter().map(|migration| migration.version).max(),
+		pending: known
+			.map(|migration| migration.version)
+			.filter(|version| !applied.iter().any(|(applied, success)| applied == version && *success))
+			.collect(),
+		failed: applied.iter().filter(|(_, success)| !success).map(|(version, _)| *version).collect(),
+	})
+}
+
+/// Whether the schema is current, checked by every auth route and updated whenever the status is looked at
+#[derive(Debug, Clone, Default)]
+pub struct SchemaGuard {
+	current: Arc<AtomicBool>,
+}
+
+impl SchemaGuard {
+	pub fn is_current(&self) -> bool {
+		self.current.load(Ordering::Relaxed)
+	}
+
+	pub fn update(&self, status: &MigrationStatus) {
+		self.current.store(status.is_current(), Ordering::Relaxed);
+	}
+
+	/// Refuse auth routes while the schema is behind, a half migrated schema could let sessions or permissions
+	/// through that it shouldn't
+	pub fn check(&self) -> Result<(), (StatusCode, String)> {
+		if self.is_current() {
+			Ok(())
+		} else {
+			Err((StatusCode::SERVICE_UNAVAILABLE, String::from("The database schema is being updated, try again later")))
+		}
+	}
+}
+
+/// Run the migrations as the mode says, panics in `fail-fast` mode when they fail or the schema is still behind
+pub async fn run(mode: MigrationMode, pool: &PgPool, guard: &SchemaGuard) {
+	if mode != MigrationMode::Skip {
+		if let Err(error) = MIGRATOR.run(pool).await {
+			match mode {
+				MigrationMode::FailFast => panic!("Running migrations failed: {error}"),
+				_ => log::error!("Running migrations failed, auth routes are refused until the schema is current: {error}"),
+			}
+		}
+	}
+
+	let status = status(pool).await.expect("Unable to read the migration status");
+	guard.update(&status);
+	if !status.is_current() {
+		match mode {
+			MigrationMode::FailFast => panic!("The database schema is behind: {status:?}"),
+			_ => log::warn!("The database schema is behind, auth routes are refused until it's current: {status:?}"),
+		}
+	}
+}
+
+/// The server functions that log users in, refused like the auth routes while the schema is behind
+pub fn refuse_server_fn(path: &str, guard: &SchemaGuard) -> Option<Response> {
+	use crate::{
+		auth::{Login, Reauthenticate, Signup},
+		jwt::IssueJwt,
+		magic_link::RequestMagicLink,
+	};
+	use leptos::server_fn::ServerFn;
+
+	if ![
+		Login::PATH,
+		Signup::PATH,
+		Reauthenticate::PATH,
+		IssueJwt::PATH,
+		RequestMagicLink::PATH,
+	]
+	.contains(&path)
+	{
+		return None;
+	}
+
+	guard.check().err().map(IntoResponse::into_response)
+}
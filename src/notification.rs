@@ -0,0 +1,378 @@
+use crate::errors::AppServerError;
+use chrono::prelude::*;
+use leptos::*;
+use leptos_router::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "ssr", derive(sqlx::FromRow, utoipa::ToSchema))]
+pub struct Notification {
+	pub id: i32,
+	pub message: String,
+	pub todo: Option<i32>,
+	pub read: bool,
+	pub created_at: DateTime<Utc>,
+}
+
+/// Which notifications a user wants, users who never saved any get all of them
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "ssr", derive(sqlx::FromRow, utoipa::ToSchema))]
+pub struct NotificationPreferences {
+	pub todo_created: bool,
+	pub todo_updated: bool,
+}
+
+impl Default for NotificationPreferences {
+	fn default() -> Self {
+		Self {
+			todo_created: true,
+			todo_updated: true,
+		}
+	}
+}
+
+#[cfg(feature = "ssr")]
+pub mod ssr {
+	use super::NotificationPreferences;
+	use crate::{
+		auth::{User, UserSQL},
+		authorize::Action,
+		events::{next_event, DomainEvent, EventBus},
+		permission::{Permission, Permissions, Scope},
+		todo::ssr::get_todo_with_permission,
+	};
+	use sqlx::PgPool;
+
+	pub async fn preferences(user: i32, pool: &PgPool) -> Result<NotificationPreferences, sqlx::Error> {
+		Ok(
+			sqlx::query_as::<_, NotificationPreferences>(
+				"SELECT todo_created, todo_updated FROM notification_preferences WHERE person = $1",
+			)
+			.bind(user)
+			.fetch_optional(pool)
+			.await?
+			.unwrap_or_default(),
+		)
+	}
+
+	// Users with a broad read scope would be notified about everything, so only the owner of a todo, its assignees and
+	// users who explicitly follow its owner through their person scope hear about it
+	fn follows(user: &User, owner: i32, assigned: bool) -> bool {
+		let Permissions::ReadWrite { read, .. } = user.permission_todo();
+		user.id == owner || assigned || matches!(read, Permission::Read(scopes) if scopes.contains(&Scope::Person(owner)))
+	}
+
+	#[derive(sqlx::FromRow)]
+	struct Candidate {
+		#[sqlx(flatten)]
+		user: UserSQL,
+		assigned: bool,
+		#[sqlx(flatten)]
+		preferences: NotificationPreferences,
+	}
+
+	async fn notify(event: &DomainEvent, pool: &PgPool) -> Result<(), sqlx::Error> {
+		let (tenant, actor, todo, verb, wants): (_, _, _, _, fn(&NotificationPreferences) -> bool) = match event {
+			DomainEvent::TodoCreated { tenant, user, todo } => (*tenant, *user, *todo, "created", |p| p.todo_created),
+			DomainEvent::TodoUpdated { tenant, user, todo } => (*tenant, *user, *todo, "updated", |p| p.todo_updated),
+			// Deleted todos are gone along with the owner we would need to find who to tell
+			_ => return Ok(()),
+		};
+
+		let Some((owner, title)) = sqlx::query_as::<_, (i32, String)>("SELECT person, title FROM todos WHERE id = $1")
+			.bind(todo)
+			.fetch_optional(pool)
+			.await?
+		else {
+			return Ok(());
+		};
+		let actor_name = User::get_from_id(actor, pool).await.map(|user| user.username).unwrap_or_default();
+
+		// Only users whose permission string mentions the owner can follow them, matched the way the parser reads it,
+		// whether it's their read scope is checked below
+		let candidates = sqlx::query_as::<_, Candidate>(
+			"SELECT users.*, todo_assignees.person IS NOT NULL AS assigned,
+				COALESCE(notification_preferences.todo_created, true) AS todo_created,
+				COALESCE(notification_preferences.todo_updated, true) AS todo_updated
+			FROM users
+			LEFT JOIN todo_assignees ON todo_assignees.todo = $3 AND todo_assignees.person = users.id
+			LEFT JOIN notification_preferences ON notification_preferences.person = users.id
+			WHERE users.tenant = $1 AND users.id <> $2 AND users.active AND NOT users.service_account
+				AND (users.id = $4 OR todo_assignees.person IS NOT NULL
+					OR upper(replace(users.permission_todo, ' ', '')) ~ ('PERSON\\[\\+?0*' || $4 || '\\]'))",
+		)
+		.bind(tenant)
+		.bind(actor)
+		.bind(todo)
+		.bind(owner)
+		.fetch_all(pool)
+		.await?;
+
+		let mut notified = Vec::new();
+		for candidate in candidates {
+			let user = User::from(candidate.user);
+			if wants(&candidate.preferences)
+				&& follows(&user, owner, candidate.assigned)
+				&& get_todo_with_permission(todo, tenant, &user, Action::Read, pool).await.is_some()
+			{
+				notified.push(user.id);
+			}
+		}
+
+		sqlx::query(
+			"INSERT INTO notifications (tenant, person, todo, message)
+			SELECT $1, person, $3, $4 FROM unnest($2::int[]) AS person",
+		)
+		.bind(tenant)
+		.bind(&notified)
+		.bind(todo)
+		.bind(format!("{actor_name} {verb} \"{title}\""))
+		.execute(pool)
+		.await?;
+
+		Ok(())
+	}
+
+	/// Turn domain events into notifications for the users they concern
+	pub fn spawn_notifier(pool: PgPool, events: &EventBus) {
+		let mut receiver = events.subscribe();
+
+		tokio::spawn(async move {
+			while let Some(event) = next_event(&mut receiver, "Notifications").await {
+				if let Err(error) = notify(&event, &pool).await {
+					log::error!("Creating notifications for {event:?} failed: {error}");
+				}
+			}
+		});
+	}
+}
+
+/// The latest notifications of the logged in user, newest first
+#[server(input = GetUrl)]
+pub async fn get_notifications() -> Result<Vec<Notification>, ServerFnError<AppServerError>> {
+	use crate::{auth::get_user, cache::cache_privately};
+	use sqlx::PgPool;
+
+	cache_privately::<GetNotifications>(0);
+
+	let pool = use_context::<PgPool>().expect("Database not initialized");
+	let user = get_user().await?.ok_or_else(AppServerError::unauthenticated)?;
+
+	Ok(
+		sqlx::query_as::<_, Notification>(
+			"SELECT id, message, todo, read_at IS NOT NULL AS read, created_at FROM notifications
+			WHERE person = $1 ORDER BY id DESC LIMIT 50",
+		)
+		.bind(user.id)
+		.fetch_all(&pool)
+		.await
+		.map_err(AppServerError::from)?,
+	)
+}
+
+#[server(input = GetUrl)]
+pub async fn get_unread_notification_count() -> Result<i64, ServerFnError<AppServerError>> {
+	use crate::{auth::get_user, cache::cache_privately, retry::retry_read};
+	use sqlx::PgPool;
+
+	cache_privately::<GetUnreadNotificationCount>(0);
+
+	let pool = use_context::<PgPool>().expect("Database not initialized");
+	let Some(user) = get_user().await? else {
+		return Ok(0);
+	};
+
+	// The bell polls this, a missed poll shouldn't show up as an error
+	Ok(
+		retry_read("get_unread_notification_count", || {
+			sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM notifications WHERE person = $1 AND read_at IS NULL")
+				.bind(user.id)
+				.fetch_one(&pool)
+		})
+		.await
+		.map_err(AppServerError::from)?,
+	)
+}
+
+/// Mark a single notification as read, or all of them when no id is given
+#[server]
+pub async fn mark_notifications_read(id: Option<i32>) -> Result<(), ServerFnError<AppServerError>> {
+	use crate::auth::get_user;
+	use sqlx::PgPool;
+
+	let pool = use_context::<PgPool>().expect("Database not initialized");
+	let user = get_user().await?.ok_or_else(AppServerError::unauthenticated)?;
+
+	sqlx::query(
+		"UPDATE notifications SET read_at = now() WHERE person = $1 AND read_at IS NULL AND ($2::INT IS NULL OR id = $2)",
+	)
+	.bind(user.id)
+	.bind(id)
+	.execute(&pool)
+	.await
+	.map_err(AppServerError::from)?;
+
+	Ok(())
+}
+
+#[server(input = GetUrl)]
+pub async fn get_notification_preferences() -> Result<NotificationPreferences, ServerFnError<AppServerError>> {
+	use crate::{auth::get_user, cache::cache_privately};
+	use sqlx::PgPool;
+
+	cache_privately::<GetNotificationPreferences>(0);
+
+	let pool = use_context::<PgPool>().expect("Database not initialized");
+	let user = get_user().await?.ok_or_else(AppServerError::unauthenticated)?;
+
+	Ok(self::ssr::preferences(user.id, &pool).await.map_err(AppServerError::from)?)
+}
+
+/// Save which notifications the user wants, the fields are checkboxes so any value means yes
+#[server]
+pub async fn set_notification_preferences(
+	todo_created: Option<String>,
+	todo_updated: Option<String>,
+) -> Result<(), ServerFnError<AppServerError>> {
+	use crate::auth::get_user;
+	use sqlx::PgPool;
+
+	let pool = use_context::<PgPool>().expect("Database not initialized");
+	let user = get_user().await?.ok_or_else(AppServerError::unauthenticated)?;
+
+	sqlx::query(
+		"INSERT INTO notification_preferences (person, todo_created, todo_updated) VALUES ($1, $2, $3)
+		ON CONFLICT (person) DO UPDATE SET todo_created = EXCLUDED.todo_created, todo_updated = EXCLUDED.todo_updated",
+	)
+	.bind(user.id)
+	.bind(todo_created.is_some())
+	.bind(todo_updated.is_some())
+	.execute(&pool)
+	.await
+	.map_err(AppServerError::from)?;
+
+	Ok(())
+}
+
+/// The unread count in the header, opens a list of the latest notifications
+#[component]
+pub fn NotificationBell() -> impl IntoView {
+	let mark_read = create_server_action::<MarkNotificationsRead>();
+	let (open, set_open) = create_signal(false);
+	let unread = create_resource(move || mark_read.version().get(), move |_| get_unread_notification_count());
+	let notifications = create_resource(
+		move || (open.get(), mark_read.version().get()),
+		move |(open, _)| async move {
+			if open {
+				get_notifications().await
+			} else {
+				Ok(Vec::new())
+			}
+		},
+	);
+
+	view! {
+		<span class="notifications">
+			<button type="button" on:click=move |_| set_open.update(|open| *open = !*open)>
+				"🔔"
+				<Transition fallback=move || ()>
+					{move || {
+						unread
+							.get()
+							.and_then(Result::ok)
+							.filter(|count| *count > 0)
+							.map(|count| view! { <span class="unread">{count}</span> })
+					}}
+
+				</Transition>
+			</button>
+			<Show when=move || open.get()>
+				<div class="notification-list">
+					<ActionForm action=mark_read>
+						<input type="submit" value="Mark all as read" />
+					</ActionForm>
+					<Transition fallback=move || view! { <p>"Loading..."</p> }>
+						{move || {
+							notifications
+								.get()
+								.map(|notifications| match notifications {
+									Err(e) => view! { <span class="error">{e.to_string()}</span> }.into_view(),
+									Ok(notifications) if notifications.is_empty() => {
+										view! { <p>"Nothing new."</p> }.into_view()
+									}
+									Ok(notifications) => {
+										notifications
+											.into_iter()
+											.map(|notification| {
+												let read = notification.read;
+												view! {
+													<ActionForm action=mark_read>
+														<input type="hidden" name="id" value=notification.id />
+														<span class:unread=!read>
+															{notification.message}
+														</span>
+														" "
+														<small>{notification.created_at.to_string()}</small>
+														<Show when=move || !read>
+															<input type="submit" value="Read" />
+														</Show>
+													</ActionForm>
+												}
+											})
+											.collect_view()
+									}
+								})
+						}}
+
+					</Transition>
+				</div>
+			</Show>
+		</span>
+	}
+}
+
+#[component]
+pub fn NotificationSettings() -> impl IntoView {
+	let save = create_server_action::<SetNotificationPreferences>();
+	let preferences = create_resource(move || save.version().get(), move |_| get_notification_preferences());
+
+	view! {
+		<h2>"Notifications"</h2>
+		<Transition fallback=move || view! { <p>"Loading..."</p> }>
+			{move || {
+				preferences
+					.get()
+					.map(|preferences| match preferences {
+						Err(e) => view! { <span class="error">{e.to_string()}</span> }.into_view(),
+						Ok(preferences) => {
+							view! {
+								<ActionForm action=save>
+									<label>
+										<input
+											type="checkbox"
+											name="todo_created"
+											checked=preferences.todo_created
+										/>
+										"Todos I follow are created"
+									</label>
+									<br />
+									<label>
+										<input
+											type="checkbox"
+											name="todo_updated"
+											checked=preferences.todo_updated
+										/>
+										"Todos I follow are changed"
+									</label>
+									<br />
+									<input type="submit" value="Save" />
+								</ActionForm>
+							}
+								.into_view()
+						}
+					})
+			}}
+
+		</Transition>
+	}
+}
@@ -0,0 +1,135 @@
+use crate::{
+	auth::use_user_context,
+	todo::{AddTodo, EditTodo, Todo},
+};
+use leptos::{server_fn::ServerFn, *};
+use leptos_meta::Script;
+use serde::{Deserialize, Serialize};
+
+/// What the service worker in `src/sw.js` reports whenever its queue of offline changes changed
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+pub struct QueueReport {
+	/// Changes still waiting for the network
+	#[serde(default)]
+	pub waiting: usize,
+	/// Changes that reached the server since the last report
+	#[serde(default)]
+	pub replayed: usize,
+	/// Todos an offline edit couldn't be saved to because they changed in the meantime, as they are now
+	#[serde(default)]
+	pub conflicts: Vec<Todo>,
+	/// Error responses of replayed changes
+	#[serde(default)]
+	pub failed: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct Message {
+	offline_queue: QueueReport,
+}
+
+/// Who the worker queues changes for, `None` drops whatever it still holds
+#[derive(Serialize)]
+struct UserMessage {
+	offline_user: Option<i32>,
+}
+
+/// Register the service worker that precaches the app and queues new todos and edits made offline
+///
+/// The worker only knows the paths it was registered with, so server functions keep their generated paths. Messages of
+/// the worker are passed on to the window where [`OfflineQueue`] listens for them, the worker is told who is logged in
+/// through the window the other way round.
+#[component]
+pub fn ServiceWorker() -> impl IntoView {
+	let script = format!(
+		"if ('serviceWorker' in navigator) {{
+			navigator.serviceWorker.register('/sw.js?queue={add}&queue={edit}&edit={edit}');
+			navigator.serviceWorker.addEventListener('message', (event) => window.postMessage(event.data, location.origin));
+			const toWorker = (message) => navigator.serviceWorker.ready.then((registration) => registration.active.postMessage(message));
+			window.addEventListener('message', (event) => {{
+				if (event.origin === location.origin && typeof event.data === 'string' && event.data.includes('\"offline_user\"')) {{
+					toWorker(event.data);
+				}}
+			}});
+			const replay = () => toWorker('replay');
+			window.addEventListener('online', replay);
+			replay();
+		}}",
+		add = AddTodo::PATH,
+		edit = EditTodo::PATH,
+	);
+
+	// Only an answer counts, while it loads or the network is down nobody is known to be logged out
+	let user = use_user_context().user;
+	create_effect(move |_| {
+		let Some(Ok(user)) = user.get() else {
+			return;
+		};
+		let message = UserMessage {
+			offline_user: user.map(|user| user.id),
+		};
+		if let Ok(message) = serde_json::to_string(&message) {
+			let origin = window().location().origin().unwrap_or_default();
+			_ = window().post_message(&message.into(), &origin);
+		}
+	});
+
+	view! { <Script>{script}</Script> }
+}
+
+/// Tells the user about offline changes and reloads the list once they are saved
+#[component]
+pub fn OfflineQueue(reload: WriteSignal<i32>) -> impl IntoView {
+	let (report, set_report) = create_signal(QueueReport::default());
+
+	let listener = window_event_listener(ev::message, move |ev| {
+		if ev.origin() != window().location().origin().unwrap_or_default() {
+			return;
+		}
+		let Some(Ok(Message { offline_queue })) = ev.data().as_string().map(|data| serde_json::from_str::<Message>(&data))
+		else {
+			return;
+		};
+
+		if offline_queue.replayed > 0 {
+			reload.update(|reloads| *reloads += 1);
+		}
+		set_report.set(offline_queue);
+	});
+	on_cleanup(move || listener.remove());
+
+	view! {
+		{move || {
+			let report = report.get();
+			view! {
+				{(report.waiting > 0)
+					.then(|| {
+						view! {
+							<p class="offline">
+								{format!("You're offline, {} changes are saved once you're back online.", report.waiting)}
+							</p>
+						}
+					})}
+				{report
+					.conflicts
+					.into_iter()
+					.map(|todo| {
+						view! {
+							<p class="error">
+								{format!(
+									"Your offline change to \"{}\" wasn't saved, it was changed by someone else in the meantime.",
+									todo.title,
+								)}
+							</p>
+						}
+					})
+					.collect_view()}
+				{report
+					.failed
+					.into_iter()
+					.map(|error| view! { <p class="error">{format!("An offline change failed: {error}")}</p> })
+					.collect_view()}
+			}
+		}}
+	}
+}
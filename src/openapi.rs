@@ -0,0 +1,1373 @@
+use crate::{
+	archive::ArchiveCompleted,
+	attachment::{Attachment, GetAttachments},
+	auth::{GetUser, Login, Logout, PingSession, PublicUser, Reauthenticate, Signup, User},
+	breaker::GetDatabaseDegraded,
+	calendar::{CalendarFeed, CreateCalendarFeed, DeleteCalendarFeed, GetCalendarFeed},
+	comment::{AddComment, Comment, DeleteComment, GetComments},
+	db_console::{QueryDatabase, QueryResult},
+	delegation::{Delegation, GetDelegations, GrantAccess, RevokeDelegation},
+	denial::{DenialCount, DeniedAction, GetPermissionDenials},
+	digest::{DigestFrequency, DigestSubscription, GetDigestSubscription, SetDigestSubscription},
+	errors::{AppServerError, ErrorCode},
+	flags::{DeleteFeatureFlag, FeatureFlag, FeatureFlags, GetFeatureFlags, SetFeatureFlag},
+	history::{GetTodoHistory, TodoChange, TodoHistoryEntry},
+	identity::{GetIdentities, Identity, LinkPassword, Provider, UnlinkIdentity},
+	integration::{
+		AddIntegration, DeleteIntegration, GetIntegrations, Integration, IntegrationEvent, IntegrationKind,
+		SetIntegrationEvents,
+	},
+	jwt::{IssueJwt, JwtTokens},
+	magic_link::{CancelLoginEmailChange, GetPendingLoginEmail, RequestMagicLink, SetLoginEmail},
+	notification::{
+		GetNotificationPreferences, GetNotifications, GetUnreadNotificationCount, MarkNotificationsRead, Notification,
+		NotificationPreferences, SetNotificationPreferences,
+	},
+	permission::{Permission, Permissions, Scope},
+	permission_schedule::{
+		CancelPermissionChange, GetPendingPermissionChanges, PendingPermissionChange, SchedulePermissionChange,
+	},
+	permission_transfer::{
+		ExportPermissions, ImportPermissions, PermissionAssignment, PermissionChange, PermissionDiff, PermissionDiffStatus,
+		PermissionDocument, PermissionFormat,
+	},
+	project::{CreateProject, DeleteProject, GetProjects, Project},
+	recurrence::{Recurrence, SetRecurrence},
+	scope_badge::{ExplainPermissions, PermissionExplanation, ResolveScopes, ResolvedScope},
+	service_account::{
+		ApiToken, CreateApiToken, CreateServiceAccount, GetServiceAccounts, RevokeApiToken, RotateApiToken, ServiceAccount,
+	},
+	share::{CreateShareLink, GetShareLinks, GetSharedTodo, RevokeShareLink, ShareLink, SharedTodo},
+	startup::{EnabledFlag, GetStartupReport, ServerSetup, Setting, StartupReport},
+	toast::{Toast, ToastLevel},
+	todo::{
+		AddTodo, ArchiveTodo, AssignTodo, DeleteTodo, DescribeTodo, EditResult, EditTodo, GetAssignableUsers, GetTodo,
+		GetTodoCounts, GetTodos, MoveTodo, Priority, SetPriority, Todo, TodoCounts, TodoList, TodoListItem,
+		UpdatePositions,
+	},
+	undo::Undo,
+	user_admin::{
+		DefaultPermissions, GetDefaultPermissions, GetManagedUsers, GetScopeReach, ManagedUser, PermissionPreview,
+		PreviewPermissions, ScopeReach, SetDefaultPermissions, SetUserActive, SetUserPermissions, ValidatePermission,
+	},
+	user_import::{ImportRow, ImportStatus, ImportUsers},
+	username::ChangeUsername,
+};
+use axum::http::Method;
+use leptos::server_fn::{codec::Encoding, ServerFn};
+use serde::{
+	de::{self, value, DeserializeOwned, Deserializer, Visitor},
+	forward_to_deserialize_any,
+};
+use utoipa::{
+	openapi::{
+		path::{OperationBuilder, ParameterBuilder, ParameterIn, ParameterStyle, PathItem, PathItemType},
+		request_body::RequestBodyBuilder,
+		AllOfBuilder, ArrayBuilder, Components, ComponentsBuilder, ContentBuilder, InfoBuilder, ObjectBuilder, OpenApi,
+		OpenApiBuilder, PathsBuilder, Ref, RefOr, Required, ResponseBuilder, Schema, SchemaType,
+	},
+	ToSchema,
+};
+
+// Server functions take their arguments as a url encoded form unless they say otherwise, these mirror the argument
+// lists so the fields can be documented, the tests hold them to the fields of the `#[server]` signatures
+
+#[derive(ToSchema)]
+#[allow(dead_code)]
+struct LoginArgs {
+	username: String,
+	password: String,
+	/// Any value keeps the session beyond the browser session
+	remember: Option<String>,
+	/// Path of the app to go to once logged in, `/` by default
+	next: Option<String>,
+}
+
+#[derive(ToSchema)]
+#[allow(dead_code)]
+struct ReauthenticateArgs {
+	password: String,
+}
+
+#[derive(ToSchema)]
+#[allow(dead_code)]
+struct PingSessionArgs {
+	/// Start the session lifetime over, defaults to `false`
+	extend: Option<bool>,
+}
+
+#[derive(ToSchema)]
+#[allow(dead_code)]
+struct SignupArgs {
+	username: String,
+	password: String,
+	password_confirmation: String,
+	/// Any value keeps the session beyond the browser session
+	remember: Option<String>,
+}
+
+#[derive(ToSchema)]
+#[allow(dead_code)]
+struct AddTodoArgs {
+	title: String,
+	/// The project to add the todo to, leave out for none
+	project: Option<i32>,
+}
+
+#[derive(ToSchema)]
+#[allow(dead_code)]
+struct DescribeTodoArgs {
+	id: i32,
+	/// Markdown, leave empty to remove the description
+	description: String,
+}
+
+#[derive(ToSchema)]
+#[allow(dead_code)]
+struct MoveTodoArgs {
+	id: i32,
+	/// Leave out to take the todo out of its project
+	project: Option<i32>,
+}
+
+#[derive(ToSchema)]
+#[allow(dead_code)]
+struct CreateProjectArgs {
+	name: String,
+}
+
+#[derive(ToSchema)]
+#[allow(dead_code)]
+struct DeleteProjectArgs {
+	id: i32,
+}
+
+#[derive(ToSchema)]
+#[allow(dead_code)]
+struct DeleteTodoArgs {
+	id: u16,
+}
+
+#[derive(ToSchema)]
+#[allow(dead_code)]
+struct UndoArgs {
+	/// Came with the toast of deleting or completing a todo
+	token: String,
+}
+
+#[derive(ToSchema)]
+#[allow(dead_code)]
+struct ArchiveTodoArgs {
+	id: i32,
+	/// `false` brings an archived todo back into the list
+	archived: bool,
+}
+
+#[derive(ToSchema)]
+#[allow(dead_code)]
+struct GetAssignableUsersArgs {
+	id: i32,
+}
+
+#[derive(ToSchema)]
+#[allow(dead_code)]
+struct AssignTodoArgs {
+	id: i32,
+	/// Replaces whoever the todo was assigned to, empty to take everyone off it
+	assignees: Vec<i32>,
+}
+
+#[derive(ToSchema)]
+#[allow(dead_code)]
+struct ArchiveCompletedArgs {
+	/// Completed todos that didn't change for this many days are archived
+	days: u32,
+}
+
+#[derive(ToSchema)]
+#[allow(dead_code)]
+struct GetAttachmentsArgs {
+	todo_id: i32,
+}
+
+#[derive(ToSchema)]
+#[allow(dead_code)]
+struct GetCommentsArgs {
+	todo_id: i32,
+}
+
+#[derive(ToSchema)]
+#[allow(dead_code)]
+struct AddCommentArgs {
+	todo_id: i32,
+	body: String,
+}
+
+#[derive(ToSchema)]
+#[allow(dead_code)]
+struct DeleteCommentArgs {
+	id: i32,
+}
+
+#[derive(ToSchema)]
+#[allow(dead_code)]
+struct GetShareLinksArgs {
+	todo_id: i32,
+}
+
+#[derive(ToSchema)]
+#[allow(dead_code)]
+struct CreateShareLinkArgs {
+	todo_id: i32,
+	/// How long the link works, at most 90 days
+	days: i32,
+}
+
+#[derive(ToSchema)]
+#[allow(dead_code)]
+struct RevokeShareLinkArgs {
+	id: i32,
+}
+
+#[derive(ToSchema)]
+#[allow(dead_code)]
+struct GetSharedTodoArgs {
+	token: String,
+}
+
+#[derive(ToSchema)]
+#[allow(dead_code)]
+struct GrantAccessArgs {
+	/// The username of who gets the access
+	to_user: String,
+	/// What to lend, like `EQUIPMENT[1], PERSON[2]`
+	scopes: String,
+	/// When the access runs out, at most 90 days from now, without an offset it's taken as UTC
+	until: String,
+	/// Lend writing along with reading when set
+	write: Option<String>,
+}
+
+#[derive(ToSchema)]
+#[allow(dead_code)]
+struct RevokeDelegationArgs {
+	id: i32,
+}
+
+#[derive(ToSchema)]
+#[allow(dead_code)]
+struct GetTodosArgs {
+	/// The version of the last response, leave out to always get the todos
+	version: Option<String>,
+	/// Get the archived todos instead of the list, defaults to `false`
+	archived: Option<bool>,
+	/// Only get the todos assigned to the user, defaults to `false`
+	assigned_to_me: Option<bool>,
+	/// Only get the todos of this project
+	project: Option<i32>,
+}
+
+#[derive(ToSchema)]
+#[allow(dead_code)]
+struct GetTodoArgs {
+	id: i32,
+}
+
+#[derive(ToSchema)]
+#[allow(dead_code)]
+struct GetTodoHistoryArgs {
+	id: i32,
+}
+
+#[derive(ToSchema)]
+#[allow(dead_code)]
+struct SetRecurrenceArgs {
+	id: i32,
+	/// `daily`, `weekly`, `monthly` or a cron expression, empty to stop repeating
+	recurrence: Option<String>,
+}
+
+#[derive(ToSchema)]
+#[allow(dead_code)]
+struct SetPriorityArgs {
+	id: i32,
+	priority: Priority,
+}
+
+#[derive(ToSchema)]
+#[allow(dead_code)]
+struct EditTodoArgs {
+	id: i32,
+	title: String,
+	/// Any value marks the todo as done
+	completed: Option<String>,
+	/// The version of the todo the edit is based on
+	version: i32,
+}
+
+#[derive(ToSchema)]
+#[allow(dead_code)]
+struct UpdatePositionsArgs {
+	/// `[id, position]` pairs
+	positions: Vec<[i32; 2]>,
+}
+
+#[derive(ToSchema)]
+#[allow(dead_code)]
+struct MarkNotificationsReadArgs {
+	/// Leave out to mark all notifications as read
+	id: Option<i32>,
+}
+
+#[derive(ToSchema)]
+#[allow(dead_code)]
+struct SetNotificationPreferencesArgs {
+	/// Any value turns the notification on
+	todo_created: Option<String>,
+	/// Any value turns the notification on
+	todo_updated: Option<String>,
+}
+
+#[derive(ToSchema)]
+#[allow(dead_code)]
+struct AddIntegrationArgs {
+	/// `slack` or `matrix`
+	kind: String,
+	/// The Slack webhook, or the homeserver for Matrix
+	url: String,
+	/// The id of the Matrix room, starting with `!`
+	room: Option<String>,
+	/// The access token of the Matrix user posting to the room
+	access_token: Option<String>,
+	/// Any value posts about every todo of the tenant, admins only
+	tenant_wide: Option<String>,
+	/// Any value posts when a todo is assigned
+	todo_assigned: Option<String>,
+	/// Any value posts when a todo is overdue
+	todo_overdue: Option<String>,
+}
+
+#[derive(ToSchema)]
+#[allow(dead_code)]
+struct SetIntegrationEventsArgs {
+	id: i32,
+	/// Any value posts when a todo is assigned
+	todo_assigned: Option<String>,
+	/// Any value posts when a todo is overdue
+	todo_overdue: Option<String>,
+}
+
+#[derive(ToSchema)]
+#[allow(dead_code)]
+struct DeleteIntegrationArgs {
+	id: i32,
+}
+
+#[derive(ToSchema)]
+#[allow(dead_code)]
+struct RequestMagicLinkArgs {
+	email: String,
+}
+
+#[derive(ToSchema)]
+#[allow(dead_code)]
+struct SetLoginEmailArgs {
+	email: String,
+}
+
+#[derive(ToSchema)]
+#[allow(dead_code)]
+struct LinkPasswordArgs {
+	password: String,
+	password_confirmation: String,
+}
+
+#[derive(ToSchema)]
+#[allow(dead_code)]
+struct ChangeUsernameArgs {
+	new: String,
+	/// The current password, to confirm it's really the user
+	password: String,
+}
+
+#[derive(ToSchema)]
+#[allow(dead_code)]
+struct UnlinkIdentityArgs {
+	/// `password`, `magic_link` or `saml`
+	provider: String,
+}
+
+#[derive(ToSchema)]
+#[allow(dead_code)]
+struct ValidatePermissionArgs {
+	perm: String,
+}
+
+#[derive(ToSchema)]
+#[allow(dead_code)]
+struct ResolveScopesArgs {
+	scopes: Vec<Scope>,
+}
+
+#[derive(ToSchema)]
+#[allow(dead_code)]
+struct SetUserPermissionsArgs {
+	id: i32,
+	permission_equipment: String,
+	permission_user: String,
+	permission_todo: String,
+}
+
+#[derive(ToSchema)]
+#[allow(dead_code)]
+struct SetUserActiveArgs {
+	id: i32,
+	active: bool,
+}
+
+#[derive(ToSchema)]
+#[allow(dead_code)]
+struct CreateServiceAccountArgs {
+	username: String,
+	permission_equipment: String,
+	permission_user: String,
+	permission_todo: String,
+}
+
+#[derive(ToSchema)]
+#[allow(dead_code)]
+struct CreateApiTokenArgs {
+	/// The service account the token authenticates as
+	person: i32,
+	name: String,
+	/// Permission strings the token is narrowed down to, leave out for the permissions of the account
+	permission_equipment: Option<String>,
+	permission_user: Option<String>,
+	permission_todo: Option<String>,
+}
+
+#[derive(ToSchema)]
+#[allow(dead_code)]
+struct ApiTokenArgs {
+	id: i32,
+}
+
+#[derive(ToSchema)]
+#[allow(dead_code)]
+struct PreviewPermissionsArgs {
+	id: i32,
+	permission_equipment: String,
+	permission_todo: String,
+}
+
+#[derive(ToSchema)]
+#[allow(dead_code)]
+struct SchedulePermissionChangeArgs {
+	id: i32,
+	permission_equipment: String,
+	permission_user: String,
+	permission_todo: String,
+	/// When the permissions take effect, without an offset it's taken as UTC
+	apply_at: String,
+}
+
+#[derive(ToSchema)]
+#[allow(dead_code)]
+struct CancelPermissionChangeArgs {
+	id: i32,
+}
+
+#[derive(ToSchema)]
+#[allow(dead_code)]
+struct SetDefaultPermissionsArgs {
+	permission_equipment: String,
+	permission_user: String,
+	permission_todo: String,
+}
+
+#[derive(ToSchema)]
+#[allow(dead_code)]
+struct ImportUsersArgs {
+	/// CSV with a `username,email,role` header, the role names a permission preset
+	csv: String,
+	/// Any value mails login links instead of generating passwords
+	invite: Option<String>,
+}
+
+#[derive(ToSchema)]
+#[allow(dead_code)]
+struct ExportPermissionsArgs {
+	/// Defaults to `json`
+	format: Option<PermissionFormat>,
+}
+
+#[derive(ToSchema)]
+#[allow(dead_code)]
+struct ImportPermissionsArgs {
+	/// A [`PermissionDocument`] as `export_permissions` writes it
+	document: String,
+	/// Defaults to `json`
+	format: Option<PermissionFormat>,
+	/// Any value only reports what would change
+	dry_run: Option<String>,
+}
+
+#[derive(ToSchema)]
+#[allow(dead_code)]
+struct SetDigestSubscriptionArgs {
+	email: String,
+	/// `daily` or `weekly`, empty to unsubscribe
+	frequency: String,
+}
+
+#[derive(ToSchema)]
+#[allow(dead_code)]
+struct SetFeatureFlagArgs {
+	name: String,
+	description: String,
+	/// Any value turns the flag on
+	enabled: Option<String>,
+	/// Percentage of users the flag is on for, from 0 to 100
+	rollout: i16,
+}
+
+#[derive(ToSchema)]
+#[allow(dead_code)]
+struct DeleteFeatureFlagArgs {
+	name: String,
+}
+
+#[derive(ToSchema)]
+#[allow(dead_code)]
+struct QueryDatabaseArgs {
+	/// One of the tables the console can look into
+	table: String,
+	/// One per line, like `person = 3`, `username ~ dom` or `archived_at is null`
+	filters: String,
+	/// A column with an optional `desc`, the first column descending when left out
+	order: Option<String>,
+	/// Rows to return, 50 when left out and 200 at most
+	limit: Option<i64>,
+}
+
+enum Output {
+	Nothing,
+	Boolean,
+	Integer,
+	Text,
+	Value(&'static str),
+	List(&'static str),
+	/// A `Toasted` envelope around the output, with a message for the user
+	Toasted(&'static Output),
+}
+
+fn body_schema(output: &Output) -> Option<RefOr<Schema>> {
+	match output {
+		Output::Nothing => None,
+		Output::Boolean => Some(ObjectBuilder::new().schema_type(SchemaType::Boolean).build().into()),
+		Output::Integer => Some(ObjectBuilder::new().schema_type(SchemaType::Integer).build().into()),
+		Output::Text => Some(ObjectBuilder::new().schema_type(SchemaType::String).build().into()),
+		Output::Value(schema) => Some(Ref::from_schema_name(*schema).into()),
+		Output::List(schema) => Some(ArrayBuilder::new().items(Ref::from_schema_name(*schema)).build().into()),
+		Output::Toasted(output) => Some(
+			ObjectBuilder::new()
+				.property(
+					"value",
+					body_schema(output)
+						.unwrap_or_else(|| ObjectBuilder::new().nullable(true).description(Some("Always `null`")).build().into()),
+				)
+				.property("toast", AllOfBuilder::new().nullable(true).item(Ref::from_schema_name("Toast")).build())
+				.required("value")
+				.required("toast")
+				.build()
+				.into(),
+		),
+	}
+}
+
+/// A documented server function, with the fields its arguments really have
+struct Endpoint {
+	path: String,
+	item: PathItem,
+	/// The schema of the arguments, `None` for server functions without any
+	args: Option<&'static str>,
+	fields: &'static [&'static str],
+}
+
+/// The fields of a struct as serde knows them, read off what it asks a deserializer for
+fn field_names<T: DeserializeOwned>() -> &'static [&'static str] {
+	struct Probe<'a>(&'a mut &'static [&'static str]);
+
+	impl<'de> Deserializer<'de> for Probe<'_> {
+		type Error = value::Error;
+
+		fn deserialize_any<V: Visitor<'de>>(self, _: V) -> Result<V::Value, Self::Error> {
+			Err(de::Error::custom("Only the fields of structs are read"))
+		}
+
+		fn deserialize_struct<V: Visitor<'de>>(
+			self,
+			_: &'static str,
+			fields: &'static [&'static str],
+			_: V,
+		) -> Result<V::Value, Self::Error> {
+			*self.0 = fields;
+			Err(de::Error::custom("The fields are all that's needed"))
+		}
+
+		forward_to_deserialize_any! {
+			bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf option unit unit_struct
+			newtype_struct seq tuple tuple_struct map enum identifier ignored_any
+		}
+	}
+
+	let mut fields: &'static [&'static str] = &[];
+	// The probe always fails, it only wants to know the fields
+	let _ = T::deserialize(Probe(&mut fields));
+	fields
+}
+
+fn server_fn<T: ServerFn + DeserializeOwned>(
+	name: &str,
+	summary: &str,
+	args: Option<&'static str>,
+	output: Output,
+) -> Endpoint {
+	let mut operation = OperationBuilder::new().operation_id(Some(name)).summary(Some(summary));
+	let method = <T::InputEncoding as Encoding>::METHOD;
+
+	// Reads take their arguments as a query string, every property of the schema is its own parameter
+	if let Some(args) = args.filter(|_| method == Method::GET) {
+		operation = operation.parameter(
+			ParameterBuilder::new()
+				.name("args")
+				.parameter_in(ParameterIn::Query)
+				.required(Required::True)
+				.schema(Some(Ref::from_schema_name(args)))
+				.style(Some(ParameterStyle::Form))
+				.explode(Some(true))
+				.build(),
+		);
+	} else if let Some(args) = args {
+		operation = operation.request_body(Some(
+			RequestBodyBuilder::new()
+				.content(
+					<T::InputEncoding as Encoding>::CONTENT_TYPE,
+					ContentBuilder::new().schema(Ref::from_schema_name(args)).build(),
+				)
+				.required(Some(Required::True))
+				.build(),
+		));
+	}
+
+	let success = match body_schema(&output) {
+		None => ResponseBuilder::new().description("Success, the body is `null`"),
+		Some(schema) => ResponseBuilder::new()
+			.description("Success")
+			.content("application/json", ContentBuilder::new().schema(schema).build()),
+	};
+
+	let operation = operation.response("200", success.build()).response(
+		"500",
+		ResponseBuilder::new()
+			.description(
+				"The server function returned an error, the body is `WrappedServerFn|CODE: message` with one of the \
+					 codes of `ErrorCode`",
+			)
+			.build(),
+	);
+
+	let item_type = if method == Method::GET {
+		PathItemType::Get
+	} else {
+		PathItemType::Post
+	};
+	Endpoint {
+		path: T::PATH.to_string(),
+		item: PathItem::new(item_type, operation.build()),
+		args,
+		fields: field_names::<T>(),
+	}
+}
+
+fn endpoints() -> Vec<Endpoint> {
+	vec![
+		server_fn::<GetUser>("get_user", "The logged in user, `null` when logged out", None, Output::Value("User")),
+		server_fn::<Login>("login", "Log in and start a session", Some("LoginArgs"), Output::Nothing),
+		server_fn::<Signup>("signup", "Create an account and start a session", Some("SignupArgs"), Output::Nothing),
+		server_fn::<Reauthenticate>(
+			"reauthenticate",
+			"Confirm the password before actions that need it",
+			Some("ReauthenticateArgs"),
+			Output::Nothing,
+		),
+		server_fn::<Logout>("logout", "End the session", None, Output::Nothing),
+		server_fn::<PingSession>(
+			"ping_session",
+			"Seconds until the session runs out, null without one, or start its lifetime over",
+			Some("PingSessionArgs"),
+			Output::Integer,
+		),
+		server_fn::<RequestMagicLink>(
+			"request_magic_link",
+			"Email a one-time login link, succeeds for unknown addresses too",
+			Some("RequestMagicLinkArgs"),
+			Output::Nothing,
+		),
+		server_fn::<SetLoginEmail>(
+			"set_login_email",
+			"Ask to link or change the address login links are sent to, takes effect once the mailed link is followed",
+			Some("SetLoginEmailArgs"),
+			Output::Nothing,
+		),
+		server_fn::<GetPendingLoginEmail>(
+			"get_pending_login_email",
+			"The new login email waiting for confirmation, if any",
+			None,
+			Output::Text,
+		),
+		server_fn::<CancelLoginEmailChange>(
+			"cancel_login_email_change",
+			"Drop the login email change waiting for confirmation",
+			None,
+			Output::Nothing,
+		),
+		server_fn::<GetIdentities>("get_identities", "The ways the user can log in", None, Output::List("Identity")),
+		server_fn::<LinkPassword>(
+			"link_password",
+			"Add or change the password of the user",
+			Some("LinkPasswordArgs"),
+			Output::Nothing,
+		),
+		server_fn::<ChangeUsername>(
+			"change_username",
+			"Rename the user, at most once every 30 days, the old name stays reserved for 90 days",
+			Some("ChangeUsernameArgs"),
+			Output::Toasted(&Output::Nothing),
+		),
+		server_fn::<UnlinkIdentity>(
+			"unlink_identity",
+			"Stop logging in with a provider, refused for the last one",
+			Some("UnlinkIdentityArgs"),
+			Output::Nothing,
+		),
+		server_fn::<IssueJwt>(
+			"issue_jwt",
+			"Issue a JWT and refresh token for the logged in user",
+			None,
+			Output::Value("JwtTokens"),
+		),
+		server_fn::<GetTodos>(
+			"get_todos",
+			"Todos the user is allowed to read, `todos` is `null` when they didn't change since `version`",
+			Some("GetTodosArgs"),
+			Output::Value("TodoList"),
+		),
+		server_fn::<GetTodo>(
+			"get_todo",
+			"A single todo the user is allowed to read",
+			Some("GetTodoArgs"),
+			Output::Value("Todo"),
+		),
+		server_fn::<GetTodoCounts>(
+			"get_todo_counts",
+			"How many of the todos the user can read are open, overdue or done",
+			None,
+			Output::Value("TodoCounts"),
+		),
+		server_fn::<AddTodo>("add_todo", "Create a todo", Some("AddTodoArgs"), Output::Nothing),
+		server_fn::<DeleteTodo>(
+			"delete_todo",
+			"Delete a todo, the toast comes with a token to undo it",
+			Some("DeleteTodoArgs"),
+			Output::Toasted(&Output::Nothing),
+		),
+		server_fn::<Undo>(
+			"undo",
+			"Take back deleting or completing a todo while the undo window is open",
+			Some("UndoArgs"),
+			Output::Toasted(&Output::Nothing),
+		),
+		server_fn::<DescribeTodo>(
+			"describe_todo",
+			"Replace the description of a todo, it's written in markdown",
+			Some("DescribeTodoArgs"),
+			Output::Toasted(&Output::Nothing),
+		),
+		server_fn::<MoveTodo>(
+			"move_todo",
+			"Move a todo into another project, the user has to be able to write it there too",
+			Some("MoveTodoArgs"),
+			Output::Toasted(&Output::Nothing),
+		),
+		server_fn::<GetProjects>("get_projects", "The projects the user can read todos of", None, Output::List("Project")),
+		server_fn::<CreateProject>(
+			"create_project",
+			"Add a project, admins only",
+			Some("CreateProjectArgs"),
+			Output::Toasted(&Output::Nothing),
+		),
+		server_fn::<DeleteProject>(
+			"delete_project",
+			"Delete a project, its todos stay without one, admins only",
+			Some("DeleteProjectArgs"),
+			Output::Toasted(&Output::Nothing),
+		),
+		server_fn::<GetAssignableUsers>(
+			"get_assignable_users",
+			"The users a todo can be assigned to, the ones whose todos the user may write",
+			Some("GetAssignableUsersArgs"),
+			Output::List("PublicUser"),
+		),
+		server_fn::<AssignTodo>(
+			"assign_todo",
+			"Replace who a todo is assigned to, the user has to be able to write the todos of at least one assignee",
+			Some("AssignTodoArgs"),
+			Output::Toasted(&Output::Nothing),
+		),
+		server_fn::<ArchiveTodo>(
+			"archive_todo",
+			"Archive a todo or bring it back from the archive",
+			Some("ArchiveTodoArgs"),
+			Output::Toasted(&Output::Nothing),
+		),
+		server_fn::<ArchiveCompleted>(
+			"archive_completed",
+			"Archive the completed todos of the tenant that didn't change for a while, admins only",
+			Some("ArchiveCompletedArgs"),
+			Output::Toasted(&Output::Integer),
+		),
+		server_fn::<GetAttachments>(
+			"get_attachments",
+			"Attachments of a todo",
+			Some("GetAttachmentsArgs"),
+			Output::List("Attachment"),
+		),
+		server_fn::<GetComments>("get_comments", "Comments of a todo", Some("GetCommentsArgs"), Output::List("Comment")),
+		server_fn::<AddComment>("add_comment", "Comment on a todo", Some("AddCommentArgs"), Output::Nothing),
+		server_fn::<DeleteComment>("delete_comment", "Delete a comment", Some("DeleteCommentArgs"), Output::Nothing),
+		server_fn::<GetShareLinks>(
+			"get_share_links",
+			"Share links of a todo that still work",
+			Some("GetShareLinksArgs"),
+			Output::List("ShareLink"),
+		),
+		server_fn::<CreateShareLink>(
+			"create_share_link",
+			"Create a read only link to a todo that works without an account, returns the link",
+			Some("CreateShareLinkArgs"),
+			Output::Text,
+		),
+		server_fn::<RevokeShareLink>(
+			"revoke_share_link",
+			"Stop a share link from working",
+			Some("RevokeShareLinkArgs"),
+			Output::Toasted(&Output::Nothing),
+		),
+		server_fn::<GetSharedTodo>(
+			"get_shared_todo",
+			"The todo a share link points to, works without logging in",
+			Some("GetSharedTodoArgs"),
+			Output::Value("SharedTodo"),
+		),
+		server_fn::<GetDelegations>(
+			"get_delegations",
+			"Access the user lent or got that didn't run out yet",
+			None,
+			Output::List("Delegation"),
+		),
+		server_fn::<GrantAccess>(
+			"grant_access",
+			"Lend another user access to todos the user has access to, until a given time",
+			Some("GrantAccessArgs"),
+			Output::Toasted(&Output::Nothing),
+		),
+		server_fn::<RevokeDelegation>(
+			"revoke_delegation",
+			"End a delegation early",
+			Some("RevokeDelegationArgs"),
+			Output::Toasted(&Output::Nothing),
+		),
+		server_fn::<SetPriority>("set_priority", "Change the priority of a todo", Some("SetPriorityArgs"), Output::Nothing),
+		server_fn::<EditTodo>(
+			"edit_todo",
+			"Change the title and state of a todo, unless it changed since `version`",
+			Some("EditTodoArgs"),
+			Output::Value("EditResult"),
+		),
+		server_fn::<UpdatePositions>(
+			"update_positions",
+			"Move todos, nothing moves unless the user may write all of them",
+			Some("UpdatePositionsArgs"),
+			Output::Nothing,
+		),
+		server_fn::<SetRecurrence>(
+			"set_recurrence",
+			"Repeat a todo once it's completed",
+			Some("SetRecurrenceArgs"),
+			Output::Nothing,
+		),
+		server_fn::<GetNotifications>(
+			"get_notifications",
+			"The latest notifications, newest first",
+			None,
+			Output::List("Notification"),
+		),
+		server_fn::<GetUnreadNotificationCount>(
+			"get_unread_notification_count",
+			"How many notifications are unread",
+			None,
+			Output::Integer,
+		),
+		server_fn::<GetDatabaseDegraded>(
+			"get_database_degraded",
+			"Whether the database is down and changes are refused",
+			None,
+			Output::Boolean,
+		),
+		server_fn::<MarkNotificationsRead>(
+			"mark_notifications_read",
+			"Mark notifications as read",
+			Some("MarkNotificationsReadArgs"),
+			Output::Nothing,
+		),
+		server_fn::<GetNotificationPreferences>(
+			"get_notification_preferences",
+			"Which notifications the user gets",
+			None,
+			Output::Value("NotificationPreferences"),
+		),
+		server_fn::<SetNotificationPreferences>(
+			"set_notification_preferences",
+			"Choose which notifications the user gets",
+			Some("SetNotificationPreferencesArgs"),
+			Output::Nothing,
+		),
+		server_fn::<GetIntegrations>(
+			"get_integrations",
+			"The chat rooms the user connected, for admins also those of the whole tenant",
+			None,
+			Output::List("Integration"),
+		),
+		server_fn::<AddIntegration>(
+			"add_integration",
+			"Connect a Slack webhook or a Matrix room that events about todos are posted to",
+			Some("AddIntegrationArgs"),
+			Output::Toasted(&Output::Nothing),
+		),
+		server_fn::<SetIntegrationEvents>(
+			"set_integration_events",
+			"Choose which events are posted to a connected room",
+			Some("SetIntegrationEventsArgs"),
+			Output::Toasted(&Output::Nothing),
+		),
+		server_fn::<DeleteIntegration>(
+			"delete_integration",
+			"Stop posting to a connected room",
+			Some("DeleteIntegrationArgs"),
+			Output::Toasted(&Output::Nothing),
+		),
+		server_fn::<GetTodoHistory>(
+			"get_todo_history",
+			"Changes made to a todo, oldest first",
+			Some("GetTodoHistoryArgs"),
+			Output::List("TodoHistoryEntry"),
+		),
+		server_fn::<GetDigestSubscription>(
+			"get_digest_subscription",
+			"The email digest the user is subscribed to, `null` when there is none",
+			None,
+			Output::Value("DigestSubscription"),
+		),
+		server_fn::<SetDigestSubscription>(
+			"set_digest_subscription",
+			"Subscribe to or unsubscribe from the email digest of open todos",
+			Some("SetDigestSubscriptionArgs"),
+			Output::Nothing,
+		),
+		server_fn::<GetCalendarFeed>(
+			"get_calendar_feed",
+			"When the calendar feed of the user was created, `null` when there is none",
+			None,
+			Output::Value("CalendarFeed"),
+		),
+		server_fn::<CreateCalendarFeed>(
+			"create_calendar_feed",
+			"Create the calendar feed of the user and return its address, only shown this once",
+			None,
+			Output::Text,
+		),
+		server_fn::<DeleteCalendarFeed>(
+			"delete_calendar_feed",
+			"Stop the calendar feed of the user from working",
+			None,
+			Output::Toasted(&Output::Nothing),
+		),
+		server_fn::<GetFeatureFlags>(
+			"get_feature_flags",
+			"The feature flags of the tenant",
+			None,
+			Output::Value("FeatureFlags"),
+		),
+		server_fn::<SetFeatureFlag>(
+			"set_feature_flag",
+			"Create or change a feature flag, admins only",
+			Some("SetFeatureFlagArgs"),
+			Output::Nothing,
+		),
+		server_fn::<DeleteFeatureFlag>(
+			"delete_feature_flag",
+			"Delete a feature flag, admins only",
+			Some("DeleteFeatureFlagArgs"),
+			Output::Nothing,
+		),
+		server_fn::<ValidatePermission>(
+			"validate_permission",
+			"Parse a permission string the way users are loaded, admins only",
+			Some("ValidatePermissionArgs"),
+			Output::Value("Permissions"),
+		),
+		server_fn::<GetManagedUsers>(
+			"get_managed_users",
+			"Every user of the tenant with their permission strings, admins only",
+			None,
+			Output::List("ManagedUser"),
+		),
+		server_fn::<SetUserPermissions>(
+			"set_user_permissions",
+			"Change the permission strings of a user, admins only",
+			Some("SetUserPermissionsArgs"),
+			Output::Nothing,
+		),
+		server_fn::<SetUserActive>(
+			"set_user_active",
+			"Deactivate a user so they can't log in anymore, or reactivate them, admins only",
+			Some("SetUserActiveArgs"),
+			Output::Toasted(&Output::Nothing),
+		),
+		server_fn::<GetServiceAccounts>(
+			"get_service_accounts",
+			"The service accounts of the tenant with their tokens, admins only",
+			None,
+			Output::List("ServiceAccount"),
+		),
+		server_fn::<CreateServiceAccount>(
+			"create_service_account",
+			"Create a service account, it can't log in and authenticates with API tokens only, admins only",
+			Some("CreateServiceAccountArgs"),
+			Output::Toasted(&Output::Nothing),
+		),
+		server_fn::<CreateApiToken>(
+			"create_api_token",
+			"Create an API token for a service account, narrowed down or not, shown only this once, admins only",
+			Some("CreateApiTokenArgs"),
+			Output::Text,
+		),
+		server_fn::<RotateApiToken>(
+			"rotate_api_token",
+			"Replace an API token with a new one and return it, the old one stops working, admins only",
+			Some("ApiTokenArgs"),
+			Output::Text,
+		),
+		server_fn::<RevokeApiToken>(
+			"revoke_api_token",
+			"Delete an API token so it stops working, admins only",
+			Some("ApiTokenArgs"),
+			Output::Toasted(&Output::Nothing),
+		),
+		server_fn::<PreviewPermissions>(
+			"preview_permissions",
+			"How many todos and equipment a user could read and change with other permission strings, admins only",
+			Some("PreviewPermissionsArgs"),
+			Output::List("PermissionPreview"),
+		),
+		server_fn::<GetScopeReach>(
+			"get_scope_reach",
+			"How many todos each scope held by users of the tenant gates and how many users hold it, admins only",
+			None,
+			Output::List("ScopeReach"),
+		),
+		server_fn::<GetPendingPermissionChanges>(
+			"get_pending_permission_changes",
+			"Permission changes of the tenant that didn't take effect yet, admins only",
+			None,
+			Output::List("PendingPermissionChange"),
+		),
+		server_fn::<SchedulePermissionChange>(
+			"schedule_permission_change",
+			"Give a user new permission strings at a later time, admins only",
+			Some("SchedulePermissionChangeArgs"),
+			Output::Toasted(&Output::Nothing),
+		),
+		server_fn::<CancelPermissionChange>(
+			"cancel_permission_change",
+			"Drop a scheduled permission change before it takes effect, admins only",
+			Some("CancelPermissionChangeArgs"),
+			Output::Toasted(&Output::Nothing),
+		),
+		server_fn::<GetDefaultPermissions>(
+			"get_default_permissions",
+			"The permission strings users who sign up start with, admins only",
+			None,
+			Output::Value("DefaultPermissions"),
+		),
+		server_fn::<SetDefaultPermissions>(
+			"set_default_permissions",
+			"Change the permission strings users who sign up start with, admins only",
+			Some("SetDefaultPermissionsArgs"),
+			Output::Nothing,
+		),
+		server_fn::<ImportUsers>(
+			"import_users",
+			"Create users from CSV with a generated password or an invite each, admins only",
+			Some("ImportUsersArgs"),
+			Output::Toasted(&Output::List("ImportRow")),
+		),
+		server_fn::<ExportPermissions>(
+			"export_permissions",
+			"The permissions of every user of the tenant as a JSON or YAML document, admins only",
+			Some("ExportPermissionsArgs"),
+			Output::Text,
+		),
+		server_fn::<ImportPermissions>(
+			"import_permissions",
+			"Apply an exported permission document to the users of the tenant, or only report what would change",
+			Some("ImportPermissionsArgs"),
+			Output::Toasted(&Output::List("PermissionDiff")),
+		),
+		server_fn::<ResolveScopes>(
+			"resolve_scopes",
+			"Name the todos and users permission scopes point to",
+			Some("ResolveScopesArgs"),
+			Output::List("ResolvedScope"),
+		),
+		server_fn::<ExplainPermissions>(
+			"explain_permissions",
+			"What the logged in user can and can't do with todos, users and equipment, in sentences",
+			None,
+			Output::List("PermissionExplanation"),
+		),
+		server_fn::<GetPermissionDenials>(
+			"get_permission_denials",
+			"Permission denials counted by this replica since it started, admins only",
+			None,
+			Output::List("DenialCount"),
+		),
+		server_fn::<QueryDatabase>(
+			"query_database",
+			"Read rows of an allowlisted table of the tenant, admins only when the console is turned on",
+			Some("QueryDatabaseArgs"),
+			Output::Value("QueryResult"),
+		),
+		server_fn::<GetStartupReport>(
+			"get_startup_report",
+			"What the server started with, the config with secrets left out only for admins running the deployment",
+			None,
+			Output::Value("StartupReport"),
+		),
+	]
+}
+
+fn components() -> Components {
+	ComponentsBuilder::new()
+		.schema_from::<LoginArgs>()
+		.schema_from::<SignupArgs>()
+		.schema_from::<ReauthenticateArgs>()
+		.schema_from::<PingSessionArgs>()
+		.schema_from::<RequestMagicLinkArgs>()
+		.schema_from::<SetLoginEmailArgs>()
+		.schema_from::<LinkPasswordArgs>()
+		.schema_from::<ChangeUsernameArgs>()
+		.schema_from::<UnlinkIdentityArgs>()
+		.schema_from::<GetTodosArgs>()
+		.schema_from::<GetTodoArgs>()
+		.schema_from::<AddTodoArgs>()
+		.schema_from::<DeleteTodoArgs>()
+		.schema_from::<UndoArgs>()
+		.schema_from::<ArchiveTodoArgs>()
+		.schema_from::<GetAssignableUsersArgs>()
+		.schema_from::<DescribeTodoArgs>()
+		.schema_from::<MoveTodoArgs>()
+		.schema_from::<CreateProjectArgs>()
+		.schema_from::<DeleteProjectArgs>()
+		.schema_from::<Project>()
+		.schema_from::<AssignTodoArgs>()
+		.schema_from::<ArchiveCompletedArgs>()
+		.schema_from::<GetAttachmentsArgs>()
+		.schema_from::<GetCommentsArgs>()
+		.schema_from::<AddCommentArgs>()
+		.schema_from::<DeleteCommentArgs>()
+		.schema_from::<GetShareLinksArgs>()
+		.schema_from::<CreateShareLinkArgs>()
+		.schema_from::<RevokeShareLinkArgs>()
+		.schema_from::<GetSharedTodoArgs>()
+		.schema_from::<GrantAccessArgs>()
+		.schema_from::<RevokeDelegationArgs>()
+		.schema_from::<GetTodoHistoryArgs>()
+		.schema_from::<SetRecurrenceArgs>()
+		.schema_from::<SetPriorityArgs>()
+		.schema_from::<EditTodoArgs>()
+		.schema_from::<UpdatePositionsArgs>()
+		.schema_from::<MarkNotificationsReadArgs>()
+		.schema_from::<SetNotificationPreferencesArgs>()
+		.schema_from::<AddIntegrationArgs>()
+		.schema_from::<SetIntegrationEventsArgs>()
+		.schema_from::<DeleteIntegrationArgs>()
+		.schema_from::<SetDigestSubscriptionArgs>()
+		.schema_from::<SetFeatureFlagArgs>()
+		.schema_from::<DeleteFeatureFlagArgs>()
+		.schema_from::<User>()
+		.schema_from::<PublicUser>()
+		.schema_from::<Permissions>()
+		.schema_from::<Permission>()
+		.schema_from::<Scope>()
+		.schema_from::<JwtTokens>()
+		.schema_from::<Todo>()
+		.schema_from::<TodoListItem>()
+		.schema_from::<TodoList>()
+		.schema_from::<EditResult>()
+		.schema_from::<TodoCounts>()
+		.schema_from::<Recurrence>()
+		.schema_from::<Priority>()
+		.schema_from::<Notification>()
+		.schema_from::<NotificationPreferences>()
+		.schema_from::<Integration>()
+		.schema_from::<IntegrationKind>()
+		.schema_from::<IntegrationEvent>()
+		.schema_from::<DigestSubscription>()
+		.schema_from::<CalendarFeed>()
+		.schema_from::<DigestFrequency>()
+		.schema_from::<Identity>()
+		.schema_from::<Provider>()
+		.schema_from::<FeatureFlags>()
+		.schema_from::<FeatureFlag>()
+		.schema_from::<ManagedUser>()
+		.schema_from::<ResolvedScope>()
+		.schema_from::<PermissionExplanation>()
+		.schema_from::<ResolveScopesArgs>()
+		.schema_from::<ValidatePermissionArgs>()
+		.schema_from::<SetUserPermissionsArgs>()
+		.schema_from::<SetUserActiveArgs>()
+		.schema_from::<CreateServiceAccountArgs>()
+		.schema_from::<CreateApiTokenArgs>()
+		.schema_from::<ApiTokenArgs>()
+		.schema_from::<ServiceAccount>()
+		.schema_from::<ApiToken>()
+		.schema_from::<PreviewPermissionsArgs>()
+		.schema_from::<PermissionPreview>()
+		.schema_from::<ScopeReach>()
+		.schema_from::<SchedulePermissionChangeArgs>()
+		.schema_from::<CancelPermissionChangeArgs>()
+		.schema_from::<PendingPermissionChange>()
+		.schema_from::<DefaultPermissions>()
+		.schema_from::<SetDefaultPermissionsArgs>()
+		.schema_from::<ImportUsersArgs>()
+		.schema_from::<ImportRow>()
+		.schema_from::<ExportPermissionsArgs>()
+		.schema_from::<ImportPermissionsArgs>()
+		.schema_from::<PermissionFormat>()
+		.schema_from::<PermissionDocument>()
+		.schema_from::<PermissionAssignment>()
+		.schema_from::<PermissionDiff>()
+		.schema_from::<PermissionDiffStatus>()
+		.schema_from::<PermissionChange>()
+		.schema_from::<ImportStatus>()
+		.schema_from::<DenialCount>()
+		.schema_from::<QueryDatabaseArgs>()
+		.schema_from::<QueryResult>()
+		.schema_from::<StartupReport>()
+		.schema_from::<ServerSetup>()
+		.schema_from::<Setting>()
+		.schema_from::<EnabledFlag>()
+		.schema_from::<DeniedAction>()
+		.schema_from::<Attachment>()
+		.schema_from::<Comment>()
+		.schema_from::<ShareLink>()
+		.schema_from::<SharedTodo>()
+		.schema_from::<Delegation>()
+		.schema_from::<TodoHistoryEntry>()
+		.schema_from::<TodoChange>()
+		.schema_from::<ErrorCode>()
+		.schema_from::<AppServerError>()
+		.schema_from::<Toast>()
+		.schema_from::<ToastLevel>()
+		.build()
+}
+
+/// Describe the `/api/*fn_name` server function endpoints
+pub fn server_fns() -> OpenApi {
+	let paths =
+		endpoints().into_iter().fold(PathsBuilder::new(), |paths, endpoint| paths.path(endpoint.path, endpoint.item));
+
+	OpenApiBuilder::new()
+		.info(InfoBuilder::new().title("Server functions").version("1").build())
+		.paths(paths.build())
+		.components(Some(components()))
+		.build()
+}
+
+pub async fn server_fns_json() -> axum::Json<OpenApi> {
+	axum::Json(server_fns())
+}
+
+/// Swagger UI for the server functions and the REST API, the assets are loaded from a CDN so nothing has to be bundled
+pub async fn swagger_ui() -> axum::response::Html<&'static str> {
+	axum::response::Html(
+		r##"<!DOCTYPE html>
+<html>
+	<head>
+		<title>API docs</title>
+		<link rel="stylesheet" href="https://unpkg.com/swagger-ui-dist@5/swagger-ui.css" />
+	</head>
+	<body>
+		<div id="swagger-ui"></div>
+		<script src="https://unpkg.com/swagger-ui-dist@5/swagger-ui-bundle.js"></script>
+		<script src="https://unpkg.com/swagger-ui-dist@5/swagger-ui-standalone-preset.js"></script>
+		<script>
+			window.ui = SwaggerUIBundle({
+				urls: [
+					{ url: "/api/openapi.json", name: "Server functions" },
+					{ url: "/api/v1/openapi.json", name: "REST API v1" },
+				],
+				dom_id: "#swagger-ui",
+				presets: [SwaggerUIBundle.presets.apis, SwaggerUIStandalonePreset],
+				layout: "StandaloneLayout",
+			});
+		</script>
+	</body>
+</html>"##,
+	)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::collections::HashSet;
+
+	#[test]
+	fn every_server_fn_is_documented() {
+		let documented = endpoints().into_iter().map(|endpoint| endpoint.path).collect::<HashSet<_>>();
+		for (path, _) in leptos::server_fn::axum::server_fn_paths() {
+			assert!(documented.contains(path), "{path} is missing from the OpenAPI spec");
+		}
+	}
+
+	#[test]
+	fn args_match_the_signatures() {
+		let components = components();
+		for endpoint in endpoints() {
+			let mut documented = match endpoint.args {
+				None => Vec::new(),
+				Some(args) => match components.schemas.get(args) {
+					Some(RefOr::T(Schema::Object(object))) => object.properties.keys().map(String::as_str).collect(),
+					_ => panic!("{args} of {} isn't a registered object schema", endpoint.path),
+				},
+			};
+			let mut fields = endpoint.fields.to_vec();
+			documented.sort_unstable();
+			fields.sort_unstable();
+
+			assert_eq!(documented, fields, "The arguments of {} are documented wrong", endpoint.path);
+		}
+	}
+}
@@ -0,0 +1,165 @@
+//! Just enough PDF to put lines of text on A4 pages, for checklists that are printed
+//!
+//! The text is set in Helvetica, which every PDF reader has, so nothing needs to be embedded. Helvetica only covers
+//! Latin-1, other characters come out as `?`.
+
+/// A4 in points
+const PAGE_WIDTH: u32 = 595;
+const PAGE_HEIGHT: u32 = 842;
+const MARGIN: u32 = 50;
+const TITLE_SIZE: u32 = 16;
+const FONT_SIZE: u32 = 11;
+const LEADING: u32 = 16;
+/// Lines are wrapped so they fit between the margins even with the wider letters of Helvetica
+const MAX_LINE_LENGTH: usize = 85;
+/// What fits between the title, or the top margin on later pages, and the page numbers
+const LINES_PER_PAGE: usize = ((PAGE_HEIGHT - 2 * MARGIN - 2 * LEADING) / LEADING) as usize;
+
+/// A document with the title on top of the first page and the lines on as many pages as they take
+pub fn text_document(title: &str, lines: &[String]) -> Vec<u8> {
+	let lines = lines.iter().flat_map(|line| wrap(line)).collect::<Vec<_>>();
+	let pages = lines.chunks(LINES_PER_PAGE).collect::<Vec<_>>();
+	let pages = if pages.is_empty() { vec![&[][..]] } else { pages };
+
+	// The catalog, the page tree and the font come first, then a page and its content for every page
+	let page_ids = (0..pages.len()).map(|page| 4 + 2 * page).collect::<Vec<_>>();
+	let mut objects = vec![
+		String::from("<< /Type /Catalog /Pages 2 0 R >>").into_bytes(),
+		format!(
+			"<< /Type /Pages /Kids [{}] /Count {} >>",
+			page_ids.iter().map(|id| format!("{id} 0 R")).collect::<Vec<_>>().join(" "),
+			pages.len()
+		)
+		.into_bytes(),
+		String::from("<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica /Encoding /WinAnsiEncoding >>").into_bytes(),
+	];
+	for (index, lines) in pages.iter().enumerate() {
+		let content = page_content((index == 0).then_some(title), lines, index + 1, pages.len());
+		objects.push(
+			format!(
+				"<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {PAGE_WIDTH} {PAGE_HEIGHT}] \
+				/Resources << /Font << /F1 3 0 R >> >> /Contents {} 0 R >>",
+				page_ids[index] + 1
+			)
+			.into_bytes(),
+		);
+		let mut stream = format!("<< /Length {} >>\nstream\n", content.len()).into_bytes();
+		stream.extend(content);
+		stream.extend(b"\nendstream");
+		objects.push(stream);
+	}
+
+	let mut pdf = b"%PDF-1.4\n".to_vec();
+	let mut offsets = Vec::with_capacity(objects.len());
+	for (index, object) in objects.iter().enumerate() {
+		offsets.push(pdf.len());
+		pdf.extend(format!("{} 0 obj\n", index + 1).into_bytes());
+		pdf.extend(object);
+		pdf.extend(b"\nendobj\n");
+	}
+	let xref = pdf.len();
+	pdf.extend(format!("xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1).into_bytes());
+	for offset in offsets {
+		pdf.extend(format!("{offset:010} 00000 n \n").into_bytes());
+	}
+	pdf
+		.extend(format!("trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{xref}\n%%EOF\n", objects.len() + 1).into_bytes());
+
+	pdf
+}
+
+fn page_content(title: Option<&str>, lines: &[String], page: usize, pages: usize) -> Vec<u8> {
+	let mut content = Vec::new();
+	let top = PAGE_HEIGHT - MARGIN;
+	if let Some(title) = title {
+		content.extend(format!("BT /F1 {TITLE_SIZE} Tf {MARGIN} {top} Td ").into_bytes());
+		content.extend(text(title));
+		content.extend(b" Tj ET\n");
+	}
+
+	let first_line = if title.is_some() { top - 2 * LEADING } else { top };
+	content.extend(format!("BT /F1 {FONT_SIZE} Tf {LEADING} TL {MARGIN} {first_line} Td\n").into_bytes());
+	for line in lines {
+		content.extend(text(line));
+		content.extend(b" Tj T*\n");
+	}
+	content.extend(b"ET\n");
+
+	content.extend(format!("BT /F1 9 Tf {MARGIN} {} Td ", MARGIN / 2).into_bytes());
+	content.extend(text(&format!("Page {page} of {pages}")));
+	content.extend(b" Tj ET");
+
+	content
+}
+
+/// A string of the PDF in Latin-1, with what would end it escaped
+fn text(text: &str) -> Vec<u8> {
+	let mut bytes = vec![b'('];
+	for character in text.chars() {
+		match character {
+			'(' | ')' | '\\' => bytes.extend([b'\\', character as u8]),
+			' '..='~' | '\u{a0}'..='\u{ff}' => bytes.push(character as u32 as u8),
+			_ => bytes.push(b'?'),
+		}
+	}
+	bytes.push(b')');
+
+	bytes
+}
+
+/// The line broken up at spaces where it's too long, words too long for a line of their own are cut
+fn wrap(line: &str) -> Vec<String> {
+	let mut lines = Vec::new();
+	let mut current = String::new();
+	for word in line.split(' ') {
+		let mut word = word.chars().collect::<Vec<_>>();
+		while word.len() > MAX_LINE_LENGTH {
+			if !current.is_empty() {
+				lines.push(std::mem::take(&mut current));
+			}
+			lines.push(word.drain(..MAX_LINE_LENGTH).collect());
+		}
+		let word = word.into_iter().collect::<String>();
+		if !current.is_empty() && current.chars().count() + 1 + word.chars().count() > MAX_LINE_LENGTH {
+			lines.push(std::mem::take(&mut current));
+		}
+		if !current.is_empty() {
+			current.push(' ');
+		}
+		current.push_str(&word);
+	}
+	lines.push(current);
+
+	lines
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn documents_are_well_formed() {
+		let lines = (0..100).map(|line| format!("[ ] Check the (spare) tyres {line} ✓")).collect::<Vec<_>>();
+		let pdf = text_document("Todos", &lines);
+		let text = String::from_utf8_lossy(&pdf);
+
+		assert!(text.starts_with("%PDF-1.4\n") && text.ends_with("%%EOF\n"));
+		assert!(text.contains("/Count 3 >>"), "100 lines take 3 pages");
+		assert!(text.contains("([ ] Check the \\(spare\\) tyres 99 ?) Tj"));
+
+		// Every object is where the cross reference table says it is
+		let xref = text.rfind("xref\n").unwrap();
+		for (index, entry) in text[xref..].lines().skip(3).take_while(|line| line.ends_with(" n ")).enumerate() {
+			let offset = entry[..10].parse::<usize>().unwrap();
+			assert!(text[offset..].starts_with(&format!("{} 0 obj", index + 1)));
+		}
+	}
+
+	#[test]
+	fn long_lines_wrap() {
+		let words = "word ".repeat(40);
+		assert!(wrap(&words).iter().all(|line| line.chars().count() <= MAX_LINE_LENGTH));
+		assert_eq!(wrap(&"a".repeat(MAX_LINE_LENGTH + 5)).len(), 2);
+		assert_eq!(wrap(""), vec![String::new()]);
+	}
+}
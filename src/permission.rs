@@ -1,15 +1,20 @@
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 #[cfg(feature = "ssr")]
 use std::fmt::Write;
+use std::sync::OnceLock;
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "ssr", derive(utoipa::ToSchema))]
 pub enum Scope {
 	Equipment(i32),
 	Person(i32),
+	/// Todos of a project, todos outside of any project aren't part of it
+	Project(i32),
 	Any,
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "ssr", derive(utoipa::ToSchema))]
 pub enum Permission {
 	ReadAny,
 	Read(Vec<Scope>),
@@ -19,6 +24,7 @@ pub enum Permission {
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "ssr", derive(utoipa::ToSchema))]
 pub enum Permissions {
 	ReadWrite {
 		read: Permission,
@@ -27,7 +33,255 @@ pub enum Permissions {
 	},
 }
 
+/// A permission string as stored, parsed the first time it is checked
+///
+/// Loading a user doesn't pay for parsing the permissions of resources the request never touches. It serializes as the
+/// parsed [`Permissions`] so clients get them ready to use.
+#[derive(Clone, Debug)]
+pub struct LazyPermissions {
+	#[cfg_attr(not(feature = "ssr"), allow(dead_code))]
+	raw: String,
+	parsed: OnceLock<Permissions>,
+}
+
+impl LazyPermissions {
+	#[cfg(feature = "ssr")]
+	pub fn new(raw: String) -> Self {
+		Self {
+			raw,
+			parsed: OnceLock::new(),
+		}
+	}
+
+	/// Panics on an invalid permission string, every way users are stored validates them first
+	#[cfg(feature = "ssr")]
+	pub fn get(&self) -> &Permissions {
+		self.parsed.get_or_init(|| Permission::parse(self.raw.clone()).expect("Invalid permission string"))
+	}
+
+	#[cfg(not(feature = "ssr"))]
+	pub fn get(&self) -> &Permissions {
+		self.parsed.get().expect("Permissions reach the client parsed")
+	}
+}
+
+impl From<Permissions> for LazyPermissions {
+	fn from(permissions: Permissions) -> Self {
+		Self {
+			raw: String::new(),
+			parsed: OnceLock::from(permissions),
+		}
+	}
+}
+
+impl PartialEq for LazyPermissions {
+	fn eq(&self, other: &Self) -> bool {
+		self.get() == other.get()
+	}
+}
+
+impl Eq for LazyPermissions {}
+
+impl Serialize for LazyPermissions {
+	fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+		self.get().serialize(serializer)
+	}
+}
+
+impl<'de> Deserialize<'de> for LazyPermissions {
+	fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+		Permissions::deserialize(deserializer).map(Self::from)
+	}
+}
+
+#[cfg(feature = "ssr")]
+/// Scopes listing more ids of a kind than this are bound as an array by [`Permission::query_filter`]
+pub const INLINE_SCOPE_IDS: usize = 100;
+
+/// A permission clause to append to a query, bind each of `arrays` in order after the parameters of the query
+#[cfg(feature = "ssr")]
+#[derive(Debug, PartialEq, Eq)]
+pub struct ScopeFilter {
+	pub sql: String,
+	pub arrays: Vec<Vec<i32>>,
+}
+
+#[cfg(feature = "ssr")]
+fn in_list(column: &str, ids: &[i32]) -> String {
+	let mut list = String::new();
+	for id in ids {
+		if !list.is_empty() {
+			list.push(',');
+		}
+		write!(&mut list, "{id}").unwrap();
+	}
+
+	format!("{column} IN ({list})")
+}
+
+/// The column equipment scopes are matched on, tables without a column of their own for it match them on the id
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IdColumn {
+	Id,
+	Equipment,
+}
+
+impl IdColumn {
+	pub fn name(&self) -> &'static str {
+		match self {
+			IdColumn::Id => "id",
+			IdColumn::Equipment => "equipment",
+		}
+	}
+}
+
+impl std::str::FromStr for IdColumn {
+	type Err = String;
+
+	fn from_str(column: &str) -> Result<Self, Self::Err> {
+		match column {
+			"id" => Ok(IdColumn::Id),
+			"equipment" => Ok(IdColumn::Equipment),
+			_ => Err(format!("Unknown id column \"{column}\"")),
+		}
+	}
+}
+
+/// The database a [`Filter`] is written for, which decides how its parameters look
+#[cfg(feature = "ssr")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Dialect {
+	/// Numbered `$1` parameters, id lists are bound as `int4[]`
+	Postgres,
+	/// Numbered `?1` parameters, id lists are bound as JSON text like `[1,2,3]` since there are no arrays
+	Sqlite,
+}
+
+/// A permission clause before it's written as SQL, so the permission strings and the policies share how clauses are
+/// written out whether the ids are inlined or bound, and for which database
+#[cfg(feature = "ssr")]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Filter {
+	/// Every row passes, which leaves the clause out
+	True,
+	False,
+	/// The column is one of the ids, no ids pass no row
+	In(&'static str, Vec<i32>),
+	/// Every filter passes, none at all is like [`Filter::True`]
+	And(Vec<Filter>),
+	/// Any of the filters passes, none at all is like [`Filter::False`]
+	Or(Vec<Filter>),
+}
+
+#[cfg(feature = "ssr")]
+impl Filter {
+	/// The condition with every id written into it, `None` when every row passes
+	pub fn to_inline_sql(&self) -> Option<String> {
+		self.condition(&mut Lists::Inline)
+	}
+
+	/// The condition to append to a query with id lists longer than [`INLINE_SCOPE_IDS`] bound as parameters of the
+	/// dialect, numbered from `first_param` on
+	pub fn to_bound_sql(&self, dialect: Dialect, first_param: usize) -> ScopeFilter {
+		let mut lists = Lists::Bound {
+			dialect,
+			first_param,
+			arrays: Vec::new(),
+		};
+		let condition = self.condition(&mut lists);
+		let Lists::Bound { arrays, .. } = lists else {
+			unreachable!("The lists were bound");
+		};
+
+		match condition {
+			None => ScopeFilter {
+				sql: String::new(),
+				arrays: Vec::new(),
+			},
+			Some(condition) => ScopeFilter {
+				sql: format!(" AND {condition}"),
+				arrays,
+			},
+		}
+	}
+
+	fn condition(&self, lists: &mut Lists) -> Option<String> {
+		match self {
+			Filter::True => None,
+			Filter::False => Some(String::from("false")),
+			Filter::In(_, ids) if ids.is_empty() => Some(String::from("false")),
+			Filter::In(column, ids) => Some(lists.list(column, ids)),
+			Filter::And(filters) => {
+				let conditions = filters.iter().filter_map(|filter| filter.condition(lists)).collect::<Vec<_>>();
+				(!conditions.is_empty()).then(|| conditions.join(" AND "))
+			},
+			Filter::Or(filters) if filters.is_empty() => Some(String::from("false")),
+			Filter::Or(filters) => {
+				let bound = lists.bound();
+				let mut alternatives = Vec::new();
+				for filter in filters {
+					match filter.condition(lists) {
+						Some(condition) => alternatives.push(format!("({condition})")),
+						// Alternatives before one that passes everything may have bound lists the clause doesn't use,
+						// they'd shift the parameters of whatever comes after
+						None => {
+							lists.truncate(bound);
+							return None;
+						},
+					}
+				}
+				Some(format!("({})", alternatives.join(" OR ")))
+			},
+		}
+	}
+}
+
+/// How [`Filter::condition`] writes id lists, either inline or bound as parameters once they are long
 #[cfg(feature = "ssr")]
+enum Lists {
+	Inline,
+	Bound {
+		dialect: Dialect,
+		first_param: usize,
+		arrays: Vec<Vec<i32>>,
+	},
+}
+
+#[cfg(feature = "ssr")]
+impl Lists {
+	fn list(&mut self, column: &str, ids: &[i32]) -> String {
+		match self {
+			Lists::Bound {
+				dialect,
+				first_param,
+				arrays,
+			} if ids.len() > INLINE_SCOPE_IDS => {
+				arrays.push(ids.to_vec());
+				let param = *first_param + arrays.len() - 1;
+				match dialect {
+					Dialect::Postgres => format!("{column} = ANY(${param}::int4[])"),
+					Dialect::Sqlite => format!("{column} IN (SELECT value FROM json_each(?{param}))"),
+				}
+			},
+			_ => in_list(column, ids),
+		}
+	}
+
+	/// How many lists are bound so far
+	fn bound(&self) -> usize {
+		match self {
+			Lists::Inline => 0,
+			Lists::Bound { arrays, .. } => arrays.len(),
+		}
+	}
+
+	fn truncate(&mut self, bound: usize) {
+		if let Lists::Bound { arrays, .. } = self {
+			arrays.truncate(bound);
+		}
+	}
+}
+
 impl Permission {
 	pub fn parse(perm: String) -> Result<Permissions, &'static str> {
 		let perms: String = perm.chars().filter(|&c| c != ' ' && c != ')').map(|c| c.to_ascii_uppercase()).collect();
@@ -76,6 +330,15 @@ impl Permission {
 								},
 								_ => return Err("Invalid permission string (Unrecognized action)"),
 							},
+							"PROJECT" => match action {
+								"READ" => {
+									read_scopes.push(Scope::Project(id));
+								},
+								"WRITE" => {
+									write_scopes.push(Scope::Project(id));
+								},
+								_ => return Err("Invalid permission string (Unrecognized action)"),
+							},
 							_ => return Err("Invalid permission string (Unrecognized scope)"),
 						}
 					}
@@ -110,60 +373,346 @@ impl Permission {
 		}
 	}
 
-	pub fn get_query_select(&self, field: &str) -> String {
-		let field_sanitized = match field {
-			"id" => "id",
-			"equipment" => "equipment",
-			_ => "id",
-		};
-
-		let mut query = String::new();
+	/// The clause rows pass, with the ids of equipment scopes matched on `field`
+	#[cfg(feature = "ssr")]
+	pub fn filter(&self, field: IdColumn) -> Filter {
 		match self {
-			Permission::ReadAny | Permission::WriteAny | Permission::Write(_) | Permission::Create(_) => {},
-			Permission::Read(scope) => {
-				let mut equipment_ids = String::new();
-				let mut person_ids = String::new();
+			Permission::ReadAny | Permission::WriteAny | Permission::Create(_) => Filter::True,
+			// No scope at all allows nothing, leaving the clause out would allow everything
+			Permission::Read(scope) | Permission::Write(scope) if scope.is_empty() => Filter::False,
+			Permission::Read(scope) | Permission::Write(scope) => {
+				let mut equipment_ids = Vec::new();
+				let mut person_ids = Vec::new();
+				let mut project_ids = Vec::new();
 
 				for item in scope.iter() {
 					match item {
-						Scope::Equipment(id) => {
-							if !equipment_ids.is_empty() {
-								equipment_ids.push(',');
-							}
-							write!(&mut equipment_ids, "{id}").unwrap();
-						},
-						Scope::Person(id) => {
-							if !person_ids.is_empty() {
-								person_ids.push(',');
-							}
-							write!(&mut person_ids, "{id}").unwrap();
-						},
+						Scope::Equipment(id) => equipment_ids.push(*id),
+						Scope::Person(id) => person_ids.push(*id),
+						Scope::Project(id) => project_ids.push(*id),
 						Scope::Any => {},
 					}
 				}
 
-				let mut first_clause = true;
-				if !equipment_ids.is_empty() || !person_ids.is_empty() {
-					write!(&mut query, " WHERE ").unwrap();
-				}
-				if !equipment_ids.is_empty() {
-					write!(&mut query, "{field_sanitized} IN ({equipment_ids})").unwrap();
-					first_clause = false;
-				}
-				if !person_ids.is_empty() {
-					if !first_clause {
-						write!(&mut query, " AND ").unwrap();
+				Filter::And(
+					[
+						(field.name(), equipment_ids),
+						("person", person_ids),
+						("project", project_ids),
+					]
+					.into_iter()
+					.filter(|(_, ids)| !ids.is_empty())
+					.map(|(column, ids)| Filter::In(column, ids))
+					.collect(),
+				)
+			},
+		}
+	}
+
+	#[cfg(feature = "ssr")]
+	pub fn get_query_select(&self, field: IdColumn) -> String {
+		self.filter(field).to_inline_sql().map(|condition| format!(" WHERE {condition}")).unwrap_or_default()
+	}
+
+	#[cfg(feature = "ssr")]
+	pub fn get_query_select_without_where(&self, field: IdColumn) -> String {
+		self.filter(field).to_inline_sql().map(|condition| format!(" AND {condition}")).unwrap_or_default()
+	}
+
+	/// The clause of [`Permission::get_query_select_without_where`] with id lists longer than [`INLINE_SCOPE_IDS`]
+	/// bound as arrays, numbered from `first_param` on
+	///
+	/// Thousands of ids written into the query make for huge statements the planner is slow with, an array parameter
+	/// keeps the statement the same size however long the scope gets.
+	#[cfg(feature = "ssr")]
+	pub fn query_filter(&self, field: IdColumn, first_param: usize) -> ScopeFilter {
+		self.filter(field).to_bound_sql(Dialect::Postgres, first_param)
+	}
+
+	/// Whether a row with `id` in the filtered field, `person` and `project` passes the clause of
+	/// [`Permission::get_query_select`], for checking rows that were never loaded through it
+	pub fn covers(&self, id: i32, person: i32, project: Option<i32>) -> bool {
+		match self {
+			Permission::ReadAny | Permission::WriteAny | Permission::Create(_) => true,
+			Permission::Read(scope) | Permission::Write(scope) if scope.is_empty() => false,
+			Permission::Read(scope) | Permission::Write(scope) => {
+				let equipment = scope.iter().filter_map(|item| match item {
+					Scope::Equipment(id) => Some(*id),
+					_ => None,
+				});
+				let persons = scope.iter().filter_map(|item| match item {
+					Scope::Person(id) => Some(*id),
+					_ => None,
+				});
+				let projects = scope.iter().filter_map(|item| match item {
+					Scope::Project(id) => Some(*id),
+					_ => None,
+				});
+
+				(equipment.clone().next().is_none() || equipment.clone().any(|allowed| allowed == id))
+					&& (persons.clone().next().is_none() || persons.clone().any(|allowed| allowed == person))
+					&& (projects.clone().next().is_none() || projects.clone().any(|allowed| Some(allowed) == project))
+			},
+		}
+	}
+
+	/// Whether the scope reaches into a project at all, scopes not limited to projects reach into every one
+	pub fn covers_project(&self, project: i32) -> bool {
+		match self {
+			Permission::ReadAny | Permission::WriteAny => true,
+			Permission::Create(_) => false,
+			Permission::Read(scope) | Permission::Write(scope) if scope.is_empty() => false,
+			Permission::Read(scope) | Permission::Write(scope) => {
+				let mut projects = scope.iter().filter_map(|item| match item {
+					Scope::Project(id) => Some(*id),
+					_ => None,
+				});
+
+				projects.clone().next().is_none() || projects.any(|allowed| allowed == project)
+			},
+		}
+	}
+
+	/// Whether every row with an id out of `equipment` and a person out of `persons` passes the clause, an empty list
+	/// standing for any id like it does in a scope
+	///
+	/// Those rows can be in any project, so scopes limited to projects never include them.
+	pub fn includes(&self, equipment: &[i32], persons: &[i32]) -> bool {
+		match self {
+			Permission::ReadAny | Permission::WriteAny => true,
+			Permission::Create(_) => false,
+			Permission::Read(scope) | Permission::Write(scope) if scope.is_empty() => false,
+			Permission::Read(scope) | Permission::Write(scope) => {
+				let own_equipment = scope
+					.iter()
+					.filter_map(|item| match item {
+						Scope::Equipment(id) => Some(*id),
+						_ => None,
+					})
+					.collect::<Vec<_>>();
+				let own_persons = scope
+					.iter()
+					.filter_map(|item| match item {
+						Scope::Person(id) => Some(*id),
+						_ => None,
+					})
+					.collect::<Vec<_>>();
+				let within =
+					|own: &[i32], ids: &[i32]| own.is_empty() || (!ids.is_empty() && ids.iter().all(|id| own.contains(id)));
+
+				!scope.iter().any(|item| matches!(item, Scope::Project(_)))
+					&& within(&own_equipment, equipment)
+					&& within(&own_persons, persons)
+			},
+		}
+	}
+
+	/// What both permissions for the same action allow, the rows that pass both clauses
+	///
+	/// The ids of a kind both limit are narrowed down to the ones they have in common, a kind only one of them limits
+	/// stays limited like that.
+	#[cfg(feature = "ssr")]
+	pub fn intersect(&self, other: &Permission) -> Permission {
+		// `None` for every row
+		fn scopes(permission: &Permission) -> Option<&[Scope]> {
+			match permission {
+				Permission::Read(scope) | Permission::Write(scope) if !scope.contains(&Scope::Any) => Some(scope),
+				_ => None,
+			}
+		}
+		let narrowed = |scope: Vec<Scope>| match self {
+			Permission::ReadAny | Permission::Read(_) => Permission::Read(scope),
+			_ => Permission::Write(scope),
+		};
+
+		if let Permission::Create(create) = self {
+			return Permission::Create(*create && *other == Permission::Create(true));
+		}
+		let (ours, theirs) = match (scopes(self), scopes(other)) {
+			(None, None) => return self.clone(),
+			(Some(scope), None) | (None, Some(scope)) => return narrowed(scope.to_vec()),
+			(Some(ours), Some(theirs)) => (ours, theirs),
+		};
+		// No scope at all allows nothing
+		if ours.is_empty() || theirs.is_empty() {
+			return narrowed(Vec::new());
+		}
+
+		let kinds: [fn(&Scope) -> bool; 3] = [
+			|item| matches!(item, Scope::Equipment(_)),
+			|item| matches!(item, Scope::Person(_)),
+			|item| matches!(item, Scope::Project(_)),
+		];
+		let mut scope = Vec::new();
+		for kind in kinds {
+			let ours = ours.iter().copied().filter(kind).collect::<Vec<_>>();
+			let theirs = theirs.iter().copied().filter(kind).collect::<Vec<_>>();
+			match (ours.is_empty(), theirs.is_empty()) {
+				(true, _) => scope.extend(theirs),
+				(_, true) => scope.extend(ours),
+				_ => {
+					let both = ours.into_iter().filter(|item| theirs.contains(item)).collect::<Vec<_>>();
+					// Both limit the kind to ids the other doesn't have, so no row passes
+					if both.is_empty() {
+						return narrowed(Vec::new());
 					}
-					write!(&mut query, "person IN ({person_ids})").unwrap();
-				}
+					scope.extend(both);
+				},
+			}
+		}
+
+		narrowed(scope)
+	}
+}
+
+impl Permissions {
+	/// What both allow, see [`Permission::intersect`]
+	#[cfg(feature = "ssr")]
+	pub fn intersect(&self, other: &Permissions) -> Permissions {
+		let (
+			Permissions::ReadWrite { read, write, create },
+			Permissions::ReadWrite {
+				read: other_read,
+				write: other_write,
+				create: other_create,
 			},
+		) = (self, other);
+
+		Permissions::ReadWrite {
+			read: read.intersect(other_read),
+			write: write.intersect(other_write),
+			create: create.intersect(other_create),
+		}
+	}
+
+	/// What the permissions allow on `resource` in sentences for the user they belong to, `label` names a scope
+	///
+	/// Scopes of different kinds narrow each other down like they do in queries, so they are joined with "and".
+	pub fn explain(&self, resource: &str, label: impl Fn(&Scope) -> String) -> Vec<String> {
+		let Permissions::ReadWrite { read, write, create } = self;
+		let scoped = |verb: &str, permission: &Permission| match permission {
+			Permission::ReadAny | Permission::WriteAny => format!("You can {verb} all {resource}"),
+			Permission::Read(scope) | Permission::Write(scope) if scope.contains(&Scope::Any) => {
+				format!("You can {verb} all {resource}")
+			},
+			Permission::Read(scope) | Permission::Write(scope) if !scope.is_empty() => {
+				let names = |kind: fn(&Scope) -> bool| {
+					scope.iter().filter(|item| kind(item)).map(&label).collect::<Vec<_>>().join(" or ")
+				};
+				let conditions = [
+					("that are", names(|item| matches!(item, Scope::Equipment(_)))),
+					("of", names(|item| matches!(item, Scope::Person(_)))),
+					("in", names(|item| matches!(item, Scope::Project(_)))),
+				]
+				.into_iter()
+				.filter(|(_, names)| !names.is_empty())
+				.map(|(prefix, names)| format!("{prefix} {names}"))
+				.collect::<Vec<_>>();
+
+				format!("You can {verb} {resource} {}", conditions.join(" and "))
+			},
+			_ => format!("You can't {verb} any {resource}"),
+		};
+		let create = if *create == Permission::Create(true) {
+			format!("You can create {resource}")
+		} else {
+			format!("You can't create {resource}")
+		};
+
+		vec![scoped("read", read), scoped("change", write), create]
+	}
+}
+
+#[cfg(feature = "ssr")]
+pub mod ssr {
+	use super::{Permission, Scope};
+	use crate::config::Config;
+	use std::{
+		future::Future,
+		sync::OnceLock,
+		time::{Duration, Instant},
+	};
+
+	/// Reports permission filtered queries that get expensive, so admins know which scopes to turn into groups
+	///
+	/// The scope is logged inside the span of the request, which names the server function and the user.
+	pub struct ScopeWatch {
+		max_ids: usize,
+		budget: Duration,
+	}
+
+	static WATCH: OnceLock<ScopeWatch> = OnceLock::new();
+
+	fn watch_from(config: &Config) -> ScopeWatch {
+		ScopeWatch::new(config.scope_list_warn_size, Duration::from_millis(config.scope_query_budget_ms))
+	}
+
+	/// Take `SCOPE_LIST_WARN_SIZE` and `SCOPE_QUERY_BUDGET_MS` from the config, call it on start before any request is
+	/// served
+	pub fn init_scope_watch(config: &Config) {
+		if WATCH.set(watch_from(config)).is_err() {
+			log::warn!("The scope watch was set up twice, keeping the first setting");
 		}
+	}
 
-		query
+	/// Watches scopes of more than `SCOPE_LIST_WARN_SIZE` ids and queries taking longer than `SCOPE_QUERY_BUDGET_MS`
+	/// milliseconds
+	pub fn scope_watch<'a>() -> &'a ScopeWatch {
+		WATCH.get_or_init(|| watch_from(&Config::default()))
 	}
 
-	pub fn get_query_select_without_where(&self, field: &str) -> String {
-		self.get_query_select(field).replace("WHERE", "AND")
+	fn scope(permission: &Permission) -> &[Scope] {
+		match permission {
+			Permission::Read(scope) | Permission::Write(scope) => scope,
+			Permission::ReadAny | Permission::WriteAny | Permission::Create(_) => &[],
+		}
+	}
+
+	/// How many ids of which kind a scope lists, with the first few of them
+	pub fn describe_scope(permission: &Permission) -> String {
+		let scope = scope(permission);
+		let equipment = scope.iter().filter(|item| matches!(item, Scope::Equipment(_))).count();
+		let person = scope.iter().filter(|item| matches!(item, Scope::Person(_))).count();
+		let project = scope.iter().filter(|item| matches!(item, Scope::Project(_))).count();
+		let first = scope.iter().take(5).map(|item| format!("{item:?}")).collect::<Vec<_>>().join(", ");
+		let more = if scope.len() > 5 { ", ..." } else { "" };
+
+		format!("{equipment} equipment, {person} person and {project} project ids [{first}{more}]")
+	}
+
+	impl ScopeWatch {
+		pub fn new(max_ids: usize, budget: Duration) -> Self {
+			Self { max_ids, budget }
+		}
+
+		pub fn too_many_ids(&self, permission: &Permission) -> bool {
+			scope(permission).len() > self.max_ids
+		}
+
+		/// Run a query filtered by `permission`, logging it when the scope is too long or the query too slow
+		pub async fn run<T>(&self, operation: &str, permission: &Permission, query: impl Future<Output = T>) -> T {
+			if self.too_many_ids(permission) {
+				log::warn!(
+					"{operation} filters by a scope of more than {} ids: {}, consider a group or a range",
+					self.max_ids,
+					describe_scope(permission)
+				);
+			}
+
+			let started = Instant::now();
+			let result = query.await;
+			let elapsed = started.elapsed();
+			if elapsed > self.budget {
+				log::warn!(
+					"{operation} took {}ms, over the budget of {}ms, with a scope of {}",
+					elapsed.as_millis(),
+					self.budget.as_millis(),
+					describe_scope(permission)
+				);
+			}
+
+			result
+		}
 	}
 }
 
@@ -214,6 +763,15 @@ mod tests {
 			})
 		);
 
+		assert_eq!(
+			Permission::parse(String::from("READ(project[2],person[1])|WRITE(project[2])|CREATE(false)")),
+			Ok(Permissions::ReadWrite {
+				read: Permission::Read(vec![Scope::Project(2), Scope::Person(1)]),
+				write: Permission::Write(vec![Scope::Project(2)]),
+				create: Permission::Create(false),
+			})
+		);
+
 		assert_eq!(
 			Permission::parse(String::from("WriTE(*)|REad(*)|CREATE(true)")),
 			Ok(Permissions::ReadWrite {
@@ -380,23 +938,22 @@ mod tests {
 	#[test]
 	fn get_query_select_test() {
 		assert_eq!(
-			Permission::Read(vec![Scope::Equipment(1), Scope::Equipment(2), Scope::Equipment(3)]).get_query_select("id"),
+			Permission::Read(vec![Scope::Equipment(1), Scope::Equipment(2), Scope::Equipment(3)])
+				.get_query_select(IdColumn::Id),
 			String::from(" WHERE id IN (1,2,3)")
 		);
 		assert_eq!(
 			Permission::Read(vec![Scope::Equipment(1), Scope::Equipment(2), Scope::Equipment(3)])
-				.get_query_select("equipment"),
+				.get_query_select(IdColumn::Equipment),
 			String::from(" WHERE equipment IN (1,2,3)")
 		);
 		assert_eq!(
-			Permission::Read(vec![Scope::Person(1), Scope::Person(2), Scope::Person(3)]).get_query_select("id"),
+			Permission::Read(vec![Scope::Person(1), Scope::Person(2), Scope::Person(3)]).get_query_select(IdColumn::Id),
 			String::from(" WHERE person IN (1,2,3)")
 		);
 
-		assert_eq!(
-			Permission::Read(vec![Scope::Equipment(1), Scope::Equipment(2), Scope::Equipment(3)]).get_query_select("foo"),
-			String::from(" WHERE id IN (1,2,3)")
-		);
+		assert_eq!("equipment".parse(), Ok(IdColumn::Equipment));
+		assert!("foo".parse::<IdColumn>().is_err());
 
 		assert_eq!(
 			Permission::Read(vec![
@@ -405,7 +962,7 @@ mod tests {
 				Scope::Person(666),
 				Scope::Person(42)
 			])
-			.get_query_select("id"),
+			.get_query_select(IdColumn::Id),
 			String::from(" WHERE id IN (1,2) AND person IN (666,42)")
 		);
 		assert_eq!(
@@ -415,11 +972,11 @@ mod tests {
 				Scope::Equipment(1),
 				Scope::Equipment(2)
 			])
-			.get_query_select("equipment"),
+			.get_query_select(IdColumn::Equipment),
 			String::from(" WHERE equipment IN (1,2) AND person IN (666,42)")
 		);
 		assert_eq!(
-			Permission::Read(vec![Scope::Person(1), Scope::Equipment(1),]).get_query_select("id"),
+			Permission::Read(vec![Scope::Person(1), Scope::Equipment(1),]).get_query_select(IdColumn::Id),
 			String::from(" WHERE id IN (1) AND person IN (1)")
 		);
 
@@ -430,17 +987,212 @@ mod tests {
 				Scope::Person(2),
 				Scope::Equipment(2)
 			])
-			.get_query_select("id"),
+			.get_query_select(IdColumn::Id),
 			String::from(" WHERE id IN (1,2) AND person IN (1,2)")
 		);
+
+		assert_eq!(
+			Permission::Write(vec![Scope::Equipment(1), Scope::Person(2)]).get_query_select(IdColumn::Id),
+			String::from(" WHERE id IN (1) AND person IN (2)")
+		);
+		assert_eq!(
+			Permission::Read(vec![Scope::Project(4), Scope::Person(2), Scope::Project(5)]).get_query_select(IdColumn::Id),
+			String::from(" WHERE person IN (2) AND project IN (4,5)")
+		);
+		assert_eq!(Permission::ReadAny.get_query_select(IdColumn::Id), String::new());
+		assert_eq!(Permission::WriteAny.get_query_select(IdColumn::Id), String::new());
+		assert_eq!(Permission::Read(Vec::new()).get_query_select(IdColumn::Id), String::from(" WHERE false"));
+		assert_eq!(Permission::Write(Vec::new()).get_query_select(IdColumn::Id), String::from(" WHERE false"));
 	}
 
 	#[test]
 	fn get_query_select_without_where_test() {
 		assert_eq!(
 			Permission::Read(vec![Scope::Equipment(1), Scope::Equipment(2), Scope::Equipment(3)])
-				.get_query_select_without_where("id"),
+				.get_query_select_without_where(IdColumn::Id),
 			String::from(" AND id IN (1,2,3)"),
 		);
 	}
+
+	#[cfg(feature = "ssr")]
+	#[test]
+	fn scope_watch_test() {
+		let watch = ssr::ScopeWatch::new(2, std::time::Duration::from_millis(100));
+		let scope = Permission::Read(vec![Scope::Equipment(1), Scope::Equipment(2), Scope::Person(3)]);
+
+		assert!(watch.too_many_ids(&scope));
+		assert!(!watch.too_many_ids(&Permission::ReadAny));
+		assert_eq!(
+			ssr::describe_scope(&scope),
+			"2 equipment, 1 person and 0 project ids [Equipment(1), Equipment(2), Person(3)]"
+		);
+	}
+
+	#[cfg(feature = "ssr")]
+	#[test]
+	fn query_filter_test() {
+		let many = (1..=INLINE_SCOPE_IDS as i32 + 1).map(Scope::Equipment).chain([Scope::Person(7)]).collect::<Vec<_>>();
+
+		assert_eq!(
+			Permission::Read(many).query_filter(IdColumn::Id, 3),
+			ScopeFilter {
+				sql: String::from(" AND id = ANY($3::int4[]) AND person IN (7)"),
+				arrays: vec![(1..=INLINE_SCOPE_IDS as i32 + 1).collect()],
+			}
+		);
+		assert_eq!(
+			Permission::Read(vec![Scope::Equipment(1), Scope::Person(2)]).query_filter(IdColumn::Id, 3),
+			ScopeFilter {
+				sql: Permission::Read(vec![Scope::Equipment(1), Scope::Person(2)]).get_query_select_without_where(IdColumn::Id),
+				arrays: Vec::new(),
+			}
+		);
+	}
+
+	#[cfg(feature = "ssr")]
+	#[test]
+	fn filter_dialects_test() {
+		let many = (1..=INLINE_SCOPE_IDS as i32 + 1).collect::<Vec<_>>();
+		let filter = Filter::Or(vec![
+			Filter::And(vec![Filter::In("person", vec![2]), Filter::In("id", many.clone())]),
+			Filter::In("project", Vec::new()),
+		]);
+
+		assert_eq!(
+			filter.to_bound_sql(Dialect::Postgres, 2).sql,
+			" AND ((person IN (2) AND id = ANY($2::int4[])) OR (false))"
+		);
+		assert_eq!(
+			filter.to_bound_sql(Dialect::Sqlite, 2),
+			ScopeFilter {
+				sql: String::from(" AND ((person IN (2) AND id IN (SELECT value FROM json_each(?2))) OR (false))"),
+				arrays: vec![many],
+			}
+		);
+		assert_eq!(
+			Filter::Or(vec![filter, Filter::True]).to_bound_sql(Dialect::Sqlite, 2),
+			ScopeFilter {
+				sql: String::new(),
+				arrays: Vec::new(),
+			}
+		);
+		// A nested alternative passing everything takes the list bound before it along, the next list is still `?2`
+		let nested = Filter::And(vec![
+			Filter::Or(vec![Filter::In("id", many.clone()), Filter::True]),
+			Filter::In("person", many.clone()),
+		]);
+		assert_eq!(
+			nested.to_bound_sql(Dialect::Sqlite, 2),
+			ScopeFilter {
+				sql: String::from(" AND person IN (SELECT value FROM json_each(?2))"),
+				arrays: vec![many],
+			}
+		);
+		assert_eq!(Filter::And(Vec::new()).to_inline_sql(), None);
+		assert_eq!(Filter::Or(Vec::new()).to_inline_sql().as_deref(), Some("false"));
+	}
+
+	#[test]
+	fn covers_test() {
+		let scoped = Permission::Read(vec![Scope::Equipment(1), Scope::Equipment(2), Scope::Person(7)]);
+
+		assert!(scoped.covers(2, 7, None));
+		assert!(!scoped.covers(3, 7, None));
+		assert!(!scoped.covers(1, 8, None));
+		assert!(Permission::Read(vec![Scope::Person(7)]).covers(42, 7, Some(3)));
+		assert!(Permission::Read(vec![Scope::Any]).covers(42, 8, None));
+		assert!(!Permission::Read(Vec::new()).covers(1, 7, None));
+		assert!(Permission::ReadAny.covers(1, 7, None));
+
+		let project = Permission::Read(vec![Scope::Project(3), Scope::Person(7)]);
+		assert!(project.covers(1, 7, Some(3)));
+		assert!(!project.covers(1, 7, Some(4)));
+		assert!(!project.covers(1, 7, None));
+		assert!(project.covers_project(3));
+		assert!(!project.covers_project(4));
+		assert!(scoped.covers_project(4));
+	}
+
+	#[test]
+	fn explain_test() {
+		let label = |scope: &Scope| format!("{scope:?}");
+
+		assert_eq!(
+			Permission::parse(String::from("READ(*)|WRITE(person[7],project[2])|CREATE(false)"))
+				.unwrap()
+				.explain("todos", label),
+			vec![
+				"You can read all todos",
+				"You can change todos of Person(7) and in Project(2)",
+				"You can't create todos"
+			]
+		);
+		assert_eq!(
+			Permissions::ReadWrite {
+				read: Permission::Read(vec![Scope::Equipment(1), Scope::Equipment(2)]),
+				write: Permission::Write(Vec::new()),
+				create: Permission::Create(true),
+			}
+			.explain("equipment", label),
+			vec![
+				"You can read equipment that are Equipment(1) or Equipment(2)",
+				"You can't change any equipment",
+				"You can create equipment"
+			]
+		);
+	}
+
+	#[test]
+	fn includes_test() {
+		let scoped = Permission::Read(vec![Scope::Equipment(1), Scope::Equipment(2), Scope::Person(7)]);
+
+		assert!(scoped.includes(&[2], &[7]));
+		assert!(scoped.includes(&[1, 2], &[7]));
+		assert!(!scoped.includes(&[2], &[]));
+		assert!(!scoped.includes(&[3], &[7]));
+		assert!(Permission::Read(vec![Scope::Person(7)]).includes(&[42], &[7]));
+		assert!(!Permission::Read(Vec::new()).includes(&[1], &[7]));
+		assert!(Permission::ReadAny.includes(&[], &[]));
+		assert!(!Permission::Read(vec![Scope::Project(3), Scope::Person(7)]).includes(&[], &[7]));
+	}
+
+	#[cfg(feature = "ssr")]
+	#[test]
+	fn intersect_test() {
+		let ours = Permission::Read(vec![Scope::Person(7), Scope::Equipment(1), Scope::Equipment(2)]);
+
+		assert_eq!(Permission::ReadAny.intersect(&ours), ours);
+		assert_eq!(
+			ours.intersect(&Permission::Read(vec![Scope::Equipment(2), Scope::Equipment(3)])),
+			Permission::Read(vec![Scope::Equipment(2), Scope::Person(7)])
+		);
+		assert_eq!(
+			ours.intersect(&Permission::Read(vec![Scope::Project(4)])),
+			Permission::Read(vec![
+				Scope::Equipment(1),
+				Scope::Equipment(2),
+				Scope::Person(7),
+				Scope::Project(4)
+			])
+		);
+		assert_eq!(ours.intersect(&Permission::Read(vec![Scope::Person(8)])), Permission::Read(Vec::new()));
+		assert_eq!(Permission::WriteAny.intersect(&Permission::WriteAny), Permission::WriteAny);
+		assert_eq!(Permission::Create(true).intersect(&Permission::Create(false)), Permission::Create(false));
+
+		// Whatever passes the intersection passes both
+		let theirs = Permission::Read(vec![Scope::Equipment(2), Scope::Project(4)]);
+		let both = ours.intersect(&theirs);
+		for (id, person, project) in [
+			(1, 7, Some(4)),
+			(2, 7, Some(4)),
+			(2, 7, None),
+			(2, 8, Some(4)),
+			(3, 7, Some(4)),
+		] {
+			assert_eq!(
+				both.covers(id, person, project),
+				ours.covers(id, person, project) && theirs.covers(id, person, project)
+			);
+		}
+	}
 }
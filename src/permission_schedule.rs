@@ -0,0 +1,334 @@
+use crate::{
+	auth::PublicUser,
+	errors::{AppError, AppServerError},
+	toast::Toasted,
+};
+use chrono::prelude::*;
+use leptos::*;
+use leptos_router::*;
+use serde::{Deserialize, Serialize};
+
+/// New permissions for a user that an admin scheduled to take effect later, e.g. for a contractor starting next week
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "ssr", derive(utoipa::ToSchema))]
+pub struct PendingPermissionChange {
+	pub id: i32,
+	/// The user whose permissions change
+	pub person: i32,
+	pub permission_equipment: String,
+	pub permission_user: String,
+	pub permission_todo: String,
+	pub apply_at: DateTime<Utc>,
+	/// The admin who scheduled it, `None` once they are deleted
+	pub scheduled_by: Option<PublicUser>,
+	pub created_at: DateTime<Utc>,
+}
+
+#[cfg(feature = "ssr")]
+pub mod ssr {
+	use super::PendingPermissionChange;
+	use crate::{
+		auth::{ssr::reload_user, PublicUser},
+		cache::response_cache,
+		config::Config,
+		delegation::ssr::revoke_uncovered,
+		events::{record, DomainEvent},
+		jobs,
+	};
+	use chrono::prelude::*;
+	use sqlx::PgPool;
+	use std::time::Duration;
+
+	#[derive(sqlx::FromRow, Clone)]
+	pub struct SqlPermissionChange {
+		pub id: i32,
+		pub tenant: i32,
+		pub person: i32,
+		pub permission_equipment: String,
+		pub permission_user: String,
+		pub permission_todo: String,
+		pub apply_at: DateTime<Utc>,
+		pub scheduled_by: Option<i32>,
+		pub created_at: DateTime<Utc>,
+	}
+
+	impl SqlPermissionChange {
+		pub async fn into_pending(self, pool: &PgPool) -> PendingPermissionChange {
+			let scheduled_by = match self.scheduled_by {
+				Some(admin) => PublicUser::get_from_id(admin, pool).await,
+				None => None,
+			};
+
+			PendingPermissionChange {
+				id: self.id,
+				person: self.person,
+				permission_equipment: self.permission_equipment,
+				permission_user: self.permission_user,
+				permission_todo: self.permission_todo,
+				apply_at: self.apply_at,
+				scheduled_by,
+				created_at: self.created_at,
+			}
+		}
+	}
+
+	/// Apply every scheduled permission change that is due, returns how many
+	///
+	/// The strings were validated when the change was scheduled, so they are stored as they are
+	pub async fn apply_due(pool: &PgPool) -> Result<usize, sqlx::Error> {
+		let mut tx = pool.begin().await?;
+
+		// Locked rows are skipped so several replicas can run the scheduler without applying a change twice
+		let due = sqlx::query_as::<_, SqlPermissionChange>(
+			"DELETE FROM scheduled_permission_changes WHERE id IN (
+				SELECT id FROM scheduled_permission_changes WHERE apply_at <= now() ORDER BY apply_at FOR UPDATE SKIP LOCKED
+			) RETURNING *",
+		)
+		.fetch_all(&mut *tx)
+		.await?;
+
+		for change in &due {
+			sqlx::query(
+				"UPDATE users SET permission_equipment = $1, permission_user = $2, permission_todo = $3
+				WHERE id = $4 AND tenant = $5",
+			)
+			.bind(&change.permission_equipment)
+			.bind(&change.permission_user)
+			.bind(&change.permission_todo)
+			.bind(change.person)
+			.bind(change.tenant)
+			.execute(&mut *tx)
+			.await?;
+			record(
+				&mut *tx,
+				&DomainEvent::PermissionChangeApplied {
+					tenant: change.tenant,
+					user: change.scheduled_by,
+					person: change.person,
+					change: change.id,
+				},
+			)
+			.await?;
+		}
+
+		tx.commit().await?;
+
+		// Sessions and cached responses of the users were built with the old permissions
+		for change in &due {
+			revoke_uncovered(change.person, pool).await?;
+			reload_user(change.person);
+			response_cache().invalidate_user(change.person);
+		}
+
+		Ok(due.len())
+	}
+
+	/// Apply due permission changes every `PERMISSION_CHANGE_INTERVAL` seconds
+	pub fn spawn_scheduler(pool: PgPool, config: &Config) {
+		jobs::every("Permission changes", Duration::from_secs(config.permission_change_interval), move || {
+			let pool = pool.clone();
+			async move {
+				let applied = apply_due(&pool).await?;
+				if applied > 0 {
+					log::info!("Applied {applied} scheduled permission changes");
+				}
+				Ok::<_, sqlx::Error>(())
+			}
+		});
+	}
+}
+
+/// The permission changes of the tenant that didn't take effect yet, the next one first, admins only
+#[server(input = GetUrl)]
+pub async fn get_pending_permission_changes() -> Result<Vec<PendingPermissionChange>, ServerFnError<AppServerError>> {
+	use self::ssr::SqlPermissionChange;
+	use crate::{
+		auth::get_user,
+		cache::cache_privately,
+		denial::{ssr::record_denial, DeniedAction},
+		tenant::Tenant,
+	};
+	use futures::future::join_all;
+	use sqlx::PgPool;
+
+	cache_privately::<GetPendingPermissionChanges>(0);
+
+	let pool = use_context::<PgPool>().expect("Database not initialized");
+	let tenant = use_context::<Tenant>().expect("No tenant found");
+	let user = get_user().await?.ok_or_else(AppServerError::unauthenticated)?;
+	if !user.is_admin() {
+		record_denial("get_pending_permission_changes", DeniedAction::Admin, &user);
+		return Err(AppServerError::forbidden("Missing permission to manage users").into());
+	}
+
+	let changes = sqlx::query_as::<_, SqlPermissionChange>(
+		"SELECT * FROM scheduled_permission_changes WHERE tenant = $1 ORDER BY apply_at, id",
+	)
+	.bind(tenant.id)
+	.fetch_all(&pool)
+	.await
+	.map_err(AppServerError::from)?;
+
+	Ok(join_all(changes.into_iter().map(|change| change.into_pending(&pool))).await)
+}
+
+/// Give a user of the tenant new permissions at `apply_at`, checked like [`crate::user_admin::set_user_permissions`]
+/// right away
+#[server]
+pub async fn schedule_permission_change(
+	id: i32,
+	permission_equipment: String,
+	permission_user: String,
+	permission_todo: String,
+	apply_at: String,
+) -> Result<Toasted<()>, ServerFnError<AppServerError>> {
+	use crate::{
+		auth::{
+			get_user,
+			ssr::{require_recent_auth, STEP_UP_MAX_AGE},
+		},
+		delegation::ssr::parse_time,
+		denial::{ssr::record_denial, DeniedAction},
+		events::{record, DomainEvent},
+		permission::{Permission, Permissions},
+		tenant::Tenant,
+		user_admin::ssr::parse_permission,
+	};
+	use sqlx::PgPool;
+
+	let pool = use_context::<PgPool>().expect("Database not initialized");
+	let tenant = use_context::<Tenant>().expect("No tenant found");
+	let user = get_user().await?.ok_or_else(AppServerError::unauthenticated)?;
+	if !user.is_admin() {
+		record_denial("schedule_permission_change", DeniedAction::Admin, &user);
+		return Err(AppServerError::forbidden("Missing permission to manage users").into());
+	}
+	require_recent_auth(STEP_UP_MAX_AGE)?;
+
+	parse_permission("Equipment", &permission_equipment)?;
+	let Permissions::ReadWrite { write, .. } = parse_permission("Users", &permission_user)?;
+	parse_permission("Todos", &permission_todo)?;
+	if id == user.id && write != Permission::WriteAny {
+		return Err(AppServerError::forbidden("You can't remove your own admin permission").into());
+	}
+	let apply_at = parse_time(&apply_at).ok_or_else(|| AppServerError::invalid("Invalid time to apply the change at"))?;
+	if apply_at <= Utc::now() {
+		return Err(AppServerError::invalid("Scheduled changes have to take effect in the future").into());
+	}
+
+	let mut tx = pool.begin().await.map_err(AppServerError::from)?;
+	let change = sqlx::query_scalar::<_, i32>(
+		"INSERT INTO scheduled_permission_changes
+		(tenant, person, permission_equipment, permission_user, permission_todo, apply_at, scheduled_by)
+		SELECT $1, id, $3, $4, $5, $6, $7 FROM users WHERE id = $2 AND tenant = $1 RETURNING id",
+	)
+	.bind(tenant.id)
+	.bind(id)
+	.bind(permission_equipment)
+	.bind(permission_user)
+	.bind(permission_todo)
+	.bind(apply_at)
+	.bind(user.id)
+	.fetch_optional(&mut *tx)
+	.await
+	.map_err(AppServerError::from)?
+	.ok_or_else(|| AppServerError::not_found("User not found"))?;
+	record(
+		&mut *tx,
+		&DomainEvent::PermissionChangeScheduled {
+			tenant: tenant.id,
+			user: user.id,
+			person: id,
+			change,
+		},
+	)
+	.await
+	.map_err(AppServerError::from)?;
+	tx.commit().await.map_err(AppServerError::from)?;
+
+	Ok(Toasted::success((), format!("The permissions change at {apply_at}")))
+}
+
+/// Drop a scheduled permission change before it takes effect, admins only
+#[server]
+pub async fn cancel_permission_change(id: i32) -> Result<Toasted<()>, ServerFnError<AppServerError>> {
+	use crate::{
+		auth::get_user,
+		denial::{ssr::record_denial, DeniedAction},
+		events::{record, DomainEvent},
+		tenant::Tenant,
+	};
+	use sqlx::PgPool;
+
+	let pool = use_context::<PgPool>().expect("Database not initialized");
+	let tenant = use_context::<Tenant>().expect("No tenant found");
+	let user = get_user().await?.ok_or_else(AppServerError::unauthenticated)?;
+	if !user.is_admin() {
+		record_denial("cancel_permission_change", DeniedAction::Admin, &user);
+		return Err(AppServerError::forbidden("Missing permission to manage users").into());
+	}
+
+	let mut tx = pool.begin().await.map_err(AppServerError::from)?;
+	let person = sqlx::query_scalar::<_, i32>(
+		"DELETE FROM scheduled_permission_changes WHERE id = $1 AND tenant = $2 RETURNING person",
+	)
+	.bind(id)
+	.bind(tenant.id)
+	.fetch_optional(&mut *tx)
+	.await
+	.map_err(AppServerError::from)?
+	.ok_or_else(|| AppServerError::not_found("Scheduled change not found, it may have taken effect already"))?;
+	record(
+		&mut *tx,
+		&DomainEvent::PermissionChangeCancelled {
+			tenant: tenant.id,
+			user: user.id,
+			person,
+			change: id,
+		},
+	)
+	.await
+	.map_err(AppServerError::from)?;
+	tx.commit().await.map_err(AppServerError::from)?;
+
+	Ok(Toasted::success((), "Scheduled change cancelled"))
+}
+
+/// The changes still to come for a user, each with a button to cancel it
+#[component]
+pub fn PendingPermissionChanges(
+	changes: Vec<PendingPermissionChange>,
+	cancel: Action<CancelPermissionChange, Result<Toasted<()>, AppError>>,
+) -> impl IntoView {
+	if changes.is_empty() {
+		return ().into_view();
+	}
+
+	view! {
+		<h3>"Pending changes"</h3>
+		<ul class="pending-permission-changes">
+			{changes
+				.into_iter()
+				.map(|change| {
+					let admin = change.scheduled_by.map(|admin| admin.username).unwrap_or_default();
+					view! {
+						<li>
+							{format!(
+								"At {}: equipment {}, users {}, todos {}, scheduled by {admin}",
+								change.apply_at,
+								change.permission_equipment,
+								change.permission_user,
+								change.permission_todo,
+							)}
+							<ActionForm action=cancel>
+								<input type="hidden" name="id" value=change.id />
+								<input type="submit" value="Cancel" />
+							</ActionForm>
+						</li>
+					}
+				})
+				.collect_view()}
+		</ul>
+	}
+	.into_view()
+}
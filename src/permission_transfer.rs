@@ -0,0 +1,464 @@
+use crate::{
+	errors::{AppError, AppServerError},
+	toast::Toasted,
+};
+use leptos::*;
+use leptos_router::*;
+use serde::{Deserialize, Serialize};
+
+/// How a permission document is written
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "ssr", derive(utoipa::ToSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum PermissionFormat {
+	#[default]
+	Json,
+	Yaml,
+}
+
+impl PermissionFormat {
+	pub const ALL: [PermissionFormat; 2] = [PermissionFormat::Json, PermissionFormat::Yaml];
+
+	pub fn as_str(&self) -> &'static str {
+		match self {
+			PermissionFormat::Json => "json",
+			PermissionFormat::Yaml => "yaml",
+		}
+	}
+
+	pub fn from_name(format: &str) -> Option<Self> {
+		PermissionFormat::ALL.into_iter().find(|known| known.as_str() == format)
+	}
+}
+
+/// The permission strings of a user, users are matched by name so a document applies to another environment
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "ssr", derive(utoipa::ToSchema))]
+pub struct PermissionAssignment {
+	pub username: String,
+	pub permission_equipment: String,
+	pub permission_user: String,
+	pub permission_todo: String,
+}
+
+/// The permissions of every user of a tenant, as they are exported and imported
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "ssr", derive(utoipa::ToSchema))]
+pub struct PermissionDocument {
+	pub users: Vec<PermissionAssignment>,
+}
+
+/// A permission string an import changes
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "ssr", derive(utoipa::ToSchema))]
+pub struct PermissionChange {
+	/// `permission_equipment`, `permission_user` or `permission_todo`
+	pub field: String,
+	pub from: String,
+	pub to: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "ssr", derive(utoipa::ToSchema))]
+pub enum PermissionDiffStatus {
+	Unchanged,
+	Changed {
+		changes: Vec<PermissionChange>,
+	},
+	/// Nobody has the name in this tenant, imports never create users
+	UnknownUser,
+}
+
+/// What an import does to one user of the document
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "ssr", derive(utoipa::ToSchema))]
+pub struct PermissionDiff {
+	pub username: String,
+	pub status: PermissionDiffStatus,
+}
+
+#[cfg(feature = "ssr")]
+pub mod ssr {
+	use super::{
+		PermissionAssignment, PermissionChange, PermissionDiff, PermissionDiffStatus, PermissionDocument, PermissionFormat,
+	};
+	use crate::user_admin::ManagedUser;
+
+	impl PermissionFormat {
+		pub fn write(&self, document: &PermissionDocument) -> Result<String, String> {
+			match self {
+				PermissionFormat::Json => serde_json::to_string_pretty(document).map_err(|error| error.to_string()),
+				PermissionFormat::Yaml => serde_yaml::to_string(document).map_err(|error| error.to_string()),
+			}
+		}
+
+		pub fn read(&self, document: &str) -> Result<PermissionDocument, String> {
+			let document = match self {
+				PermissionFormat::Json => {
+					serde_json::from_str::<PermissionDocument>(document).map_err(|error| error.to_string())?
+				},
+				PermissionFormat::Yaml => {
+					serde_yaml::from_str::<PermissionDocument>(document).map_err(|error| error.to_string())?
+				},
+			};
+			if let Some(twice) = document
+				.users
+				.iter()
+				.enumerate()
+				.find(|(index, user)| document.users[..*index].iter().any(|other| other.username == user.username))
+			{
+				return Err(format!("{} is in the document more than once", twice.1.username));
+			}
+
+			Ok(document)
+		}
+	}
+
+	impl From<ManagedUser> for PermissionAssignment {
+		fn from(user: ManagedUser) -> Self {
+			Self {
+				username: user.username,
+				permission_equipment: user.permission_equipment,
+				permission_user: user.permission_user,
+				permission_todo: user.permission_todo,
+			}
+		}
+	}
+
+	/// What importing `document` changes for the users it names, in the order of the document
+	pub fn diff(current: &[ManagedUser], document: &PermissionDocument) -> Vec<PermissionDiff> {
+		document
+			.users
+			.iter()
+			.map(|assignment| {
+				let Some(user) = current.iter().find(|user| user.username == assignment.username) else {
+					return PermissionDiff {
+						username: assignment.username.clone(),
+						status: PermissionDiffStatus::UnknownUser,
+					};
+				};
+
+				let changes = [
+					("permission_equipment", &user.permission_equipment, &assignment.permission_equipment),
+					("permission_user", &user.permission_user, &assignment.permission_user),
+					("permission_todo", &user.permission_todo, &assignment.permission_todo),
+				]
+				.into_iter()
+				.filter(|(_, from, to)| from != to)
+				.map(|(field, from, to)| PermissionChange {
+					field: field.to_string(),
+					from: from.clone(),
+					to: to.clone(),
+				})
+				.collect::<Vec<_>>();
+
+				PermissionDiff {
+					username: assignment.username.clone(),
+					status: if changes.is_empty() {
+						PermissionDiffStatus::Unchanged
+					} else {
+						PermissionDiffStatus::Changed { changes }
+					},
+				}
+			})
+			.collect()
+	}
+}
+
+/// The permissions of every user of the tenant as a document [`import_permissions`] takes, admins only
+#[server(input = GetUrl)]
+pub async fn export_permissions(
+	#[server(default)] format: PermissionFormat,
+) -> Result<String, ServerFnError<AppServerError>> {
+	use crate::{
+		auth::get_user,
+		cache::cache_privately,
+		denial::{ssr::record_denial, DeniedAction},
+		tenant::Tenant,
+		user_admin::ssr::managed_users,
+	};
+	use sqlx::PgPool;
+
+	cache_privately::<ExportPermissions>(0);
+
+	let pool = use_context::<PgPool>().expect("Database not initialized");
+	let tenant = use_context::<Tenant>().expect("No tenant found");
+	let user = get_user().await?.ok_or_else(AppServerError::unauthenticated)?;
+	if !user.is_admin() {
+		record_denial("export_permissions", DeniedAction::Admin, &user);
+		return Err(AppServerError::forbidden("Missing permission to manage users").into());
+	}
+
+	let document = PermissionDocument {
+		users: managed_users(tenant.id, &pool)
+			.await
+			.map_err(AppServerError::from)?
+			.into_iter()
+			.map(PermissionAssignment::from)
+			.collect(),
+	};
+
+	format.write(&document).map_err(|error| AppServerError::internal(error).into())
+}
+
+/// Set the permissions of the users named in an exported document, admins only
+///
+/// Nothing is stored unless every permission string of the document parses. With `dry_run` it only reports what would
+/// change. Users of the document that don't exist in this tenant are skipped, users the document doesn't name keep
+/// their permissions.
+#[server]
+pub async fn import_permissions(
+	document: String,
+	#[server(default)] format: PermissionFormat,
+	dry_run: Option<String>,
+) -> Result<Toasted<Vec<PermissionDiff>>, ServerFnError<AppServerError>> {
+	use self::ssr::diff;
+	use crate::{
+		auth::{
+			get_user,
+			ssr::{require_recent_auth, AuthSession, STEP_UP_MAX_AGE},
+		},
+		cache::response_cache,
+		delegation::ssr::revoke_uncovered,
+		denial::{ssr::record_denial, DeniedAction},
+		events::{record, DomainEvent},
+		permission::{Permission, Permissions},
+		tenant::Tenant,
+		user_admin::ssr::{managed_users, parse_permission},
+	};
+	use sqlx::PgPool;
+
+	let pool = use_context::<PgPool>().expect("Database not initialized");
+	let tenant = use_context::<Tenant>().expect("No tenant found");
+	let auth = use_context::<AuthSession>().expect("No session found");
+	let user = get_user().await?.ok_or_else(AppServerError::unauthenticated)?;
+	if !user.is_admin() {
+		record_denial("import_permissions", DeniedAction::Admin, &user);
+		return Err(AppServerError::forbidden("Missing permission to manage users").into());
+	}
+	let dry_run = dry_run.is_some();
+	if !dry_run {
+		require_recent_auth(STEP_UP_MAX_AGE)?;
+	}
+
+	let document =
+		format.read(&document).map_err(|error| AppServerError::invalid(format!("Invalid document: {error}")))?;
+	for assignment in &document.users {
+		let field = |name: &str| format!("{} {name}", assignment.username);
+		parse_permission(&field("Equipment"), &assignment.permission_equipment)?;
+		let Permissions::ReadWrite { write, .. } = parse_permission(&field("Users"), &assignment.permission_user)?;
+		parse_permission(&field("Todos"), &assignment.permission_todo)?;
+		// Admins taking away their own admin rights would lock the tenant out of the admin pages
+		if assignment.username == user.username && write != Permission::WriteAny {
+			return Err(AppServerError::forbidden("The document removes your own admin permission").into());
+		}
+	}
+
+	let current = managed_users(tenant.id, &pool).await.map_err(AppServerError::from)?;
+	let report = diff(&current, &document);
+	let changed = document
+		.users
+		.iter()
+		.zip(&report)
+		.filter(|(_, diff)| matches!(diff.status, PermissionDiffStatus::Changed { .. }))
+		.filter_map(|(assignment, _)| {
+			current.iter().find(|user| user.username == assignment.username).map(|user| (user.id, assignment))
+		})
+		.collect::<Vec<_>>();
+	let unknown = report.iter().filter(|diff| diff.status == PermissionDiffStatus::UnknownUser).count();
+	if dry_run {
+		return Ok(Toasted::silent(report));
+	}
+
+	let mut transaction = pool.begin().await.map_err(AppServerError::from)?;
+	for (id, assignment) in &changed {
+		sqlx::query(
+			"UPDATE users SET permission_equipment = $1, permission_user = $2, permission_todo = $3
+			WHERE id = $4 AND tenant = $5",
+		)
+		.bind(&assignment.permission_equipment)
+		.bind(&assignment.permission_user)
+		.bind(&assignment.permission_todo)
+		.bind(id)
+		.bind(tenant.id)
+		.execute(&mut *transaction)
+		.await
+		.map_err(AppServerError::from)?;
+		record(
+			&mut *transaction,
+			&DomainEvent::PermissionsChanged {
+				tenant: tenant.id,
+				user: Some(user.id),
+				person: *id,
+			},
+		)
+		.await
+		.map_err(AppServerError::from)?;
+	}
+	transaction.commit().await.map_err(AppServerError::from)?;
+
+	// Sessions and cached responses of the users were built with the old permissions
+	for (id, _) in &changed {
+		revoke_uncovered(*id, &pool).await.map_err(AppServerError::from)?;
+		auth.cache_clear_user(*id);
+		response_cache().invalidate_user(*id);
+	}
+
+	let message = format!("Changed the permissions of {} users", changed.len());
+	Ok(match unknown {
+		0 => Toasted::success(report, message),
+		unknown => Toasted::warning(report, format!("{message}, {unknown} users of the document don't exist here")),
+	})
+}
+
+/// Download the permissions of every user, or apply a document exported from another environment
+#[component]
+pub fn PermissionTransfer(
+	import: Action<ImportPermissions, Result<Toasted<Vec<PermissionDiff>>, AppError>>,
+) -> impl IntoView {
+	let export = create_action(|format: &PermissionFormat| export_permissions(*format));
+	let (format, set_format) = create_signal(PermissionFormat::Json);
+	let format_options = move || {
+		PermissionFormat::ALL
+			.into_iter()
+			.map(|format| view! { <option value=format.as_str()>{format.as_str()}</option> })
+			.collect_view()
+	};
+
+	view! {
+		<h2>"Export and import permissions"</h2>
+		<p>"Users are matched by name, so the permissions of one environment can be applied to another."</p>
+		<select on:change=move |ev| set_format.set(PermissionFormat::from_name(&event_target_value(&ev)).unwrap_or_default())>
+			{format_options}
+		</select>
+		<button type="button" on:click=move |_| export.dispatch(format.get_untracked())>
+			"Export"
+		</button>
+		{move || {
+			export
+				.value()
+				.get()
+				.map(|document| match document {
+					Err(e) => view! { <span class="error">{e.to_string()}</span> }.into_view(),
+					Ok(document) => view! { <textarea readonly=true rows=12 prop:value=document></textarea> }.into_view(),
+				})
+		}}
+		<ActionForm action=import>
+			<textarea name="document" rows=12 required=true></textarea>
+			<select name="format">{format_options}</select>
+			<label>"Only show what would change " <input type="checkbox" name="dry_run" checked=true /></label>
+			<input type="submit" value="Import" />
+		</ActionForm>
+		{move || {
+			import
+				.value()
+				.get()
+				.and_then(Result::ok)
+				.map(|Toasted { value: report, .. }| {
+					let rows = report
+						.into_iter()
+						.map(|diff| {
+							let (class, outcome) = match diff.status {
+								PermissionDiffStatus::Unchanged => ("", String::from("unchanged")),
+								PermissionDiffStatus::Changed { changes } => {
+									(
+										"imported",
+										changes
+											.into_iter()
+											.map(|change| format!("{}: {} → {}", change.field, change.from, change.to))
+											.collect::<Vec<_>>()
+											.join(", "),
+									)
+								}
+								PermissionDiffStatus::UnknownUser => ("error", String::from("no such user, skipped")),
+							};
+							view! {
+								<tr class=class>
+									<td>{diff.username}</td>
+									<td>{outcome}</td>
+								</tr>
+							}
+						})
+						.collect_view();
+					view! {
+						<table class="import-report">
+							<tr>
+								<th>"Username"</th>
+								<th>"Change"</th>
+							</tr>
+							{rows}
+						</table>
+					}
+				})
+		}}
+	}
+}
+
+#[cfg(all(test, feature = "ssr"))]
+mod tests {
+	use super::{ssr::diff, *};
+	use crate::user_admin::ManagedUser;
+
+	fn managed(id: i32, username: &str, todo: &str) -> ManagedUser {
+		ManagedUser {
+			id,
+			username: username.to_string(),
+			permission_equipment: String::from("READ(*)|WRITE(*)|CREATE(TRUE)"),
+			permission_user: String::from("READ(*)|WRITE(*)|CREATE(TRUE)"),
+			permission_todo: todo.to_string(),
+			active: true,
+		}
+	}
+
+	#[test]
+	fn diff_by_username() {
+		let current = [
+			managed(1, "alice", "READ(*)|WRITE(*)|CREATE(TRUE)"),
+			managed(2, "bob", "READ(*)|CREATE(FALSE)"),
+		];
+		let mut document = PermissionDocument {
+			users: current.iter().cloned().map(PermissionAssignment::from).collect(),
+		};
+		document.users[1].permission_todo = String::from("READ(*)|WRITE(*)|CREATE(TRUE)");
+		document.users.push(PermissionAssignment::from(managed(9, "carol", "READ(*)|CREATE(FALSE)")));
+
+		assert_eq!(
+			diff(&current, &document),
+			vec![
+				PermissionDiff {
+					username: String::from("alice"),
+					status: PermissionDiffStatus::Unchanged,
+				},
+				PermissionDiff {
+					username: String::from("bob"),
+					status: PermissionDiffStatus::Changed {
+						changes: vec![PermissionChange {
+							field: String::from("permission_todo"),
+							from: String::from("READ(*)|CREATE(FALSE)"),
+							to: String::from("READ(*)|WRITE(*)|CREATE(TRUE)"),
+						}],
+					},
+				},
+				PermissionDiff {
+					username: String::from("carol"),
+					status: PermissionDiffStatus::UnknownUser,
+				},
+			]
+		);
+	}
+
+	#[test]
+	fn formats_round_trip() {
+		let document = PermissionDocument {
+			users: vec![PermissionAssignment::from(managed(1, "alice", "READ(*)|CREATE(FALSE)"))],
+		};
+
+		for format in PermissionFormat::ALL {
+			assert_eq!(format.read(&format.write(&document).unwrap()), Ok(document.clone()));
+		}
+		let twice = PermissionDocument {
+			users: vec![document.users[0].clone(), document.users[0].clone()],
+		};
+		assert!(PermissionFormat::Yaml.read(&PermissionFormat::Yaml.write(&twice).unwrap()).is_err());
+	}
+}
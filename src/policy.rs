@@ -0,0 +1,379 @@
+//! Authorization policies written as rules instead of permission strings, used with `AUTHORIZATION=policy`
+//!
+//! A deployment lists its rules in `POLICY_FILE`, every rule permits an action on todos for the users it matches:
+//!
+//! ```text
+//! # Everyone reads and edits their own todos
+//! permit read todo when resource.person == principal.id;
+//! permit write todo when resource.person == principal.id;
+//! # Leads read the todos of their team
+//! permit read todo when principal.username in ["alice", "bob"] && resource.person in [3, 4, 5];
+//! permit create todo;
+//! permit write todo when principal.admin;
+//! ```
+//!
+//! Conditions on the `principal` are checked against the user, conditions on the `resource` become the clause todos
+//! are filtered with, so lists are still filtered by the database. A rule without conditions permits everything, an
+//! action no rule permits is denied. The principal has an `id`, a `username`, a `tenant` and `admin`, a todo has an
+//! `id` and the `person` it belongs to.
+
+use crate::{
+	auth::User,
+	permission::{Dialect, Filter, ScopeFilter},
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+	Read,
+	Write,
+	Create,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Value {
+	Number(i32),
+	Text(String),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Column {
+	Id,
+	Person,
+}
+
+impl Column {
+	fn name(&self) -> &'static str {
+		match self {
+			Column::Id => "id",
+			Column::Person => "person",
+		}
+	}
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Condition {
+	Admin,
+	/// The attribute of the user is one of the values
+	Principal(&'static str, Vec<Value>),
+	/// The column of the todo is one of the ids, or the id of the user when there are none
+	Resource(Column, Option<Vec<i32>>),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Rule {
+	action: Action,
+	conditions: Vec<Condition>,
+}
+
+/// The rules of a deployment, parsed once on start
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Policies {
+	rules: Vec<Rule>,
+}
+
+/// The todos a matching rule permits, a todo has to be in every one of the lists
+type Grant = Vec<(Column, Vec<i32>)>;
+
+impl Policies {
+	pub fn parse(source: &str) -> Result<Self, String> {
+		let tokens = tokenize(source)?;
+		let mut tokens = tokens.iter().peekable();
+		let mut rules = Vec::new();
+
+		while tokens.peek().is_some() {
+			let number = rules.len() + 1;
+			let error = |message: &str| format!("Rule {number}: {message}");
+			let mut next = || tokens.next().map(String::as_str);
+
+			if next() != Some("permit") {
+				return Err(error("rules start with \"permit\""));
+			}
+			let action = match next() {
+				Some("read") => Action::Read,
+				Some("write") => Action::Write,
+				Some("create") => Action::Create,
+				_ => return Err(error("expected read, write or create")),
+			};
+			if next() != Some("todo") {
+				return Err(error("only todos can be permitted"));
+			}
+
+			let mut conditions = Vec::new();
+			match next() {
+				Some(";") => {},
+				Some("when") => loop {
+					conditions.push(parse_condition(&mut next).map_err(|message| error(&message))?);
+					match next() {
+						Some("&&") => continue,
+						Some(";") => break,
+						_ => return Err(error("expected && or ;")),
+					}
+				},
+				_ => return Err(error("expected when or ;")),
+			}
+
+			rules.push(Rule { action, conditions });
+		}
+
+		Ok(Self { rules })
+	}
+
+	pub fn len(&self) -> usize {
+		self.rules.len()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.rules.is_empty()
+	}
+
+	/// The constraints on todos of every rule for `action` that matches the user
+	fn grants(&self, user: &User, action: Action) -> Vec<Grant> {
+		self
+			.rules
+			.iter()
+			.filter(|rule| rule.action == action)
+			.filter(|rule| {
+				rule.conditions.iter().all(|condition| match condition {
+					Condition::Admin => user.is_admin(),
+					Condition::Principal(attribute, values) => {
+						let value = match *attribute {
+							"id" => Value::Number(user.id),
+							"tenant" => Value::Number(user.tenant),
+							_ => Value::Text(user.username.clone()),
+						};
+						values.contains(&value)
+					},
+					Condition::Resource(..) => true,
+				})
+			})
+			.map(|rule| {
+				rule
+					.conditions
+					.iter()
+					.filter_map(|condition| match condition {
+						Condition::Resource(column, ids) => Some((*column, ids.clone().unwrap_or_else(|| vec![user.id]))),
+						_ => None,
+					})
+					.collect()
+			})
+			.collect()
+	}
+
+	/// Whether any rule permits the action on todos at all, for actions like create that aren't about a todo
+	pub fn permits(&self, user: &User, action: Action) -> bool {
+		!self.grants(user, action).is_empty()
+	}
+
+	/// Whether the rules permit the action on a todo with `id` that belongs to `person`
+	pub fn covers(&self, user: &User, action: Action, id: i32, person: i32) -> bool {
+		self.grants(user, action).iter().any(|grant| {
+			grant.iter().all(|(column, ids)| match column {
+				Column::Id => ids.contains(&id),
+				Column::Person => ids.contains(&person),
+			})
+		})
+	}
+
+	/// Whether a single rule permits the action on every todo with an id out of `equipment` and a person out of
+	/// `persons`, empty lists standing for any id
+	pub fn includes(&self, user: &User, action: Action, equipment: &[i32], persons: &[i32]) -> bool {
+		self.grants(user, action).iter().any(|grant| {
+			grant.iter().all(|(column, ids)| {
+				let wanted = match column {
+					Column::Id => equipment,
+					Column::Person => persons,
+				};
+				!wanted.is_empty() && wanted.iter().all(|id| ids.contains(id))
+			})
+		})
+	}
+
+	/// The clause todos the action is permitted on are filtered with, like
+	/// [`crate::permission::Permission::query_filter`]
+	pub fn query_filter(&self, user: &User, action: Action, first_param: usize) -> ScopeFilter {
+		let grants = self.grants(user, action);
+
+		Filter::Or(
+			grants
+				.into_iter()
+				.map(|grant| Filter::And(grant.into_iter().map(|(column, ids)| Filter::In(column.name(), ids)).collect()))
+				.collect(),
+		)
+		.to_bound_sql(Dialect::Postgres, first_param)
+	}
+}
+
+fn parse_condition<'a>(next: &mut impl FnMut() -> Option<&'a str>) -> Result<Condition, String> {
+	let attribute = next().ok_or("expected a condition")?;
+	if attribute == "principal.admin" {
+		return Ok(Condition::Admin);
+	}
+
+	let operator = next().ok_or("expected == or in")?;
+	let values = match operator {
+		"==" => vec![next().ok_or("expected a value")?],
+		"in" => {
+			if next() != Some("[") {
+				return Err(String::from("expected a list after in"));
+			}
+			let mut values = Vec::new();
+			loop {
+				match next() {
+					Some("]") if values.is_empty() => break,
+					Some(value) => values.push(value),
+					None => return Err(String::from("unclosed list")),
+				}
+				match next() {
+					Some(",") => continue,
+					Some("]") => break,
+					_ => return Err(String::from("expected , or ]")),
+				}
+			}
+			values
+		},
+		_ => return Err(format!("expected == or in after {attribute}")),
+	};
+
+	let number = |value: &str| value.parse::<i32>().map_err(|_| format!("{value} is not a number"));
+	match attribute {
+		"principal.id" | "principal.tenant" => Ok(Condition::Principal(
+			if attribute == "principal.id" { "id" } else { "tenant" },
+			values.into_iter().map(|value| number(value).map(Value::Number)).collect::<Result<_, _>>()?,
+		)),
+		"principal.username" => Ok(Condition::Principal(
+			"username",
+			values
+				.into_iter()
+				.map(|value| {
+					value
+						.strip_prefix('"')
+						.and_then(|value| value.strip_suffix('"'))
+						.map(|value| Value::Text(value.to_string()))
+						.ok_or_else(|| format!("{value} is not a quoted name"))
+				})
+				.collect::<Result<_, _>>()?,
+		)),
+		"resource.id" | "resource.person" => {
+			let column = if attribute == "resource.id" {
+				Column::Id
+			} else {
+				Column::Person
+			};
+			if values == ["principal.id"] {
+				return Ok(Condition::Resource(column, None));
+			}
+			Ok(Condition::Resource(column, Some(values.into_iter().map(number).collect::<Result<_, _>>()?)))
+		},
+		_ => Err(format!("unknown attribute {attribute}")),
+	}
+}
+
+fn tokenize(source: &str) -> Result<Vec<String>, String> {
+	let mut tokens = Vec::new();
+	for line in source.lines() {
+		let mut chars = line.chars().peekable();
+		while let Some(&char) = chars.peek() {
+			match char {
+				'#' => break,
+				char if char.is_whitespace() => {
+					chars.next();
+				},
+				'[' | ']' | ',' | ';' => {
+					tokens.push(char.to_string());
+					chars.next();
+				},
+				'=' | '&' => {
+					chars.next();
+					if chars.next() != Some(char) {
+						return Err(format!("expected {char}{char}"));
+					}
+					tokens.push(format!("{char}{char}"));
+				},
+				'"' => {
+					let mut text = String::from(chars.next().unwrap());
+					loop {
+						match chars.next() {
+							Some('"') => break,
+							Some(char) => text.push(char),
+							None => return Err(String::from("unclosed name")),
+						}
+					}
+					text.push('"');
+					tokens.push(text);
+				},
+				_ => {
+					let mut word = String::new();
+					while let Some(&char) = chars.peek() {
+						if !(char.is_alphanumeric() || char == '.' || char == '_' || char == '-') {
+							break;
+						}
+						word.push(char);
+						chars.next();
+					}
+					if word.is_empty() {
+						return Err(format!("unexpected {char}"));
+					}
+					tokens.push(word);
+				},
+			}
+		}
+	}
+
+	Ok(tokens)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn user(id: i32, username: &str) -> User {
+		User {
+			id,
+			tenant: 1,
+			username: username.to_string(),
+			..User::default()
+		}
+	}
+
+	const RULES: &str = "
+		# Everyone works on their own todos
+		permit read todo when resource.person == principal.id;
+		permit read todo when principal.username in [\"alice\"] && resource.person in [3, 4];
+		permit write todo when resource.person == principal.id && resource.id in [10];
+		permit create todo when principal.tenant == 1;
+	";
+
+	#[test]
+	fn rules_filter_todos() {
+		let policies = Policies::parse(RULES).unwrap();
+		let alice = user(2, "alice");
+
+		assert_eq!(policies.len(), 4);
+		assert_eq!(policies.query_filter(&alice, Action::Read, 2).sql, " AND ((person IN (2)) OR (person IN (3,4)))");
+		assert_eq!(policies.query_filter(&user(5, "bob"), Action::Read, 2).sql, " AND ((person IN (5)))");
+		assert!(policies.covers(&alice, Action::Write, 10, 2));
+		assert!(!policies.covers(&alice, Action::Write, 11, 2));
+		assert!(policies.includes(&alice, Action::Read, &[], &[3]));
+		assert!(!policies.includes(&alice, Action::Read, &[], &[3, 5]));
+		assert!(!policies.includes(&alice, Action::Write, &[], &[2]));
+		assert!(policies.permits(&alice, Action::Create));
+		assert!(!policies.permits(&User { tenant: 2, ..alice }, Action::Create));
+	}
+
+	#[test]
+	fn unconditional_rules_permit_everything() {
+		let policies = Policies::parse("permit read todo;").unwrap();
+
+		assert_eq!(policies.query_filter(&user(1, "alice"), Action::Read, 2).sql, "");
+		assert_eq!(policies.query_filter(&user(1, "alice"), Action::Write, 2).sql, " AND false");
+	}
+
+	#[test]
+	fn invalid_rules() {
+		assert!(Policies::parse("allow read todo;").is_err());
+		assert!(Policies::parse("permit read users;").is_err());
+		assert!(Policies::parse("permit read todo when resource.person in [1, 2;").is_err());
+		assert!(Policies::parse("permit read todo when principal.username == alice;").is_err());
+		assert!(Policies::parse("permit read todo when resource.title == 1;").is_err());
+	}
+}
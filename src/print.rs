@@ -0,0 +1,219 @@
+//! The todo list as a checklist on paper, printed from the browser or downloaded as a PDF
+//!
+//! Both take the filters the list was showing and only ever contain the todos the user can read, like the list itself.
+
+use crate::{
+	routes::Route,
+	todo::{get_todos, Priority},
+};
+use chrono::prelude::*;
+use leptos::*;
+use leptos_router::{use_query_map, ParamsMap, A};
+use serde::{Deserialize, Serialize};
+
+/// Where the PDF of the list is downloaded from, with the filters as the query
+pub const PDF_PATH: &str = "/print/todos.pdf";
+
+/// What the list was narrowed down to when it's printed
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PrintFilters {
+	#[serde(default)]
+	pub archived: bool,
+	#[serde(default)]
+	pub assigned_to_me: bool,
+	#[serde(default)]
+	pub project: Option<i32>,
+}
+
+impl PrintFilters {
+	/// The filters as the query of a link, empty without any
+	pub fn query(&self) -> String {
+		let mut query = Vec::new();
+		if self.archived {
+			query.push(String::from("archived=true"));
+		}
+		if self.assigned_to_me {
+			query.push(String::from("assigned_to_me=true"));
+		}
+		if let Some(project) = self.project {
+			query.push(format!("project={project}"));
+		}
+
+		if query.is_empty() {
+			String::new()
+		} else {
+			format!("?{}", query.join("&"))
+		}
+	}
+
+	fn from_query(query: &ParamsMap) -> Self {
+		Self {
+			archived: query.get("archived").is_some_and(|archived| archived == "true"),
+			assigned_to_me: query.get("assigned_to_me").is_some_and(|assigned| assigned == "true"),
+			project: query.get("project").and_then(|project| project.parse().ok()),
+		}
+	}
+}
+
+/// When the todo is due and how urgent it is, for the checklist next to its title
+fn details(due_at: Option<DateTime<Utc>>, priority: Priority) -> String {
+	let mut details = Vec::new();
+	if let Some(due_at) = due_at {
+		details.push(format!("due {}", due_at.format("%Y-%m-%d %H:%M UTC")));
+	}
+	if priority != Priority::Normal {
+		details.push(format!("{} priority", priority.as_str()));
+	}
+
+	details.join(", ")
+}
+
+#[cfg(feature = "ssr")]
+pub mod ssr {
+	use super::{details, PrintFilters};
+	use crate::{
+		auth::ssr::AuthSession,
+		fallback::unauthenticated,
+		pdf::text_document,
+		state::AppState,
+		tenant::Tenant,
+		todo::ssr::{list_selected_todos, TodoSelection},
+	};
+	use axum::{
+		extract::{Query, State},
+		http::{header, StatusCode},
+		response::{IntoResponse, Response},
+	};
+	use chrono::prelude::*;
+
+	/// The list as a PDF checklist, the filters are taken from the query like on [`super::PrintTodos`]
+	pub async fn export_pdf(
+		State(app_state): State<AppState>,
+		auth_session: AuthSession,
+		tenant: Tenant,
+		Query(filters): Query<PrintFilters>,
+	) -> Result<Response, (StatusCode, String)> {
+		let user = auth_session.current_user.filter(|user| user.tenant == tenant.id).ok_or_else(unauthenticated)?;
+
+		let selection = TodoSelection {
+			archived: filters.archived,
+			assigned_to_me: filters.assigned_to_me,
+			project: filters.project,
+		};
+		let todos = list_selected_todos(&user, tenant.id, selection, &app_state.pool)
+			.await
+			.map_err(|error| (StatusCode::INTERNAL_SERVER_ERROR, format!("Something went wrong: {error}")))?;
+
+		let lines = todos
+			.iter()
+			.map(|todo| {
+				let details = details(todo.due_at, todo.priority);
+				let check = if todo.completed { "[x]" } else { "[ ]" };
+				if details.is_empty() {
+					format!("{check} {}", todo.title)
+				} else {
+					format!("{check} {} ({details})", todo.title)
+				}
+			})
+			.collect::<Vec<_>>();
+		let title = format!("Todos of {}, {}", tenant.name, Utc::now().format("%Y-%m-%d"));
+
+		Ok(
+			(
+				[
+					(header::CONTENT_TYPE, "application/pdf"),
+					(header::CONTENT_DISPOSITION, "attachment; filename=\"todos.pdf\""),
+					(header::CACHE_CONTROL, "private, no-store"),
+				],
+				text_document(&title, &lines),
+			)
+				.into_response(),
+		)
+	}
+}
+
+/// The list as a checklist laid out for paper, with the filters of the list in the query
+#[component]
+pub fn PrintTodos() -> impl IntoView {
+	let query = use_query_map();
+	let filters = create_memo(move |_| query.with(PrintFilters::from_query));
+	let todos = create_resource(
+		move || filters.get(),
+		|filters| async move {
+			get_todos(None, filters.archived, filters.assigned_to_me, filters.project)
+				.await
+				.map(|list| list.todos.unwrap_or_default())
+		},
+	);
+
+	view! {
+		<div class="print-actions">
+			<button
+				type="button"
+				on:click=move |_| {
+					_ = window().print();
+				}
+			>
+				"Print"
+			</button>
+			<a href=move || format!("{PDF_PATH}{}", filters.get().query()) download="todos.pdf">
+				"Download PDF"
+			</a>
+			<A href=Route::Home>"Back to all todos"</A>
+		</div>
+		<Transition fallback=move || view! { <p>"Loading..."</p> }>
+			{move || {
+				todos
+					.get()
+					.map(|todos| match todos {
+						Err(e) => view! { <pre class="error">"Server Error: " {e.to_string()}</pre> }.into_view(),
+						Ok(todos) if todos.is_empty() => view! { <p>"No tasks were found."</p> }.into_view(),
+						Ok(todos) => {
+							view! {
+								<table class="print-list">
+									<thead>
+										<tr>
+											<th>"Done"</th>
+											<th>"Todo"</th>
+											<th>"Details"</th>
+										</tr>
+									</thead>
+									<tbody>
+										{todos
+											.into_iter()
+											.map(|todo| {
+												view! {
+													<tr>
+														<td>{if todo.completed { "☑" } else { "☐" }}</td>
+														<td>{todo.title}</td>
+														<td>{details(todo.due_at, todo.priority)}</td>
+													</tr>
+												}
+											})
+											.collect_view()}
+									</tbody>
+								</table>
+							}
+								.into_view()
+						}
+					})
+			}}
+		</Transition>
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn filters_make_the_query() {
+		assert_eq!(PrintFilters::default().query(), "");
+		let filters = PrintFilters {
+			archived: true,
+			assigned_to_me: false,
+			project: Some(4),
+		};
+		assert_eq!(filters.query(), "?archived=true&project=4");
+	}
+}
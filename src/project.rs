@@ -0,0 +1,225 @@
+//! Projects group the todos of a tenant, permissions can be limited to the todos of a project with `project[id]`
+//!
+//! Admins create and delete projects, everyone sees the projects they can read todos of next to the list. Deleting a
+//! project keeps its todos, they are just not part of any project anymore.
+
+use crate::{
+	auth::{use_user_context, UserContext},
+	errors::AppServerError,
+	routes,
+	toast::{use_toasts, Toasted},
+};
+use leptos::*;
+use leptos_router::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "ssr", derive(sqlx::FromRow, utoipa::ToSchema))]
+pub struct Project {
+	pub id: i32,
+	pub name: String,
+}
+
+#[cfg(feature = "ssr")]
+pub mod ssr {
+	use super::Project;
+	use crate::{
+		auth::User,
+		authorize::{self, Action},
+	};
+	use sqlx::PgPool;
+
+	/// The projects of the tenant the user can read todos of, admins get all of them to manage them
+	pub async fn readable_projects(user: &User, tenant: i32, pool: &PgPool) -> Result<Vec<Project>, sqlx::Error> {
+		Ok(
+			sqlx::query_as::<_, Project>("SELECT id, name FROM projects WHERE tenant = $1 ORDER BY name, id")
+				.bind(tenant)
+				.fetch_all(pool)
+				.await?
+				.into_iter()
+				.filter(|project| user.is_admin() || authorize::reaches_project(user, Action::Read, project.id))
+				.collect(),
+		)
+	}
+}
+
+/// The projects the user can read todos of, by name
+#[server(input = GetUrl)]
+pub async fn get_projects() -> Result<Vec<Project>, ServerFnError<AppServerError>> {
+	use self::ssr::readable_projects;
+	use crate::{auth::get_user, cache::cache_privately, tenant::Tenant};
+	use sqlx::PgPool;
+
+	cache_privately::<GetProjects>(0);
+
+	let pool = use_context::<PgPool>().expect("Database not initialized");
+	let tenant = use_context::<Tenant>().expect("No tenant found");
+	let user = get_user().await?.ok_or_else(AppServerError::unauthenticated)?;
+
+	Ok(readable_projects(&user, tenant.id, &pool).await.map_err(AppServerError::from)?)
+}
+
+/// Add a project to the tenant, admins only
+#[server]
+pub async fn create_project(name: String) -> Result<Toasted<()>, ServerFnError<AppServerError>> {
+	use crate::{
+		auth::get_user,
+		denial::{ssr::record_denial, DeniedAction},
+		tenant::Tenant,
+		validation::PROJECT_NAME,
+	};
+	use sqlx::PgPool;
+
+	let pool = use_context::<PgPool>().expect("Database not initialized");
+	let tenant = use_context::<Tenant>().expect("No tenant found");
+	let user = get_user().await?.ok_or_else(AppServerError::unauthenticated)?;
+	if !user.is_admin() {
+		record_denial("create_project", DeniedAction::Admin, &user);
+		return Err(AppServerError::forbidden("Missing permission to manage projects").into());
+	}
+
+	let name = PROJECT_NAME.clean(&name).map_err(AppServerError::invalid)?;
+	sqlx::query("INSERT INTO projects (tenant, name) VALUES ($1, $2)")
+		.bind(tenant.id)
+		.bind(name)
+		.execute(&pool)
+		.await
+		.map_err(|error| match error {
+			sqlx::Error::Database(error) if error.is_unique_violation() => {
+				AppServerError::conflict("There is a project with that name already")
+			},
+			error => error.into(),
+		})?;
+
+	Ok(Toasted::success((), format!("Project {name} created")))
+}
+
+/// Delete a project of the tenant, its todos stay without a project, admins only
+#[server]
+pub async fn delete_project(id: i32) -> Result<Toasted<()>, ServerFnError<AppServerError>> {
+	use crate::{
+		auth::get_user,
+		cache::response_cache,
+		denial::{ssr::record_denial, DeniedAction},
+		events::{record, DomainEvent},
+		history::ssr::{record_change, PROJECT},
+		tenant::Tenant,
+	};
+	use sqlx::PgPool;
+
+	let pool = use_context::<PgPool>().expect("Database not initialized");
+	let tenant = use_context::<Tenant>().expect("No tenant found");
+	let user = get_user().await?.ok_or_else(AppServerError::unauthenticated)?;
+	if !user.is_admin() {
+		record_denial("delete_project", DeniedAction::Admin, &user);
+		return Err(AppServerError::forbidden("Missing permission to manage projects").into());
+	}
+
+	let mut tx = pool.begin().await.map_err(AppServerError::from)?;
+	sqlx::query_scalar::<_, i32>("SELECT id FROM projects WHERE id = $1 AND tenant = $2 FOR UPDATE")
+		.bind(id)
+		.bind(tenant.id)
+		.fetch_optional(&mut *tx)
+		.await
+		.map_err(AppServerError::from)?
+		.ok_or_else(|| AppServerError::not_found("Project not found"))?;
+	// The todos are taken out of the project by hand so they show up as changed, the foreign key wouldn't touch them
+	let todos = sqlx::query_scalar::<_, i32>(
+		"UPDATE todos SET project = NULL, updated_at = now(), version = version + 1
+		WHERE project = $1 AND tenant = $2 RETURNING id",
+	)
+	.bind(id)
+	.bind(tenant.id)
+	.fetch_all(&mut *tx)
+	.await
+	.map_err(AppServerError::from)?;
+	for todo in &todos {
+		record_change(&mut tx, *todo, user.id, PROJECT, Some(id.to_string()), None).await.map_err(AppServerError::from)?;
+		record(
+			&mut *tx,
+			&DomainEvent::TodoUpdated {
+				tenant: tenant.id,
+				user: user.id,
+				todo: *todo,
+			},
+		)
+		.await
+		.map_err(AppServerError::from)?;
+	}
+	sqlx::query("DELETE FROM projects WHERE id = $1").bind(id).execute(&mut *tx).await.map_err(AppServerError::from)?;
+	tx.commit().await.map_err(AppServerError::from)?;
+	response_cache().invalidate_tenant(tenant.id);
+
+	Ok(Toasted::success((), "Project deleted"))
+}
+
+/// The project the list is narrowed down to, taken from the `project` query parameter
+pub fn use_selected_project() -> Memo<Option<i32>> {
+	let query = use_query_map();
+	create_memo(move |_| query.with(|query| query.get("project").and_then(|project| project.parse().ok())))
+}
+
+/// The projects the user can read next to the list, picking one narrows the list down to its todos
+#[component]
+pub fn ProjectNav() -> impl IntoView {
+	let create = create_server_action::<CreateProject>();
+	let delete = create_server_action::<DeleteProject>();
+	let toasts = use_toasts();
+	toasts.follow(create);
+	toasts.follow(delete);
+	let UserContext { logged_out, user, .. } = use_user_context();
+	let is_admin = move || user.get().and_then(Result::ok).flatten().is_some_and(|user| user.is_admin());
+	let selected = use_selected_project();
+	let projects = create_resource(
+		move || {
+			logged_out.track();
+			(create.version().get(), delete.version().get())
+		},
+		|_| get_projects(),
+	);
+
+	view! {
+		<nav class="project-nav">
+			<h2>"Projects"</h2>
+			<Transition fallback=move || ()>
+				{move || {
+					projects
+						.get()
+						.and_then(Result::ok)
+						.map(|projects| {
+							view! {
+								<ul>
+									<li class:selected=move || selected.get().is_none()>
+										<A href=routes::Route::Home>"All todos"</A>
+									</li>
+									{projects
+										.into_iter()
+										.map(|project| {
+											let id = project.id;
+											view! {
+												<li class:selected=move || selected.get() == Some(id)>
+													<A href=routes::Route::Project(id)>{project.name}</A>
+													<Show when=is_admin>
+														<ActionForm action=delete>
+															<input type="hidden" name="id" value=id />
+															<input type="submit" value="Delete" />
+														</ActionForm>
+													</Show>
+												</li>
+											}
+										})
+										.collect_view()}
+								</ul>
+							}
+						})
+				}}
+			</Transition>
+			<Show when=is_admin>
+				<ActionForm action=create>
+					<input type="text" name="name" required=true />
+					<input type="submit" value="Add project" />
+				</ActionForm>
+			</Show>
+		</nav>
+	}
+}
@@ -0,0 +1,216 @@
+use leptos::*;
+use leptos_meta::{Link, Meta};
+
+/// The color of the icon background, also used for the browser chrome of the installed app
+pub const THEME_COLOR: &str = "#2b6cb0";
+
+/// The sizes the app icon is served in, the ones browsers ask for before they offer to install
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IconSize {
+	Small,
+	Large,
+}
+
+impl IconSize {
+	pub const ALL: [IconSize; 2] = [IconSize::Small, IconSize::Large];
+
+	pub fn pixels(&self) -> u32 {
+		match self {
+			IconSize::Small => 192,
+			IconSize::Large => 512,
+		}
+	}
+
+	pub fn from_pixels(pixels: u32) -> Option<Self> {
+		IconSize::ALL.into_iter().find(|size| size.pixels() == pixels)
+	}
+}
+
+pub fn icon_url(size: IconSize) -> String {
+	format!("/icons/{}", size.pixels())
+}
+
+#[cfg(feature = "ssr")]
+pub mod ssr {
+	use super::{icon_url, IconSize, THEME_COLOR};
+	use crate::todo::EditResult;
+	use axum::{
+		body::Bytes,
+		extract::{Path, State},
+		http::{header, StatusCode},
+		response::{IntoResponse, Response},
+		Json,
+	};
+	use image::{imageops::FilterType, ImageFormat};
+	use leptos::LeptosOptions;
+	use sha2::{Digest, Sha256};
+	use std::{io::Cursor, path::Path as FsPath, sync::Arc};
+
+	const WORKER: &str = include_str!("sw.js");
+
+	/// What the worker answers an edit it queued with, the page takes it for one that was saved without an undo
+	pub fn queued_edit() -> String {
+		serde_json::to_string(&EditResult::Saved { undo: None }).expect("An edit result always serializes")
+	}
+
+	/// The icons and the service worker, put together once from what the build left in the site root
+	///
+	/// cargo-leptos builds the wasm bundle next to the server, so the server can't embed the asset list while it's
+	/// compiled and reads it on start instead.
+	#[derive(Debug, Clone)]
+	pub struct Pwa {
+		icons: Arc<Vec<(IconSize, Bytes)>>,
+		worker: Arc<String>,
+	}
+
+	impl Pwa {
+		pub fn build(leptos_options: &LeptosOptions) -> Self {
+			let site_root = FsPath::new(&leptos_options.site_root);
+			let icons = std::fs::read(site_root.join("icon.png"))
+				.map_err(|error| error.to_string())
+				.and_then(|data| resize(&data).map_err(|error| error.to_string()))
+				.unwrap_or_else(|error| {
+					log::warn!("No app icon in {}, the app can't be installed: {error}", site_root.display());
+					Vec::new()
+				});
+
+			let pkg_dir = site_root.join(&leptos_options.site_pkg_dir);
+			let mut assets = std::fs::read_dir(&pkg_dir)
+				.map(|entries| entries.filter_map(Result::ok).map(|entry| entry.path()).filter(|path| path.is_file()).collect())
+				.unwrap_or_else(|error| {
+					log::warn!("Nothing to precache, {} is unreadable: {error}", pkg_dir.display());
+					Vec::new()
+				});
+			assets.sort();
+
+			// Asset names don't change between builds, so the worker has to change whenever their contents do
+			let mut hasher = Sha256::new();
+			let mut precache = Vec::with_capacity(assets.len());
+			for path in assets {
+				let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+					continue;
+				};
+				let Ok(data) = std::fs::read(&path) else {
+					continue;
+				};
+				let url = format!("/{}/{name}", leptos_options.site_pkg_dir);
+				hasher.update(url.as_bytes());
+				hasher.update(&data);
+				precache.push(url);
+			}
+			for (_, data) in &icons {
+				hasher.update(data);
+			}
+			precache.push(String::from("/manifest.webmanifest"));
+			precache.extend(icons.iter().map(|(size, _)| icon_url(*size)));
+
+			let version = hasher.finalize().iter().take(8).map(|byte| format!("{byte:02x}")).collect::<String>();
+			let worker = format!(
+				"const PRECACHE = {};\nconst VERSION = \"{version}\";\nconst QUEUED_EDIT = {};\n\n{WORKER}",
+				serde_json::to_string(&precache).unwrap_or_else(|_| String::from("[]")),
+				queued_edit(),
+			);
+
+			Self {
+				icons: Arc::new(icons),
+				worker: Arc::new(worker),
+			}
+		}
+	}
+
+	fn resize(data: &[u8]) -> Result<Vec<(IconSize, Bytes)>, image::ImageError> {
+		let img = image::load_from_memory(data)?;
+
+		IconSize::ALL
+			.into_iter()
+			.map(|size| {
+				let mut buffer = Cursor::new(Vec::new());
+				img
+					.resize_to_fill(size.pixels(), size.pixels(), FilterType::Lanczos3)
+					.write_to(&mut buffer, ImageFormat::Png)?;
+				Ok((size, buffer.into_inner().into()))
+			})
+			.collect()
+	}
+
+	pub async fn manifest() -> Response {
+		let icons = IconSize::ALL
+			.into_iter()
+			.map(|size| {
+				serde_json::json!({
+					"src": icon_url(size),
+					"sizes": format!("{0}x{0}", size.pixels()),
+					"type": "image/png",
+					"purpose": "any maskable",
+				})
+			})
+			.collect::<Vec<_>>();
+
+		(
+			[(header::CONTENT_TYPE, "application/manifest+json")],
+			Json(serde_json::json!({
+				"name": "My Tasks",
+				"short_name": "Tasks",
+				"id": "/",
+				"start_url": "/",
+				"scope": "/",
+				"display": "standalone",
+				"background_color": "#ffffff",
+				"theme_color": THEME_COLOR,
+				"icons": icons,
+			})),
+		)
+			.into_response()
+	}
+
+	pub async fn serve_icon(State(pwa): State<Pwa>, Path(pixels): Path<u32>) -> Result<Response, StatusCode> {
+		let size = IconSize::from_pixels(pixels).ok_or(StatusCode::NOT_FOUND)?;
+		let data =
+			pwa.icons.iter().find(|(icon, _)| *icon == size).map(|(_, data)| data.clone()).ok_or(StatusCode::NOT_FOUND)?;
+
+		Ok(
+			(
+				[
+					(header::CONTENT_TYPE, "image/png"),
+					(header::CACHE_CONTROL, "public, max-age=86400"),
+				],
+				data,
+			)
+				.into_response(),
+		)
+	}
+
+	/// Browsers check for a new worker on every navigation, it must never come out of a cache
+	pub async fn service_worker(State(pwa): State<Pwa>) -> Response {
+		(
+			[
+				(header::CONTENT_TYPE, "text/javascript"),
+				(header::CACHE_CONTROL, "no-cache"),
+			],
+			pwa.worker.to_string(),
+		)
+			.into_response()
+	}
+}
+
+/// The links that make the app installable, render it in the head next to the stylesheet
+#[component]
+pub fn WebManifest() -> impl IntoView {
+	view! {
+		<Link rel="manifest" href="/manifest.webmanifest" />
+		<Link rel="apple-touch-icon" href=icon_url(IconSize::Small) />
+		<Meta name="theme-color" content=THEME_COLOR />
+	}
+}
+
+#[cfg(all(test, feature = "ssr"))]
+mod tests {
+	use super::ssr::queued_edit;
+	use crate::todo::EditResult;
+
+	#[test]
+	fn queued_edit_test() {
+		assert_eq!(queued_edit(), r#"{"Saved":{"undo":null}}"#);
+		assert_eq!(serde_json::from_str::<EditResult>(&queued_edit()).unwrap(), EditResult::Saved { undo: None });
+	}
+}
@@ -0,0 +1,344 @@
+use crate::{
+	config::{Config, StoreBackend},
+	errors::{AppServerError, ErrorCode},
+};
+use async_trait::async_trait;
+use axum::{
+	extract::ConnectInfo,
+	http::{header::RETRY_AFTER, request::Parts, HeaderMap},
+	response::{IntoResponse, Response},
+};
+use leptos::{
+	server_fn::error::{ServerFnError, ServerFnErrorSerde},
+	use_context,
+};
+use sqlx::PgPool;
+use std::{
+	fmt::Debug,
+	net::{IpAddr, SocketAddr},
+	sync::Arc,
+	time::Duration,
+};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum RateLimitError {
+	#[error("Too many attempts, try again in {retry_after} seconds")]
+	Limited { retry_after: u64 },
+	#[error("Rate limit error: {0}")]
+	Backend(String),
+}
+
+impl RateLimitError {
+	pub fn code(&self) -> ErrorCode {
+		match self {
+			RateLimitError::Limited { .. } => ErrorCode::RateLimited,
+			RateLimitError::Backend(_) => ErrorCode::Internal,
+		}
+	}
+}
+
+/// The hits counted for a key in its current window
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Hits {
+	pub count: u32,
+	/// Seconds until the window ends and the count starts over
+	pub reset_in: u64,
+}
+
+/// Fixed window counters, kept outside the process so every replica sees the same counts
+#[async_trait]
+pub trait RateLimiter: Debug + Send + Sync {
+	/// Count a hit, the window starts with the first hit and lasts `window`
+	async fn hit(&self, key: &str, window: Duration) -> Result<Hits, RateLimitError>;
+	/// The hits of the current window, `None` when there is none
+	async fn get(&self, key: &str) -> Result<Option<Hits>, RateLimitError>;
+	async fn clear(&self, key: &str) -> Result<(), RateLimitError>;
+}
+
+/// Pick the rate limit backend from the config
+pub fn from_config(config: &Config, pool: PgPool) -> Arc<dyn RateLimiter> {
+	match config.rate_limit_backend {
+		StoreBackend::Postgres => Arc::new(PgRateLimiter::new(pool)),
+		#[cfg(feature = "redis")]
+		StoreBackend::Redis => {
+			Arc::new(RedisRateLimiter::new(&config.redis_url).expect("Unable to configure the Redis rate limiter"))
+		},
+		#[cfg(not(feature = "redis"))]
+		StoreBackend::Redis => panic!("RATE_LIMIT_BACKEND is set to redis but the redis feature is not enabled"),
+	}
+}
+
+/// Where a request comes from, the address of the connection or with `TRUST_PROXY` the one the proxy in front of the
+/// server added last to `X-Forwarded-For`, `None` when neither is known
+pub fn client_ip(headers: &HeaderMap, peer: Option<SocketAddr>, config: &Config) -> Option<IpAddr> {
+	if !config.trust_proxy {
+		return peer.map(|peer| peer.ip());
+	}
+
+	headers
+		.get_all("x-forwarded-for")
+		.iter()
+		.last()
+		.and_then(|value| value.to_str().ok())
+		.and_then(|value| value.rsplit(',').next())
+		.and_then(|client| client.trim().parse().ok())
+}
+
+/// The [`client_ip`] of the request a server function runs for
+pub fn request_client(config: &Config) -> Option<IpAddr> {
+	let parts = use_context::<Parts>()?;
+	let peer = parts.extensions.get::<ConnectInfo<SocketAddr>>().map(|ConnectInfo(peer)| *peer);
+
+	client_ip(&parts.headers, peer, config)
+}
+
+fn login_key(tenant: i32, username: &str, client: Option<IpAddr>) -> String {
+	let client = match client {
+		// Whoever has one address of a /64 usually has all of them
+		Some(IpAddr::V6(ip)) => {
+			let [a, b, c, d, ..] = ip.segments();
+			format!("{a:x}:{b:x}:{c:x}:{d:x}::/64")
+		},
+		Some(ip) => ip.to_string(),
+		None => String::from("unknown"),
+	};
+
+	format!("login:{tenant}:{client}:{}", username.to_lowercase())
+}
+
+/// Count an attempt to log in as the username and refuse it once there were too many from this client
+///
+/// The attempt is counted before the password is checked, in a single write, so attempts running at once can't get
+/// past the limit together. Attempts are counted by username and client so nobody can lock a user out from elsewhere.
+pub async fn check_login(
+	limiter: &dyn RateLimiter,
+	config: &Config,
+	tenant: i32,
+	username: &str,
+	client: Option<IpAddr>,
+) -> Result<(), RateLimitError> {
+	let hits = limiter.hit(&login_key(tenant, username, client), Duration::from_secs(config.login_lockout)).await?;
+	if hits.count > config.login_max_attempts {
+		return Err(RateLimitError::Limited {
+			retry_after: hits.reset_in,
+		});
+	}
+
+	Ok(())
+}
+
+/// Forget the attempts of the client once the password turned out to be right
+pub async fn login_succeeded(
+	limiter: &dyn RateLimiter,
+	tenant: i32,
+	username: &str,
+	client: Option<IpAddr>,
+) -> Result<(), RateLimitError> {
+	limiter.clear(&login_key(tenant, username, client)).await
+}
+
+/// The name of the server function at `path`, without the `/api/` prefix and the hash leptos adds to it
+pub fn server_fn_name(path: &str) -> &str {
+	path.rsplit('/').next().unwrap_or_default().trim_end_matches(|c: char| c.is_ascii_digit())
+}
+
+/// Count a call of the server function `name` by the user, refused once the user used up its limit for the window
+pub async fn check_server_fn(
+	limiter: &dyn RateLimiter,
+	config: &Config,
+	user: i32,
+	name: &str,
+) -> Result<(), RateLimitError> {
+	let Some(limit) = config.server_fn_limits.get(name) else {
+		return Ok(());
+	};
+
+	let hits = limiter.hit(&format!("fn:{user}:{name}"), Duration::from_secs(limit.window)).await?;
+	if hits.count > limit.calls {
+		return Err(RateLimitError::Limited {
+			retry_after: hits.reset_in,
+		});
+	}
+
+	Ok(())
+}
+
+/// The response to a server function call over its limit, an error with [`ErrorCode::RateLimited`] the client can
+/// show and a `Retry-After` header
+pub fn refuse_server_fn(retry_after: u64) -> Response {
+	let error = ServerFnError::WrappedServerError(AppServerError::new(
+		ErrorCode::RateLimited,
+		format!("Too many requests, try again in {retry_after} seconds"),
+	));
+
+	(ErrorCode::RateLimited.status_code(), [(RETRY_AFTER, retry_after.to_string())], error.ser().unwrap_or_default())
+		.into_response()
+}
+
+fn backend_error(error: impl std::fmt::Display) -> RateLimitError {
+	RateLimitError::Backend(error.to_string())
+}
+
+/// Counters live in the `rate_limits` table, every hit is a single upsert so concurrent replicas can't lose counts
+#[derive(Debug, Clone)]
+pub struct PgRateLimiter {
+	pool: PgPool,
+}
+
+impl PgRateLimiter {
+	pub fn new(pool: PgPool) -> Self {
+		Self { pool }
+	}
+}
+
+#[async_trait]
+impl RateLimiter for PgRateLimiter {
+	async fn hit(&self, key: &str, window: Duration) -> Result<Hits, RateLimitError> {
+		let (count, reset_in) = sqlx::query_as::<_, (i32, i64)>(
+			"INSERT INTO rate_limits (key, hits, expires_at) VALUES ($1, 1, now() + make_interval(secs => $2))
+			ON CONFLICT (key) DO UPDATE SET
+				hits = CASE WHEN rate_limits.expires_at <= now() THEN 1 ELSE rate_limits.hits + 1 END,
+				expires_at = CASE WHEN rate_limits.expires_at <= now() THEN EXCLUDED.expires_at ELSE rate_limits.expires_at END
+			RETURNING hits, CEIL(EXTRACT(EPOCH FROM expires_at - now()))::BIGINT",
+		)
+		.bind(key)
+		.bind(window.as_secs_f64())
+		.fetch_one(&self.pool)
+		.await
+		.map_err(backend_error)?;
+
+		Ok(Hits {
+			count: count as u32,
+			reset_in: reset_in.max(0) as u64,
+		})
+	}
+
+	async fn get(&self, key: &str) -> Result<Option<Hits>, RateLimitError> {
+		let hits = sqlx::query_as::<_, (i32, i64)>(
+			"SELECT hits, CEIL(EXTRACT(EPOCH FROM expires_at - now()))::BIGINT FROM rate_limits
+			WHERE key = $1 AND expires_at > now()",
+		)
+		.bind(key)
+		.fetch_optional(&self.pool)
+		.await
+		.map_err(backend_error)?;
+
+		Ok(hits.map(|(count, reset_in)| Hits {
+			count: count as u32,
+			reset_in: reset_in.max(0) as u64,
+		}))
+	}
+
+	async fn clear(&self, key: &str) -> Result<(), RateLimitError> {
+		sqlx::query("DELETE FROM rate_limits WHERE key = $1").bind(key).execute(&self.pool).await.map_err(backend_error)?;
+
+		Ok(())
+	}
+}
+
+/// Counters live in Redis keys that expire with their window
+#[cfg(feature = "redis")]
+pub struct RedisRateLimiter {
+	client: redis::Client,
+	connection: tokio::sync::OnceCell<redis::aio::MultiplexedConnection>,
+}
+
+#[cfg(feature = "redis")]
+impl Debug for RedisRateLimiter {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.debug_struct("RedisRateLimiter").finish_non_exhaustive()
+	}
+}
+
+#[cfg(feature = "redis")]
+impl RedisRateLimiter {
+	pub fn new(url: &str) -> Result<Self, RateLimitError> {
+		Ok(Self {
+			client: redis::Client::open(url).map_err(backend_error)?,
+			connection: tokio::sync::OnceCell::new(),
+		})
+	}
+
+	async fn connection(&self) -> Result<redis::aio::MultiplexedConnection, RateLimitError> {
+		self
+			.connection
+			.get_or_try_init(|| self.client.get_multiplexed_async_connection())
+			.await
+			.cloned()
+			.map_err(backend_error)
+	}
+}
+
+#[cfg(feature = "redis")]
+#[async_trait]
+impl RateLimiter for RedisRateLimiter {
+	async fn hit(&self, key: &str, window: Duration) -> Result<Hits, RateLimitError> {
+		// `NX` only sets the expiry on the first hit so later hits don't push the end of the window out
+		let (count, reset_in): (u32, i64) = redis::pipe()
+			.atomic()
+			.incr(key, 1)
+			.cmd("EXPIRE")
+			.arg(key)
+			.arg(window.as_secs())
+			.arg("NX")
+			.ignore()
+			.ttl(key)
+			.query_async(&mut self.connection().await?)
+			.await
+			.map_err(backend_error)?;
+
+		Ok(Hits {
+			count,
+			reset_in: reset_in.max(0) as u64,
+		})
+	}
+
+	async fn get(&self, key: &str) -> Result<Option<Hits>, RateLimitError> {
+		let (count, reset_in): (Option<u32>, i64) = redis::pipe()
+			.atomic()
+			.get(key)
+			.ttl(key)
+			.query_async(&mut self.connection().await?)
+			.await
+			.map_err(backend_error)?;
+
+		Ok(count.map(|count| Hits {
+			count,
+			reset_in: reset_in.max(0) as u64,
+		}))
+	}
+
+	async fn clear(&self, key: &str) -> Result<(), RateLimitError> {
+		redis::cmd("DEL").arg(key).query_async::<_, ()>(&mut self.connection().await?).await.map_err(backend_error)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn login_keys_are_per_username_and_client() {
+		let peer = Some(SocketAddr::from(([10, 0, 0, 1], 4000)));
+		let mut headers = HeaderMap::new();
+		headers.insert("x-forwarded-for", "203.0.113.9, 198.51.100.7".parse().unwrap());
+		let proxied = Config {
+			trust_proxy: true,
+			..Config::default()
+		};
+		assert_eq!(client_ip(&headers, peer, &Config::default()), Some(IpAddr::from([10, 0, 0, 1])));
+		assert_eq!(client_ip(&headers, peer, &proxied), Some(IpAddr::from([198, 51, 100, 7])));
+		assert_eq!(client_ip(&HeaderMap::new(), peer, &proxied), None);
+
+		assert_eq!(login_key(1, "Alice", client_ip(&headers, peer, &proxied)), "login:1:198.51.100.7:alice");
+		assert_eq!(login_key(1, "alice", "2001:db8:1:2:3:4:5:6".parse().ok()), "login:1:2001:db8:1:2::/64:alice");
+		assert_eq!(login_key(1, "alice", None), "login:1:unknown:alice");
+	}
+
+	#[test]
+	fn server_fn_names_drop_the_hash() {
+		assert_eq!(server_fn_name("/api/add_todo4316533062373594199"), "add_todo");
+		assert_eq!(server_fn_name("/api/get_todos"), "get_todos");
+	}
+}
@@ -0,0 +1,267 @@
+use crate::errors::AppServerError;
+use leptos::*;
+use leptos_router::*;
+use serde::{Deserialize, Serialize};
+use std::{fmt, str::FromStr};
+
+/// How often a todo comes back after it's completed
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "ssr", derive(utoipa::ToSchema))]
+pub enum Recurrence {
+	Daily,
+	Weekly,
+	Monthly,
+	/// A cron expression with a seconds field, e.g. `0 0 9 * * Mon-Fri`
+	Cron(String),
+}
+
+impl fmt::Display for Recurrence {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Recurrence::Daily => write!(f, "daily"),
+			Recurrence::Weekly => write!(f, "weekly"),
+			Recurrence::Monthly => write!(f, "monthly"),
+			Recurrence::Cron(expression) => write!(f, "{expression}"),
+		}
+	}
+}
+
+impl FromStr for Recurrence {
+	type Err = String;
+
+	fn from_str(recurrence: &str) -> Result<Self, Self::Err> {
+		match recurrence.trim().to_ascii_lowercase().as_str() {
+			"" => Err(String::from("No recurrence given")),
+			"daily" => Ok(Recurrence::Daily),
+			"weekly" => Ok(Recurrence::Weekly),
+			"monthly" => Ok(Recurrence::Monthly),
+			_ => {
+				let recurrence = Recurrence::Cron(recurrence.trim().to_string());
+				#[cfg(feature = "ssr")]
+				ssr::schedule(&recurrence)?;
+				Ok(recurrence)
+			},
+		}
+	}
+}
+
+#[cfg(feature = "ssr")]
+pub mod ssr {
+	use super::Recurrence;
+	use crate::{
+		auth::User,
+		authorize,
+		config::Config,
+		events::{record, DomainEvent},
+		history::ssr::{record_change, CREATED},
+		jobs,
+	};
+	use chrono::{prelude::*, Duration as ChronoDuration, Months};
+	use sqlx::PgPool;
+	use std::{str::FromStr, time::Duration};
+
+	pub(super) fn schedule(recurrence: &Recurrence) -> Result<Option<cron::Schedule>, String> {
+		match recurrence {
+			Recurrence::Cron(expression) => cron::Schedule::from_str(expression)
+				.map(Some)
+				.map_err(|error| format!("Invalid cron expression \"{expression}\": {error}")),
+			_ => Ok(None),
+		}
+	}
+
+	impl Recurrence {
+		/// The first occurrence after the given time
+		pub fn next_after(&self, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+			match self {
+				Recurrence::Daily => Some(after + ChronoDuration::days(1)),
+				Recurrence::Weekly => Some(after + ChronoDuration::weeks(1)),
+				Recurrence::Monthly => after.checked_add_months(Months::new(1)),
+				Recurrence::Cron(_) => schedule(self).ok()??.after(&after).next(),
+			}
+		}
+	}
+
+	/// When the next occurrence of a todo is due, counted from its due date so completing early doesn't shift it and
+	/// from now so completing late doesn't leave it overdue right away
+	pub fn next_due(recurrence: &Recurrence, due_at: Option<DateTime<Utc>>) -> Option<DateTime<Utc>> {
+		let now = Utc::now();
+		recurrence.next_after(due_at.filter(|due_at| *due_at > now).unwrap_or(now))
+	}
+
+	#[derive(sqlx::FromRow)]
+	struct SqlCompletedTodo {
+		id: i32,
+		tenant: i32,
+		person: i32,
+		title: String,
+		recurrence: String,
+		due_at: Option<DateTime<Utc>>,
+		project: Option<i32>,
+	}
+
+	/// Create the next occurrence of every completed recurring todo
+	///
+	/// Occurrences are created in the name of the owner, so owners who lost their create permission stop getting them
+	pub async fn materialize(pool: &PgPool) -> Result<usize, sqlx::Error> {
+		let mut tx = pool.begin().await?;
+
+		// Locked rows are skipped so several replicas can run the scheduler without creating an occurrence twice
+		let completed = sqlx::query_as::<_, SqlCompletedTodo>(
+			"SELECT id, tenant, person, title, recurrence, due_at, project FROM todos
+			WHERE completed AND recurrence IS NOT NULL AND NOT recurred AND deleted_at IS NULL
+			ORDER BY id FOR UPDATE SKIP LOCKED",
+		)
+		.fetch_all(&mut *tx)
+		.await?;
+
+		let mut created = 0;
+		for todo in completed {
+			sqlx::query("UPDATE todos SET recurred = true WHERE id = $1").bind(todo.id).execute(&mut *tx).await?;
+
+			let may_create = User::get_from_id(todo.person, pool)
+				.await
+				.is_some_and(|owner| owner.tenant == todo.tenant && authorize::can_create_todo(&owner));
+			if !may_create {
+				log::info!("Not repeating todo {} because its owner can't create todos", todo.id);
+				continue;
+			}
+
+			let Some(due_at) =
+				Recurrence::from_str(&todo.recurrence).ok().and_then(|recurrence| next_due(&recurrence, todo.due_at))
+			else {
+				log::warn!("Not repeating todo {} because its recurrence \"{}\" has no next date", todo.id, todo.recurrence);
+				continue;
+			};
+
+			let id = sqlx::query_scalar::<_, i32>(
+				"INSERT INTO todos (tenant, title, person, completed, recurrence, due_at, project)
+				VALUES ($1, $2, $3, false, $4, $5, $6) RETURNING id",
+			)
+			.bind(todo.tenant)
+			.bind(&todo.title)
+			.bind(todo.person)
+			.bind(&todo.recurrence)
+			.bind(due_at)
+			.bind(todo.project)
+			.fetch_one(&mut *tx)
+			.await?;
+			record_change(&mut tx, id, todo.person, CREATED, None, Some(todo.title)).await?;
+			record(
+				&mut *tx,
+				&DomainEvent::TodoCreated {
+					tenant: todo.tenant,
+					user: todo.person,
+					todo: id,
+				},
+			)
+			.await?;
+			created += 1;
+		}
+
+		tx.commit().await?;
+
+		Ok(created)
+	}
+
+	/// Check for completed recurring todos every `RECURRENCE_INTERVAL` seconds
+	pub fn spawn_scheduler(pool: PgPool, config: &Config) {
+		jobs::every("Recurrence", Duration::from_secs(config.recurrence_interval), move || {
+			let pool = pool.clone();
+			async move { materialize(&pool).await.map(|_| ()) }
+		});
+	}
+}
+
+/// Make a todo come back once it's completed, or stop it from coming back
+///
+/// Needs write access to the todo and, as every occurrence is a new todo, the permission to create todos
+#[server]
+pub async fn set_recurrence(id: i32, recurrence: Option<String>) -> Result<(), ServerFnError<AppServerError>> {
+	use self::ssr::next_due;
+	use crate::{
+		auth::get_user,
+		authorize::{self, Action},
+		cache::response_cache,
+		denial::{ssr::record_denial, DeniedAction},
+		tenant::Tenant,
+		todo::ssr::get_todo_with_permission,
+	};
+	use sqlx::PgPool;
+
+	let pool = use_context::<PgPool>().expect("Database not initialized");
+	let tenant = use_context::<Tenant>().expect("No tenant found");
+	let user = get_user().await?.ok_or_else(AppServerError::unauthenticated)?;
+
+	if get_todo_with_permission(id, tenant.id, &user, Action::Write, &pool).await.is_none() {
+		record_denial("set_recurrence", DeniedAction::Write, &user);
+		return Err(AppServerError::invalid("Missing write permission for this todo").into());
+	}
+
+	let recurrence = recurrence.filter(|recurrence| !recurrence.trim().is_empty());
+	let Some(recurrence) = recurrence else {
+		sqlx::query("UPDATE todos SET recurrence = NULL, updated_at = now(), version = version + 1 WHERE id = $1")
+			.bind(id)
+			.execute(&pool)
+			.await
+			.map_err(AppServerError::from)?;
+		response_cache().invalidate_tenant(tenant.id);
+		return Ok(());
+	};
+
+	if !authorize::can_create_todo(&user) {
+		record_denial("set_recurrence", DeniedAction::Create, &user);
+		return Err(AppServerError::forbidden("Missing permission to create todos").into());
+	}
+	let recurrence = recurrence.parse::<Recurrence>().map_err(AppServerError::invalid)?;
+
+	// A todo without a due date gets one so the next occurrence has something to count from
+	sqlx::query(
+		"UPDATE todos SET recurrence = $2, due_at = COALESCE(due_at, $3), updated_at = now(), version = version + 1
+		WHERE id = $1",
+	)
+	.bind(id)
+	.bind(recurrence.to_string())
+	.bind(next_due(&recurrence, None))
+	.execute(&pool)
+	.await
+	.map_err(AppServerError::from)?;
+	response_cache().invalidate_tenant(tenant.id);
+
+	Ok(())
+}
+
+#[component]
+pub fn RecurrenceForm(todo_id: i32, recurrence: Option<Recurrence>) -> impl IntoView {
+	let set_recurrence = create_server_action::<SetRecurrence>();
+	let current = recurrence.map(|recurrence| recurrence.to_string()).unwrap_or_default();
+
+	view! {
+		<ActionForm action=set_recurrence>
+			<input type="hidden" name="id" value=todo_id />
+			<label>
+				"Repeat "
+				<input
+					type="text"
+					name="recurrence"
+					list=format!("recurrences-{todo_id}")
+					placeholder="never"
+					value=current
+				/>
+			</label>
+			<datalist id=format!("recurrences-{todo_id}")>
+				<option value="daily"></option>
+				<option value="weekly"></option>
+				<option value="monthly"></option>
+				<option value="0 0 9 * * Mon-Fri"></option>
+			</datalist>
+			<input type="submit" value="Save" />
+			{move || {
+				set_recurrence
+					.value()
+					.get()
+					.and_then(Result::err)
+					.map(|e| view! { <span class="error">{e.to_string()}</span> })
+			}}
+		</ActionForm>
+	}
+}
@@ -0,0 +1,497 @@
+use crate::{
+	auth::{
+		ssr::{authenticated_user, end_other_sessions, start_session, AuthSession},
+		PublicUser, User,
+	},
+	cleanup::{self, CleanupMetrics},
+	denial::{
+		ssr::{denials, record_denial},
+		DenialCount, DeniedAction,
+	},
+	events::{record, DomainEvent},
+	fallback::unauthenticated,
+	jwt::{
+		ssr::{authenticate_bearer, issue_tokens},
+		JwtTokens,
+	},
+	migrations::{self, MigrationStatus},
+	rate_limit::{check_login, client_ip, login_succeeded, RateLimitError},
+	state::AppState,
+	tenant::Tenant,
+	todo::{
+		ssr::{create_todo, get_todo, list_todos, remove_todo, update_todo, TodoError},
+		Priority, Todo,
+	},
+};
+use axum::{
+	async_trait,
+	extract::{ConnectInfo, FromRequestParts, Path, State},
+	http::{request::Parts, HeaderMap, StatusCode},
+	routing::{get, post},
+	Json, Router,
+};
+use chrono::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use utoipa::{
+	openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme},
+	Modify, OpenApi, ToSchema,
+};
+
+#[derive(OpenApi)]
+#[openapi(
+	info(title = "Todos API", version = "1"),
+	paths(
+		login,
+		me,
+		todos_list,
+		todos_create,
+		todos_get,
+		todos_update,
+		todos_delete,
+		migration_status,
+		cleanup_metrics,
+		denial_metrics
+	),
+	components(schemas(
+		LoginRequest,
+		LoginResponse,
+		UserResponse,
+		JwtTokens,
+		TodoResponse,
+		CreateTodoRequest,
+		UpdateTodoRequest,
+		Priority,
+		MigrationStatus,
+		CleanupMetrics,
+		DenialCount,
+		DeniedAction
+	)),
+	modifiers(&BearerAuth),
+	security(("bearer" = []))
+)]
+pub struct ApiDoc;
+
+struct BearerAuth;
+
+impl Modify for BearerAuth {
+	fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+		if let Some(components) = openapi.components.as_mut() {
+			components.add_security_scheme(
+				"bearer",
+				SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).bearer_format("JWT").build()),
+			);
+		}
+	}
+}
+
+pub fn router() -> Router<AppState> {
+	Router::new()
+		.route("/openapi.json", get(openapi))
+		.route("/auth/login", post(login))
+		.route("/auth/me", get(me))
+		.route("/todos", get(todos_list).post(todos_create))
+		.route("/todos/:id", get(todos_get).patch(todos_update).delete(todos_delete))
+		.route("/admin/migrations", get(migration_status))
+		.route("/admin/cleanup", get(cleanup_metrics))
+		.route("/admin/denials", get(denial_metrics))
+}
+
+async fn openapi() -> Json<utoipa::openapi::OpenApi> {
+	Json(ApiDoc::openapi())
+}
+
+type ApiError = (StatusCode, String);
+
+fn internal_error(error: sqlx::Error) -> ApiError {
+	(StatusCode::INTERNAL_SERVER_ERROR, error.to_string())
+}
+
+fn rate_limit_error(error: RateLimitError) -> ApiError {
+	let status = match error {
+		RateLimitError::Limited { .. } => StatusCode::TOO_MANY_REQUESTS,
+		RateLimitError::Backend(_) => StatusCode::INTERNAL_SERVER_ERROR,
+	};
+
+	(status, error.to_string())
+}
+
+fn todo_error(error: TodoError) -> ApiError {
+	let status = match error {
+		TodoError::NotFound => StatusCode::NOT_FOUND,
+		TodoError::Forbidden => StatusCode::FORBIDDEN,
+		TodoError::Conflict { .. } => StatusCode::CONFLICT,
+		TodoError::UnknownAssignee => StatusCode::UNPROCESSABLE_ENTITY,
+		TodoError::UnknownProject => StatusCode::NOT_FOUND,
+		TodoError::Invalid(_) => StatusCode::UNPROCESSABLE_ENTITY,
+		TodoError::Database(_) => StatusCode::INTERNAL_SERVER_ERROR,
+	};
+
+	(status, error.to_string())
+}
+
+/// The logged in user, taken from a Bearer JWT or the session cookie
+pub struct ApiUser {
+	user: User,
+	tenant: Tenant,
+}
+
+#[async_trait]
+impl FromRequestParts<AppState> for ApiUser {
+	type Rejection = ApiError;
+
+	async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
+		let tenant = Tenant::from_request_parts(parts, state).await?;
+		let mut auth_session =
+			AuthSession::from_request_parts(parts, state).await.map_err(|(status, error)| (status, error.to_string()))?;
+
+		let claims = match authenticate_bearer(&parts.headers, &tenant, state).await? {
+			Some((claims, user)) => {
+				auth_session.current_user = Some(user);
+				Some(claims)
+			},
+			None => None,
+		};
+
+		let user = authenticated_user(&auth_session, &tenant, claims.as_ref(), &state.config, &state.pool)
+			.await
+			.ok_or_else(unauthenticated)?;
+
+		Ok(Self { user, tenant })
+	}
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct UserResponse {
+	id: i32,
+	username: String,
+	avatar: Option<String>,
+}
+
+impl From<User> for UserResponse {
+	fn from(user: User) -> Self {
+		PublicUser::from(user).into()
+	}
+}
+
+impl From<PublicUser> for UserResponse {
+	fn from(user: PublicUser) -> Self {
+		Self {
+			id: user.id,
+			username: user.username,
+			avatar: user.avatar,
+		}
+	}
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct TodoResponse {
+	id: i32,
+	title: String,
+	completed: bool,
+	priority: Priority,
+	created_at: DateTime<Utc>,
+	owner: Option<UserResponse>,
+	/// Send it back with an update to have it refused when someone else changed the todo in the meantime
+	version: i32,
+	project: Option<i32>,
+}
+
+impl From<Todo> for TodoResponse {
+	fn from(todo: Todo) -> Self {
+		Self {
+			id: todo.id,
+			title: todo.title,
+			completed: todo.completed,
+			priority: todo.priority,
+			created_at: todo.created_at,
+			owner: todo.user.map(UserResponse::from),
+			version: todo.version,
+			project: todo.project,
+		}
+	}
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct LoginRequest {
+	username: String,
+	password: String,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct LoginResponse {
+	user: UserResponse,
+	/// Only set when JWT authentication is enabled, otherwise the response sets a session cookie
+	tokens: Option<JwtTokens>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct CreateTodoRequest {
+	title: String,
+	/// The project to add the todo to, leave it out for none
+	project: Option<i32>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct UpdateTodoRequest {
+	title: Option<String>,
+	completed: Option<bool>,
+	priority: Option<Priority>,
+	/// The version the update is based on, leave out to update whatever the todo looks like now
+	version: Option<i32>,
+}
+
+#[utoipa::path(
+	post,
+	path = "/api/v1/auth/login",
+	request_body = LoginRequest,
+	responses(
+		(status = 200, body = LoginResponse),
+		(status = 401, description = "Username or password does not match"),
+		(status = 429, description = "Too many logins for this username from this client"),
+		(status = 503, description = "The database schema is behind")
+	),
+	security(())
+)]
+async fn login(
+	State(app_state): State<AppState>,
+	auth_session: AuthSession,
+	tenant: Tenant,
+	peer: Option<ConnectInfo<SocketAddr>>,
+	headers: HeaderMap,
+	Json(request): Json<LoginRequest>,
+) -> Result<Json<LoginResponse>, ApiError> {
+	app_state.schema.check()?;
+	let limiter = app_state.rate_limiter.as_ref();
+	let client = client_ip(&headers, peer.map(|ConnectInfo(peer)| peer), &app_state.config);
+	check_login(limiter, &app_state.config, tenant.id, &request.username, client).await.map_err(rate_limit_error)?;
+
+	let user = match app_state.auth_backend.login(&request.username, &request.password, tenant.id).await {
+		Ok(user) => user,
+		Err(error) => {
+			record(
+				&app_state.pool,
+				&DomainEvent::LoginFailed {
+					tenant: tenant.id,
+					username: request.username,
+				},
+			)
+			.await
+			.map_err(internal_error)?;
+			return Err((StatusCode::UNAUTHORIZED, error.to_string()));
+		},
+	};
+	login_succeeded(limiter, tenant.id, &request.username, client).await.map_err(rate_limit_error)?;
+	record(
+		&app_state.pool,
+		&DomainEvent::UserLoggedIn {
+			tenant: tenant.id,
+			user: user.id,
+		},
+	)
+	.await
+	.map_err(internal_error)?;
+
+	let tokens = match &app_state.jwt {
+		Some(keys) => {
+			end_other_sessions(user.id, &app_state.config, &app_state.pool).await.map_err(internal_error)?;
+			Some(
+				issue_tokens(keys, &user, None, &app_state.pool)
+					.await
+					.map_err(|error| (StatusCode::INTERNAL_SERVER_ERROR, error))?,
+			)
+		},
+		None => {
+			start_session(&auth_session, user.id, false, &app_state.config, &app_state.pool).await.map_err(internal_error)?;
+			None
+		},
+	};
+
+	Ok(Json(LoginResponse {
+		user: user.into(),
+		tokens,
+	}))
+}
+
+#[utoipa::path(
+	get,
+	path = "/api/v1/auth/me",
+	responses((status = 200, body = UserResponse), (status = 401, description = "Not logged in"))
+)]
+async fn me(api_user: ApiUser) -> Json<UserResponse> {
+	Json(api_user.user.into())
+}
+
+#[utoipa::path(
+	get,
+	path = "/api/v1/todos",
+	responses((status = 200, body = [TodoResponse]), (status = 401, description = "Not logged in"))
+)]
+async fn todos_list(State(app_state): State<AppState>, api_user: ApiUser) -> Result<Json<Vec<TodoResponse>>, ApiError> {
+	let todos =
+		list_todos(&api_user.user, api_user.tenant.id, &app_state.pool).await.map_err(|error| todo_error(error.into()))?;
+
+	Ok(Json(todos.into_iter().map(TodoResponse::from).collect()))
+}
+
+#[utoipa::path(
+	post,
+	path = "/api/v1/todos",
+	request_body = CreateTodoRequest,
+	responses(
+		(status = 201, body = TodoResponse),
+		(status = 401, description = "Not logged in"),
+		(status = 403, description = "Missing permission to create todos")
+	)
+)]
+async fn todos_create(
+	State(app_state): State<AppState>,
+	api_user: ApiUser,
+	Json(request): Json<CreateTodoRequest>,
+) -> Result<(StatusCode, Json<TodoResponse>), ApiError> {
+	let todo = create_todo(&api_user.user, api_user.tenant.id, request.title, request.project, &app_state.pool)
+		.await
+		.map_err(todo_error)?;
+
+	Ok((StatusCode::CREATED, Json(todo.into())))
+}
+
+#[utoipa::path(
+	get,
+	path = "/api/v1/todos/{id}",
+	params(("id" = i32, Path, description = "Todo id")),
+	responses(
+		(status = 200, body = TodoResponse),
+		(status = 401, description = "Not logged in"),
+		(status = 404, description = "Todo not found")
+	)
+)]
+async fn todos_get(
+	State(app_state): State<AppState>,
+	api_user: ApiUser,
+	Path(id): Path<i32>,
+) -> Result<Json<TodoResponse>, ApiError> {
+	let todo = get_todo(&api_user.user, api_user.tenant.id, id, &app_state.pool).await.map_err(todo_error)?;
+
+	Ok(Json(todo.into()))
+}
+
+#[utoipa::path(
+	patch,
+	path = "/api/v1/todos/{id}",
+	params(("id" = i32, Path, description = "Todo id")),
+	request_body = UpdateTodoRequest,
+	responses(
+		(status = 200, body = TodoResponse),
+		(status = 401, description = "Not logged in"),
+		(status = 403, description = "Missing write permission for this todo"),
+		(status = 404, description = "Todo not found"),
+		(status = 409, description = "The todo changed since the version sent")
+	)
+)]
+async fn todos_update(
+	State(app_state): State<AppState>,
+	api_user: ApiUser,
+	Path(id): Path<i32>,
+	Json(request): Json<UpdateTodoRequest>,
+) -> Result<Json<TodoResponse>, ApiError> {
+	let todo = update_todo(
+		&api_user.user,
+		api_user.tenant.id,
+		id,
+		request.title,
+		request.completed,
+		request.priority,
+		request.version,
+		&app_state.pool,
+	)
+	.await
+	.map_err(todo_error)?;
+
+	Ok(Json(todo.into()))
+}
+
+#[utoipa::path(
+	delete,
+	path = "/api/v1/todos/{id}",
+	params(("id" = i32, Path, description = "Todo id")),
+	responses(
+		(status = 204, description = "Todo deleted"),
+		(status = 401, description = "Not logged in"),
+		(status = 403, description = "Missing write permission for this todo"),
+		(status = 404, description = "Todo not found")
+	)
+)]
+async fn todos_delete(
+	State(app_state): State<AppState>,
+	api_user: ApiUser,
+	Path(id): Path<i32>,
+) -> Result<StatusCode, ApiError> {
+	// API clients get no undo token, the todo is still only removed for good once the undo window is over
+	remove_todo(&api_user.user, api_user.tenant.id, id, app_state.config.undo_window, &app_state.pool)
+		.await
+		.map_err(todo_error)?;
+
+	Ok(StatusCode::NO_CONTENT)
+}
+
+#[utoipa::path(
+	get,
+	path = "/api/v1/admin/migrations",
+	responses(
+		(status = 200, body = MigrationStatus),
+		(status = 401, description = "Not logged in"),
+		(status = 403, description = "Not an admin")
+	)
+)]
+async fn migration_status(
+	State(app_state): State<AppState>,
+	api_user: ApiUser,
+) -> Result<Json<MigrationStatus>, ApiError> {
+	if !api_user.user.is_admin() {
+		record_denial("migration_status", DeniedAction::Admin, &api_user.user);
+		return Err((StatusCode::FORBIDDEN, String::from("Missing permission to see the migration status")));
+	}
+
+	// Looking also picks up migrations applied by hand since startup so auth routes open up again
+	let status = migrations::status(&app_state.pool).await.map_err(internal_error)?;
+	app_state.schema.update(&status);
+
+	Ok(Json(status))
+}
+
+#[utoipa::path(
+	get,
+	path = "/api/v1/admin/cleanup",
+	responses(
+		(status = 200, body = CleanupMetrics, description = "Rows removed by the cleanup of this replica since it started"),
+		(status = 401, description = "Not logged in"),
+		(status = 403, description = "Not an admin")
+	)
+)]
+async fn cleanup_metrics(api_user: ApiUser) -> Result<Json<CleanupMetrics>, ApiError> {
+	if !api_user.user.is_admin() {
+		record_denial("cleanup_metrics", DeniedAction::Admin, &api_user.user);
+		return Err((StatusCode::FORBIDDEN, String::from("Missing permission to see the cleanup metrics")));
+	}
+
+	Ok(Json(cleanup::metrics()))
+}
+
+#[utoipa::path(
+	get,
+	path = "/api/v1/admin/denials",
+	responses(
+		(status = 200, body = Vec<DenialCount>, description = "Permission denials of this replica, most frequent first"),
+		(status = 401, description = "Not logged in"),
+		(status = 403, description = "Not an admin")
+	)
+)]
+async fn denial_metrics(api_user: ApiUser) -> Result<Json<Vec<DenialCount>>, ApiError> {
+	if !api_user.user.is_admin() {
+		record_denial("denial_metrics", DeniedAction::Admin, &api_user.user);
+		return Err((StatusCode::FORBIDDEN, String::from("Missing permission to see the permission denials")));
+	}
+
+	Ok(Json(denials()))
+}
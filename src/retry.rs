@@ -0,0 +1,101 @@
+//! Running reads again that failed because the database connection hiccuped
+
+use crate::breaker::ssr::breaker;
+use rand::Rng;
+use std::{future::Future, time::Duration};
+
+/// How often [`retry_read`] runs a query before the error is passed on
+const READ_ATTEMPTS: u32 = 3;
+/// The longest wait before the first retry, it doubles for each one after
+const RETRY_BACKOFF_MS: u64 = 50;
+
+/// Whether a query failed because of the connection rather than the query itself, so running it again may work
+pub fn is_transient(error: &sqlx::Error) -> bool {
+	match error {
+		sqlx::Error::Io(_) | sqlx::Error::PoolTimedOut => true,
+		// Connection exceptions and the server shutting down or not accepting connections yet
+		sqlx::Error::Database(error) => {
+			error.code().is_some_and(|code| code.starts_with("08") || matches!(code.as_ref(), "57P01" | "57P02" | "57P03"))
+		},
+		_ => false,
+	}
+}
+
+/// Run `query` again when it failed for a transient reason, so a brief database blip doesn't reach the user
+///
+/// Only for reads, a write that timed out may have gone through anyway. `name` tells the retries apart in the logs.
+/// While the [breaker](crate::breaker) is tripped the query isn't sent at all and fails like a pool timeout.
+pub async fn retry_read<T, F, Fut>(name: &'static str, mut query: F) -> Result<T, sqlx::Error>
+where
+	F: FnMut() -> Fut,
+	Fut: Future<Output = Result<T, sqlx::Error>>,
+{
+	let mut attempt = 1;
+	loop {
+		if !breaker().allows() {
+			return Err(sqlx::Error::PoolTimedOut);
+		}
+		match query().await {
+			Err(error) if is_transient(&error) => {
+				breaker().failed();
+				if attempt == READ_ATTEMPTS {
+					return Err(error);
+				}
+				let delay = backoff(attempt);
+				tracing::warn!(query = name, attempt, delay_ms = delay.as_millis() as u64, %error, "Retrying read");
+				tokio::time::sleep(delay).await;
+				attempt += 1;
+			},
+			result => {
+				breaker().succeeded();
+				return result;
+			},
+		}
+	}
+}
+
+/// Somewhere between half and all of the backoff for the attempt, so requests that failed together spread out
+fn backoff(attempt: u32) -> Duration {
+	let max = RETRY_BACKOFF_MS << (attempt - 1);
+	Duration::from_millis(rand::thread_rng().gen_range(max / 2..=max))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn only_connection_errors_are_transient() {
+		assert!(is_transient(&sqlx::Error::PoolTimedOut));
+		assert!(is_transient(&sqlx::Error::Io(std::io::ErrorKind::ConnectionReset.into())));
+		assert!(!is_transient(&sqlx::Error::RowNotFound));
+		assert!(!is_transient(&sqlx::Error::PoolClosed));
+	}
+
+	#[tokio::test]
+	async fn reads_are_retried_until_they_work() {
+		let mut calls = 0;
+		let result = retry_read("test", || {
+			calls += 1;
+			let calls = calls;
+			async move {
+				if calls < 3 {
+					Err(sqlx::Error::PoolTimedOut)
+				} else {
+					Ok(calls)
+				}
+			}
+		})
+		.await;
+		assert_eq!(result.unwrap(), 3);
+
+		let mut calls = 0;
+		let result = retry_read("test", || {
+			calls += 1;
+			async { Err::<(), _>(sqlx::Error::RowNotFound) }
+		})
+		.await;
+		assert!(result.is_err());
+		assert_eq!(calls, 1);
+	}
+}
@@ -0,0 +1,98 @@
+//! The pages of the app, the router matches them on the same patterns links and redirects are built from
+//!
+//! Renaming a page only takes changing its pattern here, nothing else spells out its path.
+
+use crate::print::PrintFilters;
+use leptos_router::ToHref;
+use std::fmt;
+
+/// A page of the app with what it needs to be linked to
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Route {
+	Home,
+	/// The list narrowed down to the todos of a project
+	Project(i32),
+	Signup,
+	Login,
+	Settings,
+	Permissions,
+	AdminUsers,
+	AdminServiceAccounts,
+	AdminFlags,
+	AdminDenials,
+	AdminDatabase,
+	AdminDebug,
+	TodoDetail(i32),
+	/// A todo shared with a link, by the token of the link
+	SharedTodo(String),
+	/// The list as a checklist for paper, narrowed down like the list was
+	Print(PrintFilters),
+}
+
+impl Route {
+	pub const HOME: &'static str = "";
+	pub const SIGNUP: &'static str = "signup";
+	pub const LOGIN: &'static str = "login";
+	pub const SETTINGS: &'static str = "settings";
+	pub const PERMISSIONS: &'static str = "settings/permissions";
+	pub const ADMIN_USERS: &'static str = "admin/users";
+	pub const ADMIN_SERVICE_ACCOUNTS: &'static str = "admin/service-accounts";
+	pub const ADMIN_FLAGS: &'static str = "admin/flags";
+	pub const ADMIN_DENIALS: &'static str = "admin/denials";
+	pub const ADMIN_DATABASE: &'static str = "admin/database";
+	pub const ADMIN_DEBUG: &'static str = "admin/debug";
+	pub const TODO_DETAIL: &'static str = "todo/:id";
+	pub const SHARED_TODO: &'static str = "share/:token";
+	pub const PRINT: &'static str = "print";
+
+	/// The path of the page from the root, to link or redirect to
+	pub fn path(&self) -> String {
+		let pattern = match self {
+			Route::Home => Route::HOME,
+			Route::Project(project) => return format!("/?project={project}"),
+			Route::Signup => Route::SIGNUP,
+			Route::Login => Route::LOGIN,
+			Route::Settings => Route::SETTINGS,
+			Route::Permissions => Route::PERMISSIONS,
+			Route::AdminUsers => Route::ADMIN_USERS,
+			Route::AdminServiceAccounts => Route::ADMIN_SERVICE_ACCOUNTS,
+			Route::AdminFlags => Route::ADMIN_FLAGS,
+			Route::AdminDenials => Route::ADMIN_DENIALS,
+			Route::AdminDatabase => Route::ADMIN_DATABASE,
+			Route::AdminDebug => Route::ADMIN_DEBUG,
+			Route::TodoDetail(id) => return format!("/{}", Route::TODO_DETAIL.replace(":id", &id.to_string())),
+			Route::SharedTodo(token) => return format!("/{}", Route::SHARED_TODO.replace(":token", token)),
+			Route::Print(filters) => return format!("/{}{}", Route::PRINT, filters.query()),
+		};
+
+		format!("/{pattern}")
+	}
+}
+
+impl fmt::Display for Route {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str(&self.path())
+	}
+}
+
+impl ToHref for Route {
+	fn to_href(&self) -> Box<dyn Fn() -> String + '_> {
+		let path = self.path();
+		Box::new(move || path.clone())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn paths_follow_the_patterns() {
+		assert_eq!(Route::Home.path(), "/");
+		assert_eq!(Route::Project(3).path(), "/?project=3");
+		assert_eq!(Route::Permissions.path(), "/settings/permissions");
+		assert_eq!(Route::TodoDetail(7).path(), "/todo/7");
+		assert_eq!(Route::SharedTodo(String::from("abc")).to_string(), "/share/abc");
+		assert_eq!(Route::Print(PrintFilters::default()).path(), "/print");
+	}
+}
@@ -0,0 +1,408 @@
+use samael::{
+	metadata::EntityDescriptor,
+	schema::Assertion,
+	service_provider::{ServiceProvider, ServiceProviderBuilder},
+	traits::ToXml,
+};
+use std::sync::OnceLock;
+
+static SAML: OnceLock<Saml> = OnceLock::new();
+
+/// Minutes the IdP has to answer an authentication request
+const REQUEST_VALIDITY_MINUTES: i32 = 10;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SamlIdentity {
+	pub username: String,
+	pub groups: Vec<String>,
+}
+
+pub struct Saml {
+	sp: ServiceProvider,
+	idp_sso_url: String,
+	username_attribute: Option<String>,
+	group_attribute: String,
+}
+
+fn read_env_file(name: &str) -> Result<String, String> {
+	let path = std::env::var(name).map_err(|_| format!("No {name} found in environment"))?;
+	std::fs::read_to_string(&path).map_err(|error| format!("Unable to read {name} ({path}): {error}"))
+}
+
+impl Saml {
+	/// Configure the service provider from the environment
+	/// - `SAML_ENTITY_ID`, `SAML_METADATA_URL`, `SAML_ACS_URL`: how this SP identifies itself to the IdP
+	/// - `SAML_IDP_METADATA_FILE`: path to the metadata XML of the IdP, it provides the signing certificates
+	/// - `SAML_SP_KEY_FILE`, `SAML_SP_CERT_FILE`: PEM encoded key pair of this SP
+	/// - `SAML_USERNAME_ATTRIBUTE`: attribute used as username, defaults to the NameID
+	/// - `SAML_GROUP_ATTRIBUTE`: attribute holding the groups which map to permission presets, defaults to `groups`
+	pub fn from_env() -> Result<Self, String> {
+		dotenvy::dotenv().ok();
+		let env = |name: &str| std::env::var(name).map_err(|_| format!("No {name} found in environment"));
+
+		let idp_metadata: EntityDescriptor =
+			read_env_file("SAML_IDP_METADATA_FILE")?.parse().map_err(|error| format!("Invalid IdP metadata: {error}"))?;
+		let idp_sso_url = idp_metadata
+			.idp_sso_descriptors
+			.iter()
+			.flatten()
+			.flat_map(|descriptor| descriptor.single_sign_on_services.iter())
+			.find(|service| service.binding == "urn:oasis:names:tc:SAML:2.0:bindings:HTTP-Redirect")
+			.map(|service| service.location.clone())
+			.ok_or("IdP metadata has no HTTP-Redirect single sign on service")?;
+
+		let key = openssl::pkey::PKey::private_key_from_pem(read_env_file("SAML_SP_KEY_FILE")?.as_bytes())
+			.map_err(|error| format!("Invalid SP key: {error}"))?;
+		let certificate = openssl::x509::X509::from_pem(read_env_file("SAML_SP_CERT_FILE")?.as_bytes())
+			.map_err(|error| format!("Invalid SP certificate: {error}"))?;
+
+		let sp = ServiceProviderBuilder::default()
+			.entity_id(env("SAML_ENTITY_ID")?)
+			.metadata_url(env("SAML_METADATA_URL")?)
+			.acs_url(env("SAML_ACS_URL")?)
+			.key(key)
+			.certificate(certificate)
+			.idp_metadata(idp_metadata)
+			.allow_idp_initiated(false)
+			.build()
+			.map_err(|error| format!("Invalid SAML configuration: {error}"))?;
+
+		Ok(Self {
+			sp,
+			idp_sso_url,
+			username_attribute: std::env::var("SAML_USERNAME_ATTRIBUTE").ok(),
+			group_attribute: std::env::var("SAML_GROUP_ATTRIBUTE").unwrap_or(String::from("groups")),
+		})
+	}
+
+	pub fn metadata_xml(&self) -> Result<String, String> {
+		self.sp.metadata().map_err(|error| error.to_string())?.to_string().map_err(|error| error.to_string())
+	}
+
+	/// Returns the id of the authentication request and the IdP url to send the browser to, the IdP posts the
+	/// `relay_state` back with its answer
+	pub fn login_redirect(&self, relay_state: &str) -> Result<(String, String), String> {
+		let request = self.sp.make_authentication_request(&self.idp_sso_url).map_err(|error| error.to_string())?;
+		let url =
+			request.redirect(relay_state).map_err(|error| error.to_string())?.ok_or("Could not build the IdP redirect")?;
+
+		Ok((request.id, url.to_string()))
+	}
+
+	/// Validate a base64 encoded SAMLResponse (signature, issuer, audience, expiry and that it answers one of our
+	/// requests) and pull the identity out of its assertion
+	pub fn parse_response(&self, response: &str, request_ids: &[&str]) -> Result<SamlIdentity, String> {
+		let assertion = self.sp.parse_base64_response(response, Some(request_ids)).map_err(|error| error.to_string())?;
+
+		self.identity(&assertion)
+	}
+
+	fn identity(&self, assertion: &Assertion) -> Result<SamlIdentity, String> {
+		let attribute_values = |name: &str| -> Vec<String> {
+			assertion
+				.attribute_statements
+				.iter()
+				.flatten()
+				.flat_map(|statement| statement.attributes.iter())
+				.filter(|attribute| attribute.name.as_deref() == Some(name) || attribute.friendly_name.as_deref() == Some(name))
+				.flat_map(|attribute| attribute.values.iter().filter_map(|value| value.value.clone()))
+				.collect()
+		};
+
+		let username = match &self.username_attribute {
+			Some(attribute) => attribute_values(attribute).into_iter().next(),
+			None => assertion.subject.as_ref().and_then(|subject| subject.name_id.as_ref()).map(|id| id.value.clone()),
+		}
+		.filter(|username| !username.is_empty())
+		.ok_or("SAML assertion has no username")?;
+
+		Ok(SamlIdentity {
+			username,
+			groups: attribute_values(&self.group_attribute),
+		})
+	}
+}
+
+pub fn init_saml() -> Result<(), String> {
+	SAML.set(Saml::from_env()?).map_err(|_| String::from("SAML has already been initialized"))
+}
+
+pub fn get_saml<'a>() -> Option<&'a Saml> {
+	SAML.get()
+}
+
+pub mod ssr {
+	use super::{get_saml, SamlIdentity, REQUEST_VALIDITY_MINUTES};
+	use crate::{
+		auth::ssr::{recently_authenticated, start_session, AuthSession, User, UserSQL, STEP_UP_MAX_AGE},
+		delegation::ssr::revoke_uncovered,
+		events::{record, DomainEvent},
+		identity::{
+			ssr::{find_identity, link_identity},
+			Provider,
+		},
+		jwt::ssr::{generate_token, hash_token},
+		permission::Permission,
+		routes::Route,
+		state::AppState,
+		tenant::Tenant,
+		username::ssr::is_reserved,
+		validation::USERNAME,
+	};
+	use axum::{
+		extract::State,
+		http::{header, StatusCode},
+		response::{IntoResponse, Redirect, Response},
+		Form,
+	};
+	use serde::Deserialize;
+	use sqlx::PgPool;
+
+	#[derive(Debug, Deserialize)]
+	pub struct AcsForm {
+		#[serde(rename = "SAMLResponse")]
+		saml_response: String,
+		#[serde(rename = "RelayState")]
+		relay_state: Option<String>,
+	}
+
+	/// An authentication request the IdP hasn't answered yet
+	#[derive(sqlx::FromRow)]
+	struct PendingRequest {
+		request_id: String,
+		/// Who links SAML to their account, `None` to log in
+		person: Option<i32>,
+	}
+
+	#[derive(sqlx::FromRow)]
+	struct PermissionPreset {
+		permission_equipment: String,
+		permission_user: String,
+		permission_todo: String,
+	}
+
+	fn not_configured() -> (StatusCode, String) {
+		(StatusCode::NOT_FOUND, String::from("SAML is not configured"))
+	}
+
+	pub async fn saml_metadata() -> Result<Response, (StatusCode, String)> {
+		let xml = get_saml()
+			.ok_or_else(not_configured)?
+			.metadata_xml()
+			.map_err(|error| (StatusCode::INTERNAL_SERVER_ERROR, error))?;
+
+		Ok(([(header::CONTENT_TYPE, "application/samlmetadata+xml")], xml).into_response())
+	}
+
+	/// Send the browser to the IdP, logged in users link the SAML identity to their account instead of logging in
+	///
+	/// The IdP posts its answer from its own site so the `SameSite=Lax` session cookie doesn't come with it. The request
+	/// is kept in the database by the RelayState the IdP posts back instead, together with who links their account,
+	/// which needs a recently confirmed password like linking a password does.
+	pub async fn saml_login(
+		State(app_state): State<AppState>,
+		auth_session: AuthSession,
+		tenant: Tenant,
+	) -> Result<Redirect, (StatusCode, String)> {
+		app_state.schema.check()?;
+		let saml = get_saml().ok_or_else(not_configured)?;
+
+		let person = auth_session.current_user.as_ref().filter(|user| user.tenant == tenant.id).map(|user| user.id);
+		if person.is_some() && !recently_authenticated(&auth_session, STEP_UP_MAX_AGE) {
+			return Err((
+				StatusCode::FORBIDDEN,
+				String::from("Confirm your password in the settings first, then link SAML again"),
+			));
+		}
+
+		let relay_state = generate_token(48);
+		let (request_id, url) =
+			saml.login_redirect(&relay_state).map_err(|error| (StatusCode::INTERNAL_SERVER_ERROR, error))?;
+		sqlx::query(
+			"INSERT INTO saml_requests (token_hash, request_id, tenant, person, expires_at)
+			VALUES ($1, $2, $3, $4, now() + make_interval(mins => $5))",
+		)
+		.bind(hash_token(&relay_state))
+		.bind(request_id)
+		.bind(tenant.id)
+		.bind(person)
+		.bind(REQUEST_VALIDITY_MINUTES)
+		.execute(&app_state.pool)
+		.await
+		.map_err(|error| (StatusCode::INTERNAL_SERVER_ERROR, error.to_string()))?;
+
+		Ok(Redirect::to(&url))
+	}
+
+	/// The request the RelayState belongs to, it's used up so an answer can't be posted twice
+	async fn take_request(relay_state: &str, tenant: i32, pool: &PgPool) -> Result<Option<PendingRequest>, sqlx::Error> {
+		sqlx::query_as::<_, PendingRequest>(
+			"DELETE FROM saml_requests WHERE token_hash = $1 AND tenant = $2 AND expires_at > now()
+			RETURNING request_id, person",
+		)
+		.bind(hash_token(relay_state))
+		.bind(tenant)
+		.fetch_optional(pool)
+		.await
+	}
+
+	/// The first preset matching one of the IdP groups wins, users with no matching group are not let in
+	async fn find_preset(tenant: i32, groups: &[String], pool: &PgPool) -> Result<Option<PermissionPreset>, sqlx::Error> {
+		sqlx::query_as::<_, PermissionPreset>(
+			"SELECT permission_equipment, permission_user, permission_todo FROM saml_permission_presets
+			WHERE tenant = $1 AND idp_group = ANY($2) ORDER BY priority LIMIT 1",
+		)
+		.bind(tenant)
+		.bind(groups)
+		.fetch_optional(pool)
+		.await
+	}
+
+	/// The user linked to the SAML identity, a new one when nobody is linked yet
+	async fn upsert_user(
+		identity: &SamlIdentity,
+		tenant: i32,
+		preset: PermissionPreset,
+		pool: &PgPool,
+	) -> Result<User, sqlx::Error> {
+		// Permissions are owned by the IdP so they are refreshed on every login
+		if let Some(person) = find_identity(tenant, Provider::Saml, &identity.username, pool).await? {
+			let mut transaction = pool.begin().await?;
+			let user = sqlx::query_as::<_, UserSQL>(
+				"UPDATE users SET permission_equipment = $2, permission_user = $3, permission_todo = $4
+				WHERE id = $1 RETURNING *",
+			)
+			.bind(person)
+			.bind(preset.permission_equipment)
+			.bind(preset.permission_user)
+			.bind(preset.permission_todo)
+			.fetch_one(&mut *transaction)
+			.await?;
+			record(
+				&mut *transaction,
+				&DomainEvent::PermissionsChanged {
+					tenant,
+					user: None,
+					person,
+				},
+			)
+			.await?;
+			transaction.commit().await?;
+			revoke_uncovered(user.id, pool).await?;
+
+			return Ok(user.into());
+		}
+
+		// SAML users never log in with a password so they get one nobody knows
+		let password = {
+			use crate::auth::ssr::{Argon2, OsRng, PasswordHasher, SaltString};
+			use rand::{distributions::Alphanumeric, Rng};
+
+			let random: String = rand::thread_rng().sample_iter(&Alphanumeric).take(64).map(char::from).collect();
+			Argon2::default()
+				.hash_password(random.as_bytes(), &SaltString::generate(&mut OsRng))
+				.map_err(|error| sqlx::Error::Protocol(error.to_string()))?
+				.to_string()
+		};
+
+		// The IdP decides the name but it's held to the same rules as any other, the identity stays linked by the original
+		let username = USERNAME.clean(&identity.username).map_err(|error| sqlx::Error::Protocol(error.to_string()))?;
+		if is_reserved(pool, tenant, &username, None).await? {
+			return Err(sqlx::Error::Protocol(format!("The username {username} is reserved for whoever gave it up")));
+		}
+		// A local user with the same name isn't taken over, they have to link SAML from their settings
+		let mut transaction = pool.begin().await?;
+		let user = sqlx::query_as::<_, UserSQL>(
+			"INSERT INTO users (tenant, username, password, permission_equipment, permission_user, permission_todo)
+			VALUES ($1, $2, $3, $4, $5, $6)
+			RETURNING *",
+		)
+		.bind(tenant)
+		.bind(username)
+		.bind(password)
+		.bind(preset.permission_equipment)
+		.bind(preset.permission_user)
+		.bind(preset.permission_todo)
+		.fetch_one(&mut *transaction)
+		.await?;
+		link_identity(&mut *transaction, tenant, user.id, Provider::Saml, &identity.username).await?;
+		record(
+			&mut *transaction,
+			&DomainEvent::PermissionsChanged {
+				tenant,
+				user: None,
+				person: user.id,
+			},
+		)
+		.await?;
+		transaction.commit().await?;
+
+		Ok(user.into())
+	}
+
+	pub async fn saml_acs(
+		State(app_state): State<AppState>,
+		auth_session: AuthSession,
+		tenant: Tenant,
+		Form(form): Form<AcsForm>,
+	) -> Result<Redirect, (StatusCode, String)> {
+		app_state.schema.check()?;
+		let saml = get_saml().ok_or_else(not_configured)?;
+
+		let expired = || (StatusCode::UNAUTHORIZED, String::from("The SAML login expired, please start it again"));
+		let request = take_request(form.relay_state.as_deref().ok_or_else(expired)?, tenant.id, &app_state.pool)
+			.await
+			.map_err(|error| (StatusCode::INTERNAL_SERVER_ERROR, error.to_string()))?
+			.ok_or_else(expired)?;
+
+		let identity = saml
+			.parse_response(&form.saml_response, &[request.request_id.as_str()])
+			.map_err(|error| (StatusCode::UNAUTHORIZED, format!("Invalid SAML response: {error}")))?;
+
+		// The password was confirmed when the link was started in `saml_login`
+		if let Some(person) = request.person {
+			link_identity(&app_state.pool, tenant.id, person, Provider::Saml, &identity.username).await.map_err(|error| {
+				match error {
+					sqlx::Error::Database(error) if error.is_unique_violation() => {
+						(StatusCode::CONFLICT, String::from("This SAML identity is already linked to another user"))
+					},
+					error => (StatusCode::INTERNAL_SERVER_ERROR, error.to_string()),
+				}
+			})?;
+			return Ok(Redirect::to(&Route::Settings.path()));
+		}
+
+		let preset = find_preset(tenant.id, &identity.groups, &app_state.pool)
+			.await
+			.map_err(|error| (StatusCode::INTERNAL_SERVER_ERROR, error.to_string()))?
+			.ok_or((StatusCode::FORBIDDEN, String::from("No permission preset matches your groups")))?;
+
+		// Checking the permissions of a user with a broken permission string panics so we catch it here
+		for permission in [
+			&preset.permission_equipment,
+			&preset.permission_user,
+			&preset.permission_todo,
+		] {
+			Permission::parse(permission.clone()).map_err(|error| (StatusCode::INTERNAL_SERVER_ERROR, error.to_string()))?;
+		}
+
+		let user = upsert_user(&identity, tenant.id, preset, &app_state.pool).await.map_err(|error| match error {
+			sqlx::Error::Database(error) if error.is_unique_violation() => (
+				StatusCode::CONFLICT,
+				String::from("A user with this name already exists, log in and link SAML from the settings"),
+			),
+			error => (StatusCode::INTERNAL_SERVER_ERROR, error.to_string()),
+		})?;
+		if !user.active {
+			return Err((StatusCode::FORBIDDEN, String::from("Your account is deactivated")));
+		}
+
+		// The permissions may have changed so the cached user is stale
+		auth_session.cache_clear_user(user.id);
+		start_session(&auth_session, user.id, false, &app_state.config, &app_state.pool)
+			.await
+			.map_err(|error| (StatusCode::INTERNAL_SERVER_ERROR, error.to_string()))?;
+
+		Ok(Redirect::to(&Route::Home.path()))
+	}
+}
@@ -0,0 +1,320 @@
+use crate::{
+	auth::use_current_user,
+	errors::AppServerError,
+	permission::{Permission, Permissions, Scope},
+	routes,
+};
+use leptos::*;
+use leptos_router::*;
+use serde::{Deserialize, Serialize};
+
+/// A scope with the name of what it points to, `None` when it points to nothing the user may see
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "ssr", derive(utoipa::ToSchema))]
+pub struct ResolvedScope {
+	pub scope: Scope,
+	pub name: Option<String>,
+}
+
+/// The permissions of a resource explained in sentences, see [`Permissions::explain`]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "ssr", derive(utoipa::ToSchema))]
+pub struct PermissionExplanation {
+	/// `todos`, `users` or `equipment`
+	pub resource: String,
+	pub sentences: Vec<String>,
+}
+
+#[cfg(feature = "ssr")]
+pub mod ssr {
+	use crate::{
+		auth::User,
+		permission::{Permission, Permissions, Scope},
+	};
+	use sqlx::PgPool;
+	use std::collections::HashMap;
+
+	/// The names of what scopes point to, in the order they were given
+	///
+	/// There is no equipment table, equipment ids match todo ids in permission queries so they are named after the todo.
+	/// Admins can resolve anything in their tenant, everyone else only the scopes of their own permissions.
+	pub async fn scope_names(
+		user: &User,
+		tenant: i32,
+		scopes: &[Scope],
+		pool: &PgPool,
+	) -> Result<Vec<Option<String>>, sqlx::Error> {
+		let own = [
+			user.permission_equipment(),
+			user.permission_user(),
+			user.permission_todo(),
+		]
+		.into_iter()
+		.flat_map(|Permissions::ReadWrite { read, write, .. }| [read, write])
+		.flat_map(|permission| match permission {
+			Permission::Read(scopes) | Permission::Write(scopes) => scopes.clone(),
+			_ => Vec::new(),
+		})
+		.collect::<Vec<_>>();
+		let visible = |scope: &Scope| user.is_admin() || own.contains(scope);
+		let ids = |matches: fn(&Scope) -> Option<i32>| {
+			scopes.iter().filter(|scope| visible(scope)).filter_map(matches).collect::<Vec<_>>()
+		};
+		let todo_ids = ids(|scope| match scope {
+			Scope::Equipment(id) => Some(*id),
+			_ => None,
+		});
+		let person_ids = ids(|scope| match scope {
+			Scope::Person(id) => Some(*id),
+			_ => None,
+		});
+		let project_ids = ids(|scope| match scope {
+			Scope::Project(id) => Some(*id),
+			_ => None,
+		});
+
+		// One query per kind no matter how many scopes are asked for
+		let todos = sqlx::query_as::<_, (i32, String)>("SELECT id, title FROM todos WHERE tenant = $1 AND id = ANY($2)")
+			.bind(tenant)
+			.bind(&todo_ids)
+			.fetch_all(pool)
+			.await?
+			.into_iter()
+			.collect::<HashMap<_, _>>();
+		let people =
+			sqlx::query_as::<_, (i32, String)>("SELECT id, username FROM users WHERE tenant = $1 AND id = ANY($2)")
+				.bind(tenant)
+				.bind(&person_ids)
+				.fetch_all(pool)
+				.await?
+				.into_iter()
+				.collect::<HashMap<_, _>>();
+		let projects =
+			sqlx::query_as::<_, (i32, String)>("SELECT id, name FROM projects WHERE tenant = $1 AND id = ANY($2)")
+				.bind(tenant)
+				.bind(&project_ids)
+				.fetch_all(pool)
+				.await?
+				.into_iter()
+				.collect::<HashMap<_, _>>();
+
+		Ok(
+			scopes
+				.iter()
+				.map(|scope| match scope {
+					Scope::Equipment(id) => todos.get(id).cloned(),
+					Scope::Person(id) => people.get(id).cloned(),
+					Scope::Project(id) => projects.get(id).cloned(),
+					Scope::Any => Some(String::from("anything")),
+				})
+				.collect(),
+		)
+	}
+}
+
+/// Name the todos, users and projects scopes point to, in the order they were given, see [`ssr::scope_names`]
+///
+/// Scopes don't fit in a query string so this read is sent as JSON.
+#[server(input = Json)]
+pub async fn resolve_scopes(scopes: Vec<Scope>) -> Result<Vec<ResolvedScope>, ServerFnError<AppServerError>> {
+	use self::ssr::scope_names;
+	use crate::{auth::get_user, tenant::Tenant};
+	use sqlx::PgPool;
+
+	let pool = use_context::<PgPool>().expect("Database not initialized");
+	let tenant = use_context::<Tenant>().expect("No tenant found");
+	let user = get_user().await?.ok_or_else(AppServerError::unauthenticated)?;
+
+	let names = scope_names(&user, tenant.id, &scopes, &pool).await.map_err(AppServerError::from)?;
+
+	Ok(scopes.into_iter().zip(names).map(|(scope, name)| ResolvedScope { scope, name }).collect())
+}
+
+/// What the logged in user can and can't do with todos, users and equipment, in sentences
+#[server(input = GetUrl)]
+pub async fn explain_permissions() -> Result<Vec<PermissionExplanation>, ServerFnError<AppServerError>> {
+	use self::ssr::scope_names;
+	use crate::{auth::get_user, authorize::policies, cache::cache_privately, tenant::Tenant};
+	use sqlx::PgPool;
+
+	cache_privately::<ExplainPermissions>(0);
+
+	let pool = use_context::<PgPool>().expect("Database not initialized");
+	let tenant = use_context::<Tenant>().expect("No tenant found");
+	let user = get_user().await?.ok_or_else(AppServerError::unauthenticated)?;
+
+	let resources = [
+		("todos", user.permission_todo()),
+		("users", user.permission_user()),
+		("equipment", user.permission_equipment()),
+	];
+	let scopes = resources
+		.iter()
+		.flat_map(|(_, Permissions::ReadWrite { read, write, .. })| [read, write])
+		.flat_map(|permission| match permission {
+			Permission::Read(scopes) | Permission::Write(scopes) => scopes.clone(),
+			_ => Vec::new(),
+		})
+		.collect::<Vec<_>>();
+	let names = scope_names(&user, tenant.id, &scopes, &pool).await.map_err(AppServerError::from)?;
+	let named = scopes.into_iter().zip(names).collect::<Vec<_>>();
+	let label = |scope: &Scope| {
+		scope_label(*scope, named.iter().find(|(named, _)| named == scope).and_then(|(_, name)| name.clone()))
+	};
+
+	Ok(
+		resources
+			.into_iter()
+			.map(|(resource, permissions)| PermissionExplanation {
+				resource: resource.to_string(),
+				// Todos are up to the policies with `AUTHORIZATION=policy`, the permission string isn't looked at
+				sentences: if resource == "todos" && policies().is_some() {
+					vec![String::from(
+						"What you can do with todos is decided by the rules of this deployment",
+					)]
+				} else {
+					permissions.explain(resource, &label)
+				},
+			})
+			.collect(),
+	)
+}
+
+fn scope_kind(scope: Scope) -> (&'static str, Option<i32>) {
+	match scope {
+		Scope::Equipment(id) => ("todo", Some(id)),
+		Scope::Person(id) => ("user", Some(id)),
+		Scope::Project(id) => ("project", Some(id)),
+		Scope::Any => ("any", None),
+	}
+}
+
+/// How a scope is shown, with the name of what it points to when that is known
+pub fn scope_label(scope: Scope, name: Option<String>) -> String {
+	match (name, scope_kind(scope)) {
+		(Some(name), (_, Some(id))) => format!("{name} (#{id})"),
+		(Some(name), (_, None)) => name,
+		(None, (kind, Some(id))) => format!("{kind} #{id}"),
+		(None, (_, None)) => String::from("anything"),
+	}
+}
+
+#[component]
+pub fn ScopeBadge(scope: Scope, #[prop(optional_no_strip)] name: Option<String>) -> impl IntoView {
+	let (kind, _) = scope_kind(scope);
+
+	view! { <span class=format!("scope-badge scope-{kind}")>{scope_label(scope, name)}</span> }
+}
+
+/// Badges for a list of scopes, named with a single [`resolve_scopes`] call
+#[component]
+pub fn ScopeList(scopes: Vec<Scope>) -> impl IntoView {
+	if scopes.is_empty() {
+		return view! { <span>"nothing"</span> }.into_view();
+	}
+
+	let unresolved = scopes.clone();
+	let resolved = create_resource(move || scopes.clone(), resolve_scopes);
+
+	view! {
+		<Transition fallback=move || {
+			unresolved.iter().map(|scope| view! { <ScopeBadge scope=*scope /> }).collect_view()
+		}>
+			{move || {
+				resolved
+					.get()
+					.map(|resolved| match resolved {
+						Err(e) => view! { <span class="error">{e.to_string()}</span> }.into_view(),
+						Ok(resolved) => {
+							resolved
+								.into_iter()
+								.map(|resolved| {
+									view! { <ScopeBadge scope=resolved.scope name=resolved.name /> }
+								})
+								.collect_view()
+						}
+					})
+			}}
+
+		</Transition>
+	}
+	.into_view()
+}
+
+fn permission_view(permission: Permission) -> View {
+	match permission {
+		Permission::ReadAny | Permission::WriteAny => view! { <ScopeBadge scope=Scope::Any /> }.into_view(),
+		Permission::Read(scopes) | Permission::Write(scopes) => view! { <ScopeList scopes=scopes /> }.into_view(),
+		Permission::Create(create) => view! { <span>{if create { "yes" } else { "no" }}</span> }.into_view(),
+	}
+}
+
+/// What a set of permissions allows, with every scope named
+#[component]
+pub fn PermissionSummary(permissions: Permissions) -> impl IntoView {
+	let Permissions::ReadWrite { read, write, create } = permissions;
+
+	view! {
+		<span class="permission-summary">
+			"Read: " {permission_view(read)} " write: " {permission_view(write)} " create: " {permission_view(create)}
+		</span>
+	}
+}
+
+/// The permissions of the logged in user
+#[component]
+pub fn MyPermissions() -> impl IntoView {
+	let user = use_current_user();
+
+	view! {
+		<h2>"Your permissions"</h2>
+		<A href=routes::Route::Permissions>"What your permissions mean"</A>
+		{move || {
+			user.get()
+				.map(|user| {
+					view! {
+						<p>"Todos: " <PermissionSummary permissions=user.permission_todo().clone() /></p>
+						<p>"Users: " <PermissionSummary permissions=user.permission_user().clone() /></p>
+						<p>"Equipment: " <PermissionSummary permissions=user.permission_equipment().clone() /></p>
+					}
+				})
+		}}
+	}
+}
+
+/// Everything the logged in user can and can't do, in words instead of scopes
+#[component]
+pub fn PermissionsPage() -> impl IntoView {
+	let explanations = create_resource(|| (), |_| explain_permissions());
+
+	view! {
+		<h1>"Your permissions"</h1>
+		<A href=routes::Route::Settings>"Back to the settings"</A>
+		<Transition fallback=move || view! { <p>"Loading..."</p> }>
+			{move || {
+				explanations
+					.get()
+					.map(|explanations| match explanations {
+						Err(e) => view! { <p class="error">{e.to_string()}</p> }.into_view(),
+						Ok(explanations) => {
+							explanations
+								.into_iter()
+								.map(|explanation| {
+									view! {
+										<h2 class="permission-resource">{explanation.resource}</h2>
+										<ul>
+											{explanation
+												.sentences
+												.into_iter()
+												.map(|sentence| view! { <li>{sentence}</li> })
+												.collect_view()}
+										</ul>
+									}
+								})
+								.collect_view()
+						}
+					})
+			}}
+		</Transition>
+	}
+}
@@ -0,0 +1,523 @@
+//! Service accounts, users for automation that call the REST and GraphQL APIs and the server functions
+//!
+//! They have permission strings like any user but can't log in, they authenticate with API tokens only, see
+//! [`crate::api_token`]. Every token can be narrowed down to less than the account may do. Service accounts are left
+//! out of the user lists of the app, admins manage them on their own page.
+
+use crate::{
+	auth::ConfirmPassword,
+	errors::{AppError, AppServerError},
+	toast::{use_toasts, Toasted},
+	user_admin::{PermissionEditor, SetUserActive, SetUserPermissions},
+};
+use chrono::prelude::*;
+use leptos::*;
+use serde::{Deserialize, Serialize};
+
+/// A token as admins see it, the token itself is only shown when it's created or rotated
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "ssr", derive(sqlx::FromRow, utoipa::ToSchema))]
+pub struct ApiToken {
+	pub id: i32,
+	pub person: i32,
+	pub name: String,
+	/// What the token is narrowed down to, `None` for the permission of the account
+	pub permission_equipment: Option<String>,
+	pub permission_user: Option<String>,
+	pub permission_todo: Option<String>,
+	pub created_at: DateTime<Utc>,
+	pub last_used_at: Option<DateTime<Utc>>,
+}
+
+impl ApiToken {
+	/// The scopes the token is narrowed down to for the token list, `None` when it has all the account has
+	pub fn scopes(&self) -> Option<String> {
+		let scopes = [
+			("Equipment", &self.permission_equipment),
+			("Users", &self.permission_user),
+			("Todos", &self.permission_todo),
+		]
+		.into_iter()
+		.filter_map(|(label, scope)| scope.as_ref().map(|scope| format!("{label}: {scope}")))
+		.collect::<Vec<_>>();
+
+		(!scopes.is_empty()).then(|| scopes.join(", "))
+	}
+}
+
+/// A service account of the tenant with the permission strings as stored
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "ssr", derive(utoipa::ToSchema))]
+pub struct ServiceAccount {
+	pub id: i32,
+	pub username: String,
+	pub permission_equipment: String,
+	pub permission_user: String,
+	pub permission_todo: String,
+	pub active: bool,
+	pub tokens: Vec<ApiToken>,
+}
+
+/// Every service account of the tenant with its tokens, admins only
+#[server(input = GetUrl)]
+pub async fn get_service_accounts() -> Result<Vec<ServiceAccount>, ServerFnError<AppServerError>> {
+	use crate::{
+		auth::get_user,
+		cache::cache_privately,
+		denial::{ssr::record_denial, DeniedAction},
+		tenant::Tenant,
+	};
+	use sqlx::PgPool;
+
+	cache_privately::<GetServiceAccounts>(0);
+
+	let pool = use_context::<PgPool>().expect("Database not initialized");
+	let tenant = use_context::<Tenant>().expect("No tenant found");
+	let user = get_user().await?.ok_or_else(AppServerError::unauthenticated)?;
+	if !user.is_admin() {
+		record_denial("get_service_accounts", DeniedAction::Admin, &user);
+		return Err(AppServerError::forbidden("Missing permission to manage users").into());
+	}
+
+	let accounts = sqlx::query_as::<_, (i32, String, String, String, String, bool)>(
+		"SELECT id, username, permission_equipment, permission_user, permission_todo, active FROM users
+		WHERE tenant = $1 AND service_account ORDER BY username",
+	)
+	.bind(tenant.id)
+	.fetch_all(&pool)
+	.await
+	.map_err(AppServerError::from)?;
+	let tokens = sqlx::query_as::<_, ApiToken>(
+		"SELECT id, person, name, permission_equipment, permission_user, permission_todo, created_at, last_used_at
+		FROM api_tokens WHERE tenant = $1 ORDER BY created_at",
+	)
+	.bind(tenant.id)
+	.fetch_all(&pool)
+	.await
+	.map_err(AppServerError::from)?;
+
+	Ok(
+		accounts
+			.into_iter()
+			.map(|(id, username, permission_equipment, permission_user, permission_todo, active)| ServiceAccount {
+				id,
+				username,
+				permission_equipment,
+				permission_user,
+				permission_todo,
+				active,
+				tokens: tokens.iter().filter(|token| token.person == id).cloned().collect(),
+			})
+			.collect(),
+	)
+}
+
+/// Create a service account of the tenant with its permission strings, it has no tokens yet
+#[server]
+pub async fn create_service_account(
+	username: String,
+	permission_equipment: String,
+	permission_user: String,
+	permission_todo: String,
+) -> Result<Toasted<()>, ServerFnError<AppServerError>> {
+	use crate::{
+		auth::{
+			get_user,
+			ssr::{require_recent_auth, STEP_UP_MAX_AGE},
+		},
+		denial::{ssr::record_denial, DeniedAction},
+		events::{record, DomainEvent},
+		tenant::Tenant,
+		user_admin::ssr::parse_permission,
+		username::ssr::is_reserved,
+		validation::USERNAME,
+	};
+	use sqlx::PgPool;
+
+	let pool = use_context::<PgPool>().expect("Database not initialized");
+	let tenant = use_context::<Tenant>().expect("No tenant found");
+	let user = get_user().await?.ok_or_else(AppServerError::unauthenticated)?;
+	if !user.is_admin() {
+		record_denial("create_service_account", DeniedAction::Admin, &user);
+		return Err(AppServerError::forbidden("Missing permission to manage users").into());
+	}
+	require_recent_auth(STEP_UP_MAX_AGE)?;
+
+	let username = USERNAME.clean(&username).map_err(AppServerError::invalid)?;
+	parse_permission("Equipment", &permission_equipment)?;
+	parse_permission("Users", &permission_user)?;
+	parse_permission("Todos", &permission_todo)?;
+	if is_reserved(&pool, tenant.id, &username, None).await.map_err(AppServerError::from)? {
+		return Err(AppServerError::conflict("There is a user with that name already").into());
+	}
+
+	// The empty password isn't a hash, nothing could ever match it even if service accounts could log in
+	let id = sqlx::query_scalar::<_, i32>(
+		"INSERT INTO users
+		(tenant, username, password, permission_equipment, permission_user, permission_todo, service_account)
+		VALUES ($1, $2, '', $3, $4, $5, true)
+		RETURNING id",
+	)
+	.bind(tenant.id)
+	.bind(username)
+	.bind(permission_equipment)
+	.bind(permission_user)
+	.bind(permission_todo)
+	.fetch_one(&pool)
+	.await
+	.map_err(|error| match error {
+		sqlx::Error::Database(error) if error.is_unique_violation() => {
+			AppServerError::conflict("There is a user with that name already")
+		},
+		error => error.into(),
+	})?;
+	record(
+		&pool,
+		&DomainEvent::PermissionsChanged {
+			tenant: tenant.id,
+			user: Some(user.id),
+			person: id,
+		},
+	)
+	.await
+	.map_err(AppServerError::from)?;
+
+	Ok(Toasted::success((), "Service account created"))
+}
+
+/// Create a token for a service account of the tenant and return it, it isn't shown again
+///
+/// The permission strings narrow the token down, it can never do more than the account whatever they allow. Leave
+/// them empty for the permissions of the account.
+#[server]
+pub async fn create_api_token(
+	person: i32,
+	name: String,
+	permission_equipment: Option<String>,
+	permission_user: Option<String>,
+	permission_todo: Option<String>,
+) -> Result<String, ServerFnError<AppServerError>> {
+	use crate::{
+		api_token,
+		auth::{
+			get_user,
+			ssr::{require_recent_auth, STEP_UP_MAX_AGE},
+		},
+		denial::{ssr::record_denial, DeniedAction},
+		tenant::Tenant,
+		user_admin::ssr::parse_permission,
+	};
+	use sqlx::PgPool;
+
+	let pool = use_context::<PgPool>().expect("Database not initialized");
+	let tenant = use_context::<Tenant>().expect("No tenant found");
+	let user = get_user().await?.ok_or_else(AppServerError::unauthenticated)?;
+	if !user.is_admin() {
+		record_denial("create_api_token", DeniedAction::Admin, &user);
+		return Err(AppServerError::forbidden("Missing permission to manage users").into());
+	}
+	require_recent_auth(STEP_UP_MAX_AGE)?;
+
+	let name = name.trim();
+	if name.is_empty() {
+		return Err(AppServerError::invalid("The token needs a name").into());
+	}
+	// Forms send empty fields along
+	fn scope(permission: &Option<String>) -> Option<&str> {
+		permission.as_deref().map(str::trim).filter(|scope| !scope.is_empty())
+	}
+	let (permission_equipment, permission_user, permission_todo) =
+		(scope(&permission_equipment), scope(&permission_user), scope(&permission_todo));
+	for (name, permission) in [
+		("Equipment", permission_equipment),
+		("Users", permission_user),
+		("Todos", permission_todo),
+	] {
+		if let Some(permission) = permission {
+			parse_permission(name, permission)?;
+		}
+	}
+	let service_account = sqlx::query_scalar::<_, bool>(
+		"SELECT EXISTS (SELECT 1 FROM users WHERE id = $1 AND tenant = $2 AND service_account)",
+	)
+	.bind(person)
+	.bind(tenant.id)
+	.fetch_one(&pool)
+	.await
+	.map_err(AppServerError::from)?;
+	// Tokens of regular users would get around their login
+	if !service_account {
+		return Err(AppServerError::not_found("Service account not found").into());
+	}
+
+	Ok(
+		api_token::issue(&pool, tenant.id, person, name, permission_equipment, permission_user, permission_todo)
+			.await
+			.map_err(AppServerError::from)?,
+	)
+}
+
+/// Replace a token of a service account with a new one and return it, whatever still uses the old one is refused
+#[server]
+pub async fn rotate_api_token(id: i32) -> Result<String, ServerFnError<AppServerError>> {
+	use crate::{
+		api_token,
+		auth::{
+			get_user,
+			ssr::{require_recent_auth, STEP_UP_MAX_AGE},
+		},
+		denial::{ssr::record_denial, DeniedAction},
+		tenant::Tenant,
+	};
+	use sqlx::PgPool;
+
+	let pool = use_context::<PgPool>().expect("Database not initialized");
+	let tenant = use_context::<Tenant>().expect("No tenant found");
+	let user = get_user().await?.ok_or_else(AppServerError::unauthenticated)?;
+	if !user.is_admin() {
+		record_denial("rotate_api_token", DeniedAction::Admin, &user);
+		return Err(AppServerError::forbidden("Missing permission to manage users").into());
+	}
+	require_recent_auth(STEP_UP_MAX_AGE)?;
+
+	Ok(
+		api_token::rotate(id, tenant.id, &pool)
+			.await
+			.map_err(AppServerError::from)?
+			.ok_or_else(|| AppServerError::not_found("Token not found"))?,
+	)
+}
+
+/// Delete a token of a service account so it stops working
+#[server]
+pub async fn revoke_api_token(id: i32) -> Result<Toasted<()>, ServerFnError<AppServerError>> {
+	use crate::{
+		auth::{
+			get_user,
+			ssr::{require_recent_auth, STEP_UP_MAX_AGE},
+		},
+		denial::{ssr::record_denial, DeniedAction},
+		tenant::Tenant,
+	};
+	use sqlx::PgPool;
+
+	let pool = use_context::<PgPool>().expect("Database not initialized");
+	let tenant = use_context::<Tenant>().expect("No tenant found");
+	let user = get_user().await?.ok_or_else(AppServerError::unauthenticated)?;
+	if !user.is_admin() {
+		record_denial("revoke_api_token", DeniedAction::Admin, &user);
+		return Err(AppServerError::forbidden("Missing permission to manage users").into());
+	}
+	require_recent_auth(STEP_UP_MAX_AGE)?;
+
+	let deleted = sqlx::query("DELETE FROM api_tokens WHERE id = $1 AND tenant = $2")
+		.bind(id)
+		.bind(tenant.id)
+		.execute(&pool)
+		.await
+		.map_err(AppServerError::from)?
+		.rows_affected();
+	if deleted == 0 {
+		return Err(AppServerError::not_found("Token not found").into());
+	}
+
+	Ok(Toasted::success((), "Token revoked"))
+}
+
+/// The token that was just created or rotated, for the admin to copy
+#[component]
+fn IssuedToken(#[prop(into)] token: Signal<Option<String>>) -> impl IntoView {
+	move || {
+		token.get().map(|token| {
+			view! {
+				<p>
+					"Copy the token now, it's only shown once: "
+					<code>{token}</code>
+				</p>
+			}
+		})
+	}
+}
+
+#[component]
+fn ServiceAccountForm(
+	account: ServiceAccount,
+	save: Action<SetUserPermissions, Result<(), AppError>>,
+	set_active: Action<SetUserActive, Result<Toasted<()>, AppError>>,
+	create_token: Action<CreateApiToken, Result<String, AppError>>,
+	rotate: Action<RotateApiToken, Result<String, AppError>>,
+	revoke: Action<RevokeApiToken, Result<Toasted<()>, AppError>>,
+) -> impl IntoView {
+	let equipment = create_rw_signal(account.permission_equipment);
+	let person = create_rw_signal(account.permission_user);
+	let todo = create_rw_signal(account.permission_todo);
+	let (equipment_valid, person_valid, todo_valid) =
+		(create_rw_signal(true), create_rw_signal(true), create_rw_signal(true));
+
+	view! {
+		<div class="managed-user" class:inactive=!account.active>
+			<ActionForm action=save>
+				<h2>{account.username} {(!account.active).then_some(" (deactivated)")}</h2>
+				<input type="hidden" name="id" value=account.id />
+				<PermissionEditor name="permission_equipment" label="Equipment" value=equipment valid=equipment_valid />
+				<PermissionEditor name="permission_user" label="Users" value=person valid=person_valid />
+				<PermissionEditor name="permission_todo" label="Todos" value=todo valid=todo_valid />
+				<input
+					type="submit"
+					value="Save"
+					disabled=move || !(equipment_valid.get() && person_valid.get() && todo_valid.get())
+				/>
+			</ActionForm>
+			<table class="api-tokens">
+				<tr>
+					<th>"Token"</th>
+					<th>"Scopes"</th>
+					<th>"Created"</th>
+					<th>"Last used"</th>
+					<th></th>
+				</tr>
+				{account
+					.tokens
+					.into_iter()
+					.map(|token| {
+						view! {
+							<tr>
+								<td>{token.name.clone()}</td>
+								<td>{token.scopes().unwrap_or_else(|| String::from("Everything the account may do"))}</td>
+								<td>{token.created_at.format("%Y-%m-%d %H:%M").to_string()}</td>
+								<td>
+									{token
+										.last_used_at
+										.map_or(String::from("Never"), |used| used.format("%Y-%m-%d %H:%M").to_string())}
+								</td>
+								<td>
+									<ActionForm action=rotate>
+										<input type="hidden" name="id" value=token.id />
+										<input type="submit" value="Rotate" />
+									</ActionForm>
+									<ActionForm action=revoke>
+										<input type="hidden" name="id" value=token.id />
+										<input type="submit" value="Revoke" />
+									</ActionForm>
+								</td>
+							</tr>
+						}
+					})
+					.collect_view()}
+			</table>
+			<ActionForm action=create_token>
+				<input type="hidden" name="person" value=account.id />
+				<label>"Token name " <input type="text" name="name" required=true /></label>
+				<p>"Narrow the token down with permission strings, leave them empty for what the account may do."</p>
+				<label>"Equipment " <input type="text" name="permission_equipment" /></label>
+				<label>"Users " <input type="text" name="permission_user" /></label>
+				<label>"Todos " <input type="text" name="permission_todo" /></label>
+				<input type="submit" value="Create token" />
+			</ActionForm>
+			<ActionForm action=set_active>
+				<input type="hidden" name="id" value=account.id />
+				<input type="hidden" name="active" value=(!account.active).to_string() />
+				<input type="submit" value=if account.active { "Deactivate" } else { "Reactivate" } />
+			</ActionForm>
+		</div>
+	}
+}
+
+#[component]
+fn NewServiceAccountForm(create: Action<CreateServiceAccount, Result<Toasted<()>, AppError>>) -> impl IntoView {
+	let equipment = create_rw_signal(String::new());
+	let person = create_rw_signal(String::new());
+	let todo = create_rw_signal(String::new());
+	let (equipment_valid, person_valid, todo_valid) =
+		(create_rw_signal(false), create_rw_signal(false), create_rw_signal(false));
+
+	view! {
+		<ActionForm action=create>
+			<h2>"New service account"</h2>
+			<label>"Username " <input type="text" name="username" required=true /></label>
+			<PermissionEditor name="permission_equipment" label="Equipment" value=equipment valid=equipment_valid />
+			<PermissionEditor name="permission_user" label="Users" value=person valid=person_valid />
+			<PermissionEditor name="permission_todo" label="Todos" value=todo valid=todo_valid />
+			<input
+				type="submit"
+				value="Create"
+				disabled=move || !(equipment_valid.get() && person_valid.get() && todo_valid.get())
+			/>
+		</ActionForm>
+	}
+}
+
+#[component]
+pub fn ServiceAccountAdmin() -> impl IntoView {
+	let create = create_server_action::<CreateServiceAccount>();
+	let save = create_server_action::<SetUserPermissions>();
+	let set_active = create_server_action::<SetUserActive>();
+	let create_token = create_server_action::<CreateApiToken>();
+	let rotate = create_server_action::<RotateApiToken>();
+	let revoke = create_server_action::<RevokeApiToken>();
+	use_toasts().follow(create);
+	use_toasts().follow(set_active);
+	use_toasts().follow(revoke);
+	let accounts = create_resource(
+		move || {
+			(
+				create.version().get(),
+				save.version().get(),
+				set_active.version().get(),
+				create_token.version().get(),
+				rotate.version().get(),
+				revoke.version().get(),
+			)
+		},
+		move |_| get_service_accounts(),
+	);
+	let error = Signal::derive(move || {
+		create
+			.value()
+			.get()
+			.and_then(Result::err)
+			.or_else(|| save.value().get().and_then(Result::err))
+			.or_else(|| set_active.value().get().and_then(Result::err))
+			.or_else(|| create_token.value().get().and_then(Result::err))
+			.or_else(|| rotate.value().get().and_then(Result::err))
+			.or_else(|| revoke.value().get().and_then(Result::err))
+	});
+
+	view! {
+		<h1>"Service accounts"</h1>
+		<p>"Service accounts can't log in, automation authenticates as them with a token in the Authorization header."</p>
+		<IssuedToken token=move || create_token.value().get().and_then(Result::ok) />
+		<IssuedToken token=move || rotate.value().get().and_then(Result::ok) />
+		<Transition fallback=move || view! { <p>"Loading..."</p> }>
+			{move || {
+				accounts
+					.get()
+					.map(|accounts| match accounts {
+						Err(e) => view! { <span class="error">{e.to_string()}</span> }.into_view(),
+						Ok(accounts) if accounts.is_empty() => view! { <p>"There are no service accounts yet."</p> }.into_view(),
+						Ok(accounts) => {
+							accounts
+								.into_iter()
+								.map(|account| {
+									view! {
+										<ServiceAccountForm
+											account=account
+											save=save
+											set_active=set_active
+											create_token=create_token
+											rotate=rotate
+											revoke=revoke
+										/>
+									}
+								})
+								.collect_view()
+						}
+					})
+			}}
+
+		</Transition>
+		{move || error.get().map(|e| view! { <span class="error">{e.to_string()}</span> })}
+		<NewServiceAccountForm create=create />
+		<ConfirmPassword error=error />
+	}
+}
@@ -0,0 +1,353 @@
+use crate::{
+	auth::PublicUser,
+	errors::AppServerError,
+	toast::{use_toasts, Toasted},
+	todo::Priority,
+};
+use chrono::prelude::*;
+use leptos::*;
+use leptos_router::*;
+use serde::{Deserialize, Serialize};
+
+/// Longest a share link can be valid for, in days
+pub const MAX_SHARE_DAYS: i32 = 90;
+/// How long a share link is valid for unless asked otherwise, in days
+pub const DEFAULT_SHARE_DAYS: i32 = 7;
+
+/// An active share link, the token itself is only ever shown when the link is created
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "ssr", derive(utoipa::ToSchema))]
+pub struct ShareLink {
+	pub id: i32,
+	pub todo: i32,
+	/// Who created the link
+	pub user: Option<PublicUser>,
+	pub created_at: DateTime<Utc>,
+	pub expires_at: DateTime<Utc>,
+}
+
+/// What people opening a share link get to see, nothing about the users of the tenant
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "ssr", derive(utoipa::ToSchema))]
+pub struct SharedTodo {
+	pub title: String,
+	pub completed: bool,
+	pub priority: Priority,
+	pub created_at: DateTime<Utc>,
+	pub due_at: Option<DateTime<Utc>>,
+	/// When the link stops working
+	pub expires_at: DateTime<Utc>,
+}
+
+#[cfg(feature = "ssr")]
+pub mod ssr {
+	use super::ShareLink;
+	use crate::auth::PublicUser;
+	use chrono::prelude::*;
+	use sqlx::PgPool;
+
+	#[derive(sqlx::FromRow, Clone)]
+	pub struct SqlShareLink {
+		pub id: i32,
+		pub todo: i32,
+		pub person: i32,
+		pub created_at: DateTime<Utc>,
+		pub expires_at: DateTime<Utc>,
+	}
+
+	impl SqlShareLink {
+		pub async fn into_share_link(self, pool: &PgPool) -> ShareLink {
+			ShareLink {
+				id: self.id,
+				todo: self.todo,
+				user: PublicUser::get_from_id(self.person, pool).await,
+				created_at: self.created_at,
+				expires_at: self.expires_at,
+			}
+		}
+	}
+}
+
+/// The links of a todo that still work, anyone who can read the todo can see who shared it
+#[server(input = GetUrl)]
+pub async fn get_share_links(todo_id: i32) -> Result<Vec<ShareLink>, ServerFnError<AppServerError>> {
+	use self::ssr::SqlShareLink;
+	use crate::{
+		auth::get_user, authorize::Action, cache::cache_privately, tenant::Tenant, todo::ssr::get_todo_with_permission,
+	};
+	use futures::future::join_all;
+	use sqlx::PgPool;
+
+	cache_privately::<GetShareLinks>(0);
+
+	let pool = use_context::<PgPool>().expect("Database not initialized");
+	let tenant = use_context::<Tenant>().expect("No tenant found");
+	let user = get_user().await?.ok_or_else(AppServerError::unauthenticated)?;
+
+	if get_todo_with_permission(todo_id, tenant.id, &user, Action::Read, &pool).await.is_none() {
+		return Err(AppServerError::not_found("Todo not found").into());
+	}
+
+	let links = sqlx::query_as::<_, SqlShareLink>(
+		"SELECT id, todo, person, created_at, expires_at FROM share_links
+		WHERE todo = $1 AND expires_at > now() ORDER BY created_at",
+	)
+	.bind(todo_id)
+	.fetch_all(&pool)
+	.await
+	.map_err(AppServerError::from)?;
+
+	Ok(join_all(links.into_iter().map(|link| link.into_share_link(&pool))).await)
+}
+
+/// Create a read only link to a todo that works without an account, returns the link
+///
+/// The token is random and only its hash is stored, so a link can't be guessed and a leaked database doesn't leak
+/// working links. Sharing lets people outside the tenant read the todo so it takes write access.
+#[server]
+pub async fn create_share_link(todo_id: i32, days: i32) -> Result<String, ServerFnError<AppServerError>> {
+	use crate::{
+		auth::get_user,
+		authorize::Action,
+		config::Config,
+		denial::{ssr::record_denial, DeniedAction},
+		jwt::ssr::{generate_token, hash_token},
+		routes::Route,
+		tenant::Tenant,
+		todo::ssr::get_todo_with_permission,
+	};
+	use sqlx::PgPool;
+
+	let pool = use_context::<PgPool>().expect("Database not initialized");
+	let tenant = use_context::<Tenant>().expect("No tenant found");
+	let config = use_context::<Config>().expect("No config found");
+	let user = get_user().await?.ok_or_else(AppServerError::unauthenticated)?;
+
+	if !(1..=MAX_SHARE_DAYS).contains(&days) {
+		return Err(AppServerError::invalid(format!("A share link can be valid for 1 to {MAX_SHARE_DAYS} days")).into());
+	}
+
+	if get_todo_with_permission(todo_id, tenant.id, &user, Action::Read, &pool).await.is_none() {
+		return Err(AppServerError::not_found("Todo not found").into());
+	}
+	if get_todo_with_permission(todo_id, tenant.id, &user, Action::Write, &pool).await.is_none() {
+		record_denial("create_share_link", DeniedAction::Write, &user);
+		return Err(AppServerError::forbidden("Missing permission to share this todo").into());
+	}
+
+	let token = generate_token(48);
+	sqlx::query(
+		"INSERT INTO share_links (todo, person, token_hash, expires_at)
+		VALUES ($1, $2, $3, now() + make_interval(days => $4))",
+	)
+	.bind(todo_id)
+	.bind(user.id)
+	.bind(hash_token(&token))
+	.bind(days)
+	.execute(&pool)
+	.await
+	.map_err(AppServerError::from)?;
+
+	Ok(format!("{}{}", config.public_url, Route::SharedTodo(token)))
+}
+
+/// Stop a share link from working, takes write access to the todo like creating one
+#[server]
+pub async fn revoke_share_link(id: i32) -> Result<Toasted<()>, ServerFnError<AppServerError>> {
+	use crate::{
+		auth::get_user,
+		authorize::Action,
+		denial::{ssr::record_denial, DeniedAction},
+		tenant::Tenant,
+		todo::ssr::get_todo_with_permission,
+	};
+	use sqlx::PgPool;
+
+	let pool = use_context::<PgPool>().expect("Database not initialized");
+	let tenant = use_context::<Tenant>().expect("No tenant found");
+	let user = get_user().await?.ok_or_else(AppServerError::unauthenticated)?;
+
+	let not_found = || AppServerError::not_found("Share link not found");
+	let todo_id = sqlx::query_scalar::<_, i32>("SELECT todo FROM share_links WHERE id = $1")
+		.bind(id)
+		.fetch_optional(&pool)
+		.await
+		.map_err(AppServerError::from)?
+		.ok_or_else(not_found)?;
+
+	// Links of todos the user can't even read are reported as missing so their ids can't be probed
+	if get_todo_with_permission(todo_id, tenant.id, &user, Action::Read, &pool).await.is_none() {
+		return Err(not_found().into());
+	}
+	if get_todo_with_permission(todo_id, tenant.id, &user, Action::Write, &pool).await.is_none() {
+		record_denial("revoke_share_link", DeniedAction::Write, &user);
+		return Err(AppServerError::forbidden("Missing permission to revoke this share link").into());
+	}
+
+	sqlx::query("DELETE FROM share_links WHERE id = $1").bind(id).execute(&pool).await.map_err(AppServerError::from)?;
+
+	Ok(Toasted::success((), "Share link revoked, it stopped working"))
+}
+
+/// The todo a share link points to, works without logging in
+#[server(input = GetUrl)]
+pub async fn get_shared_todo(token: String) -> Result<SharedTodo, ServerFnError<AppServerError>> {
+	use crate::{cache::cache_privately, jwt::ssr::hash_token, tenant::Tenant};
+	use sqlx::PgPool;
+
+	// Revoking a link has to take effect right away, so nothing may keep the response around
+	cache_privately::<GetSharedTodo>(0);
+
+	let pool = use_context::<PgPool>().expect("Database not initialized");
+	let tenant = use_context::<Tenant>().expect("No tenant found");
+
+	let (title, completed, priority, created_at, due_at, expires_at) =
+		sqlx::query_as::<_, (String, bool, i16, DateTime<Utc>, Option<DateTime<Utc>>, DateTime<Utc>)>(
+			"SELECT todos.title, todos.completed, todos.priority, todos.created_at, todos.due_at, share_links.expires_at
+			FROM share_links JOIN todos ON todos.id = share_links.todo
+			WHERE share_links.token_hash = $1 AND share_links.expires_at > now() AND todos.tenant = $2
+			AND todos.deleted_at IS NULL",
+		)
+		.bind(hash_token(&token))
+		.bind(tenant.id)
+		.fetch_optional(&pool)
+		.await
+		.map_err(AppServerError::from)?
+		.ok_or_else(|| AppServerError::not_found("Unknown, expired or revoked share link"))?;
+
+	Ok(SharedTodo {
+		title,
+		completed,
+		priority: priority.into(),
+		created_at,
+		due_at,
+		expires_at,
+	})
+}
+
+/// A closed list of the share links of a todo, the links are only loaded once it's opened
+#[component]
+pub fn ShareLinks(todo_id: i32) -> impl IntoView {
+	let (open, set_open) = create_signal(false);
+
+	view! {
+		<div class="share-links">
+			<button type="button" on:click=move |_| set_open.update(|open| *open = !*open)>
+				{move || if open.get() { "Hide sharing" } else { "Share" }}
+			</button>
+			<Show when=move || open.get()>
+				<ShareLinkList todo_id=todo_id />
+			</Show>
+		</div>
+	}
+}
+
+#[component]
+fn ShareLinkList(todo_id: i32) -> impl IntoView {
+	let create = create_server_action::<CreateShareLink>();
+	let revoke = create_server_action::<RevokeShareLink>();
+	use_toasts().follow(revoke);
+	let links =
+		create_resource(move || (create.version().get(), revoke.version().get()), move |_| get_share_links(todo_id));
+
+	view! {
+		<aside class="share-link-list">
+			<Transition fallback=move || view! { <p>"Loading..."</p> }>
+				{move || {
+					links
+						.get()
+						.map(|links| match links {
+							Err(e) => view! { <span class="error">{e.to_string()}</span> }.into_view(),
+							Ok(links) if links.is_empty() => view! { <p>"Not shared."</p> }.into_view(),
+							Ok(links) => {
+								view! {
+									<ul>
+										{links
+											.into_iter()
+											.map(|link| {
+												let user = link.user.unwrap_or_default();
+												view! {
+													<li>
+														{format!(
+															"Shared by {} on {}, works until {}",
+															user.username,
+															link.created_at,
+															link.expires_at,
+														)}
+														<ActionForm action=revoke>
+															<input type="hidden" name="id" value=link.id />
+															<input type="submit" value="Revoke" />
+														</ActionForm>
+													</li>
+												}
+											})
+											.collect_view()}
+									</ul>
+								}
+									.into_view()
+							}
+						})
+				}}
+
+			</Transition>
+			<ActionForm action=create>
+				<input type="hidden" name="todo_id" value=todo_id />
+				<label>
+					"Valid for " <input type="number" name="days" value=DEFAULT_SHARE_DAYS min=1 max=MAX_SHARE_DAYS /> " days"
+				</label>
+				<input type="submit" value="Create link" />
+			</ActionForm>
+			{move || {
+				create
+					.value()
+					.get()
+					.map(|result| match result {
+						Ok(url) => {
+							view! {
+								<p>
+									"Anyone with this link can read the todo, it's only shown once: "
+									<a href=url.clone()>{url}</a>
+								</p>
+							}
+								.into_view()
+						}
+						Err(e) => view! { <span class="error">{e.to_string()}</span> }.into_view(),
+					})
+			}}
+		</aside>
+	}
+}
+
+/// The page a share link opens, rendered for anyone holding the link
+#[component]
+pub fn SharedTodoPage() -> impl IntoView {
+	let params = use_params_map();
+	let todo =
+		create_resource(move || params.with(|params| params.get("token").cloned().unwrap_or_default()), get_shared_todo);
+
+	view! {
+		<Transition fallback=move || view! { <p>"Loading..."</p> }>
+			{move || {
+				todo.get()
+					.map(|todo| match todo {
+						Err(e) => view! { <span class="error">{e.to_string()}</span> }.into_view(),
+						Ok(todo) => {
+							view! {
+								<h1>{todo.title}</h1>
+								<p>
+									{if todo.completed { "Done" } else { "Open" }} ", priority "
+									{todo.priority.as_str()} ", created at " {todo.created_at.to_string()}
+									{todo.due_at.map(|due_at| format!(", due {due_at}"))}
+								</p>
+								<p>
+									<small>{format!("Shared read only until {}", todo.expires_at)}</small>
+								</p>
+							}
+								.into_view()
+						}
+					})
+			}}
+
+		</Transition>
+	}
+}
@@ -0,0 +1,347 @@
+//! What the server started with, logged once on boot and shown to admins on the debug page
+//!
+//! The report is put together before the server listens, so it shows what the running process uses rather than what
+//! the environment says by now. Secrets only show whether they are set and passwords in urls are starred out. With
+//! several tenants the setup of the process is only shown to admins of the default tenant, who run the deployment.
+
+use crate::errors::AppServerError;
+use chrono::prelude::*;
+use leptos::*;
+use serde::{Deserialize, Serialize};
+
+/// A setting by the variable it's read from
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "ssr", derive(utoipa::ToSchema))]
+pub struct Setting {
+	pub name: String,
+	pub value: String,
+}
+
+/// A feature flag that was on for some users of a tenant at startup
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "ssr", derive(utoipa::ToSchema))]
+pub struct EnabledFlag {
+	/// The slug of the tenant
+	pub tenant: String,
+	pub name: String,
+	pub rollout: i16,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "ssr", derive(utoipa::ToSchema))]
+pub struct StartupReport {
+	pub version: String,
+	pub started_at: DateTime<Utc>,
+	/// The latest migration applied, `None` when there is none
+	pub migration_version: Option<i64>,
+	/// Migrations that aren't applied yet or failed, auth routes are refused until there are none
+	pub migrations_behind: Vec<i64>,
+	pub feature_flags: Vec<EnabledFlag>,
+	/// What every tenant shares, `None` for admins of tenants that don't run the deployment
+	pub server: Option<ServerSetup>,
+}
+
+/// How the process is set up, the same for every tenant
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "ssr", derive(utoipa::ToSchema))]
+pub struct ServerSetup {
+	pub listening_on: String,
+	/// The cargo features the server was built with
+	pub features: Vec<String>,
+	/// Every setting, secrets only say whether they are set
+	pub config: Vec<Setting>,
+	pub session_table: String,
+	/// How many connections the pools open at most
+	pub pool_size: u32,
+	pub session_pool_size: u32,
+	/// How many authorization policies are loaded, `None` when permission strings are used
+	pub policies: Option<usize>,
+}
+
+#[cfg(feature = "ssr")]
+pub mod ssr {
+	use super::{EnabledFlag, ServerSetup, Setting, StartupReport};
+	use crate::{
+		authorize,
+		config::{Config, TenantMode},
+		migrations,
+		secrets::Secrets,
+	};
+	use chrono::prelude::*;
+	use sqlx::PgPool;
+	use std::net::SocketAddr;
+
+	/// The features of the build, `ssr` is always there on the server
+	const FEATURES: &[(&str, bool)] = &[
+		("s3", cfg!(feature = "s3")),
+		("saml", cfg!(feature = "saml")),
+		("graphql", cfg!(feature = "graphql")),
+		("redis", cfg!(feature = "redis")),
+		("docker", cfg!(feature = "docker")),
+	];
+
+	impl StartupReport {
+		/// Looking up the migrations or flags failing leaves them out of the report, it doesn't stop the server
+		pub async fn build(
+			config: &Config,
+			secrets: &Secrets,
+			listening_on: SocketAddr,
+			session_table: &str,
+			pool: &PgPool,
+			session_pool: &PgPool,
+		) -> Self {
+			let settings = config.summary().into_iter();
+			let secrets =
+				secrets.summary().into_iter().map(|(name, set)| (name, String::from(if set { "set" } else { "not set" })));
+			let migrations = migrations::status(pool).await.unwrap_or_else(|error| {
+				log::error!("Reading the migration status for the startup report failed: {error}");
+				migrations::MigrationStatus {
+					current: None,
+					latest: None,
+					pending: Vec::new(),
+					failed: Vec::new(),
+				}
+			});
+			let feature_flags = sqlx::query_as::<_, (String, String, i16)>(
+				"SELECT tenants.slug, feature_flags.name, feature_flags.rollout
+				FROM feature_flags JOIN tenants ON tenants.id = feature_flags.tenant
+				WHERE feature_flags.enabled AND feature_flags.rollout > 0
+				ORDER BY tenants.slug, feature_flags.name",
+			)
+			.fetch_all(pool)
+			.await
+			.unwrap_or_else(|error| {
+				log::error!("Reading the feature flags for the startup report failed: {error}");
+				Vec::new()
+			});
+
+			Self {
+				version: env!("CARGO_PKG_VERSION").to_string(),
+				started_at: Utc::now(),
+				migration_version: migrations.current,
+				migrations_behind: migrations.pending.into_iter().chain(migrations.failed).collect(),
+				feature_flags: feature_flags
+					.into_iter()
+					.map(|(tenant, name, rollout)| EnabledFlag { tenant, name, rollout })
+					.collect(),
+				server: Some(ServerSetup {
+					listening_on: format!("http://{listening_on}"),
+					features: FEATURES.iter().filter(|(_, enabled)| *enabled).map(|(name, _)| name.to_string()).collect(),
+					config: settings
+						.chain(secrets)
+						.map(|(name, value)| Setting {
+							name: name.to_string(),
+							value,
+						})
+						.collect(),
+					session_table: session_table.to_string(),
+					pool_size: pool.options().get_max_connections(),
+					session_pool_size: session_pool.options().get_max_connections(),
+					policies: authorize::policies().map(|policies| policies.len()),
+				}),
+			}
+		}
+
+		/// What an admin of `tenant` gets to see, only the default tenant runs the deployment when there are several
+		pub fn for_tenant(&self, tenant: &str, config: &Config) -> Self {
+			let mut report = self.clone();
+			// The flags of other tenants are none of this admin's business
+			report.feature_flags.retain(|flag| flag.tenant == tenant);
+			if config.tenant_mode != TenantMode::Single && tenant != config.default_tenant {
+				report.server = None;
+			}
+
+			report
+		}
+
+		/// One JSON line under the `startup` target, so log pipelines can pick it up like the audit log
+		pub fn log(&self) {
+			match serde_json::to_string(self) {
+				Ok(report) => log::info!(target: "startup", "{report}"),
+				Err(error) => log::error!("Unable to serialize the startup report: {error}"),
+			}
+		}
+	}
+}
+
+/// What the server started with, with the feature flags of the current tenant only, admins only
+#[server(input = GetUrl)]
+pub async fn get_startup_report() -> Result<StartupReport, ServerFnError<AppServerError>> {
+	use crate::{
+		auth::get_user,
+		cache::cache_privately,
+		config::Config,
+		denial::{ssr::record_denial, DeniedAction},
+		tenant::Tenant,
+	};
+	use std::sync::Arc;
+
+	cache_privately::<GetStartupReport>(0);
+
+	let tenant = use_context::<Tenant>().expect("No tenant found");
+	let config = use_context::<Config>().expect("No config found");
+	let report = use_context::<Arc<StartupReport>>().expect("No startup report found");
+	let user = get_user().await?.ok_or_else(AppServerError::unauthenticated)?;
+	if !user.is_admin() {
+		record_denial("get_startup_report", DeniedAction::Admin, &user);
+		return Err(AppServerError::forbidden("Missing permission to see how the server is set up").into());
+	}
+
+	Ok(report.for_tenant(&tenant.slug, &config))
+}
+
+#[component]
+pub fn StartupReportPage() -> impl IntoView {
+	let report = create_resource(|| (), |_| get_startup_report());
+
+	view! {
+		<h1>"Debug"</h1>
+		<Transition fallback=move || view! { <p>"Loading..."</p> }>
+			{move || {
+				report
+					.get()
+					.map(|report| match report {
+						Err(e) => view! { <span class="error">{e.to_string()}</span> }.into_view(),
+						Ok(report) => {
+							let server = report.server.clone();
+							view! {
+								<p>
+									{format!(
+										"Version {} started at {}",
+										report.version,
+										report.started_at.format("%Y-%m-%d %H:%M:%S"),
+									)}
+									{server.as_ref().map(|server| format!(" listening on {}", server.listening_on))}
+								</p>
+								<table class="startup-report">
+									<tr>
+										<th>"Migration"</th>
+										<td>
+											{report.migration_version.map_or(String::from("none applied"), |version| version.to_string())}
+											{(!report.migrations_behind.is_empty())
+												.then(|| {
+													format!(
+														", behind by {}",
+														report.migrations_behind.iter().map(ToString::to_string).collect::<Vec<_>>().join(", "),
+													)
+												})}
+										</td>
+									</tr>
+									<tr>
+										<th>"Feature flags"</th>
+										<td>
+											{if report.feature_flags.is_empty() {
+												String::from("none")
+											} else {
+												report
+													.feature_flags
+													.iter()
+													.map(|flag| format!("{} ({}%)", flag.name, flag.rollout))
+													.collect::<Vec<_>>()
+													.join(", ")
+											}}
+										</td>
+									</tr>
+								</table>
+								{match server {
+									Some(server) => view! { <ServerSetupTables server=server /> }.into_view(),
+									None => {
+										view! { <p>"How the server is set up is only shown to whoever runs the deployment."</p> }
+											.into_view()
+									},
+								}}
+							}
+								.into_view()
+						}
+					})
+			}}
+
+		</Transition>
+	}
+}
+
+#[component]
+fn ServerSetupTables(server: ServerSetup) -> impl IntoView {
+	view! {
+		<table class="startup-report">
+			<tr>
+				<th>"Features"</th>
+				<td>{if server.features.is_empty() { String::from("none") } else { server.features.join(", ") }}</td>
+			</tr>
+			<tr>
+				<th>"Session table"</th>
+				<td>{server.session_table}</td>
+			</tr>
+			<tr>
+				<th>"Pool size"</th>
+				<td>{format!("{} connections, {} for sessions", server.pool_size, server.session_pool_size)}</td>
+			</tr>
+			<tr>
+				<th>"Authorization"</th>
+				<td>
+					{server.policies.map_or(String::from("permission strings"), |policies| format!("{policies} policies"))}
+				</td>
+			</tr>
+		</table>
+		<h2>"Config"</h2>
+		<table class="startup-report">
+			{server
+				.config
+				.into_iter()
+				.map(|setting| {
+					view! {
+						<tr>
+							<th>
+								<code>{setting.name}</code>
+							</th>
+							<td>{setting.value}</td>
+						</tr>
+					}
+				})
+				.collect_view()}
+		</table>
+	}
+}
+
+#[cfg(all(test, feature = "ssr"))]
+mod tests {
+	use super::*;
+	use crate::config::{Config, TenantMode};
+
+	#[test]
+	fn for_tenant_test() {
+		let flag = |tenant: &str| EnabledFlag {
+			tenant: tenant.to_string(),
+			name: String::from("beta"),
+			rollout: 50,
+		};
+		let report = StartupReport {
+			version: String::from("1.0.0"),
+			started_at: Utc::now(),
+			migration_version: Some(1),
+			migrations_behind: Vec::new(),
+			feature_flags: vec![flag("default"), flag("acme")],
+			server: Some(ServerSetup {
+				listening_on: String::from("http://127.0.0.1:3000"),
+				features: Vec::new(),
+				config: Vec::new(),
+				session_table: String::from("axum_sessions"),
+				pool_size: 10,
+				session_pool_size: 5,
+				policies: None,
+			}),
+		};
+		let single = Config::default();
+		let subdomain = Config {
+			tenant_mode: TenantMode::Subdomain,
+			..Config::default()
+		};
+
+		assert_eq!(report.for_tenant("default", &single).feature_flags, vec![flag("default")]);
+		assert!(report.for_tenant("default", &single).server.is_some());
+		assert!(report.for_tenant("default", &subdomain).server.is_some());
+		let acme = report.for_tenant("acme", &subdomain);
+		assert_eq!(acme.feature_flags, vec![flag("acme")]);
+		assert_eq!(acme.server, None);
+	}
+}
@@ -1,11 +1,31 @@
+use crate::{
+	auth_backend::AuthBackend, breach::PasswordBreachCheck, config::Config, events::EventBus, jwt::ssr::JwtKeys,
+	mailer::Mailer, migrations::SchemaGuard, pwa::ssr::Pwa, rate_limit::RateLimiter, startup::StartupReport,
+	storage::Storage,
+};
 use axum::extract::FromRef;
 use leptos::LeptosOptions;
 use leptos_router::RouteListing;
 use sqlx::PgPool;
+use std::sync::Arc;
 
 #[derive(FromRef, Debug, Clone)]
 pub struct AppState {
 	pub leptos_options: LeptosOptions,
 	pub routes: Vec<RouteListing>,
 	pub pool: PgPool,
+	/// Where sessions are kept, can be a different schema or database than `pool`
+	pub session_pool: PgPool,
+	pub storage: Arc<dyn Storage>,
+	pub auth_backend: Arc<dyn AuthBackend>,
+	pub config: Config,
+	pub jwt: Option<JwtKeys>,
+	pub events: EventBus,
+	pub rate_limiter: Arc<dyn RateLimiter>,
+	pub mailer: Arc<dyn Mailer>,
+	pub schema: SchemaGuard,
+	pub breach_check: PasswordBreachCheck,
+	pub pwa: Pwa,
+	/// What the server started with, for the debug page
+	pub startup: Arc<StartupReport>,
 }
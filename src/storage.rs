@@ -0,0 +1,142 @@
+use async_trait::async_trait;
+use axum::body::Bytes;
+use std::{fmt::Debug, path::PathBuf, sync::Arc};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum StorageError {
+	#[error("File not found")]
+	NotFound,
+	#[error("Invalid storage key")]
+	InvalidKey,
+	#[error("Storage error: {0}")]
+	Backend(String),
+}
+
+#[async_trait]
+pub trait Storage: Debug + Send + Sync {
+	async fn put(&self, key: &str, data: Bytes) -> Result<(), StorageError>;
+	async fn get(&self, key: &str) -> Result<Bytes, StorageError>;
+	async fn delete(&self, key: &str) -> Result<(), StorageError>;
+}
+
+/// Create a random key to store an uploaded file under so user supplied file names never touch the storage backend
+pub fn generate_key() -> String {
+	use rand::{distributions::Alphanumeric, Rng};
+
+	rand::thread_rng().sample_iter(&Alphanumeric).take(32).map(char::from).collect()
+}
+
+/// Pick the storage backend from the environment
+/// - `STORAGE_BACKEND`: `local` (default) or `s3`
+/// - `STORAGE_PATH`: folder for the local backend, defaults to `./uploads`
+/// - `S3_BUCKET`: bucket for the s3 backend, credentials are read from the usual `AWS_*` variables
+pub fn from_env() -> Arc<dyn Storage> {
+	match std::env::var("STORAGE_BACKEND").unwrap_or_default().as_str() {
+		#[cfg(feature = "s3")]
+		"s3" => {
+			let bucket = std::env::var("S3_BUCKET").expect("No S3_BUCKET found in environment");
+			Arc::new(S3Storage::new(&bucket).expect("Unable to configure S3 storage"))
+		},
+		#[cfg(not(feature = "s3"))]
+		"s3" => panic!("STORAGE_BACKEND is set to s3 but the s3 feature is not enabled"),
+		_ => Arc::new(LocalStorage::new(std::env::var("STORAGE_PATH").unwrap_or(String::from("./uploads")))),
+	}
+}
+
+#[derive(Debug, Clone)]
+pub struct LocalStorage {
+	root: PathBuf,
+}
+
+impl LocalStorage {
+	pub fn new(root: impl Into<PathBuf>) -> Self {
+		Self { root: root.into() }
+	}
+
+	fn path(&self, key: &str) -> Result<PathBuf, StorageError> {
+		// Keys are generated by us but let's make sure nothing can escape the root folder
+		if key.is_empty() || !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+			return Err(StorageError::InvalidKey);
+		}
+
+		Ok(self.root.join(key))
+	}
+}
+
+#[async_trait]
+impl Storage for LocalStorage {
+	async fn put(&self, key: &str, data: Bytes) -> Result<(), StorageError> {
+		let path = self.path(key)?;
+		tokio::fs::create_dir_all(&self.root).await.map_err(|error| StorageError::Backend(error.to_string()))?;
+		tokio::fs::write(path, data).await.map_err(|error| StorageError::Backend(error.to_string()))
+	}
+
+	async fn get(&self, key: &str) -> Result<Bytes, StorageError> {
+		match tokio::fs::read(self.path(key)?).await {
+			Ok(data) => Ok(data.into()),
+			Err(error) if error.kind() == std::io::ErrorKind::NotFound => Err(StorageError::NotFound),
+			Err(error) => Err(StorageError::Backend(error.to_string())),
+		}
+	}
+
+	async fn delete(&self, key: &str) -> Result<(), StorageError> {
+		match tokio::fs::remove_file(self.path(key)?).await {
+			Ok(_) => Ok(()),
+			Err(error) if error.kind() == std::io::ErrorKind::NotFound => Ok(()),
+			Err(error) => Err(StorageError::Backend(error.to_string())),
+		}
+	}
+}
+
+#[cfg(feature = "s3")]
+#[derive(Debug)]
+pub struct S3Storage {
+	store: object_store::aws::AmazonS3,
+}
+
+#[cfg(feature = "s3")]
+impl S3Storage {
+	pub fn new(bucket: &str) -> Result<Self, StorageError> {
+		let store = object_store::aws::AmazonS3Builder::from_env()
+			.with_bucket_name(bucket)
+			.build()
+			.map_err(|error| StorageError::Backend(error.to_string()))?;
+
+		Ok(Self { store })
+	}
+}
+
+#[cfg(feature = "s3")]
+#[async_trait]
+impl Storage for S3Storage {
+	async fn put(&self, key: &str, data: Bytes) -> Result<(), StorageError> {
+		use object_store::ObjectStore;
+
+		self
+			.store
+			.put(&object_store::path::Path::from(key), data.into())
+			.await
+			.map(|_| ())
+			.map_err(|error| StorageError::Backend(error.to_string()))
+	}
+
+	async fn get(&self, key: &str) -> Result<Bytes, StorageError> {
+		use object_store::ObjectStore;
+
+		match self.store.get(&object_store::path::Path::from(key)).await {
+			Ok(result) => result.bytes().await.map_err(|error| StorageError::Backend(error.to_string())),
+			Err(object_store::Error::NotFound { .. }) => Err(StorageError::NotFound),
+			Err(error) => Err(StorageError::Backend(error.to_string())),
+		}
+	}
+
+	async fn delete(&self, key: &str) -> Result<(), StorageError> {
+		use object_store::ObjectStore;
+
+		match self.store.delete(&object_store::path::Path::from(key)).await {
+			Ok(_) | Err(object_store::Error::NotFound { .. }) => Ok(()),
+			Err(error) => Err(StorageError::Backend(error.to_string())),
+		}
+	}
+}
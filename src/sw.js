@@ -0,0 +1,194 @@
+// Queues the server function calls listed in the `queue` query parameter while offline and replays them in order once
+// the network is back, `edit` lists the ones answering with an `EditResult`. The page is told about every change of the
+// queue with a `{"offline_queue": ...}` message.
+//
+// Changes belong to the user who made them, the page says who is logged in with a `{"offline_user": id}` message and
+// `{"offline_user": null}` once nobody is. Logging out drops the queue and changes of another user are never replayed.
+//
+// The server prepends `PRECACHE`, the paths of the built `/pkg` assets, and `VERSION`, a hash of their contents. They
+// are cached on install and served from the cache from then on, a new build changes the version and with it the worker.
+// `QUEUED_EDIT` is the `EditResult` of an edit that was saved, serialized by the server so it can't drift from it.
+
+const QUEUED = new URL(self.location).searchParams.getAll("queue");
+const EDITS = new URL(self.location).searchParams.getAll("edit");
+const DB_NAME = "offline";
+const STORE = "mutations";
+const META = "meta";
+
+const ASSETS = `assets-${VERSION}`;
+
+self.addEventListener("install", (event) => {
+	event.waitUntil(
+		caches
+			.open(ASSETS)
+			.then((cache) => cache.addAll(PRECACHE))
+			.then(() => self.skipWaiting()),
+	);
+});
+
+// The assets of older builds are never asked for again
+self.addEventListener("activate", (event) => {
+	event.waitUntil(
+		caches
+			.keys()
+			.then((keys) => Promise.all(keys.filter((key) => key !== ASSETS).map((key) => caches.delete(key))))
+			.then(() => self.clients.claim()),
+	);
+});
+
+function openDb() {
+	return new Promise((resolve, reject) => {
+		const request = indexedDB.open(DB_NAME, 2);
+		request.onupgradeneeded = (event) => {
+			// Changes queued before they were keyed by user can't be told apart, so they don't survive the upgrade
+			if (event.oldVersion >= 1) {
+				request.result.deleteObjectStore(STORE);
+			}
+			request.result.createObjectStore(STORE, { keyPath: "id", autoIncrement: true });
+			request.result.createObjectStore(META);
+		};
+		request.onsuccess = () => resolve(request.result);
+		request.onerror = () => reject(request.error);
+	});
+}
+
+async function withStore(mode, run, name = STORE) {
+	const db = await openDb();
+	return new Promise((resolve, reject) => {
+		const transaction = db.transaction(name, mode);
+		const result = run(transaction.objectStore(name));
+		transaction.oncomplete = () => resolve(result.result);
+		transaction.onerror = () => reject(transaction.error);
+	});
+}
+
+const enqueue = (mutation) => withStore("readwrite", (store) => store.add(mutation));
+const queued = () => withStore("readonly", (store) => store.getAll());
+const dequeue = (id) => withStore("readwrite", (store) => store.delete(id));
+const clearQueue = () => withStore("readwrite", (store) => store.clear());
+// Kept in the database since the browser stops idle workers and a background sync may start it without a page
+const currentUser = async () => (await withStore("readonly", (store) => store.get("user"), META)) ?? null;
+const setUser = (user) => withStore("readwrite", (store) => store.put(user, "user"), META);
+
+async function report(queueReport) {
+	const message = JSON.stringify({ offline_queue: queueReport });
+	for (const client of await self.clients.matchAll({ type: "window" })) {
+		client.postMessage(message);
+	}
+}
+
+// What the server function would have answered, the real answer comes in the report once it's replayed
+function queuedResponse(path) {
+	const body = EDITS.includes(path) ? JSON.stringify(QUEUED_EDIT) : "null";
+	return new Response(body, { headers: { "Content-Type": "application/json", "X-Offline-Queued": "true" } });
+}
+
+self.addEventListener("fetch", (event) => {
+	const url = new URL(event.request.url);
+	if (event.request.method === "GET" && url.origin === self.location.origin && PRECACHE.includes(url.pathname)) {
+		event.respondWith(caches.match(url.pathname).then((cached) => cached ?? fetch(event.request)));
+		return;
+	}
+	if (event.request.method !== "POST" || url.origin !== self.location.origin || !QUEUED.includes(url.pathname)) {
+		return;
+	}
+
+	const copy = event.request.clone();
+	event.respondWith(
+		fetch(event.request).catch(async () => {
+			const user = await currentUser();
+			// Nobody to replay it for, the server would refuse it anyway
+			if (user === null) {
+				return Response.error();
+			}
+			await enqueue({
+				user,
+				path: url.pathname,
+				contentType: copy.headers.get("Content-Type"),
+				body: await copy.text(),
+			});
+			if (self.registration.sync) {
+				await self.registration.sync.register("offline-queue").catch(() => {});
+			}
+			await report({ waiting: (await queued()).length });
+			return queuedResponse(url.pathname);
+		}),
+	);
+});
+
+let replaying = null;
+
+// Oldest first and at most one replay at a time, a todo added offline has to exist before it's edited
+function replay() {
+	replaying ??= (async () => {
+		const queueReport = { waiting: 0, replayed: 0, conflicts: [], failed: [] };
+		const user = await currentUser();
+		for (const mutation of await queued()) {
+			// Replayed with the session of whoever is logged in now, so changes of anyone else are dropped
+			if (mutation.user !== user) {
+				await dequeue(mutation.id);
+				continue;
+			}
+			let response;
+			try {
+				response = await fetch(mutation.path, {
+					method: "POST",
+					headers: { "Content-Type": mutation.contentType, Accept: "application/json" },
+					body: mutation.body,
+					credentials: "same-origin",
+				});
+			} catch {
+				// Still offline, keep the rest for the next try
+				break;
+			}
+
+			await dequeue(mutation.id);
+			if (!response.ok) {
+				queueReport.failed.push(await response.text());
+				continue;
+			}
+			queueReport.replayed += 1;
+			const result = await response.json().catch(() => null);
+			// Edits are sent with the version they were based on, a newer version on the server is a conflict
+			if (result && result.Conflict) {
+				queueReport.conflicts.push(result.Conflict.current);
+			}
+		}
+		queueReport.waiting = (await queued()).length;
+		if (queueReport.replayed || queueReport.failed.length) {
+			await report(queueReport);
+		}
+	})().finally(() => {
+		replaying = null;
+	});
+
+	return replaying;
+}
+
+self.addEventListener("sync", (event) => {
+	if (event.tag === "offline-queue") {
+		event.waitUntil(replay());
+	}
+});
+
+async function changeUser(user) {
+	if (user === null) {
+		await clearQueue();
+		await report({ waiting: 0 });
+	}
+	await setUser(user);
+}
+
+self.addEventListener("message", (event) => {
+	// Browsers without background sync ask for a replay whenever they come back online
+	if (event.data === "replay") {
+		event.waitUntil(replay());
+		return;
+	}
+	if (typeof event.data === "string" && event.data.startsWith("{")) {
+		const message = JSON.parse(event.data);
+		if ("offline_user" in message) {
+			event.waitUntil(changeUser(message.offline_user));
+		}
+	}
+});
@@ -0,0 +1,249 @@
+use crate::permission::{Permission, Permissions};
+use serde::{Deserialize, Serialize};
+
+/// Stands for the id of the new user in the permission strings users sign up with, like `WRITE(person[self])`
+pub const SELF_ID: &str = "[self]";
+
+/// The permission string with every `[self]` replaced by the id of the user it is for
+pub fn with_self(permission: &str, id: i32) -> String {
+	let mut replaced = String::with_capacity(permission.len());
+	let mut rest = permission;
+	// Permission strings don't care about case so `[SELF]` works too
+	while let Some(start) = rest.to_ascii_lowercase().find(SELF_ID) {
+		replaced.push_str(&rest[..start]);
+		replaced.push_str(&format!("[{id}]"));
+		rest = &rest[start + SELF_ID.len()..];
+	}
+	replaced.push_str(rest);
+
+	replaced
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "ssr", derive(sqlx::FromRow))]
+pub struct Tenant {
+	pub id: i32,
+	pub slug: String,
+	pub name: String,
+}
+
+/// Auth related settings each tenant can configure, tenants without a `tenant_settings` row get the defaults
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "ssr", derive(sqlx::FromRow))]
+pub struct TenantSettings {
+	pub password_min_length: i32,
+	pub password_require_number: bool,
+	pub password_require_symbol: bool,
+	pub session_lifetime_hours: i32,
+	pub open_signup: bool,
+	/// The permission strings users who sign up start with, `[self]` stands for their id
+	pub default_permission_equipment: String,
+	pub default_permission_user: String,
+	pub default_permission_todo: String,
+}
+
+impl Default for TenantSettings {
+	fn default() -> Self {
+		Self {
+			password_min_length: 8,
+			password_require_number: false,
+			password_require_symbol: false,
+			session_lifetime_hours: 24 * 30,
+			open_signup: true,
+			default_permission_equipment: String::from("READ(person[self])|WRITE(person[self])|CREATE(false)"),
+			default_permission_user: String::from("READ(*)|WRITE(person[self])|CREATE(false)"),
+			default_permission_todo: String::from("READ(person[self])|WRITE(person[self])|CREATE(true)"),
+		}
+	}
+}
+
+impl TenantSettings {
+	/// The permissions a new user with `id` starts with, for equipment, users and todos
+	///
+	/// Fails on strings that don't parse and on users permissions that would make every new user an admin.
+	pub fn default_permissions(&self, id: i32) -> Result<[(String, Permissions); 3], String> {
+		let parse = |name: &str, permission: &str| {
+			let permission = with_self(permission, id);
+			Permission::parse(permission.clone())
+				.map(|parsed| (permission, parsed))
+				.map_err(|error| format!("{name}: {error}"))
+		};
+		let equipment = parse("Equipment", &self.default_permission_equipment)?;
+		let user = parse("Users", &self.default_permission_user)?;
+		let todo = parse("Todos", &self.default_permission_todo)?;
+
+		// Whoever may change any user is an admin, see `User::is_admin`
+		let Permissions::ReadWrite { write, .. } = &user.1;
+		if *write == Permission::WriteAny {
+			return Err(String::from("Users: new users can't start out able to change every user"));
+		}
+
+		Ok([equipment, user, todo])
+	}
+
+	pub fn validate_password(&self, password: &str) -> Result<(), String> {
+		if password.chars().count() < self.password_min_length.max(0) as usize {
+			return Err(format!("Password must be at least {} characters long.", self.password_min_length));
+		}
+		if self.password_require_number && !password.chars().any(|c| c.is_ascii_digit()) {
+			return Err(String::from("Password must contain a number."));
+		}
+		if self.password_require_symbol && password.chars().all(|c| c.is_alphanumeric()) {
+			return Err(String::from("Password must contain a symbol."));
+		}
+
+		Ok(())
+	}
+}
+
+#[cfg(feature = "ssr")]
+pub mod ssr {
+	use super::{Tenant, TenantSettings};
+	use crate::{config::TenantMode, state::AppState};
+	use axum::{
+		async_trait,
+		extract::FromRequestParts,
+		http::{header, request::Parts, StatusCode},
+	};
+	use sqlx::PgPool;
+
+	impl Tenant {
+		pub async fn get_from_slug(slug: &str, pool: &PgPool) -> Option<Self> {
+			sqlx::query_as::<_, Tenant>("SELECT id, slug, name FROM tenants WHERE slug = $1")
+				.bind(slug)
+				.fetch_optional(pool)
+				.await
+				.ok()?
+		}
+	}
+
+	impl TenantSettings {
+		pub async fn get_for_tenant(tenant: i32, pool: &PgPool) -> Self {
+			sqlx::query_as::<_, TenantSettings>(
+				"SELECT password_min_length, password_require_number, password_require_symbol, session_lifetime_hours, open_signup,
+					default_permission_equipment, default_permission_user, default_permission_todo
+				FROM tenant_settings WHERE tenant = $1",
+			)
+			.bind(tenant)
+			.fetch_optional(pool)
+			.await
+			.ok()
+			.flatten()
+			.unwrap_or_default()
+		}
+	}
+
+	/// Work out which tenant slug a request is for
+	/// - `single`: always the default tenant
+	/// - `subdomain`: `acme.example.com` is tenant `acme` when the base domain is `example.com`, the bare base domain is
+	///   the default tenant
+	pub fn resolve_slug<'a>(
+		host: &'a str,
+		mode: TenantMode,
+		base_domain: &str,
+		default_tenant: &'a str,
+	) -> Option<&'a str> {
+		match mode {
+			TenantMode::Single => Some(default_tenant),
+			TenantMode::Subdomain => {
+				let host = host.split_once(':').map(|(host, _port)| host).unwrap_or(host);
+
+				if host.eq_ignore_ascii_case(base_domain) {
+					return Some(default_tenant);
+				}
+
+				let subdomain = host.strip_suffix(base_domain)?.strip_suffix('.')?;
+				if subdomain.is_empty() || subdomain.contains('.') {
+					None
+				} else {
+					Some(subdomain)
+				}
+			},
+		}
+	}
+
+	#[async_trait]
+	impl FromRequestParts<AppState> for Tenant {
+		type Rejection = (StatusCode, String);
+
+		async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
+			let unknown_tenant = (StatusCode::NOT_FOUND, String::from("Unknown tenant"));
+			let host = parts.headers.get(header::HOST).and_then(|host| host.to_str().ok()).unwrap_or_default();
+
+			let slug =
+				resolve_slug(host, state.config.tenant_mode, &state.config.tenant_base_domain, &state.config.default_tenant)
+					.ok_or(unknown_tenant.clone())?;
+
+			Tenant::get_from_slug(slug, &state.pool).await.ok_or(unknown_tenant)
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{ssr::*, with_self, TenantSettings};
+	use crate::{
+		config::TenantMode,
+		permission::{Permission, Permissions, Scope},
+	};
+
+	#[test]
+	fn resolve_slug_test() {
+		assert_eq!(resolve_slug("acme.example.com", TenantMode::Single, "example.com", "default"), Some("default"));
+
+		assert_eq!(resolve_slug("acme.example.com", TenantMode::Subdomain, "example.com", "default"), Some("acme"));
+		assert_eq!(resolve_slug("acme.example.com:3000", TenantMode::Subdomain, "example.com", "default"), Some("acme"));
+		assert_eq!(resolve_slug("example.com", TenantMode::Subdomain, "example.com", "default"), Some("default"));
+		assert_eq!(resolve_slug("example.com:3000", TenantMode::Subdomain, "example.com", "default"), Some("default"));
+		assert_eq!(resolve_slug("acme.localhost:3000", TenantMode::Subdomain, "localhost", "default"), Some("acme"));
+
+		assert_eq!(resolve_slug("a.acme.example.com", TenantMode::Subdomain, "example.com", "default"), None);
+		assert_eq!(resolve_slug("acmeexample.com", TenantMode::Subdomain, "example.com", "default"), None);
+		assert_eq!(resolve_slug(".example.com", TenantMode::Subdomain, "example.com", "default"), None);
+		assert_eq!(resolve_slug("acme.evil.com", TenantMode::Subdomain, "example.com", "default"), None);
+		assert_eq!(resolve_slug("", TenantMode::Subdomain, "example.com", "default"), None);
+	}
+
+	#[test]
+	fn default_permissions_test() {
+		let [equipment, user, todo] = TenantSettings::default().default_permissions(7).unwrap();
+		assert_eq!(equipment.0, "READ(person[7])|WRITE(person[7])|CREATE(false)");
+		assert_eq!(todo.0, "READ(person[7])|WRITE(person[7])|CREATE(true)");
+		let Permissions::ReadWrite { write, .. } = user.1;
+		assert_eq!(write, Permission::Write(vec![Scope::Person(7)]));
+
+		assert_eq!(with_self("WRITE(person[SELF],equipment[2])", 3), "WRITE(person[3],equipment[2])");
+		let admins = TenantSettings {
+			default_permission_user: String::from("READ(*)|WRITE(*)|CREATE(true)"),
+			..TenantSettings::default()
+		};
+		assert!(admins.default_permissions(7).is_err());
+		let invalid = TenantSettings {
+			default_permission_todo: String::from("READ(*)|WRITE(*)"),
+			..TenantSettings::default()
+		};
+		assert!(invalid.default_permissions(7).is_err());
+	}
+
+	#[test]
+	fn validate_password_test() {
+		let settings = TenantSettings::default();
+		assert_eq!(settings.validate_password("12345678"), Ok(()));
+		assert_eq!(
+			settings.validate_password("1234567"),
+			Err(String::from("Password must be at least 8 characters long."))
+		);
+		assert_eq!(settings.validate_password("äöüäöüäö"), Ok(()));
+
+		let settings = TenantSettings {
+			password_min_length: 4,
+			password_require_number: true,
+			password_require_symbol: true,
+			..TenantSettings::default()
+		};
+		assert_eq!(settings.validate_password("abc1!"), Ok(()));
+		assert_eq!(settings.validate_password("abcd!"), Err(String::from("Password must contain a number.")));
+		assert_eq!(settings.validate_password("abcd1"), Err(String::from("Password must contain a symbol.")));
+		assert_eq!(settings.validate_password("a1!"), Err(String::from("Password must be at least 4 characters long.")));
+	}
+}
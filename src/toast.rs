@@ -0,0 +1,193 @@
+use crate::{errors::AppError, undo::Undo};
+use leptos::*;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// How long a toast stays up unless it's dismissed earlier
+pub const TOAST_DURATION: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "ssr", derive(utoipa::ToSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum ToastLevel {
+	Success,
+	Warning,
+	/// Only raised in the browser for server functions that failed
+	Error,
+}
+
+impl ToastLevel {
+	pub fn as_str(&self) -> &'static str {
+		match self {
+			ToastLevel::Success => "success",
+			ToastLevel::Warning => "warning",
+			ToastLevel::Error => "error",
+		}
+	}
+}
+
+/// A message for the user about how an action went
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "ssr", derive(utoipa::ToSchema))]
+pub struct Toast {
+	pub level: ToastLevel,
+	pub message: String,
+	/// Takes the action back with [`crate::undo::undo`], the toast shows an undo button for it
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub undo: Option<String>,
+}
+
+/// The response of a server function that has something to tell the user next to its value
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Toasted<T> {
+	pub value: T,
+	pub toast: Option<Toast>,
+}
+
+impl<T> Toasted<T> {
+	pub fn silent(value: T) -> Self {
+		Self { value, toast: None }
+	}
+
+	pub fn success(value: T, message: impl Into<String>) -> Self {
+		Self::with(value, ToastLevel::Success, message)
+	}
+
+	pub fn warning(value: T, message: impl Into<String>) -> Self {
+		Self::with(value, ToastLevel::Warning, message)
+	}
+
+	/// Offer to take the action back with the token in the toast, if there is one
+	pub fn with_undo(mut self, token: String) -> Self {
+		if let Some(toast) = &mut self.toast {
+			toast.undo = Some(token);
+		}
+		self
+	}
+
+	fn with(value: T, level: ToastLevel, message: impl Into<String>) -> Self {
+		Self {
+			value,
+			toast: Some(Toast {
+				level,
+				message: message.into(),
+				undo: None,
+			}),
+		}
+	}
+}
+
+/// The toasts that are up right now, shown by [`Toaster`]
+#[derive(Clone, Copy)]
+pub struct Toasts {
+	toasts: RwSignal<Vec<(u64, Toast)>>,
+	next_id: StoredValue<u64>,
+	undo: Action<Undo, Result<Toasted<()>, AppError>>,
+}
+
+impl Toasts {
+	/// Show a toast for [`TOAST_DURATION`]
+	pub fn push(&self, toast: Toast) {
+		let id = self.next_id.get_value();
+		self.next_id.set_value(id + 1);
+		self.toasts.update(|toasts| toasts.push((id, toast)));
+
+		let toasts = *self;
+		set_timeout(move || toasts.dismiss(id), TOAST_DURATION);
+	}
+
+	pub fn dismiss(&self, id: u64) {
+		self.toasts.update(|toasts| toasts.retain(|(toast, _)| *toast != id));
+	}
+
+	/// Counts up whenever something was undone from a toast, for everything showing what was undone to reload
+	pub fn undone(&self) -> RwSignal<usize> {
+		self.undo.version()
+	}
+
+	/// Toast every result of an action, its own toast when it succeeded and the error when it failed
+	pub fn follow<I: 'static, T: Clone + 'static>(&self, action: Action<I, Result<Toasted<T>, AppError>>) {
+		let toasts = *self;
+		create_effect(move |_| match action.value().get() {
+			Some(Ok(Toasted { toast: Some(toast), .. })) => toasts.push(toast),
+			Some(Err(error)) => toasts.push(Toast {
+				level: ToastLevel::Error,
+				message: error.to_string(),
+				undo: None,
+			}),
+			_ => {},
+		});
+	}
+}
+
+/// Start without toasts for every component below, call it once above the router
+pub fn provide_toasts() -> Toasts {
+	let toasts = Toasts {
+		toasts: create_rw_signal(Vec::new()),
+		next_id: store_value(0),
+		undo: create_server_action::<Undo>(),
+	};
+	provide_context(toasts);
+	toasts.follow(toasts.undo);
+	toasts
+}
+
+pub fn use_toasts() -> Toasts {
+	use_context::<Toasts>().expect("No toasts found, call provide_toasts first")
+}
+
+/// Renders the toasts in a corner, newest at the bottom
+#[component]
+pub fn Toaster() -> impl IntoView {
+	let toasts = use_toasts();
+
+	view! {
+		<ul class="toaster" role="status" aria-live="polite">
+			<For each=move || toasts.toasts.get() key=|(id, _)| *id let:toast>
+				<li class=format!("toast toast-{}", toast.1.level.as_str())>
+					{toast.1.message}
+					{toast
+						.1
+						.undo
+						.map(|token| {
+							view! {
+								<button
+									type="button"
+									on:click=move |_| {
+										toasts.undo.dispatch(Undo { token: token.clone() });
+										toasts.dismiss(toast.0);
+									}
+								>
+									"Undo"
+								</button>
+							}
+						})}
+					<button type="button" aria-label="Dismiss" on:click=move |_| toasts.dismiss(toast.0)>
+						"×"
+					</button>
+				</li>
+			</For>
+		</ul>
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn envelope_shape() {
+		let toasted = Toasted::warning(3, "Careful");
+
+		assert_eq!(
+			serde_json::to_value(&toasted).unwrap(),
+			serde_json::json!({ "value": 3, "toast": { "level": "warning", "message": "Careful" } })
+		);
+		assert_eq!(serde_json::to_value(Toasted::silent(())).unwrap(), serde_json::json!({ "value": null, "toast": null }));
+		assert_eq!(
+			serde_json::to_value(Toasted::success((), "Deleted").with_undo(String::from("abc"))).unwrap(),
+			serde_json::json!({ "value": null, "toast": { "level": "success", "message": "Deleted", "undo": "abc" } })
+		);
+		assert_eq!(Toasted::silent(()).with_undo(String::from("abc")), Toasted::silent(()));
+	}
+}
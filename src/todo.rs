@@ -1,135 +1,1348 @@
-use crate::{auth::*, error_template::ErrorTemplate};
+use crate::{
+	archive::ArchiveCompletedForm,
+	attachment::Attachments,
+	auth::*,
+	avatar::{Avatar, AvatarSize, AvatarUpload},
+	breaker::DegradedBanner,
+	calendar::CalendarFeedSettings,
+	command::{provide_command_registry, use_command_registry, CommandPalette, DefaultCommands},
+	comment::Comments,
+	db_console::DatabaseConsole,
+	delegation::Delegations,
+	denial::PermissionDenials,
+	digest::DigestSettings,
+	draft::use_form_draft,
+	error_template::ErrorTemplate,
+	errors::{AppError, AppServerError},
+	flags::{provide_feature_flags, FeatureFlagAdmin},
+	history::History,
+	identity::LinkedIdentities,
+	integration::IntegrationSettings,
+	layout::{use_swipe, AppHeader, Sidebar, SwipeDirection, WithSidebar},
+	live::use_live_events,
+	magic_link::MagicLinkLogin,
+	notification::{NotificationBell, NotificationSettings},
+	offline::{OfflineQueue, ServiceWorker},
+	print::{PrintFilters, PrintTodos},
+	project::{get_projects, use_selected_project, ProjectNav},
+	pwa::WebManifest,
+	recurrence::{Recurrence, RecurrenceForm},
+	routes,
+	scope_badge::{MyPermissions, PermissionsPage},
+	service_account::ServiceAccountAdmin,
+	share::{ShareLinks, SharedTodoPage},
+	startup::StartupReportPage,
+	toast::{provide_toasts, use_toasts, Toast, ToastLevel, Toasted, Toaster},
+	todo_menu::TodoMenu,
+	user_admin::UserAdmin,
+	username::ChangeUsername,
+	validation::{TODO_DESCRIPTION, TODO_TITLE},
+};
 use chrono::prelude::*;
 use leptos::*;
 use leptos_meta::*;
 use leptos_router::*;
 use serde::{Deserialize, Serialize};
 
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[cfg_attr(feature = "ssr", derive(utoipa::ToSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum Priority {
+	Low,
+	#[default]
+	Normal,
+	High,
+}
+
+impl Priority {
+	pub const ALL: [Priority; 3] = [Priority::Low, Priority::Normal, Priority::High];
+
+	pub fn as_str(&self) -> &'static str {
+		match self {
+			Priority::Low => "low",
+			Priority::Normal => "normal",
+			Priority::High => "high",
+		}
+	}
+}
+
+// Stored as a number so the database can sort by it
+impl From<i16> for Priority {
+	fn from(priority: i16) -> Self {
+		match priority {
+			i16::MIN..=-1 => Priority::Low,
+			0 => Priority::Normal,
+			1..=i16::MAX => Priority::High,
+		}
+	}
+}
+
+impl From<Priority> for i16 {
+	fn from(priority: Priority) -> Self {
+		match priority {
+			Priority::Low => -1,
+			Priority::Normal => 0,
+			Priority::High => 1,
+		}
+	}
+}
+
+/// The todos of a [`get_todos`] call along with the version to send back next time
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "ssr", derive(utoipa::ToSchema))]
+pub struct TodoList {
+	pub version: String,
+	/// `None` when nothing changed since the version that was sent
+	pub todos: Option<Vec<TodoListItem>>,
+}
+
+/// How many of the todos a user can read are in which state
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "ssr", derive(sqlx::FromRow, utoipa::ToSchema))]
+pub struct TodoCounts {
+	pub open: i64,
+	pub completed: i64,
+	/// Open todos past their due date
+	pub overdue: i64,
+}
+
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "ssr", derive(utoipa::ToSchema))]
 pub struct Todo {
-	id: i32,
-	user: Option<User>,
-	title: String,
-	created_at: DateTime<Utc>,
-	completed: bool,
+	pub id: i32,
+	pub user: Option<PublicUser>,
+	pub title: String,
+	/// Markdown, as it was written
+	pub description: Option<String>,
+	/// The description turned into HTML by the server, the only part of a todo that goes into the page as HTML, only
+	/// for a single todo and never in lists
+	pub description_html: Option<String>,
+	pub created_at: DateTime<Utc>,
+	pub completed: bool,
+	pub recurrence: Option<Recurrence>,
+	pub due_at: Option<DateTime<Utc>>,
+	pub priority: Priority,
+	/// Todos are listed by position, lowest first
+	pub position: i32,
+	pub updated_at: DateTime<Utc>,
+	/// Goes up with every edit, send it along with an edit to make sure it's based on the latest state
+	pub version: i32,
+	/// Archived todos are only listed when asking for them
+	pub archived_at: Option<DateTime<Utc>>,
+	/// Who else the todo is assigned to besides the user it belongs to
+	pub assignees: Vec<PublicUser>,
+	pub project: Option<i32>,
+}
+
+/// A todo as the list shows it, only the detail page shows when it last changed
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "ssr", derive(utoipa::ToSchema))]
+pub struct TodoListItem {
+	pub id: i32,
+	pub user: Option<PublicUser>,
+	pub title: String,
+	pub created_at: DateTime<Utc>,
+	pub completed: bool,
+	pub recurrence: Option<Recurrence>,
+	pub due_at: Option<DateTime<Utc>>,
+	pub priority: Priority,
+	pub position: i32,
+	pub version: i32,
+	pub archived_at: Option<DateTime<Utc>>,
+	pub assignees: Vec<PublicUser>,
+	pub project: Option<i32>,
+}
+
+impl From<Todo> for TodoListItem {
+	fn from(todo: Todo) -> Self {
+		Self {
+			id: todo.id,
+			user: todo.user,
+			title: todo.title,
+			created_at: todo.created_at,
+			completed: todo.completed,
+			recurrence: todo.recurrence,
+			due_at: todo.due_at,
+			priority: todo.priority,
+			position: todo.position,
+			version: todo.version,
+			archived_at: todo.archived_at,
+			assignees: todo.assignees,
+			project: todo.project,
+		}
+	}
+}
+
+/// What came of an edit, a conflict carries the todo as it is now
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "ssr", derive(utoipa::ToSchema))]
+pub enum EditResult {
+	/// Completing a todo comes with a token to [`crate::undo::undo`] it
+	Saved {
+		undo: Option<String>,
+	},
+	Conflict {
+		current: Todo,
+	},
 }
 
 #[cfg(feature = "ssr")]
 pub mod ssr {
-	use super::Todo;
-	use crate::auth::User;
+	use super::{Priority, Todo, TodoCounts};
+	use crate::{
+		auth::{PublicUser, User, UserSQL},
+		authorize::{self, Action},
+		cache::{permission_hash, response_cache},
+		delegation::ssr::{allows_delegated, delegated, SqlDelegation},
+		denial::{ssr::record_denial, DeniedAction},
+		errors::ErrorCode,
+		events::{record, DomainEvent},
+		history::ssr::{
+			record_change, ARCHIVED, ASSIGNEES, COMPLETED, CREATED, DESCRIPTION, PERSON, PRIORITY, PROJECT, TITLE,
+		},
+		markdown,
+		permission::{ssr::scope_watch, Permissions},
+		undo::ssr::{issue, UndoAction},
+		validation::{ValidationError, TODO_DESCRIPTION, TODO_TITLE},
+		visibility,
+	};
 	use chrono::prelude::*;
-	use sqlx::PgPool;
+	use sqlx::{PgConnection, PgPool};
+	use std::collections::HashMap;
+	use thiserror::Error;
+
+	#[derive(Debug, Error)]
+	pub enum TodoError {
+		#[error("Todo not found")]
+		NotFound,
+		#[error("Missing permission for this todo")]
+		Forbidden,
+		#[error("The todo was changed since version {expected}, it's at version {current} now")]
+		Conflict { expected: i32, current: i32 },
+		#[error("Todos can only be assigned to users of the tenant")]
+		UnknownAssignee,
+		#[error("Project not found")]
+		UnknownProject,
+		#[error(transparent)]
+		Invalid(#[from] ValidationError),
+		#[error("Database error: {0}")]
+		Database(#[from] sqlx::Error),
+	}
+
+	impl TodoError {
+		pub fn code(&self) -> ErrorCode {
+			match self {
+				TodoError::NotFound => ErrorCode::NotFound,
+				TodoError::Forbidden => ErrorCode::Forbidden,
+				TodoError::Conflict { .. } => ErrorCode::Conflict,
+				TodoError::UnknownAssignee => ErrorCode::Invalid,
+				TodoError::UnknownProject => ErrorCode::NotFound,
+				TodoError::Invalid(_) => ErrorCode::Invalid,
+				TodoError::Database(_) => ErrorCode::Internal,
+			}
+		}
+	}
 
 	#[derive(sqlx::FromRow, Clone)]
 	pub struct SqlTodo {
 		id: i32,
 		person: i32,
 		title: String,
+		description: Option<String>,
 		created_at: DateTime<Utc>,
 		completed: bool,
+		recurrence: Option<String>,
+		due_at: Option<DateTime<Utc>>,
+		priority: i16,
+		position: i32,
+		updated_at: DateTime<Utc>,
+		version: i32,
+		archived_at: Option<DateTime<Utc>>,
+		project: Option<i32>,
 	}
 
 	impl SqlTodo {
-		pub async fn into_todo(self, pool: &PgPool) -> Todo {
-			Todo {
-				id: self.id,
-				user: User::get_from_id(self.person, pool).await,
-				title: self.title,
-				created_at: self.created_at,
-				completed: self.completed,
+		pub async fn into_todo(self, pool: &PgPool) -> Result<Todo, sqlx::Error> {
+			Ok(SqlTodo::into_todos(vec![self], pool).await?.remove(0))
+		}
+
+		/// The users the todos belong to and are assigned to are loaded for all of them at once
+		pub async fn into_todos(todos: Vec<SqlTodo>, pool: &PgPool) -> Result<Vec<Todo>, sqlx::Error> {
+			let ids = todos.iter().map(|todo| todo.id).collect::<Vec<_>>();
+			let persons = todos.iter().map(|todo| todo.person).collect::<Vec<_>>();
+			let owners = sqlx::query_as::<_, PublicUser>("SELECT id, username, avatar FROM users WHERE id = ANY($1)")
+				.bind(&persons)
+				.fetch_all(pool)
+				.await?
+				.into_iter()
+				.map(|owner| (owner.id, owner))
+				.collect::<HashMap<_, _>>();
+			let mut assignees = HashMap::<i32, Vec<PublicUser>>::new();
+			for (todo, assignee) in sqlx::query_as::<_, (i32, i32, String, Option<String>)>(
+				"SELECT todo_assignees.todo, users.id, users.username, users.avatar
+				FROM todo_assignees JOIN users ON users.id = todo_assignees.person
+				WHERE todo_assignees.todo = ANY($1) ORDER BY users.id",
+			)
+			.bind(&ids)
+			.fetch_all(pool)
+			.await?
+			.into_iter()
+			.map(|(todo, id, username, avatar)| (todo, PublicUser { id, username, avatar }))
+			{
+				assignees.entry(todo).or_default().push(assignee);
 			}
+
+			Ok(
+				todos
+					.into_iter()
+					.map(|todo| Todo {
+						id: todo.id,
+						user: owners.get(&todo.person).cloned(),
+						title: todo.title,
+						// Rendered for the detail page only, lists don't show it
+						description_html: None,
+						description: todo.description,
+						created_at: todo.created_at,
+						completed: todo.completed,
+						// Rules are checked when they are set, one that doesn't parse anymore just isn't shown
+						recurrence: todo.recurrence.and_then(|recurrence| recurrence.parse().ok()),
+						due_at: todo.due_at,
+						priority: todo.priority.into(),
+						position: todo.position,
+						updated_at: todo.updated_at,
+						version: todo.version,
+						archived_at: todo.archived_at,
+						assignees: assignees.remove(&todo.id).unwrap_or_default(),
+						project: todo.project,
+					})
+					.collect(),
+			)
+		}
+	}
+
+	/// Fetch a single todo of a tenant but only if the user may read or write it, depending on the action
+	pub async fn get_todo_with_permission(
+		id: i32,
+		tenant: i32,
+		user: &User,
+		action: Action,
+		pool: &PgPool,
+	) -> Option<SqlTodo> {
+		let filter = authorize::todo_filter(user, action, 3);
+		let query = format!("SELECT * FROM todos WHERE id = $1 AND tenant = $2 AND deleted_at IS NULL{}", filter.sql);
+		let query = filter
+			.arrays
+			.iter()
+			.fold(sqlx::query_as::<_, SqlTodo>(&query).bind(id).bind(tenant), |query, scope| query.bind(scope));
+
+		let scope = authorize::scope(user, action);
+		scope_watch().run("get_todo_with_permission", scope, query.fetch_optional(pool)).await.ok()?
+	}
+
+	/// Archived todos only show up when asked for, which keeps the list to the todos still in use, deleted ones never do
+	fn archived_clause(archived: bool) -> &'static str {
+		if archived {
+			" AND archived_at IS NOT NULL AND deleted_at IS NULL"
+		} else {
+			" AND archived_at IS NULL AND deleted_at IS NULL"
 		}
 	}
+
+	/// Which of the todos the user can read a list is narrowed down to
+	#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+	pub struct TodoSelection {
+		/// The archived todos instead of the ones still in use
+		pub archived: bool,
+		/// Only the todos assigned to the user
+		pub assigned_to_me: bool,
+		/// Only the todos of a project
+		pub project: Option<i32>,
+	}
+
+	fn selection_clause(user: &User, selection: TodoSelection) -> String {
+		let mut clause = String::from(archived_clause(selection.archived));
+		// The ids are integers so there is nothing to escape
+		if selection.assigned_to_me {
+			clause.push_str(&format!(" AND id IN (SELECT todo FROM todo_assignees WHERE person = {})", user.id));
+		}
+		if let Some(project) = selection.project {
+			clause.push_str(&format!(" AND project = {project}"));
+		}
+
+		clause
+	}
+
+	/// All todos of a tenant the user is allowed to read, without the archived ones
+	pub async fn list_todos(user: &User, tenant: i32, pool: &PgPool) -> Result<Vec<Todo>, sqlx::Error> {
+		list_selected_todos(user, tenant, TodoSelection::default(), pool).await
+	}
+
+	/// The archived todos of a tenant the user is allowed to read, the latest archived first
+	pub async fn list_archived_todos(user: &User, tenant: i32, pool: &PgPool) -> Result<Vec<Todo>, sqlx::Error> {
+		let selection = TodoSelection {
+			archived: true,
+			..TodoSelection::default()
+		};
+		list_selected_todos(user, tenant, selection, pool).await
+	}
+
+	/// The todos of a tenant the user is allowed to read that are part of the selection, sorted like
+	/// [`list_todos`] or, when archived, like [`list_archived_todos`]
+	pub async fn list_selected_todos(
+		user: &User,
+		tenant: i32,
+		selection: TodoSelection,
+		pool: &PgPool,
+	) -> Result<Vec<Todo>, sqlx::Error> {
+		let Permissions::ReadWrite { read, .. } = user.permission_todo();
+
+		// The tenant filter always comes first so permission scopes can only ever narrow it down
+		let filter = visibility::read_filter(user, 2);
+		let order = if selection.archived {
+			"archived_at DESC, id"
+		} else {
+			"position, id"
+		};
+		let query = format!(
+			"SELECT * FROM todos WHERE tenant = $1{}{} ORDER BY {order}",
+			selection_clause(user, selection),
+			filter.sql
+		);
+		let query =
+			filter.arrays.iter().fold(sqlx::query_as::<_, SqlTodo>(&query).bind(tenant), |query, scope| query.bind(scope));
+
+		let todos = scope_watch().run("list_todos", read, query.fetch_all(pool)).await?;
+
+		SqlTodo::into_todos(todos, pool).await
+	}
+
+	/// A token that changes whenever a todo the user can read is created, changed or deleted, or the user's read scope
+	/// changes, without loading the todos themselves
+	///
+	/// Changes to the users the todos belong to, like a new avatar, don't change it. Every selection has a version of
+	/// its own.
+	pub async fn todos_version(
+		user: &User,
+		tenant: i32,
+		selection: TodoSelection,
+		pool: &PgPool,
+	) -> Result<String, sqlx::Error> {
+		let Permissions::ReadWrite { read, .. } = user.permission_todo();
+
+		let filter = visibility::read_filter(user, 2);
+		let query = format!(
+			"SELECT COUNT(*), MAX(updated_at) FROM todos WHERE tenant = $1{}{}",
+			selection_clause(user, selection),
+			filter.sql
+		);
+		let query = filter
+			.arrays
+			.iter()
+			.fold(sqlx::query_as::<_, (i64, Option<DateTime<Utc>>)>(&query).bind(tenant), |query, scope| query.bind(scope));
+		let (count, updated_at) = scope_watch().run("todos_version", read, query.fetch_one(pool)).await?;
+
+		Ok(format!(
+			"{count}-{}-{:x}",
+			updated_at.map(|updated_at| updated_at.timestamp_micros()).unwrap_or_default(),
+			permission_hash(user)
+		))
+	}
+
+	/// The counts of the todos in the list, archived ones aren't counted
+	pub async fn count_todos(user: &User, tenant: i32, pool: &PgPool) -> Result<TodoCounts, sqlx::Error> {
+		let Permissions::ReadWrite { read, .. } = user.permission_todo();
+
+		let filter = visibility::read_filter(user, 2);
+		let query = format!(
+			"SELECT COUNT(*) FILTER (WHERE NOT completed) AS open, COUNT(*) FILTER (WHERE completed) AS completed,
+			COUNT(*) FILTER (WHERE NOT completed AND due_at < now()) AS overdue
+			FROM todos WHERE tenant = $1{}{}",
+			archived_clause(false),
+			filter.sql
+		);
+		let query =
+			filter.arrays.iter().fold(sqlx::query_as::<_, TodoCounts>(&query).bind(tenant), |query, scope| query.bind(scope));
+
+		scope_watch().run("count_todos", read, query.fetch_one(pool)).await
+	}
+
+	/// A single todo the user is allowed to read
+	pub async fn get_todo(user: &User, tenant: i32, id: i32, pool: &PgPool) -> Result<Todo, TodoError> {
+		let todo = get_todo_with_permission(id, tenant, user, Action::Read, pool).await.ok_or(TodoError::NotFound)?;
+		let mut todo = todo.into_todo(pool).await?;
+		todo.description_html = todo.description.as_deref().map(markdown::render);
+
+		Ok(todo)
+	}
+
+	// Todos the user can't even read are reported as missing so their ids can't be probed
+	async fn check_write(
+		operation: &'static str,
+		user: &User,
+		tenant: i32,
+		id: i32,
+		pool: &PgPool,
+	) -> Result<SqlTodo, TodoError> {
+		if let Some(todo) = get_todo_with_permission(id, tenant, user, Action::Write, pool).await {
+			Ok(todo)
+		} else if get_todo_with_permission(id, tenant, user, Action::Read, pool).await.is_some() {
+			record_denial(operation, DeniedAction::Write, user);
+			Err(TodoError::Forbidden)
+		} else {
+			Err(TodoError::NotFound)
+		}
+	}
+
+	// Every field that changed gets its own history entry
+	async fn record_history(
+		connection: &mut PgConnection,
+		user: i32,
+		before: &SqlTodo,
+		after: &SqlTodo,
+	) -> Result<(), sqlx::Error> {
+		if before.title != after.title {
+			record_change(connection, after.id, user, TITLE, Some(before.title.clone()), Some(after.title.clone())).await?;
+		}
+		if before.completed != after.completed {
+			let value = |completed: bool| Some(completed.to_string());
+			record_change(connection, after.id, user, COMPLETED, value(before.completed), value(after.completed)).await?;
+		}
+		if before.priority != after.priority {
+			let value = |priority: i16| Some(priority.to_string());
+			record_change(connection, after.id, user, PRIORITY, value(before.priority), value(after.priority)).await?;
+		}
+		if before.person != after.person {
+			let value = |person: i32| Some(person.to_string());
+			record_change(connection, after.id, user, PERSON, value(before.person), value(after.person)).await?;
+		}
+
+		Ok(())
+	}
+
+	// Projects of other tenants are reported as missing, the lock keeps the project from being deleted meanwhile
+	async fn check_project(connection: &mut PgConnection, tenant: i32, project: Option<i32>) -> Result<(), TodoError> {
+		let Some(project) = project else {
+			return Ok(());
+		};
+
+		sqlx::query_scalar::<_, i32>("SELECT id FROM projects WHERE id = $1 AND tenant = $2 FOR SHARE")
+			.bind(project)
+			.bind(tenant)
+			.fetch_optional(connection)
+			.await?
+			.map(|_| ())
+			.ok_or(TodoError::UnknownProject)
+	}
+
+	/// Add a todo to the list, or to a project the user can write todos of
+	pub async fn create_todo(
+		user: &User,
+		tenant: i32,
+		title: String,
+		project: Option<i32>,
+		pool: &PgPool,
+	) -> Result<Todo, TodoError> {
+		if !authorize::can_create_todo(user)
+			|| project.is_some_and(|project| !authorize::reaches_project(user, Action::Write, project))
+		{
+			record_denial("create_todo", DeniedAction::Create, user);
+			return Err(TodoError::Forbidden);
+		}
+		let title = TODO_TITLE.clean(&title)?;
+
+		let mut tx = pool.begin().await?;
+		check_project(&mut tx, tenant, project).await?;
+		let todo = sqlx::query_as::<_, SqlTodo>(
+			"INSERT INTO todos (tenant, title, person, completed, position, project)
+			VALUES ($1, $2, $3, false, (SELECT COALESCE(MAX(position), 0) + 1 FROM todos WHERE tenant = $1), $4) RETURNING *",
+		)
+		.bind(tenant)
+		.bind(title)
+		.bind(user.id)
+		.bind(project)
+		.fetch_one(&mut *tx)
+		.await?;
+		record_change(&mut tx, todo.id, user.id, CREATED, None, Some(todo.title.clone())).await?;
+		record(
+			&mut *tx,
+			&DomainEvent::TodoCreated {
+				tenant,
+				user: user.id,
+				todo: todo.id,
+			},
+		)
+		.await?;
+		tx.commit().await?;
+		response_cache().invalidate_tenant(tenant);
+
+		Ok(todo.into_todo(pool).await?)
+	}
+
+	pub async fn update_todo(
+		user: &User,
+		tenant: i32,
+		id: i32,
+		title: Option<String>,
+		completed: Option<bool>,
+		priority: Option<Priority>,
+		expected_version: Option<i32>,
+		pool: &PgPool,
+	) -> Result<Todo, TodoError> {
+		check_write("update_todo", user, tenant, id, pool).await?;
+		let title = title.map(|title| TODO_TITLE.clean(&title)).transpose()?;
+
+		let mut tx = pool.begin().await?;
+		// Locking the row keeps concurrent updates from recording changes against a stale title
+		let before = sqlx::query_as::<_, SqlTodo>("SELECT * FROM todos WHERE id = $1 AND tenant = $2 FOR UPDATE")
+			.bind(id)
+			.bind(tenant)
+			.fetch_optional(&mut *tx)
+			.await?
+			.ok_or(TodoError::NotFound)?;
+		// Without an expected version the edit wins, like it did before todos had versions
+		if let Some(expected) = expected_version.filter(|expected| *expected != before.version) {
+			return Err(TodoError::Conflict {
+				expected,
+				current: before.version,
+			});
+		}
+		let todo = sqlx::query_as::<_, SqlTodo>(
+			"UPDATE todos SET title = COALESCE($3, title), completed = COALESCE($4, completed),
+			priority = COALESCE($5, priority), updated_at = now(), version = version + 1
+			WHERE id = $1 AND tenant = $2 RETURNING *",
+		)
+		.bind(id)
+		.bind(tenant)
+		.bind(title)
+		.bind(completed)
+		.bind(priority.map(i16::from))
+		.fetch_one(&mut *tx)
+		.await?;
+		record_history(&mut tx, user.id, &before, &todo).await?;
+		record(
+			&mut *tx,
+			&DomainEvent::TodoUpdated {
+				tenant,
+				user: user.id,
+				todo: id,
+			},
+		)
+		.await?;
+		tx.commit().await?;
+		response_cache().invalidate_tenant(tenant);
+
+		Ok(todo.into_todo(pool).await?)
+	}
+
+	/// Move todos to new positions, either all of them move or none do
+	pub async fn reorder_todos(
+		user: &User,
+		tenant: i32,
+		positions: &[(i32, i32)],
+		pool: &PgPool,
+	) -> Result<(), TodoError> {
+		let (ids, positions): (Vec<i32>, Vec<i32>) = positions.iter().copied().unzip();
+		let Permissions::ReadWrite { write, .. } = user.permission_todo();
+
+		// The write scope is checked for all rows in the same statement that moves them, so nothing is moved unless
+		// every single row may be
+		let filter = authorize::todo_filter(user, Action::Write, 4);
+		let query = format!(
+			"WITH moved AS (SELECT DISTINCT ON (todo_id) * FROM UNNEST($1::INT[], $2::INT[]) AS moved (todo_id, new_position)),
+			allowed AS (SELECT id FROM todos WHERE tenant = $3 AND deleted_at IS NULL AND id IN (SELECT todo_id FROM moved){})
+			UPDATE todos SET position = moved.new_position, updated_at = now(), version = todos.version + 1 FROM moved
+			WHERE todos.id = moved.todo_id AND (SELECT COUNT(*) FROM allowed) = (SELECT COUNT(*) FROM moved)
+			RETURNING todos.id",
+			filter.sql
+		);
+		let query = filter
+			.arrays
+			.iter()
+			.fold(sqlx::query_scalar::<_, i32>(&query).bind(&ids).bind(&positions).bind(tenant), |query, scope| {
+				query.bind(scope)
+			});
+
+		let mut tx = pool.begin().await?;
+		let moved = scope_watch().run("reorder_todos", write, query.fetch_all(&mut *tx)).await?;
+		if moved.is_empty() && !ids.is_empty() {
+			record_denial("reorder_todos", DeniedAction::Write, user);
+			return Err(TodoError::Forbidden);
+		}
+
+		for todo in moved {
+			record(
+				&mut *tx,
+				&DomainEvent::TodoUpdated {
+					tenant,
+					user: user.id,
+					todo,
+				},
+			)
+			.await?;
+		}
+		tx.commit().await?;
+		response_cache().invalidate_tenant(tenant);
+
+		Ok(())
+	}
+
+	/// Delete a todo, returns the token that brings it back for `undo_window` seconds
+	///
+	/// The todo is gone for everyone right away, the cleanup removes it for good once it can't be brought back anymore.
+	pub async fn remove_todo(
+		user: &User,
+		tenant: i32,
+		id: i32,
+		undo_window: u64,
+		pool: &PgPool,
+	) -> Result<String, TodoError> {
+		check_write("remove_todo", user, tenant, id, pool).await?;
+
+		let mut tx = pool.begin().await?;
+		sqlx::query(
+			"UPDATE todos SET deleted_at = now(), updated_at = now(), version = version + 1 WHERE id = $1 AND tenant = $2",
+		)
+		.bind(id)
+		.bind(tenant)
+		.execute(&mut *tx)
+		.await?;
+		let token = issue(&mut *tx, tenant, user.id, id, UndoAction::Delete, undo_window).await?;
+		record(
+			&mut *tx,
+			&DomainEvent::TodoDeleted {
+				tenant,
+				user: user.id,
+				todo: id,
+			},
+		)
+		.await?;
+		tx.commit().await?;
+		response_cache().invalidate_tenant(tenant);
+
+		Ok(token)
+	}
+
+	/// Bring back a deleted todo, the undo token it was deleted with stands in for the write permission since the todo
+	/// can't be read anymore to check it
+	pub async fn restore_todo(user: &User, tenant: i32, id: i32, pool: &PgPool) -> Result<(), TodoError> {
+		let mut tx = pool.begin().await?;
+		let restored = sqlx::query(
+			"UPDATE todos SET deleted_at = NULL, updated_at = now(), version = version + 1
+			WHERE id = $1 AND tenant = $2 AND deleted_at IS NOT NULL",
+		)
+		.bind(id)
+		.bind(tenant)
+		.execute(&mut *tx)
+		.await?
+		.rows_affected();
+		if restored == 0 {
+			return Err(TodoError::NotFound);
+		}
+		record(
+			&mut *tx,
+			&DomainEvent::TodoUpdated {
+				tenant,
+				user: user.id,
+				todo: id,
+			},
+		)
+		.await?;
+		tx.commit().await?;
+		response_cache().invalidate_tenant(tenant);
+
+		Ok(())
+	}
+
+	/// The users of the tenant whose todos the user may write, by their own access or by delegation, which are the
+	/// ones they can assign a todo to
+	pub async fn assignable_users(
+		user: &User,
+		tenant: i32,
+		id: i32,
+		pool: &PgPool,
+	) -> Result<Vec<PublicUser>, TodoError> {
+		let todo = check_write("assignable_users", user, tenant, id, pool).await?;
+		let delegations = delegated(user, tenant, Action::Write, pool).await?;
+
+		Ok(
+			sqlx::query_as::<_, UserSQL>(
+				"SELECT * FROM users WHERE tenant = $1 AND active AND NOT service_account ORDER BY username",
+			)
+			.bind(tenant)
+			.fetch_all(pool)
+			.await?
+			.into_iter()
+			.map(User::from)
+			.filter(|person| allows_delegated(user, Action::Write, id, person.id, todo.project, &delegations))
+			.map(PublicUser::from)
+			.collect(),
+		)
+	}
+
+	/// The assignees asked for sorted and without repeats, refused unless the user can write the todos of one of them,
+	/// `delegations` are the write delegations to the user
+	pub(crate) fn requested_assignees(
+		user: &User,
+		id: i32,
+		project: Option<i32>,
+		assignees: &[i32],
+		delegations: &[SqlDelegation],
+	) -> Result<Vec<i32>, TodoError> {
+		let mut assignees = assignees.to_vec();
+		assignees.sort_unstable();
+		assignees.dedup();
+		if !assignees.is_empty()
+			&& !assignees.iter().any(|person| allows_delegated(user, Action::Write, id, *person, project, delegations))
+		{
+			record_denial("set_assignees", DeniedAction::Write, user);
+			return Err(TodoError::Forbidden);
+		}
+
+		Ok(assignees)
+	}
+
+	/// Replace who a todo is assigned to
+	///
+	/// Besides writing the todo, the user has to be able to write the todos of at least one of the assignees, so todos
+	/// can't be handed to people entirely out of their reach. Taking everyone off a todo only needs the former. Only
+	/// active users who aren't service accounts are newly assigned, whoever is assigned already may stay.
+	pub async fn set_assignees(
+		user: &User,
+		tenant: i32,
+		id: i32,
+		assignees: &[i32],
+		pool: &PgPool,
+	) -> Result<(), TodoError> {
+		let todo = check_write("set_assignees", user, tenant, id, pool).await?;
+		let delegations = delegated(user, tenant, Action::Write, pool).await?;
+		let assignees = requested_assignees(user, id, todo.project, assignees, &delegations)?;
+
+		let mut tx = pool.begin().await?;
+		// Locking the todo keeps concurrent changes from recording their history against stale assignees
+		sqlx::query_scalar::<_, i32>("SELECT id FROM todos WHERE id = $1 AND tenant = $2 FOR UPDATE")
+			.bind(id)
+			.bind(tenant)
+			.fetch_optional(&mut *tx)
+			.await?
+			.ok_or(TodoError::NotFound)?;
+		let before = sqlx::query_scalar::<_, i32>("SELECT person FROM todo_assignees WHERE todo = $1 ORDER BY person")
+			.bind(id)
+			.fetch_all(&mut *tx)
+			.await?;
+		if before == assignees {
+			return Ok(());
+		}
+
+		sqlx::query("DELETE FROM todo_assignees WHERE todo = $1").bind(id).execute(&mut *tx).await?;
+		let assigned = sqlx::query(
+			"INSERT INTO todo_assignees (todo, person)
+			SELECT $1, id FROM users
+			WHERE tenant = $2 AND id = ANY($3) AND ((active AND NOT service_account) OR id = ANY($4))",
+		)
+		.bind(id)
+		.bind(tenant)
+		.bind(&assignees)
+		.bind(&before)
+		.execute(&mut *tx)
+		.await?
+		.rows_affected();
+		if assigned != assignees.len() as u64 {
+			return Err(TodoError::UnknownAssignee);
+		}
+		sqlx::query("UPDATE todos SET updated_at = now(), version = version + 1 WHERE id = $1")
+			.bind(id)
+			.execute(&mut *tx)
+			.await?;
+
+		let value = |persons: &[i32]| Some(persons.iter().map(i32::to_string).collect::<Vec<_>>().join(","));
+		record_change(&mut tx, id, user.id, ASSIGNEES, value(&before), value(&assignees)).await?;
+		record(
+			&mut *tx,
+			&DomainEvent::TodoUpdated {
+				tenant,
+				user: user.id,
+				todo: id,
+			},
+		)
+		.await?;
+		for person in assignees.iter().filter(|person| !before.contains(person)) {
+			record(
+				&mut *tx,
+				&DomainEvent::TodoAssigned {
+					tenant,
+					user: user.id,
+					todo: id,
+					person: *person,
+				},
+			)
+			.await?;
+		}
+		tx.commit().await?;
+		response_cache().invalidate_tenant(tenant);
+
+		Ok(())
+	}
+
+	/// Move a todo into a project or, with `None`, out of any, the user has to be able to write it in its new place too
+	pub async fn set_project(
+		user: &User,
+		tenant: i32,
+		id: i32,
+		project: Option<i32>,
+		pool: &PgPool,
+	) -> Result<(), TodoError> {
+		let before = check_write("set_project", user, tenant, id, pool).await?;
+		if before.project == project {
+			return Ok(());
+		}
+		let delegations = delegated(user, tenant, Action::Write, pool).await?;
+		if !allows_delegated(user, Action::Write, id, before.person, project, &delegations) {
+			record_denial("set_project", DeniedAction::Write, user);
+			return Err(TodoError::Forbidden);
+		}
+
+		let mut tx = pool.begin().await?;
+		check_project(&mut tx, tenant, project).await?;
+		sqlx::query(
+			"UPDATE todos SET project = $3, updated_at = now(), version = version + 1 WHERE id = $1 AND tenant = $2",
+		)
+		.bind(id)
+		.bind(tenant)
+		.bind(project)
+		.execute(&mut *tx)
+		.await?;
+
+		let value = |project: Option<i32>| project.map(|project| project.to_string());
+		record_change(&mut tx, id, user.id, PROJECT, value(before.project), value(project)).await?;
+		record(
+			&mut *tx,
+			&DomainEvent::TodoUpdated {
+				tenant,
+				user: user.id,
+				todo: id,
+			},
+		)
+		.await?;
+		tx.commit().await?;
+		response_cache().invalidate_tenant(tenant);
+
+		Ok(())
+	}
+
+	/// Replace the description of a todo, an empty one removes it
+	pub async fn set_description(
+		user: &User,
+		tenant: i32,
+		id: i32,
+		description: &str,
+		pool: &PgPool,
+	) -> Result<(), TodoError> {
+		let before = check_write("set_description", user, tenant, id, pool).await?;
+		let description = match TODO_DESCRIPTION.clean(description) {
+			Ok(description) => Some(description),
+			Err(ValidationError::Empty { .. }) => None,
+			Err(error) => return Err(error.into()),
+		};
+		if before.description == description {
+			return Ok(());
+		}
+
+		let mut tx = pool.begin().await?;
+		sqlx::query(
+			"UPDATE todos SET description = $3, updated_at = now(), version = version + 1 WHERE id = $1 AND tenant = $2",
+		)
+		.bind(id)
+		.bind(tenant)
+		.bind(description)
+		.execute(&mut *tx)
+		.await?;
+		record_change(&mut tx, id, user.id, DESCRIPTION, None, None).await?;
+		record(
+			&mut *tx,
+			&DomainEvent::TodoUpdated {
+				tenant,
+				user: user.id,
+				todo: id,
+			},
+		)
+		.await?;
+		tx.commit().await?;
+		response_cache().invalidate_tenant(tenant);
+
+		Ok(())
+	}
+
+	/// Move a todo into the archive or back into the list, archiving one that already is archived changes nothing
+	pub async fn set_archived(user: &User, tenant: i32, id: i32, archived: bool, pool: &PgPool) -> Result<(), TodoError> {
+		check_write("set_archived", user, tenant, id, pool).await?;
+
+		let mut tx = pool.begin().await?;
+		let changed = sqlx::query(
+			"UPDATE todos SET archived_at = CASE WHEN $3 THEN now() END, updated_at = now(), version = version + 1
+			WHERE id = $1 AND tenant = $2 AND (archived_at IS NOT NULL) <> $3",
+		)
+		.bind(id)
+		.bind(tenant)
+		.bind(archived)
+		.execute(&mut *tx)
+		.await?
+		.rows_affected();
+		if changed == 0 {
+			return Ok(());
+		}
+
+		record_change(&mut tx, id, user.id, ARCHIVED, Some((!archived).to_string()), Some(archived.to_string())).await?;
+		record(
+			&mut *tx,
+			&DomainEvent::TodoUpdated {
+				tenant,
+				user: user.id,
+				todo: id,
+			},
+		)
+		.await?;
+		tx.commit().await?;
+		response_cache().invalidate_tenant(tenant);
+
+		Ok(())
+	}
 }
 
-#[server]
-pub async fn get_todos() -> Result<Vec<Todo>, ServerFnError> {
-	use self::ssr::SqlTodo;
-	use crate::permission::Permissions;
-	use futures::future::join_all;
+/// Todos the user can read, send the version of the last response and the todos are only sent again once they
+/// changed
+///
+/// Only the archived todos are sent with `archived`, and none of them without it. With `assigned_to_me` it's just the
+/// todos that are assigned to the user, with `project` just the todos of the project.
+#[server(input = GetUrl)]
+pub async fn get_todos(
+	version: Option<String>,
+	#[server(default)] archived: bool,
+	#[server(default)] assigned_to_me: bool,
+	#[server(default)] project: Option<i32>,
+) -> Result<TodoList, ServerFnError<AppServerError>> {
+	use self::ssr::{list_selected_todos, todos_version, TodoSelection};
+	use crate::{
+		cache::{cache_privately, response_cache},
+		retry::retry_read,
+		tenant::Tenant,
+	};
 	use sqlx::PgPool;
 
+	cache_privately::<GetTodos>(0);
+
 	let pool = use_context::<PgPool>().expect("Database not initialized");
-	let user = get_user().await?;
-
-	let mut query = String::from("SELECT * FROM todos");
-	match user {
-		Some(user) => {
-			let Permissions::ReadWrite {
-				read: perm,
-				write: _,
-				create: _,
-			} = user.permission_todo;
-			query.push_str(&perm.get_query_select("id"));
-		},
-		None => return Err(ServerFnError::Request(String::from("User not authenticated"))),
+	let tenant = use_context::<Tenant>().expect("No tenant found");
+	let user = get_user().await?.ok_or_else(AppServerError::unauthenticated)?;
+	let selection = TodoSelection {
+		archived,
+		assigned_to_me,
+		project,
 	};
 
+	let current = retry_read("todos_version", || todos_version(&user, tenant.id, selection, &pool))
+		.await
+		.map_err(AppServerError::from)?;
+	if version.as_ref() == Some(&current) {
+		return Ok(TodoList {
+			version: current,
+			todos: None,
+		});
+	}
+
+	let load = || retry_read("list_todos", || list_selected_todos(&user, tenant.id, selection, &pool));
+	let function = match (archived, assigned_to_me) {
+		(false, false) => "get_todos",
+		(true, false) => "get_archived_todos",
+		(false, true) => "get_assigned_todos",
+		(true, true) => "get_assigned_archived_todos",
+	};
+	// The lists of projects aren't cached, every project someone looked at would take up entries
+	let todos = if project.is_some() {
+		load().await
+	} else {
+		response_cache().get_or_load(function, tenant.id, &user, load).await
+	}
+	.map_err(AppServerError::from)?;
+
+	Ok(TodoList {
+		version: current,
+		todos: Some(todos.into_iter().map(TodoListItem::from).collect()),
+	})
+}
+
+#[server(input = GetUrl)]
+pub async fn get_todo_counts() -> Result<TodoCounts, ServerFnError<AppServerError>> {
+	use self::ssr::count_todos;
+	use crate::{
+		cache::{cache_privately, response_cache},
+		retry::retry_read,
+		tenant::Tenant,
+	};
+	use sqlx::PgPool;
+
+	cache_privately::<GetTodoCounts>(0);
+
+	let pool = use_context::<PgPool>().expect("Database not initialized");
+	let tenant = use_context::<Tenant>().expect("No tenant found");
+	let user = get_user().await?.ok_or_else(AppServerError::unauthenticated)?;
+
 	Ok(
-		join_all(
-			sqlx::query_as::<_, SqlTodo>(&query)
-				.fetch_all(&pool)
-				.await?
-				.iter()
-				.map(|todo: &SqlTodo| todo.clone().into_todo(&pool)),
-		)
-		.await,
+		response_cache()
+			.get_or_load("get_todo_counts", tenant.id, &user, || {
+				retry_read("count_todos", || count_todos(&user, tenant.id, &pool))
+			})
+			.await
+			.map_err(AppServerError::from)?,
 	)
 }
 
+/// A single todo, todos the user can't read are reported as missing
+#[server(input = GetUrl)]
+pub async fn get_todo(id: i32) -> Result<Todo, ServerFnError<AppServerError>> {
+	use crate::{cache::cache_privately, tenant::Tenant};
+	use sqlx::PgPool;
+
+	cache_privately::<GetTodo>(0);
+
+	let pool = use_context::<PgPool>().expect("Database not initialized");
+	let tenant = use_context::<Tenant>().expect("No tenant found");
+	let user = get_user().await?.ok_or_else(AppServerError::unauthenticated)?;
+
+	Ok(ssr::get_todo(&user, tenant.id, id, &pool).await.map_err(|error| AppServerError::new(error.code(), error))?)
+}
+
+/// Add a todo to the list, or to `project` when it's given
 #[server]
-pub async fn add_todo(title: String) -> Result<(), ServerFnError> {
+pub async fn add_todo(title: String, project: Option<i32>) -> Result<(), ServerFnError<AppServerError>> {
+	use self::ssr::create_todo;
+	use crate::tenant::Tenant;
 	use sqlx::PgPool;
 
 	let pool = use_context::<PgPool>().expect("Database not initialized");
-	let user = get_user().await?;
+	let tenant = use_context::<Tenant>().expect("No tenant found");
+	let user = get_user().await?.ok_or_else(AppServerError::unauthenticated)?;
 
-	let id = match user {
-		Some(user) => user.id,
-		None => -1,
-	};
+	Ok(
+		create_todo(&user, tenant.id, title, project, &pool)
+			.await
+			.map(|_| ())
+			.map_err(|error| AppServerError::new(error.code(), error))?,
+	)
+}
 
-	// fake API delay
-	std::thread::sleep(std::time::Duration::from_millis(1250));
+/// Save the order of todos after they were dragged around, takes `(id, position)` pairs
+#[server(input = Json)]
+pub async fn update_positions(positions: Vec<(i32, i32)>) -> Result<(), ServerFnError<AppServerError>> {
+	use self::ssr::reorder_todos;
+	use crate::tenant::Tenant;
+	use sqlx::PgPool;
+
+	let pool = use_context::<PgPool>().expect("Database not initialized");
+	let tenant = use_context::<Tenant>().expect("No tenant found");
+	let user = get_user().await?.ok_or_else(AppServerError::unauthenticated)?;
 
 	Ok(
-		sqlx::query!("INSERT INTO todos (title, person, completed) VALUES ($1, $2, false)", title, id)
-			.execute(&pool)
+		reorder_todos(&user, tenant.id, &positions, &pool)
 			.await
-			.map(|_| ())?,
+			.map_err(|error| AppServerError::new(error.code(), error))?,
 	)
 }
 
 #[server]
-pub async fn delete_todo(id: u16) -> Result<(), ServerFnError> {
+pub async fn set_priority(id: i32, priority: Priority) -> Result<(), ServerFnError<AppServerError>> {
+	use self::ssr::update_todo;
+	use crate::tenant::Tenant;
 	use sqlx::PgPool;
 
 	let pool = use_context::<PgPool>().expect("Database not initialized");
+	let tenant = use_context::<Tenant>().expect("No tenant found");
+	let user = get_user().await?.ok_or_else(AppServerError::unauthenticated)?;
 
-	Ok(sqlx::query("DELETE FROM todos WHERE id = $1").bind(id as i16).execute(&pool).await.map(|_| ())?)
+	Ok(
+		update_todo(&user, tenant.id, id, None, None, Some(priority), None, &pool)
+			.await
+			.map(|_| ())
+			.map_err(|error| AppServerError::new(error.code(), error))?,
+	)
+}
+
+/// Change the title and state of a todo, refused with the current todo when it changed since `version`
+#[server]
+pub async fn edit_todo(
+	id: i32,
+	title: String,
+	completed: Option<String>,
+	version: i32,
+) -> Result<EditResult, ServerFnError<AppServerError>> {
+	use self::ssr::{get_todo, update_todo, TodoError};
+	use crate::{
+		config::Config,
+		tenant::Tenant,
+		undo::ssr::{issue, UndoAction},
+	};
+	use sqlx::PgPool;
+
+	let pool = use_context::<PgPool>().expect("Database not initialized");
+	let config = use_context::<Config>().expect("No config found");
+	let tenant = use_context::<Tenant>().expect("No tenant found");
+	let user = get_user().await?.ok_or_else(AppServerError::unauthenticated)?;
+
+	// A todo that changed since it was read is refused as a conflict below, so this is the state the edit starts from
+	let was_completed =
+		get_todo(&user, tenant.id, id, &pool).await.map_err(|error| AppServerError::new(error.code(), error))?.completed;
+	match update_todo(&user, tenant.id, id, Some(title), Some(completed.is_some()), None, Some(version), &pool).await {
+		Ok(todo) if todo.completed && !was_completed => Ok(EditResult::Saved {
+			undo: Some(
+				issue(&pool, tenant.id, user.id, id, UndoAction::Complete, config.undo_window)
+					.await
+					.map_err(AppServerError::from)?,
+			),
+		}),
+		Ok(_) => Ok(EditResult::Saved { undo: None }),
+		Err(TodoError::Conflict { .. }) => Ok(EditResult::Conflict {
+			current: get_todo(&user, tenant.id, id, &pool).await.map_err(|error| AppServerError::new(error.code(), error))?,
+		}),
+		Err(error) => Err(AppServerError::new(error.code(), error).into()),
+	}
+}
+
+#[server]
+pub async fn delete_todo(id: u16) -> Result<Toasted<()>, ServerFnError<AppServerError>> {
+	use self::ssr::remove_todo;
+	use crate::{config::Config, tenant::Tenant};
+	use sqlx::PgPool;
+
+	let pool = use_context::<PgPool>().expect("Database not initialized");
+	let config = use_context::<Config>().expect("No config found");
+	let tenant = use_context::<Tenant>().expect("No tenant found");
+	let user = get_user().await?.ok_or_else(AppServerError::unauthenticated)?;
+
+	let undo = remove_todo(&user, tenant.id, id as i32, config.undo_window, &pool)
+		.await
+		.map_err(|error| AppServerError::new(error.code(), error))?;
+
+	Ok(Toasted::success((), "Todo deleted").with_undo(undo))
+}
+
+/// The users a todo can be assigned to, for users who can write it
+#[server(input = GetUrl)]
+pub async fn get_assignable_users(id: i32) -> Result<Vec<PublicUser>, ServerFnError<AppServerError>> {
+	use self::ssr::assignable_users;
+	use crate::{cache::cache_privately, tenant::Tenant};
+	use sqlx::PgPool;
+
+	cache_privately::<GetAssignableUsers>(0);
+
+	let pool = use_context::<PgPool>().expect("Database not initialized");
+	let tenant = use_context::<Tenant>().expect("No tenant found");
+	let user = get_user().await?.ok_or_else(AppServerError::unauthenticated)?;
+
+	Ok(assignable_users(&user, tenant.id, id, &pool).await.map_err(|error| AppServerError::new(error.code(), error))?)
+}
+
+/// Assign a todo to `assignees`, replacing whoever it was assigned to, an empty list takes everyone off it
+#[server(input = Json)]
+pub async fn assign_todo(id: i32, assignees: Vec<i32>) -> Result<Toasted<()>, ServerFnError<AppServerError>> {
+	use self::ssr::set_assignees;
+	use crate::tenant::Tenant;
+	use sqlx::PgPool;
+
+	let pool = use_context::<PgPool>().expect("Database not initialized");
+	let tenant = use_context::<Tenant>().expect("No tenant found");
+	let user = get_user().await?.ok_or_else(AppServerError::unauthenticated)?;
+
+	set_assignees(&user, tenant.id, id, &assignees, &pool)
+		.await
+		.map_err(|error| AppServerError::new(error.code(), error))?;
+
+	Ok(Toasted::success((), "Assignees saved"))
+}
+
+/// Move a todo into a project, or out of any with no `project`
+#[server(input = Json)]
+pub async fn move_todo(id: i32, project: Option<i32>) -> Result<Toasted<()>, ServerFnError<AppServerError>> {
+	use self::ssr::set_project;
+	use crate::tenant::Tenant;
+	use sqlx::PgPool;
+
+	let pool = use_context::<PgPool>().expect("Database not initialized");
+	let tenant = use_context::<Tenant>().expect("No tenant found");
+	let user = get_user().await?.ok_or_else(AppServerError::unauthenticated)?;
+
+	set_project(&user, tenant.id, id, project, &pool).await.map_err(|error| AppServerError::new(error.code(), error))?;
+
+	Ok(Toasted::success(
+		(),
+		if project.is_some() {
+			"Todo moved"
+		} else {
+			"Todo taken out of its project"
+		},
+	))
+}
+
+/// Replace the description of a todo with markdown, an empty one removes it
+#[server]
+pub async fn describe_todo(id: i32, description: String) -> Result<Toasted<()>, ServerFnError<AppServerError>> {
+	use self::ssr::set_description;
+	use crate::tenant::Tenant;
+	use sqlx::PgPool;
+
+	let pool = use_context::<PgPool>().expect("Database not initialized");
+	let tenant = use_context::<Tenant>().expect("No tenant found");
+	let user = get_user().await?.ok_or_else(AppServerError::unauthenticated)?;
+
+	set_description(&user, tenant.id, id, &description, &pool)
+		.await
+		.map_err(|error| AppServerError::new(error.code(), error))?;
+
+	Ok(Toasted::success((), "Description saved"))
+}
+
+/// Archive a todo to take it off the list without deleting it, or bring an archived one back
+#[server]
+pub async fn archive_todo(id: i32, archived: bool) -> Result<Toasted<()>, ServerFnError<AppServerError>> {
+	use self::ssr::set_archived;
+	use crate::tenant::Tenant;
+	use sqlx::PgPool;
+
+	let pool = use_context::<PgPool>().expect("Database not initialized");
+	let tenant = use_context::<Tenant>().expect("No tenant found");
+	let user = get_user().await?.ok_or_else(AppServerError::unauthenticated)?;
+
+	set_archived(&user, tenant.id, id, archived, &pool)
+		.await
+		.map_err(|error| AppServerError::new(error.code(), error))?;
+
+	Ok(Toasted::success((), if archived { "Todo archived" } else { "Todo restored" }))
 }
 
 #[component]
 pub fn TodoApp() -> impl IntoView {
-	let login = create_server_action::<Login>();
-	let logout = create_server_action::<Logout>();
-	let signup = create_server_action::<Signup>();
-
-	let user = create_resource(
-		move || (login.version().get(), signup.version().get(), logout.version().get()),
-		move |_| get_user(),
-	);
+	let UserContext {
+		login,
+		signup,
+		logout,
+		user,
+		..
+	} = provide_user_context();
 	provide_meta_context();
+	provide_feature_flags();
+	provide_command_registry();
+	provide_toasts();
 
 	view! {
 		<Link rel="shortcut icon" type_="image/ico" href="/favicon.ico" />
 		<Stylesheet id="leptos" href="/pkg/session_auth_axum.css" />
+		<WebManifest />
+		<ServiceWorker />
 		<Router>
-			<header>
-				<A href="/">
-					<h1>"My Tasks"</h1>
-				</A>
+			<DefaultCommands />
+			<CommandPalette />
+			<Toaster />
+			<DegradedBanner />
+			<SessionExpiryWarning />
+			<AppHeader title="My Tasks">
 				<Transition fallback=move || {
 					view! { <span>"Loading..."</span> }
 				}>
@@ -138,29 +1351,38 @@ pub fn TodoApp() -> impl IntoView {
 							.map(|user| match user {
 								Err(e) => {
 									view! {
-										<A href="/signup">"Signup"</A>
-										", "
-										<A href="/login">"Login"</A>
-										", "
+										<A href=routes::Route::Signup>"Signup"</A>
+										<A href=routes::Route::Login>"Login"</A>
 										<span>{format!("Login error: {}", e)}</span>
 									}
 										.into_view()
 								}
 								Ok(None) => {
 									view! {
-										<A href="/signup">"Signup"</A>
-										", "
-										<A href="/login">"Login"</A>
-										", "
+										<A href=routes::Route::Signup>"Signup"</A>
+										<A href=routes::Route::Login>"Login"</A>
 										<span>"Logged out."</span>
 									}
 										.into_view()
 								}
 								Ok(Some(user)) => {
 									view! {
-										<A href="/settings">"Settings"</A>
-										", "
-										<span>
+										<A href=routes::Route::Settings>"Settings"</A>
+										{user
+											.is_admin()
+											.then(|| {
+												view! {
+													<A href=routes::Route::AdminUsers>"Users"</A>
+													<A href=routes::Route::AdminServiceAccounts>"Service accounts"</A>
+													<A href=routes::Route::AdminFlags>"Feature flags"</A>
+													<A href=routes::Route::AdminDenials>"Denials"</A>
+													<A href=routes::Route::AdminDatabase>"Database"</A>
+													<A href=routes::Route::AdminDebug>"Debug"</A>
+												}
+											})}
+										<NotificationBell />
+										<span class="current-user">
+											<Avatar avatar=user.avatar.clone() size=AvatarSize::Small />
 											{format!("Logged in as: {} ({})", user.username, user.id)}
 										</span>
 									}
@@ -170,23 +1392,61 @@ pub fn TodoApp() -> impl IntoView {
 					}}
 
 				</Transition>
-			</header>
-			<hr />
-			<main>
+			</AppHeader>
+			<main class="page">
 				<Routes>
 					// Route
-					<Route path="" view=Todos />
-					<Route path="signup" view=move || view! { <Signup action=signup /> } />
-					<Route path="login" view=move || view! { <Login action=login /> } />
 					<Route
-						path="settings"
+						path=routes::Route::HOME
+						view=move || {
+							view! {
+								<WithSidebar>
+									<Sidebar slot>
+										<ProjectNav />
+									</Sidebar>
+									<Todos />
+								</WithSidebar>
+							}
+						}
+					/>
+					<Route path=routes::Route::SIGNUP view=move || view! { <Signup action=signup /> } />
+					<Route
+						path=routes::Route::LOGIN
+						view=move || {
+							view! {
+								<Login action=login />
+								<MagicLinkLogin />
+							}
+						}
+					/>
+					<Route
+						path=routes::Route::SETTINGS
 						view=move || {
 							view! {
 								<h1>"Settings"</h1>
+								<AvatarUpload />
+								<ChangeUsername />
+								<NotificationSettings />
+								<DigestSettings />
+								<CalendarFeedSettings />
+								<IntegrationSettings />
+								<LinkedIdentities />
+								<MyPermissions />
+								<Delegations />
 								<Logout action=logout />
 							}
 						}
 					/>
+					<Route path=routes::Route::PERMISSIONS view=PermissionsPage />
+					<Route path=routes::Route::ADMIN_USERS view=UserAdmin />
+					<Route path=routes::Route::ADMIN_SERVICE_ACCOUNTS view=ServiceAccountAdmin />
+					<Route path=routes::Route::ADMIN_FLAGS view=FeatureFlagAdmin />
+					<Route path=routes::Route::ADMIN_DENIALS view=PermissionDenials />
+					<Route path=routes::Route::ADMIN_DATABASE view=DatabaseConsole />
+					<Route path=routes::Route::ADMIN_DEBUG view=StartupReportPage />
+					<Route path=routes::Route::TODO_DETAIL view=TodoDetail />
+					<Route path=routes::Route::SHARED_TODO view=SharedTodoPage />
+					<Route path=routes::Route::PRINT view=PrintTodos />
 
 				</Routes>
 			</main>
@@ -194,21 +1454,213 @@ pub fn TodoApp() -> impl IntoView {
 	}
 }
 
+// The positions that change when a todo is dropped onto another one, the dropped todo takes the other one's place
+fn move_before(order: &[(i32, i32)], moved: i32, target: i32) -> Vec<(i32, i32)> {
+	if moved == target {
+		return Vec::new();
+	}
+
+	let mut ids = order.iter().map(|(id, _)| *id).filter(|id| *id != moved).collect::<Vec<_>>();
+	let Some(index) = ids.iter().position(|id| *id == target) else {
+		return Vec::new();
+	};
+	ids.insert(index, moved);
+
+	ids
+		.into_iter()
+		.zip(1..)
+		.filter(|(id, position)| order.iter().all(|(other, old_position)| other != id || old_position != position))
+		.collect()
+}
+
 #[component]
 pub fn Todos() -> impl IntoView {
 	let add_todo = create_server_multi_action::<AddTodo>();
 	let delete_todo = create_server_action::<DeleteTodo>();
+	let update_positions = create_server_action::<UpdatePositions>();
+	let set_priority = create_server_action::<SetPriority>();
+	let edit_todo = create_server_action::<EditTodo>();
+	let archive_todo = create_server_action::<ArchiveTodo>();
 	let submissions = add_todo.submissions();
+	let toasts = use_toasts();
+	toasts.follow(delete_todo);
+	toasts.follow(archive_todo);
+	let draft = use_form_draft("add_todo");
+	let new_todo = create_node_ref::<html::Input>();
+	// A todo that couldn't be added, like one refused for adding too fast, would just vanish from the list otherwise
+	create_effect(move |_| {
+		add_todo.version().track();
+		let last = submissions.with_untracked(|submissions| {
+			submissions.last().map(|last| (last.input.get_untracked(), last.value.get_untracked()))
+		});
+		match last {
+			// Anything typed since the todo was sent stays in the draft
+			Some((Some(added), Some(Ok(_)))) if added.title == draft.value_untracked("title") => draft.clear(),
+			Some((_, Some(Err(error)))) => {
+				toasts.push(Toast {
+					level: ToastLevel::Error,
+					message: error.to_string(),
+					undo: None,
+				});
+				// Submitting emptied the form, the draft puts back what was typed
+				if let Some(input) = new_todo.get_untracked() {
+					input.set_value(&draft.value_untracked("title"));
+				}
+			},
+			_ => {},
+		}
+	});
+	let (reloads, set_reloads) = create_signal(0);
+	let (archived, set_archived) = create_signal(false);
+	let (assigned_to_me, set_assigned_to_me) = create_signal(false);
+	let project = use_selected_project();
+	// Changes made elsewhere, by other users or in other tabs, show up without reloading the page
+	use_live_events(move |event| {
+		if event.changes_todos() {
+			set_reloads.update(|reloads| *reloads += 1);
+		}
+	});
+
+	// Saved edits reload the list right away, conflicting ones wait for the user to decide
+	create_effect(move |_| {
+		if let Some(Ok(EditResult::Saved { undo })) = edit_todo.value().get() {
+			set_reloads.update(|reloads| *reloads += 1);
+			if let Some(undo) = undo {
+				toasts.push(Toast {
+					level: ToastLevel::Success,
+					message: String::from("Todo completed"),
+					undo: Some(undo),
+				});
+			}
+		}
+	});
+	let (dragged, set_dragged) = create_signal(None::<i32>);
+	let UserContext { logged_out, user, .. } = use_user_context();
+	let is_admin = move || user.get().and_then(Result::ok).flatten().is_some_and(|user| user.is_admin());
 
 	// list of todos is loaded from the server in reaction to changes
-	let todos = create_resource(move || (add_todo.version().get(), delete_todo.version().get()), move |_| get_todos());
+	let changes = move || {
+		logged_out.track();
+		(
+			add_todo.version().get(),
+			delete_todo.version().get(),
+			update_positions.version().get(),
+			set_priority.version().get(),
+			archive_todo.version().get(),
+			toasts.undone().get(),
+			reloads.get(),
+		)
+	};
+	// The last list is kept so the server only has to send it again once it changed, along with the filters it was for
+	let last = store_value(None::<((bool, bool, Option<i32>), TodoList)>);
+	// Nothing of a user is kept around once they logged out
+	create_effect(move |previous: Option<()>| {
+		logged_out.track();
+		if previous.is_some() {
+			last.set_value(None);
+		}
+	});
+	let todos = create_resource(
+		move || (changes(), archived.get(), assigned_to_me.get(), project.get()),
+		move |(_, archived, assigned_to_me, project)| async move {
+			let filters = (archived, assigned_to_me, project);
+			let version = last
+				.with_value(|last| last.as_ref().filter(|(shown, _)| *shown == filters).map(|(_, last)| last.version.clone()));
+			let list = get_todos(version, archived, assigned_to_me, project).await?;
+			if list.todos.is_some() {
+				last.set_value(Some((filters, list)));
+			}
+
+			Ok::<_, AppError>(
+				last.with_value(|last| last.as_ref().and_then(|(_, last)| last.todos.clone())).unwrap_or_default(),
+			)
+		},
+	);
+	let counts = create_resource(changes, move |_| get_todo_counts());
+
+	// Searching the palette for a title opens the todo
+	let registry = use_command_registry();
+	registry.register("new_todo", "New todo", move || {
+		if let Some(input) = new_todo.get_untracked() {
+			_ = input.focus();
+		}
+	});
+	let navigate = use_navigate();
+	create_effect(move |_| {
+		for todo in todos.get().and_then(Result::ok).unwrap_or_default() {
+			let navigate = navigate.clone();
+			registry.register(format!("open_todo_{}", todo.id), format!("Open todo: {}", todo.title), move || {
+				navigate(&routes::Route::TodoDetail(todo.id).path(), Default::default())
+			});
+		}
+	});
 
 	view! {
 		<div>
+			<OfflineQueue reload=set_reloads />
 			<MultiActionForm action=add_todo>
-				<label>"Add a Todo" <input type="text" name="title" /></label>
+				<label>
+					"Add a Todo"
+					<input
+						type="text"
+						name="title"
+						maxlength=TODO_TITLE.max_length
+						node_ref=new_todo
+						prop:value=move || draft.value("title")
+						on:input=move |ev| draft.set("title", event_target_value(&ev))
+					/>
+				</label>
+				// New todos go into the project the list shows
+				{move || project.get().map(|project| view! { <input type="hidden" name="project" value=project /> })}
 				<input type="submit" value="Add" />
 			</MultiActionForm>
+			<label class="filter">
+				<input
+					type="checkbox"
+					prop:checked=archived
+					on:change=move |ev| set_archived.set(event_target_checked(&ev))
+				/>
+				"Archived"
+			</label>
+			<label class="filter">
+				<input
+					type="checkbox"
+					prop:checked=assigned_to_me
+					on:change=move |ev| set_assigned_to_me.set(event_target_checked(&ev))
+				/>
+				"Assigned to me"
+			</label>
+			<A href=move || {
+				let filters = PrintFilters {
+					archived: archived.get(),
+					assigned_to_me: assigned_to_me.get(),
+					project: project.get(),
+				};
+				routes::Route::Print(filters).path()
+			}>"Print"</A>
+			<Show when=move || archived.get() && is_admin()>
+				<ArchiveCompletedForm />
+			</Show>
+			<Transition fallback=move || ()>
+				{move || {
+					counts
+						.get()
+						.and_then(Result::ok)
+						.map(|counts| {
+							view! {
+								<p class="counts">
+									{format!(
+										"{} open, {} overdue, {} done",
+										counts.open,
+										counts.overdue,
+										counts.completed,
+									)}
+								</p>
+							}
+						})
+				}}
+
+			</Transition>
 			<Transition fallback=move || view! { <p>"Loading..."</p> }>
 				<ErrorBoundary fallback=|errors| {
 					view! { <ErrorTemplate errors=errors /> }
@@ -229,17 +1681,116 @@ pub fn Todos() -> impl IntoView {
 											if todos.is_empty() {
 												view! { <p>"No tasks were found."</p> }.into_view()
 											} else {
+												let order = store_value(
+													todos.iter().map(|todo| (todo.id, todo.position)).collect::<Vec<_>>(),
+												);
 												todos
 													.into_iter()
 													.map(move |todo| {
+														let id = todo.id;
+														let drop = move |ev: ev::DragEvent| {
+															ev.prevent_default();
+															if let Some(moved) = dragged.get_untracked() {
+																let positions = order.with_value(|order| move_before(order, moved, id));
+																if !positions.is_empty() {
+																	update_positions.dispatch(UpdatePositions { positions });
+																}
+															}
+															set_dragged.set(None);
+														};
+														// Swiping a row to the right marks it as done or opens it again
+														let swipe = use_swipe();
+														let (title, completed, version) = (todo.title.clone(), todo.completed, todo.version);
+														let swiped = move |ev: ev::PointerEvent| {
+															if swipe.up(&ev) == Some(SwipeDirection::Right) {
+																edit_todo
+																	.dispatch(EditTodo {
+																		id,
+																		title: title.clone(),
+																		completed: (!completed).then(|| String::from("on")),
+																		version,
+																	});
+															}
+														};
 														view! {
-															<li>
-																{todo.title} ": Created at " {todo.created_at.to_string()}
-																" by " {todo.user.unwrap_or_default().username}
+															<li
+																class="todo-row"
+																class:completed=todo.completed
+																draggable="true"
+																class:dragging=move || dragged.get() == Some(id)
+																on:dragstart=move |_| set_dragged.set(Some(id))
+																on:dragend=move |_| set_dragged.set(None)
+																on:dragover=|ev: ev::DragEvent| ev.prevent_default()
+																on:drop=drop
+																on:pointerdown=move |ev| swipe.down(&ev)
+																on:pointerup=swiped
+																on:pointercancel=move |_| swipe.cancel()
+															>
+																<span class=format!("priority priority-{}", todo.priority.as_str())>
+																	{todo.priority.as_str()}
+																</span>
+																" "
+																<A href=routes::Route::TodoDetail(todo.id)>{todo.title.clone()}</A>
+																<TodoMenu todo_id=todo.id title=todo.title.clone() />
+																": Created at " {todo.created_at.to_string()}
+																" by " {
+																	let user = todo.user.unwrap_or_default();
+																	view! {
+																		<Avatar avatar=user.avatar size=AvatarSize::Small />
+																		{user.username}
+																	}
+																}
 																<ActionForm action=delete_todo>
 																	<input type="hidden" name="id" value=todo.id />
 																	<input type="submit" value="X" />
 																</ActionForm>
+																<ActionForm action=archive_todo>
+																	<input type="hidden" name="id" value=todo.id />
+																	<input
+																		type="hidden"
+																		name="archived"
+																		value=todo.archived_at.is_none().to_string()
+																	/>
+																	<input
+																		type="submit"
+																		value=if todo.archived_at.is_some() {
+																			"Restore"
+																		} else {
+																			"Archive"
+																		}
+																	/>
+																</ActionForm>
+																{todo
+																	.due_at
+																	.map(|due_at| format!(" (due {due_at})"))}
+																<ActionForm action=set_priority>
+																	<input type="hidden" name="id" value=todo.id />
+																	<select name="priority">
+																		{Priority::ALL
+																			.into_iter()
+																			.map(|priority| {
+																				view! {
+																					<option
+																						value=priority.as_str()
+																						selected={priority == todo.priority}
+																					>
+																						{priority.as_str()}
+																					</option>
+																				}
+																			})
+																			.collect_view()}
+																	</select>
+																	<input type="submit" value="Set" />
+																</ActionForm>
+																<EditTodoForm
+																	todo_id=todo.id
+																	title=todo.title.clone()
+																	completed=todo.completed
+																	version=todo.version
+																	edit=edit_todo
+																	reload=set_reloads
+																/>
+																<RecurrenceForm todo_id=todo.id recurrence=todo.recurrence />
 															</li>
 														}
 													})
@@ -273,11 +1824,86 @@ pub fn Todos() -> impl IntoView {
 	}
 }
 
+/// Everything about a single todo, the list only shows the summary and links here
+#[component]
+pub fn TodoDetail() -> impl IntoView {
+	let params = use_params_map();
+	let id = move || params.with(|params| params.get("id").and_then(|id| id.parse::<i32>().ok()));
+	let assign = create_server_action::<AssignTodo>();
+	let move_todo = create_server_action::<MoveTodo>();
+	let describe = create_server_action::<DescribeTodo>();
+	let toasts = use_toasts();
+	toasts.follow(assign);
+	toasts.follow(move_todo);
+	toasts.follow(describe);
+	let todo = create_resource(
+		move || (id(), assign.version().get(), move_todo.version().get(), describe.version().get()),
+		move |(id, ..)| async move {
+			match id {
+				Some(id) => get_todo(id).await,
+				None => Err(AppServerError::not_found("Todo not found").into()),
+			}
+		},
+	);
+
+	view! {
+		<A href=routes::Route::Home>"Back to all todos"</A>
+		<Transition fallback=move || view! { <p>"Loading..."</p> }>
+			{move || {
+				todo.get()
+					.map(|todo| match todo {
+						Err(e) => view! { <pre class="error">"Server Error: " {e.to_string()}</pre> }.into_view(),
+						Ok(todo) => {
+							let user = todo.user.unwrap_or_default();
+							view! {
+								<article class="todo-detail">
+									<h1>{todo.title}</h1>
+									<p>
+										<span class=format!("priority priority-{}", todo.priority.as_str())>
+											{todo.priority.as_str()}
+										</span>
+										" " {if todo.completed { "Done" } else { "Open" }}
+										{todo.due_at.map(|due_at| format!(", due {due_at}"))}
+										{todo.recurrence.map(|recurrence| format!(", repeats {recurrence}"))}
+										{todo.archived_at.map(|archived_at| format!(", archived at {archived_at}"))}
+									</p>
+									<p>
+										"Created at " {todo.created_at.to_string()} " by "
+										<Avatar avatar=user.avatar size=AvatarSize::Small />
+										{user.username} ", last changed at " {todo.updated_at.to_string()}
+									</p>
+									<Description
+										todo_id=todo.id
+										description=todo.description
+										html=todo.description_html
+										describe=describe
+									/>
+									<ProjectPicker todo_id=todo.id project=todo.project move_todo=move_todo />
+									<Assignees todo_id=todo.id assignees=todo.assignees assign=assign />
+									<Attachments todo_id=todo.id />
+									<Comments todo_id=todo.id />
+									<History todo_id=todo.id />
+									<ShareLinks todo_id=todo.id />
+								</article>
+							}
+								.into_view()
+						}
+					})
+			}}
+
+		</Transition>
+	}
+}
+
 #[component]
-pub fn Login(action: Action<Login, Result<(), ServerFnError>>) -> impl IntoView {
+pub fn Login(action: Action<Login, Result<(), AppError>>) -> impl IntoView {
+	let query = use_query_map();
+	let next = move || query.with(|query| query.get("next").cloned());
+
 	view! {
 		<ActionForm action=action>
 			<h1>"Log In"</h1>
+			{move || next().map(|next| view! { <input type="hidden" name="next" value=next /> })}
 			<label>
 				"User:"
 				<input
@@ -307,7 +1933,7 @@ pub fn Login(action: Action<Login, Result<(), ServerFnError>>) -> impl IntoView
 }
 
 #[component]
-pub fn Signup(action: Action<Signup, Result<(), ServerFnError>>) -> impl IntoView {
+pub fn Signup(action: Action<Signup, Result<(), AppError>>) -> impl IntoView {
 	view! {
 		<ActionForm action=action>
 			<h1>"Sign Up"</h1>
@@ -350,7 +1976,7 @@ pub fn Signup(action: Action<Signup, Result<(), ServerFnError>>) -> impl IntoVie
 }
 
 #[component]
-pub fn Logout(action: Action<Logout, Result<(), ServerFnError>>) -> impl IntoView {
+pub fn Logout(action: Action<Logout, Result<(), AppError>>) -> impl IntoView {
 	view! {
 		<div id="loginbox">
 			<ActionForm action=action>
@@ -361,3 +1987,266 @@ pub fn Logout(action: Action<Logout, Result<(), ServerFnError>>) -> impl IntoVie
 		</div>
 	}
 }
+
+/// The description as the server rendered it, with a form to change the markdown it's made from
+#[component]
+fn Description(
+	todo_id: i32,
+	description: Option<String>,
+	html: Option<String>,
+	describe: Action<DescribeTodo, Result<Toasted<()>, AppError>>,
+) -> impl IntoView {
+	let summary = if description.is_some() {
+		"Edit description"
+	} else {
+		"Add a description"
+	};
+
+	view! {
+		<div class="todo-description">
+			// The server escaped everything that was written, what's left are the tags of the markdown
+			{html.map(|html| view! { <div inner_html=html></div> })}
+			<details>
+				<summary>{summary}</summary>
+				<ActionForm action=describe>
+					<input type="hidden" name="id" value=todo_id />
+					<textarea name="description" maxlength=TODO_DESCRIPTION.max_length>
+						{description}
+					</textarea>
+					<small>"Markdown: **bold**, *italic*, `code`, # headings, - lists and [links](https://example.com)"</small>
+					<input type="submit" value="Save" />
+				</ActionForm>
+			</details>
+		</div>
+	}
+}
+
+/// The project a todo is in, picking another one moves it there right away
+#[component]
+fn ProjectPicker(
+	todo_id: i32,
+	project: Option<i32>,
+	move_todo: Action<MoveTodo, Result<Toasted<()>, AppError>>,
+) -> impl IntoView {
+	let projects = create_resource(|| (), |_| get_projects());
+
+	view! {
+		<Transition fallback=move || ()>
+			{move || {
+				projects
+					.get()
+					.and_then(Result::ok)
+					.map(|projects| {
+						view! {
+							<label>
+								"Project "
+								<select on:change=move |ev| {
+									move_todo
+										.dispatch(MoveTodo {
+											id: todo_id,
+											project: event_target_value(&ev).parse().ok(),
+										})
+								}>
+									<option value="" selected=project.is_none()>
+										"No project"
+									</option>
+									{projects
+										.into_iter()
+										.map(|option| {
+											view! {
+												<option value=option.id selected=project == Some(option.id)>
+													{option.name}
+												</option>
+											}
+										})
+										.collect_view()}
+								</select>
+							</label>
+						}
+					})
+			}}
+		</Transition>
+	}
+}
+
+/// Who a todo is assigned to, users who can write it pick the assignees out of the users they can assign it to
+#[component]
+fn Assignees(
+	todo_id: i32,
+	assignees: Vec<PublicUser>,
+	assign: Action<AssignTodo, Result<Toasted<()>, AppError>>,
+) -> impl IntoView {
+	// Readers that can't write the todo are refused the candidates and only get to see the assignees
+	let candidates = create_resource(|| (), move |_| get_assignable_users(todo_id));
+	let selected = create_rw_signal(assignees.iter().map(|assignee| assignee.id).collect::<Vec<_>>());
+	let names = assignees.iter().map(|assignee| assignee.username.clone()).collect::<Vec<_>>().join(", ");
+
+	view! {
+		<h2>"Assignees"</h2>
+		<p>{if names.is_empty() { String::from("Nobody else is assigned") } else { names }}</p>
+		<Transition fallback=move || ()>
+			{move || {
+				candidates
+					.get()
+					.and_then(Result::ok)
+					.map(|candidates| {
+						// Assignees out of the user's reach stay assigned unless they are unticked
+						let mut users = assignees.clone();
+						users.extend(candidates.into_iter().filter(|candidate| !assignees.contains(candidate)));
+						view! {
+							<ul class="assignees">
+								{users
+									.into_iter()
+									.map(|person| {
+										let id = person.id;
+										view! {
+											<li>
+												<label>
+													<input
+														type="checkbox"
+														prop:checked=move || selected.with(|selected| selected.contains(&id))
+														on:change=move |ev| {
+															let checked = event_target_checked(&ev);
+															selected
+																.update(|selected| {
+																	selected.retain(|assignee| *assignee != id);
+																	if checked {
+																		selected.push(id);
+																	}
+																});
+														}
+													/>
+													<Avatar avatar=person.avatar size=AvatarSize::Small />
+													{person.username}
+												</label>
+											</li>
+										}
+									})
+									.collect_view()}
+							</ul>
+							<button
+								type="button"
+								on:click=move |_| {
+									assign
+										.dispatch(AssignTodo {
+											id: todo_id,
+											assignees: selected.get_untracked(),
+										})
+								}
+							>
+								"Save assignees"
+							</button>
+						}
+					})
+			}}
+		</Transition>
+	}
+}
+
+/// Edit the title and state of a todo, an edit someone else got in before is reported instead of overwritten
+#[component]
+fn EditTodoForm(
+	todo_id: i32,
+	title: String,
+	completed: bool,
+	version: i32,
+	edit: Action<EditTodo, Result<EditResult, AppError>>,
+	reload: WriteSignal<i32>,
+) -> impl IntoView {
+	let conflict =
+		move || matches!(edit.value().get(), Some(Ok(EditResult::Conflict { current })) if current.id == todo_id);
+
+	view! {
+		<ActionForm action=edit>
+			<input type="hidden" name="id" value=todo_id />
+			<input type="hidden" name="version" value=version />
+			<input type="text" name="title" value=title maxlength=TODO_TITLE.max_length required=true />
+			<label>
+				<input type="checkbox" name="completed" checked=completed />
+				"Done"
+			</label>
+			<input type="submit" value="Save" />
+		</ActionForm>
+		<Show when=conflict>
+			<span class="error">
+				"This todo changed, reload?"
+				<button
+					type="button"
+					on:click=move |_| {
+						edit.value().set(None);
+						reload.update(|reloads| *reloads += 1);
+					}
+				>
+					"Reload"
+				</button>
+			</span>
+		</Show>
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[cfg(feature = "ssr")]
+	#[test]
+	fn assignees_need_one_in_reach() {
+		use self::ssr::{requested_assignees, TodoError};
+		use crate::{delegation::ssr::SqlDelegation, permission::LazyPermissions};
+
+		let user = User {
+			id: 4,
+			permission_todo: LazyPermissions::new(String::from("READ(*)|WRITE(person[4],person[5])|CREATE(true)")),
+			..User::default()
+		};
+
+		assert_eq!(requested_assignees(&user, 1, None, &[], &[]).unwrap(), Vec::<i32>::new());
+		assert_eq!(requested_assignees(&user, 1, None, &[9, 5, 4, 5], &[]).unwrap(), vec![4, 5, 9]);
+		assert!(matches!(requested_assignees(&user, 1, None, &[7, 9], &[]), Err(TodoError::Forbidden)));
+		assert!(matches!(requested_assignees(&User::default(), 1, None, &[4], &[]), Err(TodoError::Forbidden)));
+
+		// Whose todos were delegated to the user counts like their own access
+		let delegation = SqlDelegation {
+			id: 1,
+			grantor: 2,
+			grantee: 4,
+			equipment: Vec::new(),
+			persons: vec![7],
+			write: true,
+			until: Utc::now(),
+			created_at: Utc::now(),
+		};
+		assert_eq!(requested_assignees(&user, 1, None, &[7], &[delegation.clone()]).unwrap(), vec![7]);
+		assert!(matches!(requested_assignees(&user, 1, None, &[8], &[delegation]), Err(TodoError::Forbidden)));
+	}
+
+	#[test]
+	fn todos_carry_no_permissions() {
+		let todo = Todo {
+			id: 1,
+			user: Some(User::privileged_default().into()),
+			title: String::from("Shared"),
+			description: None,
+			description_html: None,
+			created_at: Utc::now(),
+			completed: false,
+			recurrence: None,
+			due_at: None,
+			priority: Priority::Normal,
+			position: 1,
+			updated_at: Utc::now(),
+			version: 1,
+			archived_at: None,
+			assignees: Vec::new(),
+			project: None,
+		};
+
+		let json = serde_json::to_value(&todo).unwrap();
+		assert_eq!(
+			json["user"],
+			serde_json::json!({ "id": -1, "username": "Guest", "avatar": null }),
+			"readers of a todo only get to see who created it"
+		);
+		assert!(!serde_json::to_string(&TodoListItem::from(todo)).unwrap().contains("permission"));
+	}
+}
@@ -0,0 +1,248 @@
+//! The menu behind the dots next to each todo of the list, to pass a todo on without opening it
+//!
+//! Links are copied to the clipboard of the browser, and handed to the share sheet of the device where the browser has
+//! one, the share entry is left out everywhere else. A public link is a share link that works without an account, it's
+//! only created once confirmed and copied again from the same menu while it works.
+
+use crate::{
+	errors::AppServerError,
+	routes::Route,
+	share::{create_share_link, DEFAULT_SHARE_DAYS},
+	toast::{use_toasts, Toast, ToastLevel, Toasts},
+};
+use leptos::{
+	web_sys::js_sys::{self, Array, Object, Reflect},
+	*,
+};
+use std::{cell::RefCell, rc::Rc};
+use wasm_bindgen::{closure::Closure, prelude::wasm_bindgen, JsValue};
+
+// These throw when the browser doesn't allow them, outside of a secure context for example
+#[wasm_bindgen]
+extern "C" {
+	type Promise;
+	#[wasm_bindgen(method)]
+	fn then(this: &Promise, done: &JsValue, failed: &JsValue);
+	#[wasm_bindgen(catch, js_namespace = ["navigator", "clipboard"], js_name = writeText)]
+	fn write_text(text: &str) -> Result<Promise, JsValue>;
+	#[wasm_bindgen(catch, js_namespace = ["navigator", "clipboard"], js_name = write)]
+	fn write_items(items: &Array) -> Result<Promise, JsValue>;
+	#[wasm_bindgen(catch, js_namespace = navigator, js_name = share)]
+	fn share(data: &JsValue) -> Result<Promise, JsValue>;
+}
+
+#[wasm_bindgen]
+extern "C" {
+	type Blob;
+	#[wasm_bindgen(constructor)]
+	fn new(parts: &Array, options: &Object) -> Blob;
+}
+
+// Not every browser has clipboard items yet
+#[wasm_bindgen]
+extern "C" {
+	type ClipboardItem;
+	#[wasm_bindgen(constructor, catch)]
+	fn new(items: &Object) -> Result<ClipboardItem, JsValue>;
+}
+
+/// Whether the browser has a share sheet, only ask in the browser
+fn can_share() -> bool {
+	Reflect::get(&window(), &JsValue::from_str("navigator"))
+		.and_then(|navigator| Reflect::has(&navigator, &JsValue::from_str("share")))
+		.unwrap_or(false)
+}
+
+/// The detail page of the todo with the address the app runs on, to open it from anywhere
+fn deep_link(todo_id: i32) -> String {
+	format!("{}{}", window().location().origin().unwrap_or_default(), Route::TodoDetail(todo_id))
+}
+
+fn toast(level: ToastLevel, message: impl Into<String>) -> Toast {
+	Toast {
+		level,
+		message: message.into(),
+		undo: None,
+	}
+}
+
+/// Put the link into the clipboard and toast how it went once the browser is done
+fn copy(toasts: Toasts, link: &str, copied: String) {
+	let failed = || toast(ToastLevel::Error, "The browser didn't allow copying the link");
+	match write_text(link) {
+		Ok(promise) => promise.then(
+			&Closure::once_into_js(move |_: JsValue| toasts.push(toast(ToastLevel::Success, copied))),
+			&Closure::once_into_js(move |_: JsValue| toasts.push(failed())),
+		),
+		Err(_) => toasts.push(failed()),
+	}
+}
+
+/// A public link created from the menu, with when it stops working in milliseconds like `Date.now()`
+#[derive(Clone)]
+struct PublicLink {
+	link: String,
+	expires_at: f64,
+}
+
+fn public_link_copied() -> String {
+	format!("Public link copied, anyone with it can read the todo for {DEFAULT_SHARE_DAYS} days")
+}
+
+/// Create a public link and copy it, the browser only allows the clipboard while the click is handled so it gets the
+/// text as a promise right away and the link once the server created it
+fn copy_new_public_link(toasts: Toasts, todo_id: i32, public_link: StoredValue<Option<PublicLink>>) {
+	let create = move || async move {
+		let link = create_share_link(todo_id, DEFAULT_SHARE_DAYS).await?;
+		public_link.set_value(Some(PublicLink {
+			link: link.clone(),
+			expires_at: js_sys::Date::now() + f64::from(DEFAULT_SHARE_DAYS) * 24.0 * 60.0 * 60.0 * 1000.0,
+		}));
+		Ok::<_, ServerFnError<AppServerError>>(link)
+	};
+
+	let mut settle = None;
+	let text = js_sys::Promise::new(&mut |resolve, reject| settle = Some((resolve, reject)));
+	let Some((resolve, reject)) = settle else {
+		return;
+	};
+	let item = Object::new();
+	_ = Reflect::set(&item, &JsValue::from_str("text/plain"), &text);
+	let written = ClipboardItem::new(&item).and_then(|item| write_items(&Array::of1(&item)));
+	let Ok(written) = written else {
+		// Without clipboard items the link is copied once it's there, if the browser still allows it by then
+		spawn_local(async move {
+			match create().await {
+				Ok(link) => copy(toasts, &link, public_link_copied()),
+				Err(error) => toasts.push(toast(ToastLevel::Error, error.to_string())),
+			}
+		});
+		return;
+	};
+
+	let refused = Rc::new(RefCell::new(None::<String>));
+	written.then(
+		&Closure::once_into_js(move |_: JsValue| toasts.push(toast(ToastLevel::Success, public_link_copied()))),
+		&Closure::once_into_js({
+			let refused = refused.clone();
+			move |_: JsValue| {
+				let message = refused.take().unwrap_or_else(|| String::from("The browser didn't allow copying the link"));
+				toasts.push(toast(ToastLevel::Error, message));
+			}
+		}),
+	);
+	spawn_local(async move {
+		match create().await {
+			Ok(link) => {
+				let options = Object::new();
+				_ = Reflect::set(&options, &JsValue::from_str("type"), &JsValue::from_str("text/plain"));
+				_ = resolve.call1(&JsValue::NULL, &Blob::new(&Array::of1(&JsValue::from_str(&link)), &options));
+			},
+			Err(error) => {
+				refused.replace(Some(error.to_string()));
+				_ = reject.call1(&JsValue::NULL, &JsValue::NULL);
+			},
+		}
+	});
+}
+
+/// Copy a link to the todo or share it from a menu on the row of the todo
+#[component]
+pub fn TodoMenu(todo_id: i32, title: String) -> impl IntoView {
+	let toasts = use_toasts();
+	let title = store_value(title);
+	let (open, set_open) = create_signal(false);
+	let (shareable, set_shareable) = create_signal(false);
+	let (confirming, set_confirming) = create_signal(false);
+	let public_link = store_value(None::<PublicLink>);
+
+	let toggle = move |_| {
+		set_shareable.set(can_share());
+		set_confirming.set(false);
+		set_open.update(|open| *open = !*open);
+	};
+	let copy_link = move |_| {
+		set_open.set(false);
+		copy(toasts, &deep_link(todo_id), String::from("Link copied"));
+	};
+	let share_link = move |_| {
+		set_open.set(false);
+		let data = Object::new();
+		_ = Reflect::set(&data, &JsValue::from_str("title"), &JsValue::from_str(&title.get_value()));
+		_ = Reflect::set(&data, &JsValue::from_str("url"), &JsValue::from_str(&deep_link(todo_id)));
+		// Closing the share sheet without picking anything rejects it too, which is nothing to tell about
+		if share(&data).is_err() {
+			toasts.push(toast(ToastLevel::Error, "The browser didn't allow sharing the link"));
+		}
+	};
+	// The link created before is copied again while it works, a new one is only created once confirmed
+	let copy_public_link =
+		move |_| match public_link.get_value().filter(|public_link| public_link.expires_at > js_sys::Date::now()) {
+			Some(PublicLink { link, .. }) => {
+				set_open.set(false);
+				copy(toasts, &link, String::from("Public link copied"));
+			},
+			None => set_confirming.set(true),
+		};
+	let create_public_link = move |_| {
+		set_open.set(false);
+		set_confirming.set(false);
+		copy_new_public_link(toasts, todo_id, public_link);
+	};
+
+	view! {
+		<div class="todo-menu">
+			<button
+				type="button"
+				aria-label="More actions"
+				aria-haspopup="menu"
+				aria-expanded=move || open.get().to_string()
+				on:click=toggle
+			>
+				"⋮"
+			</button>
+			<Show when=move || open.get()>
+				<ul role="menu">
+					<li role="none">
+						<button type="button" role="menuitem" on:click=copy_link>
+							"Copy link"
+						</button>
+					</li>
+					<Show when=move || shareable.get()>
+						<li role="none">
+							<button type="button" role="menuitem" on:click=share_link>
+								"Share..."
+							</button>
+						</li>
+					</Show>
+					<Show
+						when=move || confirming.get()
+						fallback=move || {
+							view! {
+								<li role="none">
+									<button type="button" role="menuitem" on:click=copy_public_link>
+										"Copy public link"
+									</button>
+								</li>
+							}
+						}
+					>
+						<li role="none">
+							<p>
+								{format!(
+									"Anyone with a public link can read the todo for {DEFAULT_SHARE_DAYS} days without an account."
+								)}
+							</p>
+							<button type="button" role="menuitem" on:click=create_public_link>
+								"Create and copy"
+							</button>
+							<button type="button" role="menuitem" on:click=move |_| set_confirming.set(false)>
+								"Cancel"
+							</button>
+						</li>
+					</Show>
+				</ul>
+			</Show>
+		</div>
+	}
+}
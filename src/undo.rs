@@ -0,0 +1,120 @@
+//! Deleting or completing a todo can be taken back for `UNDO_WINDOW` seconds
+//!
+//! Both come with a token the toast offers to [`undo`] them with, only whoever did it can use it and only once. Deleted
+//! todos are only marked as deleted until the window is over, the cleanup removes them for good afterwards.
+
+use crate::{errors::AppServerError, toast::Toasted};
+use leptos::*;
+
+#[cfg(feature = "ssr")]
+pub mod ssr {
+	use crate::jwt::ssr::{generate_token, hash_token};
+	use sqlx::{PgExecutor, PgPool};
+
+	/// What an undo token takes back
+	#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+	pub enum UndoAction {
+		/// Brings the todo back
+		Delete,
+		/// Opens the todo again
+		Complete,
+	}
+
+	impl UndoAction {
+		pub fn as_str(&self) -> &'static str {
+			match self {
+				UndoAction::Delete => "delete",
+				UndoAction::Complete => "complete",
+			}
+		}
+
+		fn parse(action: &str) -> Option<Self> {
+			match action {
+				"delete" => Some(UndoAction::Delete),
+				"complete" => Some(UndoAction::Complete),
+				_ => None,
+			}
+		}
+	}
+
+	/// A token for the user to take back what they just did to the todo, valid for `window` seconds
+	pub async fn issue<'c>(
+		executor: impl PgExecutor<'c>,
+		tenant: i32,
+		person: i32,
+		todo: i32,
+		action: UndoAction,
+		window: u64,
+	) -> Result<String, sqlx::Error> {
+		let token = generate_token(32);
+		sqlx::query(
+			"INSERT INTO undo_tokens (tenant, person, todo, action, token_hash, expires_at)
+			VALUES ($1, $2, $3, $4, $5, now() + make_interval(secs => $6))",
+		)
+		.bind(tenant)
+		.bind(person)
+		.bind(todo)
+		.bind(action.as_str())
+		.bind(hash_token(&token))
+		.bind(window as f64)
+		.execute(executor)
+		.await?;
+
+		Ok(token)
+	}
+
+	/// The todo and what to take back of a token the user got that is still valid, the token is used up by it
+	pub async fn redeem(
+		token: &str,
+		tenant: i32,
+		person: i32,
+		pool: &PgPool,
+	) -> Result<Option<(i32, UndoAction)>, sqlx::Error> {
+		let redeemed = sqlx::query_as::<_, (i32, String)>(
+			"DELETE FROM undo_tokens WHERE token_hash = $1 AND tenant = $2 AND person = $3 AND expires_at > now()
+			RETURNING todo, action",
+		)
+		.bind(hash_token(token))
+		.bind(tenant)
+		.bind(person)
+		.fetch_optional(pool)
+		.await?;
+
+		Ok(redeemed.and_then(|(todo, action)| Some((todo, UndoAction::parse(&action)?))))
+	}
+}
+
+/// Take back deleting or completing a todo with the token that came with it
+#[server]
+pub async fn undo(token: String) -> Result<Toasted<()>, ServerFnError<AppServerError>> {
+	use self::ssr::{redeem, UndoAction};
+	use crate::{
+		auth::get_user,
+		tenant::Tenant,
+		todo::ssr::{restore_todo, update_todo},
+	};
+	use sqlx::PgPool;
+
+	let pool = use_context::<PgPool>().expect("Database not initialized");
+	let tenant = use_context::<Tenant>().expect("No tenant found");
+	let user = get_user().await?.ok_or_else(AppServerError::unauthenticated)?;
+
+	let (todo, action) = redeem(&token, tenant.id, user.id, &pool)
+		.await
+		.map_err(AppServerError::from)?
+		.ok_or_else(|| AppServerError::not_found("It's too late to undo that"))?;
+
+	match action {
+		UndoAction::Delete => {
+			restore_todo(&user, tenant.id, todo, &pool).await.map_err(|error| AppServerError::new(error.code(), error))?;
+			Ok(Toasted::success((), "Todo restored"))
+		},
+		// Reopening is an edit like any other, so it still takes write access to the todo
+		UndoAction::Complete => {
+			update_todo(&user, tenant.id, todo, None, Some(false), None, None, &pool)
+				.await
+				.map_err(|error| AppServerError::new(error.code(), error))?;
+			Ok(Toasted::success((), "Todo reopened"))
+		},
+	}
+}
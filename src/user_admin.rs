@@ -0,0 +1,917 @@
+use crate::{
+	auth::ConfirmPassword,
+	errors::{AppError, AppServerError},
+	permission::{Permission, Permissions, Scope},
+	permission_schedule::{
+		get_pending_permission_changes, CancelPermissionChange, PendingPermissionChange, PendingPermissionChanges,
+		SchedulePermissionChange,
+	},
+	permission_transfer::{ImportPermissions, PermissionTransfer},
+	scope_badge::{PermissionSummary, ScopeBadge},
+	toast::{use_toasts, Toasted},
+	user_import::{ImportUsers, UserImport},
+};
+use leptos::*;
+use leptos_router::*;
+use serde::{Deserialize, Serialize};
+
+/// A user of the tenant as admins edit them, with the permission strings as stored
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "ssr", derive(sqlx::FromRow, utoipa::ToSchema))]
+pub struct ManagedUser {
+	pub id: i32,
+	pub username: String,
+	pub permission_equipment: String,
+	pub permission_user: String,
+	pub permission_todo: String,
+	pub active: bool,
+}
+
+/// The permissions users who sign up start with, the tenant admins pick them
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "ssr", derive(utoipa::ToSchema))]
+pub struct DefaultPermissions {
+	pub permission_equipment: String,
+	pub permission_user: String,
+	pub permission_todo: String,
+}
+
+/// How many rows of a resource a user reaches with the permission they have and with the one previewed
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "ssr", derive(utoipa::ToSchema))]
+pub struct PermissionPreview {
+	/// `todos` or `equipment`
+	pub resource: String,
+	/// All rows of the tenant
+	pub total: i64,
+	pub read_now: i64,
+	pub read: i64,
+	pub write_now: i64,
+	pub write: i64,
+}
+
+/// How many todos a scope held by users of the tenant gates
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "ssr", derive(utoipa::ToSchema))]
+pub struct ScopeReach {
+	pub scope: Scope,
+	/// The title of the todo, the username or the name of the project the scope names, if it still exists
+	pub name: Option<String>,
+	/// Users with the scope in their equipment or todo permission
+	pub users: i64,
+	pub todos: i64,
+}
+
+#[cfg(feature = "ssr")]
+pub mod ssr {
+	use super::ManagedUser;
+	use crate::{
+		errors::AppServerError,
+		permission::{IdColumn, Permission, Permissions, Scope},
+	};
+	use sqlx::PgPool;
+
+	/// The column of todos a scope narrows down and the id it has to have there, equipment ids are todo ids like in
+	/// [`count_covered`]
+	pub fn todo_column(scope: Scope) -> Option<(&'static str, i32)> {
+		match scope {
+			Scope::Equipment(id) => Some((IdColumn::Id.name(), id)),
+			Scope::Person(id) => Some(("person", id)),
+			Scope::Project(id) => Some(("project", id)),
+			Scope::Any => None,
+		}
+	}
+
+	/// How many todos of the tenant have each of the ids in `column`, ids without todos are left out
+	pub async fn count_by(
+		tenant: i32,
+		column: &'static str,
+		ids: &[i32],
+		pool: &PgPool,
+	) -> Result<Vec<(i32, i64)>, sqlx::Error> {
+		let query = format!(
+			"SELECT {column}, COUNT(*) FROM todos WHERE tenant = $1 AND deleted_at IS NULL AND {column} = ANY($2)
+			GROUP BY {column}"
+		);
+
+		sqlx::query_as::<_, (i32, i64)>(&query).bind(tenant).bind(ids).fetch_all(pool).await
+	}
+
+	/// How many todos of the tenant pass the clause of the permission, equipment ids are todo ids so equipment
+	/// permissions are counted on todos as well
+	pub async fn count_covered(tenant: i32, permission: &Permission, pool: &PgPool) -> Result<i64, sqlx::Error> {
+		let filter = permission.query_filter(IdColumn::Id, 2);
+		let query = format!("SELECT COUNT(*) FROM todos WHERE tenant = $1 AND deleted_at IS NULL{}", filter.sql);
+
+		filter
+			.arrays
+			.iter()
+			.fold(sqlx::query_scalar::<_, i64>(&query).bind(tenant), |query, scope| query.bind(scope))
+			.fetch_one(pool)
+			.await
+	}
+
+	pub fn parse_permission(name: &str, permission: &str) -> Result<Permissions, AppServerError> {
+		Permission::parse(permission.to_string()).map_err(|error| AppServerError::invalid(format!("{name}: {error}")))
+	}
+
+	pub async fn managed_users(tenant: i32, pool: &PgPool) -> Result<Vec<ManagedUser>, sqlx::Error> {
+		sqlx::query_as::<_, ManagedUser>(
+			"SELECT id, username, permission_equipment, permission_user, permission_todo, active FROM users
+			WHERE tenant = $1 AND NOT service_account ORDER BY username",
+		)
+		.bind(tenant)
+		.fetch_all(pool)
+		.await
+	}
+}
+
+/// Parse a permission string with the same parser users are loaded with
+#[server(input = GetUrl)]
+pub async fn validate_permission(perm: String) -> Result<Permissions, ServerFnError<AppServerError>> {
+	use crate::{
+		auth::get_user,
+		cache::cache_privately,
+		denial::{ssr::record_denial, DeniedAction},
+	};
+
+	cache_privately::<ValidatePermission>(0);
+
+	let user = get_user().await?.ok_or_else(AppServerError::unauthenticated)?;
+	if !user.is_admin() {
+		record_denial("validate_permission", DeniedAction::Admin, &user);
+		return Err(AppServerError::forbidden("Missing permission to manage users").into());
+	}
+
+	Permission::parse(perm).map_err(AppServerError::invalid)
+}
+
+/// How many todos and equipment a user of the tenant could read and change with other permission strings, before
+/// they are saved, admins only
+///
+/// Only the strings are counted, access others delegated to the user comes on top and with `AUTHORIZATION=policy` the
+/// policies decide about todos instead.
+#[server(input = GetUrl)]
+pub async fn preview_permissions(
+	id: i32,
+	permission_equipment: String,
+	permission_todo: String,
+) -> Result<Vec<PermissionPreview>, ServerFnError<AppServerError>> {
+	use self::ssr::{count_covered, parse_permission};
+	use crate::{
+		auth::get_user,
+		denial::{ssr::record_denial, DeniedAction},
+		tenant::Tenant,
+	};
+	use sqlx::PgPool;
+
+	let pool = use_context::<PgPool>().expect("Database not initialized");
+	let tenant = use_context::<Tenant>().expect("No tenant found");
+	let user = get_user().await?.ok_or_else(AppServerError::unauthenticated)?;
+	if !user.is_admin() {
+		record_denial("preview_permissions", DeniedAction::Admin, &user);
+		return Err(AppServerError::forbidden("Missing permission to manage users").into());
+	}
+
+	let (equipment_now, todo_now) = sqlx::query_as::<_, (String, String)>(
+		"SELECT permission_equipment, permission_todo FROM users WHERE id = $1 AND tenant = $2",
+	)
+	.bind(id)
+	.bind(tenant.id)
+	.fetch_optional(&pool)
+	.await
+	.map_err(AppServerError::from)?
+	.ok_or_else(|| AppServerError::not_found("User not found"))?;
+	let resources = [
+		(
+			"equipment",
+			parse_permission("Equipment", &equipment_now)?,
+			parse_permission("Equipment", &permission_equipment)?,
+		),
+		("todos", parse_permission("Todos", &todo_now)?, parse_permission("Todos", &permission_todo)?),
+	];
+
+	let total = sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM todos WHERE tenant = $1 AND deleted_at IS NULL")
+		.bind(tenant.id)
+		.fetch_one(&pool)
+		.await
+		.map_err(AppServerError::from)?;
+	let mut previews = Vec::new();
+	for (resource, now, previewed) in resources {
+		let (
+			Permissions::ReadWrite {
+				read: read_now,
+				write: write_now,
+				..
+			},
+			Permissions::ReadWrite { read, write, .. },
+		) = (now, previewed);
+		previews.push(PermissionPreview {
+			resource: resource.to_string(),
+			total,
+			read_now: count_covered(tenant.id, &read_now, &pool).await.map_err(AppServerError::from)?,
+			read: count_covered(tenant.id, &read, &pool).await.map_err(AppServerError::from)?,
+			write_now: count_covered(tenant.id, &write_now, &pool).await.map_err(AppServerError::from)?,
+			write: count_covered(tenant.id, &write, &pool).await.map_err(AppServerError::from)?,
+		});
+	}
+
+	Ok(previews)
+}
+
+/// How many todos each scope held by users of the tenant gates and how many users hold it, the scopes reaching the
+/// most todos first, admins only
+///
+/// Only the permission strings have scopes, access others delegated comes on top and with `AUTHORIZATION=policy` the
+/// policies decide about todos instead. Permissions that don't parse are logged and left out, the rest of the table
+/// still shows.
+#[server(input = GetUrl)]
+pub async fn get_scope_reach() -> Result<Vec<ScopeReach>, ServerFnError<AppServerError>> {
+	use self::ssr::{count_by, managed_users, parse_permission, todo_column};
+	use crate::{
+		auth::get_user,
+		cache::cache_privately,
+		denial::{ssr::record_denial, DeniedAction},
+		scope_badge::ssr::scope_names,
+		tenant::Tenant,
+	};
+	use sqlx::PgPool;
+
+	cache_privately::<GetScopeReach>(0);
+
+	let pool = use_context::<PgPool>().expect("Database not initialized");
+	let tenant = use_context::<Tenant>().expect("No tenant found");
+	let user = get_user().await?.ok_or_else(AppServerError::unauthenticated)?;
+	if !user.is_admin() {
+		record_denial("get_scope_reach", DeniedAction::Admin, &user);
+		return Err(AppServerError::forbidden("Missing permission to manage users").into());
+	}
+
+	// The scopes in the order they were first found, with the users holding them
+	let mut holders = Vec::<(Scope, Vec<i32>)>::new();
+	for managed in managed_users(tenant.id, &pool).await.map_err(AppServerError::from)? {
+		for (name, permission) in [
+			("Equipment", &managed.permission_equipment),
+			("Todos", &managed.permission_todo),
+		] {
+			let Permissions::ReadWrite { read, write, .. } = match parse_permission(name, permission) {
+				Ok(permissions) => permissions,
+				Err(error) => {
+					log::warn!("Leaving user {} out of the scope reach: {error}", managed.id);
+					continue;
+				},
+			};
+			let scopes = [read, write].into_iter().flat_map(|permission| match permission {
+				Permission::Read(scopes) | Permission::Write(scopes) => scopes,
+				_ => Vec::new(),
+			});
+			for scope in scopes.filter(|scope| *scope != Scope::Any) {
+				match holders.iter_mut().find(|(held, _)| *held == scope) {
+					Some((_, users)) if !users.contains(&managed.id) => users.push(managed.id),
+					Some(_) => {},
+					None => holders.push((scope, vec![managed.id])),
+				}
+			}
+		}
+	}
+
+	// One query per column no matter how many scopes there are
+	let mut counts = Vec::new();
+	for column in ["id", "person", "project"] {
+		let ids = holders
+			.iter()
+			.filter_map(|(scope, _)| todo_column(*scope))
+			.filter(|(of, _)| *of == column)
+			.map(|(_, id)| id)
+			.collect::<Vec<_>>();
+		for (id, todos) in count_by(tenant.id, column, &ids, &pool).await.map_err(AppServerError::from)? {
+			counts.push(((column, id), todos));
+		}
+	}
+	let scopes = holders.iter().map(|(scope, _)| *scope).collect::<Vec<_>>();
+	let names = scope_names(&user, tenant.id, &scopes, &pool).await.map_err(AppServerError::from)?;
+
+	let mut reach = holders
+		.into_iter()
+		.zip(names)
+		.map(|((scope, users), name)| ScopeReach {
+			scope,
+			name,
+			users: users.len() as i64,
+			todos: counts.iter().find(|(key, _)| Some(*key) == todo_column(scope)).map_or(0, |(_, todos)| *todos),
+		})
+		.collect::<Vec<_>>();
+	reach.sort_by(|a, b| b.todos.cmp(&a.todos).then(b.users.cmp(&a.users)));
+
+	Ok(reach)
+}
+
+/// Every user of the tenant, admins only
+#[server(input = GetUrl)]
+pub async fn get_managed_users() -> Result<Vec<ManagedUser>, ServerFnError<AppServerError>> {
+	use self::ssr::managed_users;
+	use crate::{
+		auth::get_user,
+		cache::cache_privately,
+		denial::{ssr::record_denial, DeniedAction},
+		tenant::Tenant,
+	};
+	use sqlx::PgPool;
+
+	cache_privately::<GetManagedUsers>(0);
+
+	let pool = use_context::<PgPool>().expect("Database not initialized");
+	let tenant = use_context::<Tenant>().expect("No tenant found");
+	let user = get_user().await?.ok_or_else(AppServerError::unauthenticated)?;
+	if !user.is_admin() {
+		record_denial("get_managed_users", DeniedAction::Admin, &user);
+		return Err(AppServerError::forbidden("Missing permission to manage users").into());
+	}
+
+	Ok(managed_users(tenant.id, &pool).await.map_err(AppServerError::from)?)
+}
+
+/// Change the permissions of a user of the tenant, strings the parser refuses are never stored
+#[server]
+pub async fn set_user_permissions(
+	id: i32,
+	permission_equipment: String,
+	permission_user: String,
+	permission_todo: String,
+) -> Result<(), ServerFnError<AppServerError>> {
+	use self::ssr::parse_permission;
+	use crate::{
+		auth::{
+			get_user,
+			ssr::{require_recent_auth, AuthSession, STEP_UP_MAX_AGE},
+		},
+		cache::response_cache,
+		delegation::ssr::revoke_uncovered,
+		denial::{ssr::record_denial, DeniedAction},
+		events::{record, DomainEvent},
+		tenant::Tenant,
+	};
+	use sqlx::PgPool;
+
+	let pool = use_context::<PgPool>().expect("Database not initialized");
+	let tenant = use_context::<Tenant>().expect("No tenant found");
+	let auth = use_context::<AuthSession>().expect("No session found");
+	let user = get_user().await?.ok_or_else(AppServerError::unauthenticated)?;
+	if !user.is_admin() {
+		record_denial("set_user_permissions", DeniedAction::Admin, &user);
+		return Err(AppServerError::forbidden("Missing permission to manage users").into());
+	}
+	require_recent_auth(STEP_UP_MAX_AGE)?;
+
+	parse_permission("Equipment", &permission_equipment)?;
+	let Permissions::ReadWrite { write, .. } = parse_permission("Users", &permission_user)?;
+	parse_permission("Todos", &permission_todo)?;
+	// Admins taking away their own admin rights would lock the tenant out of this page
+	if id == user.id && write != Permission::WriteAny {
+		return Err(AppServerError::forbidden("You can't remove your own admin permission").into());
+	}
+
+	let mut tx = pool.begin().await.map_err(AppServerError::from)?;
+	let updated = sqlx::query(
+		"UPDATE users SET permission_equipment = $1, permission_user = $2, permission_todo = $3
+		WHERE id = $4 AND tenant = $5",
+	)
+	.bind(permission_equipment)
+	.bind(permission_user)
+	.bind(permission_todo)
+	.bind(id)
+	.bind(tenant.id)
+	.execute(&mut *tx)
+	.await
+	.map_err(AppServerError::from)?
+	.rows_affected();
+	if updated == 0 {
+		return Err(AppServerError::not_found("User not found").into());
+	}
+	record(
+		&mut *tx,
+		&DomainEvent::PermissionsChanged {
+			tenant: tenant.id,
+			user: Some(user.id),
+			person: id,
+		},
+	)
+	.await
+	.map_err(AppServerError::from)?;
+	tx.commit().await.map_err(AppServerError::from)?;
+	revoke_uncovered(id, &pool).await.map_err(AppServerError::from)?;
+
+	// Sessions and cached responses of the user were built with the old permissions
+	auth.cache_clear_user(id);
+	response_cache().invalidate_user(id);
+
+	Ok(())
+}
+
+/// Deactivate a user of the tenant so they can't log in anymore and their sessions and tokens end, or reactivate them
+///
+/// Their todos stay where they are, nothing is deleted
+#[server]
+pub async fn set_user_active(id: i32, active: bool) -> Result<Toasted<()>, ServerFnError<AppServerError>> {
+	use crate::{
+		auth::{
+			get_user,
+			ssr::{require_recent_auth, AuthSession, STEP_UP_MAX_AGE},
+		},
+		cache::response_cache,
+		denial::{ssr::record_denial, DeniedAction},
+		events::{record, DomainEvent},
+		tenant::Tenant,
+	};
+	use sqlx::PgPool;
+
+	let pool = use_context::<PgPool>().expect("Database not initialized");
+	let tenant = use_context::<Tenant>().expect("No tenant found");
+	let auth = use_context::<AuthSession>().expect("No session found");
+	let user = get_user().await?.ok_or_else(AppServerError::unauthenticated)?;
+	if !user.is_admin() {
+		record_denial("set_user_active", DeniedAction::Admin, &user);
+		return Err(AppServerError::forbidden("Missing permission to manage users").into());
+	}
+	require_recent_auth(STEP_UP_MAX_AGE)?;
+	if id == user.id && !active {
+		return Err(AppServerError::forbidden("You can't deactivate yourself").into());
+	}
+
+	let mut tx = pool.begin().await.map_err(AppServerError::from)?;
+	let updated = sqlx::query("UPDATE users SET active = $1 WHERE id = $2 AND tenant = $3")
+		.bind(active)
+		.bind(id)
+		.bind(tenant.id)
+		.execute(&mut *tx)
+		.await
+		.map_err(AppServerError::from)?
+		.rows_affected();
+	if updated == 0 {
+		return Err(AppServerError::not_found("User not found").into());
+	}
+	if !active {
+		// Access tokens die with their refresh token family, see `authenticate_bearer`
+		sqlx::query("UPDATE refresh_tokens SET revoked = true WHERE person = $1 AND NOT revoked")
+			.bind(id)
+			.execute(&mut *tx)
+			.await
+			.map_err(AppServerError::from)?;
+	}
+	let event = if active {
+		DomainEvent::UserReactivated {
+			tenant: tenant.id,
+			user: user.id,
+			person: id,
+		}
+	} else {
+		DomainEvent::UserDeactivated {
+			tenant: tenant.id,
+			user: user.id,
+			person: id,
+		}
+	};
+	record(&mut *tx, &event).await.map_err(AppServerError::from)?;
+	tx.commit().await.map_err(AppServerError::from)?;
+
+	// Cached sessions still carry the old flag, the next request of a deactivated user logs them out
+	auth.cache_clear_user(id);
+	response_cache().invalidate_user(id);
+
+	Ok(Toasted::success((), if active { "User reactivated" } else { "User deactivated" }))
+}
+
+/// The permissions of the tenant's signup, admins only
+#[server(input = GetUrl)]
+pub async fn get_default_permissions() -> Result<DefaultPermissions, ServerFnError<AppServerError>> {
+	use crate::{
+		auth::get_user,
+		cache::cache_privately,
+		denial::{ssr::record_denial, DeniedAction},
+		tenant::{Tenant, TenantSettings},
+	};
+	use sqlx::PgPool;
+
+	cache_privately::<GetDefaultPermissions>(0);
+
+	let pool = use_context::<PgPool>().expect("Database not initialized");
+	let tenant = use_context::<Tenant>().expect("No tenant found");
+	let user = get_user().await?.ok_or_else(AppServerError::unauthenticated)?;
+	if !user.is_admin() {
+		record_denial("get_default_permissions", DeniedAction::Admin, &user);
+		return Err(AppServerError::forbidden("Missing permission to manage users").into());
+	}
+
+	let settings = TenantSettings::get_for_tenant(tenant.id, &pool).await;
+
+	Ok(DefaultPermissions {
+		permission_equipment: settings.default_permission_equipment,
+		permission_user: settings.default_permission_user,
+		permission_todo: settings.default_permission_todo,
+	})
+}
+
+/// Change what users who sign up from now on start with, users who already signed up keep their permissions
+#[server]
+pub async fn set_default_permissions(
+	permission_equipment: String,
+	permission_user: String,
+	permission_todo: String,
+) -> Result<(), ServerFnError<AppServerError>> {
+	use crate::{
+		auth::{
+			get_user,
+			ssr::{require_recent_auth, STEP_UP_MAX_AGE},
+		},
+		denial::{ssr::record_denial, DeniedAction},
+		tenant::{Tenant, TenantSettings},
+	};
+	use sqlx::PgPool;
+
+	let pool = use_context::<PgPool>().expect("Database not initialized");
+	let tenant = use_context::<Tenant>().expect("No tenant found");
+	let user = get_user().await?.ok_or_else(AppServerError::unauthenticated)?;
+	if !user.is_admin() {
+		record_denial("set_default_permissions", DeniedAction::Admin, &user);
+		return Err(AppServerError::forbidden("Missing permission to manage users").into());
+	}
+	require_recent_auth(STEP_UP_MAX_AGE)?;
+
+	// Checked like signup checks them, with an id standing in for `[self]`
+	TenantSettings {
+		default_permission_equipment: permission_equipment.clone(),
+		default_permission_user: permission_user.clone(),
+		default_permission_todo: permission_todo.clone(),
+		..TenantSettings::default()
+	}
+	.default_permissions(0)
+	.map_err(AppServerError::invalid)?;
+
+	// Tenants without settings so far keep the defaults for everything else
+	sqlx::query(
+		"INSERT INTO tenant_settings (tenant, default_permission_equipment, default_permission_user, default_permission_todo)
+		VALUES ($1, $2, $3, $4)
+		ON CONFLICT (tenant) DO UPDATE SET default_permission_equipment = EXCLUDED.default_permission_equipment,
+			default_permission_user = EXCLUDED.default_permission_user,
+			default_permission_todo = EXCLUDED.default_permission_todo",
+	)
+	.bind(tenant.id)
+	.bind(permission_equipment)
+	.bind(permission_user)
+	.bind(permission_todo)
+	.execute(&pool)
+	.await.map_err(AppServerError::from)?;
+
+	Ok(())
+}
+
+/// A permission string input that runs every change through [`validate_permission`] and shows what it parses to
+#[component]
+pub fn PermissionEditor(
+	name: &'static str,
+	label: &'static str,
+	value: RwSignal<String>,
+	valid: RwSignal<bool>,
+	/// Allows `[self]` for the strings new users start with, it's shown as the id 0
+	#[prop(optional)]
+	for_new_users: bool,
+) -> impl IntoView {
+	let parsed = create_resource(
+		move || value.get(),
+		move |value| {
+			validate_permission(if for_new_users {
+				crate::tenant::with_self(&value, 0)
+			} else {
+				value
+			})
+		},
+	);
+	create_effect(move |_| valid.set(!matches!(parsed.get(), Some(Err(_)))));
+
+	view! {
+		<label>
+			{label} " "
+			<input
+				type="text"
+				name=name
+				prop:value=move || value.get()
+				on:input=move |ev| value.set(event_target_value(&ev))
+			/>
+		</label>
+		<Transition fallback=move || ()>
+			{move || {
+				parsed
+					.get()
+					.map(|parsed| match parsed {
+						Err(e) => view! { <span class="error">{e.to_string()}</span> }.into_view(),
+						Ok(permissions) => view! { <PermissionSummary permissions=permissions /> }.into_view(),
+					})
+			}}
+
+		</Transition>
+	}
+}
+
+/// How many todos and equipment the user reaches now and would reach with the strings being edited, on request since
+/// every preview counts rows of the whole tenant
+#[component]
+fn PermissionPreviewTable(
+	id: i32,
+	equipment: RwSignal<String>,
+	todo: RwSignal<String>,
+	#[prop(into)] valid: Signal<bool>,
+) -> impl IntoView {
+	let previewed = create_rw_signal(None::<(String, String)>);
+	let preview = create_resource(
+		move || previewed.get(),
+		move |strings| async move {
+			match strings {
+				Some((equipment, todo)) => Some(preview_permissions(id, equipment, todo).await),
+				None => None,
+			}
+		},
+	);
+
+	view! {
+		<button
+			type="button"
+			disabled=move || !valid.get()
+			on:click=move |_| previewed.set(Some((equipment.get_untracked(), todo.get_untracked())))
+		>
+			"Preview access"
+		</button>
+		<Transition fallback=move || ()>
+			{move || {
+				preview
+					.get()
+					.flatten()
+					.map(|preview| match preview {
+						Err(e) => view! { <span class="error">{e.to_string()}</span> }.into_view(),
+						Ok(previews) => {
+							view! {
+								<table class="permission-preview">
+									<tr>
+										<th></th>
+										<th>"Read"</th>
+										<th>"Change"</th>
+									</tr>
+									{previews
+										.into_iter()
+										.map(|preview| {
+											view! {
+												<tr>
+													<th class="permission-resource">{preview.resource}</th>
+													<td>
+														{format!("{} → {} of {}", preview.read_now, preview.read, preview.total)}
+													</td>
+													<td>
+														{format!("{} → {} of {}", preview.write_now, preview.write, preview.total)}
+													</td>
+												</tr>
+											}
+										})
+										.collect_view()}
+								</table>
+							}
+								.into_view()
+						}
+					})
+			}}
+		</Transition>
+	}
+}
+
+/// The scopes users hold with how many todos each gates, as bars against the scope reaching the most
+#[component]
+fn ScopeReachTable(#[prop(into)] changes: Signal<usize>) -> impl IntoView {
+	let reach = create_resource(move || changes.get(), |_| get_scope_reach());
+
+	view! {
+		<h2>"Reach of scopes"</h2>
+		<Transition fallback=move || ()>
+			{move || {
+				reach
+					.get()
+					.map(|reach| match reach {
+						Err(e) => view! { <span class="error">{e.to_string()}</span> }.into_view(),
+						Ok(reach) => {
+							let most = reach.first().map_or(0, |reach| reach.todos);
+							view! {
+								<table class="scope-reach">
+									<tr>
+										<th>"Scope"</th>
+										<th>"Users"</th>
+										<th>"Todos"</th>
+										<th></th>
+									</tr>
+									{reach
+										.into_iter()
+										.map(|reach| {
+											view! {
+												<tr>
+													<td>
+														<ScopeBadge scope=reach.scope name=reach.name />
+													</td>
+													<td>{reach.users}</td>
+													<td>{reach.todos}</td>
+													<td>
+														<meter min=0 max=most value=reach.todos></meter>
+													</td>
+												</tr>
+											}
+										})
+										.collect_view()}
+								</table>
+							}
+								.into_view()
+						}
+					})
+			}}
+		</Transition>
+	}
+}
+
+#[component]
+fn ManagedUserForm(
+	user: ManagedUser,
+	save: Action<SetUserPermissions, Result<(), AppError>>,
+	schedule: Action<SchedulePermissionChange, Result<Toasted<()>, AppError>>,
+	cancel: Action<CancelPermissionChange, Result<Toasted<()>, AppError>>,
+	set_active: Action<SetUserActive, Result<Toasted<()>, AppError>>,
+	pending: Vec<PendingPermissionChange>,
+) -> impl IntoView {
+	let equipment = create_rw_signal(user.permission_equipment);
+	let person = create_rw_signal(user.permission_user);
+	let todo = create_rw_signal(user.permission_todo);
+	let (equipment_valid, person_valid, todo_valid) =
+		(create_rw_signal(true), create_rw_signal(true), create_rw_signal(true));
+
+	view! {
+		<div class="managed-user" class:inactive=!user.active>
+			<ActionForm action=save>
+				<h2>{user.username} {(!user.active).then_some(" (deactivated)")}</h2>
+				<input type="hidden" name="id" value=user.id />
+				<PermissionEditor name="permission_equipment" label="Equipment" value=equipment valid=equipment_valid />
+				<PermissionEditor name="permission_user" label="Users" value=person valid=person_valid />
+				<PermissionEditor name="permission_todo" label="Todos" value=todo valid=todo_valid />
+				<input
+					type="submit"
+					value="Save"
+					disabled=move || !(equipment_valid.get() && person_valid.get() && todo_valid.get())
+				/>
+			</ActionForm>
+			<PermissionPreviewTable
+				id=user.id
+				equipment=equipment
+				todo=todo
+				valid=move || equipment_valid.get() && todo_valid.get()
+			/>
+			<ActionForm action=schedule>
+				<input type="hidden" name="id" value=user.id />
+				<input type="hidden" name="permission_equipment" prop:value=move || equipment.get() />
+				<input type="hidden" name="permission_user" prop:value=move || person.get() />
+				<input type="hidden" name="permission_todo" prop:value=move || todo.get() />
+				<label>
+					"Or from (UTC) " <input type="datetime-local" name="apply_at" required=true />
+				</label>
+				<input
+					type="submit"
+					value="Schedule"
+					disabled=move || !(equipment_valid.get() && person_valid.get() && todo_valid.get())
+				/>
+			</ActionForm>
+			<PendingPermissionChanges changes=pending cancel=cancel />
+			<ActionForm action=set_active>
+				<input type="hidden" name="id" value=user.id />
+				<input type="hidden" name="active" value=(!user.active).to_string() />
+				<input type="submit" value=if user.active { "Deactivate" } else { "Reactivate" } />
+			</ActionForm>
+		</div>
+	}
+}
+
+#[component]
+fn DefaultPermissionsForm(
+	defaults: DefaultPermissions,
+	save: Action<SetDefaultPermissions, Result<(), AppError>>,
+) -> impl IntoView {
+	let equipment = create_rw_signal(defaults.permission_equipment);
+	let person = create_rw_signal(defaults.permission_user);
+	let todo = create_rw_signal(defaults.permission_todo);
+	let (equipment_valid, person_valid, todo_valid) =
+		(create_rw_signal(true), create_rw_signal(true), create_rw_signal(true));
+
+	view! {
+		<ActionForm action=save>
+			<h2>"New users"</h2>
+			<p>"Users who sign up start with these permissions, " <code>"[self]"</code> " stands for the new user."</p>
+			<PermissionEditor
+				name="permission_equipment"
+				label="Equipment"
+				value=equipment
+				valid=equipment_valid
+				for_new_users=true
+			/>
+			<PermissionEditor name="permission_user" label="Users" value=person valid=person_valid for_new_users=true />
+			<PermissionEditor name="permission_todo" label="Todos" value=todo valid=todo_valid for_new_users=true />
+			<input
+				type="submit"
+				value="Save"
+				disabled=move || !(equipment_valid.get() && person_valid.get() && todo_valid.get())
+			/>
+		</ActionForm>
+	}
+}
+
+#[component]
+pub fn UserAdmin() -> impl IntoView {
+	let save = create_server_action::<SetUserPermissions>();
+	let import = create_server_action::<ImportUsers>();
+	let save_defaults = create_server_action::<SetDefaultPermissions>();
+	let import_permissions = create_server_action::<ImportPermissions>();
+	let schedule = create_server_action::<SchedulePermissionChange>();
+	let cancel = create_server_action::<CancelPermissionChange>();
+	let set_active = create_server_action::<SetUserActive>();
+	use_toasts().follow(import);
+	use_toasts().follow(import_permissions);
+	use_toasts().follow(schedule);
+	use_toasts().follow(cancel);
+	use_toasts().follow(set_active);
+	let users = create_resource(
+		move || {
+			(save.version().get(), import.version().get(), import_permissions.version().get(), set_active.version().get())
+		},
+		move |_| get_managed_users(),
+	);
+	let pending = create_resource(
+		move || (save.version().get(), schedule.version().get(), cancel.version().get()),
+		move |_| get_pending_permission_changes(),
+	);
+	let defaults = create_resource(move || save_defaults.version().get(), move |_| get_default_permissions());
+	let error = Signal::derive(move || {
+		save
+			.value()
+			.get()
+			.and_then(Result::err)
+			.or_else(|| import.value().get().and_then(Result::err))
+			.or_else(|| save_defaults.value().get().and_then(Result::err))
+			.or_else(|| import_permissions.value().get().and_then(Result::err))
+			.or_else(|| schedule.value().get().and_then(Result::err))
+			.or_else(|| set_active.value().get().and_then(Result::err))
+	});
+
+	view! {
+		<h1>"Users"</h1>
+		<Transition fallback=move || ()>
+			{move || {
+				defaults
+					.get()
+					.map(|defaults| match defaults {
+						Err(e) => view! { <span class="error">{e.to_string()}</span> }.into_view(),
+						Ok(defaults) => view! { <DefaultPermissionsForm defaults=defaults save=save_defaults /> }.into_view(),
+					})
+			}}
+
+		</Transition>
+		<Transition fallback=move || view! { <p>"Loading..."</p> }>
+			{move || {
+				users
+					.get()
+					.map(|users| match users {
+						Err(e) => view! { <span class="error">{e.to_string()}</span> }.into_view(),
+						Ok(users) => {
+							let pending = pending.get().and_then(Result::ok).unwrap_or_default();
+							users
+								.into_iter()
+								.map(|user| {
+									let pending = pending
+										.iter()
+										.filter(|change| change.person == user.id)
+										.cloned()
+										.collect::<Vec<_>>();
+									view! {
+										<ManagedUserForm
+											user=user
+											save=save
+											schedule=schedule
+											cancel=cancel
+											set_active=set_active
+											pending=pending
+										/>
+									}
+								})
+								.collect_view()
+						}
+					})
+			}}
+
+		</Transition>
+		{move || error.get().map(|e| view! { <span class="error">{e.to_string()}</span> })}
+		<ScopeReachTable changes=Signal::derive(move || {
+			save.version().get() + import.version().get() + import_permissions.version().get()
+		}) />
+		<UserImport import=import />
+		<PermissionTransfer import=import_permissions />
+		<ConfirmPassword error=error />
+	}
+}
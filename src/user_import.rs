@@ -0,0 +1,387 @@
+use crate::{
+	errors::{AppError, AppServerError},
+	toast::Toasted,
+};
+use leptos::*;
+use leptos_router::*;
+use serde::{Deserialize, Serialize};
+
+/// More rows than this are refused as a whole, every row hashes a password
+pub const MAX_IMPORT_ROWS: usize = 500;
+
+/// What happened to one row of an import
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "ssr", derive(utoipa::ToSchema))]
+pub enum ImportStatus {
+	/// Created with a generated password, it is only ever shown in this report
+	Created {
+		password: String,
+	},
+	/// Created and mailed a login link
+	Invited,
+	Failed {
+		error: String,
+	},
+}
+
+/// The outcome of one CSV row, `line` counts the header as line 1
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "ssr", derive(utoipa::ToSchema))]
+pub struct ImportRow {
+	pub line: u64,
+	pub username: String,
+	pub status: ImportStatus,
+}
+
+#[cfg(feature = "ssr")]
+pub mod ssr {
+	use super::MAX_IMPORT_ROWS;
+	use serde::Deserialize;
+
+	/// A row of the CSV, the columns can come in any order as long as the header names them
+	#[derive(Debug, PartialEq, Eq, Deserialize)]
+	pub struct ImportRecord {
+		pub username: String,
+		pub email: String,
+		/// The permission preset the user gets, the same presets SAML groups map to
+		pub role: String,
+	}
+
+	/// Split the CSV into records with their line numbers, a row that doesn't fit the header fails on its own
+	pub fn parse_csv(csv: &str) -> Result<Vec<(u64, Result<ImportRecord, String>)>, String> {
+		let mut reader = csv::ReaderBuilder::new().trim(csv::Trim::All).from_reader(csv.as_bytes());
+		let headers = reader.headers().map_err(|error| format!("Unreadable header: {error}"))?.clone();
+		for column in ["username", "email", "role"] {
+			if !headers.iter().any(|header| header == column) {
+				return Err(format!("The header has no \"{column}\" column"));
+			}
+		}
+
+		let rows = reader
+			.records()
+			.enumerate()
+			.map(|(index, record)| {
+				let line = record.as_ref().ok().and_then(|record| record.position()).map_or(index as u64 + 2, |p| p.line());
+				let record = record
+					.and_then(|record| record.deserialize::<ImportRecord>(Some(&headers)))
+					.map_err(|error| error.to_string());
+				(line, record)
+			})
+			.collect::<Vec<_>>();
+		if rows.len() > MAX_IMPORT_ROWS {
+			return Err(format!("Import at most {MAX_IMPORT_ROWS} users at once"));
+		}
+
+		Ok(rows)
+	}
+}
+
+/// Create the users of a CSV with `username`, `email` and `role` columns, admins only
+///
+/// Every row is imported on its own so one bad row doesn't stop the rest, not even when the database fails for it, and
+/// users created before are always reported. Users either get a generated password that is shown once in the report,
+/// or with `invite` an email with a login link and no password at all.
+#[server]
+pub async fn import_users(
+	csv: String,
+	invite: Option<String>,
+) -> Result<Toasted<Vec<ImportRow>>, ServerFnError<AppServerError>> {
+	use self::ssr::parse_csv;
+	use crate::{
+		auth::{
+			get_user,
+			ssr::{require_recent_auth, AuthBackend, STEP_UP_MAX_AGE},
+		},
+		config::Config,
+		denial::{ssr::record_denial, DeniedAction},
+		events::{record, DomainEvent},
+		identity::{
+			ssr::{find_identity, link_identity},
+			Provider,
+		},
+		jwt::ssr::{generate_token, hash_token},
+		mailer::{Mail, Mailer},
+		permission::Permission,
+		tenant::{Tenant, TenantSettings},
+		validation::USERNAME,
+	};
+	use sqlx::PgPool;
+	use std::{collections::HashMap, sync::Arc};
+
+	let pool = use_context::<PgPool>().expect("Database not initialized");
+	let backend = use_context::<Arc<dyn AuthBackend>>().expect("No auth backend found");
+	let mailer = use_context::<Arc<dyn Mailer>>().expect("No mailer found");
+	let config = use_context::<Config>().expect("No config found");
+	let tenant = use_context::<Tenant>().expect("No tenant found");
+	let user = get_user().await?.ok_or_else(AppServerError::unauthenticated)?;
+	if !user.is_admin() {
+		record_denial("import_users", DeniedAction::Admin, &user);
+		return Err(AppServerError::forbidden("Missing permission to manage users").into());
+	}
+	require_recent_auth(STEP_UP_MAX_AGE)?;
+
+	let rows = parse_csv(&csv).map_err(AppServerError::invalid)?;
+	let settings = TenantSettings::get_for_tenant(tenant.id, &pool).await;
+
+	// A preset with a permission the parser refuses fails every row using it instead of creating broken users
+	let presets = sqlx::query_as::<_, (String, String, String, String)>(
+		"SELECT idp_group, permission_equipment, permission_user, permission_todo FROM saml_permission_presets
+		WHERE tenant = $1",
+	)
+	.bind(tenant.id)
+	.fetch_all(&pool)
+	.await
+	.map_err(AppServerError::from)?
+	.into_iter()
+	.map(|(role, equipment, person, todo)| {
+		let valid = [&equipment, &person, &todo]
+			.into_iter()
+			.try_for_each(|permission| Permission::parse(permission.clone()).map(|_| ()))
+			.map(|()| (equipment, person, todo))
+			.map_err(|error| format!("The \"{role}\" preset has an invalid permission: {error}"));
+		(role, valid)
+	})
+	.collect::<HashMap<_, _>>();
+
+	let mut report = Vec::with_capacity(rows.len());
+	for (line, parsed) in rows {
+		let entry = match parsed {
+			Ok(entry) => entry,
+			Err(error) => {
+				report.push(ImportRow {
+					line,
+					username: String::new(),
+					status: ImportStatus::Failed { error },
+				});
+				continue;
+			},
+		};
+		let email = entry.email.to_lowercase();
+		let failed = |error: String| ImportRow {
+			line,
+			username: entry.username.clone(),
+			status: ImportStatus::Failed { error },
+		};
+
+		let username = match USERNAME.clean(&entry.username) {
+			Ok(username) => username,
+			Err(error) => {
+				report.push(failed(error.to_string()));
+				continue;
+			},
+		};
+		if !email.contains('@') {
+			report.push(failed(String::from("Invalid email address")));
+			continue;
+		}
+		let (equipment, person, todo) = match presets.get(&entry.role) {
+			Some(Ok(permissions)) => permissions,
+			Some(Err(error)) => {
+				report.push(failed(error.clone()));
+				continue;
+			},
+			None => {
+				report.push(failed(format!("Unknown role \"{}\"", entry.role)));
+				continue;
+			},
+		};
+		match find_identity(tenant.id, Provider::MagicLink, &email, &pool).await {
+			Ok(None) => {},
+			Ok(Some(_)) => {
+				report.push(failed(String::from("This email address is already used by another account")));
+				continue;
+			},
+			Err(error) => {
+				report.push(failed(format!("Checking the email address failed: {error}")));
+				continue;
+			},
+		}
+
+		let password = generate_token(20);
+		// The role's preset replaces the default permissions signup gives below
+		let created = match backend.signup(&username, &password, tenant.id, &settings).await {
+			Ok(created) => created,
+			Err(error) => {
+				report.push(failed(error.to_string()));
+				continue;
+			},
+		};
+
+		let token = generate_token(48);
+		let finished = async {
+			let mut transaction = pool.begin().await?;
+			sqlx::query(
+				"UPDATE users SET permission_equipment = $1, permission_user = $2, permission_todo = $3 WHERE id = $4",
+			)
+			.bind(equipment)
+			.bind(person)
+			.bind(todo)
+			.bind(created.id)
+			.execute(&mut *transaction)
+			.await?;
+			link_identity(&mut *transaction, tenant.id, created.id, Provider::MagicLink, &email).await?;
+			if invite.is_some() {
+				// Nobody knows the generated password, the login link is the only way in until they add their own
+				sqlx::query("DELETE FROM identities WHERE person = $1 AND provider = $2")
+					.bind(created.id)
+					.bind(Provider::Password.to_string())
+					.execute(&mut *transaction)
+					.await?;
+				sqlx::query(
+					"INSERT INTO magic_links (token_hash, person, expires_at)
+					VALUES ($1, $2, now() + make_interval(secs => $3))",
+				)
+				.bind(hash_token(&token))
+				.bind(created.id)
+				.bind(config.invite_lifetime as f64)
+				.execute(&mut *transaction)
+				.await?;
+			}
+			transaction.commit().await
+		}
+		.await;
+		if let Err(error) = finished {
+			// Signup already committed the user, a half imported user would block importing the row again
+			if let Err(error) = sqlx::query("DELETE FROM users WHERE id = $1").bind(created.id).execute(&pool).await {
+				log::error!("Removing the half imported user {} failed: {error}", created.id);
+			}
+			report.push(failed(error.to_string()));
+			continue;
+		}
+		// The user is there either way, so the row is still reported with its password
+		if let Err(error) = record(
+			&pool,
+			&DomainEvent::UserSignedUp {
+				tenant: tenant.id,
+				user: created.id,
+			},
+		)
+		.await
+		{
+			log::error!("Recording the signup of the imported user {} failed: {error}", created.id);
+		}
+
+		let status = if invite.is_some() {
+			let sent = mailer
+				.send(Mail {
+					to: email,
+					subject: String::from("You have been invited"),
+					body: format!(
+						"An account named {} was created for you. Log in with this link:\n{}/auth/magic/{token}\n\n\
+						It works once within the next {} days, after that ask for a new login link on the login page.",
+						username,
+						config.public_url,
+						config.invite_lifetime / (24 * 60 * 60)
+					),
+				})
+				.await;
+			match sent {
+				Ok(()) => ImportStatus::Invited,
+				Err(error) => ImportStatus::Failed {
+					error: format!("Created, but the invite could not be sent: {error}"),
+				},
+			}
+		} else {
+			ImportStatus::Created { password }
+		};
+		report.push(ImportRow { line, username, status });
+	}
+
+	let failed = report.iter().filter(|row| matches!(row.status, ImportStatus::Failed { .. })).count();
+	Ok(if failed > 0 {
+		Toasted::warning(report, format!("{failed} rows were not imported, see the report"))
+	} else {
+		Toasted::success(report, "Every row was imported")
+	})
+}
+
+#[component]
+pub fn UserImport(import: Action<ImportUsers, Result<Toasted<Vec<ImportRow>>, AppError>>) -> impl IntoView {
+	view! {
+		<h2>"Import users"</h2>
+		<ActionForm action=import>
+			<p>
+				"One user per row with a header of " <code>"username,email,role"</code>
+				", the role names a permission preset."
+			</p>
+			<textarea name="csv" rows=8 required=true></textarea>
+			<label>
+				"Email login links instead of generating passwords " <input type="checkbox" name="invite" />
+			</label>
+			<input type="submit" value="Import" />
+		</ActionForm>
+		{move || {
+			import
+				.value()
+				.get()
+				.and_then(Result::ok)
+				.map(|Toasted { value: report, .. }| {
+					let imported = report.iter().filter(|row| !matches!(row.status, ImportStatus::Failed { .. })).count();
+					let rows = report
+						.into_iter()
+						.map(|row| {
+							let (class, outcome) = match row.status {
+								ImportStatus::Created { password } => ("imported", format!("created, password {password}")),
+								ImportStatus::Invited => ("imported", String::from("invited")),
+								ImportStatus::Failed { error } => ("error", error),
+							};
+							view! {
+								<tr class=class>
+									<td>{row.line}</td>
+									<td>{row.username}</td>
+									<td>{outcome}</td>
+								</tr>
+							}
+						})
+						.collect_view();
+					view! {
+						<p>{format!("Imported {imported} users, passwords are only shown now.")}</p>
+						<table class="import-report">
+							<tr>
+								<th>"Line"</th>
+								<th>"Username"</th>
+								<th>"Result"</th>
+							</tr>
+							{rows}
+						</table>
+					}
+				})
+		}}
+	}
+}
+
+#[cfg(all(test, feature = "ssr"))]
+mod tests {
+	use super::ssr::{parse_csv, ImportRecord};
+
+	#[test]
+	fn columns_in_any_order() {
+		let rows = parse_csv("role, email ,username\nstaff,a@example.com,alice\n").unwrap();
+
+		assert_eq!(
+			rows,
+			vec![(
+				2,
+				Ok(ImportRecord {
+					username: String::from("alice"),
+					email: String::from("a@example.com"),
+					role: String::from("staff"),
+				})
+			)]
+		);
+	}
+
+	#[test]
+	fn bad_rows_fail_alone() {
+		let rows = parse_csv("username,email,role\nalice,a@example.com\nbob,b@example.com,staff\n").unwrap();
+
+		assert_eq!(rows.len(), 2);
+		assert!(rows[0].1.is_err());
+		assert!(rows[1].1.is_ok());
+	}
+
+	#[test]
+	fn missing_column() {
+		assert!(parse_csv("username,email\nalice,a@example.com\n").is_err());
+	}
+}
@@ -0,0 +1,194 @@
+use crate::{
+	auth::use_user_context,
+	errors::AppServerError,
+	toast::{use_toasts, Toasted},
+};
+use leptos::*;
+use leptos_router::*;
+
+/// How long users wait after changing their username before they can change it again
+pub const USERNAME_CHANGE_COOLDOWN_DAYS: i64 = 30;
+/// How long an old username stays reserved for the user who gave it up, so nobody else can pose as them right away
+pub const USERNAME_RETENTION_DAYS: i64 = 90;
+
+#[cfg(feature = "ssr")]
+pub mod ssr {
+	use super::USERNAME_RETENTION_DAYS;
+	use sqlx::PgExecutor;
+
+	/// Whether someone other than `person` gave up the username in the last [`USERNAME_RETENTION_DAYS`], `person` is
+	/// `None` for users that don't exist yet
+	///
+	/// Check it wherever a user gets a name, new ones included, or the reservation can be gone around.
+	pub async fn is_reserved<'c>(
+		executor: impl PgExecutor<'c>,
+		tenant: i32,
+		username: &str,
+		person: Option<i32>,
+	) -> Result<bool, sqlx::Error> {
+		sqlx::query_scalar::<_, bool>(
+			"SELECT EXISTS (
+				SELECT 1 FROM username_history
+				WHERE tenant = $1 AND username = $2 AND person IS DISTINCT FROM $3
+				AND changed_at > now() - make_interval(days => $4)
+			)",
+		)
+		.bind(tenant)
+		.bind(username)
+		.bind(person)
+		.bind(USERNAME_RETENTION_DAYS as i32)
+		.fetch_one(executor)
+		.await
+	}
+}
+
+/// Rename the logged in user, the password confirms it's really them
+///
+/// The old username stays in the history for [`USERNAME_RETENTION_DAYS`] and only its previous owner can take it back
+/// in that time
+#[server]
+pub async fn change_username(new: String, password: String) -> Result<Toasted<()>, ServerFnError<AppServerError>> {
+	use crate::{
+		auth::{
+			get_user,
+			ssr::{AuthBackend, AuthSession},
+		},
+		cache::response_cache,
+		config::Config,
+		events::{record, DomainEvent},
+		identity::Provider,
+		rate_limit::{check_login, login_succeeded, request_client, RateLimiter},
+		tenant::Tenant,
+		validation::USERNAME,
+	};
+	use sqlx::PgPool;
+	use std::sync::Arc;
+
+	let pool = use_context::<PgPool>().expect("Database not initialized");
+	let backend = use_context::<Arc<dyn AuthBackend>>().expect("No auth backend found");
+	let limiter = use_context::<Arc<dyn RateLimiter>>().expect("No rate limiter found");
+	let config = use_context::<Config>().expect("No config found");
+	let auth = use_context::<AuthSession>().expect("No session found");
+	let tenant = use_context::<Tenant>().expect("No tenant found");
+	let user = get_user().await?.ok_or_else(AppServerError::unauthenticated)?;
+
+	let new = USERNAME.clean(&new).map_err(AppServerError::invalid)?;
+	if new == user.username {
+		return Err(AppServerError::invalid("That is your username already").into());
+	}
+
+	// Confirming the password counts like a login, same as anywhere else
+	let client = request_client(&config);
+	check_login(limiter.as_ref(), &config, tenant.id, &user.username, client)
+		.await
+		.map_err(|error| AppServerError::new(error.code(), error))?;
+	if let Err(error) = backend.login(&user.username, &password, tenant.id).await {
+		return Err(AppServerError::new(error.code(), error).into());
+	}
+	login_succeeded(limiter.as_ref(), tenant.id, &user.username, client)
+		.await
+		.map_err(|error| AppServerError::new(error.code(), error))?;
+
+	// Locking the user keeps two renames running at once from both getting past the cooldown
+	let mut tx = pool.begin().await.map_err(AppServerError::from)?;
+	sqlx::query("SELECT id FROM users WHERE id = $1 FOR UPDATE")
+		.bind(user.id)
+		.execute(&mut *tx)
+		.await
+		.map_err(AppServerError::from)?;
+	let cooling_down = sqlx::query_scalar::<_, bool>(
+		"SELECT EXISTS (SELECT 1 FROM username_history WHERE person = $1 AND changed_at > now() - make_interval(days => $2))",
+	)
+	.bind(user.id)
+	.bind(USERNAME_CHANGE_COOLDOWN_DAYS as i32)
+	.fetch_one(&mut *tx)
+	.await.map_err(AppServerError::from)?;
+	if cooling_down {
+		return Err(
+			AppServerError::invalid(format!("Usernames can only be changed once every {USERNAME_CHANGE_COOLDOWN_DAYS} days"))
+				.into(),
+		);
+	}
+	if ssr::is_reserved(&mut *tx, tenant.id, &new, Some(user.id)).await.map_err(AppServerError::from)? {
+		return Err(AppServerError::conflict("Username is already taken.").into());
+	}
+
+	sqlx::query("UPDATE users SET username = $1 WHERE id = $2")
+		.bind(&new)
+		.bind(user.id)
+		.execute(&mut *tx)
+		.await
+		.map_err(|error| match error {
+			sqlx::Error::Database(error) if error.is_unique_violation() => {
+				AppServerError::conflict("Username is already taken.")
+			},
+			error => error.into(),
+		})?;
+	sqlx::query("INSERT INTO username_history (tenant, person, username) VALUES ($1, $2, $3)")
+		.bind(tenant.id)
+		.bind(user.id)
+		.bind(&user.username)
+		.execute(&mut *tx)
+		.await
+		.map_err(AppServerError::from)?;
+	// The password credential is known by the username, it has to follow along to keep working
+	sqlx::query("UPDATE identities SET subject = $1 WHERE person = $2 AND provider = $3")
+		.bind(&new)
+		.bind(user.id)
+		.bind(Provider::Password.to_string())
+		.execute(&mut *tx)
+		.await
+		.map_err(AppServerError::from)?;
+	record(
+		&mut *tx,
+		&DomainEvent::UsernameChanged {
+			tenant: tenant.id,
+			user: user.id,
+			from: user.username,
+			to: new.clone(),
+		},
+	)
+	.await
+	.map_err(AppServerError::from)?;
+	tx.commit().await.map_err(AppServerError::from)?;
+
+	// Todos, comments and history of every user show the username, so all cached responses of the tenant are stale
+	auth.cache_clear_user(user.id);
+	response_cache().invalidate_tenant(tenant.id);
+
+	Ok(Toasted::success((), format!("You are now {new}")))
+}
+
+#[component]
+pub fn ChangeUsername() -> impl IntoView {
+	let change = create_server_action::<ChangeUsername>();
+	use_toasts().follow(change);
+	let user = use_user_context().user;
+	let error = Signal::derive(move || change.value().get().and_then(Result::err));
+
+	// Everything showing the logged in user reads it from the context
+	create_effect(move |_| {
+		if let Some(Ok(_)) = change.value().get() {
+			user.refetch();
+		}
+	});
+
+	view! {
+		<h2>"Username"</h2>
+		<ActionForm action=change>
+			<label>
+				"New username " <input type="text" name="new" required=true />
+			</label>
+			<label>
+				"Password " <input type="password" name="password" required=true />
+			</label>
+			<input type="submit" value="Change username" />
+		</ActionForm>
+		<p>
+			{format!(
+				"You can change your username once every {USERNAME_CHANGE_COOLDOWN_DAYS} days, nobody else can take your old one for {USERNAME_RETENTION_DAYS} days."
+			)}
+		</p>
+		{move || error.get().map(|e| view! { <span class="error">{e.to_string()}</span> })}
+	}
+}
@@ -0,0 +1,122 @@
+//! Text users send to server functions, cleaned up the same way wherever it's stored
+//!
+//! Control characters are dropped, surrounding whitespace is trimmed and each field has a length it can't go over, so
+//! nothing larger than what the forms allow ends up in the database.
+
+use thiserror::Error;
+
+/// Why the text of a field can't be stored
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum ValidationError {
+	#[error("The {field} can't be empty")]
+	Empty { field: &'static str },
+	#[error("The {field} can be up to {max} characters long")]
+	TooLong { field: &'static str, max: usize },
+}
+
+/// A text field of a form with the rules its input is held to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TextField {
+	/// How the field is called in errors
+	pub name: &'static str,
+	/// In characters, after cleaning up
+	pub max_length: usize,
+	/// Whether line breaks are kept, single line fields drop them like any other control character
+	pub multiline: bool,
+}
+
+pub const TODO_TITLE: TextField = TextField {
+	name: "title",
+	max_length: 200,
+	multiline: false,
+};
+
+pub const TODO_DESCRIPTION: TextField = TextField {
+	name: "description",
+	max_length: 10_000,
+	multiline: true,
+};
+
+pub const COMMENT_BODY: TextField = TextField {
+	name: "comment",
+	max_length: 2000,
+	multiline: true,
+};
+
+pub const EMAIL: TextField = TextField {
+	name: "email address",
+	max_length: 254,
+	multiline: false,
+};
+
+/// Whichever way a user is created or renamed
+pub const USERNAME: TextField = TextField {
+	name: "username",
+	max_length: 64,
+	multiline: false,
+};
+
+pub const PROJECT_NAME: TextField = TextField {
+	name: "project name",
+	max_length: 64,
+	multiline: false,
+};
+
+impl TextField {
+	/// The input as it is stored, without control characters and surrounding whitespace
+	pub fn clean(&self, input: &str) -> Result<String, ValidationError> {
+		let too_long = ValidationError::TooLong {
+			field: self.name,
+			max: self.max_length,
+		};
+		// No character takes more than 4 bytes, anything longer is refused before going through it
+		if input.len() > self.max_length.saturating_mul(4) {
+			return Err(too_long);
+		}
+
+		let cleaned = input
+			.chars()
+			.filter(|character| !character.is_control() || (self.multiline && *character == '\n'))
+			.collect::<String>();
+		let cleaned = cleaned.trim();
+		if cleaned.is_empty() {
+			return Err(ValidationError::Empty { field: self.name });
+		}
+		if cleaned.chars().count() > self.max_length {
+			return Err(too_long);
+		}
+
+		Ok(cleaned.to_string())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn text_is_cleaned_up() {
+		assert_eq!(TODO_TITLE.clean("  Buy\u{0} milk\n"), Ok(String::from("Buy milk")));
+		assert_eq!(COMMENT_BODY.clean("Line\r\nnext\u{1b}"), Ok(String::from("Line\nnext")));
+		assert_eq!(TODO_TITLE.clean(" \u{7} "), Err(ValidationError::Empty { field: "title" }));
+		assert_eq!(
+			TODO_TITLE.clean(&"a".repeat(10 * 1024 * 1024)),
+			Err(ValidationError::TooLong {
+				field: "title",
+				max: 200
+			})
+		);
+		assert!(TODO_TITLE.clean(&"ä".repeat(200)).is_ok());
+	}
+
+	#[test]
+	fn names_are_cleaned_up() {
+		assert_eq!(USERNAME.clean("  alice "), Ok(String::from("alice")));
+		assert_eq!(USERNAME.clean("al\nice"), Ok(String::from("alice")));
+		assert_eq!(USERNAME.clean(" "), Err(ValidationError::Empty { field: "username" }));
+		assert!(USERNAME.clean(&"a".repeat(65)).is_err());
+		assert_eq!(PROJECT_NAME.clean(" Launch "), Ok(String::from("Launch")));
+		assert!(PROJECT_NAME.clean("  ").is_err());
+		assert!(PROJECT_NAME.clean(&"a".repeat(65)).is_err());
+	}
+}
@@ -0,0 +1,283 @@
+//! Which todos every user can read, kept in `user_visible_todos` when `MATERIALIZED_VISIBILITY` is on
+//!
+//! Deployments with huge or complex scopes trade the storage for listing todos with a join instead of filtering by
+//! the scope on every read. The outbox applies the events of todos that were created or changed and of users whose
+//! permissions changed while it delivers them, before live listeners hear of them. Todos and users that are deleted
+//! take their rows with them. Delegated access expires on its own, so it's never materialized.
+//!
+//! On start everything is built again into a shadow table that replaces the materialization once it's complete, so
+//! lists never show one that is only halfway there.
+
+use crate::{
+	auth::{User, UserSQL},
+	authorize::{self, Action},
+	config::Config,
+	delegation::ssr::widen,
+	events::DomainEvent,
+	permission::{Permission, ScopeFilter},
+};
+use sqlx::{PgConnection, PgPool};
+use std::sync::OnceLock;
+
+static MATERIALIZED: OnceLock<bool> = OnceLock::new();
+
+const TABLE: &str = "user_visible_todos";
+/// Where a rebuild writes to until it replaces [`TABLE`]
+const SHADOW: &str = "user_visible_todos_rebuild";
+/// Taken by every change to the materialization so a rebuild never misses one
+const WRITE_LOCK: i64 = 0x7669_7369_6269_6c01;
+/// Held for as long as a rebuild runs so only one replica does it
+const REBUILD_LOCK: i64 = 0x7669_7369_6269_6c02;
+
+/// Take `MATERIALIZED_VISIBILITY` from the config, call it on start before any request is served
+pub fn init(config: &Config) {
+	if MATERIALIZED.set(config.materialized_visibility).is_err() {
+		log::warn!("The visibility materialization was set up twice, keeping the first setting");
+	}
+}
+
+/// Whether `MATERIALIZED_VISIBILITY` is on, off until [`init`] ran
+pub fn materialized() -> bool {
+	MATERIALIZED.get().copied().unwrap_or(false)
+}
+
+/// The clause that narrows todos down to the ones the user can read, a lookup in the materialization when it's on
+pub fn read_filter(user: &User, first_param: usize) -> ScopeFilter {
+	// Only the stored permissions are materialized, not what an API token narrowed them down to
+	if materialized() && !user.token_scoped {
+		// The id is an integer so there is nothing to escape
+		let filter = ScopeFilter {
+			sql: format!(" AND id IN (SELECT todo FROM {TABLE} WHERE person = {})", user.id),
+			arrays: Vec::new(),
+		};
+		return widen(filter, user, Action::Read);
+	}
+
+	authorize::todo_filter(user, Action::Read, first_param)
+}
+
+/// Bring the materialization up to date with an event, the outbox runs it in the transaction delivering the event
+pub async fn apply(connection: &mut PgConnection, event: &DomainEvent) -> Result<(), sqlx::Error> {
+	if !materialized() {
+		return Ok(());
+	}
+
+	match event {
+		DomainEvent::TodoCreated { todo, .. } | DomainEvent::TodoUpdated { todo, .. } => {
+			refresh_todo(connection, *todo).await
+		},
+		DomainEvent::UserSignedUp { user: person, .. }
+		| DomainEvent::PermissionsChanged { person, .. }
+		| DomainEvent::PermissionChangeApplied { person, .. } => refresh_user(connection, *person).await,
+		_ => Ok(()),
+	}
+}
+
+/// The tables a change goes to, the shadow table too while a rebuild runs, the lock is held until the transaction ends
+async fn lock(connection: &mut PgConnection) -> Result<&'static [&'static str], sqlx::Error> {
+	sqlx::query("SELECT pg_advisory_xact_lock($1)").bind(WRITE_LOCK).execute(&mut *connection).await?;
+	let rebuilding = sqlx::query_scalar::<_, bool>("SELECT to_regclass($1) IS NOT NULL")
+		.bind(SHADOW)
+		.fetch_one(&mut *connection)
+		.await?;
+
+	Ok(if rebuilding { &[TABLE, SHADOW] } else { &[TABLE] })
+}
+
+/// A regular expression matching every todo permission, written the way [`Permission::parse`] reads it, with a read
+/// scope that could cover the todo
+///
+/// It only narrows down whose permissions are checked, it lets through more than they allow.
+fn read_pattern(todo: i32, person: i32, project: Option<i32>) -> String {
+	let scope = |kind: &str, id: i32| format!(r"{kind}\[\+?0*{id}\]");
+	let mut scopes = vec![scope("EQUIPMENT", todo), scope("PERSON", person)];
+	scopes.extend(project.map(|project| scope("PROJECT", project)));
+
+	format!(r"(^|\|)READ\((\*(\||$)|[^|]*({}))", scopes.join("|"))
+}
+
+/// The users of the tenant that read the todo by their own access
+///
+/// Users reading it by delegation get it through the [`widen`] of [`read_filter`], which stops as soon as the
+/// delegation runs out, so only their own access is written. Policies can grant a todo to anyone, with them every
+/// user of the tenant is checked.
+async fn readers(
+	connection: &mut PgConnection,
+	tenant: i32,
+	todo: i32,
+	person: i32,
+	project: Option<i32>,
+) -> Result<Vec<i32>, sqlx::Error> {
+	let candidates = match authorize::policies() {
+		Some(_) => {
+			sqlx::query_as::<_, UserSQL>("SELECT * FROM users WHERE tenant = $1").bind(tenant).fetch_all(connection).await?
+		},
+		None => {
+			sqlx::query_as::<_, UserSQL>(
+				"SELECT * FROM users
+				WHERE tenant = $1 AND upper(replace(replace(permission_todo, ' ', ''), ')', '')) ~ $2",
+			)
+			.bind(tenant)
+			.bind(read_pattern(todo, person, project))
+			.fetch_all(connection)
+			.await?
+		},
+	};
+
+	Ok(
+		candidates
+			.into_iter()
+			.filter_map(|user| valid(user, &format!("todo {todo}")))
+			.filter(|user| authorize::allows(user, Action::Read, todo, person, project))
+			.map(|user| user.id)
+			.collect(),
+	)
+}
+
+/// The user as long as their todo permission parses, one that doesn't would panic once checked
+fn valid(user: UserSQL, todos: &str) -> Option<User> {
+	match Permission::parse(user.permission_todo.clone()) {
+		Ok(_) => Some(User::from(user)),
+		Err(error) => {
+			log::error!("User {} has an invalid todo permission and can't be given {todos}: {error}", user.id);
+			None
+		},
+	}
+}
+
+/// Work out again who can read a todo, it may have moved to another project
+async fn refresh_todo(connection: &mut PgConnection, todo: i32) -> Result<(), sqlx::Error> {
+	let tables = lock(connection).await?;
+	let Some((tenant, person, project)) =
+		sqlx::query_as::<_, (i32, i32, Option<i32>)>("SELECT tenant, person, project FROM todos WHERE id = $1")
+			.bind(todo)
+			.fetch_optional(&mut *connection)
+			.await?
+	else {
+		return Ok(());
+	};
+	let readers = readers(connection, tenant, todo, person, project).await?;
+
+	for table in tables {
+		sqlx::query(&format!("DELETE FROM {table} WHERE todo = $1")).bind(todo).execute(&mut *connection).await?;
+		sqlx::query(&format!("INSERT INTO {table} (person, todo) SELECT UNNEST($1::int4[]), $2 ON CONFLICT DO NOTHING"))
+			.bind(&readers)
+			.bind(todo)
+			.execute(&mut *connection)
+			.await?;
+	}
+
+	Ok(())
+}
+
+/// Replace the todos a user can read
+async fn refresh_user(connection: &mut PgConnection, person: i32) -> Result<(), sqlx::Error> {
+	let tables = lock(connection).await?;
+	for table in tables {
+		fill(connection, table, person).await?;
+	}
+
+	Ok(())
+}
+
+/// Replace the rows of a user in one of the tables, a user with an invalid todo permission is left without any
+async fn fill(connection: &mut PgConnection, table: &str, person: i32) -> Result<(), sqlx::Error> {
+	sqlx::query(&format!("DELETE FROM {table} WHERE person = $1")).bind(person).execute(&mut *connection).await?;
+	let user = sqlx::query_as::<_, UserSQL>("SELECT * FROM users WHERE id = $1")
+		.bind(person)
+		.fetch_optional(&mut *connection)
+		.await?;
+	let Some(user) = user.and_then(|user| valid(user, "any todos")) else {
+		return Ok(());
+	};
+
+	let filter = authorize::own_filter(&user, Action::Read, 3);
+	let query = format!("INSERT INTO {table} (person, todo) SELECT $1, id FROM todos WHERE tenant = $2{}", filter.sql);
+	filter
+		.arrays
+		.iter()
+		.fold(sqlx::query(&query).bind(user.id).bind(user.tenant), |query, scope| query.bind(scope))
+		.execute(connection)
+		.await?;
+
+	Ok(())
+}
+
+/// Build the materialization of every user again in the background, catching up with whatever changed while it was off
+///
+/// Only one replica rebuilds at a time, the others keep serving what's there until it's replaced.
+pub fn spawn_rebuild(pool: PgPool) {
+	if !materialized() {
+		return;
+	}
+
+	tokio::spawn(async move {
+		match rebuild(&pool).await {
+			Ok(Some(users)) => log::info!("Materialized the visible todos of {users} users"),
+			Ok(None) => log::info!("Another replica is materializing the visible todos already"),
+			Err(error) => log::error!("Materializing the visible todos failed: {error}"),
+		}
+	});
+}
+
+/// How many users were rebuilt, `None` when another replica is at it
+async fn rebuild(pool: &PgPool) -> Result<Option<usize>, sqlx::Error> {
+	// A session lock on a connection of its own that is closed afterwards, so the lock can't outlive the rebuild
+	let mut guard = pool.acquire().await?;
+	let locked =
+		sqlx::query_scalar::<_, bool>("SELECT pg_try_advisory_lock($1)").bind(REBUILD_LOCK).fetch_one(&mut *guard).await?;
+	let rebuilt = if locked {
+		build_shadow(pool).await.map(Some)
+	} else {
+		Ok(None)
+	};
+	if let Err(error) = guard.close().await {
+		log::error!("Closing the connection of the visibility rebuild failed: {error}");
+	}
+
+	rebuilt
+}
+
+async fn build_shadow(pool: &PgPool) -> Result<usize, sqlx::Error> {
+	// A rebuild that died halfway left its shadow table behind, it's started over
+	let mut tx = pool.begin().await?;
+	sqlx::query("SELECT pg_advisory_xact_lock($1)").bind(WRITE_LOCK).execute(&mut *tx).await?;
+	sqlx::query(&format!("DROP TABLE IF EXISTS {SHADOW}")).execute(&mut *tx).await?;
+	sqlx::query(&format!(
+		"CREATE TABLE {SHADOW} (
+			person INT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+			todo   INT NOT NULL REFERENCES todos(id) ON DELETE CASCADE,
+			PRIMARY KEY (person, todo)
+		)"
+	))
+	.execute(&mut *tx)
+	.await?;
+	sqlx::query(&format!("CREATE INDEX {SHADOW}_todo ON {SHADOW} (todo)")).execute(&mut *tx).await?;
+	tx.commit().await?;
+
+	// Every user on their own so events applied in between wait on one user at most
+	let users = sqlx::query_scalar::<_, i32>("SELECT id FROM users").fetch_all(pool).await?;
+	for user in &users {
+		let mut tx = pool.begin().await?;
+		sqlx::query("SELECT pg_advisory_xact_lock($1)").bind(WRITE_LOCK).execute(&mut *tx).await?;
+		fill(&mut tx, SHADOW, *user).await?;
+		tx.commit().await?;
+	}
+
+	// The names are given back so the next rebuild can create its shadow table again
+	let mut tx = pool.begin().await?;
+	sqlx::query("SELECT pg_advisory_xact_lock($1)").bind(WRITE_LOCK).execute(&mut *tx).await?;
+	for statement in [
+		format!("DROP TABLE {TABLE}"),
+		format!("ALTER TABLE {SHADOW} RENAME TO {TABLE}"),
+		format!("ALTER TABLE {TABLE} RENAME CONSTRAINT {SHADOW}_pkey TO {TABLE}_pkey"),
+		format!("ALTER TABLE {TABLE} RENAME CONSTRAINT {SHADOW}_person_fkey TO {TABLE}_person_fkey"),
+		format!("ALTER TABLE {TABLE} RENAME CONSTRAINT {SHADOW}_todo_fkey TO {TABLE}_todo_fkey"),
+		format!("ALTER INDEX {SHADOW}_todo RENAME TO {TABLE}_todo"),
+	] {
+		sqlx::query(&statement).execute(&mut *tx).await?;
+	}
+	tx.commit().await?;
+
+	Ok(users.len())
+}